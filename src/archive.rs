@@ -0,0 +1,174 @@
+use miniz_oxide::deflate::compress_to_vec;
+
+use crate::{
+    fs::{join_path, WsvcFsError},
+    model::{EntryKind, ObjectId, Repository},
+    readonly::ReadOnlyRepository,
+};
+
+/// size of tar headers and blocks.
+const BLOCK_SIZE: usize = 512;
+
+/// deflate level of archives, the same level always gives the same bytes.
+const ARCHIVE_COMPRESSION_LEVEL: u8 = 9;
+
+/// crc32 of gzip members, the ieee polynomial.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// write `value` as a NUL terminated octal number filling `field`.
+fn octal(field: &mut [u8], value: u64) {
+    let width = field.len() - 1;
+    let digits = format!("{:0width$o}", value);
+    field[..width].copy_from_slice(&digits.as_bytes()[digits.len() - width..]);
+    field[width] = 0;
+}
+
+/// `TarBuilder` writes a ustar archive with fixed owners and dates, so the same entries
+/// always give the same bytes.
+struct TarBuilder {
+    data: Vec<u8>,
+    mtime: u64,
+}
+
+impl TarBuilder {
+    fn header(&mut self, name: &[u8], mode: u32, size: u64, kind: u8, link: &[u8]) {
+        let mut header = [0u8; BLOCK_SIZE];
+        header[..name.len().min(100)].copy_from_slice(&name[..name.len().min(100)]);
+        octal(&mut header[100..108], mode as u64);
+        octal(&mut header[108..116], 0);
+        octal(&mut header[116..124], 0);
+        octal(&mut header[124..136], size);
+        octal(&mut header[136..148], self.mtime);
+        header[156] = kind;
+        header[157..157 + link.len().min(100)].copy_from_slice(&link[..link.len().min(100)]);
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        // the checksum is computed with its own field filled with spaces.
+        header[148..156].fill(b' ');
+        let checksum = header.iter().map(|b| *b as u64).sum::<u64>();
+        octal(&mut header[148..155], checksum);
+        header[155] = b' ';
+        self.data.extend_from_slice(&header);
+    }
+
+    fn content(&mut self, content: &[u8]) {
+        self.data.extend_from_slice(content);
+        let padding = (BLOCK_SIZE - content.len() % BLOCK_SIZE) % BLOCK_SIZE;
+        self.data.resize(self.data.len() + padding, 0);
+    }
+
+    /// add an entry, names and link targets longer than 100 bytes are written as gnu long
+    /// name entries before it.
+    fn append(&mut self, path: &str, mode: u32, kind: u8, link: &str, content: &[u8]) {
+        if link.len() > 100 {
+            let mut long = link.as_bytes().to_vec();
+            long.push(0);
+            self.header(b"././@LongLink", 0o644, long.len() as u64, b'K', b"");
+            self.content(&long);
+        }
+        if path.len() > 100 {
+            let mut long = path.as_bytes().to_vec();
+            long.push(0);
+            self.header(b"././@LongLink", 0o644, long.len() as u64, b'L', b"");
+            self.content(&long);
+        }
+        self.header(
+            path.as_bytes(),
+            mode,
+            content.len() as u64,
+            kind,
+            link.as_bytes(),
+        );
+        self.content(content);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.data.resize(self.data.len() + BLOCK_SIZE * 2, 0);
+        self.data
+    }
+}
+
+/// wrap `data` in a gzip member without name and date.
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut result = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 2, 0xff];
+    result.extend(compress_to_vec(data, ARCHIVE_COMPRESSION_LEVEL));
+    result.extend(crc32(data).to_le_bytes());
+    result.extend((data.len() as u32).to_le_bytes());
+    result
+}
+
+impl Repository {
+    /// a `.tar.gz` archive of the files of a record, every path starts with `prefix`.
+    ///
+    /// the archive is reproducible: entries are sorted by path, owned by root and dated by
+    /// the record, so archiving a record always gives the same bytes. files of unknown
+    /// mode are archived as `0644`. paths with the `export-ignore` attribute are left out,
+    /// with everything under them.
+    pub async fn archive_record(
+        &self,
+        record_hash: &ObjectId,
+        prefix: &str,
+    ) -> Result<Vec<u8>, WsvcFsError> {
+        let record = self.read_record(record_hash).await?;
+        let mut tar = TarBuilder {
+            data: vec![],
+            mtime: record.date.timestamp().max(0) as u64,
+        };
+        let prefix = prefix.trim_matches('/');
+        let attributes = self.tree_attributes(&record.root).await?;
+        let mut entries = vec![];
+        // dirs by their path in the record, entries by their path in the archive.
+        let mut queue = vec![(record.root, String::new())];
+        while let Some((tree_hash, dir)) = queue.pop() {
+            for entry in self.read_tree(&tree_hash).await?.entries {
+                let path = join_path(&dir, &entry.name);
+                if attributes.get(&path).export_ignore {
+                    continue;
+                }
+                if entry.kind == EntryKind::Dir {
+                    queue.push((entry.hash.clone(), path.clone()));
+                }
+                entries.push((join_path(prefix, &path), entry));
+            }
+        }
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        if !prefix.is_empty() {
+            tar.append(&format!("{}/", prefix), 0o755, b'5', "", &[]);
+        }
+        for (path, entry) in entries {
+            match entry.kind {
+                EntryKind::Dir => tar.append(&format!("{}/", path), 0o755, b'5', "", &[]),
+                EntryKind::File => {
+                    let mode = if entry.mode == 0 { 0o644 } else { entry.mode };
+                    let content = self.read_blob(&entry.hash).await?;
+                    tar.append(&path, mode, b'0', "", &content);
+                }
+                EntryKind::Symlink => {
+                    let target = String::from_utf8(self.read_blob(&entry.hash).await?)
+                        .map_err(|_| WsvcFsError::InvalidFilename(path.clone()))?;
+                    tar.append(&path, 0o777, b'2', &target, &[]);
+                }
+            }
+        }
+        Ok(gzip(&tar.finish()))
+    }
+}
+
+impl ReadOnlyRepository {
+    pub async fn archive_record(
+        &self,
+        record_hash: &ObjectId,
+        prefix: &str,
+    ) -> Result<Vec<u8>, WsvcFsError> {
+        self.repository().archive_record(record_hash, prefix).await
+    }
+}
@@ -0,0 +1,176 @@
+use std::path::PathBuf;
+
+use blake3::Hash;
+use chrono::serde::ts_seconds::{deserialize as from_ts, serialize as to_ts};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{create_dir_all, read, read_dir, write};
+
+use crate::{
+    fs::WsvcFsError,
+    model::{ObjectId, Repository},
+    sign::{GpgKey, GpgVerifier, Signature, SignatureKind, Signer, Verifier},
+    WsvcError,
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+/// `Attestation` stand for a detached statement about a record, e.g. "reviewed-by".
+pub struct Attestation {
+    pub hash: ObjectId,
+    pub record: ObjectId,
+    pub kind: String,
+    pub author: String,
+    #[serde(deserialize_with = "from_ts", serialize_with = "to_ts")]
+    pub date: DateTime<Utc>,
+    /// signature of the attestation hash, not covered by the hash itself.
+    #[serde(default)]
+    pub signature: Option<Signature>,
+}
+
+impl Attestation {
+    /// calculate the hash of the attestation, `hash` and `signature` are excluded.
+    pub fn calc_hash(&self) -> Result<ObjectId, WsvcFsError> {
+        let attestation = Attestation {
+            hash: ObjectId(Hash::from([0; 32])),
+            signature: None,
+            ..self.clone()
+        };
+        Ok(ObjectId(blake3::hash(
+            serde_json::to_vec(&attestation)?.as_slice(),
+        )))
+    }
+
+    /// verify the signature of the attestation, `None` if not signed by `verifier`'s kind.
+    pub async fn verify(
+        &self,
+        repo: &Repository,
+        verifier: &(dyn Verifier + Sync),
+    ) -> Result<Option<bool>, WsvcError> {
+        let signature = match &self.signature {
+            Some(signature) if signature.kind == verifier.kind() => signature,
+            _ => return Ok(None),
+        };
+        if self.calc_hash()? != self.hash || signature.record != self.record {
+            return Ok(Some(false));
+        }
+        let payload = self.hash.0.to_hex().to_string();
+        Ok(Some(
+            verifier.verify(repo, payload.as_bytes(), signature).await?,
+        ))
+    }
+
+    /// the key of the gpg signature of the attestation, `None` if it is not signed with
+    /// gpg or the signature is invalid, see `GpgVerifier`.
+    pub async fn verified_gpg_key(
+        &self,
+        repo: &Repository,
+        verifier: &GpgVerifier,
+    ) -> Result<Option<GpgKey>, WsvcError> {
+        let signature = match &self.signature {
+            Some(signature) if signature.kind == SignatureKind::Gpg => signature,
+            _ => return Ok(None),
+        };
+        if self.calc_hash()? != self.hash || signature.record != self.record {
+            return Ok(None);
+        }
+        let payload = self.hash.0.to_hex().to_string();
+        Ok(verifier
+            .verified_key(repo, payload.as_bytes(), signature)
+            .await?
+            .filter(|key| key.matches(&signature.signer)))
+    }
+}
+
+impl Repository {
+    /// get the attestations folder of the repository.
+    pub async fn attestations_dir(&self) -> Result<PathBuf, WsvcFsError> {
+        let result = self.path.join("attestations");
+        if !result.exists() && !self.options.read_only {
+            create_dir_all(&result).await?;
+        }
+        Ok(result)
+    }
+
+    /// store an attestation to attestations dir.
+    pub async fn store_attestation(&self, attestation: &Attestation) -> Result<(), WsvcFsError> {
+        self.check_writable()?;
+        write(
+            self.attestations_dir()
+                .await?
+                .join(attestation.hash.0.to_hex().as_str()),
+            serde_json::to_vec(attestation)?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn attestation_exists(&self, hash: &ObjectId) -> Result<bool, WsvcFsError> {
+        Ok(self
+            .attestations_dir()
+            .await?
+            .join(hash.0.to_hex().as_str())
+            .exists())
+    }
+
+    /// get all attestations
+    pub async fn get_attestations(&self) -> Result<Vec<Attestation>, WsvcFsError> {
+        let mut result = Vec::new();
+        let attestations_dir = self.attestations_dir().await?;
+        if !attestations_dir.exists() {
+            return Ok(result);
+        }
+        let mut entries = read_dir(attestations_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_file() {
+                result.push(serde_json::from_slice(&read(entry.path()).await?)?);
+            }
+        }
+        Ok(result)
+    }
+
+    /// get all attestations of a record, sorted by date.
+    pub async fn get_attestations_of_record(
+        &self,
+        record_hash: &ObjectId,
+    ) -> Result<Vec<Attestation>, WsvcFsError> {
+        let mut result = self
+            .get_attestations()
+            .await?
+            .into_iter()
+            .filter(|a| a.record == *record_hash)
+            .collect::<Vec<_>>();
+        result.sort_by_key(|a| a.date);
+        Ok(result)
+    }
+
+    /// attest a record, the attestation will be signed if `signer` is given.
+    pub async fn attest_record(
+        &self,
+        record_hash: &ObjectId,
+        kind: impl AsRef<str>,
+        author: impl AsRef<str>,
+        signer: Option<&(dyn Signer + Sync)>,
+    ) -> Result<Attestation, WsvcError> {
+        let record = self.read_record(record_hash).await?;
+        let mut attestation = Attestation {
+            hash: ObjectId(Hash::from([0; 32])),
+            record: record.hash,
+            kind: kind.as_ref().to_string(),
+            author: author.as_ref().to_string(),
+            date: Utc::now(),
+            signature: None,
+        };
+        attestation.hash = attestation.calc_hash()?;
+        if let Some(signer) = signer {
+            let payload = attestation.hash.0.to_hex().to_string();
+            attestation.signature = Some(Signature {
+                record: attestation.record.clone(),
+                kind: signer.kind(),
+                signer: signer.identity(),
+                signature: signer.sign(payload.as_bytes()).await?,
+            });
+        }
+        self.store_attestation(&attestation).await?;
+        Ok(attestation)
+    }
+}
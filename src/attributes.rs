@@ -0,0 +1,478 @@
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    path::Path,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tokio::fs::{read, read_to_string, write};
+
+use crate::{
+    binary::is_binary,
+    filter::{BuiltinFilter, FilterDriver},
+    fs::{hash_file, join_path, WsvcFsError},
+    ignore::Pattern,
+    model::{ObjectId, Record, Repository, Tree},
+    readonly::ReadOnlyRepository,
+};
+
+/// file name of attributes files in the workspace, in the root dir or any sub dir.
+pub const ATTRIBUTES_FILE: &str = ".wsvcattributes";
+
+/// `AutoCrlf` stand for the line ending conversion of text files without an `eol`
+/// attribute, like `core.autocrlf` of git.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AutoCrlf {
+    /// files are recorded and checked out as they are, unless attributes say otherwise.
+    #[default]
+    False,
+    /// CRLF is recorded as LF, and LF is checked out as CRLF.
+    True,
+    /// CRLF is recorded as LF, files are checked out as recorded.
+    Input,
+}
+
+impl Serialize for AutoCrlf {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            AutoCrlf::False => serializer.serialize_bool(false),
+            AutoCrlf::True => serializer.serialize_bool(true),
+            AutoCrlf::Input => serializer.serialize_str("input"),
+        }
+    }
+}
+
+impl<'d> Deserialize<'d> for AutoCrlf {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'d>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Bool(bool),
+            Str(String),
+        }
+        match Repr::deserialize(deserializer)? {
+            Repr::Bool(true) => Ok(AutoCrlf::True),
+            Repr::Bool(false) => Ok(AutoCrlf::False),
+            Repr::Str(s) => match s.as_str() {
+                "true" => Ok(AutoCrlf::True),
+                "false" => Ok(AutoCrlf::False),
+                "input" => Ok(AutoCrlf::Input),
+                _ => Err(serde::de::Error::custom(format!(
+                    "invalid autocrlf: {}, expected true, false or input",
+                    s
+                ))),
+            },
+        }
+    }
+}
+
+/// `Eol` stand for the line ending of text files in the workspace.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Eol {
+    Lf,
+    Crlf,
+}
+
+/// `DiffAttribute` stand for how diffs show changes of a path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiffAttribute {
+    /// `diff`, line diffs even if the content looks binary.
+    Text,
+    /// `-diff` or `binary`, summarized like binary files.
+    Binary,
+    /// `diff=<name>`, summarized like binary files by the differ registered as `name`, see
+    /// `BinaryDiffers::register_driver`.
+    Driver(String),
+}
+
+/// `PathAttributes` stand for the attributes of a path, `None` or `false` if no line sets
+/// them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PathAttributes {
+    /// `text` marks text files, `-text` or `binary` marks binary files, which are never
+    /// converted. text is detected from the content if not set.
+    pub text: Option<bool>,
+    /// `eol=lf` or `eol=crlf`, the line ending text files are checked out with. files
+    /// with an `eol` are text unless `-text` is set.
+    pub eol: Option<Eol>,
+    /// `diff`, `-diff` or `diff=<name>`, binary files are detected from the content if not
+    /// set.
+    pub diff: Option<DiffAttribute>,
+    /// `lazy` marks large files whose blobs may be fetched when needed, checking out skips
+    /// them while their blobs are missing.
+    pub lazy: bool,
+    /// `export-ignore` leaves the path out of archives.
+    pub export_ignore: bool,
+    /// `filter=<name>`, the content filter run when recording and checking out the path,
+    /// see `FilterDriver`.
+    pub filter: Option<String>,
+}
+
+/// `AttributeRule` stand for a line of an attributes file.
+#[derive(Clone, Debug)]
+struct AttributeRule {
+    pattern: Pattern,
+    /// attribute names with their values, `None` for `-name`.
+    attributes: Vec<(String, Option<String>)>,
+}
+
+impl AttributeRule {
+    fn parse(base: &str, line: &str) -> Option<Self> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let mut words = line.split_whitespace();
+        let pattern = Pattern::parse(base, words.next()?)?;
+        let mut attributes = vec![];
+        for word in words {
+            match word {
+                // a macro of `-text -diff`.
+                "binary" => {
+                    attributes.push(("text".to_owned(), None));
+                    attributes.push(("diff".to_owned(), None));
+                }
+                _ => match (word.strip_prefix('-'), word.split_once('=')) {
+                    (Some(name), _) => attributes.push((name.to_owned(), None)),
+                    (None, Some((name, value))) => {
+                        attributes.push((name.to_owned(), Some(value.to_owned())))
+                    }
+                    (None, None) => attributes.push((word.to_owned(), Some(String::new()))),
+                },
+            }
+        }
+        Some(Self {
+            pattern,
+            attributes,
+        })
+    }
+}
+
+/// `Attributes` stand for per-path attributes of a workspace, and how line endings of
+/// text files are converted between records and the workspace.
+///
+/// a line of an attributes file is a pattern of the ignore file syntax, without `!`
+/// and trailing `/`, followed by attributes. later lines win, and lines of attributes
+/// files in sub dirs come after lines of their parents:
+///
+/// ```text
+/// *.sh        text eol=lf
+/// *.bat       text eol=crlf
+/// *.png       binary
+/// *.psd       lazy diff=psd
+/// /docs/draft export-ignore
+/// secrets/**  filter=encrypt
+/// *.ipynb     filter=strip-outputs
+/// ```
+///
+/// each attributes file is parsed once, when a walk enters its dir.
+///
+/// text files are recorded with LF line endings if they are marked `text`, have an
+/// `eol`, or `autocrlf` is not `false`. filters run before line endings are converted
+/// when recording, and after when checking out.
+#[derive(Clone, Debug, Default)]
+pub struct Attributes {
+    rules: Vec<AttributeRule>,
+    /// dirs whose attributes files are added, relative to the workspace root.
+    entered: HashSet<String>,
+    pub autocrlf: AutoCrlf,
+    /// filter drivers by name, builtin filters are found by name if not set.
+    pub filters: BTreeMap<String, FilterDriver>,
+    /// the record checked out, keywords of the `keywords` filter are expanded with it.
+    pub record: Option<Record>,
+}
+
+impl Attributes {
+    pub fn new(autocrlf: AutoCrlf) -> Self {
+        Self {
+            rules: vec![],
+            entered: HashSet::new(),
+            autocrlf,
+            filters: BTreeMap::new(),
+            record: None,
+        }
+    }
+
+    /// use the filter drivers `filters`.
+    pub fn with_filters(mut self, filters: BTreeMap<String, FilterDriver>) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    /// add the attributes file in `dir`, the dir `base` relative to the workspace root.
+    /// nothing if the dir is entered already or has no attributes file.
+    ///
+    /// walks call this entering a dir, so its lines apply to paths in it.
+    pub async fn enter_dir(&mut self, base: &str, dir: &Path) -> Result<(), WsvcFsError> {
+        if !self.entered.insert(base.to_owned()) {
+            return Ok(());
+        }
+        let path = dir.join(ATTRIBUTES_FILE);
+        if path.is_file() {
+            self.add_patterns(base, &read_to_string(path).await?);
+        }
+        Ok(())
+    }
+
+    /// enter the dirs of `workspace` along `path`, for a path not reached by a walk.
+    pub async fn enter_path(&mut self, workspace: &Path, path: &str) -> Result<(), WsvcFsError> {
+        self.enter_dir("", workspace).await?;
+        let components = path.split('/').collect::<Vec<_>>();
+        for depth in 1..components.len() {
+            let base = components[..depth].join("/");
+            self.enter_dir(&base, &workspace.join(&base)).await?;
+        }
+        Ok(())
+    }
+
+    /// add the attributes file of `tree`, the dir `base` of a record, nothing if the dir is
+    /// entered already or has no attributes file.
+    pub async fn enter_tree(
+        &mut self,
+        repo: &Repository,
+        base: &str,
+        tree: &Tree,
+    ) -> Result<(), WsvcFsError> {
+        if !self.entered.insert(base.to_owned()) {
+            return Ok(());
+        }
+        if let Some(file) = tree.files().find(|f| f.name == ATTRIBUTES_FILE) {
+            let data = repo.read_blob(&file.hash).await?;
+            self.add_patterns(base, &String::from_utf8_lossy(&data));
+        }
+        Ok(())
+    }
+
+    /// add the lines of an attributes file in dir `base`, relative to the workspace root.
+    pub fn add_patterns(&mut self, base: &str, content: &str) {
+        self.rules.extend(
+            content
+                .lines()
+                .filter_map(|line| AttributeRule::parse(base, line)),
+        );
+    }
+
+    /// the value of attribute `name` of `path`, `Some(None)` if it is unset by `-name`.
+    fn value(&self, path: &str, name: &str) -> Option<Option<&str>> {
+        self.rules
+            .iter()
+            .rev()
+            .filter(|rule| rule.pattern.matches(path))
+            .find_map(|rule| {
+                rule.attributes
+                    .iter()
+                    .rev()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, v)| v.as_deref())
+            })
+    }
+
+    /// attributes of a path relative to the workspace root, `/` separated.
+    pub fn get(&self, path: &str) -> PathAttributes {
+        PathAttributes {
+            text: self.value(path, "text").map(|v| v.is_some()),
+            eol: match self.value(path, "eol") {
+                Some(Some("lf")) => Some(Eol::Lf),
+                Some(Some("crlf")) => Some(Eol::Crlf),
+                _ => None,
+            },
+            diff: match self.value(path, "diff") {
+                Some(None) => Some(DiffAttribute::Binary),
+                Some(Some("")) => Some(DiffAttribute::Text),
+                Some(Some(name)) => Some(DiffAttribute::Driver(name.to_owned())),
+                None => None,
+            },
+            lazy: self.value(path, "lazy").flatten().is_some(),
+            export_ignore: self.value(path, "export-ignore").flatten().is_some(),
+            filter: match self.value(path, "filter") {
+                Some(Some(name)) if !name.is_empty() => Some(name.to_owned()),
+                _ => None,
+            },
+        }
+    }
+
+    /// the filter driver of `path`, `None` if it has no `filter` or the filter is neither
+    /// set nor builtin, like git leaves paths of undefined filters as they are. a driver
+    /// named after a builtin filter without commands configures the builtin filter.
+    pub fn driver(&self, path: &str) -> Option<FilterDriver> {
+        let name = self.get(path).filter?;
+        match self.filters.get(&name) {
+            // a driver named after a builtin filter without commands sets its options,
+            // e.g. the salt of `encrypt`.
+            Some(driver)
+                if driver.builtin.is_none()
+                    && driver.clean.is_none()
+                    && driver.smudge.is_none() =>
+            {
+                Some(FilterDriver {
+                    builtin: BuiltinFilter::from_name(&name),
+                    ..driver.clone()
+                })
+            }
+            Some(driver) => Some(driver.clone()),
+            None => BuiltinFilter::from_name(&name).map(FilterDriver::builtin),
+        }
+    }
+
+    /// whether `path` has keywords expanded with the record checked out, so checking out
+    /// another record changes it even if its content is the same.
+    pub fn stamps(&self, path: &str) -> bool {
+        self.record.is_some()
+            && self
+                .driver(path)
+                .is_some_and(|driver| driver.builtin == Some(BuiltinFilter::Keywords))
+    }
+
+    /// whether `path` is filtered or its line endings may be converted, so its content
+    /// has to be read.
+    pub fn converts(&self, path: &str) -> bool {
+        let attributes = self.get(path);
+        if self.driver(path).is_some() {
+            return true;
+        }
+        match attributes.text {
+            Some(text) => text,
+            None => attributes.eol.is_some() || self.autocrlf != AutoCrlf::False,
+        }
+    }
+
+    /// whether `path` with content `data` is converted as a text file.
+    pub fn is_text(&self, path: &str, data: &[u8]) -> bool {
+        match self.get(path).text {
+            Some(text) => text,
+            None => self.converts(path) && !is_binary(data),
+        }
+    }
+
+    /// the content of a workspace file as recorded, CRLF of text files becomes LF.
+    pub fn to_record(&self, path: &str, data: Vec<u8>) -> Vec<u8> {
+        if !self.is_text(path, &data) || !data.contains(&b'\r') {
+            return data;
+        }
+        let mut result = Vec::with_capacity(data.len());
+        for (i, &b) in data.iter().enumerate() {
+            if b == b'\r' && data.get(i + 1) == Some(&b'\n') {
+                continue;
+            }
+            result.push(b);
+        }
+        result
+    }
+
+    /// the content of a recorded file as checked out, LF of text files becomes CRLF if
+    /// the `eol` is `crlf`, or there is no `eol` and `autocrlf` is `true`.
+    pub fn to_workspace(&self, path: &str, data: Vec<u8>) -> Vec<u8> {
+        let crlf = match self.get(path).eol {
+            Some(eol) => eol == Eol::Crlf,
+            None => self.autocrlf == AutoCrlf::True,
+        };
+        if !crlf || !self.is_text(path, &data) {
+            return data;
+        }
+        let mut result = Vec::with_capacity(data.len() + data.len() / 16);
+        for (i, &b) in data.iter().enumerate() {
+            if b == b'\n' && (i == 0 || data[i - 1] != b'\r') {
+                result.push(b'\r');
+            }
+            result.push(b);
+        }
+        result
+    }
+
+    /// the content of a workspace file as recorded, filtered by the `clean` of its filter
+    /// and with line endings converted.
+    pub async fn clean(&self, path: &str, data: Vec<u8>) -> Result<Vec<u8>, WsvcFsError> {
+        let data = match self.driver(path) {
+            Some(driver) => driver.clean(path, data).await?,
+            None => data,
+        };
+        Ok(self.to_record(path, data))
+    }
+
+    /// the content of a recorded file as checked out, with line endings converted and
+    /// filtered by the `smudge` of its filter.
+    pub async fn smudge(&self, path: &str, data: Vec<u8>) -> Result<Vec<u8>, WsvcFsError> {
+        let data = self.to_workspace(path, data);
+        match self.driver(path) {
+            Some(driver) => driver.smudge(path, data, self.record.as_ref()).await,
+            None => Ok(data),
+        }
+    }
+
+    /// the blob hash of the workspace file at `full_path` as it would be recorded, `path`
+    /// is relative to the workspace root.
+    pub(crate) async fn hash_file(
+        &self,
+        full_path: &Path,
+        path: &str,
+    ) -> Result<ObjectId, WsvcFsError> {
+        if !self.converts(path) {
+            return hash_file(full_path).await;
+        }
+        let data = self.clean(path, read(full_path).await?).await?;
+        Ok(ObjectId(blake3::hash(&data)))
+    }
+
+    /// filter and convert the line endings of a file just checked out to `full_path`.
+    pub(crate) async fn smudge_file(
+        &self,
+        full_path: &Path,
+        path: &str,
+    ) -> Result<(), WsvcFsError> {
+        if !self.converts(path) {
+            return Ok(());
+        }
+        let data = read(full_path).await?;
+        let converted = self.smudge(path, data.clone()).await?;
+        if converted != data {
+            write(full_path, converted).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Repository {
+    /// attributes without any attributes file, with the options of the repository.
+    pub(crate) fn new_attributes(&self) -> Attributes {
+        Attributes::new(self.options.autocrlf).with_filters(self.options.filters.clone())
+    }
+
+    /// attributes of the root attributes file of `workspace`, walks enter sub dirs.
+    pub async fn load_attributes(&self, workspace: &Path) -> Result<Attributes, WsvcFsError> {
+        let mut result = self.new_attributes();
+        result.enter_dir("", workspace).await?;
+        Ok(result)
+    }
+
+    /// attributes of all attributes files of the tree `root`, for reading the files of a
+    /// record without walking it, e.g. diffs.
+    pub async fn tree_attributes(&self, root: &ObjectId) -> Result<Attributes, WsvcFsError> {
+        let mut result = self.new_attributes();
+        // parents are entered before their sub dirs.
+        let mut queue = VecDeque::from([(root.clone(), String::new())]);
+        while let Some((tree_hash, dir)) = queue.pop_front() {
+            let tree = self.read_tree(&tree_hash).await?;
+            result.enter_tree(self, &dir, &tree).await?;
+            for entry in tree.dirs() {
+                queue.push_back((entry.hash.clone(), join_path(&dir, &entry.name)));
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl ReadOnlyRepository {
+    pub async fn load_attributes(&self, workspace: &Path) -> Result<Attributes, WsvcFsError> {
+        self.repository().load_attributes(workspace).await
+    }
+
+    pub async fn tree_attributes(&self, root: &ObjectId) -> Result<Attributes, WsvcFsError> {
+        self.repository().tree_attributes(root).await
+    }
+}
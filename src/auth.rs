@@ -0,0 +1,71 @@
+use std::fmt::Debug;
+
+use serde::{Deserialize, Serialize};
+
+/// `SyncCredential` stand for the account and password a client authenticates with when a
+/// server requires auth, sent in the identity round of a sync.
+///
+/// the password is sent as is, so servers requiring auth should be reached over `wss://`,
+/// `quic://` or with a passphrase.
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+pub struct SyncCredential {
+    pub account: String,
+    pub password: String,
+}
+
+impl Debug for SyncCredential {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SyncCredential")
+            .field("account", &self.account)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+/// `AuthResult` stand for the answer of a server requiring auth to the credential of a
+/// client, sent right after the identity of the client. the server closes the sync if the
+/// credential is not accepted.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct AuthResult {
+    pub accepted: bool,
+    /// whether the client may only read, the server refuses anything the client pushes.
+    #[serde(default)]
+    pub read_only: bool,
+    /// why the credential was not accepted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// `LoginRequest` stand for the account and password `wsvc login` exchanges for a token at
+/// the login endpoint beside the sync of a server.
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+pub struct LoginRequest {
+    pub account: String,
+    pub password: String,
+}
+
+impl Debug for LoginRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoginRequest")
+            .field("account", &self.account)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+/// `LoginResponse` stand for a token issued by a server, sent as the password of the
+/// account by later syncs.
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+pub struct LoginResponse {
+    pub account: String,
+    pub token: String,
+}
+
+impl Debug for LoginResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoginResponse")
+            .field("account", &self.account)
+            .field("token", &"<redacted>")
+            .finish()
+    }
+}
@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    attributes::{Attributes, DiffAttribute},
+    diff::Change,
+    fs::WsvcFsError,
+    model::{ObjectId, Repository},
+    readonly::ReadOnlyRepository,
+};
+
+/// leading bytes checked for NUL to tell binary content from text, the same as git.
+pub const SNIFF_LEN: usize = 8000;
+
+/// whether `data` is binary, i.e. it has a NUL byte in its leading bytes.
+pub fn is_binary(data: &[u8]) -> bool {
+    data[..data.len().min(SNIFF_LEN)].contains(&0)
+}
+
+/// whether `data`, leading bytes of a file, is utf-8 text a browser may show, i.e. not
+/// binary and valid utf-8 but maybe for a char cut at its end.
+pub fn is_text(data: &[u8]) -> bool {
+    !is_binary(data)
+        && match std::str::from_utf8(data) {
+            Ok(_) => true,
+            Err(err) => err.error_len().is_none(),
+        }
+}
+
+/// leading bytes of formats told by their content, checked in order.
+const MAGIC: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"\0\0\x01\0", "image/x-icon"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"PK\x05\x06", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (b"\x28\xb5\x2f\xfd", "application/zstd"),
+    (b"\0asm", "application/wasm"),
+    (b"\x7fELF", "application/x-elf"),
+    (b"OggS", "audio/ogg"),
+    (b"fLaC", "audio/flac"),
+];
+
+/// mime types of text files told by their extension.
+const TEXT_EXTENSIONS: &[(&str, &str)] = &[
+    ("md", "text/markdown"),
+    ("markdown", "text/markdown"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("svg", "image/svg+xml"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("json", "application/json"),
+    ("xml", "application/xml"),
+    ("csv", "text/csv"),
+];
+
+/// the mime type of `data`, the leading bytes of a file named `name` if known.
+///
+/// binary formats are told by their content, so a renamed image is still an image and a
+/// binary file named `.md` is not markdown. text files are told by the extension of
+/// `name`, or by their content for html and svg, and are `text/plain` otherwise.
+pub fn sniff_mime(data: &[u8], name: Option<&str>) -> &'static str {
+    if let Some((_, mime)) = MAGIC.iter().find(|(magic, _)| data.starts_with(magic)) {
+        return mime;
+    }
+    if data.len() >= 12 && &data[..4] == b"RIFF" {
+        match &data[8..12] {
+            b"WEBP" => return "image/webp",
+            b"WAVE" => return "audio/wav",
+            _ => {}
+        }
+    }
+    if data.get(4..8) == Some(b"ftyp") {
+        return "video/mp4";
+    }
+    if data.get(257..262) == Some(b"ustar") {
+        return "application/x-tar";
+    }
+    if !is_text(data) {
+        // short magics only tell binary content, `BM` may start a text file too.
+        return match data {
+            [b'B', b'M', ..] => "image/bmp",
+            [b'I', b'D', b'3', ..] => "audio/mpeg",
+            _ => "application/octet-stream",
+        };
+    }
+    let extension = name
+        .and_then(|name| name.rsplit_once('.'))
+        .map(|(_, extension)| extension.to_lowercase());
+    if let Some((_, mime)) = TEXT_EXTENSIONS
+        .iter()
+        .find(|(known, _)| extension.as_deref() == Some(*known))
+    {
+        return mime;
+    }
+    // markup is told by content too, so it is never served as plain text by mistake.
+    let head = String::from_utf8_lossy(&data[..data.len().min(512)]).to_lowercase();
+    let head = head.trim_start_matches('\u{feff}').trim_start();
+    if head.starts_with("<!doctype html") || head.starts_with("<html") {
+        return "text/html";
+    }
+    if head.starts_with("<svg") || (head.starts_with("<?xml") && head.contains("<svg")) {
+        return "image/svg+xml";
+    }
+    "text/plain"
+}
+
+fn be_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn le_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn le_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// `BinaryDiffer` stand for a summarizer of a binary format, e.g. the dimensions of
+/// images, so diffs of binary files tell more than their hashes.
+pub trait BinaryDiffer: Send + Sync {
+    /// a short summary of `data`, e.g. `PNG image 640x480`, `None` if `data` is not of the
+    /// format of this differ.
+    fn summarize(&self, data: &[u8]) -> Option<String>;
+}
+
+/// `PngDiffer` stand for the dimensions of PNG images.
+pub struct PngDiffer;
+
+impl BinaryDiffer for PngDiffer {
+    fn summarize(&self, data: &[u8]) -> Option<String> {
+        if !data.starts_with(b"\x89PNG\r\n\x1a\n") || data.get(12..16)? != b"IHDR" {
+            return None;
+        }
+        Some(format!(
+            "PNG image {}x{}",
+            be_u32(data, 16)?,
+            be_u32(data, 20)?
+        ))
+    }
+}
+
+/// `GifDiffer` stand for the dimensions of GIF images.
+pub struct GifDiffer;
+
+impl BinaryDiffer for GifDiffer {
+    fn summarize(&self, data: &[u8]) -> Option<String> {
+        if !data.starts_with(b"GIF87a") && !data.starts_with(b"GIF89a") {
+            return None;
+        }
+        Some(format!(
+            "GIF image {}x{}",
+            le_u16(data, 6)?,
+            le_u16(data, 8)?
+        ))
+    }
+}
+
+/// `JpegDiffer` stand for the dimensions of JPEG images, read from the first frame header.
+pub struct JpegDiffer;
+
+impl BinaryDiffer for JpegDiffer {
+    fn summarize(&self, data: &[u8]) -> Option<String> {
+        if !data.starts_with(&[0xff, 0xd8]) {
+            return None;
+        }
+        let mut at = 2;
+        loop {
+            if *data.get(at)? != 0xff {
+                return None;
+            }
+            let marker = *data.get(at + 1)?;
+            match marker {
+                // fill bytes before a marker.
+                0xff => at += 1,
+                // markers without a segment.
+                0x01 | 0xd0..=0xd9 => at += 2,
+                // frame headers, except DHT, JPG and DAC sharing the range.
+                0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                    return Some(format!(
+                        "JPEG image {}x{}",
+                        be_u16(data, at + 7)?,
+                        be_u16(data, at + 5)?
+                    ));
+                }
+                _ => at += 2 + be_u16(data, at + 2)? as usize,
+            }
+        }
+    }
+}
+
+/// `ZipDiffer` stand for the entry count of zip archives, read from the end of the
+/// central directory.
+pub struct ZipDiffer;
+
+impl BinaryDiffer for ZipDiffer {
+    fn summarize(&self, data: &[u8]) -> Option<String> {
+        if !data.starts_with(b"PK\x03\x04") && !data.starts_with(b"PK\x05\x06") {
+            return None;
+        }
+        // the end record is 22 bytes with a comment of at most 65535 bytes after it.
+        let start = data.len().saturating_sub(22 + 65535);
+        let end = data.len().checked_sub(22)?;
+        let at = (start..=end)
+            .rev()
+            .find(|&i| data[i..].starts_with(b"PK\x05\x06"))?;
+        Some(format!("zip archive, {} entries", le_u16(data, at + 10)?))
+    }
+}
+
+/// `TarDiffer` stand for the entry count of tar archives.
+pub struct TarDiffer;
+
+impl BinaryDiffer for TarDiffer {
+    fn summarize(&self, data: &[u8]) -> Option<String> {
+        if data.get(257..262)? != b"ustar" {
+            return None;
+        }
+        let mut entries = 0;
+        let mut at = 0;
+        while let Some(header) = data.get(at..at + 512) {
+            // the archive ends with zero blocks.
+            if header[0] == 0 {
+                break;
+            }
+            let size = std::str::from_utf8(&header[124..136])
+                .ok()
+                .map(|s| s.trim_matches(|c: char| c == '\0' || c == ' '))
+                .and_then(|s| u64::from_str_radix(s, 8).ok())?;
+            entries += 1;
+            at += 512 + size.div_ceil(512) as usize * 512;
+        }
+        Some(format!("tar archive, {} entries", entries))
+    }
+}
+
+/// `GzipDiffer` stand for the uncompressed size of gzip files, modulo 2^32 as stored.
+pub struct GzipDiffer;
+
+impl BinaryDiffer for GzipDiffer {
+    fn summarize(&self, data: &[u8]) -> Option<String> {
+        if !data.starts_with(&[0x1f, 0x8b]) {
+            return None;
+        }
+        Some(format!(
+            "gzip compressed, {} bytes uncompressed",
+            le_u32(data, data.len().checked_sub(4)?)?
+        ))
+    }
+}
+
+/// `BinaryDiffers` stand for the registry of binary differs, the summary of the first
+/// differ knowing the format is used.
+///
+/// the default registry knows PNG, GIF and JPEG images, zip, tar and gzip archives.
+/// registered differs are asked before the ones registered earlier:
+///
+/// ```ignore
+/// struct WasmDiffer;
+///
+/// impl BinaryDiffer for WasmDiffer {
+///     fn summarize(&self, data: &[u8]) -> Option<String> {
+///         data.starts_with(b"\0asm").then(|| "wasm module".to_owned())
+///     }
+/// }
+///
+/// let mut differs = BinaryDiffers::default();
+/// differs.register(WasmDiffer);
+/// ```
+///
+/// differs registered as drivers are only asked for paths with their `diff=<name>`
+/// attribute, see `Attributes`. the default drivers are `png`, `gif`, `jpeg`, `zip`, `tar`
+/// and `gzip`.
+pub struct BinaryDiffers {
+    differs: Vec<Box<dyn BinaryDiffer>>,
+    drivers: HashMap<String, Box<dyn BinaryDiffer>>,
+}
+
+impl Default for BinaryDiffers {
+    fn default() -> Self {
+        let mut result = Self::empty();
+        result
+            .register(GzipDiffer)
+            .register(TarDiffer)
+            .register(ZipDiffer)
+            .register(JpegDiffer)
+            .register(GifDiffer)
+            .register(PngDiffer)
+            .register_driver("gzip", GzipDiffer)
+            .register_driver("tar", TarDiffer)
+            .register_driver("zip", ZipDiffer)
+            .register_driver("jpeg", JpegDiffer)
+            .register_driver("gif", GifDiffer)
+            .register_driver("png", PngDiffer);
+        result
+    }
+}
+
+impl BinaryDiffers {
+    /// a registry without differs, binary files are only summarized by size and hash.
+    pub fn empty() -> Self {
+        Self {
+            differs: vec![],
+            drivers: HashMap::new(),
+        }
+    }
+
+    /// register a differ, it is asked before the registered ones.
+    pub fn register(&mut self, differ: impl BinaryDiffer + 'static) -> &mut Self {
+        self.differs.insert(0, Box::new(differ));
+        self
+    }
+
+    /// register a differ as the driver `name`, replacing the driver of the same name.
+    pub fn register_driver(
+        &mut self,
+        name: impl Into<String>,
+        differ: impl BinaryDiffer + 'static,
+    ) -> &mut Self {
+        self.drivers.insert(name.into(), Box::new(differ));
+        self
+    }
+
+    /// the summary of the first differ knowing the format of `data`.
+    pub fn summarize(&self, data: &[u8]) -> Option<String> {
+        self.differs.iter().find_map(|d| d.summarize(data))
+    }
+
+    /// the summary of the driver `name`, `None` if there is no such driver.
+    pub fn summarize_with(&self, name: &str, data: &[u8]) -> Option<String> {
+        self.drivers.get(name)?.summarize(data)
+    }
+}
+
+/// `BinarySummary` stand for one side of a binary file change.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct BinarySummary {
+    pub hash: ObjectId,
+    pub size: u64,
+    /// the summary of a differ, `None` if no differ knows the format.
+    pub summary: Option<String>,
+}
+
+/// `BinaryDiff` stand for a change of a binary file, a side is `None` if the file is
+/// added or removed.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct BinaryDiff {
+    pub old: Option<BinarySummary>,
+    pub new: Option<BinarySummary>,
+}
+
+impl BinaryDiff {
+    /// size of the new side minus size of the old side.
+    pub fn size_delta(&self) -> i64 {
+        let size = |s: &Option<BinarySummary>| s.as_ref().map(|s| s.size as i64).unwrap_or(0);
+        size(&self.new) - size(&self.old)
+    }
+}
+
+impl Repository {
+    /// summarize a change of a binary file with `differs`, `None` if neither side is
+    /// binary or the content did not change.
+    ///
+    /// the `diff` attribute of the path in `attributes` overrides whether it is binary,
+    /// `diff=<name>` summarizes it with the driver `name` only.
+    pub async fn binary_diff(
+        &self,
+        change: &Change,
+        differs: &BinaryDiffers,
+        attributes: &Attributes,
+    ) -> Result<Option<BinaryDiff>, WsvcFsError> {
+        let diff = attributes.get(change.path()).diff;
+        if diff == Some(DiffAttribute::Text) {
+            return Ok(None);
+        }
+        let (old, new) = match change {
+            Change::Added { hash, .. } => (None, Some(hash)),
+            Change::Removed { hash, .. } => (Some(hash), None),
+            Change::Modified {
+                old_hash, new_hash, ..
+            }
+            | Change::Moved {
+                old_hash, new_hash, ..
+            } if old_hash != new_hash => (Some(old_hash), Some(new_hash)),
+            _ => return Ok(None),
+        };
+        let mut binary = diff.is_some();
+        let mut side = |hash: Option<&ObjectId>, data: Option<Vec<u8>>| {
+            let data = data?;
+            binary |= is_binary(&data);
+            Some(BinarySummary {
+                hash: hash?.clone(),
+                size: data.len() as u64,
+                summary: match &diff {
+                    Some(DiffAttribute::Driver(name)) => differs.summarize_with(name, &data),
+                    _ => differs.summarize(&data),
+                },
+            })
+        };
+        let old_data = match old {
+            Some(hash) => Some(self.read_blob(hash).await?),
+            None => None,
+        };
+        let new_data = match new {
+            Some(hash) => Some(self.read_blob(hash).await?),
+            None => None,
+        };
+        let result = BinaryDiff {
+            old: side(old, old_data),
+            new: side(new, new_data),
+        };
+        Ok(binary.then_some(result))
+    }
+}
+
+impl ReadOnlyRepository {
+    pub async fn binary_diff(
+        &self,
+        change: &Change,
+        differs: &BinaryDiffers,
+        attributes: &Attributes,
+    ) -> Result<Option<BinaryDiff>, WsvcFsError> {
+        self.repository()
+            .binary_diff(change, differs, attributes)
+            .await
+    }
+}
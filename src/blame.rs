@@ -0,0 +1,143 @@
+use std::collections::{btree_map::Entry, BTreeMap, BinaryHeap, HashMap};
+
+use blake3::Hash;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    binary::is_binary,
+    model::{EntryKind, ObjectId, Record, Repository},
+    patch::equal_lines,
+    readonly::ReadOnlyRepository,
+    WsvcError,
+};
+
+/// `BlameLine` stand for a line of a file with the record that last changed it.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct BlameLine {
+    /// the record that added the line in its current form.
+    pub record: ObjectId,
+    /// line number in the file of that record, starting at 1.
+    pub original_line: usize,
+    pub text: String,
+}
+
+/// `Blame` stand for the lines of a file of a record, each with the record that last
+/// changed it.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Blame {
+    pub path: String,
+    pub lines: Vec<BlameLine>,
+    /// records lines are attributed to, by their hash.
+    pub records: BTreeMap<String, Record>,
+}
+
+impl Repository {
+    /// attribute each line of the file at `path` of record `start` to the record that last
+    /// changed it.
+    ///
+    /// records are visited from `start` towards their ancestors, descendants first. lines
+    /// a record kept from a parent, by the line diff of both files, are passed on to that
+    /// parent, so merges attribute lines to the parent they came from. other lines are
+    /// attributed to the record.
+    pub async fn blame(&self, start: &ObjectId, path: &str) -> Result<Blame, WsvcError> {
+        let graph = self.record_graph().await?;
+        let text = self.blame_text(start, path).await?.ok_or_else(|| {
+            WsvcError::BadUsage(format!(
+                "{} is not a text file of record {}",
+                path,
+                &start.0.to_hex()[0..6]
+            ))
+        })?;
+        let mut lines = text
+            .lines()
+            .map(|line| BlameLine {
+                record: start.clone(),
+                original_line: 0,
+                text: line.to_owned(),
+            })
+            .collect::<Vec<_>>();
+        // lines still to attribute, by record, as (line index in that record, index in
+        // `lines`).
+        let mut pending = HashMap::<ObjectId, Vec<(usize, usize)>>::new();
+        pending.insert(start.clone(), (0..lines.len()).map(|i| (i, i)).collect());
+        let mut texts = HashMap::from([(start.clone(), Some(text))]);
+        // the largest generation first, so records are visited after all their children.
+        let key = |hash: &ObjectId| (graph.generation(hash).unwrap_or(0), *hash.0.as_bytes());
+        let mut queue = BinaryHeap::from([key(start)]);
+        while let Some((_, hash)) = queue.pop() {
+            let hash = ObjectId(Hash::from(hash));
+            let Some(mut remaining) = pending.remove(&hash) else {
+                continue;
+            };
+            let text = texts[&hash].clone().unwrap_or_default();
+            let own = text.lines().collect::<Vec<_>>();
+            for parent in graph.parents(&hash) {
+                if remaining.is_empty() {
+                    break;
+                }
+                if !texts.contains_key(parent) {
+                    let parent_text = self.blame_text(parent, path).await?;
+                    texts.insert(parent.clone(), parent_text);
+                }
+                let Some(parent_text) = &texts[parent] else {
+                    continue;
+                };
+                let kept = equal_lines(&own, &parent_text.lines().collect::<Vec<_>>())
+                    .into_iter()
+                    .collect::<HashMap<_, _>>();
+                let passed = pending.entry(parent.clone()).or_default();
+                let before = passed.len();
+                remaining.retain(|(line, index)| match kept.get(line) {
+                    Some(parent_line) => {
+                        passed.push((*parent_line, *index));
+                        false
+                    }
+                    None => true,
+                });
+                if passed.len() > before {
+                    queue.push(key(parent));
+                }
+            }
+            for (line, index) in remaining {
+                lines[index].record = hash.clone();
+                lines[index].original_line = line + 1;
+            }
+        }
+        let mut records = BTreeMap::new();
+        for line in &lines {
+            let key = line.record.0.to_hex().to_string();
+            if let Entry::Vacant(entry) = records.entry(key) {
+                entry.insert(self.read_record(&line.record).await?);
+            }
+        }
+        Ok(Blame {
+            path: path.to_owned(),
+            lines,
+            records,
+        })
+    }
+
+    /// the text of the file at `path` of a record, `None` if there is no such file or it is
+    /// binary or not UTF-8.
+    async fn blame_text(
+        &self,
+        record_hash: &ObjectId,
+        path: &str,
+    ) -> Result<Option<String>, WsvcError> {
+        let entry = match self.resolve_path(record_hash, path).await? {
+            Some(entry) if entry.kind == EntryKind::File => entry,
+            _ => return Ok(None),
+        };
+        let data = self.read_blob(&entry.hash).await?;
+        if is_binary(&data) {
+            return Ok(None);
+        }
+        Ok(String::from_utf8(data).ok())
+    }
+}
+
+impl ReadOnlyRepository {
+    pub async fn blame(&self, start: &ObjectId, path: &str) -> Result<Blame, WsvcError> {
+        self.repository().blame(start, path).await
+    }
+}
@@ -0,0 +1,69 @@
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+
+use crate::{
+    fs::WsvcFsError,
+    model::{ObjectId, Repository},
+};
+
+/// file marking a blobless repository in the repository dir, see `Repository::set_blobless`.
+pub const BLOBLESS_FILE: &str = "blobless";
+
+/// `BlobSource` fetches blobs a blobless repository did not sync, e.g. from the blobs
+/// endpoint beside the sync of its remote origin. set on `RepoOptions::blob_source` or
+/// with `RepositoryBuilder::blob_source`.
+#[async_trait]
+pub trait BlobSource: Send + Sync + Debug {
+    /// the content of the blob `hash` of `repo`.
+    async fn fetch(&self, repo: &Repository, hash: &ObjectId) -> Result<Vec<u8>, WsvcFsError>;
+}
+
+impl Repository {
+    /// whether the repository syncs records and trees without their blobs, fetching blobs
+    /// when they are read.
+    pub fn is_blobless(&self) -> bool {
+        self.path.join(BLOBLESS_FILE).exists()
+    }
+
+    /// make the repository blobless, later syncs receive no blobs. blobs are fetched from
+    /// the blob source of the repository when they are read, e.g. by a checkout.
+    pub async fn set_blobless(&self) -> Result<(), WsvcFsError> {
+        self.check_writable()?;
+        tokio::fs::write(self.path.join(BLOBLESS_FILE), b"").await?;
+        Ok(())
+    }
+
+    /// fetch the blob `hash` from the blob source and store it, if the repository is
+    /// blobless and does not have it. returns whether it was fetched. a fetched blob not
+    /// matching its hash is refused.
+    pub async fn fetch_blob_on_demand(&self, hash: &ObjectId) -> Result<bool, WsvcFsError> {
+        if !self.is_blobless() || self.blob_exists(hash).await? {
+            return Ok(false);
+        }
+        let Some(source) = self.options.blob_source.clone() else {
+            return Err(WsvcFsError::Os(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!(
+                    "blob {} is not fetched yet and the repository has no blob source",
+                    hash.0.to_hex()
+                ),
+            )));
+        };
+        let data = source.fetch(self, hash).await?;
+        if blake3::hash(&data) != hash.0 {
+            return Err(WsvcFsError::ObjectMismatch(hash.0.to_hex().to_string()));
+        }
+        self.store_blob_data(&data).await?;
+        Ok(true)
+    }
+
+    /// fetch the blob `hash` on demand if the repository has a blob source, before reading
+    /// it.
+    pub(crate) async fn ensure_blob(&self, hash: &ObjectId) -> Result<(), WsvcFsError> {
+        if self.options.blob_source.is_some() {
+            self.fetch_blob_on_demand(hash).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,398 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs::{create_dir_all, read_dir, remove_dir_all};
+
+use crate::{
+    events::EventKind,
+    fs::WsvcFsError,
+    model::{ObjectId, Record, Repository},
+    readonly::ReadOnlyRepository,
+    refs::LOCK_SUFFIX,
+    tag::check_tag_name,
+    WsvcError,
+};
+
+/// dir of branches in the repository, a branch is the ref `refs/<name>`.
+pub const BRANCHES_DIR: &str = "refs";
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+/// `Branch` stand for a named line of records, advanced by commits while HEAD is on it.
+pub struct Branch {
+    pub name: String,
+    pub record: ObjectId,
+}
+
+/// `SyncBranch` stand for a branch pushed by sync.
+///
+/// a branch with a `lease` replaces the branch of the same name even if it is not a
+/// descendant, but only while that branch is still at the lease, so a force push never
+/// drops records pushed meanwhile by others.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SyncBranch {
+    #[serde(flatten)]
+    pub branch: Branch,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lease: Option<ObjectId>,
+}
+
+/// `HeadRef` stand for what HEAD points to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HeadRef {
+    /// HEAD is a record, `None` if it is not set yet.
+    Record(Option<ObjectId>),
+    /// HEAD is on a branch, the record is `None` before the first commit on it.
+    Branch {
+        name: String,
+        record: Option<ObjectId>,
+    },
+}
+
+impl HeadRef {
+    /// the HEAD record.
+    pub fn record(&self) -> Option<&ObjectId> {
+        match self {
+            HeadRef::Record(record) | HeadRef::Branch { record, .. } => record.as_ref(),
+        }
+    }
+
+    /// the branch HEAD is on.
+    pub fn branch(&self) -> Option<&str> {
+        match self {
+            HeadRef::Branch { name, .. } => Some(name),
+            HeadRef::Record(_) => None,
+        }
+    }
+
+    /// the ref moved by commits, the branch HEAD is on or HEAD itself.
+    pub fn target(&self) -> String {
+        match self {
+            HeadRef::Branch { name, .. } => branch_ref(name),
+            HeadRef::Record(_) => "HEAD".to_owned(),
+        }
+    }
+}
+
+/// the ref name of branch `name`.
+pub fn branch_ref(name: &str) -> String {
+    format!("{}/{}", BRANCHES_DIR, name)
+}
+
+/// check whether `name` can name a branch, the same rules as tag names.
+pub fn check_branch_name(name: &str) -> Result<(), WsvcError> {
+    check_tag_name(name).map_err(|_| WsvcError::BadUsage(format!("invalid branch name: {}", name)))
+}
+
+impl Repository {
+    /// get the branches folder of the repository.
+    pub async fn branches_dir(&self) -> Result<PathBuf, WsvcFsError> {
+        let result = self.path.join(BRANCHES_DIR);
+        if !result.exists() && !self.options.read_only {
+            create_dir_all(&result).await?;
+        }
+        Ok(result)
+    }
+
+    /// what HEAD points to.
+    pub async fn read_head(&self) -> Result<HeadRef, WsvcFsError> {
+        let record = self.read_ref("HEAD").await?;
+        Ok(match self.read_symbolic_ref("HEAD").await? {
+            Some(target) => HeadRef::Branch {
+                name: target
+                    .strip_prefix(&format!("{}/", BRANCHES_DIR))
+                    .unwrap_or(&target)
+                    .to_owned(),
+                record,
+            },
+            None => HeadRef::Record(record),
+        })
+    }
+
+    /// get all branches, sorted by name.
+    pub async fn get_branches(&self) -> Result<Vec<Branch>, WsvcFsError> {
+        let mut result = Vec::new();
+        let dir = self.branches_dir().await?;
+        if !dir.exists() {
+            return Ok(result);
+        }
+        let mut entries = read_dir(dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry
+                .file_name()
+                .to_str()
+                .ok_or(WsvcFsError::InvalidOsString(format!("{:?}", entry)))?
+                .to_string();
+            // branch names never start with `.` or end with `.lock`, such files are temp and
+            // lock files of ref updates.
+            if !entry.file_type().await?.is_file()
+                || name.starts_with('.')
+                || name.ends_with(LOCK_SUFFIX)
+            {
+                continue;
+            }
+            if let Some(record) = self.read_ref(branch_ref(&name)).await? {
+                result.push(Branch { name, record });
+            }
+        }
+        result.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(result)
+    }
+
+    /// read a branch by name, `None` if not exists.
+    pub async fn read_branch(&self, name: impl AsRef<str>) -> Result<Option<Branch>, WsvcError> {
+        let name = name.as_ref();
+        check_branch_name(name)?;
+        Ok(self.read_ref(branch_ref(name)).await?.map(|record| Branch {
+            name: name.to_owned(),
+            record,
+        }))
+    }
+
+    /// create a branch at a record, an existing branch is only moved if `force` is set.
+    ///
+    /// the branch HEAD is on is never moved, its records would not match the workspace.
+    pub async fn create_branch(
+        &self,
+        name: impl AsRef<str>,
+        record_hash: &ObjectId,
+        force: bool,
+    ) -> Result<Branch, WsvcError> {
+        self.check_writable()?;
+        let name = name.as_ref();
+        check_branch_name(name)?;
+        let record = self.read_record(record_hash).await?;
+        let current = self.read_branch(name).await?;
+        if current.is_some() && !force {
+            return Err(WsvcError::BadUsage(format!(
+                "branch {} already exists",
+                name
+            )));
+        }
+        if current.as_ref().is_some_and(|b| b.record != record.hash)
+            && self.read_head().await?.branch() == Some(name)
+        {
+            return Err(WsvcError::BadUsage(format!(
+                "branch {} is checked out, it can not be moved",
+                name
+            )));
+        }
+        // fails if the branch is created or moved meanwhile.
+        self.transaction()
+            .compare_and_set(
+                branch_ref(name),
+                current.map(|b| b.record).as_ref(),
+                &record.hash,
+            )
+            .commit()
+            .await?;
+        Ok(Branch {
+            name: name.to_owned(),
+            record: record.hash,
+        })
+    }
+
+    /// delete a branch, its records are kept. the branch HEAD is on can not be deleted.
+    pub async fn delete_branch(&self, name: impl AsRef<str>) -> Result<Branch, WsvcError> {
+        self.check_writable()?;
+        let name = name.as_ref();
+        let branch = self
+            .read_branch(name)
+            .await?
+            .ok_or(WsvcError::BadUsage(format!("no branch {}", name)))?;
+        if self.read_head().await?.branch() == Some(name) {
+            return Err(WsvcError::BadUsage(format!(
+                "branch {} is checked out, switch to another one first",
+                name
+            )));
+        }
+        self.transaction()
+            .delete(branch_ref(name), &branch.record)
+            .commit()
+            .await?;
+        Ok(branch)
+    }
+
+    /// checkout the HEAD record to `workspace` without moving HEAD, e.g. after sync moved
+    /// the branch HEAD is on. returns the record, `None` if HEAD is not set.
+    pub async fn checkout_head(&self, workspace: &Path) -> Result<Option<Record>, WsvcError> {
+        self.check_writable()?;
+        let Some(hash) = self.read_head().await?.record().cloned() else {
+            return Ok(None);
+        };
+        let record = self.read_record(&hash).await?;
+        self.checkout_record_tree(&record, workspace).await?;
+        remove_dir_all(self.temp_dir().await?)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        self.append_event(EventKind::Checkout {
+            record: record.hash.clone(),
+        })
+        .await
+        .ok();
+        Ok(Some(record))
+    }
+
+    /// the reasons branches pushed with a lease can not be stored, the branches of their
+    /// names moved away from their leases.
+    pub async fn check_branch_leases(
+        &self,
+        branches: &[SyncBranch],
+    ) -> Result<Vec<String>, WsvcError> {
+        let mut result = vec![];
+        for SyncBranch { branch, lease } in branches {
+            let Some(lease) = lease else {
+                continue;
+            };
+            let current = self.read_branch(&branch.name).await?.map(|b| b.record);
+            if current.as_ref() != Some(lease) {
+                result.push(format!(
+                    "branch {} is at {} instead of {} expected by the lease",
+                    branch.name,
+                    current.map_or("nothing".to_owned(), |c| c.0.to_hex()[0..6].to_owned()),
+                    &lease.0.to_hex()[0..6]
+                ));
+            }
+        }
+        Ok(result)
+    }
+
+    /// store branches received by sync, returns the branches stored and the received ones
+    /// diverged from the local branches of their names.
+    ///
+    /// a missing branch is created, an existing one is only moved forward to a descendant
+    /// of its record, or replaced if it is at the lease of the received branch. branches
+    /// of records not in the repository are skipped. the branch HEAD is on is moved too,
+    /// callers check out its new record.
+    pub async fn store_sync_branches(
+        &self,
+        branches: &[SyncBranch],
+    ) -> Result<(Vec<Branch>, Vec<Branch>), WsvcError> {
+        self.check_writable()?;
+        let graph = self.record_graph().await?;
+        let mut stored = vec![];
+        let mut diverged = vec![];
+        for SyncBranch { branch, lease } in branches {
+            if check_branch_name(&branch.name).is_err() || !graph.contains(&branch.record) {
+                continue;
+            }
+            let current = self.read_branch(&branch.name).await?;
+            if lease.is_some() {
+                if current.as_ref().map(|b| &b.record) != lease.as_ref() {
+                    diverged.push(branch.clone());
+                    continue;
+                }
+            } else if let Some(current) = &current {
+                // the same record or behind the local branch.
+                if graph.is_ancestor(&branch.record, &current.record) {
+                    continue;
+                }
+                if !graph.is_ancestor(&current.record, &branch.record) {
+                    diverged.push(branch.clone());
+                    continue;
+                }
+            }
+            self.transaction()
+                .compare_and_set(
+                    branch_ref(&branch.name),
+                    current.map(|b| b.record).as_ref(),
+                    &branch.record,
+                )
+                .commit()
+                .await?;
+            stored.push(branch.clone());
+        }
+        Ok((stored, diverged))
+    }
+
+    /// put HEAD on branch `name`, and checkout its record to `workspace` unless it is the
+    /// HEAD record already. returns the record of the branch.
+    pub async fn switch_branch(
+        &self,
+        name: impl AsRef<str>,
+        workspace: &Path,
+    ) -> Result<Record, WsvcError> {
+        self.check_writable()?;
+        let name = name.as_ref();
+        let branch = self
+            .read_branch(name)
+            .await?
+            .ok_or(WsvcError::BadUsage(format!("no branch {}", name)))?;
+        let record = self.read_record(&branch.record).await?;
+        if self.read_head().await?.record() != Some(&record.hash) {
+            self.checkout_record_tree(&record, workspace).await?;
+            remove_dir_all(self.temp_dir().await?)
+                .await
+                .map_err(WsvcFsError::Os)?;
+        }
+        self.transaction()
+            .set_symbolic("HEAD", branch_ref(name))
+            .commit()
+            .await?;
+        self.append_event(EventKind::Checkout {
+            record: record.hash.clone(),
+        })
+        .await
+        .ok();
+        Ok(record)
+    }
+}
+
+impl ReadOnlyRepository {
+    pub async fn read_head(&self) -> Result<HeadRef, WsvcFsError> {
+        self.repository().read_head().await
+    }
+
+    pub async fn get_branches(&self) -> Result<Vec<Branch>, WsvcFsError> {
+        self.repository().get_branches().await
+    }
+
+    pub async fn read_branch(&self, name: impl AsRef<str>) -> Result<Option<Branch>, WsvcError> {
+        self.repository().read_branch(name).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn leases_replace_only_the_leased_record() {
+        let dir = std::env::temp_dir().join(format!("wsvc-test-{}", nanoid::nanoid!()));
+        create_dir_all(&dir).await.unwrap();
+        let repo = Repository::new(&dir, false).await.unwrap();
+        let mut records = vec![];
+        for content in ["a", "b", "c"] {
+            tokio::fs::write(dir.join("file"), content).await.unwrap();
+            records.push(repo.commit_record(&dir, "me", content, None).await.unwrap());
+        }
+        let (a, b, c) = (&records[0].hash, &records[1].hash, &records[2].hash);
+        repo.transaction()
+            .set(branch_ref("main"), b)
+            .commit()
+            .await
+            .unwrap();
+        let push = |record: &ObjectId, lease: Option<&ObjectId>| SyncBranch {
+            branch: Branch {
+                name: "main".to_owned(),
+                record: record.clone(),
+            },
+            lease: lease.cloned(),
+        };
+        // behind the branch, or at a stale lease, the branch stays.
+        let (stored, diverged) = repo.store_sync_branches(&[push(a, None)]).await.unwrap();
+        assert!(stored.is_empty() && diverged.is_empty());
+        let stale = [push(a, Some(c))];
+        assert_eq!(repo.check_branch_leases(&stale).await.unwrap().len(), 1);
+        let (stored, diverged) = repo.store_sync_branches(&stale).await.unwrap();
+        assert!(stored.is_empty());
+        assert_eq!(diverged.len(), 1);
+        assert_eq!(repo.read_branch("main").await.unwrap().unwrap().record, *b);
+        // at the lease, the branch is replaced even by an ancestor.
+        let leased = [push(a, Some(b))];
+        assert!(repo.check_branch_leases(&leased).await.unwrap().is_empty());
+        let (stored, _) = repo.store_sync_branches(&leased).await.unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(repo.read_branch("main").await.unwrap().unwrap().record, *a);
+        remove_dir_all(dir).await.unwrap();
+    }
+}
@@ -0,0 +1,166 @@
+use std::fmt::Write;
+
+use crate::{
+    model::{ObjectId, Record},
+    tag::SHORT_HASH_LEN,
+};
+
+/// sections of the changelog by conventional type, in order. records of other types and
+/// records not following the convention are listed in `Other` at last.
+pub const SECTIONS: &[(&str, &str)] = &[
+    ("feat", "Features"),
+    ("fix", "Bug Fixes"),
+    ("perf", "Performance"),
+    ("refactor", "Refactoring"),
+    ("docs", "Documentation"),
+    ("test", "Tests"),
+    ("build", "Build"),
+    ("ci", "CI"),
+    ("style", "Style"),
+    ("chore", "Chores"),
+    ("revert", "Reverts"),
+];
+
+/// `ConventionalMessage` stand for a record message following conventional commits, e.g.
+/// `feat(sync)!: resume interrupted syncs`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConventionalMessage {
+    /// the type in lowercase, e.g. `feat` or `fix`.
+    pub kind: String,
+    pub scope: Option<String>,
+    /// marked by `!` after the type or a `BREAKING CHANGE:` line in the body.
+    pub breaking: bool,
+    pub description: String,
+}
+
+impl ConventionalMessage {
+    /// parse the subject of a message, `None` if it does not follow the convention.
+    pub fn parse(message: &str) -> Option<Self> {
+        let subject = message.lines().next()?;
+        let (head, description) = subject.split_once(": ")?;
+        let (head, bang) = match head.strip_suffix('!') {
+            Some(head) => (head, true),
+            None => (head, false),
+        };
+        let (kind, scope) = match head.split_once('(') {
+            Some((kind, scope)) => (kind, Some(scope.strip_suffix(')')?.trim())),
+            None => (head, None),
+        };
+        if kind.is_empty()
+            || !kind.chars().all(|c| c.is_ascii_alphanumeric())
+            || scope.is_some_and(|s| s.is_empty())
+            || description.trim().is_empty()
+        {
+            return None;
+        }
+        let breaking = bang
+            || message.lines().skip(1).any(|line| {
+                line.starts_with("BREAKING CHANGE:") || line.starts_with("BREAKING-CHANGE:")
+            });
+        Some(Self {
+            kind: kind.to_ascii_lowercase(),
+            scope: scope.map(|s| s.to_owned()),
+            breaking,
+            description: description.trim().to_owned(),
+        })
+    }
+}
+
+/// `ChangelogEntry` stand for a record listed in a changelog.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangelogEntry {
+    pub record: ObjectId,
+    pub scope: Option<String>,
+    pub description: String,
+}
+
+impl ChangelogEntry {
+    fn render(&self, output: &mut String) {
+        let hash = self.record.0.to_hex();
+        let scope = match &self.scope {
+            Some(scope) => format!("**{}:** ", scope),
+            None => String::new(),
+        };
+        writeln!(
+            output,
+            "- {}{} ({})",
+            scope,
+            self.description,
+            &hash[0..SHORT_HASH_LEN]
+        )
+        .ok();
+    }
+}
+
+/// `Changelog` stand for records grouped by their conventional type.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Changelog {
+    /// breaking records, also listed in the section of their type.
+    pub breaking: Vec<ChangelogEntry>,
+    /// non-empty sections with their titles, in the order of `SECTIONS`.
+    pub sections: Vec<(String, Vec<ChangelogEntry>)>,
+}
+
+impl Changelog {
+    /// group `records`, entries keep the order of `records`.
+    pub fn new(records: &[Record]) -> Self {
+        let mut breaking = vec![];
+        let mut sections = SECTIONS
+            .iter()
+            .map(|(_, title)| (title.to_string(), vec![]))
+            .chain([("Other".to_owned(), vec![])])
+            .collect::<Vec<_>>();
+        for record in records {
+            let (index, entry) = match ConventionalMessage::parse(&record.message) {
+                Some(message) => {
+                    let entry = ChangelogEntry {
+                        record: record.hash.clone(),
+                        scope: message.scope,
+                        description: message.description,
+                    };
+                    if message.breaking {
+                        breaking.push(entry.clone());
+                    }
+                    let index = SECTIONS
+                        .iter()
+                        .position(|(kind, _)| *kind == message.kind)
+                        .unwrap_or(SECTIONS.len());
+                    (index, entry)
+                }
+                None => (
+                    SECTIONS.len(),
+                    ChangelogEntry {
+                        record: record.hash.clone(),
+                        scope: None,
+                        description: record.message.lines().next().unwrap_or("").to_owned(),
+                    },
+                ),
+            };
+            sections[index].1.push(entry);
+        }
+        sections.retain(|(_, entries)| !entries.is_empty());
+        Self { breaking, sections }
+    }
+
+    /// render the changelog as markdown under a `## title` heading.
+    pub fn render_markdown(&self, title: &str) -> String {
+        let mut result = format!("## {}\n", title);
+        if self.breaking.is_empty() && self.sections.is_empty() {
+            result.push_str("\nNo changes.\n");
+            return result;
+        }
+        if !self.breaking.is_empty() {
+            result.push_str("\n### Breaking Changes\n\n");
+            for entry in &self.breaking {
+                entry.render(&mut result);
+            }
+        }
+        for (title, entries) in &self.sections {
+            write!(result, "\n### {}\n\n", title).ok();
+            for entry in entries {
+                entry.render(&mut result);
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{fs::WsvcFsError, model::ObjectId};
+
+/// files of at least this many bytes are stored in content-defined chunks.
+pub const CHUNKED_SIZE: u64 = 1024 * 1024;
+
+/// no chunk is cut before this many bytes, except the last one.
+pub const MIN_CHUNK_SIZE: usize = 16 * 1024;
+
+/// chunks are cut around this many bytes on average.
+pub const AVG_CHUNK_SIZE: usize = 64 * 1024;
+
+/// chunks are cut at this many bytes at the latest.
+pub const MAX_CHUNK_SIZE: usize = 256 * 1024;
+
+/// leading bytes of a blob object listing its chunks instead of holding the content.
+pub(crate) const CHUNKED_MAGIC: &[u8] = b"WSVCCDC1";
+
+/// a mask of the `bits` highest bits, a cut point is where the rolling hash has them
+/// all clear.
+const fn high_bits(bits: u32) -> u64 {
+    !0u64 << (64 - bits)
+}
+
+/// mask before the average size, harder to match so chunks are rarely cut small.
+const MASK_SMALL: u64 = high_bits(AVG_CHUNK_SIZE.trailing_zeros() + 2);
+
+/// mask after the average size, easier to match so chunks are rarely cut at the max.
+const MASK_LARGE: u64 = high_bits(AVG_CHUNK_SIZE.trailing_zeros() - 2);
+
+/// random values of bytes for the gear rolling hash, fixed by a splitmix64 sequence so
+/// every repository cuts the same content at the same points.
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x7773_7663_2023_0001;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// the length of the first chunk of `data`, as FastCDC with normalized chunking cuts it.
+///
+/// the cut point only depends on the bytes around it, so an edit in a large file only
+/// changes the chunks near the edit. `data` must hold `MAX_CHUNK_SIZE` bytes unless it
+/// is the end of the content.
+pub fn cut_point(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK_SIZE {
+        return data.len();
+    }
+    let end = data.len().min(MAX_CHUNK_SIZE);
+    let normal = end.min(AVG_CHUNK_SIZE);
+    let mut hash = 0u64;
+    for (i, byte) in data.iter().enumerate().take(end).skip(MIN_CHUNK_SIZE) {
+        hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+        let mask = if i < normal { MASK_SMALL } else { MASK_LARGE };
+        if hash & mask == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+/// split `data` into content-defined chunks, see `cut_point`.
+pub fn chunks(mut data: &[u8]) -> Vec<&[u8]> {
+    let mut result = vec![];
+    while !data.is_empty() {
+        let (chunk, rest) = data.split_at(cut_point(data));
+        result.push(chunk);
+        data = rest;
+    }
+    result
+}
+
+/// `ChunkRef` stand for a chunk of a chunked blob, stored as an object of its own.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ChunkRef {
+    pub hash: ObjectId,
+    pub size: u64,
+}
+
+/// `ChunkManifest` stand for the chunks of a blob stored in chunks, in the order of the
+/// content. the blob object holds the manifest and is still named by the hash of the
+/// whole content, so trees and syncs do not tell chunked blobs apart.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ChunkManifest {
+    /// bytes of the whole content.
+    pub size: u64,
+    pub chunks: Vec<ChunkRef>,
+}
+
+impl ChunkManifest {
+    /// the manifest as stored in a blob object.
+    pub(crate) fn encode(&self) -> Result<Vec<u8>, WsvcFsError> {
+        let mut result = CHUNKED_MAGIC.to_vec();
+        result.extend_from_slice(&serde_json::to_vec(self)?);
+        Ok(result)
+    }
+
+    /// the manifest of a blob object, `None` if the object holds the content itself.
+    pub(crate) fn decode(object: &[u8]) -> Result<Option<Self>, WsvcFsError> {
+        match object.strip_prefix(CHUNKED_MAGIC) {
+            Some(manifest) => Ok(Some(serde_json::from_slice(manifest)?)),
+            None => Ok(None),
+        }
+    }
+}
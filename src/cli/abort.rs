@@ -0,0 +1,49 @@
+use colored::Colorize;
+use wsvc::{
+    fs::{RepoGuard, WsvcFsError},
+    model::Repository,
+    state::Operation,
+    WsvcError,
+};
+
+use super::config::WsvcConfig;
+
+/// undo `operation` in progress or interrupted, restoring the workspace, branches and
+/// HEAD saved by its marker.
+pub async fn abort(operation: Operation, root: Option<String>) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    let mut repo = Repository::try_open(root).await?;
+    let config = WsvcConfig::load(Some(&repo)).await?;
+    config.configure(&mut repo);
+    let guard = RepoGuard::new(&repo).await?;
+    let state = repo.abort_operation(operation).await?;
+    drop(guard);
+    match &state.head {
+        Some(head) => {
+            let hash = head.0.to_hex().to_string();
+            println!(
+                "{} Aborted {}, workspace restored to record {} ({})",
+                "[*]".bright_blue(),
+                operation,
+                hash[0..6].green().bold(),
+                hash
+            );
+        }
+        None => println!("{} Aborted {}", "[*]".bright_blue(), operation),
+    }
+    if let Some(branch) = &state.branch {
+        println!("On branch {}", branch.bright_yellow());
+    }
+    if state.stash.is_some() {
+        println!(
+            "Workspace changes stashed by the {} were restored.",
+            operation
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use std::path::PathBuf;
+
+use colored::Colorize;
+use wsvc::{
+    fs::{RepoGuard, WsvcFsError},
+    index::Index,
+    model::Repository,
+    WsvcError,
+};
+
+fn print_index(index: &Index) {
+    if index.is_empty() {
+        println!("Nothing staged.");
+        return;
+    }
+    println!("Staged for `wsvc commit --staged`:");
+    for path in &index.paths {
+        println!("    {}", path.bright_green());
+    }
+}
+
+pub async fn add(
+    paths: Vec<String>,
+    workspace: Option<String>,
+    root: Option<String>,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let workspace = PathBuf::from(workspace.unwrap_or(pwd.clone()));
+    let root = root.unwrap_or(pwd);
+    let repo = Repository::try_open(root).await?;
+    let guard = RepoGuard::new(&repo).await?;
+    let index = repo.stage_paths(&workspace, &paths).await?;
+    print_index(&index);
+    drop(guard);
+    Ok(())
+}
+
+/// unstage `paths`, or everything if no path is given.
+pub async fn unstage(paths: Vec<String>, root: Option<String>) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let repo = Repository::try_open(root.unwrap_or(pwd)).await?;
+    let guard = RepoGuard::new(&repo).await?;
+    let index = if paths.is_empty() {
+        repo.clear_index().await?;
+        Index::default()
+    } else {
+        repo.unstage_paths(&paths).await?
+    };
+    print_index(&index);
+    drop(guard);
+    Ok(())
+}
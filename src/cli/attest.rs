@@ -0,0 +1,45 @@
+use colored::Colorize;
+use wsvc::{
+    fs::{RepoGuard, WsvcFsError},
+    model::Repository,
+    WsvcError,
+};
+
+use super::{resolve_record, sign::SignOptions};
+
+pub async fn attest(
+    root: Option<String>,
+    kind: String,
+    hash: Option<String>,
+    author: String,
+    options: SignOptions,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    let repo = Repository::try_open(root).await?;
+    let guard = RepoGuard::new(&repo).await?;
+    let signer = options.signer()?;
+    let record = resolve_record(&repo.to_read_only(), hash).await?;
+    let attestation = repo
+        .attest_record(&record.hash, &kind, &author, signer.as_deref())
+        .await?;
+    let hash = record.hash.0.to_hex().to_string();
+    println!(
+        "Attested record: {} ({}) {} by {}{}",
+        hash[0..6].green().bold(),
+        hash,
+        attestation.kind.bright_magenta(),
+        attestation.author.bright_blue(),
+        if attestation.signature.is_some() {
+            " [signed]".bright_green()
+        } else {
+            "".clear()
+        }
+    );
+    drop(guard);
+    Ok(())
+}
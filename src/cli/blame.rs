@@ -0,0 +1,52 @@
+use colored::Colorize;
+use wsvc::{fs::WsvcFsError, mailmap::Mailmap, model::Repository, WsvcError};
+
+use super::resolve_record;
+
+/// print each line of the file at `path` of record `rev` with the record that last changed
+/// it, `rev` is HEAD if omitted.
+pub async fn blame(
+    root: Option<String>,
+    path: String,
+    rev: Option<String>,
+    json: bool,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    let repo = Repository::open_read_only(&root).await?;
+    let mailmap = Mailmap::load(&root).await?;
+    let record = resolve_record(&repo, rev).await?;
+    let blame = repo.blame(&record.hash, &path).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&blame)?);
+        return Ok(());
+    }
+    let authors = blame
+        .records
+        .iter()
+        .map(|(hash, record)| (hash.clone(), mailmap.resolve(&record.author)))
+        .collect::<std::collections::HashMap<_, _>>();
+    let author_width = authors
+        .values()
+        .map(|a| a.chars().count())
+        .max()
+        .unwrap_or(0);
+    let number_width = blame.lines.len().to_string().len();
+    for (i, line) in blame.lines.iter().enumerate() {
+        let hash = line.record.0.to_hex().to_string();
+        let record = &blame.records[&hash];
+        println!(
+            "{} ({:<author_width$} {} {:>number_width$}) {}",
+            hash[0..6].bold(),
+            authors[&hash].bright_blue(),
+            record.date.naive_local().to_string().yellow(),
+            i + 1,
+            line.text
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use wsvc::{
+    blobless::BlobSource,
+    fs::WsvcFsError,
+    model::{ObjectId, Repository},
+};
+
+use super::{
+    config::WsvcConfig,
+    credentials::credential,
+    poll::{client, http_url},
+};
+
+fn fetch_error(message: impl std::fmt::Display) -> WsvcFsError {
+    WsvcFsError::Os(std::io::Error::other(message.to_string()))
+}
+
+/// `HttpBlobSource` fetches blobs of a blobless clone from the blobs endpoint beside the
+/// sync of its remote origin, `ws://host/repo` fetches `http://host/repo/blobs/<hash>`.
+#[derive(Clone, Debug)]
+pub struct HttpBlobSource {
+    pub config: WsvcConfig,
+}
+
+#[async_trait]
+impl BlobSource for HttpBlobSource {
+    async fn fetch(&self, repo: &Repository, hash: &ObjectId) -> Result<Vec<u8>, WsvcFsError> {
+        let origin = repo.read_origin().await?;
+        let url = http_url(&origin, &format!("blobs/{}", hash.0.to_hex())).ok_or(fetch_error(
+            format!(
+                "blobs are fetched over http, origin {} is not a ws:// or wss:// url",
+                origin
+            ),
+        ))?;
+        let mut request = client(&url, &self.config).map_err(fetch_error)?.get(&url);
+        if let Some(credential) = credential(&origin, &self.config).await {
+            request = request.basic_auth(credential.account, Some(credential.password));
+        }
+        let response = request.send().await.map_err(fetch_error)?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(fetch_error(format!(
+                "blob {} is not on {}",
+                hash.0.to_hex(),
+                origin
+            )));
+        }
+        let body = response
+            .error_for_status()
+            .map_err(fetch_error)?
+            .bytes()
+            .await
+            .map_err(fetch_error)?;
+        Ok(body.to_vec())
+    }
+}
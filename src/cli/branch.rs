@@ -0,0 +1,103 @@
+use std::path::PathBuf;
+
+use colored::Colorize;
+use wsvc::{
+    fs::{RepoGuard, WsvcFsError},
+    model::Repository,
+    WsvcError,
+};
+
+use super::{config::WsvcConfig, resolve_record};
+
+pub async fn branch(
+    root: Option<String>,
+    name: Option<String>,
+    hash: Option<String>,
+    delete: bool,
+    force: bool,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    let name = match name {
+        Some(name) => name,
+        None => {
+            let repo = Repository::open_read_only(root).await?;
+            let current = repo.read_head().await?;
+            for branch in repo.get_branches().await? {
+                let hash = branch.record.0.to_hex().to_string();
+                if current.branch() == Some(branch.name.as_str()) {
+                    println!("* {} -> {}", branch.name.bright_green().bold(), &hash[0..6]);
+                } else {
+                    println!("  {} -> {}", branch.name.bold(), &hash[0..6]);
+                }
+            }
+            return Ok(());
+        }
+    };
+    let repo = Repository::try_open(root).await?;
+    let guard = RepoGuard::new(&repo).await?;
+    if delete {
+        let branch = repo.delete_branch(&name).await?;
+        let hash = branch.record.0.to_hex().to_string();
+        println!(
+            "Deleted branch {} (was {})",
+            branch.name.bright_yellow(),
+            &hash[0..6]
+        );
+    } else {
+        let record = resolve_record(&repo.to_read_only(), hash).await?;
+        let branch = repo.create_branch(&name, &record.hash, force).await?;
+        let hash = record.hash.0.to_hex().to_string();
+        println!(
+            "Branch {} at record: {} ({})",
+            branch.name.bright_yellow(),
+            hash[0..6].green().bold(),
+            hash
+        );
+    }
+    drop(guard);
+    Ok(())
+}
+
+/// put HEAD on branch `name` and checkout its record, creating it at HEAD first if asked.
+pub async fn switch(
+    name: String,
+    create: bool,
+    workspace: Option<String>,
+    root: Option<String>,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let workspace = PathBuf::from(workspace.unwrap_or(pwd.clone()));
+    let root = root.unwrap_or(pwd);
+    let mut repo = Repository::try_open(root).await?;
+    let config = WsvcConfig::load(Some(&repo)).await?;
+    config.configure(&mut repo);
+    let guard = RepoGuard::new(&repo).await?;
+    if repo.path == workspace {
+        return Err(WsvcError::BadUsage(
+            "workspace and repo path can not be the same".to_owned(),
+        ));
+    }
+    if create {
+        let head = resolve_record(&repo.to_read_only(), None).await?;
+        repo.create_branch(&name, &head.hash, false).await?;
+    }
+    let record = repo.switch_branch(&name, &workspace).await?;
+    let hash = record.hash.0.to_hex().to_string();
+    println!(
+        "Switched to branch {} at record: {} ({})",
+        name.bright_yellow(),
+        hash[0..6].green().bold(),
+        hash
+    );
+    drop(guard);
+    Ok(())
+}
@@ -0,0 +1,38 @@
+use wsvc::{changelog::Changelog, fs::WsvcFsError, model::Repository, walk::WalkOrder, WsvcError};
+
+use super::resolve_record;
+
+/// print markdown release notes of records after `from` up to `to`, grouped by type.
+///
+/// `from` and `to` are tag names or record hash prefixes, `to` is HEAD if omitted and all
+/// records up to `to` are listed if `from` is omitted.
+pub async fn changelog(
+    root: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    title: Option<String>,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    let repo = Repository::open_read_only(root).await?;
+    let to_record = resolve_record(&repo, to.clone()).await?;
+    let from = match from {
+        Some(from) => Some(resolve_record(&repo, Some(from)).await?.hash),
+        None => None,
+    };
+    let records = repo
+        .record_graph()
+        .await?
+        .range(from.as_ref(), &to_record.hash, WalkOrder::Date);
+    let title = title.unwrap_or(format!(
+        "{} ({})",
+        to.unwrap_or("Unreleased".to_owned()),
+        to_record.date.format("%Y-%m-%d")
+    ));
+    print!("{}", Changelog::new(&records).render_markdown(&title));
+    Ok(())
+}
@@ -1,12 +1,15 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use colored::Colorize;
 use wsvc::{
     fs::{RepoGuard, WsvcFsError},
     model::Repository,
+    state::Operation,
     WsvcError,
 };
 
+use super::{config::WsvcConfig, detached_notice};
+
 pub async fn checkout(
     hash: Option<String>,
     workspace: Option<String>,
@@ -19,38 +22,97 @@ pub async fn checkout(
         .to_string();
     let workspace = PathBuf::from(workspace.unwrap_or(pwd.clone()));
     let root = root.unwrap_or(pwd);
-    let repo = Repository::try_open(root).await?;
+    let mut repo = Repository::try_open(root).await?;
+    let config = WsvcConfig::load(Some(&repo)).await?;
+    config.configure(&mut repo);
     let guard = RepoGuard::new(&repo).await?;
     if repo.path == workspace {
         return Err(WsvcError::BadUsage(
             "workspace and repo path can not be the same".to_owned(),
         ));
     }
-    // let tips = "wsvc can't keep current workspace changes when you checkout to record.\n\ntips: you must `wsvc config set commit.auto_record [true/false]` to determine whether auto commit changes when checkout, if it set to false, unsaved changes will be abandoned.";
+    // changes left by an interrupted checkout are not kept, it is checked out again.
+    let resumed = repo
+        .operation_in_progress()
+        .await?
+        .is_some_and(|state| state.operation == Operation::Checkout);
+    // workspace changes are recorded if `commit.auto_record` is set, or stashed otherwise.
+    let mut stash = None;
+    if resumed {
+        println!(
+            "{} Finishing an interrupted checkout, workspace changes are dropped.",
+            "[!]".bright_yellow()
+        );
+    } else if config.commit.auto_record.unwrap_or(false) {
+        let record = repo
+            .commit_record(
+                &workspace,
+                "AUTO BACKUP".to_owned(),
+                "auto backup by checkout",
+                None,
+            )
+            .await
+            .ok();
+        if let Some(record) = record {
+            let hash = record.hash.0.to_hex().to_string();
+            println!(
+                "Auto-backup created a record: {} ({})",
+                hash[0..6].green().bold(),
+                hash
+            );
+        }
+    } else if repo.get_head_record().await?.is_some() && !repo.status(&workspace).await?.is_clean()
+    {
+        let stashed = repo.push_stash(&workspace, None).await?;
+        println!(
+            "Stashed workspace changes: {}, use `wsvc stash pop` to restore them.",
+            stashed.message
+        );
+        stash = Some(stashed.id);
+    }
+    repo.begin_operation(Operation::Checkout, &workspace, stash)
+        .await?;
+    let result = checkout_impl(&repo, hash, &workspace).await;
+    // usage errors are found before touching the workspace.
+    if matches!(result, Ok(_) | Err(WsvcError::BadUsage(_))) {
+        repo.end_operation(Operation::Checkout).await?;
+    }
+    drop(guard);
+    result
+}
 
-    let record = repo
-        .commit_record(
-            &workspace,
-            "AUTO BACKUP".to_owned(),
-            "auto backup by checkout",
-        )
-        .await
-        .ok();
-    if let Some(record) = record {
+async fn checkout_impl(
+    repo: &Repository,
+    hash: Option<String>,
+    workspace: &Path,
+) -> Result<(), WsvcError> {
+    // branch names win over hash prefixes, and `wsvc checkout` stays on the branch.
+    let branch = match &hash {
+        Some(name) => repo.read_branch(name).await.ok().flatten().map(|b| b.name),
+        None => repo.read_head().await?.branch().map(|b| b.to_owned()),
+    };
+    if let Some(branch) = branch {
+        let record = repo.switch_branch(&branch, workspace).await?;
         let hash = record.hash.0.to_hex().to_string();
         println!(
-            "Auto-backup created a record: {} ({})",
+            "Checked-out branch {}: {} ({})",
+            branch.bright_yellow(),
             hash[0..6].green().bold(),
             hash
         );
-    }
-    if let Some(hash) = hash {
-        let hash = hash.to_ascii_lowercase();
-        let records = repo.get_records().await?;
-        let records = records
-            .iter()
-            .filter(|h| h.hash.0.to_hex().to_ascii_lowercase().starts_with(&hash))
-            .collect::<Vec<_>>();
+    } else if let Some(hash) = hash {
+        // tags win over hash prefixes too.
+        let records = match repo.resolve_tag(&hash).await? {
+            Some(record) => vec![record],
+            None => {
+                let hash = hash.to_ascii_lowercase();
+                repo.get_records()
+                    .await?
+                    .into_iter()
+                    .filter(|h| h.hash.0.to_hex().to_ascii_lowercase().starts_with(&hash))
+                    .collect::<Vec<_>>()
+            }
+        };
         if records.is_empty() {
             return Err(WsvcError::BadUsage(format!(
                 "no record found for hash {}",
@@ -75,20 +137,27 @@ pub async fn checkout(
                 hash
             )));
         }
-        let record = repo.checkout_record(&records[0].hash, &workspace).await?;
+        let record = repo.checkout_record(&records[0].hash, workspace).await?;
         let hash = record.hash.0.to_hex().to_string();
         println!(
             "Checked-out record: {} ({})",
             hash[0..6].green().bold(),
             hash
         );
+        if let Some(notice) = detached_notice(&repo.head_state().await?) {
+            println!(
+                "{} {}, new records fork from HEAD. use `wsvc checkout` to return to the latest record.",
+                "[!]".bright_yellow(),
+                notice
+            );
+        }
     } else {
         let latest_hash = repo
             .get_latest_record()
             .await?
             .ok_or(WsvcError::BadUsage("no record found".to_owned()))?
             .hash;
-        let record = repo.checkout_record(&latest_hash, &workspace).await?;
+        let record = repo.checkout_record(&latest_hash, workspace).await?;
         let hash = record.hash.0.to_hex().to_string();
         println!(
             "Checked-out latest record: {} ({})",
@@ -96,6 +165,5 @@ pub async fn checkout(
             hash
         );
     }
-    drop(guard);
     Ok(())
 }
@@ -1,17 +1,90 @@
 use std::path::PathBuf;
 
+use chrono::{DateTime, Timelike, Utc};
 use colored::Colorize;
 use wsvc::{
     fs::{RepoGuard, WsvcFsError},
+    head::HeadState,
+    mailmap::{identity, split_identity},
     model::Repository,
     WsvcError,
 };
 
+use super::{config::WsvcConfig, detached_notice};
+
+/// author and date of a new record, overridable for imports and scripts.
+#[derive(clap::Args)]
+pub struct MetadataOptions {
+    /// commit author, `commit.author` of the config by default
+    #[clap(short, long)]
+    pub author: Option<String>,
+    /// author name replacing the name of the author
+    #[clap(long)]
+    pub author_name: Option<String>,
+    /// author email replacing the email of the author, recorded as `name <email>`
+    #[clap(long)]
+    pub author_email: Option<String>,
+    /// record date in rfc3339 like `2023-05-01T12:00:00+08:00` instead of now
+    #[clap(long)]
+    pub date: Option<String>,
+}
+
+impl MetadataOptions {
+    /// the author string, `--author` or `commit.author` with the name and email replaced
+    /// by `--author-name` and `--author-email`.
+    fn author(&self, config: &WsvcConfig) -> Result<String, WsvcError> {
+        let base = self.author.as_ref().or(config.commit.author.as_ref());
+        if let (Some(base), None, None) = (base, &self.author_name, &self.author_email) {
+            return Ok(base.clone());
+        }
+        let (name, email) = base.map(|base| split_identity(base)).unwrap_or_default();
+        let name = self.author_name.as_deref().unwrap_or(name);
+        if name.is_empty() {
+            return Err(WsvcError::LackOfConfig(
+                "commit.author".to_owned(),
+                "use `--author`, `--author-name` or `wsvc config set commit.author <name>`"
+                    .to_owned(),
+            ));
+        }
+        identity(name, self.author_email.as_deref().or(email))
+    }
+
+    /// the date of `--date`, `None` for now. records keep whole seconds in UTC, so dates
+    /// with fractions of a second are refused instead of being recorded otherwise.
+    fn date(&self) -> Result<Option<DateTime<Utc>>, WsvcError> {
+        let Some(date) = &self.date else {
+            return Ok(None);
+        };
+        let date = DateTime::parse_from_rfc3339(date).map_err(|err| {
+            WsvcError::BadUsage(format!(
+                "invalid date {:?}: {}, expected rfc3339 like 2023-05-01T12:00:00+08:00",
+                date, err
+            ))
+        })?;
+        if date.nanosecond() != 0 {
+            return Err(WsvcError::BadUsage(format!(
+                "date {} has fractions of a second, records keep whole seconds",
+                date
+            )));
+        }
+        let date = date.with_timezone(&Utc);
+        if date > Utc::now() {
+            return Err(WsvcError::BadUsage(format!(
+                "date {} is in the future",
+                date
+            )));
+        }
+        Ok(Some(date))
+    }
+}
+
 pub async fn commit(
     message: String,
-    author: String,
+    metadata: MetadataOptions,
     workspace: Option<String>,
     root: Option<String>,
+    advance: bool,
+    staged: bool,
 ) -> Result<(), WsvcError> {
     let pwd = std::env::current_dir()
         .map_err(WsvcFsError::Os)?
@@ -20,16 +93,76 @@ pub async fn commit(
         .to_string();
     let workspace = PathBuf::from(workspace.unwrap_or(pwd.clone()));
     let root = root.unwrap_or(pwd);
-    let repo = Repository::try_open(root).await?;
+    let mut repo = Repository::try_open(root).await?;
+    let config = WsvcConfig::load(Some(&repo)).await?;
+    config.configure(&mut repo);
+    let author = metadata.author(&config)?;
+    let date = metadata.date()?.unwrap_or_else(Utc::now);
     let guard = RepoGuard::new(&repo).await?;
     if repo.path == workspace {
         return Err(WsvcError::BadUsage(
             "workspace and repo path can not be the same".to_owned(),
         ));
     }
-    let record = repo.commit_record(&workspace, &author, &message).await?;
+    let state = repo.head_state().await?;
+    if let Some(notice) = detached_notice(&state) {
+        let fast_forward = matches!(
+            state,
+            HeadState::Detached {
+                fast_forward: true,
+                ..
+            }
+        );
+        // the config only advances when possible, the flag fails otherwise.
+        if advance || (fast_forward && config.head.advance.unwrap_or(false)) {
+            if let Some(latest) = repo.advance_head(&workspace).await? {
+                let hash = latest.0.to_hex().to_string();
+                println!(
+                    "Advanced HEAD to the latest record: {} ({})",
+                    hash[0..6].green().bold(),
+                    hash
+                );
+            }
+        } else if config.head.warn_detached.unwrap_or(true) {
+            println!(
+                "{} {}, the new record forks from HEAD.",
+                "[!]".bright_yellow(),
+                notice
+            );
+            if fast_forward {
+                println!("    use `--advance` to commit on the latest record, keeping workspace changes.");
+            }
+        }
+    }
+    let index = repo.read_index().await?;
+    if staged && index.is_empty() {
+        return Err(WsvcError::BadUsage(
+            "nothing staged, use `wsvc add <paths>` first".to_owned(),
+        ));
+    }
+    let paths = staged.then_some(index.paths.as_slice());
+    let record = repo
+        .commit_record_at(&workspace, &author, &message, paths, date)
+        .await?;
+    // staged paths are recorded by either kind of commit.
+    repo.clear_index().await?;
     let hash = record.hash.0.to_hex().to_string();
     println!("Committed record: {} ({})", hash[0..6].green().bold(), hash);
+    let base = match record.parents.iter().flatten().next() {
+        Some(parent) => Some(repo.read_record(parent).await?.root),
+        None => None,
+    };
+    let warnings = repo
+        .size_warnings(base.as_ref(), &record.root, &repo.options.size_policy)
+        .await?;
+    for warning in &warnings {
+        println!("{} {}", "[!]".bright_yellow(), warning);
+    }
+    if !warnings.is_empty() {
+        println!(
+            "    generated data committed by accident? set `size.block` to refuse such records."
+        );
+    }
     drop(guard);
     Ok(())
 }
@@ -0,0 +1,543 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use merge::Merge;
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+use wsvc::{
+    attributes::AutoCrlf,
+    auth::SyncCredential,
+    compress::Codec,
+    encoding::ObjectFormat,
+    filter::FilterDriver,
+    fs::WsvcFsError,
+    model::Repository,
+    patch::Whitespace,
+    readonly::ReadOnlyRepository,
+    refspec::{RefSpec, RefSpecs},
+    size::SizePolicy,
+    WsvcError,
+};
+
+use super::blobless::HttpBlobSource;
+
+/// file name of the config, in the repository dir or `~/.config/wsvc`.
+pub const CONFIG_FILE: &str = "config.toml";
+
+/// env var forbidding network access like `net.offline` when set to anything but `0`.
+pub const OFFLINE_ENV: &str = "WSVC_OFFLINE";
+
+/// name of the remote origin among the remotes of the config.
+pub const ORIGIN_REMOTE: &str = "origin";
+
+/// keep entries of `left`, only add entries missing from it.
+fn merge_map<V>(left: &mut HashMap<String, V>, right: HashMap<String, V>) {
+    for (key, value) in right {
+        left.entry(key).or_insert(value);
+    }
+}
+
+/// `WsvcConfig` stand for the merged config, the repository config overrides the global one.
+///
+/// ```toml
+/// [commit]
+/// author = "alice"
+///
+/// [head]
+/// advance = true
+///
+/// [ignore]
+/// excludes_file = "/home/alice/.config/wsvc/ignore"
+///
+/// [net]
+/// offline = false
+///
+/// [net.resolve]
+/// "wsvc.lab" = "10.0.0.2"
+///
+/// [diff]
+/// whitespace = "change"
+///
+/// [text]
+/// autocrlf = "input"
+///
+/// [size]
+/// max_growth = 10
+/// max_size = 104857600
+/// block = true
+///
+/// [compression]
+/// codec = "zstd"
+/// level = 19
+///
+/// [storage]
+/// format = "binary"
+///
+/// [remote.nas]
+/// url = "ws://nas.lan:7878/repo"
+/// push = ["refs/main", "tags/*"]
+/// fetch = ["refs/*", "!refs/wip-*", "tags/*"]
+///
+/// [filter.secret]
+/// builtin = "encrypt"
+/// key_env = "SECRET_KEY"
+///
+/// [tls]
+/// ca_file = "/etc/wsvc/lab-ca.pem"
+///
+/// [tls.pin]
+/// "wsvc.lab" = "<blake3 hex of the DER certificate>"
+/// ```
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Merge)]
+#[serde(default)]
+pub struct WsvcConfig {
+    pub commit: CommitConfig,
+    pub head: HeadConfig,
+    pub ignore: IgnoreConfig,
+    pub auth: AuthConfig,
+    pub net: NetConfig,
+    pub tls: TlsConfig,
+    pub diff: DiffConfig,
+    pub text: TextConfig,
+    /// content filters by name, for paths with a `filter=<name>` attribute.
+    #[merge(strategy = merge_map)]
+    pub filter: HashMap<String, FilterDriver>,
+    pub size: SizeConfig,
+    pub compression: CompressionConfig,
+    pub storage: StorageConfig,
+    /// remotes by name besides the remote origin, e.g. mirrors synced by `sync --all`.
+    #[merge(strategy = merge_map)]
+    pub remote: HashMap<String, RemoteConfig>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Merge)]
+#[serde(default)]
+pub struct CommitConfig {
+    pub author: Option<String>,
+    pub auto_record: Option<bool>,
+}
+
+/// behaviors when HEAD is not the latest record, e.g. after checking out an older one.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Merge)]
+#[serde(default)]
+pub struct HeadConfig {
+    /// advance HEAD to the latest record before committing if possible, false by default.
+    pub advance: Option<bool>,
+    /// warn when committing on a detached HEAD, true by default.
+    pub warn_detached: Option<bool>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Merge)]
+#[serde(default)]
+pub struct IgnoreConfig {
+    /// ignore file applied to every workspace, `ignore` in the global config dir by default.
+    pub excludes_file: Option<PathBuf>,
+}
+
+/// credential sent to servers requiring auth when syncing.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Merge)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub account: Option<String>,
+    pub passwd: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Merge)]
+#[serde(default)]
+pub struct NetConfig {
+    /// hostname to ip overrides, used instead of dns when dialing remotes.
+    #[merge(strategy = merge_map)]
+    pub resolve: HashMap<String, String>,
+    /// never access the network if `true`, commands needing it fail at once, false by
+    /// default.
+    pub offline: Option<bool>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Merge)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// pem bundle of extra root certificates, trusted besides the system roots.
+    pub ca_file: Option<PathBuf>,
+    /// hostname to blake3 fingerprint of the certificate the remote must present.
+    #[merge(strategy = merge_map)]
+    pub pin: HashMap<String, String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Merge)]
+#[serde(default)]
+pub struct DiffConfig {
+    /// how whitespace counts in line diffs, `exact` by default, or `change` and `all`.
+    pub whitespace: Option<Whitespace>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Merge)]
+#[serde(default)]
+pub struct TextConfig {
+    /// convert CRLF of text files to LF when recording, and back when checking out if
+    /// `true`. `input` only converts when recording, false by default.
+    pub autocrlf: Option<AutoCrlf>,
+}
+
+/// limits of file sizes checked when committing, see `SizePolicy`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Merge)]
+#[serde(default)]
+pub struct SizeConfig {
+    /// times its size in the parent record a file may grow to, 10 by default, 0 to never
+    /// compare.
+    pub max_growth: Option<f64>,
+    /// bytes a file must reach to be reported as grown, 1 MiB by default.
+    pub min_size: Option<u64>,
+    /// bytes no file may exceed, unlimited by default.
+    pub max_size: Option<u64>,
+    /// fail committing files over the limits instead of warning, false by default.
+    pub block: Option<bool>,
+}
+
+/// how newly stored blobs are compressed, objects stored before are still read.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Merge)]
+#[serde(default)]
+pub struct CompressionConfig {
+    /// `deflate` by default, or `zstd`.
+    pub codec: Option<Codec>,
+    /// level of the codec, 8 by default, up to 10 for deflate and 22 for zstd.
+    pub level: Option<u8>,
+}
+
+/// how newly stored trees and records are encoded, objects stored before are still read.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Merge)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// `json` by default, or `binary`.
+    pub format: Option<ObjectFormat>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Merge)]
+#[serde(default)]
+pub struct RemoteConfig {
+    pub url: Option<String>,
+    /// refs sent to the remote, see `RefSpec`. all tags by default.
+    pub push: Option<Vec<RefSpec>>,
+    /// refs of the remote stored locally, see `RefSpec`. all tags by default.
+    pub fetch: Option<Vec<RefSpec>>,
+}
+
+/// the global config dir, `wsvc` in the config dir of the user.
+pub(super) fn global_dir() -> Result<PathBuf, WsvcError> {
+    dirs::config_dir()
+        .map(|dir| dir.join("wsvc"))
+        .ok_or(WsvcError::NeedConfiguring(
+            "no config dir found for global config".to_owned(),
+        ))
+}
+
+fn global_path() -> Result<PathBuf, WsvcError> {
+    Ok(global_dir()?.join(CONFIG_FILE))
+}
+
+async fn read_table(path: &Path) -> Result<Table, WsvcError> {
+    if !path.exists() {
+        return Ok(Table::new());
+    }
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .map_err(WsvcFsError::Os)?;
+    Ok(toml::from_str(&content)?)
+}
+
+async fn write_table(path: &Path, table: Table) -> Result<(), WsvcError> {
+    // reject values that do not fit the config before writing them.
+    let config: WsvcConfig = table.clone().try_into()?;
+    for host in config.net.resolve.keys() {
+        config.resolve(host)?;
+    }
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(WsvcFsError::Os)?;
+    }
+    tokio::fs::write(path, toml::to_string(&table)?)
+        .await
+        .map_err(WsvcFsError::Os)?;
+    Ok(())
+}
+
+impl WsvcConfig {
+    /// load the global config merged with the config of `repo`.
+    pub async fn load(repo: Option<&Repository>) -> Result<Self, WsvcError> {
+        Self::load_dir(repo.map(|repo| repo.path.as_path())).await
+    }
+
+    /// load the global config merged with the config of a repository opened read-only.
+    pub async fn load_read_only(repo: &ReadOnlyRepository) -> Result<Self, WsvcError> {
+        Self::load_dir(Some(repo.path())).await
+    }
+
+    async fn load_dir(dir: Option<&Path>) -> Result<Self, WsvcError> {
+        let mut config: WsvcConfig = match dir {
+            Some(dir) => read_table(&dir.join(CONFIG_FILE)).await?.try_into()?,
+            None => WsvcConfig::default(),
+        };
+        if let Ok(path) = global_path() {
+            config.merge(read_table(&path).await?.try_into()?);
+        }
+        Ok(config)
+    }
+
+    /// the global ignore file, `ignore.excludes_file` or `ignore` in the global config dir if
+    /// it exists.
+    pub fn excludes_file(&self) -> Option<PathBuf> {
+        match &self.ignore.excludes_file {
+            Some(path) => Some(path.clone()),
+            None => global_path()
+                .ok()
+                .map(|path| path.with_file_name("ignore"))
+                .filter(|path| path.is_file()),
+        }
+    }
+
+    /// set options of `repo` from the config, the global ignore file, line ending
+    /// conversion, content filters, compression and the format of trees and records.
+    /// blobless repositories fetch blobs from their remote origin.
+    pub fn configure(&self, repo: &mut Repository) {
+        repo.options.excludes_file = self.excludes_file();
+        repo.options.autocrlf = self.text.autocrlf.unwrap_or_default();
+        repo.options.filters = self.filters();
+        repo.options.size_policy = self.size_policy();
+        if let Some(codec) = self.compression.codec {
+            repo.options.codec = codec;
+        }
+        if let Some(level) = self.compression.level {
+            repo.options.compression_level = level;
+        }
+        if let Some(format) = self.storage.format {
+            repo.options.object_format = format;
+        }
+        if repo.is_blobless() {
+            repo.options.blob_source = Some(Arc::new(HttpBlobSource {
+                config: self.clone(),
+            }));
+        }
+    }
+
+    /// the size policy of `size`, defaults of `SizePolicy` for missing keys.
+    pub fn size_policy(&self) -> SizePolicy {
+        let default = SizePolicy::default();
+        SizePolicy {
+            max_growth: self.size.max_growth.unwrap_or(default.max_growth),
+            min_size: self.size.min_size.unwrap_or(default.min_size),
+            max_size: self.size.max_size.or(default.max_size),
+            block: self.size.block.unwrap_or(default.block),
+        }
+    }
+
+    /// content filters of `filter`, by name.
+    pub fn filters(&self) -> BTreeMap<String, FilterDriver> {
+        self.filter.clone().into_iter().collect()
+    }
+
+    /// remotes with a url by name, sorted.
+    pub fn remotes(&self) -> Vec<(String, String)> {
+        let mut remotes = self
+            .remote
+            .iter()
+            .filter_map(|(name, remote)| Some((name.clone(), remote.url.clone()?)))
+            .collect::<Vec<_>>();
+        remotes.sort();
+        remotes
+    }
+
+    /// the name of the remote with `url`.
+    pub fn remote_name(&self, url: &str) -> Option<String> {
+        self.remote
+            .iter()
+            .find(|(_, remote)| remote.url.as_deref() == Some(url))
+            .map(|(name, _)| name.clone())
+    }
+
+    /// refspecs of the remote `name`, defaults of `RefSpecs` for missing keys.
+    pub fn refspecs(&self, name: &str) -> RefSpecs {
+        let default = RefSpecs::default();
+        match self.remote.get(name) {
+            Some(remote) => RefSpecs {
+                push: remote.push.clone().unwrap_or(default.push),
+                fetch: remote.fetch.clone().unwrap_or(default.fetch),
+            },
+            None => default,
+        }
+    }
+
+    /// the url of the remote `name`.
+    pub fn remote_url(&self, name: &str) -> Option<String> {
+        self.remote.get(name)?.url.clone()
+    }
+
+    /// the url of `remote`, a remote of the config by its name or a url, the remote origin
+    /// of `repo` if `remote` is `None` or `origin` and the config has no remote so named.
+    pub async fn resolve_remote(
+        &self,
+        repo: Option<&Repository>,
+        remote: Option<String>,
+    ) -> Result<String, WsvcError> {
+        let remote = remote.unwrap_or(ORIGIN_REMOTE.to_owned());
+        match (self.remote_url(&remote), repo) {
+            (Some(url), _) => Ok(url),
+            (None, Some(repo)) if remote == ORIGIN_REMOTE => Ok(repo.read_origin().await?),
+            (None, None) if remote == ORIGIN_REMOTE => Err(WsvcError::BadUsage(
+                "not in a repository, give the url of the remote".to_owned(),
+            )),
+            (None, _) => Ok(remote),
+        }
+    }
+
+    /// the credential of `auth.account` and `auth.passwd`, `None` unless both are set.
+    pub fn credential(&self) -> Option<SyncCredential> {
+        Some(SyncCredential {
+            account: self.auth.account.clone()?,
+            password: self.auth.passwd.clone()?,
+        })
+    }
+
+    /// whether network access is forbidden by `net.offline` or `WSVC_OFFLINE`.
+    pub fn offline(&self) -> bool {
+        std::env::var(OFFLINE_ENV).is_ok_and(|value| !value.is_empty() && value != "0")
+            || self.net.offline.unwrap_or(false)
+    }
+
+    /// the overridden address of `host` in `net.resolve`.
+    pub fn resolve(&self, host: &str) -> Result<Option<IpAddr>, WsvcError> {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let addr = match self.net.resolve.get(host) {
+            Some(addr) => addr,
+            None => return Ok(None),
+        };
+        match addr.parse() {
+            Ok(addr) => Ok(Some(addr)),
+            Err(_) => Err(WsvcError::BadUsage(format!(
+                "invalid address of net.resolve.{}: {}",
+                host, addr
+            ))),
+        }
+    }
+}
+
+/// split a key like `net.resolve.wsvc.lab` into its section, field and map key, so map
+/// keys may contain dots.
+fn split_key(key: &str) -> Result<Vec<&str>, WsvcError> {
+    let parts = key.splitn(3, '.').collect::<Vec<_>>();
+    if parts.len() < 2 || parts.iter().any(|p| p.is_empty()) {
+        return Err(WsvcError::BadUsage(format!("invalid config key: {}", key)));
+    }
+    Ok(parts)
+}
+
+async fn config_path(global: bool) -> Result<PathBuf, WsvcError> {
+    if global {
+        return global_path();
+    }
+    let pwd = std::env::current_dir().map_err(WsvcFsError::Os)?;
+    let repo = Repository::try_open(pwd).await?;
+    Ok(repo.path.join(CONFIG_FILE))
+}
+
+pub async fn config_get(key: String) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir().map_err(WsvcFsError::Os)?;
+    let repo = Repository::try_open(pwd).await.ok();
+    let config = WsvcConfig::load(repo.as_ref()).await?;
+    let mut value = &Value::try_from(config)?;
+    for part in split_key(&key)? {
+        value = match value.get(part) {
+            Some(value) => value,
+            None => {
+                return Err(WsvcError::LackOfConfig(
+                    key.clone(),
+                    format!("set it with `wsvc config set {} <value>`", key),
+                ))
+            }
+        };
+    }
+    match value {
+        Value::String(value) => println!("{}", value),
+        value => println!("{}", value),
+    }
+    Ok(())
+}
+
+/// insert `value` at `key`, split into `sections` and its `last` part, into `table`.
+fn insert_value(
+    table: &mut Table,
+    key: &str,
+    sections: &[&str],
+    last: &str,
+    value: Value,
+) -> Result<(), WsvcError> {
+    let mut current = table;
+    for section in sections {
+        current = match current
+            .entry(section.to_string())
+            .or_insert(Value::Table(Table::new()))
+        {
+            Value::Table(table) => table,
+            _ => return Err(WsvcError::BadUsage(format!("invalid config key: {}", key))),
+        };
+    }
+    current.insert(last.to_string(), value);
+    Ok(())
+}
+
+pub async fn config_set(key: String, value: String, global: bool) -> Result<(), WsvcError> {
+    let path = config_path(global).await?;
+    let mut table = read_table(&path).await?;
+    let parts = split_key(&key)?;
+    let (last, sections) = parts.split_last().unwrap();
+    let number = match value.parse::<i64>() {
+        Ok(number) => Some(Value::Integer(number)),
+        Err(_) => value
+            .parse::<f64>()
+            .ok()
+            .filter(|v| v.is_finite())
+            .map(Value::Float),
+    };
+    // numbers are kept as strings for keys of strings, e.g. a numeric author.
+    if let Some(number) = number {
+        let mut candidate = table.clone();
+        insert_value(&mut candidate, &key, sections, last, number)?;
+        if candidate.clone().try_into::<WsvcConfig>().is_ok() {
+            return write_table(&path, candidate).await;
+        }
+    }
+    // lists are given as toml arrays, e.g. `["refs/main", "tags/*"]`.
+    let array = match value.starts_with('[') {
+        true => toml::from_str::<Table>(&format!("value = {}", value))
+            .ok()
+            .and_then(|mut table| table.remove("value")),
+        false => None,
+    };
+    let value = match (array, value.as_str()) {
+        (Some(array), _) => array,
+        (None, "true") => Value::Boolean(true),
+        (None, "false") => Value::Boolean(false),
+        (None, _) => Value::String(value),
+    };
+    insert_value(&mut table, &key, sections, last, value)?;
+    write_table(&path, table).await
+}
+
+pub async fn config_unset(key: String, global: bool) -> Result<(), WsvcError> {
+    let path = config_path(global).await?;
+    let mut table = read_table(&path).await?;
+    let parts = split_key(&key)?;
+    let (last, sections) = parts.split_last().unwrap();
+    let mut current = &mut table;
+    for section in sections {
+        current = match current.get_mut(*section) {
+            Some(Value::Table(table)) => table,
+            _ => return Ok(()),
+        };
+    }
+    current.remove(*last);
+    write_table(&path, table).await
+}
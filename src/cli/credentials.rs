@@ -0,0 +1,149 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use colored::Colorize;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use wsvc::{auth::SyncCredential, fs::WsvcFsError, WsvcError};
+
+use super::config::{global_dir, WsvcConfig};
+
+/// service name of tokens in the os keyring.
+const KEYRING_SERVICE: &str = "wsvc";
+
+/// file of tokens in the global config dir, used when no os keyring is available.
+pub const CREDENTIALS_FILE: &str = "credentials.json";
+
+/// `StoredToken` stand for a token `wsvc login` got from a server for an account.
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+pub struct StoredToken {
+    pub account: String,
+    pub token: String,
+}
+
+/// `TokenStorage` stand for where a token is kept.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenStorage {
+    /// the os keyring, e.g. the macos keychain, the windows credential manager or the
+    /// secret service of linux desktops.
+    Keyring,
+    /// the credentials file readable only by the user, when no keyring is available.
+    File(PathBuf),
+}
+
+/// the server of `url` tokens are kept for, `wss://example.com:8443` of
+/// `wss://example.com:8443/team/repo`, tokens of a server serve all its repositories.
+pub fn server_key(url: &str) -> Result<String, WsvcError> {
+    let parsed = Url::parse(url).map_err(|err| WsvcError::BadUsage(err.to_string()))?;
+    let host = parsed
+        .host_str()
+        .ok_or(WsvcError::BadUsage(format!("no host in url: {}", url)))?;
+    Ok(match parsed.port() {
+        Some(port) => format!("{}://{}:{}", parsed.scheme(), host, port),
+        None => format!("{}://{}", parsed.scheme(), host),
+    })
+}
+
+/// run a blocking call to the keyring, some keyrings talk to a daemon.
+async fn keyring<T: Send + 'static>(
+    key: &str,
+    call: impl FnOnce(keyring::Entry) -> keyring::Result<T> + Send + 'static,
+) -> keyring::Result<T> {
+    let key = key.to_owned();
+    tokio::task::spawn_blocking(move || call(keyring::Entry::new(KEYRING_SERVICE, &key)?))
+        .await
+        .map_err(|err| keyring::Error::PlatformFailure(Box::new(err)))?
+}
+
+fn credentials_path() -> Result<PathBuf, WsvcError> {
+    Ok(global_dir()?.join(CREDENTIALS_FILE))
+}
+
+async fn read_file() -> Result<BTreeMap<String, StoredToken>, WsvcError> {
+    match tokio::fs::read(credentials_path()?).await {
+        Ok(content) => Ok(serde_json::from_slice(&content)?),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(err) => Err(WsvcFsError::Os(err).into()),
+    }
+}
+
+/// write the credentials file, only the user may read it.
+async fn write_file(tokens: &BTreeMap<String, StoredToken>) -> Result<PathBuf, WsvcError> {
+    let path = credentials_path()?;
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(WsvcFsError::Os)?;
+    }
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(&path).await.map_err(WsvcFsError::Os)?;
+    tokio::io::AsyncWriteExt::write_all(&mut file, &serde_json::to_vec_pretty(tokens)?)
+        .await
+        .map_err(WsvcFsError::Os)?;
+    Ok(path)
+}
+
+/// keep `token` for the server of `url`, in the os keyring if there is one.
+pub async fn store_token(url: &str, token: &StoredToken) -> Result<TokenStorage, WsvcError> {
+    let key = server_key(url)?;
+    let value = serde_json::to_string(token)?;
+    let stored = keyring(&key, move |entry| entry.set_password(&value)).await;
+    let mut tokens = read_file().await?;
+    match stored {
+        Ok(_) => {
+            // a token kept before the keyring was available is stale now.
+            if tokens.remove(&key).is_some() {
+                write_file(&tokens).await?;
+            }
+            Ok(TokenStorage::Keyring)
+        }
+        Err(err) => {
+            println!(
+                "{} os keyring unavailable, keeping the token in a file: {}",
+                "[!]".bright_yellow(),
+                err
+            );
+            tokens.insert(key, token.clone());
+            Ok(TokenStorage::File(write_file(&tokens).await?))
+        }
+    }
+}
+
+/// the token kept for the server of `url`, from the os keyring or the credentials file.
+pub async fn load_token(url: &str) -> Result<Option<StoredToken>, WsvcError> {
+    let key = server_key(url)?;
+    if let Ok(value) = keyring(&key, |entry| entry.get_password()).await {
+        if let Ok(token) = serde_json::from_str(&value) {
+            return Ok(Some(token));
+        }
+    }
+    Ok(read_file().await?.remove(&key))
+}
+
+/// forget the token kept for the server of `url`, returns whether there was one.
+pub async fn remove_token(url: &str) -> Result<bool, WsvcError> {
+    let key = server_key(url)?;
+    let removed = keyring(&key, |entry| entry.delete_credential())
+        .await
+        .is_ok();
+    let mut tokens = read_file().await?;
+    if tokens.remove(&key).is_some() {
+        write_file(&tokens).await?;
+        return Ok(true);
+    }
+    Ok(removed)
+}
+
+/// the credential sent to the server of `url` if it requires auth, the token of
+/// `wsvc login` or else `auth.account` and `auth.passwd` of `config`.
+pub async fn credential(url: &str, config: &WsvcConfig) -> Option<SyncCredential> {
+    match load_token(url).await {
+        Ok(Some(token)) => Some(SyncCredential {
+            account: token.account,
+            password: token.token,
+        }),
+        _ => config.credential(),
+    }
+}
@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+
+use chrono::Utc;
+use colored::Colorize;
+use wsvc::{
+    query::parse_date,
+    server::{
+        deploy::{DeployScope, DeployTokenStore, DEPLOY_TOKENS_FILE},
+        visibility::Access,
+    },
+    WsvcError,
+};
+
+/// the deploy tokens of the root dir `dir` of hosted repositories, served by `wsvc serve`.
+fn store(dir: String) -> DeployTokenStore {
+    DeployTokenStore::new(PathBuf::from(dir).join(DEPLOY_TOKENS_FILE))
+}
+
+pub async fn deploy_mint(
+    dir: String,
+    name: String,
+    repos: String,
+    write: bool,
+    expires: Option<String>,
+) -> Result<(), WsvcError> {
+    let expires = match expires {
+        Some(date) => Some(parse_date(&date, true)?),
+        None => None,
+    };
+    let scope = DeployScope {
+        repos,
+        access: match write {
+            true => Access::Write,
+            false => Access::Read,
+        },
+        expires,
+    };
+    let (token, secret) = store(dir).mint(&name, scope).await?;
+    println!(
+        "{} Minted deploy token {} for {}, it is shown only once:",
+        "[*]".bright_blue(),
+        token.id.green().bold(),
+        token.name
+    );
+    println!("{}", secret);
+    Ok(())
+}
+
+pub async fn deploy_list(dir: String) -> Result<(), WsvcError> {
+    let tokens = store(dir).tokens().await?;
+    if tokens.is_empty() {
+        println!("No deploy tokens.");
+    }
+    let now = Utc::now();
+    for token in tokens {
+        let expires = match token.scope.expires {
+            Some(expires) if expires <= now => "expired".red().to_string(),
+            Some(expires) => format!("until {}", expires.format("%Y-%m-%d %H:%M")),
+            None => "never expires".to_owned(),
+        };
+        println!(
+            "{} {} {} {} ({}, created {})",
+            token.id.green().bold(),
+            token.name,
+            format!("{:?}", token.scope.access).to_lowercase(),
+            token.scope.repos,
+            expires,
+            token.created.format("%Y-%m-%d")
+        );
+    }
+    Ok(())
+}
+
+pub async fn deploy_revoke(dir: String, id: String) -> Result<(), WsvcError> {
+    match store(dir).revoke(&id).await? {
+        true => {
+            println!("{} Revoked deploy token {}.", "[*]".bright_blue(), id);
+            Ok(())
+        }
+        false => Err(WsvcError::BadUsage(format!("no deploy token {}", id))),
+    }
+}
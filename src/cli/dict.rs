@@ -0,0 +1,67 @@
+use colored::Colorize;
+use wsvc::{
+    fs::{RepoGuard, WsvcFsError},
+    model::Repository,
+    WsvcError,
+};
+
+use super::config::WsvcConfig;
+
+async fn open(root: Option<String>) -> Result<Repository, WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    let mut repo = Repository::try_open(root).await?;
+    let config = WsvcConfig::load(Some(&repo)).await?;
+    config.configure(&mut repo);
+    Ok(repo)
+}
+
+pub async fn dict_train(size: usize, root: Option<String>) -> Result<(), WsvcError> {
+    let repo = open(root).await?;
+    let guard = RepoGuard::new(&repo).await?;
+    let dict = repo.train_transport_dict(size).await?;
+    drop(guard);
+    println!(
+        "{} Trained transport dictionary {} of {} bytes, offered to clients syncing from now on.",
+        "[*]".bright_blue(),
+        dict.id[0..6].green().bold(),
+        dict.data.len()
+    );
+    Ok(())
+}
+
+pub async fn dict_show(root: Option<String>) -> Result<(), WsvcError> {
+    let repo = open(root).await?;
+    match repo.transport_dict().await? {
+        Some(dict) => println!(
+            "Transport dictionary: {} ({} bytes)",
+            dict.id.green().bold(),
+            dict.data.len()
+        ),
+        None => println!("No transport dictionary, syncs from clients use zstd without one."),
+    }
+    for dict in repo.remote_dicts().await? {
+        println!(
+            "Dictionary of a remote: {} ({} bytes)",
+            dict.id.dimmed(),
+            dict.data.len()
+        );
+    }
+    Ok(())
+}
+
+pub async fn dict_remove(root: Option<String>) -> Result<(), WsvcError> {
+    let repo = open(root).await?;
+    let guard = RepoGuard::new(&repo).await?;
+    let removed = repo.remove_transport_dict().await?;
+    drop(guard);
+    match removed {
+        true => println!("{} Removed transport dictionary.", "[*]".bright_blue()),
+        false => println!("No transport dictionary to remove."),
+    }
+    Ok(())
+}
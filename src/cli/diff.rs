@@ -0,0 +1,356 @@
+use std::io::Write;
+
+use colored::Colorize;
+use wsvc::{
+    binary::{BinaryDiff, BinaryDiffers, BinarySummary},
+    diff::Change,
+    fs::WsvcFsError,
+    model::Repository,
+    patch::{Hunk, LineKind, LineStat, Whitespace, WordChange},
+    WsvcError,
+};
+
+use super::{config::WsvcConfig, resolve_record};
+
+/// context lines around changed lines of line diffs.
+const CONTEXT_LINES: usize = 3;
+
+/// widest bar of added and removed lines printed by `--stat`.
+const STAT_BAR_WIDTH: usize = 50;
+
+/// options of how `wsvc diff` prints changes.
+#[derive(clap::Args)]
+pub struct DiffFormat {
+    /// print changes as json
+    #[clap(long, action = clap::ArgAction::SetTrue, conflicts_with_all = ["porcelain", "patch", "word_diff"])]
+    pub json: bool,
+    /// print a summary of changed files with their added and removed lines
+    #[clap(long, action = clap::ArgAction::SetTrue, conflicts_with_all = ["json", "porcelain", "patch", "word_diff"])]
+    pub stat: bool,
+    /// print changes as stable tab separated lines for scripts
+    #[clap(long, action = clap::ArgAction::SetTrue, conflicts_with_all = ["patch", "word_diff"])]
+    pub porcelain: bool,
+    /// end porcelain lines with NUL instead of newline and do not quote paths
+    #[clap(short = 'z', action = clap::ArgAction::SetTrue, requires = "porcelain")]
+    pub nul: bool,
+    /// print line diffs of text files, changed words of modified lines are highlighted
+    #[clap(short, long, action = clap::ArgAction::SetTrue)]
+    pub patch: bool,
+    /// print line diffs of text files with changed words inline, as `[-old-]{+new+}`
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    pub word_diff: bool,
+    /// compare lines of line diffs ignoring changes in the amount of whitespace
+    #[clap(short = 'b', long, action = clap::ArgAction::SetTrue, conflicts_with = "ignore_all_space")]
+    pub ignore_space_change: bool,
+    /// compare lines of line diffs ignoring whitespace entirely
+    #[clap(short = 'w', long, action = clap::ArgAction::SetTrue)]
+    pub ignore_all_space: bool,
+    /// also pair removed and added text files sharing at least this percent of lines as
+    /// renames, 50 if no percent is given
+    #[clap(short = 'M', long, value_name = "PERCENT", num_args = 0..=1, default_missing_value = "50", value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub find_renames: Option<u8>,
+}
+
+impl DiffFormat {
+    /// how whitespace counts in line diffs, flags override `diff.whitespace` of `config`.
+    fn whitespace(&self, config: &WsvcConfig) -> Whitespace {
+        match (self.ignore_space_change, self.ignore_all_space) {
+            (true, _) => Whitespace::IgnoreChange,
+            (_, true) => Whitespace::IgnoreAll,
+            _ => config.diff.whitespace.unwrap_or_default(),
+        }
+    }
+}
+
+/// list files changed from record `from` to record `to`.
+///
+/// `to` is HEAD if omitted, and `from` is the first parent of `to` if omitted too.
+pub async fn diff(
+    root: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    format: DiffFormat,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    let repo = Repository::open_read_only(root).await?;
+    let (from, to) = match (from, to) {
+        (Some(from), to) => (
+            Some(resolve_record(&repo, Some(from)).await?),
+            resolve_record(&repo, to).await?,
+        ),
+        (None, to) => {
+            let to = resolve_record(&repo, to).await?;
+            let graph = repo.record_graph().await?;
+            let from = match graph.parents(&to.hash).first() {
+                Some(parent) => Some(repo.read_record(parent).await?),
+                None => None,
+            };
+            (from, to)
+        }
+    };
+    let mut changes = repo
+        .changes_of_trees(from.as_ref().map(|r| &r.root), Some(&to.root))
+        .await?;
+    if let Some(threshold) = format.find_renames {
+        changes = repo.find_similar_renames(changes, threshold, None).await?;
+    }
+    if format.json {
+        println!("{}", serde_json::to_string_pretty(&changes)?);
+        return Ok(());
+    }
+    if format.porcelain {
+        let mut stdout = std::io::stdout().lock();
+        for change in changes {
+            stdout
+                .write_all(change.porcelain(format.nul).as_bytes())
+                .map_err(WsvcFsError::Os)?;
+        }
+        return Ok(());
+    }
+    let config = WsvcConfig::load_read_only(&repo).await?;
+    let whitespace = format.whitespace(&config);
+    let differs = BinaryDiffers::default();
+    let attributes = repo
+        .tree_attributes(&to.root)
+        .await?
+        .with_filters(config.filters());
+    if format.stat {
+        let mut stats = vec![];
+        for change in changes {
+            let stat = match repo.binary_diff(&change, &differs, &attributes).await? {
+                Some(diff) => FileStat::Binary(diff),
+                None => FileStat::Text(LineStat::of(
+                    &repo
+                        .text_diff(&change, 0, whitespace, &attributes)
+                        .await?
+                        .unwrap_or_default(),
+                )),
+            };
+            if !matches!(change, Change::DirAdded { .. } | Change::DirRemoved { .. }) {
+                stats.push((change, stat));
+            }
+        }
+        print_stat(&stats);
+        return Ok(());
+    }
+    for change in changes {
+        if let Some(diff) = repo.binary_diff(&change, &differs, &attributes).await? {
+            print_change(&change);
+            print_binary_diff(&diff);
+        } else if format.patch || format.word_diff {
+            let hunks = repo
+                .text_diff(&change, CONTEXT_LINES, whitespace, &attributes)
+                .await?;
+            // files only changed in ignored whitespace are left out.
+            if whitespace != Whitespace::Exact
+                && matches!((&change, &hunks), (Change::Modified { .. }, Some(h)) if h.is_empty())
+            {
+                continue;
+            }
+            print_change(&change);
+            for hunk in hunks.unwrap_or_default() {
+                print_hunk(&hunk, format.word_diff);
+            }
+        } else {
+            print_change(&change);
+        }
+    }
+    Ok(())
+}
+
+/// `FileStat` stand for the line counts of a changed text file or the sizes of a binary
+/// one, as printed by `wsvc diff --stat`.
+enum FileStat {
+    Text(LineStat),
+    Binary(BinaryDiff),
+}
+
+/// print changed files with bars of their added and removed lines, and the totals.
+fn print_stat(stats: &[(Change, FileStat)]) {
+    let name = |change: &Change| match change {
+        Change::Renamed {
+            old_path, new_path, ..
+        }
+        | Change::Moved {
+            old_path, new_path, ..
+        } => format!("{} => {}", old_path, new_path),
+        change => change.path().to_owned(),
+    };
+    let width = stats
+        .iter()
+        .map(|(change, _)| name(change).chars().count())
+        .max()
+        .unwrap_or(0);
+    let max = stats
+        .iter()
+        .filter_map(|(_, stat)| match stat {
+            FileStat::Text(lines) => Some(lines.total()),
+            FileStat::Binary(_) => None,
+        })
+        .max()
+        .unwrap_or(0);
+    let count_width = max.to_string().len();
+    let mut total = LineStat::default();
+    for (change, stat) in stats {
+        match stat {
+            FileStat::Text(lines) => {
+                total.insertions += lines.insertions;
+                total.deletions += lines.deletions;
+                // bars are scaled down to fit, a changed file keeps at least one mark.
+                let scale = |n: usize| match max > STAT_BAR_WIDTH {
+                    true if n > 0 => (n * STAT_BAR_WIDTH / max).max(1),
+                    true => 0,
+                    false => n,
+                };
+                println!(
+                    " {:<width$} | {:>count_width$} {}{}",
+                    name(change),
+                    lines.total(),
+                    "+".repeat(scale(lines.insertions)).green(),
+                    "-".repeat(scale(lines.deletions)).red(),
+                );
+            }
+            FileStat::Binary(diff) => {
+                let size = |s: &Option<BinarySummary>| s.as_ref().map(|s| s.size).unwrap_or(0);
+                println!(
+                    " {:<width$} | {:>count_width$} {} -> {} bytes",
+                    name(change),
+                    "Bin",
+                    size(&diff.old),
+                    size(&diff.new)
+                );
+            }
+        }
+    }
+    println!(
+        " {} file{} changed, {} insertion{}(+), {} deletion{}(-)",
+        stats.len(),
+        if stats.len() == 1 { "" } else { "s" },
+        total.insertions,
+        if total.insertions == 1 { "" } else { "s" },
+        total.deletions,
+        if total.deletions == 1 { "" } else { "s" },
+    );
+}
+
+/// print a hunk of a line diff, changed words of paired lines are highlighted. with
+/// `word_diff`, a removed line and the added line replacing it are printed as one line.
+fn print_hunk(hunk: &Hunk, word_diff: bool) {
+    println!("{}", hunk.header().cyan());
+    for line in &hunk.lines {
+        match (line.kind, &line.words) {
+            (LineKind::Context, _) if word_diff => println!("{}", line.text),
+            (LineKind::Context, _) => println!(" {}", line.text),
+            (LineKind::Removed, None) if word_diff => {
+                println!("{}", format!("[-{}-]", line.text).red())
+            }
+            (LineKind::Added, None) if word_diff => {
+                println!("{}", format!("{{+{}+}}", line.text).green())
+            }
+            (LineKind::Removed, Some(words)) if word_diff => {
+                let text = words
+                    .iter()
+                    .map(|w| match w {
+                        WordChange::Equal(text) => text.normal(),
+                        WordChange::Removed(text) => format!("[-{}-]", text).red(),
+                        WordChange::Added(text) => format!("{{+{}+}}", text).green(),
+                    })
+                    .map(|w| w.to_string())
+                    .collect::<String>();
+                println!("{}", text)
+            }
+            // printed with the removed line it replaces.
+            (LineKind::Added, Some(_)) if word_diff => {}
+            (LineKind::Removed, None) => println!("{}", format!("-{}", line.text).red()),
+            (LineKind::Added, None) => println!("{}", format!("+{}", line.text).green()),
+            (LineKind::Removed, Some(words)) => {
+                let text = words
+                    .iter()
+                    .filter_map(|w| match w {
+                        WordChange::Equal(text) => Some(text.red()),
+                        WordChange::Removed(text) => Some(text.red().reversed()),
+                        WordChange::Added(_) => None,
+                    })
+                    .map(|w| w.to_string())
+                    .collect::<String>();
+                println!("{}{}", "-".red(), text)
+            }
+            (LineKind::Added, Some(words)) => {
+                let text = words
+                    .iter()
+                    .filter_map(|w| match w {
+                        WordChange::Equal(text) => Some(text.green()),
+                        WordChange::Added(text) => Some(text.green().reversed()),
+                        WordChange::Removed(_) => None,
+                    })
+                    .map(|w| w.to_string())
+                    .collect::<String>();
+                println!("{}{}", "+".green(), text)
+            }
+        }
+    }
+}
+
+/// print the size, hash and summary of a binary file change under the change.
+fn print_binary_diff(diff: &BinaryDiff) {
+    let short = |s: &BinarySummary| s.hash.0.to_hex()[0..6].to_string();
+    match (&diff.old, &diff.new) {
+        (Some(old), Some(new)) => {
+            println!(
+                "     binary {} -> {} bytes ({:+}), {} -> {}",
+                old.size,
+                new.size,
+                diff.size_delta(),
+                short(old).dimmed(),
+                short(new).dimmed()
+            );
+            match (&old.summary, &new.summary) {
+                (Some(old), Some(new)) if old == new => println!("     {}", old),
+                (None, None) => {}
+                (old, new) => println!(
+                    "     {} -> {}",
+                    old.as_deref().unwrap_or("unknown"),
+                    new.as_deref().unwrap_or("unknown")
+                ),
+            }
+        }
+        (Some(side), None) | (None, Some(side)) => {
+            println!("     binary {} bytes, {}", side.size, short(side).dimmed());
+            if let Some(summary) = &side.summary {
+                println!("     {}", summary);
+            }
+        }
+        (None, None) => {}
+    }
+}
+
+/// print a change as a colored status letter and its path.
+pub(super) fn print_change(change: &Change) {
+    match change {
+        Change::Added { path, .. } => println!("{}  {}", "A".bright_green(), path),
+        Change::Removed { path, .. } => println!("{}  {}", "D".bright_red(), path),
+        Change::Modified { path, .. } => println!("{}  {}", "M".bright_yellow(), path),
+        Change::DirAdded { path } => println!("{}  {}/", "A".bright_green(), path),
+        Change::DirRemoved { path } => println!("{}  {}/", "D".bright_red(), path),
+        Change::Renamed {
+            old_path, new_path, ..
+        } => println!("{}  {} -> {}", "R".bright_blue(), old_path, new_path),
+        Change::Moved {
+            old_path,
+            new_path,
+            similarity,
+            ..
+        } => println!(
+            "{}  {} -> {} ({}%)",
+            "R".bright_blue(),
+            old_path,
+            new_path,
+            similarity
+        ),
+    }
+}
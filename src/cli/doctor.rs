@@ -0,0 +1,47 @@
+use colored::Colorize;
+use wsvc::{fs::WsvcFsError, model::Repository, WsvcError};
+
+use super::config::WsvcConfig;
+
+pub async fn doctor(root: Option<String>) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    let mut repo = Repository::try_open(root).await?;
+    let config = WsvcConfig::load(Some(&repo)).await?;
+    config.configure(&mut repo);
+    println!(
+        "{} Repository at {}",
+        "[*]".bright_blue(),
+        repo.path.display().to_string().bold()
+    );
+    let identity = repo.identity().await?;
+    match &identity {
+        Some(identity) => println!("    id: {}", identity.id.bold()),
+        None => println!(
+            "{} no id, the repository was created before ids, it adopts the id of the next remote it syncs with",
+            "[!]".bright_yellow()
+        ),
+    }
+    match repo.read_origin().await {
+        Ok(origin) => println!("    origin: {}", origin),
+        Err(_) => println!("    origin: not set"),
+    }
+    match identity.and_then(|identity| identity.clone) {
+        Some(clone) => println!(
+            "    cloned from {} at {} on {}",
+            clone.url,
+            clone
+                .tip
+                .map(|tip| tip.0.to_hex()[..6].to_string())
+                .unwrap_or_else(|| "an empty repository".to_owned())
+                .bright_yellow(),
+            clone.date.format("%Y-%m-%d %H:%M:%S")
+        ),
+        None => println!("    not cloned"),
+    }
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use std::{io::SeekFrom, path::PathBuf, time::Duration};
+
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+use wsvc::{fs::WsvcFsError, model::Repository, WsvcError};
+
+/// how often the journal is checked for new events while following.
+pub(super) const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+/// print the last `lines` events as json lines, and new ones as they come if `follow`.
+pub async fn events_tail(
+    root: Option<String>,
+    lines: usize,
+    follow: bool,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    let repo = Repository::open_read_only(root).await?;
+    let events = repo.read_events().await?;
+    for event in &events[events.len().saturating_sub(lines)..] {
+        println!("{}", serde_json::to_string(event)?);
+    }
+    if !follow {
+        return Ok(());
+    }
+    let mut follower = JournalFollower::new(repo.events_path()).await;
+    loop {
+        tokio::time::sleep(FOLLOW_INTERVAL).await;
+        for line in follower.poll().await? {
+            println!("{}", line);
+        }
+    }
+}
+
+/// `JournalFollower` reads events appended to a journal after it is created.
+pub(super) struct JournalFollower {
+    path: PathBuf,
+    offset: u64,
+    pending: Vec<u8>,
+}
+
+impl JournalFollower {
+    pub(super) async fn new(path: PathBuf) -> Self {
+        let offset = tokio::fs::metadata(&path)
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0);
+        Self {
+            path,
+            offset,
+            pending: Vec::new(),
+        }
+    }
+
+    /// complete lines appended since the last poll.
+    pub(super) async fn poll(&mut self) -> Result<Vec<String>, WsvcError> {
+        let len = match tokio::fs::metadata(&self.path).await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => return Ok(vec![]),
+        };
+        if len < self.offset {
+            // rotated, the new journal is read from its start.
+            self.offset = 0;
+            self.pending.clear();
+        }
+        if len == self.offset {
+            return Ok(vec![]);
+        }
+        let mut file = File::open(&self.path).await.map_err(WsvcFsError::Os)?;
+        file.seek(SeekFrom::Start(self.offset))
+            .await
+            .map_err(WsvcFsError::Os)?;
+        let read = file
+            .read_to_end(&mut self.pending)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        self.offset += read as u64;
+        // only complete lines are returned, the rest is kept for the next poll.
+        let end = match self.pending.iter().rposition(|b| *b == b'\n') {
+            Some(end) => end,
+            None => return Ok(vec![]),
+        };
+        let complete = self.pending.drain(..=end).collect::<Vec<_>>();
+        Ok(String::from_utf8_lossy(&complete)
+            .lines()
+            .map(|line| line.to_owned())
+            .collect())
+    }
+}
@@ -0,0 +1,59 @@
+use colored::Colorize;
+use wsvc::{fs::WsvcFsError, mailmap::Mailmap, model::Repository, WsvcError};
+
+use super::resolve_record;
+
+/// list records that changed `path`, the latest first, with the status of the path and its
+/// blob hash after each record.
+pub async fn filelog(
+    root: Option<String>,
+    path: String,
+    rev: Option<String>,
+    limit: Option<usize>,
+    json: bool,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    let repo = Repository::open_read_only(&root).await?;
+    let mailmap = Mailmap::load(&root).await?;
+    let start = match rev {
+        Some(rev) => Some(resolve_record(&repo, Some(rev)).await?.hash),
+        None => None,
+    };
+    let mut revisions = repo.file_history(&path, start.as_ref()).await?;
+    if let Some(limit) = limit {
+        revisions.truncate(limit);
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&revisions)?);
+        return Ok(());
+    }
+    if revisions.is_empty() {
+        return Err(WsvcError::BadUsage(format!("no record changed {}", path)));
+    }
+    for revision in &revisions {
+        let status = match (&revision.entry, revision.added) {
+            (None, _) => "D".bright_red(),
+            (Some(_), true) => "A".bright_green(),
+            (Some(_), false) => "M".bright_yellow(),
+        };
+        let blob = match &revision.entry {
+            Some(entry) => entry.hash.0.to_hex()[0..6].to_string(),
+            None => "-".to_owned(),
+        };
+        println!(
+            "{}  {} {}  {}  {}  {}",
+            status,
+            revision.record.hash.0.to_hex()[0..6].bold(),
+            revision.record.date.naive_local().to_string().yellow(),
+            mailmap.resolve(&revision.record.author).bright_blue(),
+            blob.dimmed(),
+            revision.record.message.lines().next().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
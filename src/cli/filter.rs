@@ -0,0 +1,65 @@
+use colored::Colorize;
+use wsvc::{
+    fs::{RepoGuard, WsvcFsError},
+    mailmap::Mailmap,
+    model::{ObjectId, Repository},
+    WsvcError,
+};
+
+fn print_mapping(mapping: &[(ObjectId, ObjectId)]) {
+    println!("{} {}", "[*]".bright_blue(), "Rewritten records:".bold());
+    for (old, new) in mapping {
+        println!(
+            "  {} {} {}",
+            old.0.to_string().dimmed(),
+            "->".bright_yellow(),
+            new.0.to_string().green()
+        );
+    }
+}
+
+pub async fn filter(
+    root: Option<String>,
+    remove_path: Option<String>,
+    mailmap: bool,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    if remove_path.is_none() && !mailmap {
+        return Err(WsvcError::BadUsage(
+            "nothing to rewrite, use `--remove-path` or `--mailmap`".to_owned(),
+        ));
+    }
+    let repo = Repository::try_open(&root).await?;
+    let guard = RepoGuard::new(&repo).await?;
+    if let Some(path) = remove_path {
+        let mapping = repo.remove_path_from_history(&path).await?;
+        if mapping.is_empty() {
+            println!("No record contains {}", path.bold());
+        } else {
+            print_mapping(&mapping);
+            let (trees, blobs) = repo.prune_unreachable().await?;
+            println!(
+                "{} Removed {} trees and {} blobs",
+                "[*]".bright_blue(),
+                trees.to_string().bold(),
+                blobs.to_string().bold()
+            );
+        }
+    }
+    if mailmap {
+        let mailmap = Mailmap::load(&root).await?;
+        let mapping = repo.rewrite_authors(&mailmap).await?;
+        if mapping.is_empty() {
+            println!("No record needs author rewriting");
+        } else {
+            print_mapping(&mapping);
+        }
+    }
+    drop(guard);
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use colored::Colorize;
+use wsvc::{
+    fs::{RepoGuard, WsvcFsError},
+    fsck::{FsckProblem, ObjectKind, QUARANTINE_DIR},
+    model::Repository,
+    WsvcError,
+};
+
+use super::config::WsvcConfig;
+
+pub async fn fsck(root: Option<String>, quarantine: bool) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    let mut repo = Repository::try_open(root).await?;
+    let config = WsvcConfig::load(Some(&repo)).await?;
+    config.configure(&mut repo);
+    let guard = RepoGuard::new(&repo).await?;
+    let report = repo.fsck(quarantine).await?;
+    drop(guard);
+    println!(
+        "{} Checked {} records, {} trees and {} blobs",
+        "[*]".bright_blue(),
+        report.records.to_string().bold(),
+        report.trees.to_string().bold(),
+        report.blobs.to_string().bold()
+    );
+    if report.is_ok() {
+        println!("No problem found");
+        return Ok(());
+    }
+    for issue in &report.issues {
+        let problem = match &issue.problem {
+            FsckProblem::Missing { referenced_by } => {
+                format!("missing, referenced by {}", &referenced_by[..6])
+            }
+            FsckProblem::Mismatch { actual } => format!("content hashes to {}", actual),
+            FsckProblem::Corrupt { reason } => format!("corrupt, {}", reason),
+        };
+        println!(
+            "{} {} {} {}{}",
+            "[!]".bright_red(),
+            match issue.kind {
+                ObjectKind::Record => "record",
+                ObjectKind::Tree => "tree",
+                ObjectKind::Blob => "blob",
+            },
+            issue.hash.bold(),
+            problem,
+            if issue.quarantined {
+                " (quarantined)".bright_yellow().to_string()
+            } else {
+                String::new()
+            }
+        );
+    }
+    if quarantine {
+        println!(
+            "    corrupt objects were moved to {}, copy the objects of the same names from another clone to restore them.",
+            repo.path.join(QUARANTINE_DIR).display()
+        );
+    }
+    Err(WsvcError::DataError(format!(
+        "{} problems found",
+        report.issues.len()
+    )))
+}
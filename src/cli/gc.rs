@@ -0,0 +1,37 @@
+use colored::Colorize;
+use wsvc::{
+    fs::{RepoGuard, WsvcFsError},
+    model::Repository,
+    WsvcError,
+};
+
+use super::config::WsvcConfig;
+
+pub async fn gc(root: Option<String>, dry_run: bool) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    let mut repo = Repository::try_open(root).await?;
+    let config = WsvcConfig::load(Some(&repo)).await?;
+    config.configure(&mut repo);
+    let guard = RepoGuard::new(&repo).await?;
+    let stats = repo.gc(dry_run).await?;
+    drop(guard);
+    if stats.trees == 0 && stats.blobs == 0 {
+        println!("Nothing to collect, all objects are reachable");
+        return Ok(());
+    }
+    println!(
+        "{} {} {} trees and {} blobs, {} bytes {}",
+        "[*]".bright_blue(),
+        if dry_run { "Would remove" } else { "Removed" },
+        stats.trees.to_string().bold(),
+        stats.blobs.to_string().bold(),
+        stats.bytes.to_string().bold(),
+        if dry_run { "reclaimable" } else { "reclaimed" }
+    );
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use colored::Colorize;
+use wsvc::{
+    auth::{LoginRequest, LoginResponse},
+    fs::WsvcFsError,
+    model::Repository,
+    WsvcError,
+};
+
+use super::{
+    config::WsvcConfig,
+    credentials::{self, StoredToken, TokenStorage},
+    poll::{client, http_error, http_url},
+};
+
+/// read the password of the login, prompting without echo unless `stdin`.
+fn read_password(account: &str, stdin: bool) -> Result<String, WsvcError> {
+    let password = match stdin {
+        true => {
+            let mut line = String::new();
+            std::io::stdin()
+                .read_line(&mut line)
+                .map_err(WsvcFsError::Os)?;
+            line.trim_end_matches(['\r', '\n']).to_owned()
+        }
+        false => rpassword::prompt_password(format!("password of {}: ", account))
+            .map_err(WsvcFsError::Os)?,
+    };
+    if password.is_empty() {
+        return Err(WsvcError::BadUsage("empty password".to_owned()));
+    }
+    Ok(password)
+}
+
+/// log in to the server of `remote`, keeping the token it issues for later syncs.
+pub async fn login(
+    root: Option<String>,
+    remote: Option<String>,
+    account: Option<String>,
+    password_stdin: bool,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir().map_err(WsvcFsError::Os)?;
+    let root = root.map(Into::into).unwrap_or(pwd);
+    let repo = Repository::try_open(&root).await.ok();
+    let config = WsvcConfig::load(repo.as_ref()).await?;
+    let url = config.resolve_remote(repo.as_ref(), remote).await?;
+    let account = account
+        .or(config.auth.account.clone())
+        .ok_or(WsvcError::LackOfConfig(
+            "auth.account".to_owned(),
+            "give --account, or run `wsvc config set auth.account <account>`".to_owned(),
+        ))?;
+    let endpoint = http_url(&url, "login").ok_or(WsvcError::BadUsage(format!(
+        "login is done over http, {} is not a ws:// or wss:// url",
+        url
+    )))?;
+    let request = LoginRequest {
+        password: read_password(&account, password_stdin)?,
+        account,
+    };
+    let response = client(&endpoint, &config)?
+        .post(&endpoint)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(serde_json::to_vec(&request)?)
+        .send()
+        .await
+        .map_err(http_error)?;
+    match response.status() {
+        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => {
+            return Err(WsvcError::DataError(
+                "authentication failed: invalid account or password".to_owned(),
+            ));
+        }
+        reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::METHOD_NOT_ALLOWED => {
+            return Err(WsvcError::RepoError(format!(
+                "{} does not issue tokens, or the repository is missing",
+                url
+            )));
+        }
+        _ => {}
+    }
+    let body = response
+        .error_for_status()
+        .map_err(http_error)?
+        .bytes()
+        .await
+        .map_err(http_error)?;
+    let response: LoginResponse = serde_json::from_slice(&body)?;
+    let token = StoredToken {
+        account: response.account,
+        token: response.token,
+    };
+    let storage = credentials::store_token(&url, &token).await?;
+    let kept = match storage {
+        TokenStorage::Keyring => "the os keyring".to_owned(),
+        TokenStorage::File(path) => path.display().to_string(),
+    };
+    println!(
+        "{} Logged in to {} as {}, token kept in {}",
+        "[+]".bright_green(),
+        credentials::server_key(&url)?.bold(),
+        token.account.bold(),
+        kept
+    );
+    Ok(())
+}
+
+/// forget the token kept for the server of `remote`.
+pub async fn logout(root: Option<String>, remote: Option<String>) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir().map_err(WsvcFsError::Os)?;
+    let root = root.map(Into::into).unwrap_or(pwd);
+    let repo = Repository::try_open(&root).await.ok();
+    let config = WsvcConfig::load(repo.as_ref()).await?;
+    let url = config.resolve_remote(repo.as_ref(), remote).await?;
+    let server = credentials::server_key(&url)?;
+    match credentials::remove_token(&url).await? {
+        true => println!("{} Logged out of {}", "[+]".bright_green(), server.bold()),
+        false => println!(
+            "{} No token kept for {}",
+            "[*]".bright_blue(),
+            server.bold()
+        ),
+    }
+    Ok(())
+}
@@ -1,10 +1,82 @@
+use chrono::{DateTime, Utc};
 use colored::Colorize;
-use wsvc::{fs::WsvcFsError, model::Repository, WsvcError};
+use wsvc::{
+    fs::WsvcFsError,
+    mailmap::Mailmap,
+    model::{Record, Repository},
+    query::{parse_date, RecordQuery, RecordsPage},
+    trailer::{RecordWithTrailers, TrailerFilter},
+    walk::WalkOrder,
+    WsvcError,
+};
+
+use super::{
+    config::WsvcConfig,
+    detached_notice,
+    poll::{client, http_error, http_url},
+    resolve_record,
+};
+
+/// filters of records shown by `wsvc logs`.
+#[derive(clap::Args, Clone, Default)]
+pub struct LogsFilter {
+    /// only show records with this trailer, `key` or `key=value`, can be repeated
+    #[clap(short, long)]
+    pub trailer: Vec<TrailerFilter>,
+    /// only show records of authors holding this text, case-insensitive
+    #[clap(long)]
+    pub author: Option<String>,
+    /// only show records at or after this date, `YYYY-MM-DD` or rfc3339
+    #[clap(long, value_parser = since_date)]
+    pub since: Option<DateTime<Utc>>,
+    /// only show records before this date, a `YYYY-MM-DD` day included, or rfc3339
+    #[clap(long, value_parser = until_date)]
+    pub until: Option<DateTime<Utc>>,
+    /// only show records whose message has words starting with all words of this text
+    #[clap(long)]
+    pub grep: Option<String>,
+}
+
+fn since_date(value: &str) -> Result<DateTime<Utc>, WsvcError> {
+    parse_date(value, false)
+}
+
+fn until_date(value: &str) -> Result<DateTime<Utc>, WsvcError> {
+    parse_date(value, true)
+}
+
+impl LogsFilter {
+    fn query(&self) -> RecordQuery {
+        RecordQuery {
+            author: self.author.clone(),
+            since: self.since,
+            until: self.until,
+            q: self.grep.clone(),
+        }
+    }
+}
+
+fn print_record(record: &Record, cursor: &str, mailmap: &Mailmap) {
+    let hash_str = record.hash.0.to_string();
+    println!(
+        "Record {} ({}) {}\nAt: {} Author: {}\nMessage: {}",
+        &hash_str[0..6].bold(),
+        hash_str.dimmed(),
+        cursor,
+        record.date.naive_local().to_string().yellow(),
+        mailmap.resolve(&record.author).bright_blue(),
+        record.message
+    );
+}
 
 pub async fn logs(
     root: Option<String>,
+    rev: Option<String>,
     skip: Option<usize>,
     limit: Option<usize>,
+    order: WalkOrder,
+    filter: LogsFilter,
+    json: bool,
 ) -> Result<(), WsvcError> {
     let pwd = std::env::current_dir()
         .map_err(WsvcFsError::Os)?
@@ -12,17 +84,54 @@ pub async fn logs(
         .unwrap()
         .to_string();
     let root = root.unwrap_or(pwd);
-    let repo = Repository::try_open(root).await?;
+    let repo = Repository::open_read_only(&root).await?;
+    let mailmap = Mailmap::load(&root).await?;
     let skip = skip.unwrap_or(0);
     let limit = limit.unwrap_or(10);
-    let mut records = repo.get_records().await?;
-    records.sort_by(|a, b| b.date.cmp(&a.date));
+    let start = match rev {
+        Some(rev) => Some(resolve_record(&repo, Some(rev)).await?.hash),
+        None => None,
+    };
+    let query = filter.query();
+    // records are read only until enough of them are shown.
+    let mut records = vec![];
+    let mut skipped = 0;
+    for hash in repo.walk_ids(start.as_ref(), order).await? {
+        if records.len() == limit {
+            break;
+        }
+        let record = repo.read_record(&hash).await?;
+        if !filter.trailer.iter().all(|filter| filter.matches(&record)) || !query.matches(&record) {
+            continue;
+        }
+        match skipped < skip {
+            true => skipped += 1,
+            false => records.push(record),
+        }
+    }
+    if json {
+        let records = records
+            .iter()
+            .map(RecordWithTrailers::from)
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&records)?);
+        return Ok(());
+    }
     let head_record = repo.get_head_record().await?;
     let latest_record = repo.get_latest_record().await?;
+    if let Some(notice) = detached_notice(&repo.head_state().await?) {
+        println!(
+            "{} {}, new records fork from HEAD.\n",
+            "[!]".bright_yellow(),
+            notice
+        );
+    }
+    if let Some(state) = repo.operation_in_progress().await? {
+        println!("{} {}\n", "[!]".bright_red(), state.notice());
+    }
     let head_hash = head_record.map(|r| r.hash).unwrap_or_default();
     let latest_hash = latest_record.map(|r| r.hash).unwrap_or_default();
-    for record in records.iter().skip(skip).take(limit) {
-        let hash_str = record.hash.0.to_string();
+    for record in &records {
         let cursor = if head_hash == record.hash || latest_hash == record.hash {
             format!(
                 "<== {}{}",
@@ -40,15 +149,106 @@ pub async fn logs(
         } else {
             "".to_owned()
         };
-        println!(
-            "Record {} ({}) {}\nAt: {} Author: {}\nMessage: {}\n",
-            &hash_str[0..6].bold(),
-            hash_str.dimmed(),
-            cursor,
-            record.date.naive_local().to_string().yellow(),
-            record.author.bright_blue(),
-            record.message
-        );
+        print_record(record, &cursor, &mailmap);
+        for attestation in repo.get_attestations_of_record(&record.hash).await? {
+            println!(
+                "Attested: {} by {} at {}{}",
+                attestation.kind.bright_magenta(),
+                mailmap.resolve(&attestation.author).bright_blue(),
+                attestation.date.naive_local().to_string().yellow(),
+                if attestation.signature.is_some() {
+                    " [signed]".bright_green()
+                } else {
+                    "".clear()
+                }
+            );
+        }
+        for note in repo.get_notes_of_record(&record.hash).await? {
+            println!(
+                "Note: {} by {} at {}",
+                note.message,
+                mailmap.resolve(&note.author).bright_blue(),
+                note.date.naive_local().to_string().yellow()
+            );
+        }
+        println!();
+    }
+    Ok(())
+}
+
+/// list records of `remote`, a remote of the config by its name or a url, from the records
+/// endpoint beside its sync, without syncing. the server filters and pages the records.
+pub async fn remote_logs(
+    root: Option<String>,
+    remote: String,
+    skip: Option<usize>,
+    limit: Option<usize>,
+    filter: LogsFilter,
+    json: bool,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir().map_err(WsvcFsError::Os)?;
+    let root = root.map(Into::into).unwrap_or(pwd);
+    let repo = Repository::try_open(&root).await.ok();
+    let config = WsvcConfig::load(repo.as_ref()).await?;
+    let mailmap = Mailmap::load(&root).await?;
+    let url = config.resolve_remote(repo.as_ref(), Some(remote)).await?;
+    let endpoint = http_url(&url, "records").ok_or(WsvcError::BadUsage(format!(
+        "records are listed over http, {} is not a ws:// or wss:// url",
+        url
+    )))?;
+    let query = filter.query();
+    let mut params = vec![
+        ("offset", skip.unwrap_or(0).to_string()),
+        ("limit", limit.unwrap_or(10).to_string()),
+    ];
+    if let Some(author) = query.author {
+        params.push(("author", author));
+    }
+    if let Some(since) = query.since {
+        params.push(("since", since.to_rfc3339()));
+    }
+    if let Some(until) = query.until {
+        params.push(("until", until.to_rfc3339()));
+    }
+    if let Some(q) = query.q {
+        params.push(("q", q));
+    }
+    let response = client(&endpoint, &config)?
+        .get(&endpoint)
+        .query(&params)
+        .send()
+        .await
+        .map_err(http_error)?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(WsvcError::RepoError(format!(
+            "{} does not list records, or the repository is missing",
+            url
+        )));
+    }
+    let body = response
+        .error_for_status()
+        .map_err(http_error)?
+        .bytes()
+        .await
+        .map_err(http_error)?;
+    let page: RecordsPage = serde_json::from_slice(&body)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&page)?);
+        return Ok(());
+    }
+    for record in &page.records {
+        print_record(record, "", &mailmap);
+        println!();
     }
+    println!(
+        "{} {} of {} records of {}",
+        "[*]".bright_blue(),
+        match page.records.len() {
+            0 => "none".to_owned(),
+            n => format!("{}-{}", page.offset + 1, page.offset + n),
+        },
+        page.total,
+        url
+    );
     Ok(())
 }
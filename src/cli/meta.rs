@@ -0,0 +1,37 @@
+use std::path::PathBuf;
+
+use wsvc::{
+    fs::{RepositoryBuilder, WsvcFsError},
+    meta::{MetaFormat, RepoMeta},
+    WsvcError,
+};
+
+use super::config::WsvcConfig;
+
+pub async fn emit(
+    format: MetaFormat,
+    output: Option<String>,
+    workspace: Option<String>,
+    root: Option<String>,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let workspace = PathBuf::from(workspace.unwrap_or(pwd.clone()));
+    let root = root.unwrap_or(pwd);
+    let mut repo = RepositoryBuilder::new(root).read_only(true).open().await?;
+    let config = WsvcConfig::load(Some(&repo)).await?;
+    config.configure(&mut repo);
+    let content = RepoMeta::collect(&repo.to_read_only(), &workspace)
+        .await?
+        .render(format)?;
+    match output {
+        Some(output) => tokio::fs::write(output, content)
+            .await
+            .map_err(WsvcFsError::Os)?,
+        None => print!("{}", content),
+    }
+    Ok(())
+}
@@ -1,12 +1,54 @@
-use clap::{command, Parser};
-use wsvc::WsvcError;
+use clap::Parser;
+use colored::Colorize;
+use wsvc::{
+    head::HeadState, model::Record, readonly::ReadOnlyRepository, state::Operation, WsvcError,
+};
 
+mod abort;
+mod add;
+mod attest;
+mod blame;
+mod blobless;
+mod branch;
+mod changelog;
 mod checkout;
 mod commit;
+mod config;
 mod create;
+mod credentials;
+#[cfg(feature = "server")]
+mod deploy;
+mod dict;
+mod diff;
+mod doctor;
+mod events;
+mod filelog;
+mod filter;
+mod fsck;
+mod gc;
+mod login;
 mod logs;
+mod meta;
+mod modules;
+mod note;
+mod overlay;
+mod peers;
+mod poll;
+mod release;
 mod remote;
+mod repack;
+mod revert;
+mod rpc;
+#[cfg(feature = "server")]
+mod serve;
+mod show;
+mod sign;
+mod stash;
+mod status;
+mod tag;
+mod tls;
 mod transport;
+mod who_changed;
 
 /// wsvc is a simple version control system.
 #[derive(Parser)]
@@ -18,9 +60,51 @@ enum WsvcCli {
         /// commit message
         #[clap(short, long)]
         message: String,
-        /// commit author
+        #[command(flatten)]
+        metadata: commit::MetadataOptions,
+        /// optional workspace dir, if not configured, current dir will be used
         #[clap(short, long)]
-        author: String,
+        workspace: Option<String>,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+        /// advance a detached HEAD to the latest record first, keeping workspace changes
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        advance: bool,
+        /// only record the paths staged by `wsvc add`, the rest is kept as HEAD
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        staged: bool,
+    },
+    /// stage paths of the workspace for `wsvc commit --staged`.
+    Add {
+        /// files or dirs relative to the workspace root, removed files can be staged too
+        #[clap(required = true)]
+        paths: Vec<String>,
+        /// optional workspace dir, if not configured, current dir will be used
+        #[clap(short, long)]
+        workspace: Option<String>,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// unstage paths staged by `wsvc add`, everything if no path is given.
+    Unstage {
+        /// staged files or dirs relative to the workspace root
+        paths: Vec<String>,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// undo the changes of a record in a new record.
+    Revert {
+        /// the record to revert, a hash prefix or a tag
+        hash: String,
+        /// message of the new record, `Revert "<message>"` if omitted
+        #[clap(short, long)]
+        message: Option<String>,
+        /// author of the new record, `commit.author` of the config if omitted
+        #[clap(short, long)]
+        author: Option<String>,
         /// optional workspace dir, if not configured, current dir will be used
         #[clap(short, long)]
         workspace: Option<String>,
@@ -28,13 +112,16 @@ enum WsvcCli {
         #[clap(short, long)]
         root: Option<String>,
     },
-    /// checkout a commit.
+    /// checkout a commit or a branch.
     Checkout {
-        /// the aim commit hash
+        /// the aim commit hash, or a branch name to switch to
         hash: Option<String>,
         /// optional workspace dir, if not configured, current dir will be used
         #[clap(short, long)]
         workspace: Option<String>,
+        /// undo an interrupted checkout, restoring the workspace, HEAD and stashed changes
+        #[clap(long, action = clap::ArgAction::SetTrue, conflicts_with_all = ["hash", "workspace"])]
+        abort: bool,
         /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
         #[clap(short, long)]
         root: Option<String>,
@@ -55,6 +142,8 @@ enum WsvcCli {
     },
     /// show records list
     Logs {
+        /// only show records reachable from this tag or record hash prefix
+        rev: Option<String>,
         /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
         #[clap(short, long)]
         root: Option<String>,
@@ -64,6 +153,17 @@ enum WsvcCli {
         /// limit records that are shown
         #[clap(short, long)]
         limit: Option<usize>,
+        /// order of records, `date`, `topo` or `reverse`
+        #[clap(short, long, default_value = "date")]
+        order: wsvc::walk::WalkOrder,
+        #[command(flatten)]
+        filter: logs::LogsFilter,
+        /// list records of this remote or url from the server instead, without syncing
+        #[clap(long, conflicts_with_all = ["rev", "order", "trailer"])]
+        remote: Option<String>,
+        /// print records with their trailers as json
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        json: bool,
     },
     /// clone a repository
     Clone {
@@ -71,16 +171,733 @@ enum WsvcCli {
         url: String,
         /// the local repository dir
         dir: Option<String>,
+        /// `blobless` syncs records and trees only, blobs are fetched from the remote
+        /// origin when they are checked out or read
+        #[clap(long, value_parser = ["blobless"])]
+        filter: Option<String>,
+        /// sync the records without checking out the latest one
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        no_checkout: bool,
+        #[command(flatten)]
+        options: transport::ConnectOptions,
     },
     /// sync a repository with remote origin
-    Sync,
+    Sync {
+        /// sync with this url or remote of the config instead of remote origin, e.g.
+        /// `peer://<name>`, the remote origin is left as is
+        url: Option<String>,
+        /// sync with the remote origin and every remote of the config at once
+        #[clap(long, action = clap::ArgAction::SetTrue, conflicts_with = "url")]
+        all: bool,
+        /// remotes synced at the same time with `--all`, 4 by default
+        #[clap(short, long, default_value_t = 4, requires = "all")]
+        jobs: usize,
+        /// undo a sync in progress or interrupted, restoring the workspace, HEAD and
+        /// branches as they were before it
+        #[clap(long, action = clap::ArgAction::SetTrue, conflicts_with_all = ["url", "all"])]
+        abort: bool,
+        /// leave the workspace and HEAD as is, like `wsvc fetch`
+        #[clap(long, action = clap::ArgAction::SetTrue, conflicts_with = "abort")]
+        no_checkout: bool,
+        #[command(flatten)]
+        options: transport::ConnectOptions,
+    },
+    /// sync a repository with remote origin without checking out, the workspace and HEAD
+    /// are left as is and the branch HEAD is on is not fetched
+    Fetch {
+        /// fetch from this url or remote of the config instead of remote origin, the
+        /// remote origin is left as is
+        url: Option<String>,
+        /// fetch from the remote origin and every remote of the config at once
+        #[clap(long, action = clap::ArgAction::SetTrue, conflicts_with = "url")]
+        all: bool,
+        /// remotes fetched from at the same time with `--all`, 4 by default
+        #[clap(short, long, default_value_t = 4, requires = "all")]
+        jobs: usize,
+        #[command(flatten)]
+        options: transport::ConnectOptions,
+    },
+    /// sync with a remote, only sending the chosen branches and tags, e.g.
+    /// `wsvc push origin main`
+    Push {
+        /// remote of the config or url, the remote origin if not specified. the remote
+        /// origin is left as is
+        remote: Option<String>,
+        /// branches, tags or refspecs like `refs/release-*` to send, the `push` refspecs
+        /// of the remote if not specified
+        refs: Vec<String>,
+        /// replace diverged branches of the remote, only while they are at the records
+        /// seen by this push, or at `<branch>:<record>` if given
+        #[clap(
+            long,
+            value_name = "BRANCH[:RECORD]",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "",
+            action = clap::ArgAction::Append
+        )]
+        force_with_lease: Vec<String>,
+        #[command(flatten)]
+        options: transport::ConnectOptions,
+    },
+    /// sync with a remote, only storing the chosen branches and tags of the remote
+    Pull {
+        /// remote of the config or url, the remote origin if not specified. the remote
+        /// origin is left as is
+        remote: Option<String>,
+        /// branches, tags or refspecs like `refs/release-*` to store, the `fetch`
+        /// refspecs of the remote if not specified
+        refs: Vec<String>,
+        #[command(flatten)]
+        options: transport::ConnectOptions,
+    },
+    /// share a repository with peers through a relay.
+    #[cfg(feature = "server")]
+    Relay {
+        #[command(subcommand)]
+        cmd: RelaySubCmd,
+    },
+    /// find and serve peers on the local network.
+    Peers {
+        #[command(subcommand)]
+        cmd: PeersSubCmd,
+    },
+    /// serve hosted repositories of the roots in a hosting config, until interrupted.
+    #[cfg(feature = "server")]
+    Serve {
+        /// hosting config mapping hostnames and url prefixes to repository dirs
+        #[clap(short, long)]
+        config: String,
+        /// the listening address, `0.0.0.0:7878` by default
+        #[clap(short, long)]
+        listen: Option<String>,
+    },
+    /// mint, list and revoke scoped deploy tokens of a root of hosted repositories, for ci
+    /// and deployment scripts.
+    #[cfg(feature = "server")]
+    DeployToken {
+        #[command(subcommand)]
+        cmd: DeployTokenSubCmd,
+    },
+    /// get or set config, the repository config overrides the global one.
+    Config {
+        #[command(subcommand)]
+        cmd: ConfigSubCmd,
+    },
     /// set remote origin
+    #[command(args_conflicts_with_subcommands = true)]
     Remote {
         /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
         #[clap(short, long)]
         root: Option<String>,
         /// remote origin url
-        url: String,
+        url: Option<String>,
+        #[command(subcommand)]
+        cmd: Option<RemoteSubCmd>,
+    },
+    /// log in to the server of a remote, keeping the token it issues in the os keyring for
+    /// later syncs.
+    Login {
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+        /// remote name or url, origin by default
+        remote: Option<String>,
+        /// account to log in as, `auth.account` by default
+        #[clap(short, long)]
+        account: Option<String>,
+        /// read the password from stdin instead of prompting
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        password_stdin: bool,
+    },
+    /// forget the token kept for the server of a remote.
+    Logout {
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+        /// remote name or url, origin by default
+        remote: Option<String>,
+    },
+    /// rewrite history of all records.
+    Filter {
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+        /// the path relative to workspace that will be purged from all records
+        #[clap(long)]
+        remove_path: Option<String>,
+        /// permanently rewrite record authors with the canonical ones in .wsvcmailmap
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        mailmap: bool,
+    },
+    /// show the id of the repository, its origin and where it was cloned from.
+    Doctor {
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// verify the content of every stored record, tree and blob, and that records have
+    /// all their trees and blobs.
+    Fsck {
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+        /// move corrupt objects to the quarantine dir of the repository
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        quarantine: bool,
+    },
+    /// remove trees and blobs not reachable from any record.
+    Gc {
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+        /// only report what would be removed and the bytes reclaimable
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        dry_run: bool,
+    },
+    /// pack loose objects and existing packs into a single packfile.
+    Repack {
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// show how objects are stored, loose and packed, the chunks shared by large files and
+    /// the bytes a repack would reclaim.
+    Stats {
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+        /// print the stats as json
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        json: bool,
+    },
+    /// zstd dictionary compressing syncs with this repository as a server.
+    Dict {
+        #[command(subcommand)]
+        cmd: DictSubCmd,
+    },
+    /// sign a record with an ssh key or gpg key.
+    Sign {
+        /// the record hash prefix, HEAD will be used if not specified
+        hash: Option<String>,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+        #[command(flatten)]
+        options: sign::SignOptions,
+    },
+    /// attest a record, e.g. `reviewed-by` or `build-passed`.
+    Attest {
+        /// the attestation kind
+        kind: String,
+        /// the record hash prefix, HEAD will be used if not specified
+        hash: Option<String>,
+        /// attestation author
+        #[clap(short, long)]
+        author: String,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+        #[command(flatten)]
+        options: sign::SignOptions,
+    },
+    /// attach a note to a record without changing its hash.
+    Note {
+        /// the record hash prefix, HEAD will be used if not specified
+        hash: Option<String>,
+        /// note message
+        #[clap(short, long)]
+        message: String,
+        /// note author
+        #[clap(short, long)]
+        author: String,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// create or delete a branch, list all branches if no name is given.
+    Branch {
+        /// the branch name
+        name: Option<String>,
+        /// the record hash prefix to create the branch at, HEAD will be used if not specified
+        hash: Option<String>,
+        /// delete the branch instead, its records are kept
+        #[clap(short, long, action = clap::ArgAction::SetTrue, requires = "name", conflicts_with_all = ["hash", "force"])]
+        delete: bool,
+        /// move the existing branch with the same name
+        #[clap(short, long, action = clap::ArgAction::SetTrue)]
+        force: bool,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// switch to a branch and checkout its record, new records advance the branch.
+    Switch {
+        /// the branch name
+        name: String,
+        /// create the branch at HEAD first
+        #[clap(short, long, action = clap::ArgAction::SetTrue)]
+        create: bool,
+        /// optional workspace dir, if not configured, current dir will be used
+        #[clap(short, long)]
+        workspace: Option<String>,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// tag a record, list all tags if no name is given.
+    Tag {
+        /// the tag name
+        name: Option<String>,
+        /// the record hash prefix, HEAD will be used if not specified
+        hash: Option<String>,
+        /// replace the existing tag with the same name
+        #[clap(short, long, action = clap::ArgAction::SetTrue)]
+        force: bool,
+        /// message of an annotated tag, the tag is lightweight if not specified
+        #[clap(short, long, requires = "name")]
+        message: Option<String>,
+        /// author of an annotated tag, `commit.author` in config if not specified
+        #[clap(short, long, requires = "message")]
+        author: Option<String>,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// name a record by its nearest tag, e.g. `v1.0-3-g1a2b3c4`.
+    Describe {
+        /// the record hash prefix, HEAD will be used if not specified
+        hash: Option<String>,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// list files changed between two records.
+    Diff {
+        /// the older record hash prefix, the first parent of the newer one if not specified
+        from: Option<String>,
+        /// the newer record hash prefix, HEAD will be used if not specified
+        to: Option<String>,
+        #[command(flatten)]
+        format: diff::DiffFormat,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// show files of the workspace changed from the HEAD record.
+    Status {
+        /// print the status as json
+        #[clap(long, action = clap::ArgAction::SetTrue, conflicts_with = "porcelain")]
+        json: bool,
+        /// print changes as stable tab separated lines for scripts, the same as `wsvc diff`
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        porcelain: bool,
+        /// end porcelain lines with NUL instead of newline and do not quote paths
+        #[clap(short = 'z', action = clap::ArgAction::SetTrue, requires = "porcelain")]
+        nul: bool,
+        /// also pair removed and added text files sharing at least this percent of lines as
+        /// renames, 50 if no percent is given
+        #[clap(short = 'M', long, value_name = "PERCENT", num_args = 0..=1, default_missing_value = "50", value_parser = clap::value_parser!(u8).range(0..=100))]
+        find_renames: Option<u8>,
+        /// optional workspace dir, if not configured, current dir will be used
+        #[clap(short, long)]
+        workspace: Option<String>,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// tag a record as a release with its changelog and archive, and push them if asked.
+    Release {
+        /// the release version, also the tag name
+        version: String,
+        #[command(flatten)]
+        release: release::ReleaseOptions,
+        #[command(flatten)]
+        options: transport::ConnectOptions,
+    },
+    /// print markdown release notes grouped by conventional commit type.
+    Changelog {
+        /// the tag or record hash prefix of the previous release, all records if not specified
+        #[clap(long)]
+        from: Option<String>,
+        /// the tag or record hash prefix of the release, HEAD will be used if not specified
+        #[clap(long)]
+        to: Option<String>,
+        /// heading of the notes, `<to> (<date>)` if not specified
+        #[clap(long)]
+        title: Option<String>,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// print a file or list a dir of a record, e.g. `wsvc show 1a2b3c:src/main.rs`.
+    Show {
+        /// `<record>:<path>`, HEAD is used if the record is omitted, the root dir if the path is
+        spec: String,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// show the record that last changed each line of a file.
+    Blame {
+        /// the path of the file relative to the workspace root, `/` separated
+        path: String,
+        /// the tag or record hash prefix to blame the file of, HEAD will be used if not specified
+        rev: Option<String>,
+        /// print the lines with their records as json
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        json: bool,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// list records that changed a file or dir, the latest first.
+    Filelog {
+        /// the path relative to the workspace root, `/` separated
+        path: String,
+        /// only show records reachable from this tag or record hash prefix
+        rev: Option<String>,
+        /// limit records that are shown
+        #[clap(short, long)]
+        limit: Option<usize>,
+        /// print the records with the entry of the path as json
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        json: bool,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// summarize who changed a file or dir, how many times and how recently.
+    WhoChanged {
+        /// the path relative to the workspace root, `/` separated
+        path: String,
+        /// records to count, `<from>..<to>` of tags or record hash prefixes, either may be omitted, `to` defaults to HEAD
+        #[clap(long)]
+        range: Option<String>,
+        /// print the authors with their changes as json
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        json: bool,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// repository metadata for build scripts.
+    Meta {
+        #[command(subcommand)]
+        cmd: MetaSubCmd,
+    },
+    /// serve a local json-rpc api for editors, one message per line.
+    Rpc {
+        /// socket path, `rpc.sock` in the repository dir by default. a named pipe name on windows
+        #[clap(short, long)]
+        socket: Option<String>,
+        /// optional workspace dir, if not configured, current dir will be used
+        #[clap(short, long)]
+        workspace: Option<String>,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// journal of commits, checkouts, syncs and gcs for external tools.
+    Events {
+        #[command(subcommand)]
+        cmd: EventsSubCmd,
+    },
+    /// verify signatures of a record.
+    Verify {
+        /// the record hash prefix, HEAD will be used if not specified
+        hash: Option<String>,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+        /// ssh allowed signers file used to verify ssh signatures
+        #[clap(long)]
+        allowed_signers: Option<String>,
+        /// gpg compatible program, `gpg` by default
+        #[clap(long)]
+        gpg_program: Option<String>,
+    },
+    /// sub-repositories pinned at records in `.wsvcmodules`.
+    Modules {
+        #[command(subcommand)]
+        cmd: ModulesSubCmd,
+    },
+    /// records materialized into dirs of the workspace, kept out of its records.
+    Overlay {
+        #[command(subcommand)]
+        cmd: OverlaySubCmd,
+    },
+    /// put workspace changes aside without recording them.
+    Stash {
+        #[command(subcommand)]
+        cmd: StashSubCmd,
+    },
+}
+
+#[cfg(feature = "server")]
+#[derive(Parser)]
+enum RelaySubCmd {
+    /// host the repository in the relay room set as remote origin, until interrupted.
+    Host {
+        #[command(flatten)]
+        options: transport::ConnectOptions,
+    },
+}
+
+#[derive(Parser)]
+enum RemoteSubCmd {
+    /// connect to the remote with the handshake only, and report reachability, latency,
+    /// server version, capabilities and auth status.
+    Ping {
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+        /// ping this url instead of remote origin
+        url: Option<String>,
+        #[command(flatten)]
+        options: transport::ConnectOptions,
+    },
+}
+
+#[derive(Parser)]
+enum PeersSubCmd {
+    /// list wsvc peers advertised on the local network.
+    Discover {
+        /// seconds to wait for peers, 3 by default
+        #[clap(short, long)]
+        timeout: Option<u64>,
+    },
+    /// serve the repository to peers and advertise it on the local network, until interrupted.
+    #[cfg(feature = "server")]
+    Serve {
+        /// the advertised peer name, the repository dir name will be used if not specified
+        #[clap(short, long)]
+        name: Option<String>,
+        /// the listening port, a random port will be used if not specified
+        #[clap(short, long)]
+        port: Option<u16>,
+        /// serve over quic instead of websocket, faster on lossy or high latency networks
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        quic: bool,
+        /// passphrase to encrypt frames end to end, peers must use the same one
+        #[clap(long)]
+        passphrase: Option<String>,
+    },
+}
+
+#[cfg(feature = "server")]
+#[derive(Parser)]
+enum DeployTokenSubCmd {
+    /// mint a deploy token, sent by clients as the password of the account `name`, or as
+    /// a bearer token over http.
+    Mint {
+        /// root dir of the hosted repositories the token is for
+        #[clap(short, long)]
+        dir: String,
+        /// name of the token, the account it authenticates as
+        name: String,
+        /// glob of the names of repositories the token may access
+        #[clap(long, default_value = "*")]
+        repos: String,
+        /// allow pushing and publishing, the token may only read if not set
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        write: bool,
+        /// last day the token is accepted, `YYYY-MM-DD` or rfc3339, never expires if not set
+        #[clap(long)]
+        expires: Option<String>,
+    },
+    /// list deploy tokens of a root, their secrets are not kept.
+    List {
+        /// root dir of the hosted repositories
+        #[clap(short, long)]
+        dir: String,
+    },
+    /// revoke a deploy token by its id.
+    Revoke {
+        /// root dir of the hosted repositories
+        #[clap(short, long)]
+        dir: String,
+        /// id of the token
+        id: String,
+    },
+}
+
+#[derive(Parser)]
+enum MetaSubCmd {
+    /// write current record hash, description, dirty flag and date.
+    Emit {
+        /// output format, `json` or `env`
+        #[clap(short, long, default_value = "json")]
+        format: wsvc::meta::MetaFormat,
+        /// output file, stdout will be used if not specified
+        #[clap(short, long)]
+        output: Option<String>,
+        /// optional workspace dir, if not configured, current dir will be used
+        #[clap(short, long)]
+        workspace: Option<String>,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+}
+
+#[derive(Parser)]
+enum DictSubCmd {
+    /// train the transport dictionary on records and trees, replacing the previous one.
+    Train {
+        /// max size of the dictionary in bytes
+        #[clap(short, long, default_value_t = wsvc::codec::DEFAULT_DICT_SIZE)]
+        size: usize,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// show the transport dictionary and those received from remotes.
+    Show {
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// remove the transport dictionary, syncs use zstd without one.
+    Remove {
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+}
+
+#[derive(Parser)]
+enum EventsSubCmd {
+    /// print the latest events as json lines.
+    Tail {
+        /// count of events printed
+        #[clap(short = 'n', long, default_value = "10")]
+        lines: usize,
+        /// keep printing new events until interrupted
+        #[clap(short, long, action = clap::ArgAction::SetTrue)]
+        follow: bool,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+}
+
+#[derive(Parser)]
+enum ModulesSubCmd {
+    /// fetch records of modules from their urls, cloning missing ones, without checking out.
+    Sync {
+        /// optional workspace dir, if not configured, current dir will be used
+        #[clap(short, long)]
+        workspace: Option<String>,
+        #[command(flatten)]
+        options: transport::ConnectOptions,
+    },
+    /// checkout modules at their pinned records, cloning and fetching them if needed.
+    Update {
+        /// drop changes not recorded in modules
+        #[clap(short, long, action = clap::ArgAction::SetTrue)]
+        force: bool,
+        /// optional workspace dir, if not configured, current dir will be used
+        #[clap(short, long)]
+        workspace: Option<String>,
+        #[command(flatten)]
+        options: transport::ConnectOptions,
+    },
+    /// show whether modules match their pinned records.
+    Status {
+        /// print the status as json, without modules of modules
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        json: bool,
+        /// optional workspace dir, if not configured, current dir will be used
+        #[clap(short, long)]
+        workspace: Option<String>,
+    },
+}
+
+#[derive(Parser)]
+enum OverlaySubCmd {
+    /// materialize a record into a dir of the workspace as an overlay.
+    Add {
+        /// the overlay dir relative to the workspace root
+        path: String,
+        /// the record hash prefix, HEAD of the source repository will be used if not specified
+        hash: Option<String>,
+        /// root dir of the source repository, this repository if not specified
+        #[clap(long)]
+        from: Option<String>,
+        /// optional workspace dir, if not configured, current dir will be used
+        #[clap(short, long)]
+        workspace: Option<String>,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// move an overlay to another record of its source repository.
+    Update {
+        /// the overlay dir relative to the workspace root
+        path: String,
+        /// the record hash prefix, HEAD of the source repository will be used if not specified
+        hash: Option<String>,
+        /// optional workspace dir, if not configured, current dir will be used
+        #[clap(short, long)]
+        workspace: Option<String>,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// stop tracking an overlay, its files become part of the workspace.
+    Detach {
+        /// the overlay dir relative to the workspace root
+        path: String,
+        /// delete the files of the overlay too
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        remove: bool,
+        /// optional workspace dir, if not configured, current dir will be used
+        #[clap(short, long)]
+        workspace: Option<String>,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// list overlays of the workspace.
+    List {
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+}
+
+#[derive(Parser)]
+enum StashSubCmd {
+    /// stash the workspace changes and reset the workspace to HEAD.
+    Push {
+        /// stash message, `WIP on <HEAD>` if not specified
+        #[clap(short, long)]
+        message: Option<String>,
+        /// optional workspace dir, if not configured, current dir will be used
+        #[clap(short, long)]
+        workspace: Option<String>,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// apply a stash to the workspace and drop it.
+    Pop {
+        /// index of the stash in `wsvc stash list`, the latest stash is 0
+        #[clap(default_value_t = 0)]
+        index: usize,
+        /// optional workspace dir, if not configured, current dir will be used
+        #[clap(short, long)]
+        workspace: Option<String>,
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
+    },
+    /// list stashes, the latest first.
+    List {
+        /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+        #[clap(short, long)]
+        root: Option<String>,
     },
 }
 
@@ -111,25 +928,421 @@ enum ConfigSubCmd {
     },
 }
 
+/// explain a detached HEAD, `None` if HEAD is not detached.
+fn detached_notice(state: &HeadState) -> Option<String> {
+    match state {
+        HeadState::Detached {
+            head,
+            latest,
+            behind,
+            fast_forward,
+        } => {
+            let head = head.0.to_hex();
+            let latest = latest.0.to_hex();
+            Some(if *fast_forward {
+                format!(
+                    "HEAD {} is detached, {} record(s) behind the latest record {}",
+                    &head[0..6],
+                    behind,
+                    &latest[0..6]
+                )
+            } else {
+                format!(
+                    "HEAD {} is detached, on another line than the latest record {}",
+                    &head[0..6],
+                    &latest[0..6]
+                )
+            })
+        }
+        _ => None,
+    }
+}
+
+/// resolve a record by tag name or hash prefix, tags win. HEAD will be used if `rev` is
+/// `None`.
+async fn resolve_record(
+    repo: &ReadOnlyRepository,
+    rev: Option<String>,
+) -> Result<Record, WsvcError> {
+    let hash = match rev {
+        Some(rev) => match repo.resolve_tag(&rev).await? {
+            Some(record) => return Ok(record),
+            None => rev,
+        },
+        None => {
+            return repo
+                .get_head_record()
+                .await?
+                .ok_or(WsvcError::EmptyRepoError)
+        }
+    };
+    let records = repo.find_records_by_prefix(&hash).await?;
+    if records.len() > 1 {
+        println!("{}", "More than one record found:".bright_red());
+        for record in records.iter() {
+            let hash_str = record.hash.0.to_hex().to_string();
+            println!(
+                "Record {} ({})\nAt: {} Author: {}\nMessage: {}\n",
+                &hash_str[0..6].bold(),
+                hash_str.dimmed(),
+                record.date.naive_local().to_string().yellow(),
+                record.author.bright_blue(),
+                record.message
+            );
+        }
+        return Err(WsvcError::BadUsage(format!(
+            "more than one record found for hash {}",
+            hash
+        )));
+    }
+    records
+        .into_iter()
+        .next()
+        .ok_or(WsvcError::BadUsage(format!(
+            "no record found for hash {}",
+            hash
+        )))
+}
+
 pub async fn run() -> Result<(), WsvcError> {
     let cli = WsvcCli::parse();
     match cli {
         WsvcCli::Commit {
+            message,
+            metadata,
+            workspace,
+            root,
+            advance,
+            staged,
+        } => commit::commit(message, metadata, workspace, root, advance, staged).await,
+        WsvcCli::Add {
+            paths,
+            workspace,
+            root,
+        } => add::add(paths, workspace, root).await,
+        WsvcCli::Unstage { paths, root } => add::unstage(paths, root).await,
+        WsvcCli::Revert {
+            hash,
             message,
             author,
             workspace,
             root,
-        } => commit::commit(message, author, workspace, root).await,
+        } => revert::revert(hash, message, author, workspace, root).await,
+        WsvcCli::Checkout {
+            abort: true, root, ..
+        } => abort::abort(Operation::Checkout, root).await,
         WsvcCli::Checkout {
             hash,
             workspace,
             root,
+            ..
         } => checkout::checkout(hash, workspace, root).await,
         WsvcCli::Init { bare } => create::init(bare).await,
         WsvcCli::New { name, bare } => create::new(name, bare).await,
-        WsvcCli::Logs { root, skip, limit } => logs::logs(root, skip, limit).await,
-        WsvcCli::Clone { url, dir } => transport::clone(url, dir).await,
-        WsvcCli::Sync => transport::sync().await,
-        WsvcCli::Remote { root, url } => remote::remote_set(root, url).await,
+        WsvcCli::Logs {
+            root,
+            skip,
+            limit,
+            filter,
+            remote: Some(remote),
+            json,
+            ..
+        } => logs::remote_logs(root, remote, skip, limit, filter, json).await,
+        WsvcCli::Logs {
+            rev,
+            root,
+            skip,
+            limit,
+            order,
+            filter,
+            json,
+            ..
+        } => logs::logs(root, rev, skip, limit, order, filter, json).await,
+        WsvcCli::Clone {
+            url,
+            dir,
+            filter,
+            no_checkout,
+            options,
+        } => transport::clone(url, dir, filter, !no_checkout, options).await,
+        WsvcCli::Sync { abort: true, .. } => abort::abort(Operation::Sync, None).await,
+        WsvcCli::Sync {
+            url,
+            all,
+            jobs,
+            no_checkout,
+            options,
+            ..
+        } => transport::sync(url, all, jobs, !no_checkout, options).await,
+        WsvcCli::Fetch {
+            url,
+            all,
+            jobs,
+            options,
+        } => transport::sync(url, all, jobs, false, options).await,
+        WsvcCli::Push {
+            remote,
+            refs,
+            force_with_lease,
+            options,
+        } => transport::sync_refs(remote, refs, true, force_with_lease, options).await,
+        WsvcCli::Pull {
+            remote,
+            refs,
+            options,
+        } => transport::sync_refs(remote, refs, false, vec![], options).await,
+        #[cfg(feature = "server")]
+        WsvcCli::Relay {
+            cmd: RelaySubCmd::Host { options },
+        } => transport::relay_host(options).await,
+        WsvcCli::Peers {
+            cmd: PeersSubCmd::Discover { timeout },
+        } => peers::discover(timeout).await,
+        #[cfg(feature = "server")]
+        WsvcCli::Peers {
+            cmd:
+                PeersSubCmd::Serve {
+                    name,
+                    port,
+                    quic,
+                    passphrase,
+                },
+        } => peers::serve(name, port, quic, passphrase).await,
+        #[cfg(feature = "server")]
+        WsvcCli::Serve { config, listen } => serve::serve(config, listen).await,
+        #[cfg(feature = "server")]
+        WsvcCli::DeployToken { cmd } => match cmd {
+            DeployTokenSubCmd::Mint {
+                dir,
+                name,
+                repos,
+                write,
+                expires,
+            } => deploy::deploy_mint(dir, name, repos, write, expires).await,
+            DeployTokenSubCmd::List { dir } => deploy::deploy_list(dir).await,
+            DeployTokenSubCmd::Revoke { dir, id } => deploy::deploy_revoke(dir, id).await,
+        },
+        WsvcCli::Config { cmd } => match cmd {
+            ConfigSubCmd::Get { key } => config::config_get(key).await,
+            ConfigSubCmd::Set { key, value, global } => {
+                config::config_set(key, value, global.unwrap_or(false)).await
+            }
+            ConfigSubCmd::Unset { key, global } => {
+                config::config_unset(key, global.unwrap_or(false)).await
+            }
+        },
+        WsvcCli::Remote {
+            cmd: Some(RemoteSubCmd::Ping { root, url, options }),
+            ..
+        } => remote::remote_ping(root, url, options).await,
+        WsvcCli::Remote { root, url, .. } => match url {
+            Some(url) => remote::remote_set(root, url).await,
+            None => Err(WsvcError::BadUsage(
+                "give the remote origin url, or a subcommand".to_owned(),
+            )),
+        },
+        WsvcCli::Login {
+            root,
+            remote,
+            account,
+            password_stdin,
+        } => login::login(root, remote, account, password_stdin).await,
+        WsvcCli::Logout { root, remote } => login::logout(root, remote).await,
+        WsvcCli::Filter {
+            root,
+            remove_path,
+            mailmap,
+        } => filter::filter(root, remove_path, mailmap).await,
+        WsvcCli::Doctor { root } => doctor::doctor(root).await,
+        WsvcCli::Fsck { root, quarantine } => fsck::fsck(root, quarantine).await,
+        WsvcCli::Gc { root, dry_run } => gc::gc(root, dry_run).await,
+        WsvcCli::Repack { root } => repack::repack(root).await,
+        WsvcCli::Stats { root, json } => repack::stats(root, json).await,
+        WsvcCli::Dict {
+            cmd: DictSubCmd::Train { size, root },
+        } => dict::dict_train(size, root).await,
+        WsvcCli::Dict {
+            cmd: DictSubCmd::Show { root },
+        } => dict::dict_show(root).await,
+        WsvcCli::Dict {
+            cmd: DictSubCmd::Remove { root },
+        } => dict::dict_remove(root).await,
+        WsvcCli::Sign {
+            hash,
+            root,
+            options,
+        } => sign::sign(root, hash, options).await,
+        WsvcCli::Attest {
+            kind,
+            hash,
+            author,
+            root,
+            options,
+        } => attest::attest(root, kind, hash, author, options).await,
+        WsvcCli::Note {
+            hash,
+            message,
+            author,
+            root,
+        } => note::note(root, hash, message, author).await,
+        WsvcCli::Branch {
+            name,
+            hash,
+            delete,
+            force,
+            root,
+        } => branch::branch(root, name, hash, delete, force).await,
+        WsvcCli::Switch {
+            name,
+            create,
+            workspace,
+            root,
+        } => branch::switch(name, create, workspace, root).await,
+        WsvcCli::Tag {
+            name,
+            hash,
+            force,
+            message,
+            author,
+            root,
+        } => tag::tag(root, name, hash, force, message, author).await,
+        WsvcCli::Describe { hash, root } => tag::describe(root, hash).await,
+        WsvcCli::Diff {
+            from,
+            to,
+            format,
+            root,
+        } => diff::diff(root, from, to, format).await,
+        WsvcCli::Status {
+            json,
+            porcelain,
+            nul,
+            find_renames,
+            workspace,
+            root,
+        } => status::status(workspace, root, json, porcelain, nul, find_renames).await,
+        WsvcCli::Release {
+            version,
+            release,
+            options,
+        } => release::release(version, release, options).await,
+        WsvcCli::Changelog {
+            from,
+            to,
+            title,
+            root,
+        } => changelog::changelog(root, from, to, title).await,
+        WsvcCli::Show { spec, root } => show::show(root, spec).await,
+        WsvcCli::Blame {
+            path,
+            rev,
+            json,
+            root,
+        } => blame::blame(root, path, rev, json).await,
+        WsvcCli::Filelog {
+            path,
+            rev,
+            limit,
+            json,
+            root,
+        } => filelog::filelog(root, path, rev, limit, json).await,
+        WsvcCli::WhoChanged {
+            path,
+            range,
+            json,
+            root,
+        } => who_changed::who_changed(root, path, range, json).await,
+        WsvcCli::Rpc {
+            socket,
+            workspace,
+            root,
+        } => rpc::rpc(root, workspace, socket).await,
+        WsvcCli::Events {
+            cmd:
+                EventsSubCmd::Tail {
+                    lines,
+                    follow,
+                    root,
+                },
+        } => events::events_tail(root, lines, follow).await,
+        WsvcCli::Meta {
+            cmd:
+                MetaSubCmd::Emit {
+                    format,
+                    output,
+                    workspace,
+                    root,
+                },
+        } => meta::emit(format, output, workspace, root).await,
+        WsvcCli::Verify {
+            hash,
+            root,
+            allowed_signers,
+            gpg_program,
+        } => sign::verify(root, hash, allowed_signers, gpg_program).await,
+        WsvcCli::Modules {
+            cmd: ModulesSubCmd::Sync { workspace, options },
+        } => modules::modules_sync(workspace, options).await,
+        WsvcCli::Modules {
+            cmd:
+                ModulesSubCmd::Update {
+                    force,
+                    workspace,
+                    options,
+                },
+        } => modules::modules_update(workspace, force, options).await,
+        WsvcCli::Modules {
+            cmd: ModulesSubCmd::Status { json, workspace },
+        } => modules::modules_status(workspace, json).await,
+        WsvcCli::Overlay {
+            cmd:
+                OverlaySubCmd::Add {
+                    path,
+                    hash,
+                    from,
+                    workspace,
+                    root,
+                },
+        } => overlay::overlay_add(path, hash, from, workspace, root).await,
+        WsvcCli::Overlay {
+            cmd:
+                OverlaySubCmd::Update {
+                    path,
+                    hash,
+                    workspace,
+                    root,
+                },
+        } => overlay::overlay_update(path, hash, workspace, root).await,
+        WsvcCli::Overlay {
+            cmd:
+                OverlaySubCmd::Detach {
+                    path,
+                    remove,
+                    workspace,
+                    root,
+                },
+        } => overlay::overlay_detach(path, remove, workspace, root).await,
+        WsvcCli::Overlay {
+            cmd: OverlaySubCmd::List { root },
+        } => overlay::overlay_list(root).await,
+        WsvcCli::Stash {
+            cmd:
+                StashSubCmd::Push {
+                    message,
+                    workspace,
+                    root,
+                },
+        } => stash::stash_push(message, workspace, root).await,
+        WsvcCli::Stash {
+            cmd:
+                StashSubCmd::Pop {
+                    index,
+                    workspace,
+                    root,
+                },
+        } => stash::stash_pop(index, workspace, root).await,
+        WsvcCli::Stash {
+            cmd: StashSubCmd::List { root },
+        } => stash::stash_list(root).await,
     }
 }
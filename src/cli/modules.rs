@@ -0,0 +1,199 @@
+use std::path::{Path, PathBuf};
+
+use colored::Colorize;
+use wsvc::{
+    fs::{RepoGuard, WsvcFsError},
+    model::Repository,
+    modules::{Module, ModuleStatus, ModulesManifest},
+    WsvcError,
+};
+
+use super::{
+    config::WsvcConfig,
+    diff::print_change,
+    transport::{sync_journaled, ConnectOptions},
+};
+
+fn resolve_workspace(workspace: Option<String>) -> Result<PathBuf, WsvcFsError> {
+    match workspace {
+        Some(workspace) => Ok(PathBuf::from(workspace)),
+        None => Ok(std::env::current_dir()?),
+    }
+}
+
+/// path of a module relative to the top workspace, `/` separated.
+fn display_path(prefix: &str, module: &Module) -> String {
+    match prefix {
+        "" => module.path.clone(),
+        prefix => format!("{}/{}", prefix, module.path),
+    }
+}
+
+/// open the repository of a module with its excludes file, a repository with the url of the
+/// module as origin is created first if it is not cloned yet.
+async fn open_or_init(workspace: &Path, module: &Module) -> Result<Repository, WsvcError> {
+    let mut repo = match module.open(workspace, false).await? {
+        Some(repo) => repo,
+        None => {
+            let repo = Repository::new(module.dir(workspace), false).await?;
+            repo.write_origin(module.url.clone()).await?;
+            repo
+        }
+    };
+    let config = WsvcConfig::load(Some(&repo)).await?;
+    config.configure(&mut repo);
+    Ok(repo)
+}
+
+/// the state of a module, with the excludes file of its repository.
+async fn module_status(workspace: &Path, module: &Module) -> Result<ModuleStatus, WsvcError> {
+    let excludes_file = match module.open(workspace, true).await? {
+        Some(repo) => WsvcConfig::load(Some(&repo)).await?.excludes_file(),
+        None => None,
+    };
+    module.status(workspace, excludes_file).await
+}
+
+/// fetch records of all modules from their urls, cloning missing ones, without checking out.
+/// modules of the checked out modules are fetched too.
+#[async_recursion::async_recursion(?Send)]
+async fn sync_modules(
+    workspace: &Path,
+    prefix: &str,
+    options: &ConnectOptions,
+) -> Result<(), WsvcError> {
+    for module in ModulesManifest::load(workspace).await?.modules {
+        let path = display_path(prefix, &module);
+        println!("{} Syncing module {}", "[+]".bright_green(), path.bold());
+        let repo = open_or_init(workspace, &module).await?;
+        let guard = RepoGuard::new(&repo).await?;
+        sync_journaled(&repo, Some(&module.url), None, options, false).await?;
+        drop(guard);
+        sync_modules(&module.dir(workspace), &path, options).await?;
+    }
+    Ok(())
+}
+
+/// checkout the pinned record of all modules, cloning and fetching them first if needed,
+/// then update the modules of the pinned records.
+#[async_recursion::async_recursion(?Send)]
+async fn update_modules(
+    workspace: &Path,
+    prefix: &str,
+    force: bool,
+    options: &ConnectOptions,
+) -> Result<(), WsvcError> {
+    for module in ModulesManifest::load(workspace).await?.modules {
+        let path = display_path(prefix, &module);
+        let dir = module.dir(workspace);
+        let repo = open_or_init(workspace, &module).await?;
+        let guard = RepoGuard::new(&repo).await?;
+        if repo.read_record(&module.record).await.is_err() {
+            sync_journaled(&repo, Some(&module.url), None, options, false).await?;
+        }
+        let record = repo.read_record(&module.record).await.map_err(|_| {
+            WsvcError::RepoError(format!(
+                "record {} of module {} is not found in {}",
+                &module.record.0.to_hex()[0..6],
+                path,
+                module.url
+            ))
+        })?;
+        let status = repo.status(&dir).await?;
+        if !status.is_clean() && !force {
+            return Err(WsvcError::BadUsage(format!(
+                "module {} has changes not recorded, record them or use `--force` to drop them",
+                path
+            )));
+        }
+        if status.head.as_ref() != Some(&record.hash) || !status.is_clean() {
+            repo.checkout_record(&record.hash, &dir).await?;
+        }
+        drop(guard);
+        let hash = record.hash.0.to_hex().to_string();
+        println!(
+            "Module {} at record: {} ({})",
+            path.bright_yellow(),
+            hash[0..6].green().bold(),
+            hash
+        );
+        update_modules(&dir, &path, force, options).await?;
+    }
+    Ok(())
+}
+
+/// print the state of all modules and their modules.
+#[async_recursion::async_recursion(?Send)]
+async fn print_modules(workspace: &Path, prefix: &str) -> Result<(), WsvcError> {
+    for module in ModulesManifest::load(workspace).await?.modules {
+        let path = display_path(prefix, &module);
+        let pinned = module.record.0.to_hex().to_string();
+        let status = module_status(workspace, &module).await?;
+        let state = if !status.cloned {
+            "not cloned".bright_red()
+        } else if !status.fetched {
+            "pinned record not fetched".bright_red()
+        } else if status.head.as_ref() != Some(&module.record) {
+            "not at the pinned record".bright_yellow()
+        } else if status.is_synced() {
+            "up to date".bright_green()
+        } else {
+            "changes not recorded".bright_yellow()
+        };
+        println!(
+            "Module {} pinned at {} from {}: {}",
+            path.bold(),
+            pinned[0..6].green().bold(),
+            module.url,
+            state
+        );
+        if let Some(head) = &status.head {
+            if head != &module.record {
+                println!("  HEAD at {}", head.0.to_hex()[0..6].yellow());
+            }
+        }
+        for change in &status.changes {
+            print_change(change);
+        }
+        if status.cloned {
+            print_modules(&module.dir(workspace), &path).await?;
+        }
+    }
+    Ok(())
+}
+
+/// fetch all modules of the workspace.
+pub async fn modules_sync(
+    workspace: Option<String>,
+    options: ConnectOptions,
+) -> Result<(), WsvcError> {
+    sync_modules(&resolve_workspace(workspace)?, "", &options).await
+}
+
+/// checkout all modules of the workspace at their pinned records.
+pub async fn modules_update(
+    workspace: Option<String>,
+    force: bool,
+    options: ConnectOptions,
+) -> Result<(), WsvcError> {
+    update_modules(&resolve_workspace(workspace)?, "", force, &options).await
+}
+
+/// show whether modules of the workspace match their pinned records.
+pub async fn modules_status(workspace: Option<String>, json: bool) -> Result<(), WsvcError> {
+    let workspace = resolve_workspace(workspace)?;
+    if json {
+        let mut result = vec![];
+        for module in ModulesManifest::load(&workspace).await?.modules {
+            result.push(module_status(&workspace, &module).await?);
+        }
+        println!("{}", serde_json::to_string_pretty(&result)?);
+        return Ok(());
+    }
+    let manifest = ModulesManifest::load(&workspace).await?;
+    if manifest.modules.is_empty() {
+        println!("No modules, pin them in .wsvcmodules");
+        return Ok(());
+    }
+    print_modules(&workspace, "").await
+}
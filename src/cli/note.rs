@@ -0,0 +1,35 @@
+use colored::Colorize;
+use wsvc::{
+    fs::{RepoGuard, WsvcFsError},
+    model::Repository,
+    WsvcError,
+};
+
+use super::resolve_record;
+
+pub async fn note(
+    root: Option<String>,
+    hash: Option<String>,
+    message: String,
+    author: String,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    let repo = Repository::try_open(root).await?;
+    let guard = RepoGuard::new(&repo).await?;
+    let record = resolve_record(&repo.to_read_only(), hash).await?;
+    let note = repo.add_note(&record.hash, &author, &message).await?;
+    let hash = record.hash.0.to_hex().to_string();
+    println!(
+        "Noted record: {} ({}) by {}",
+        hash[0..6].green().bold(),
+        hash,
+        note.author.bright_blue()
+    );
+    drop(guard);
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use std::path::PathBuf;
+
+use colored::Colorize;
+use wsvc::{
+    fs::{RepoGuard, RepositoryBuilder, WsvcFsError},
+    model::Repository,
+    overlay::Overlay,
+    WsvcError,
+};
+
+use super::{config::WsvcConfig, resolve_record};
+
+fn print_overlay(action: &str, overlay: &Overlay) {
+    let hash = overlay.record.0.to_hex().to_string();
+    println!(
+        "{} {} at record: {} ({}) from {}",
+        action,
+        overlay.path.bright_yellow(),
+        hash[0..6].green().bold(),
+        hash,
+        overlay.source.as_deref().unwrap_or("this repository")
+    );
+}
+
+/// materialize a record of the repository at `from`, or this repository, into the dir
+/// `path` of the workspace. the overlay must not exist yet unless `update` is set, an
+/// updated overlay keeps its source.
+async fn set_overlay(
+    path: String,
+    hash: Option<String>,
+    from: Option<String>,
+    update: bool,
+    workspace: Option<String>,
+    root: Option<String>,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let workspace = PathBuf::from(workspace.unwrap_or(pwd.clone()));
+    let root = root.unwrap_or(pwd);
+    let mut repo = Repository::try_open(root).await?;
+    let config = WsvcConfig::load(Some(&repo)).await?;
+    config.configure(&mut repo);
+    let guard = RepoGuard::new(&repo).await?;
+    let from = match (repo.read_overlay(&path).await?, update) {
+        (Some(_), false) => {
+            return Err(WsvcError::BadUsage(format!(
+                "overlay {} already exists, use `wsvc overlay update`",
+                path
+            )))
+        }
+        (None, true) => return Err(WsvcError::BadUsage(format!("no overlay at {}", path))),
+        (Some(overlay), true) => overlay.source,
+        (None, false) => match from {
+            Some(from) => Some(
+                PathBuf::from(from)
+                    .canonicalize()
+                    .map_err(WsvcFsError::Os)?
+                    .to_string_lossy()
+                    .to_string(),
+            ),
+            None => None,
+        },
+    };
+    let overlay = match from {
+        Some(from) => {
+            let source = RepositoryBuilder::new(&from).read_only(true).open().await?;
+            let record = resolve_record(&source.to_read_only(), hash).await?;
+            repo.set_overlay(&path, Some((&source, from)), &record.hash, &workspace)
+                .await?
+        }
+        None => {
+            let record = resolve_record(&repo.to_read_only(), hash).await?;
+            repo.set_overlay(&path, None, &record.hash, &workspace)
+                .await?
+        }
+    };
+    print_overlay(
+        if update {
+            "Updated overlay"
+        } else {
+            "Added overlay"
+        },
+        &overlay,
+    );
+    drop(guard);
+    Ok(())
+}
+
+pub async fn overlay_add(
+    path: String,
+    hash: Option<String>,
+    from: Option<String>,
+    workspace: Option<String>,
+    root: Option<String>,
+) -> Result<(), WsvcError> {
+    set_overlay(path, hash, from, false, workspace, root).await
+}
+
+pub async fn overlay_update(
+    path: String,
+    hash: Option<String>,
+    workspace: Option<String>,
+    root: Option<String>,
+) -> Result<(), WsvcError> {
+    set_overlay(path, hash, None, true, workspace, root).await
+}
+
+pub async fn overlay_detach(
+    path: String,
+    remove: bool,
+    workspace: Option<String>,
+    root: Option<String>,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let workspace = PathBuf::from(workspace.unwrap_or(pwd.clone()));
+    let root = root.unwrap_or(pwd);
+    let repo = Repository::try_open(root).await?;
+    let guard = RepoGuard::new(&repo).await?;
+    let overlay = repo.detach_overlay(&path, remove, &workspace).await?;
+    print_overlay(
+        if remove {
+            "Removed overlay"
+        } else {
+            "Detached overlay"
+        },
+        &overlay,
+    );
+    drop(guard);
+    Ok(())
+}
+
+pub async fn overlay_list(root: Option<String>) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let repo = Repository::open_read_only(root.unwrap_or(pwd)).await?;
+    for overlay in repo.get_overlays().await? {
+        let hash = overlay.record.0.to_hex().to_string();
+        println!(
+            "{} -> {} from {}",
+            overlay.path.bold(),
+            hash[0..6].green(),
+            overlay.source.as_deref().unwrap_or("this repository")
+        );
+    }
+    Ok(())
+}
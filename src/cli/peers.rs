@@ -0,0 +1,307 @@
+use std::{collections::HashMap, net::IpAddr, time::Duration};
+
+use colored::Colorize;
+use mdns_sd::{ServiceDaemon, ServiceEvent};
+#[cfg(feature = "server")]
+use tokio_tungstenite::tungstenite::handshake::server::{
+    Callback, ErrorResponse, Request, Response,
+};
+use wsvc::WsvcError;
+
+/// mDNS service type advertised by `wsvc peers serve`.
+pub const SERVICE_TYPE: &str = "_wsvc._tcp.local.";
+
+/// url scheme of peers found by mDNS, e.g. `peer://alice-laptop`.
+pub const PEER_SCHEME: &str = "peer://";
+
+/// `Peer` stand for a wsvc daemon found on the local network.
+pub struct Peer {
+    pub name: String,
+    pub addrs: Vec<IpAddr>,
+    pub port: u16,
+    /// certificate fingerprint if the peer serves over quic.
+    pub quic_fingerprint: Option<String>,
+}
+
+impl Peer {
+    /// sync url of the peer, ipv4 addresses are preferred.
+    pub fn url(&self) -> Option<String> {
+        let addr = self
+            .addrs
+            .iter()
+            .find(|a| a.is_ipv4())
+            .or(self.addrs.first())?;
+        let host = match addr {
+            IpAddr::V4(addr) => format!("{}:{}", addr, self.port),
+            IpAddr::V6(addr) => format!("[{}]:{}", addr, self.port),
+        };
+        Some(match &self.quic_fingerprint {
+            Some(fingerprint) => format!("quic://{}?fingerprint={}", host, fingerprint),
+            None => format!("ws://{}", host),
+        })
+    }
+}
+
+fn mdns_error(err: mdns_sd::Error) -> WsvcError {
+    WsvcError::DataError(format!("mdns error: {}", err))
+}
+
+/// link local ipv6 addresses need a scope id, they can not be used in urls.
+fn usable(addr: &IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(_) => true,
+        IpAddr::V6(addr) => addr.segments()[0] & 0xffc0 != 0xfe80,
+    }
+}
+
+/// browse peers for `timeout`, stops early once a peer named `until` is resolved with an
+/// ipv4 address.
+async fn browse(timeout: Duration, until: Option<&str>) -> Result<Vec<Peer>, WsvcError> {
+    let daemon = ServiceDaemon::new().map_err(mdns_error)?;
+    let receiver = daemon.browse(SERVICE_TYPE).map_err(mdns_error)?;
+    let mut peers: HashMap<String, Peer> = HashMap::new();
+    let deadline = tokio::time::Instant::now() + timeout;
+    while let Ok(Ok(event)) = tokio::time::timeout_at(deadline, receiver.recv_async()).await {
+        if let ServiceEvent::ServiceResolved(info) = event {
+            let name = info
+                .get_fullname()
+                .trim_end_matches(SERVICE_TYPE)
+                .trim_end_matches('.')
+                .to_string();
+            // a peer may be resolved several times, each time with some of its addresses.
+            let peer = peers.entry(name.clone()).or_insert(Peer {
+                name: name.clone(),
+                addrs: vec![],
+                port: info.get_port(),
+                quic_fingerprint: None,
+            });
+            for addr in info.get_addresses() {
+                if usable(addr) && !peer.addrs.contains(addr) {
+                    peer.addrs.push(*addr);
+                }
+            }
+            peer.port = info.get_port();
+            if let Some(fingerprint) = info.get_property_val_str("quic-fingerprint") {
+                peer.quic_fingerprint = Some(fingerprint.to_owned());
+            }
+            if until == Some(name.as_str()) && peer.addrs.iter().any(|a| a.is_ipv4()) {
+                break;
+            }
+        }
+    }
+    daemon.shutdown().ok();
+    let mut peers = peers
+        .into_values()
+        .filter(|p| !p.addrs.is_empty())
+        .collect::<Vec<_>>();
+    peers.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(peers)
+}
+
+/// resolve a `peer://<name>` url to the sync url of the peer.
+pub async fn resolve(url: &str) -> Result<String, WsvcError> {
+    let name = url.trim_start_matches(PEER_SCHEME).trim_end_matches('/');
+    browse(Duration::from_secs(5), Some(name))
+        .await?
+        .into_iter()
+        .find(|p| p.name == name)
+        .and_then(|p| p.url())
+        .ok_or(WsvcError::DataError(format!(
+            "peer {} not found on local network",
+            name
+        )))
+}
+
+pub async fn discover(timeout: Option<u64>) -> Result<(), WsvcError> {
+    let timeout = Duration::from_secs(timeout.unwrap_or(3));
+    println!(
+        "{} {}",
+        "[+]".bright_green(),
+        "Discovering peers on local network...".bold()
+    );
+    let peers = browse(timeout, None).await?;
+    if peers.is_empty() {
+        println!("No peer found");
+    }
+    for peer in peers {
+        println!(
+            "{}{} {}",
+            PEER_SCHEME.dimmed(),
+            peer.name.bright_blue().bold(),
+            peer.url().unwrap_or_default().dimmed()
+        );
+    }
+    Ok(())
+}
+
+/// advertise the peer `name` serving on `port`.
+#[cfg(feature = "server")]
+fn advertise(
+    name: &str,
+    port: u16,
+    properties: HashMap<String, String>,
+) -> Result<ServiceDaemon, WsvcError> {
+    let daemon = ServiceDaemon::new().map_err(mdns_error)?;
+    let info = mdns_sd::ServiceInfo::new(
+        SERVICE_TYPE,
+        name,
+        &format!("{}.local.", name),
+        "",
+        port,
+        Some(properties),
+    )
+    .map_err(mdns_error)?
+    .enable_addr_auto();
+    daemon.register(info).map_err(mdns_error)?;
+    println!(
+        "{} Serving as {}{} on port {}",
+        "[+]".bright_green(),
+        PEER_SCHEME.dimmed(),
+        name.bright_blue().bold(),
+        port
+    );
+    Ok(daemon)
+}
+
+#[cfg(feature = "server")]
+fn report(addr: std::net::SocketAddr, result: Result<(), wsvc::server::WsvcServerError>) {
+    match result {
+        Ok(_) => println!("{} Peer {} synced.", "[*]".bright_blue(), addr),
+        Err(err) => println!("{} {}: {}", "[!]".bright_red(), addr, err),
+    }
+}
+
+/// `WithVersion` reports the version of wsvc in the handshake response, so
+/// `wsvc remote ping` can show it.
+#[cfg(feature = "server")]
+struct WithVersion;
+
+#[cfg(feature = "server")]
+impl Callback for WithVersion {
+    fn on_request(self, _: &Request, mut response: Response) -> Result<Response, ErrorResponse> {
+        let version = wsvc::frame::VERSION.parse().expect("valid header value");
+        response
+            .headers_mut()
+            .insert(wsvc::frame::VERSION_HEADER, version);
+        Ok(response)
+    }
+}
+
+/// serve the repository to peers and advertise it by mDNS, until interrupted.
+#[cfg(feature = "server")]
+pub async fn serve(
+    name: Option<String>,
+    port: Option<u16>,
+    quic: bool,
+    passphrase: Option<String>,
+) -> Result<(), WsvcError> {
+    use wsvc::{fs::WsvcFsError, model::Repository};
+
+    let pwd = std::env::current_dir().map_err(WsvcFsError::Os)?;
+    let repo = Repository::try_open(&pwd).await?;
+    let name = name.unwrap_or(
+        pwd.file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or("wsvc".to_owned()),
+    );
+    if quic {
+        return serve_quic(&repo, &name, port, passphrase).await;
+    }
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port.unwrap_or(0)))
+        .await
+        .map_err(WsvcFsError::Os)?;
+    let port = listener.local_addr().map_err(WsvcFsError::Os)?.port();
+    let _daemon = advertise(&name, port, HashMap::new())?;
+    loop {
+        let (stream, addr) = listener.accept().await.map_err(WsvcFsError::Os)?;
+        let mut ws = match tokio_tungstenite::accept_hdr_async(stream, WithVersion).await {
+            Ok(ws) => ws,
+            Err(err) => {
+                println!("{} {}: {}", "[!]".bright_red(), addr, err);
+                continue;
+            }
+        };
+        println!(
+            "{} Peer {} connected, syncing...",
+            "[+]".bright_green(),
+            addr
+        );
+        let result = match &passphrase {
+            Some(passphrase) => {
+                match wsvc::frame::EncryptedStream::handshake(ws, passphrase, false).await {
+                    Ok(mut ws) => wsvc::server::sync_with(&repo, &mut ws).await,
+                    Err(err) => Err(err.into()),
+                }
+            }
+            None => wsvc::server::sync_with(&repo, &mut ws).await,
+        };
+        report(addr, result);
+    }
+}
+
+/// serve the repository over quic, the certificate fingerprint is advertised so peers
+/// can pin it.
+#[cfg(all(feature = "server", feature = "quic"))]
+async fn serve_quic(
+    repo: &wsvc::model::Repository,
+    name: &str,
+    port: Option<u16>,
+    passphrase: Option<String>,
+) -> Result<(), WsvcError> {
+    use wsvc::quic::{QuicIdentity, QuicServer};
+
+    let identity = QuicIdentity::load_or_generate(&repo.path).await?;
+    let server = QuicServer::bind(([0, 0, 0, 0], port.unwrap_or(0)).into(), &identity)?;
+    let port = server.local_addr()?.port();
+    let _daemon = advertise(
+        name,
+        port,
+        HashMap::from([("quic-fingerprint".to_owned(), identity.fingerprint())]),
+    )?;
+    println!(
+        "Certificate fingerprint: {}",
+        identity.fingerprint().dimmed()
+    );
+    while let Some(stream) = server.accept().await {
+        let addr = stream.remote_address();
+        println!(
+            "{} Peer {} connected, syncing...",
+            "[+]".bright_green(),
+            addr
+        );
+        let (result, stream) = match &passphrase {
+            Some(passphrase) => {
+                match wsvc::frame::EncryptedStream::handshake(stream, passphrase, false).await {
+                    Ok(mut stream) => {
+                        let result = wsvc::server::sync_with(repo, &mut stream).await;
+                        (result, Some(stream.into_inner()))
+                    }
+                    // the stream is dropped with the failed handshake.
+                    Err(err) => (Err(err.into()), None),
+                }
+            }
+            None => {
+                let mut stream = stream;
+                let result = wsvc::server::sync_with(repo, &mut stream).await;
+                (result, Some(stream))
+            }
+        };
+        if let Some(stream) = stream {
+            stream.close().await;
+        }
+        report(addr, result);
+    }
+    Ok(())
+}
+
+#[cfg(all(feature = "server", not(feature = "quic")))]
+async fn serve_quic(
+    _repo: &wsvc::model::Repository,
+    _name: &str,
+    _port: Option<u16>,
+    _passphrase: Option<String>,
+) -> Result<(), WsvcError> {
+    Err(WsvcError::BadUsage(
+        "quic transport is not enabled in this build".to_owned(),
+    ))
+}
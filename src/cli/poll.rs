@@ -0,0 +1,152 @@
+use std::collections::VecDeque;
+
+use async_trait::async_trait;
+use reqwest::{Client, StatusCode, Url};
+use wsvc::{
+    frame::{decode_frames, encode_frames, FrameStream},
+    WsvcError,
+};
+
+use super::config::WsvcConfig;
+
+/// pushed frames are batched until this size or the client waits for frames.
+const PUSH_BATCH_SIZE: usize = 1024 * 1024;
+
+pub(super) fn http_error(err: reqwest::Error) -> WsvcError {
+    WsvcError::DataError(format!("http error: {}", err))
+}
+
+/// the url of an endpoint beside the sync of a websocket sync url, `ws://host/repo` and
+/// `stats` becomes `http://host/repo/stats`.
+pub fn http_url(url: &str, path: &str) -> Option<String> {
+    let rest = if let Some(rest) = url.strip_prefix("wss://") {
+        format!("https://{}", rest)
+    } else {
+        format!("http://{}", url.strip_prefix("ws://")?)
+    };
+    Some(format!("{}/{}", rest.trim_end_matches('/'), path))
+}
+
+/// the long-poll url of a websocket sync url, `ws://host/repo` becomes
+/// `http://host/repo/poll`.
+pub fn poll_url(url: &str) -> Option<String> {
+    http_url(url, "poll")
+}
+
+/// a http client for `url`, `net.resolve` and `tls` settings of `config` apply like to
+/// websocket.
+pub(super) fn client(url: &str, config: &WsvcConfig) -> Result<Client, WsvcError> {
+    let url = Url::parse(url).map_err(|err| WsvcError::BadUsage(err.to_string()))?;
+    let host = url
+        .host_str()
+        .ok_or(WsvcError::BadUsage(format!("no host in url: {}", url)))?
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let mut builder = Client::builder();
+    if let Some(addr) = config.resolve(host)? {
+        let port = url.port_or_known_default().unwrap_or(80);
+        builder = builder.resolve(host, (addr, port).into());
+    }
+    if let Some(tls) = config.tls.client_config(host)? {
+        builder = builder.use_preconfigured_tls((*tls).clone());
+    }
+    builder.build().map_err(http_error)
+}
+
+/// `PollStream` runs the sync over http long-polling, for networks where websocket is
+/// blocked.
+pub struct PollStream {
+    client: Client,
+    session_url: String,
+    pending: Vec<Vec<u8>>,
+    pending_size: usize,
+    received: VecDeque<Vec<u8>>,
+    closed: bool,
+}
+
+impl PollStream {
+    /// open a long-poll session at `url`, `net.resolve` and `tls` settings of `config`
+    /// apply like to websocket.
+    pub async fn open(url: &str, config: &WsvcConfig) -> Result<Self, WsvcError> {
+        let client = client(url, config)?;
+        let response = client
+            .post(url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(http_error)?;
+        let id = response.text().await.map_err(http_error)?;
+        Ok(Self {
+            client,
+            session_url: format!("{}/{}", url, id.trim()),
+            pending: vec![],
+            pending_size: 0,
+            received: VecDeque::new(),
+            closed: false,
+        })
+    }
+
+    async fn flush(&mut self) -> Result<(), WsvcError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let batch = encode_frames(&self.pending);
+        self.pending.clear();
+        self.pending_size = 0;
+        self.client
+            .post(&self.session_url)
+            .body(batch)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(http_error)?;
+        Ok(())
+    }
+
+    /// close the session on the server.
+    pub async fn close(mut self) -> Result<(), WsvcError> {
+        self.flush().await?;
+        self.client
+            .delete(&self.session_url)
+            .send()
+            .await
+            .map_err(http_error)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl FrameStream for PollStream {
+    async fn send_frame(&mut self, data: Vec<u8>) -> Result<(), WsvcError> {
+        self.pending_size += data.len();
+        self.pending.push(data);
+        if self.pending_size >= PUSH_BATCH_SIZE {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn recv_frame(&mut self) -> Result<Option<Vec<u8>>, WsvcError> {
+        self.flush().await?;
+        while self.received.is_empty() && !self.closed {
+            let response = self
+                .client
+                .get(&self.session_url)
+                .send()
+                .await
+                .map_err(http_error)?;
+            if response.status() == StatusCode::GONE {
+                self.closed = true;
+                break;
+            }
+            let batch = response
+                .error_for_status()
+                .map_err(http_error)?
+                .bytes()
+                .await
+                .map_err(http_error)?;
+            self.received.extend(decode_frames(&batch)?);
+        }
+        Ok(self.received.pop_front())
+    }
+}
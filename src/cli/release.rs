@@ -0,0 +1,217 @@
+use std::path::PathBuf;
+
+use colored::Colorize;
+use reqwest::StatusCode;
+use wsvc::{
+    changelog::Changelog,
+    fs::{RepoGuard, WsvcFsError},
+    model::Repository,
+    release::{Release, ReleaseArchive, ARCHIVE_FILE},
+    tag::check_tag_name,
+    walk::WalkOrder,
+    WsvcError,
+};
+
+use super::{
+    config::WsvcConfig,
+    poll::{client, http_error, http_url},
+    resolve_record,
+    transport::{sync_journaled, ConnectOptions},
+};
+
+/// options of `wsvc release` from command line.
+#[derive(clap::Args)]
+pub struct ReleaseOptions {
+    /// the record hash prefix to release, HEAD will be used if not specified
+    #[clap(long)]
+    pub hash: Option<String>,
+    /// message of the release tag, `release <version>` if not specified
+    #[clap(short, long)]
+    pub message: Option<String>,
+    /// release author, `commit.author` in config if not specified
+    #[clap(short, long)]
+    pub author: Option<String>,
+    /// the tag or record hash prefix of the previous release, the nearest tagged record if not specified
+    #[clap(long)]
+    pub from: Option<String>,
+    /// name of the archive, `<name>-<version>.tar.gz`, the workspace dir name if not specified
+    #[clap(long)]
+    pub name: Option<String>,
+    /// replace the tag if it points to another record
+    #[clap(short, long, action = clap::ArgAction::SetTrue)]
+    pub force: bool,
+    /// sync with the remote origin, then push the tag and upload the archive to it
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    pub push: bool,
+    /// optional workspace dir, if not configured, current dir will be used
+    #[clap(short, long)]
+    pub workspace: Option<String>,
+    /// optional root dir where stores the repo data, if not configured, current dir or .wsvc will be used
+    #[clap(short, long)]
+    pub root: Option<String>,
+}
+
+/// tag a record as `version`, write its changelog and archive to `releases/<version>` of
+/// the repository, and push them to the remote origin if asked.
+pub async fn release(
+    version: String,
+    release: ReleaseOptions,
+    options: ConnectOptions,
+) -> Result<(), WsvcError> {
+    check_tag_name(&version)?;
+    let (force, push) = (release.force, release.push);
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let workspace = PathBuf::from(release.workspace.unwrap_or(pwd.clone()));
+    let repo = Repository::try_open(release.root.unwrap_or(pwd)).await?;
+    let config = WsvcConfig::load(Some(&repo)).await?;
+    let author = release
+        .author
+        .or(config.commit.author.clone())
+        .ok_or(WsvcError::LackOfConfig(
+            "commit.author".to_owned(),
+            "use `--author` or `wsvc config set commit.author <name>`".to_owned(),
+        ))?;
+    let guard = RepoGuard::new(&repo).await?;
+    let read_only = repo.to_read_only();
+    let record = resolve_record(&read_only, release.hash).await?;
+    let graph = repo.record_graph().await?;
+    let from = match release.from {
+        Some(from) => Some(resolve_record(&read_only, Some(from)).await?),
+        None => {
+            // the nearest record before the released one with a tag of another version.
+            let tags = repo.get_tags().await?;
+            let mut previous = None;
+            for candidate in graph
+                .walk(Some(&record.hash), WalkOrder::Topological)
+                .into_iter()
+                .skip(1)
+            {
+                if tags
+                    .iter()
+                    .any(|t| t.record == candidate.hash && t.name != version)
+                {
+                    previous = Some(candidate);
+                    break;
+                }
+            }
+            previous
+        }
+    };
+    let date = chrono::Utc::now();
+    let records = graph.range(
+        from.as_ref().map(|r| &r.hash),
+        &record.hash,
+        WalkOrder::Date,
+    );
+    let notes = Changelog::new(&records).render_markdown(&format!(
+        "{} ({})",
+        version,
+        date.format("%Y-%m-%d")
+    ));
+    let name = match release.name {
+        Some(name) => name,
+        None => workspace
+            .canonicalize()
+            .map_err(WsvcFsError::Os)?
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("archive")
+            .to_owned(),
+    };
+    let prefix = format!("{}-{}", name, version);
+    let archive = repo.archive_record(&record.hash, &prefix).await?;
+    let release = Release {
+        version: version.clone(),
+        record: record.hash.clone(),
+        author,
+        date,
+        message: release.message.unwrap_or(format!("release {}", version)),
+        notes,
+        archive: Some(ReleaseArchive::new(format!("{}.tar.gz", prefix), &archive)),
+    };
+    repo.publish_release(&release, Some(&archive), force)
+        .await?;
+    let hash = record.hash.0.to_hex().to_string();
+    println!(
+        "Released {}: record {} ({}) since {}",
+        version.bright_yellow().bold(),
+        hash[0..6].green().bold(),
+        hash,
+        from.map(|r| r.hash.0.to_hex()[0..6].to_string())
+            .unwrap_or("the first record".to_owned())
+    );
+    println!(
+        "Archive: {}",
+        repo.releases_dir()
+            .await?
+            .join(&version)
+            .join(ARCHIVE_FILE)
+            .display()
+    );
+    println!("\n{}", release.notes);
+    if push {
+        push_release(&repo, &release, &archive, &config, &options).await?;
+    }
+    drop(guard);
+    Ok(())
+}
+
+/// sync the record of the release to the remote origin, then push the release and its
+/// archive to the release endpoints beside the sync.
+async fn push_release(
+    repo: &Repository,
+    release: &Release,
+    archive: &[u8],
+    config: &WsvcConfig,
+    options: &ConnectOptions,
+) -> Result<(), WsvcError> {
+    let origin = repo.read_origin().await?;
+    let url = http_url(&origin, &format!("releases/{}", release.version)).ok_or(
+        WsvcError::BadUsage(format!(
+            "releases are pushed over http, origin {} is not a ws:// or wss:// url",
+            origin
+        )),
+    )?;
+    sync_journaled(repo, None, None, options, false).await?;
+    let client = client(&url, config)?;
+    let response = client
+        .post(&url)
+        .body(serde_json::to_vec(release)?)
+        .send()
+        .await
+        .map_err(http_error)?;
+    match response.status() {
+        StatusCode::CONFLICT => {
+            return Err(WsvcError::RepoError(format!(
+                "the remote refused release {}, its tag points to another record",
+                release.version
+            )))
+        }
+        StatusCode::NOT_FOUND => {
+            return Err(WsvcError::RepoError(
+                "the remote does not host releases".to_owned(),
+            ))
+        }
+        _ => {
+            response.error_for_status().map_err(http_error)?;
+        }
+    }
+    client
+        .post(format!("{}/archive", url))
+        .body(archive.to_vec())
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(http_error)?;
+    println!(
+        "{} Pushed release {} to {}",
+        "[+]".bright_green(),
+        release.version.bright_yellow(),
+        origin
+    );
+    Ok(())
+}
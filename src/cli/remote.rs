@@ -1,4 +1,22 @@
-use wsvc::{fs::WsvcFsError, model::Repository, WsvcError};
+use std::time::{Duration, Instant};
+
+use colored::Colorize;
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest};
+use wsvc::{
+    frame::{FrameStream, CAPABILITIES_HEADER, VERSION_HEADER},
+    fs::WsvcFsError,
+    identity::SyncIdentity,
+    model::Repository,
+    WsvcError,
+};
+
+use super::{
+    config::WsvcConfig,
+    transport::{self, ConnectOptions},
+};
+
+/// how long ping waits for the first packet of the sync.
+const FIRST_PACKET_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub async fn remote_set(root: Option<String>, url: String) -> Result<(), WsvcError> {
     let pwd = std::env::current_dir()
@@ -11,3 +29,210 @@ pub async fn remote_set(root: Option<String>, url: String) -> Result<(), WsvcErr
     repo.write_origin(url).await?;
     Ok(())
 }
+
+fn millis(duration: Duration) -> String {
+    format!("{:.1}ms", duration.as_secs_f64() * 1000.0)
+}
+
+fn row(key: &str, value: impl AsRef<str>) {
+    println!("  {:<10} {}", key.dimmed(), value.as_ref());
+}
+
+/// check the first frame is the packet header of the identity round, returns whether it is.
+fn check_first_packet(
+    frame: Result<Result<Option<Vec<u8>>, WsvcError>, tokio::time::error::Elapsed>,
+    elapsed: Duration,
+) -> bool {
+    match frame {
+        Ok(Ok(Some(frame))) if frame.len() >= 6 && frame[0] == 0x33 && frame[1] == 0x07 => {
+            row(
+                "protocol",
+                format!("ok, first packet in {}", millis(elapsed)),
+            );
+            return true;
+        }
+        Ok(Ok(Some(_))) => row(
+            "protocol",
+            "unexpected first packet, encrypted or not a wsvc server"
+                .bright_yellow()
+                .to_string(),
+        ),
+        Ok(Ok(None)) => row(
+            "protocol",
+            "closed by server, the repository may be busy or missing"
+                .bright_yellow()
+                .to_string(),
+        ),
+        Ok(Err(err)) => row("protocol", err.to_string().bright_red().to_string()),
+        Err(_) => row(
+            "protocol",
+            "no packet in 10s, e.g. a relay room without host"
+                .bright_yellow()
+                .to_string(),
+        ),
+    }
+    false
+}
+
+/// report whether the server requires auth, from the identity it sends first.
+async fn check_auth(stream: &mut impl FrameStream) {
+    let identity = tokio::time::timeout(FIRST_PACKET_TIMEOUT, stream.recv_frame())
+        .await
+        .ok()
+        .and_then(Result::ok)
+        .flatten()
+        .and_then(|frame| serde_json::from_slice::<SyncIdentity>(&frame).ok());
+    match identity {
+        Some(identity) if identity.public => row(
+            "auth",
+            "required to push, public to read"
+                .bright_yellow()
+                .to_string(),
+        ),
+        Some(identity) if identity.auth => row("auth", "required".bright_yellow().to_string()),
+        Some(_) => row("auth", "not required"),
+        None => row("auth", "unknown"),
+    }
+}
+
+/// connect to the remote, run the handshake only and report what is found.
+pub async fn remote_ping(
+    root: Option<String>,
+    url: Option<String>,
+    options: ConnectOptions,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir().map_err(WsvcFsError::Os)?;
+    let repo = Repository::try_open(root.map(Into::into).unwrap_or(pwd))
+        .await
+        .ok();
+    let config = WsvcConfig::load(repo.as_ref()).await?;
+    let mut url = match (url, &repo) {
+        (Some(url), _) => url,
+        (None, Some(repo)) => repo.read_origin().await?,
+        (None, None) => {
+            return Err(WsvcError::BadUsage(
+                "not in a repository, give the url to ping".to_owned(),
+            ))
+        }
+    };
+    options.ensure_online(&config, &url)?;
+    println!("{} {} {}", "[+]".bright_green(), "Pinging".bold(), url);
+    if url.starts_with(super::peers::PEER_SCHEME) {
+        url = super::peers::resolve(&url).await?;
+        row("peer", &url);
+    }
+    if url.starts_with("quic://") {
+        return ping_quic(&url, &options).await;
+    }
+    let request = url.as_str().into_client_request()?;
+    let start = Instant::now();
+    let stream = match transport::dial_ws(&request, options.family(), &config).await {
+        Ok(stream) => stream,
+        Err(err) => {
+            row("reachable", "no".bright_red().to_string());
+            return Err(err);
+        }
+    };
+    let addr = stream
+        .peer_addr()
+        .map(|addr| addr.to_string())
+        .unwrap_or_default();
+    row(
+        "reachable",
+        format!("yes, {} in {}", addr, millis(start.elapsed())),
+    );
+    let start = Instant::now();
+    let (mut ws, response) = match transport::handshake_ws(request, stream, &config).await {
+        Ok(result) => result,
+        Err(err) => {
+            let status = match &err {
+                WsvcError::NetworkError(err) => match err.as_ref() {
+                    tungstenite::Error::Http(response) => Some(response.status()),
+                    _ => None,
+                },
+                _ => None,
+            };
+            let Some(status) = status else {
+                row("handshake", err.to_string().bright_red().to_string());
+                return Err(err);
+            };
+            row("handshake", format!("rejected with http {}", status));
+            match status.as_u16() {
+                401 => row("auth", "required".bright_yellow().to_string()),
+                403 => row(
+                    "auth",
+                    "denied, or websocket blocked by a proxy"
+                        .bright_yellow()
+                        .to_string(),
+                ),
+                _ => {}
+            }
+            return Err(WsvcError::DataError(format!(
+                "websocket handshake rejected: {}",
+                status
+            )));
+        }
+    };
+    row("handshake", format!("ok in {}", millis(start.elapsed())));
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_owned())
+    };
+    row(
+        "server",
+        match header(VERSION_HEADER) {
+            Some(version) => format!("wsvc {}", version),
+            None => "unknown, version not reported".to_owned(),
+        },
+    );
+    row(
+        "endpoints",
+        header(CAPABILITIES_HEADER)
+            .filter(|c| !c.is_empty())
+            .unwrap_or("sync only".to_owned()),
+    );
+    let start = Instant::now();
+    let frame = tokio::time::timeout(FIRST_PACKET_TIMEOUT, ws.recv_frame()).await;
+    if check_first_packet(frame, start.elapsed()) {
+        check_auth(&mut ws).await;
+    }
+    ws.close(None).await.ok();
+    Ok(())
+}
+
+#[cfg(feature = "quic")]
+async fn ping_quic(url: &str, options: &ConnectOptions) -> Result<(), WsvcError> {
+    let start = Instant::now();
+    let mut stream = match wsvc::quic::QuicStream::connect(url, options.family()).await {
+        Ok(stream) => stream,
+        Err(err) => {
+            row("reachable", "no".bright_red().to_string());
+            return Err(err);
+        }
+    };
+    row(
+        "reachable",
+        format!(
+            "yes, {} in {}, certificate pinned",
+            stream.remote_address(),
+            millis(start.elapsed())
+        ),
+    );
+    let start = Instant::now();
+    let frame = tokio::time::timeout(FIRST_PACKET_TIMEOUT, stream.recv_frame()).await;
+    if check_first_packet(frame, start.elapsed()) {
+        check_auth(&mut stream).await;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "quic"))]
+async fn ping_quic(url: &str, _options: &ConnectOptions) -> Result<(), WsvcError> {
+    Err(WsvcError::BadUsage(format!(
+        "quic transport is not enabled in this build: {}",
+        url
+    )))
+}
@@ -0,0 +1,84 @@
+use colored::Colorize;
+use wsvc::{
+    fs::{RepoGuard, WsvcFsError},
+    model::Repository,
+    size::format_size,
+    WsvcError,
+};
+
+use super::config::WsvcConfig;
+
+pub async fn repack(root: Option<String>) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    let mut repo = Repository::try_open(root).await?;
+    let config = WsvcConfig::load(Some(&repo)).await?;
+    config.configure(&mut repo);
+    let guard = RepoGuard::new(&repo).await?;
+    let stats = repo.repack().await?;
+    drop(guard);
+    if stats.objects == 0 {
+        println!("No objects to pack");
+        return Ok(());
+    }
+    println!(
+        "{} Packed {} objects ({} loose, {} packs merged) into {} bytes",
+        "[*]".bright_blue(),
+        stats.objects.to_string().bold(),
+        stats.loose.to_string().bold(),
+        stats.packs.to_string().bold(),
+        stats.size.to_string().bold()
+    );
+    Ok(())
+}
+
+pub async fn stats(root: Option<String>, json: bool) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    let repo = Repository::try_open(root).await?;
+    let stats = repo.storage_stats().await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+    println!(
+        "loose:    {} objects, {}",
+        stats.loose_objects.to_string().bold(),
+        format_size(stats.loose_size)
+    );
+    println!(
+        "packed:   {} objects in {} pack(s), {}",
+        stats.packed_objects.to_string().bold(),
+        stats.packs,
+        format_size(stats.packed_size)
+    );
+    if stats.duplicate_objects > 0 {
+        println!(
+            "copies:   {} objects stored more than once",
+            stats.duplicate_objects.to_string().yellow()
+        );
+    }
+    println!(
+        "chunked:  {} blobs of {} chunks, {} shared",
+        stats.chunked_blobs.to_string().bold(),
+        stats.chunks,
+        format_size(stats.shared_chunk_size)
+    );
+    match stats.repack_savings {
+        0 => println!("{} A repack would not reclaim space", "[*]".bright_blue()),
+        savings => println!(
+            "{} A repack would reclaim {}, run `wsvc repack`",
+            "[*]".bright_blue(),
+            format_size(savings).green()
+        ),
+    }
+    Ok(())
+}
@@ -0,0 +1,58 @@
+use std::path::PathBuf;
+
+use colored::Colorize;
+use wsvc::{
+    fs::{RepoGuard, WsvcFsError},
+    model::Repository,
+    WsvcError,
+};
+
+use super::{config::WsvcConfig, diff::print_change, resolve_record};
+
+pub async fn revert(
+    hash: String,
+    message: Option<String>,
+    author: Option<String>,
+    workspace: Option<String>,
+    root: Option<String>,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let workspace = PathBuf::from(workspace.unwrap_or(pwd.clone()));
+    let root = root.unwrap_or(pwd);
+    let mut repo = Repository::try_open(root).await?;
+    let config = WsvcConfig::load(Some(&repo)).await?;
+    config.configure(&mut repo);
+    let guard = RepoGuard::new(&repo).await?;
+    if repo.path == workspace {
+        return Err(WsvcError::BadUsage(
+            "workspace and repo path can not be the same".to_owned(),
+        ));
+    }
+    let author = author
+        .or(config.commit.author)
+        .ok_or(WsvcError::LackOfConfig(
+            "commit.author".to_owned(),
+            "use `--author` or `wsvc config set commit.author <name>`".to_owned(),
+        ))?;
+    let record = resolve_record(&repo.to_read_only(), Some(hash)).await?;
+    let revert = repo
+        .revert_record(&record.hash, &workspace, author, message)
+        .await?;
+    for change in &revert.changes {
+        print_change(change);
+    }
+    let reverted = revert.reverted.0.to_hex().to_string();
+    let hash = revert.record.hash.0.to_hex().to_string();
+    println!(
+        "Reverted record {} in record: {} ({})",
+        reverted[0..6].yellow(),
+        hash[0..6].green().bold(),
+        hash
+    );
+    drop(guard);
+    Ok(())
+}
@@ -0,0 +1,477 @@
+use std::{path::PathBuf, rc::Rc};
+
+use colored::Colorize;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    sync::broadcast,
+    task::LocalSet,
+};
+use wsvc::{
+    fs::{RepoGuard, WsvcFsError},
+    head::HeadState,
+    meta::RepoMeta,
+    model::{Record, Repository},
+    patch::Whitespace,
+    trailer::{RecordWithTrailers, TrailerFilter},
+    walk::WalkOrder,
+    WsvcError,
+};
+
+use super::{
+    config::WsvcConfig,
+    events::{JournalFollower, FOLLOW_INTERVAL},
+};
+
+/// file name of the socket in the repository dir.
+#[cfg(unix)]
+const SOCKET_FILE: &str = "rpc.sock";
+
+/// json-rpc error codes.
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+/// notifications kept for a client busy with a request.
+const NOTIFICATION_BUFFER: usize = 64;
+
+struct RpcState {
+    repo: Repository,
+    workspace: PathBuf,
+    config: WsvcConfig,
+}
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    /// requests without id are notifications and get no response.
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<WsvcError> for RpcError {
+    fn from(err: WsvcError) -> Self {
+        Self::new(SERVER_ERROR, err.to_string())
+    }
+}
+
+impl From<WsvcFsError> for RpcError {
+    fn from(err: WsvcFsError) -> Self {
+        WsvcError::from(err).into()
+    }
+}
+
+impl From<serde_json::Error> for RpcError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::new(SERVER_ERROR, err.to_string())
+    }
+}
+
+/// parse params of a method, missing params are an empty object.
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    let params = match params {
+        Value::Null => json!({}),
+        params => params,
+    };
+    serde_json::from_value(params).map_err(|err| RpcError::new(INVALID_PARAMS, err.to_string()))
+}
+
+#[derive(Deserialize)]
+struct LogsParams {
+    skip: Option<usize>,
+    limit: Option<usize>,
+    order: Option<String>,
+    /// trailer filters, `key` or `key=value`.
+    #[serde(default)]
+    trailers: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct DiffParams {
+    from: Option<String>,
+    to: Option<String>,
+    /// add line diffs of text files as `hunks` to changes.
+    #[serde(default)]
+    patch: bool,
+    /// how whitespace counts in line diffs, `exact`, `change` or `all`, `diff.whitespace`
+    /// of the config if not set.
+    whitespace: Option<Whitespace>,
+}
+
+#[derive(Deserialize)]
+struct CommitParams {
+    message: String,
+    author: Option<String>,
+    #[serde(default)]
+    advance: bool,
+}
+
+#[derive(Deserialize)]
+struct CheckoutParams {
+    hash: Option<String>,
+    /// discard workspace changes.
+    #[serde(default)]
+    force: bool,
+}
+
+/// resolve a record by tag name or hash prefix without asking, HEAD if `hash` is `None`.
+async fn resolve(repo: &Repository, hash: Option<String>) -> Result<Record, RpcError> {
+    let hash = match hash {
+        Some(hash) => match repo.resolve_tag(&hash).await? {
+            Some(record) => return Ok(record),
+            None => hash,
+        },
+        None => {
+            return repo
+                .get_head_record()
+                .await?
+                .ok_or(RpcError::new(SERVER_ERROR, "repository has no record"))
+        }
+    };
+    let mut records = repo.find_records_by_prefix(&hash).await?;
+    match records.len() {
+        1 => Ok(records.remove(0)),
+        0 => Err(RpcError::new(
+            INVALID_PARAMS,
+            format!("no record found for hash {}", hash),
+        )),
+        _ => Err(RpcError::new(
+            INVALID_PARAMS,
+            format!("more than one record found for hash {}", hash),
+        )),
+    }
+}
+
+async fn status(state: &RpcState) -> Result<Value, RpcError> {
+    let meta = RepoMeta::collect(&state.repo.to_read_only(), &state.workspace).await?;
+    let head_state = state.repo.head_state().await?;
+    let (latest, behind, fast_forward) = match &head_state {
+        HeadState::Detached {
+            latest,
+            behind,
+            fast_forward,
+            ..
+        } => (Some(latest), *behind, *fast_forward),
+        HeadState::Latest(head) => (Some(head), 0, false),
+        HeadState::Branch { head, .. } => (head.as_ref(), 0, false),
+        HeadState::Unborn => (None, 0, false),
+    };
+    let branch = match &head_state {
+        HeadState::Branch { name, .. } => Some(name),
+        _ => None,
+    };
+    Ok(json!({
+        "head": meta.record,
+        "describe": meta.describe,
+        "dirty": meta.dirty,
+        "latest": latest,
+        "branch": branch,
+        "detached": head_state.is_detached(),
+        "behind": behind,
+        "fast_forward": fast_forward,
+    }))
+}
+
+async fn logs(state: &RpcState, params: LogsParams) -> Result<Value, RpcError> {
+    let order = match params.order {
+        Some(order) => order
+            .parse::<WalkOrder>()
+            .map_err(|err| RpcError::new(INVALID_PARAMS, err))?,
+        None => WalkOrder::Date,
+    };
+    let filters = params
+        .trailers
+        .iter()
+        .map(|filter| filter.parse::<TrailerFilter>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| RpcError::new(INVALID_PARAMS, err))?;
+    let records = state.repo.walk(None, order).await?;
+    let records = records
+        .iter()
+        .filter(|record| filters.iter().all(|filter| filter.matches(record)))
+        .skip(params.skip.unwrap_or(0))
+        .take(params.limit.unwrap_or(usize::MAX))
+        .map(RecordWithTrailers::from)
+        .collect::<Vec<_>>();
+    Ok(serde_json::to_value(records)?)
+}
+
+async fn diff(state: &RpcState, params: DiffParams) -> Result<Value, RpcError> {
+    let repo = &state.repo;
+    let to = resolve(repo, params.to).await?;
+    let from = match params.from {
+        Some(from) => Some(resolve(repo, Some(from)).await?),
+        None => match repo.record_graph().await?.parents(&to.hash).first() {
+            Some(parent) => Some(repo.read_record(parent).await?),
+            None => None,
+        },
+    };
+    let changes = repo
+        .changes_of_trees(from.as_ref().map(|r| &r.root), Some(&to.root))
+        .await?;
+    if !params.patch {
+        return Ok(serde_json::to_value(changes)?);
+    }
+    let whitespace = params
+        .whitespace
+        .or(state.config.diff.whitespace)
+        .unwrap_or_default();
+    let attributes = repo
+        .tree_attributes(&to.root)
+        .await?
+        .with_filters(state.config.filters());
+    let mut result = vec![];
+    for change in changes {
+        let mut value = serde_json::to_value(&change)?;
+        if let Some(hunks) = repo.text_diff(&change, 3, whitespace, &attributes).await? {
+            value["hunks"] = serde_json::to_value(hunks)?;
+        }
+        result.push(value);
+    }
+    Ok(Value::Array(result))
+}
+
+async fn commit(state: &RpcState, params: CommitParams) -> Result<Value, RpcError> {
+    let author = params
+        .author
+        .or(state.config.commit.author.clone())
+        .ok_or(RpcError::new(
+            INVALID_PARAMS,
+            "author is required, or set commit.author in config",
+        ))?;
+    let guard = RepoGuard::new(&state.repo).await?;
+    if params.advance {
+        state.repo.advance_head(&state.workspace).await?;
+    }
+    let record = state
+        .repo
+        .commit_record(&state.workspace, &author, &params.message, None)
+        .await?;
+    drop(guard);
+    Ok(serde_json::to_value(record)?)
+}
+
+async fn checkout(state: &RpcState, params: CheckoutParams) -> Result<Value, RpcError> {
+    let repo = &state.repo;
+    let record = match params.hash {
+        Some(hash) => resolve(repo, Some(hash)).await?,
+        None => repo
+            .get_latest_record()
+            .await?
+            .ok_or(RpcError::new(SERVER_ERROR, "repository has no record"))?,
+    };
+    if !params.force
+        && RepoMeta::collect(&repo.to_read_only(), &state.workspace)
+            .await?
+            .dirty
+    {
+        return Err(RpcError::new(
+            SERVER_ERROR,
+            "workspace has changes, commit them or checkout with force",
+        ));
+    }
+    let guard = RepoGuard::new(repo).await?;
+    let record = repo.checkout_record(&record.hash, &state.workspace).await?;
+    drop(guard);
+    Ok(serde_json::to_value(record)?)
+}
+
+async fn call(state: &RpcState, method: &str, params_value: Value) -> Result<Value, RpcError> {
+    match method {
+        "status" => status(state).await,
+        "logs" => logs(state, params(params_value)?).await,
+        "diff" => diff(state, params(params_value)?).await,
+        "commit" => commit(state, params(params_value)?).await,
+        "checkout" => checkout(state, params(params_value)?).await,
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("method not found: {}", method),
+        )),
+    }
+}
+
+/// handle a request line, the response is `None` for notifications.
+async fn handle(state: &RpcState, line: &str) -> Option<String> {
+    let request = match serde_json::from_str::<RpcRequest>(line) {
+        Ok(request) => request,
+        Err(err) => {
+            return Some(
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": { "code": PARSE_ERROR, "message": err.to_string() },
+                })
+                .to_string(),
+            )
+        }
+    };
+    let result = call(state, &request.method, request.params).await;
+    let id = request.id?;
+    Some(
+        match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(err) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": err.code, "message": err.message },
+            }),
+        }
+        .to_string(),
+    )
+}
+
+/// serve a client, requests and notifications are json-rpc messages, one per line.
+async fn serve_client<S: AsyncRead + AsyncWrite>(
+    state: Rc<RpcState>,
+    stream: S,
+    mut notifications: broadcast::Receiver<String>,
+) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    loop {
+        let message = tokio::select! {
+            line = lines.next_line() => match line {
+                Ok(Some(line)) if line.trim().is_empty() => continue,
+                Ok(Some(line)) => match handle(&state, &line).await {
+                    Some(response) => response,
+                    None => continue,
+                },
+                _ => break,
+            },
+            notification = notifications.recv() => match notification {
+                Ok(notification) => notification,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(_) => break,
+            },
+        };
+        if writer
+            .write_all(format!("{}\n", message).as_bytes())
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+/// send events appended to the journal to all clients as `event` notifications.
+async fn notify_events(path: PathBuf, sender: broadcast::Sender<String>) {
+    let mut follower = JournalFollower::new(path).await;
+    loop {
+        tokio::time::sleep(FOLLOW_INTERVAL).await;
+        for line in follower.poll().await.unwrap_or_default() {
+            if let Ok(event) = serde_json::from_str::<Value>(&line) {
+                let notification = json!({ "jsonrpc": "2.0", "method": "event", "params": event });
+                // no clients connected.
+                sender.send(notification.to_string()).ok();
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn listen(
+    state: Rc<RpcState>,
+    socket: Option<String>,
+    sender: broadcast::Sender<String>,
+) -> Result<(), WsvcError> {
+    use tokio::net::{UnixListener, UnixStream};
+
+    let path = socket
+        .map(PathBuf::from)
+        .unwrap_or(state.repo.path.join(SOCKET_FILE));
+    if path.exists() {
+        if UnixStream::connect(&path).await.is_ok() {
+            return Err(WsvcError::BadUsage(format!(
+                "another server is listening on {}",
+                path.display()
+            )));
+        }
+        // left by a server not exited cleanly.
+        tokio::fs::remove_file(&path)
+            .await
+            .map_err(WsvcFsError::Os)?;
+    }
+    let listener = UnixListener::bind(&path).map_err(WsvcFsError::Os)?;
+    println!("{} Listening on {}", "[+]".bright_green(), path.display());
+    loop {
+        let (stream, _) = listener.accept().await.map_err(WsvcFsError::Os)?;
+        tokio::task::spawn_local(serve_client(state.clone(), stream, sender.subscribe()));
+    }
+}
+
+#[cfg(windows)]
+async fn listen(
+    state: Rc<RpcState>,
+    socket: Option<String>,
+    sender: broadcast::Sender<String>,
+) -> Result<(), WsvcError> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let name = socket.unwrap_or_else(|| {
+        let hash = blake3::hash(state.repo.path.to_string_lossy().as_bytes());
+        format!(r"\\.\pipe\wsvc-{}", &hash.to_hex()[0..16])
+    });
+    let mut server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(&name)
+        .map_err(WsvcFsError::Os)?;
+    println!("{} Listening on {}", "[+]".bright_green(), name);
+    loop {
+        server.connect().await.map_err(WsvcFsError::Os)?;
+        let client = server;
+        server = ServerOptions::new()
+            .create(&name)
+            .map_err(WsvcFsError::Os)?;
+        tokio::task::spawn_local(serve_client(state.clone(), client, sender.subscribe()));
+    }
+}
+
+/// serve the local api for editors until interrupted, on a unix socket in the repository
+/// dir or a named pipe on windows.
+pub async fn rpc(
+    root: Option<String>,
+    workspace: Option<String>,
+    socket: Option<String>,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let workspace = PathBuf::from(workspace.unwrap_or(pwd.clone()));
+    let mut repo = Repository::try_open(root.unwrap_or(pwd)).await?;
+    let config = WsvcConfig::load(Some(&repo)).await?;
+    config.configure(&mut repo);
+    let (sender, _) = broadcast::channel(NOTIFICATION_BUFFER);
+    let state = Rc::new(RpcState {
+        repo,
+        workspace,
+        config,
+    });
+    // repository operations are not `Send`, clients are served on this thread.
+    LocalSet::new()
+        .run_until(async move {
+            tokio::task::spawn_local(notify_events(state.repo.events_path(), sender.clone()));
+            listen(state, socket, sender).await
+        })
+        .await
+}
@@ -0,0 +1,1023 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+
+use base64::Engine;
+
+use axum::{
+    body::Bytes,
+    extract::{ws::WebSocketUpgrade, DefaultBodyLimit, Host, Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use colored::Colorize;
+#[cfg(feature = "oidc")]
+use wsvc::server::oidc::OidcVerifier;
+use wsvc::{
+    auth::SyncCredential,
+    frame::{FrameStream, CAPABILITIES_HEADER, VERSION, VERSION_HEADER},
+    fs::{RepoGuard, WsvcFsError},
+    model::{ObjectId, Repository},
+    query::{parse_date, RecordQuery},
+    release::{check_asset_name, Release},
+    server::{
+        auth::{verify_htpasswd, CredentialVerifier, HtpasswdAccounts},
+        blobs::{BlobInfo, ByteRange},
+        deploy::{
+            repo_name, DeployTokenStore, DeployVerifier, DEPLOY_TOKENS_FILE, DEPLOY_TOKEN_PREFIX,
+        },
+        hosting::{HostingConfig, RepoRoot},
+        poll::PollHub,
+        readme::Readme,
+        relay::RelayHub,
+        search::SearchIndex,
+        stats::RepoStats,
+        trash::RepoTrash,
+        usage::{
+            remove_asset_metered, store_asset_metered, AssetUpload, UsageLedger, UsageMeter,
+            ANONYMOUS_USER,
+        },
+        visibility::{Access, RepoAccess},
+        WsvcServerError,
+    },
+    WsvcError,
+};
+
+/// endpoints served beside the sync of each repository.
+const CAPABILITIES: &str = "poll,stats,search,records,blobs,readme,releases,assets";
+
+/// first segment of the admin api of each root, never the name of a repository.
+const ADMIN_SEGMENT: &str = ".admin";
+
+/// interval of purging repositories whose retention in the trash is over.
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// largest request body, e.g. an uploaded release archive or asset.
+const MAX_BODY_SIZE: usize = 512 * 1024 * 1024;
+
+/// the account and password of the param of a `Basic` authorization.
+fn basic_credential(param: &str) -> Result<SyncCredential, StatusCode> {
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(param)
+        .ok()
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let (account, password) = decoded.split_once(':').ok_or(StatusCode::BAD_REQUEST)?;
+    Ok(SyncCredential {
+        account: account.to_owned(),
+        password: password.to_owned(),
+    })
+}
+
+/// `RootAuth` stand for the auth backend guarding a root, by the `auth` of the root. deploy
+/// tokens of `.deploy-tokens.json` of the root are accepted besides its accounts.
+enum RootAuth {
+    /// accounts of the `htpasswd` file of the root.
+    Htpasswd(DeployVerifier<HtpasswdAccounts>),
+    /// tokens of the `oidc` issuer of the root.
+    #[cfg(feature = "oidc")]
+    Oidc(DeployVerifier<OidcVerifier>),
+}
+
+/// `HttpClient` stand for the account a http request authenticated as, and what its
+/// credential grants by itself, see `CredentialVerifier::grant`.
+struct HttpClient {
+    account: String,
+    grant: Option<Access>,
+}
+
+impl RootAuth {
+    /// the auth backend of `root`, `None` if anyone can access it.
+    async fn load(root: &RepoRoot) -> Result<Option<Self>, WsvcError> {
+        let tokens = || DeployTokenStore::new(root.path.join(DEPLOY_TOKENS_FILE));
+        match root.auth.as_deref() {
+            None => Ok(None),
+            Some("htpasswd") => match &root.htpasswd {
+                Some(path) => Ok(Some(Self::Htpasswd(DeployVerifier {
+                    tokens: tokens(),
+                    inner: HtpasswdAccounts::load(path).await?,
+                }))),
+                None => Err(WsvcError::LackOfConfig(
+                    "htpasswd".to_owned(),
+                    format!("set `htpasswd` of root {}", root.path.display()),
+                )),
+            },
+            #[cfg(feature = "oidc")]
+            Some("oidc") => match &root.oidc {
+                Some(config) => Ok(Some(Self::Oidc(DeployVerifier {
+                    tokens: tokens(),
+                    inner: OidcVerifier::new(config.clone()),
+                }))),
+                None => Err(WsvcError::LackOfConfig(
+                    "oidc".to_owned(),
+                    format!("add [root.oidc] to root {}", root.path.display()),
+                )),
+            },
+            Some(auth) => Err(WsvcError::BadUsage(format!(
+                "auth backend `{}` of root {} is not supported by `wsvc serve`",
+                auth,
+                root.path.display()
+            ))),
+        }
+    }
+
+    fn verifier(&self) -> &dyn CredentialVerifier {
+        match self {
+            Self::Htpasswd(verifier) => verifier,
+            #[cfg(feature = "oidc")]
+            Self::Oidc(verifier) => verifier,
+        }
+    }
+
+    fn deploy_tokens(&self) -> &DeployTokenStore {
+        match self {
+            Self::Htpasswd(verifier) => &verifier.tokens,
+            #[cfg(feature = "oidc")]
+            Self::Oidc(verifier) => &verifier.tokens,
+        }
+    }
+
+    /// the client authenticated by the `Authorization` header of `headers`, `None` without
+    /// one. `Basic` credentials are verified like the credential of a sync, `Bearer` tokens
+    /// are deploy tokens, or tokens of the oidc issuer.
+    async fn client(
+        &self,
+        repo: &Repository,
+        name: &str,
+        headers: &HeaderMap,
+    ) -> Result<Option<HttpClient>, StatusCode> {
+        let Some(value) = headers.get(header::AUTHORIZATION) else {
+            return Ok(None);
+        };
+        let value = value.to_str().map_err(|_| StatusCode::BAD_REQUEST)?;
+        let (scheme, param) = value.split_once(' ').ok_or(StatusCode::BAD_REQUEST)?;
+        let param = param.trim();
+        let client = match (scheme.to_ascii_lowercase().as_str(), self) {
+            ("basic", _) => {
+                let credential = basic_credential(param)?;
+                let verifier = self.verifier();
+                match verifier.verify(repo, &credential).await {
+                    Ok(true) => Some(HttpClient {
+                        grant: verifier
+                            .grant(repo, &credential)
+                            .await
+                            .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?,
+                        account: credential.account,
+                    }),
+                    Ok(false) => None,
+                    Err(_) => return Err(StatusCode::SERVICE_UNAVAILABLE),
+                }
+            }
+            ("bearer", _) if param.starts_with(DEPLOY_TOKEN_PREFIX) => self
+                .deploy_tokens()
+                .find(param, name)
+                .await
+                .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?
+                .map(|token| HttpClient {
+                    account: token.name,
+                    grant: Some(token.scope.access),
+                }),
+            #[cfg(feature = "oidc")]
+            ("bearer", Self::Oidc(verifier)) => verifier
+                .inner
+                .validate(param)
+                .await
+                .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?
+                .map(|account| HttpClient {
+                    account,
+                    grant: None,
+                }),
+            _ => return Err(StatusCode::BAD_REQUEST),
+        };
+        client.map(Some).ok_or(StatusCode::UNAUTHORIZED)
+    }
+}
+
+struct ServeState {
+    hosting: HostingConfig,
+    /// auth backends of the roots of `hosting`, in the same order.
+    auths: Vec<Option<Arc<RootAuth>>>,
+    /// usage ledgers of the roots of `hosting`, in the same order.
+    ledgers: Vec<Option<Arc<UsageLedger>>>,
+    /// trashes of the roots of `hosting`, in the same order.
+    trashes: Vec<Arc<RepoTrash>>,
+    /// admins of the admin api, the admin api is off if not set.
+    admins: Option<HtpasswdAccounts>,
+    polls: PollHub,
+    relays: RelayHub,
+}
+
+impl ServeState {
+    fn index(&self, root: &RepoRoot) -> Option<usize> {
+        self.hosting
+            .roots
+            .iter()
+            .position(|r| std::ptr::eq(r, root))
+    }
+
+    /// the auth backend guarding `root`, `None` if anyone can access it.
+    fn auth(&self, root: &RepoRoot) -> Option<Arc<RootAuth>> {
+        self.auths[self.index(root)?].clone()
+    }
+
+    /// the usage ledger of `root`, `None` if usage is not accounted.
+    fn ledger(&self, root: &RepoRoot) -> Option<Arc<UsageLedger>> {
+        self.ledgers[self.index(root)?].clone()
+    }
+
+    fn trash(&self, root: &RepoRoot) -> Result<Arc<RepoTrash>, StatusCode> {
+        let index = self.index(root).ok_or(StatusCode::NOT_FOUND)?;
+        Ok(self.trashes[index].clone())
+    }
+
+    /// check the `Basic` authorization of `headers` is of an admin. the admin api is not
+    /// found if it is off.
+    async fn check_admin(&self, headers: &HeaderMap) -> Result<(), StatusCode> {
+        let admins = self.admins.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+        let value = headers
+            .get(header::AUTHORIZATION)
+            .ok_or(StatusCode::UNAUTHORIZED)?
+            .to_str()
+            .map_err(|_| StatusCode::BAD_REQUEST)?;
+        let (scheme, param) = value.split_once(' ').ok_or(StatusCode::BAD_REQUEST)?;
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        let credential = basic_credential(param.trim())?;
+        let hash = admins
+            .accounts
+            .get(&credential.account)
+            .cloned()
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+        // bcrypt takes a while by design, out of the runtime.
+        let verified =
+            tokio::task::spawn_blocking(move || verify_htpasswd(&credential.password, &hash))
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        match verified {
+            true => Ok(()),
+            false => Err(StatusCode::UNAUTHORIZED),
+        }
+    }
+}
+
+type SharedState = State<Arc<ServeState>>;
+
+/// the root and the path segments after its prefix, the first one is the repository name.
+fn route<'a>(
+    state: &'a ServeState,
+    host: &str,
+    path: &str,
+) -> Result<(&'a RepoRoot, Vec<String>), StatusCode> {
+    let path = format!("/{}", path.trim_matches('/'));
+    let (root, rest) = state
+        .hosting
+        .resolve(Some(host), &path)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let segments = rest
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_owned())
+        .collect::<Vec<_>>();
+    if segments.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok((root, segments))
+}
+
+/// the filters, offset and limit of the query string of the records endpoint, e.g.
+/// `?author=alice&since=2024-01-01&q=fix&offset=50&limit=50`.
+fn records_query(
+    query: &HashMap<String, String>,
+) -> Result<(RecordQuery, usize, Option<usize>), StatusCode> {
+    let date = |key: &str, end: bool| match query.get(key) {
+        Some(value) => parse_date(value, end)
+            .map(Some)
+            .map_err(|_| StatusCode::BAD_REQUEST),
+        None => Ok(None),
+    };
+    let number = |key: &str| match query.get(key) {
+        Some(value) => value.parse().map(Some).map_err(|_| StatusCode::BAD_REQUEST),
+        None => Ok(None),
+    };
+    let filters = RecordQuery {
+        author: query.get("author").cloned(),
+        since: date("since", false)?,
+        until: date("until", true)?,
+        q: query.get("q").cloned(),
+    };
+    Ok((filters, number("offset")?.unwrap_or(0), number("limit")?))
+}
+
+/// what the blob `hash` of a repository of `root` is, named `name` in a tree if known.
+async fn blob_info(
+    repo: &Repository,
+    root: &RepoRoot,
+    hash: &str,
+    name: Option<&str>,
+) -> Result<BlobInfo, StatusCode> {
+    let hash = ObjectId::try_from(hash).map_err(|_| StatusCode::BAD_REQUEST)?;
+    BlobInfo::load(repo, &hash, name, &root.inline_types())
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)
+}
+
+/// the readme of the dir `?path=` of the record `?record=`, or of the tip record.
+async fn readme(repo: &Repository, query: &HashMap<String, String>) -> Result<Readme, StatusCode> {
+    let record = match query.get("record") {
+        Some(hash) => Some(ObjectId::try_from(hash.as_str()).map_err(|_| StatusCode::BAD_REQUEST)?),
+        None => None,
+    };
+    let path = query.get("path").map(String::as_str).unwrap_or_default();
+    match Readme::load_at(repo, record.as_ref(), path).await {
+        Ok(Some(readme)) => Ok(readme),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(WsvcError::FsError(WsvcFsError::InvalidFilename(_))) => Err(StatusCode::BAD_REQUEST),
+        Err(_) => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// the content of the blob of `info`, or the part of it asked by the `Range` header of
+/// `headers`. blobs never change, so they are cached by their hash. blobs not allowed to
+/// be rendered inline are served as downloads, and none may run scripts.
+async fn blob_response(
+    repo: &Repository,
+    info: &BlobInfo,
+    headers: &HeaderMap,
+) -> Result<Response, StatusCode> {
+    let hash = ObjectId::try_from(info.hash.as_str()).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let size = info.size;
+    let range = headers.get(header::RANGE).and_then(|v| v.to_str().ok());
+    let (status, start, end) = match ByteRange::parse(range, size) {
+        ByteRange::Full => (StatusCode::OK, 0, size),
+        ByteRange::Partial { start, end } => (StatusCode::PARTIAL_CONTENT, start, end),
+        ByteRange::Unsatisfiable => {
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{}", size))],
+            )
+                .into_response())
+        }
+    };
+    let data = repo
+        .read_blob_range(&hash, start, end)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut response = (
+        status,
+        [
+            (header::CONTENT_TYPE, info.content_type()),
+            (
+                header::CONTENT_DISPOSITION,
+                match info.inline {
+                    true => "inline".to_owned(),
+                    false => "attachment".to_owned(),
+                },
+            ),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_owned()),
+            (
+                header::CONTENT_SECURITY_POLICY,
+                "default-src 'none'; style-src 'unsafe-inline'; sandbox".to_owned(),
+            ),
+            (header::ACCEPT_RANGES, "bytes".to_owned()),
+            (header::ETAG, format!("\"{}\"", hash.0.to_hex())),
+            (
+                header::CACHE_CONTROL,
+                "public, max-age=31536000, immutable".to_owned(),
+            ),
+        ],
+        data,
+    )
+        .into_response();
+    if status == StatusCode::PARTIAL_CONTENT {
+        response.headers_mut().insert(
+            header::CONTENT_RANGE,
+            HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end - 1, size))
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        );
+    }
+    Ok(response)
+}
+
+fn report(name: &str, result: Result<(), wsvc::server::WsvcServerError>) {
+    match result {
+        Ok(_) => println!("{} {} synced.", "[*]".bright_blue(), name),
+        Err(err) => println!("{} {}: {}", "[!]".bright_red(), name, err),
+    }
+}
+
+/// who may read and write `repo` of `root`.
+async fn load_access(root: &RepoRoot, repo: &Repository) -> Result<Option<RepoAccess>, StatusCode> {
+    root.access(repo)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// who may read and write `repo` of `root`, if the client authenticated by the
+/// `Authorization` header of `headers` may do what `needed` is. accounts of a guarded root
+/// may write its repositories without a visibility, anonymous clients may not access them.
+/// repositories clients may not read are not told apart from missing ones, unless the
+/// client may authenticate to read them.
+async fn check_access(
+    state: &ServeState,
+    root: &RepoRoot,
+    repo: &Repository,
+    headers: &HeaderMap,
+    needed: Access,
+) -> Result<Option<RepoAccess>, StatusCode> {
+    Ok(check_client(state, root, repo, headers, needed).await?.0)
+}
+
+/// who may read and write `repo` of `root` like `check_access`, with the account the
+/// client authenticated as, `None` if anonymous.
+async fn check_client(
+    state: &ServeState,
+    root: &RepoRoot,
+    repo: &Repository,
+    headers: &HeaderMap,
+    needed: Access,
+) -> Result<(Option<RepoAccess>, Option<String>), StatusCode> {
+    let auth = state.auth(root);
+    let name = repo_name(repo);
+    let client = match &auth {
+        Some(auth) => auth.client(repo, &name, headers).await?,
+        None => None,
+    };
+    let account = client.as_ref().map(|client| client.account.as_str());
+    let access = load_access(root, repo).await?;
+    let granted = match (client.as_ref().and_then(|c| c.grant), &access, &auth) {
+        (Some(granted), _, _) => granted,
+        (None, Some(access), _) => access.access(account),
+        (None, None, Some(_)) if account.is_none() => Access::None,
+        (None, None, _) => Access::Write,
+    };
+    match granted {
+        granted if granted >= needed => Ok((access, client.map(|client| client.account))),
+        _ if auth.is_some() && account.is_none() => Err(StatusCode::UNAUTHORIZED),
+        Access::None => Err(StatusCode::NOT_FOUND),
+        _ if account.is_some() => Err(StatusCode::FORBIDDEN),
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+/// sync `repo` named `name` with a client, who authenticates to `auth` in the sync and
+/// may do what `access` allows. the sync is accounted in `ledger` if set.
+async fn sync_client(
+    name: &str,
+    repo: &Repository,
+    stream: &mut impl FrameStream,
+    root: &RepoRoot,
+    auth: Option<&RootAuth>,
+    access: Option<&RepoAccess>,
+    ledger: Option<&UsageLedger>,
+) {
+    let limits = root.session_limits();
+    let verifier = auth.map(RootAuth::verifier);
+    let result = match (ledger, access, verifier) {
+        (Some(ledger), access, verifier) => {
+            let meter = UsageMeter {
+                ledger,
+                cap: root.usage.as_ref().and_then(|usage| usage.transfer_cap),
+            };
+            wsvc::server::sync_with_meter(repo, stream, &limits, verifier, access, &meter).await
+        }
+        (None, Some(access), verifier) => {
+            wsvc::server::sync_with_access(repo, stream, &limits, verifier, access).await
+        }
+        (None, None, Some(verifier)) => {
+            wsvc::server::sync_with_auth(repo, stream, &limits, verifier).await
+        }
+        (None, None, None) => wsvc::server::sync_with_limits(repo, stream, &limits).await,
+    };
+    report(name, result);
+}
+
+/// who may read and write `repo` of `root` for a sync with a client, if it may read it.
+/// clients of guarded roots authenticate in the sync, so they are checked there.
+async fn sync_access(
+    state: &ServeState,
+    root: &RepoRoot,
+    repo: &Repository,
+    headers: &HeaderMap,
+) -> Result<Option<RepoAccess>, StatusCode> {
+    match state.auth(root) {
+        Some(_) => load_access(root, repo).await,
+        None => check_access(state, root, repo, headers, Access::Read).await,
+    }
+}
+
+/// answer a get request of the admin api of `root`, `segments` are after `.admin`.
+async fn admin_get(
+    state: &ServeState,
+    root: &RepoRoot,
+    segments: &[&str],
+) -> Result<Response, StatusCode> {
+    match segments {
+        ["usage"] => {
+            let ledger = state.ledger(root).ok_or(StatusCode::NOT_FOUND)?;
+            Ok(Json(ledger.all().await).into_response())
+        }
+        ["usage", user] => {
+            let ledger = state.ledger(root).ok_or(StatusCode::NOT_FOUND)?;
+            Ok(Json(ledger.usage(user).await).into_response())
+        }
+        ["metrics"] => {
+            let ledger = state.ledger(root).ok_or(StatusCode::NOT_FOUND)?;
+            Ok((
+                [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+                ledger.metrics().await,
+            )
+                .into_response())
+        }
+        ["trash"] => match state.trash(root)?.list().await {
+            Ok(deleted) => Ok(Json(deleted).into_response()),
+            Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        },
+        _ => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// answer a post request of the admin api of `root`, `segments` are after `.admin`.
+async fn admin_post(
+    state: &ServeState,
+    root: &RepoRoot,
+    segments: &[&str],
+) -> Result<Response, StatusCode> {
+    match segments {
+        ["trash", id, "restore"] => match state.trash(root)?.restore(id).await {
+            Ok(restored) => Ok(Json(restored).into_response()),
+            Err(WsvcError::BadUsage(_)) => Err(StatusCode::BAD_REQUEST),
+            Err(WsvcError::RepoError(_)) => Err(StatusCode::NOT_FOUND),
+            Err(_) => Err(StatusCode::CONFLICT),
+        },
+        _ => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// answer a delete request of the admin api of `root`, `segments` are after `.admin`.
+/// deleted repositories are moved to the trash of the root.
+async fn admin_delete(
+    state: &ServeState,
+    root: &RepoRoot,
+    segments: &[&str],
+) -> Result<Response, StatusCode> {
+    match segments {
+        ["repos", name] => {
+            root.open(name).await.map_err(|_| StatusCode::NOT_FOUND)?;
+            match state.trash(root)?.delete(name).await {
+                Ok(deleted) => Ok(Json(deleted).into_response()),
+                // the repository is being synced.
+                Err(_) => Err(StatusCode::CONFLICT),
+            }
+        }
+        _ => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn handle_get(
+    State(state): SharedState,
+    Host(host): Host,
+    Path(path): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    ws: Option<WebSocketUpgrade>,
+) -> Result<Response, StatusCode> {
+    let (root, segments) = route(&state, &host, &path)?;
+    let segments = segments.iter().map(String::as_str).collect::<Vec<_>>();
+    if segments[0] == ADMIN_SEGMENT {
+        state.check_admin(&headers).await?;
+        return admin_get(&state, root, &segments[1..]).await;
+    }
+    let repo = root
+        .open(segments[0])
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    // polls are told apart by their secret id.
+    let access = match (&segments[1..], &ws) {
+        (["poll", _], _) => None,
+        ([], Some(_)) => sync_access(&state, root, &repo, &headers).await?,
+        _ => check_access(&state, root, &repo, &headers, Access::Read).await?,
+    };
+    match (&segments[1..], ws) {
+        ([], Some(ws)) => {
+            let name = segments[0].to_owned();
+            let auth = state.auth(root);
+            let ledger = state.ledger(root);
+            let root = root.clone();
+            let mut response = ws.on_upgrade(move |mut ws| async move {
+                let (auth, ledger) = (auth.as_deref(), ledger.as_deref());
+                sync_client(&name, &repo, &mut ws, &root, auth, access.as_ref(), ledger).await;
+            });
+            let headers = response.headers_mut();
+            headers.insert(VERSION_HEADER, HeaderValue::from_static(VERSION));
+            headers.insert(CAPABILITIES_HEADER, HeaderValue::from_static(CAPABILITIES));
+            Ok(response)
+        }
+        (["stats"], _) => match RepoStats::load(&repo).await {
+            Ok(stats) => Ok(Json(stats.report()).into_response()),
+            Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        },
+        (["search"], _) => match SearchIndex::load(&repo).await {
+            Ok(index) => {
+                let q = query.get("q").map(|q| q.as_str()).unwrap_or_default();
+                let limit = query.get("limit").and_then(|l| l.parse().ok());
+                Ok(Json(index.search(q, limit)).into_response())
+            }
+            Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        },
+        (["records"], _) => {
+            let (filters, offset, limit) = records_query(&query)?;
+            match SearchIndex::load(&repo).await {
+                Ok(index) => Ok(Json(index.page(&filters, offset, limit)).into_response()),
+                Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+            }
+        }
+        (["blobs", hash], _) => {
+            let info = blob_info(&repo, root, hash, query.get("name").map(String::as_str)).await?;
+            blob_response(&repo, &info, &headers).await
+        }
+        (["blobs", hash, "meta"], _) => Ok(Json(
+            blob_info(&repo, root, hash, query.get("name").map(String::as_str)).await?,
+        )
+        .into_response()),
+        (["readme"], _) => Ok(Json(readme(&repo, &query).await?).into_response()),
+        (["releases"], _) => match repo.get_releases().await {
+            Ok(releases) => Ok(Json(releases).into_response()),
+            Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        },
+        (["releases", version], _) => match repo.read_release(version).await {
+            Ok(Some(release)) => Ok(Json(release).into_response()),
+            Ok(None) => Err(StatusCode::NOT_FOUND),
+            Err(_) => Err(StatusCode::BAD_REQUEST),
+        },
+        (["releases", version, "archive"], _) => {
+            let release = match repo.read_release(version).await {
+                Ok(Some(release)) => release,
+                Ok(None) => return Err(StatusCode::NOT_FOUND),
+                Err(_) => return Err(StatusCode::BAD_REQUEST),
+            };
+            let name = release
+                .archive
+                .map(|archive| archive.name)
+                .ok_or(StatusCode::NOT_FOUND)?;
+            match repo.read_release_archive(version).await {
+                Ok(Some(data)) => Ok((
+                    [
+                        (header::CONTENT_TYPE, "application/gzip".to_owned()),
+                        (
+                            header::CONTENT_DISPOSITION,
+                            format!("attachment; filename=\"{}\"", name),
+                        ),
+                    ],
+                    data,
+                )
+                    .into_response()),
+                Ok(None) => Err(StatusCode::NOT_FOUND),
+                Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+            }
+        }
+        (["releases", tag, "assets"], _) => match repo.read_tag(tag).await {
+            Ok(Some(_)) => match repo.get_release_assets(tag).await {
+                Ok(assets) => Ok(Json(assets).into_response()),
+                Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+            },
+            Ok(None) => Err(StatusCode::NOT_FOUND),
+            Err(_) => Err(StatusCode::BAD_REQUEST),
+        },
+        (["releases", tag, "assets", name], _) => match repo.read_release_asset(tag, name).await {
+            Ok(Some((asset, data))) => Ok((
+                [
+                    (header::CONTENT_TYPE, asset.content_type),
+                    (
+                        header::CONTENT_DISPOSITION,
+                        format!("attachment; filename=\"{}\"", asset.name),
+                    ),
+                ],
+                data,
+            )
+                .into_response()),
+            Ok(None) => Err(StatusCode::NOT_FOUND),
+            Err(WsvcError::BadUsage(_)) => Err(StatusCode::BAD_REQUEST),
+            Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        },
+        (["poll", id], _) => match state.polls.pull(id).await {
+            Some(batch) => Ok(batch.into_response()),
+            None => Err(StatusCode::GONE),
+        },
+        _ => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn handle_post(
+    State(state): SharedState,
+    Host(host): Host,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, StatusCode> {
+    let (root, segments) = route(&state, &host, &path)?;
+    let segments = segments.iter().map(String::as_str).collect::<Vec<_>>();
+    if segments[0] == ADMIN_SEGMENT {
+        state.check_admin(&headers).await?;
+        return admin_post(&state, root, &segments[1..]).await;
+    }
+    match &segments[1..] {
+        ["poll"] => {
+            let repo = root
+                .open(segments[0])
+                .await
+                .map_err(|_| StatusCode::NOT_FOUND)?;
+            let access = sync_access(&state, root, &repo, &headers).await?;
+            let (id, mut stream) = state.polls.open().await;
+            let name = segments[0].to_owned();
+            let auth = state.auth(root);
+            let ledger = state.ledger(root);
+            let root = root.clone();
+            tokio::spawn(async move {
+                let (auth, ledger) = (auth.as_deref(), ledger.as_deref());
+                sync_client(
+                    &name,
+                    &repo,
+                    &mut stream,
+                    &root,
+                    auth,
+                    access.as_ref(),
+                    ledger,
+                )
+                .await;
+            });
+            Ok(id.into_response())
+        }
+        ["poll", id] => match state.polls.push(id, &body).await {
+            true => Ok(StatusCode::NO_CONTENT.into_response()),
+            false => Err(StatusCode::NOT_FOUND),
+        },
+        ["releases", version] => {
+            let repo = root
+                .open(segments[0])
+                .await
+                .map_err(|_| StatusCode::NOT_FOUND)?;
+            check_access(&state, root, &repo, &headers, Access::Write).await?;
+            let release =
+                serde_json::from_slice::<Release>(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+            if release.version != *version {
+                return Err(StatusCode::BAD_REQUEST);
+            }
+            // the record is pushed by a sync before its release.
+            if repo.read_record(&release.record).await.is_err() {
+                return Err(StatusCode::CONFLICT);
+            }
+            let guard = RepoGuard::new(&repo)
+                .await
+                .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+            let result = repo.publish_release(&release, None, false).await;
+            drop(guard);
+            match result {
+                Ok(_) => Ok(StatusCode::CREATED.into_response()),
+                Err(WsvcError::BadUsage(_)) => Err(StatusCode::CONFLICT),
+                Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+            }
+        }
+        ["releases", version, "archive"] => {
+            let repo = root
+                .open(segments[0])
+                .await
+                .map_err(|_| StatusCode::NOT_FOUND)?;
+            check_access(&state, root, &repo, &headers, Access::Write).await?;
+            let guard = RepoGuard::new(&repo)
+                .await
+                .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+            let result = repo.store_release_archive(version, &body).await;
+            drop(guard);
+            match result {
+                Ok(_) => Ok(StatusCode::CREATED.into_response()),
+                Err(WsvcError::BadUsage(_)) => Err(StatusCode::NOT_FOUND),
+                Err(WsvcError::DataError(_)) => Err(StatusCode::UNPROCESSABLE_ENTITY),
+                Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+            }
+        }
+        _ => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn handle_put(
+    State(state): SharedState,
+    Host(host): Host,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, StatusCode> {
+    let (root, segments) = route(&state, &host, &path)?;
+    let segments = segments.iter().map(String::as_str).collect::<Vec<_>>();
+    match &segments[1..] {
+        ["releases", tag, "assets", name] => {
+            check_asset_name(name).map_err(|_| StatusCode::BAD_REQUEST)?;
+            let repo = root
+                .open(segments[0])
+                .await
+                .map_err(|_| StatusCode::NOT_FOUND)?;
+            let (_, account) = check_client(&state, root, &repo, &headers, Access::Write).await?;
+            let content_type = headers
+                .get(header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("application/octet-stream");
+            match repo.read_tag(tag).await {
+                Ok(Some(_)) => {}
+                Ok(None) => return Err(StatusCode::NOT_FOUND),
+                Err(_) => return Err(StatusCode::BAD_REQUEST),
+            }
+            let guard = RepoGuard::new(&repo)
+                .await
+                .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+            if let Some(quota) = root.asset_quota {
+                let used = repo
+                    .release_assets_size()
+                    .await
+                    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+                // a replaced asset frees its size.
+                let replaced = repo
+                    .get_release_assets(tag)
+                    .await
+                    .map_err(|_| StatusCode::BAD_REQUEST)?
+                    .into_iter()
+                    .find(|a| a.name == *name)
+                    .map(|a| a.size)
+                    .unwrap_or(0);
+                if used.saturating_sub(replaced) + body.len() as u64 > quota {
+                    return Err(StatusCode::INSUFFICIENT_STORAGE);
+                }
+            }
+            let result = match state.ledger(root) {
+                Some(ledger) => {
+                    let upload = AssetUpload {
+                        tag,
+                        name,
+                        content_type,
+                        data: &body,
+                    };
+                    let user = account.as_deref().unwrap_or(ANONYMOUS_USER);
+                    let quota = root.usage.as_ref().and_then(|usage| usage.storage_quota);
+                    store_asset_metered(&repo, upload, &ledger, user, quota).await
+                }
+                None => repo
+                    .store_release_asset(tag, name, content_type, &body)
+                    .await
+                    .map(|(asset, _)| asset)
+                    .map_err(WsvcServerError::from),
+            };
+            drop(guard);
+            match result {
+                Ok(asset) => Ok((StatusCode::CREATED, Json(asset)).into_response()),
+                Err(WsvcServerError::Rejected(_)) => Err(StatusCode::INSUFFICIENT_STORAGE),
+                Err(WsvcServerError::WsvcError(WsvcError::BadUsage(_))) => {
+                    Err(StatusCode::NOT_FOUND)
+                }
+                Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+            }
+        }
+        _ => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn handle_delete(
+    State(state): SharedState,
+    Host(host): Host,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let (root, segments) = route(&state, &host, &path)?;
+    let segments = segments.iter().map(String::as_str).collect::<Vec<_>>();
+    if segments[0] == ADMIN_SEGMENT {
+        state.check_admin(&headers).await?;
+        return admin_delete(&state, root, &segments[1..]).await;
+    }
+    match &segments[1..] {
+        ["poll", id] => {
+            state.polls.close(id).await;
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+        ["releases", tag, "assets", name] => {
+            let repo = root
+                .open(segments[0])
+                .await
+                .map_err(|_| StatusCode::NOT_FOUND)?;
+            let (_, account) = check_client(&state, root, &repo, &headers, Access::Write).await?;
+            let guard = RepoGuard::new(&repo)
+                .await
+                .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+            let result = match state.ledger(root) {
+                Some(ledger) => {
+                    let user = account.as_deref().unwrap_or(ANONYMOUS_USER);
+                    remove_asset_metered(&repo, tag, name, &ledger, user).await
+                }
+                None => repo
+                    .remove_release_asset(tag, name)
+                    .await
+                    .map_err(WsvcServerError::from),
+            };
+            drop(guard);
+            match result {
+                Ok(Some(_)) => Ok(StatusCode::NO_CONTENT.into_response()),
+                Ok(None) => Err(StatusCode::NOT_FOUND),
+                Err(WsvcServerError::WsvcError(WsvcError::BadUsage(_))) => {
+                    Err(StatusCode::BAD_REQUEST)
+                }
+                Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+            }
+        }
+        _ => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// join the relay room `room`, pairing with the next peer joining it.
+async fn handle_relay(
+    State(state): SharedState,
+    Path(room): Path<String>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.on_upgrade(move |ws| async move { state.relays.join(room, ws).await })
+}
+
+/// serve repositories of the roots in the hosting config `config`, until interrupted.
+pub async fn serve(config: String, listen: Option<String>) -> Result<(), WsvcError> {
+    let hosting = HostingConfig::load(&config).await?;
+    if hosting.roots.is_empty() && !hosting.relay {
+        return Err(WsvcError::LackOfConfig(
+            "root".to_owned(),
+            format!(
+                "add a [[root]] with `prefix` and `path`, or `relay = true` to {}",
+                config
+            ),
+        ));
+    }
+    // refuse to serve guarded roots openly, other auth backends are provided by
+    // embedding servers.
+    let mut auths = vec![];
+    let mut ledgers = vec![];
+    let mut trashes = vec![];
+    for root in &hosting.roots {
+        auths.push(RootAuth::load(root).await?.map(Arc::new));
+        ledgers.push(root.usage_ledger().await?.map(Arc::new));
+        let trash = Arc::new(root.trash());
+        trash.clone().spawn_purge_job(PURGE_INTERVAL);
+        trashes.push(trash);
+    }
+    let admins = match &hosting.admin_htpasswd {
+        Some(path) => Some(HtpasswdAccounts::load(path).await?),
+        None => None,
+    };
+    let addr: SocketAddr = listen
+        .as_deref()
+        .unwrap_or("0.0.0.0:7878")
+        .parse()
+        .map_err(|_| WsvcError::BadUsage(format!("invalid listen address: {:?}", listen)))?;
+    for root in &hosting.roots {
+        println!(
+            "{} Serving {}{}/<repo> from {}",
+            "[+]".bright_green(),
+            root.host.as_deref().unwrap_or("*"),
+            root.normalized_prefix(),
+            root.path.display()
+        );
+    }
+    if hosting.relay {
+        println!("{} Serving relay rooms /relay/<room>", "[+]".bright_green());
+    }
+    if admins.is_some() {
+        println!(
+            "{} Serving the admin api <prefix>/{} of each root",
+            "[+]".bright_green(),
+            ADMIN_SEGMENT
+        );
+    }
+    let relay = hosting.relay;
+    let state = Arc::new(ServeState {
+        hosting,
+        auths,
+        ledgers,
+        trashes,
+        admins,
+        polls: PollHub::new(),
+        relays: RelayHub::new(),
+    });
+    let mut app = Router::new();
+    if relay {
+        app = app.route("/relay/:room", get(handle_relay));
+    }
+    let app = app
+        .route(
+            "/*path",
+            get(handle_get)
+                .post(handle_post)
+                .put(handle_put)
+                .delete(handle_delete),
+        )
+        .layer(DefaultBodyLimit::max(MAX_BODY_SIZE))
+        .with_state(state);
+    println!("{} Listening on {}", "[+]".bright_green(), addr);
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await
+        .map_err(|err| WsvcFsError::Os(std::io::Error::other(err)))?;
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::io::{IsTerminal, Write};
+
+use colored::Colorize;
+use wsvc::{
+    binary::{is_binary, BinaryDiffers},
+    fs::WsvcFsError,
+    model::{EntryKind, Repository},
+    WsvcError,
+};
+
+use super::resolve_record;
+
+/// print the file at `record:path`, or list the dir at it.
+pub async fn show(root: Option<String>, spec: String) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    let repo = Repository::open_read_only(root).await?;
+    let (hash, path) = spec.split_once(':').unwrap_or((&spec, ""));
+    let hash = Some(hash.to_owned()).filter(|h| !h.is_empty());
+    let record = resolve_record(&repo, hash).await?;
+    let entry = repo
+        .resolve_path(&record.hash, path)
+        .await?
+        .ok_or_else(|| {
+            WsvcError::BadUsage(format!(
+                "path {} not found in record {}",
+                path,
+                &record.hash.0.to_hex()[0..6]
+            ))
+        })?;
+    if entry.kind != EntryKind::Dir {
+        let content = repo.read_blob(&entry.hash).await?;
+        let mut stdout = std::io::stdout().lock();
+        // binary content is summarized on terminals, and written as is to pipes and files.
+        if stdout.is_terminal() && is_binary(&content) {
+            println!(
+                "binary file {} bytes, {}",
+                content.len(),
+                entry.hash.0.to_hex()[0..6].dimmed()
+            );
+            if let Some(summary) = BinaryDiffers::default().summarize(&content) {
+                println!("{}", summary);
+            }
+            return Ok(());
+        }
+        stdout.write_all(&content).map_err(WsvcFsError::Os)?;
+        stdout.flush().map_err(WsvcFsError::Os)?;
+        return Ok(());
+    }
+    for entry in repo.read_tree(&entry.hash).await?.entries {
+        let hash = entry.hash.0.to_hex().to_string();
+        match entry.kind {
+            EntryKind::Dir => println!(
+                "{}  {:>10}  {}/",
+                &hash[0..6].dimmed(),
+                "-",
+                entry.name.bright_blue().bold()
+            ),
+            _ => println!(
+                "{}  {:>10}  {}",
+                &hash[0..6].dimmed(),
+                entry.size,
+                entry.name
+            ),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use std::path::PathBuf;
+
+use colored::Colorize;
+use wsvc::{
+    fs::{RepoGuard, WsvcFsError},
+    model::Repository,
+    sign::{GpgSigner, GpgVerifier, Signer, SshSigner, SshVerifier, Verifier},
+    WsvcError,
+};
+
+use super::resolve_record;
+
+/// options to build a signer from command line.
+#[derive(clap::Args)]
+pub struct SignOptions {
+    /// ssh key file used by `ssh-keygen -Y sign`
+    #[clap(long)]
+    pub ssh_key: Option<String>,
+    /// signer identity matched in allowed signers file, required by ssh signing
+    #[clap(long)]
+    pub identity: Option<String>,
+    /// take the private key from ssh-agent, `--ssh-key` should be the public key
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    pub agent: bool,
+    /// fingerprint or long key id of the gpg key used to sign
+    #[clap(long)]
+    pub gpg_key: Option<String>,
+    /// gpg compatible program, `gpg` by default
+    #[clap(long)]
+    pub gpg_program: Option<String>,
+}
+
+impl SignOptions {
+    /// build a signer, `None` if neither ssh key nor gpg key is given.
+    pub fn signer(self) -> Result<Option<Box<dyn Signer + Sync>>, WsvcError> {
+        match (self.ssh_key, self.gpg_key) {
+            (Some(key), None) => Ok(Some(Box::new(SshSigner {
+                key: PathBuf::from(key),
+                identity: self.identity.ok_or(WsvcError::BadUsage(
+                    "ssh signing needs an `--identity` to be verified with".to_owned(),
+                ))?,
+                use_agent: self.agent,
+            }))),
+            (None, Some(key_id)) => Ok(Some(Box::new(GpgSigner {
+                program: self.gpg_program.unwrap_or("gpg".to_owned()),
+                key_id,
+            }))),
+            (None, None) => Ok(None),
+            _ => Err(WsvcError::BadUsage(
+                "use only one of `--ssh-key` and `--gpg-key`".to_owned(),
+            )),
+        }
+    }
+}
+
+pub async fn sign(
+    root: Option<String>,
+    hash: Option<String>,
+    options: SignOptions,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    let repo = Repository::try_open(root).await?;
+    let guard = RepoGuard::new(&repo).await?;
+    let signer = options.signer()?.ok_or(WsvcError::BadUsage(
+        "use one of `--ssh-key` and `--gpg-key` to sign".to_owned(),
+    ))?;
+    let record = resolve_record(&repo.to_read_only(), hash).await?;
+    let signature = repo.sign_record(&record.hash, signer.as_ref()).await?;
+    let hash = record.hash.0.to_hex().to_string();
+    println!(
+        "Signed record: {} ({}) by {}",
+        hash[0..6].green().bold(),
+        hash,
+        signature.signer.bright_blue()
+    );
+    drop(guard);
+    Ok(())
+}
+
+pub async fn verify(
+    root: Option<String>,
+    hash: Option<String>,
+    allowed_signers: Option<String>,
+    gpg_program: Option<String>,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    let repo = Repository::try_open(root).await?;
+    let guard = RepoGuard::new(&repo).await?;
+    let record = resolve_record(&repo.to_read_only(), hash).await?;
+    let mut verifiers: Vec<Box<dyn Verifier + Sync>> = vec![Box::new(GpgVerifier {
+        program: gpg_program.unwrap_or("gpg".to_owned()),
+    })];
+    if let Some(allowed_signers) = allowed_signers {
+        verifiers.push(Box::new(SshVerifier {
+            allowed_signers: PathBuf::from(allowed_signers),
+        }));
+    }
+    let mut checked = 0;
+    let mut all_valid = true;
+    for verifier in &verifiers {
+        for (signature, valid) in repo.verify_record(&record.hash, verifier.as_ref()).await? {
+            checked += 1;
+            all_valid &= valid;
+            println!(
+                "{} {:?} signature by {}",
+                if valid {
+                    "[GOOD]".bright_green().bold()
+                } else {
+                    "[BAD]".bright_red().bold()
+                },
+                signature.kind,
+                signature.signer.bright_blue()
+            );
+        }
+    }
+    drop(guard);
+    if checked == 0 {
+        return Err(WsvcError::SignatureError(
+            "no verifiable signature found".to_owned(),
+        ));
+    }
+    if !all_valid {
+        return Err(WsvcError::SignatureError("bad signature found".to_owned()));
+    }
+    Ok(())
+}
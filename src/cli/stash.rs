@@ -0,0 +1,85 @@
+use std::path::PathBuf;
+
+use colored::Colorize;
+use wsvc::{
+    fs::{RepoGuard, WsvcFsError},
+    model::Repository,
+    stash::Stash,
+    WsvcError,
+};
+
+use super::config::WsvcConfig;
+
+fn print_stash(index: usize, stash: &Stash) {
+    println!(
+        "{} {} on {}: {}",
+        format!("stash@{{{}}}", index).bright_yellow(),
+        stash.date.format("%Y-%m-%d %H:%M:%S").to_string().dimmed(),
+        stash.base.0.to_hex()[0..6].green(),
+        stash.message
+    );
+}
+
+/// open the repository of the workspace for writing, with the excludes file of the config.
+async fn open_workspace(
+    workspace: Option<String>,
+    root: Option<String>,
+) -> Result<(Repository, PathBuf), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let workspace = PathBuf::from(workspace.unwrap_or(pwd.clone()));
+    let root = root.unwrap_or(pwd);
+    let mut repo = Repository::try_open(root).await?;
+    let config = WsvcConfig::load(Some(&repo)).await?;
+    config.configure(&mut repo);
+    if repo.path == workspace {
+        return Err(WsvcError::BadUsage(
+            "workspace and repo path can not be the same".to_owned(),
+        ));
+    }
+    Ok((repo, workspace))
+}
+
+pub async fn stash_push(
+    message: Option<String>,
+    workspace: Option<String>,
+    root: Option<String>,
+) -> Result<(), WsvcError> {
+    let (repo, workspace) = open_workspace(workspace, root).await?;
+    let guard = RepoGuard::new(&repo).await?;
+    let stash = repo.push_stash(&workspace, message).await?;
+    print!("Stashed workspace changes: ");
+    print_stash(0, &stash);
+    drop(guard);
+    Ok(())
+}
+
+pub async fn stash_pop(
+    index: usize,
+    workspace: Option<String>,
+    root: Option<String>,
+) -> Result<(), WsvcError> {
+    let (repo, workspace) = open_workspace(workspace, root).await?;
+    let guard = RepoGuard::new(&repo).await?;
+    let stash = repo.pop_stash(&workspace, index).await?;
+    print!("Restored and dropped ");
+    print_stash(index, &stash);
+    drop(guard);
+    Ok(())
+}
+
+pub async fn stash_list(root: Option<String>) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let repo = Repository::open_read_only(root.unwrap_or(pwd)).await?;
+    for (index, stash) in repo.get_stashes().await?.iter().enumerate() {
+        print_stash(index, stash);
+    }
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use std::{io::Write, path::PathBuf};
+
+use colored::Colorize;
+use wsvc::{
+    fs::{RepositoryBuilder, WsvcFsError},
+    WsvcError,
+};
+
+use super::{config::WsvcConfig, detached_notice, diff::print_change};
+
+/// show files of the workspace changed from the HEAD record.
+pub async fn status(
+    workspace: Option<String>,
+    root: Option<String>,
+    json: bool,
+    porcelain: bool,
+    nul: bool,
+    find_renames: Option<u8>,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let workspace = PathBuf::from(workspace.unwrap_or(pwd.clone()));
+    let root = root.unwrap_or(pwd);
+    let mut repo = RepositoryBuilder::new(root).read_only(true).open().await?;
+    let config = WsvcConfig::load(Some(&repo)).await?;
+    config.configure(&mut repo);
+    let repo = repo.to_read_only();
+    let mut status = repo.status(&workspace).await?;
+    if let Some(threshold) = find_renames {
+        status.changes = repo
+            .find_similar_renames(status.changes, threshold, Some(&workspace))
+            .await?;
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(());
+    }
+    if porcelain {
+        let mut stdout = std::io::stdout().lock();
+        for change in &status.changes {
+            stdout
+                .write_all(change.porcelain(nul).as_bytes())
+                .map_err(WsvcFsError::Os)?;
+        }
+        return Ok(());
+    }
+    if let Some(branch) = repo.read_head().await?.branch() {
+        println!("On branch {}", branch.bright_yellow());
+    }
+    match &status.head {
+        Some(head) => {
+            let hash = head.0.to_hex().to_string();
+            println!(
+                "On record {} ({})",
+                hash[0..6].green().bold(),
+                repo.describe_record(head).await?
+            );
+        }
+        None => println!("No record yet"),
+    }
+    if let Some(notice) = detached_notice(&repo.head_state().await?) {
+        println!("{} {}", "[!]".bright_yellow(), notice);
+    }
+    if let Some(state) = &status.operation {
+        println!("{} {}", "[!]".bright_red(), state.notice());
+    }
+    if status.is_clean() {
+        println!("Nothing changed, the workspace matches HEAD.");
+        return Ok(());
+    }
+    println!("Changes not recorded:");
+    for change in &status.changes {
+        print_change(change);
+    }
+    let index = repo.read_index().await?;
+    if !index.is_empty() {
+        println!("Staged for `wsvc commit --staged`:");
+        for path in &index.paths {
+            println!("    {}", path.bright_green());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use colored::Colorize;
+use wsvc::{
+    fs::{RepoGuard, WsvcFsError},
+    model::Repository,
+    tag::TagAnnotation,
+    WsvcError,
+};
+
+use super::{config::WsvcConfig, resolve_record};
+
+pub async fn tag(
+    root: Option<String>,
+    name: Option<String>,
+    hash: Option<String>,
+    force: bool,
+    message: Option<String>,
+    author: Option<String>,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    let name = match name {
+        Some(name) => name,
+        None => {
+            let repo = Repository::open_read_only(root).await?;
+            for tag in repo.get_tags().await? {
+                let hash = tag.record.0.to_hex().to_string();
+                match repo.read_tag_annotation(&tag.name).await? {
+                    Some(annotation) => println!(
+                        "{} -> {} {}",
+                        tag.name.bright_yellow().bold(),
+                        &hash[0..6],
+                        annotation
+                            .message
+                            .lines()
+                            .next()
+                            .unwrap_or_default()
+                            .dimmed()
+                    ),
+                    None => println!("{} -> {}", tag.name.bright_yellow().bold(), &hash[0..6]),
+                }
+            }
+            return Ok(());
+        }
+    };
+    let repo = Repository::try_open(root).await?;
+    let guard = RepoGuard::new(&repo).await?;
+    let record = resolve_record(&repo.to_read_only(), hash).await?;
+    let tag = match message {
+        Some(message) => {
+            let author = author
+                .or(WsvcConfig::load(Some(&repo)).await?.commit.author)
+                .ok_or(WsvcError::LackOfConfig(
+                    "commit.author".to_owned(),
+                    "use `--author` or `wsvc config set commit.author <name>`".to_owned(),
+                ))?;
+            let annotation = TagAnnotation {
+                author,
+                date: chrono::Utc::now(),
+                message,
+            };
+            repo.create_annotated_tag(&name, &record.hash, force, &annotation)
+                .await?
+        }
+        None => repo.create_tag(&name, &record.hash, force).await?,
+    };
+    let hash = record.hash.0.to_hex().to_string();
+    println!(
+        "Tagged record: {} ({}) as {}",
+        hash[0..6].green().bold(),
+        hash,
+        tag.name.bright_yellow()
+    );
+    drop(guard);
+    Ok(())
+}
+
+pub async fn describe(root: Option<String>, hash: Option<String>) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    let repo = Repository::open_read_only(root).await?;
+    let record = resolve_record(&repo, hash).await?;
+    println!("{}", repo.describe_record(&record.hash).await?);
+    Ok(())
+}
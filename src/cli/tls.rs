@@ -0,0 +1,75 @@
+use std::{sync::Arc, time::SystemTime};
+
+use ws_rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    Certificate, ClientConfig, RootCertStore, ServerName,
+};
+use wsvc::{fs::WsvcFsError, WsvcError};
+
+use super::config::TlsConfig;
+
+/// `PinVerifier` accepts only the certificate with the pinned fingerprint, so self-signed
+/// servers can be trusted without a ca.
+struct PinVerifier {
+    fingerprint: String,
+}
+
+impl ServerCertVerifier for PinVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, ws_rustls::Error> {
+        let fingerprint = blake3::hash(&end_entity.0).to_hex();
+        if fingerprint.as_str() == self.fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(ws_rustls::Error::General(format!(
+                "certificate fingerprint {} does not match the pinned one",
+                fingerprint
+            )))
+        }
+    }
+}
+
+impl TlsConfig {
+    /// the tls client config to reach `host`, `None` if nothing is configured for it and
+    /// the default one should be used.
+    pub fn client_config(&self, host: &str) -> Result<Option<Arc<ClientConfig>>, WsvcError> {
+        let builder = ClientConfig::builder().with_safe_defaults();
+        if let Some(fingerprint) = self.pin.get(host) {
+            let verifier = PinVerifier {
+                fingerprint: fingerprint.to_lowercase(),
+            };
+            return Ok(Some(Arc::new(
+                builder
+                    .with_custom_certificate_verifier(Arc::new(verifier))
+                    .with_no_client_auth(),
+            )));
+        }
+        let ca_file = match &self.ca_file {
+            Some(ca_file) => ca_file,
+            None => return Ok(None),
+        };
+        let mut roots = RootCertStore::empty();
+        for cert in rustls_native_certs::load_native_certs().map_err(WsvcFsError::Os)? {
+            roots.add(&Certificate(cert.0)).ok();
+        }
+        let pem = std::fs::read(ca_file).map_err(WsvcFsError::Os)?;
+        let certs = rustls_pemfile::certs(&mut pem.as_slice()).map_err(WsvcFsError::Os)?;
+        let (added, _) = roots.add_parsable_certificates(&certs);
+        if added == 0 {
+            return Err(WsvcError::BadUsage(format!(
+                "no certificate found in tls.ca_file: {}",
+                ca_file.display()
+            )));
+        }
+        Ok(Some(Arc::new(
+            builder.with_root_certificates(roots).with_no_client_auth(),
+        )))
+    }
+}
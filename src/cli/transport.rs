@@ -1,21 +1,81 @@
-use std::path::Path;
+use std::{collections::HashMap, io::SeekFrom, path::Path};
 
 use colored::Colorize;
-use futures::{SinkExt, StreamExt};
+use futures::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
+use nanoid::nanoid;
 use serde::{Deserialize, Serialize};
 use tokio::{
-    fs::{create_dir_all, rename, write, File},
-    io::{AsyncReadExt, AsyncWriteExt},
+    fs::{create_dir_all, remove_dir_all, File},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
     net::TcpStream,
 };
-use tokio_tungstenite::{self, tungstenite, MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::{
+    tungstenite::{
+        client::IntoClientRequest,
+        handshake::client::{Request, Response},
+    },
+    Connector, MaybeTlsStream, WebSocketStream,
+};
 use wsvc::{
-    fs::{RepoGuard, WsvcFsError},
-    model::{Blob, Record, Repository, Tree},
+    attest::Attestation,
+    auth::{AuthResult, SyncCredential},
+    branch::{branch_ref, Branch, HeadRef, SyncBranch, BRANCHES_DIR},
+    codec::{CodecStream, TransportCodec, TransportDict, ZSTD_CODEC},
+    dial::{self, AddrFamily},
+    events::EventKind,
+    frame::{
+        decode_size_header, encode_size_header, EncryptedStream, FrameStream, FILE_MAGIC,
+        PACKET_MAGIC, REFETCH_PROTOCOL,
+    },
+    fs::{corrupt_objects, RepoGuard, WsvcFsError},
+    identity::{IdentityCheck, SyncIdentity},
+    model::{Blob, ObjectId, Record, Repository, Tree},
+    note::Note,
+    pack::{BlobTransfer, BOOTSTRAP_INDEX, BOOTSTRAP_PACK},
+    refspec::{RefSpec, RefSpecs},
+    resume::{
+        decode_resume_frame, encode_resume_frame, open_received, partial_files, resume_offset,
+        PartialFile, RESUME_PROTOCOL,
+    },
+    state::Operation,
+    tag::{tag_ref, SyncTag, TAGS_DIR},
+    walk::RecordGraph,
     WsvcError,
 };
 
+use super::{
+    config::{WsvcConfig, ORIGIN_REMOTE},
+    resolve_record,
+};
+
+/// `Lease` stand for diverged branches pushed anyway by `--force-with-lease`, replacing
+/// the branch of the remote only while it is at `expected`, or at the record seen by the
+/// sync if not set.
+#[derive(Clone, Debug)]
+pub(super) struct Lease {
+    /// the leased branch, all pushed branches if not set.
+    pub branch: Option<String>,
+    pub expected: Option<ObjectId>,
+}
+
+/// `SyncRefs` stand for the refs a sync shares and the branches it may force.
+#[derive(Clone, Debug, Default)]
+pub(super) struct SyncRefs {
+    pub specs: RefSpecs,
+    pub leases: Vec<Lease>,
+}
+
+impl SyncRefs {
+    /// the lease of branch `name`, a lease of the branch wins over one of all branches.
+    fn lease(&self, name: &str) -> Option<&Lease> {
+        self.leases
+            .iter()
+            .find(|l| l.branch.as_deref() == Some(name))
+            .or_else(|| self.leases.iter().find(|l| l.branch.is_none()))
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct RecordWithState {
     pub record: Record,
@@ -37,60 +97,62 @@ pub struct BlobWithState {
     pub state: i32,
 }
 
-async fn send_data(
-    ws: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
-    data: Vec<u8>,
-) -> Result<(), WsvcError> {
-    let mut header_buf = [0x33u8, 0x07u8, 0u8, 0u8, 0u8, 0u8];
-    let size = data.len();
-    header_buf[2] = (size >> 24) as u8;
-    header_buf[3] = (size >> 16) as u8;
-    header_buf[4] = (size >> 8) as u8;
-    header_buf[5] = size as u8;
-    ws.send(header_buf[..].into()).await?;
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SyncReport {
+    pub accepted: bool,
+    pub violations: Vec<String>,
+}
+
+async fn send_data(ws: &mut impl FrameStream, data: Vec<u8>) -> Result<(), WsvcError> {
+    let header = encode_size_header(PACKET_MAGIC, data.len() as u64, ws.protocol())?;
+    ws.send_frame(header).await?;
     // split data into 16384 bytes
     let mut offset = 0;
     while offset < data.len() {
         let end = offset + 16384;
         let end = if end > data.len() { data.len() } else { end };
-        ws.send(data[offset..end].into()).await?;
+        ws.send_frame(data[offset..end].to_vec()).await?;
         offset = end;
     }
     Ok(())
 }
 
-async fn recv_data(
-    ws: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
-) -> Result<Vec<u8>, WsvcError> {
-    if let Some(Ok(tungstenite::Message::Binary(msg))) = ws.next().await {
-        let mut header_buf = [0u8; 6];
-        header_buf.copy_from_slice(&msg[..6]);
-        if header_buf[0] != 0x33 || header_buf[1] != 0x07 {
-            return Err(WsvcError::DataError("invalid packet header".to_owned()));
-        }
-        let size = ((header_buf[2] as usize) << 24)
-            + ((header_buf[3] as usize) << 16)
-            + ((header_buf[4] as usize) << 8)
-            + (header_buf[5] as usize);
-        let mut data = Vec::with_capacity(size);
-        data.extend_from_slice(&msg[6..]);
-        let mut offset = data.len();
-        while offset < size {
-            if let Some(Ok(tungstenite::Message::Binary(msg))) = ws.next().await {
-                data.extend_from_slice(&msg);
-                offset = data.len();
-            }
-        }
-        Ok(data)
-    } else {
-        Err(WsvcError::DataError("invalid packet header".to_owned()))
+async fn recv_frame(ws: &mut impl FrameStream, what: &str) -> Result<Vec<u8>, WsvcError> {
+    ws.recv_frame()
+        .await?
+        .ok_or(WsvcError::DataError(format!("invalid {}: none", what)))
+}
+
+/// largest packet accepted from the remote, a packet holds metadata like the records and
+/// trees of a sync, never file contents.
+const MAX_PACKET_SIZE: u64 = 1 << 30;
+
+async fn recv_data(ws: &mut impl FrameStream) -> Result<Vec<u8>, WsvcError> {
+    let msg = recv_frame(ws, "packet header").await?;
+    let (size, rest) = decode_size_header(&msg, PACKET_MAGIC, ws.protocol())
+        .ok_or(WsvcError::DataError("invalid packet header".to_owned()))?;
+    if size > MAX_PACKET_SIZE {
+        return Err(WsvcError::DataError(format!(
+            "packet of {} bytes exceeds the limit of {} bytes",
+            size, MAX_PACKET_SIZE
+        )));
+    }
+    let size = size as usize;
+    // the size is claimed by the remote, memory grows with the data actually received.
+    let mut data = Vec::with_capacity(size.min(1 << 20));
+    data.extend_from_slice(rest);
+    while data.len() < size {
+        data.extend_from_slice(&recv_frame(ws, "packet data").await?);
     }
+    Ok(data)
 }
 
+/// send `file` named `file_name` from `offset`, the bytes the receiver already has.
 async fn send_file(
-    ws: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+    ws: &mut impl FrameStream,
     file_name: &str,
     mut file: File,
+    offset: u64,
 ) -> Result<(), WsvcError> {
     // file name packet header: 0x09 0x28 [size], 9.28 is Kamisato Ayaka's birthday
     let mut header_buf = [0x09u8, 0x28u8, 0u8, 0u8];
@@ -100,130 +162,215 @@ async fn send_file(
     }
     header_buf[2] = (file_name_size >> 8) as u8;
     header_buf[3] = file_name_size as u8;
-    ws.send(header_buf[..].into()).await?;
-    let mut file_header_buf = [0x07u8, 0x15u8, 0u8, 0u8, 0u8, 0u8];
+    ws.send_frame(header_buf.to_vec()).await?;
+    ws.send_frame(file_name.as_bytes().to_vec()).await?;
     let mut buf = [0u8; 16384];
     let size = file
         .metadata()
         .await
         .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?
-        .len() as usize;
-    file_header_buf[2] = (size >> 24) as u8;
-    file_header_buf[3] = (size >> 16) as u8;
-    file_header_buf[4] = (size >> 8) as u8;
-    file_header_buf[5] = size as u8;
-    ws.send(file_header_buf[..].into()).await?;
-    let mut offset = 0;
+        .len();
+    ws.send_frame(encode_size_header(FILE_MAGIC, size, ws.protocol())?)
+        .await?;
+    let mut offset = offset.min(size);
+    if ws.protocol() >= RESUME_PROTOCOL {
+        ws.send_frame(encode_resume_frame(offset)).await?;
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
+    }
     while offset != size {
         let read_size = file
             .read(&mut buf)
             .await
             .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
-        ws.send(buf[..read_size].into()).await?;
-        offset += read_size;
+        if read_size == 0 {
+            return Err(WsvcError::DataError(format!(
+                "file {} changed while sending",
+                file_name
+            )));
+        }
+        ws.send_frame(buf[..read_size].to_vec()).await?;
+        offset += read_size as u64;
     }
     Ok(())
 }
 
 async fn recv_file(
-    ws: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+    ws: &mut impl FrameStream,
     storage_dir: impl AsRef<Path>,
 ) -> Result<(), WsvcError> {
-    let file_name_header = ws
-        .next()
-        .await
-        .ok_or(WsvcError::DataError(format!(
-            "invalid file name header: {}",
-            "none"
-        )))?
-        .map_err(WsvcError::NetworkError)?;
-    let mut file_name_header_buf = [0u8; 4];
-    if let tungstenite::Message::Binary(msg) = file_name_header {
-        file_name_header_buf.copy_from_slice(&msg[..4]);
-    } else {
+    let file_name_header = recv_frame(ws, "file name header").await?;
+    if file_name_header.len() < 4 || file_name_header[0] != 0x09 || file_name_header[1] != 0x28 {
         return Err(WsvcError::DataError(format!(
             "invalid file name header: {:?}",
             file_name_header
         )));
     }
-    if file_name_header_buf[0] != 0x09 || file_name_header_buf[1] != 0x28 {
-        return Err(WsvcError::DataError(format!(
-            "invalid file name header: {:?}",
-            file_name_header_buf
-        )));
-    }
-    let file_name_size =
-        ((file_name_header_buf[2] as usize) << 8) + (file_name_header_buf[3] as usize);
-    let file_name = ws
-        .next()
-        .await
-        .ok_or(WsvcError::DataError(format!(
-            "invalid file name: {}",
-            "none"
-        )))?
-        .map_err(WsvcError::NetworkError)?;
-    let file_name = if let tungstenite::Message::Binary(msg) = file_name {
-        String::from_utf8(msg[..file_name_size].to_vec())
-            .map_err(|err| WsvcError::DataError(err.to_string()))?
-    } else {
-        return Err(WsvcError::DataError(format!(
-            "invalid file name: {:?}",
-            file_name
-        )));
-    };
+    let file_name_size = ((file_name_header[2] as usize) << 8) + (file_name_header[3] as usize);
+    let file_name = recv_frame(ws, "file name").await?;
+    let file_name = String::from_utf8(file_name[..file_name_size.min(file_name.len())].to_vec())
+        .map_err(|err| WsvcError::DataError(err.to_string()))?;
     let file_path = storage_dir.as_ref().join(file_name);
-    let file_header = ws
-        .next()
-        .await
-        .ok_or(WsvcError::DataError("invalid file header".to_owned()))?
-        .map_err(WsvcError::NetworkError)?;
-    let mut file_header_buf = [0u8; 6];
-    if let tungstenite::Message::Binary(msg) = file_header {
-        file_header_buf.copy_from_slice(&msg[..6]);
-    } else {
-        return Err(WsvcError::DataError("invalid file header".to_owned()));
-    }
-    if file_header_buf[0] != 0x07 || file_header_buf[1] != 0x15 {
-        return Err(WsvcError::DataError("invalid file header".to_owned()));
-    }
-    let size = ((file_header_buf[2] as usize) << 24)
-        + ((file_header_buf[3] as usize) << 16)
-        + ((file_header_buf[4] as usize) << 8)
-        + (file_header_buf[5] as usize);
-    let mut file = File::create(&file_path)
+    let file_header = recv_frame(ws, "file header").await?;
+    let (size, _) = decode_size_header(&file_header, FILE_MAGIC, ws.protocol())
+        .ok_or(WsvcError::DataError("invalid file header".to_owned()))?;
+    // the sender continues from the bytes we already have since protocol version 3.
+    let offset = match ws.protocol() >= RESUME_PROTOCOL {
+        true => decode_resume_frame(&recv_frame(ws, "resume frame").await?)
+            .ok_or(WsvcError::DataError("invalid resume frame".to_owned()))?,
+        false => 0,
+    };
+    let mut file = open_received(&file_path, offset)
         .await
-        .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
-    let mut offset = 0;
-    while offset != size {
-        let data = ws
-            .next()
+        .map_err(WsvcError::FsError)?;
+    let mut offset = offset;
+    while offset < size {
+        let data = recv_frame(ws, "file data").await?;
+        offset += data.len() as u64;
+        file.write_all(&data)
             .await
-            .ok_or(WsvcError::DataError("invalid file data".to_owned()))?
-            .map_err(WsvcError::NetworkError)?;
-        if let tungstenite::Message::Binary(data) = data {
-            offset += data.len();
-            file.write(&data)
-                .await
-                .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
-        } else {
-            return Err(WsvcError::DataError("invalid file data".to_owned()));
-        }
+            .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
     }
 
     Ok(())
 }
 
-async fn sync_records(
-    repo: &Repository,
-    ws: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
-) -> Result<(Vec<Record>, Vec<Record>), WsvcError> {
-    println!("{} {}", "[+]".bright_green(), "Sync records...".bold());
+/// print a step of the sync, unless `quiet`.
+fn step(quiet: bool, message: &str) {
+    if !quiet {
+        println!("{} {}", "[+]".bright_green(), message.bold());
+    }
+}
+
+/// a spinner of a sync step, hidden if `quiet`.
+fn spinner(quiet: bool) -> ProgressBar {
+    if quiet {
+        return ProgressBar::hidden();
+    }
     let pb = ProgressBar::new_spinner();
     pb.set_style(
         ProgressStyle::with_template("{spinner:.bold.green}    {wide_msg}")
             .unwrap()
             .tick_chars("* "),
     );
+    pb
+}
+
+/// a progress bar of `len` items in `color`, hidden if `quiet`.
+fn bar(quiet: bool, len: usize, color: &str) -> ProgressBar {
+    if quiet {
+        return ProgressBar::hidden();
+    }
+    let pb = ProgressBar::new(len as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template(&format!(
+                "[{{elapsed_precise}}] {{bar:40.{}}} {{pos:>7}}/{{len:7}} {{msg}}",
+                color
+            ))
+            .unwrap()
+            .progress_chars("=>."),
+    );
+    pb
+}
+
+/// exchange repository ids with the server, fails if it is another repository.
+/// exchange identities with the server and choose a transport codec among those it
+/// offers, receiving its transport dictionary if it has one the repository lacks. returns
+/// the codec and the protocol version of the sync.
+/// exchange identities with the server, authenticating with `credential` if the server
+/// requires auth, and negotiate the codec and protocol version of the next rounds. the
+/// last of the returned is whether the server only lets the client read.
+async fn sync_identity(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+    credential: Option<&SyncCredential>,
+) -> Result<(Option<TransportCodec>, u32, bool), WsvcError> {
+    let mut local = repo.sync_identity(false).await?;
+    let remote: SyncIdentity = serde_json::from_slice(&recv_data(ws).await?)?;
+    if remote.auth {
+        // public repositories may be read without a credential.
+        local.credential =
+            match (credential, remote.public) {
+                (Some(credential), _) => Some(credential.clone()),
+                (None, true) => None,
+                (None, false) => return Err(WsvcError::LackOfConfig(
+                    "a token or auth.account and auth.passwd".to_owned(),
+                    "the remote requires auth, run `wsvc login` to keep a token in the os keyring"
+                        .to_owned(),
+                )),
+            };
+    }
+    let zstd = remote.codecs.iter().any(|codec| codec == ZSTD_CODEC);
+    let mut dict = None;
+    if zstd {
+        local.codec = Some(ZSTD_CODEC.to_owned());
+        if let Some(id) = &remote.dict {
+            dict = repo.transport_dict_by_id(id).await?;
+            local.dict = dict.as_ref().map(|dict| dict.id.clone());
+        }
+    }
+    send_data(ws, serde_json::to_string(&local)?.into_bytes()).await?;
+    let mut read_only = false;
+    if remote.auth {
+        let result: AuthResult = serde_json::from_slice(&recv_data(ws).await?)?;
+        if !result.accepted {
+            return Err(WsvcError::RepoError(format!(
+                "authentication failed: {}",
+                result.message.unwrap_or_default()
+            )));
+        }
+        read_only = result.read_only;
+    }
+    match local.check(&remote) {
+        IdentityCheck::Same => {}
+        IdentityCheck::Adopt(id) => {
+            if repo.check_writable().is_ok() {
+                repo.adopt_id(&id).await?;
+            }
+        }
+        IdentityCheck::Mismatch { local, remote } => {
+            return Err(WsvcError::RepoError(format!(
+                "remote is another repository, its id is {} but the local id is {}",
+                remote, local
+            )))
+        }
+    }
+    let protocol = local.protocol_with(&remote);
+    if !zstd {
+        return Ok((None, protocol, read_only));
+    }
+    let Some(id) = remote.dict else {
+        return Ok((Some(TransportCodec::Zstd), protocol, read_only));
+    };
+    let dict = match dict {
+        Some(dict) => dict,
+        None => {
+            let dict = TransportDict::new(recv_data(ws).await?);
+            if dict.id != id {
+                return Err(WsvcError::DataError(
+                    "transport dictionary does not match its id".to_owned(),
+                ));
+            }
+            if repo.check_writable().is_ok() {
+                repo.store_remote_dict(&dict).await?;
+            }
+            dict
+        }
+    };
+    Ok((Some(TransportCodec::ZstdDict(dict)), protocol, read_only))
+}
+
+/// receive the records of the server, and give the local ones it lacks if `push`.
+async fn sync_records(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+    push: bool,
+    quiet: bool,
+) -> Result<(Vec<Record>, Vec<Record>), WsvcError> {
+    step(quiet, "Sync records...");
+    let pb = spinner(quiet);
     pb.set_message("Receiving server records...");
     let server_records = recv_data(ws).await?;
     let server_records: Vec<Record> = serde_json::from_slice(&server_records)?;
@@ -237,7 +384,7 @@ async fn sync_records(
         .collect::<Vec<_>>();
     let will_give_records = local_records
         .iter()
-        .filter(|r| !server_records.contains(r))
+        .filter(|r| push && !server_records.contains(r))
         .cloned()
         .collect::<Vec<_>>();
     let mut response_records: Vec<RecordWithState> = wanted_records
@@ -265,15 +412,12 @@ async fn sync_records(
 
 async fn sync_trees(
     repo: &Repository,
-    ws: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+    ws: &mut impl FrameStream,
     given_records: &[Record],
+    quiet: bool,
 ) -> Result<(Vec<Tree>, Vec<Tree>), WsvcError> {
-    println!("{} {}", "[+]".bright_green(), "Sync trees...".bold());
-    let tick_style = ProgressStyle::with_template("{spinner:.bold.green}    {wide_msg}")
-        .unwrap()
-        .tick_chars("* ");
-    let pb = ProgressBar::new_spinner();
-    pb.set_style(tick_style.clone());
+    step(quiet, "Sync trees...");
+    let pb = spinner(quiet);
     pb.set_message("Receiving server trees...");
     let server_trees = recv_data(ws).await?;
     pb.set_message(format!(
@@ -334,16 +478,12 @@ async fn sync_trees(
 
 async fn sync_blobs_meta(
     repo: &Repository,
-    ws: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+    ws: &mut impl FrameStream,
     given_trees: &[Tree],
+    quiet: bool,
 ) -> Result<(Vec<Blob>, Vec<Blob>), WsvcError> {
-    println!("{} {}", "[+]".bright_green(), "Sync blobs meta...".bold());
-    let pb = ProgressBar::new_spinner();
-    pb.set_style(
-        ProgressStyle::with_template("{spinner:.bold.green}    {wide_msg}")
-            .unwrap()
-            .tick_chars("* "),
-    );
+    step(quiet, "Sync blobs meta...");
+    let pb = spinner(quiet);
     pb.set_message("Receiving server blobs...");
     let server_blobs = recv_data(ws).await?;
     let server_blobs: Vec<Blob> = serde_json::from_slice(&server_blobs)?;
@@ -367,15 +507,26 @@ async fn sync_blobs_meta(
         );
     }
     pb.set_message("Differing blobs...");
-    let mut wanted_blobs = Vec::new();
-    for blob in &server_blobs {
-        if !repo.blob_exists(&blob.hash).await? {
-            wanted_blobs.push(blob.clone());
-        }
-    }
-    let mut will_give_blobs = Vec::new();
+    // blobs are transferred by hash, the same content may appear in many trees.
+    let server_blob_ids = server_blobs
+        .iter()
+        .map(|b| b.hash.clone())
+        .collect::<Vec<_>>();
+    // blobless repositories fetch blobs when they are read instead.
+    let wanted_blobs = match repo.is_blobless() {
+        true => vec![],
+        false => repo
+            .missing_objects(&server_blob_ids)
+            .await?
+            .into_iter()
+            .filter_map(|id| server_blobs.iter().find(|b| b.hash == id).cloned())
+            .collect::<Vec<Blob>>(),
+    };
+    let mut will_give_blobs: Vec<Blob> = Vec::new();
     for blob in local_blobs {
-        if !server_blobs.contains(&blob) {
+        if !server_blobs.iter().any(|b| b.hash == blob.hash)
+            && !will_give_blobs.iter().any(|b| b.hash == blob.hash)
+        {
             will_give_blobs.push(blob);
         }
     }
@@ -404,168 +555,1318 @@ async fn sync_blobs_meta(
 
 async fn sync_blobs(
     repo: &Repository,
-    ws: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+    ws: &mut impl FrameStream,
     wanted_blobs: &[Blob],
     will_given_blobs: &[Blob],
+    quiet: bool,
 ) -> Result<(), WsvcError> {
-    println!("{} {}", "[+]".bright_green(), "Sync blobs...".bold());
-    let pb = ProgressBar::new(wanted_blobs.len() as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("[{elapsed_precise}] {bar:40.yellow} {pos:>7}/{len:7} {msg}")
-            .unwrap()
-            .progress_chars("=>."),
-    );
-    let objects_dir = repo.objects_dir().await?;
-    let temp_objects_dir = repo.temp_dir().await?.join("objects");
-    if !temp_objects_dir.exists() {
-        create_dir_all(&temp_objects_dir)
-            .await
-            .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
+    step(quiet, "Sync blobs...");
+    // each sync receives into its own dir, syncs with many remotes at once may receive
+    // the same blobs.
+    let temp_objects_dir = repo
+        .temp_dir()
+        .await?
+        .join(format!("objects-{}", nanoid!()));
+    create_dir_all(&temp_objects_dir)
+        .await
+        .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
+    let result = transfer_blobs(
+        repo,
+        ws,
+        wanted_blobs,
+        will_given_blobs,
+        &temp_objects_dir,
+        quiet,
+    )
+    .await;
+    // what a failed sync received is continued by the next one.
+    if result.is_err() {
+        repo.keep_partial_files(&temp_objects_dir).await.ok();
+    }
+    remove_dir_all(&temp_objects_dir).await.ok();
+    result
+}
+
+/// receive `wanted_blobs` into `temp_objects_dir` and send `will_given_blobs`.
+async fn transfer_blobs(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+    wanted_blobs: &[Blob],
+    will_given_blobs: &[Blob],
+    temp_objects_dir: &Path,
+    quiet: bool,
+) -> Result<(), WsvcError> {
+    let pb = bar(quiet, wanted_blobs.len(), "yellow");
+    let transfer: BlobTransfer = serde_json::from_slice(&recv_data(ws).await?)?;
+    // a pack and its index, or each blob.
+    let files = match transfer.pack {
+        true => vec![BOOTSTRAP_PACK.to_owned(), BOOTSTRAP_INDEX.to_owned()],
+        false => wanted_blobs.iter().map(|b| b.hash.0.to_string()).collect(),
+    };
+    pb.set_message(match transfer.pack {
+        true => "Receiving pack...",
+        false => "Receiving...",
+    });
+    // files left by a sync that died mid-way are continued since protocol version 3.
+    if ws.protocol() >= RESUME_PROTOCOL {
+        repo.prune_partial_files().await?;
+        repo.claim_partial_files(temp_objects_dir, &files).await?;
+        let partials = partial_files(temp_objects_dir, &files).await?;
+        if !partials.is_empty() {
+            pb.set_message(format!("Receiving, resuming {} files...", partials.len()));
+        }
+        send_data(ws, serde_json::to_string(&partials)?.into_bytes()).await?;
     }
-    pb.set_message("Receiving...");
     pb.set_position(0);
-    for _ in 0..wanted_blobs.len() {
-        recv_file(ws, &temp_objects_dir).await?;
-        pb.inc(1);
+    if ws.recv_files(files.len(), temp_objects_dir).await? {
+        pb.set_position(wanted_blobs.len() as u64);
+    } else {
+        for _ in 0..files.len() {
+            recv_file(ws, temp_objects_dir).await?;
+            if !transfer.pack {
+                pb.inc(1);
+            }
+        }
+        pb.set_position(wanted_blobs.len() as u64);
     }
     pb.set_message("Verifing...");
-    pb.set_position(0);
-    for i in wanted_blobs {
-        let object_file = temp_objects_dir.join(i.hash.0.to_string());
-        if !object_file.exists() {
+    for name in &files {
+        if !temp_objects_dir.join(name).exists() {
             return Err(WsvcError::DataError(format!(
-                "blob {} not synced from remote",
-                i.hash.0.to_string()
+                "{} not synced from remote",
+                name
             )));
         }
-        pb.inc(1);
+    }
+    // a pack is verified when it is imported.
+    let mut corrupt = match transfer.pack {
+        true => vec![],
+        false => corrupt_objects(temp_objects_dir, wanted_blobs).await?,
+    };
+    if ws.protocol() >= REFETCH_PROTOCOL {
+        if !corrupt.is_empty() {
+            pb.set_message(format!(
+                "Receiving {} corrupt files again...",
+                corrupt.len()
+            ));
+        }
+        send_data(ws, serde_json::to_string(&corrupt)?.into_bytes()).await?;
+        for _ in 0..corrupt.len() {
+            recv_file(ws, temp_objects_dir).await?;
+        }
+        let refetched = wanted_blobs
+            .iter()
+            .filter(|b| corrupt.contains(&b.hash.0.to_string()))
+            .cloned()
+            .collect::<Vec<_>>();
+        corrupt = corrupt_objects(temp_objects_dir, &refetched).await?;
+    }
+    if !corrupt.is_empty() {
+        return Err(WsvcError::DataError(format!(
+            "corrupt blob files from remote: {}",
+            corrupt.join(", ")
+        )));
     }
     pb.finish_with_message("Done.");
-    let pb = ProgressBar::new(will_given_blobs.len() as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("[{elapsed_precise}] {bar:40.blue} {pos:>7}/{len:7} {msg}")
-            .unwrap()
-            .progress_chars("=>."),
-    );
+    let pb = bar(quiet, will_given_blobs.len(), "blue");
     pb.set_message("Sending...");
     pb.set_position(0);
+    let partials: HashMap<String, PartialFile> = match ws.protocol() >= RESUME_PROTOCOL {
+        true => serde_json::from_slice::<Vec<PartialFile>>(&recv_data(ws).await?)?
+            .into_iter()
+            .map(|partial| (partial.name.clone(), partial))
+            .collect(),
+        false => HashMap::new(),
+    };
+    let mut files = vec![];
     for blob in will_given_blobs {
-        let object_file = objects_dir.join(blob.hash.0.to_string());
-        let file = File::open(object_file)
-            .await
-            .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
-        send_file(ws, &blob.hash.0.to_string(), file).await?;
-        pb.inc(1);
+        files.push((
+            blob.hash.0.to_string(),
+            repo.export_object(&blob.hash).await?,
+        ));
+    }
+    if ws.send_files(&files).await? {
+        pb.set_position(files.len() as u64);
+    } else {
+        for (name, path) in &files {
+            let file = File::open(path)
+                .await
+                .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
+            let offset = resume_offset(path, partials.get(name)).await?;
+            send_file(ws, name, file, offset).await?;
+            pb.inc(1);
+        }
+    }
+    // files the server found corrupt are sent again whole, once.
+    if ws.protocol() >= REFETCH_PROTOCOL {
+        let corrupt: Vec<String> = serde_json::from_slice(&recv_data(ws).await?)?;
+        if !corrupt.is_empty() {
+            pb.set_message(format!("Sending {} corrupt files again...", corrupt.len()));
+        }
+        for name in corrupt {
+            let (_, path) = files
+                .iter()
+                .find(|(n, _)| *n == name)
+                .ok_or(WsvcError::DataError(format!("file not sent: {}", name)))?;
+            let file = File::open(path)
+                .await
+                .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
+            send_file(ws, &name, file, 0).await?;
+        }
     }
     pb.finish_with_message("Done.");
-    let pb = ProgressBar::new(wanted_blobs.len() as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("[{elapsed_precise}] {bar:40.green} {pos:>7}/{len:7} {msg}")
-            .unwrap()
-            .progress_chars("=>."),
-    );
+    let pb = bar(quiet, wanted_blobs.len(), "green");
     pb.set_message("Moving...");
-    for i in wanted_blobs {
-        rename(
-            temp_objects_dir.join(i.hash.0.to_string()),
-            objects_dir.join(i.hash.0.to_string()),
+    if transfer.pack {
+        let hashes = wanted_blobs
+            .iter()
+            .map(|b| b.hash.clone())
+            .collect::<Vec<_>>();
+        repo.import_pack(
+            &temp_objects_dir.join(BOOTSTRAP_PACK),
+            &temp_objects_dir.join(BOOTSTRAP_INDEX),
+            &hashes,
         )
-        .await
-        .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
-        pb.inc(1);
+        .await?;
+        pb.set_position(wanted_blobs.len() as u64);
+    } else {
+        for i in wanted_blobs {
+            repo.import_object(&i.hash, &temp_objects_dir.join(i.hash.0.to_string()))
+                .await?;
+            pb.inc(1);
+        }
     }
     pb.finish_with_message("Done.");
     Ok(())
 }
 
-async fn sync_impl(repo: &Repository) -> Result<(), WsvcError> {
-    let origin = repo.read_origin().await?;
+async fn sync_attestations(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+    push: bool,
+    quiet: bool,
+) -> Result<(usize, usize), WsvcError> {
+    step(quiet, "Sync attestations...");
+    let server_attestations = recv_data(ws).await?;
+    let server_attestations: Vec<Attestation> = serde_json::from_slice(&server_attestations)?;
+    let mut wanted = 0;
+    for attestation in &server_attestations {
+        if attestation.calc_hash()? != attestation.hash {
+            return Err(WsvcError::DataError(format!(
+                "attestation hash mismatch: {}",
+                attestation.hash.0
+            )));
+        }
+        if !repo.attestation_exists(&attestation.hash).await? {
+            repo.store_attestation(attestation).await?;
+            wanted += 1;
+        }
+    }
+    let will_give_attestations = repo
+        .get_attestations()
+        .await?
+        .into_iter()
+        .filter(|a| push && !server_attestations.iter().any(|s| s.hash == a.hash))
+        .collect::<Vec<_>>();
+    let packet_body = serde_json::to_string(&will_give_attestations)?;
+    send_data(ws, packet_body.into_bytes()).await?;
+    Ok((wanted, will_give_attestations.len()))
+}
+
+async fn sync_notes(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+    push: bool,
+    quiet: bool,
+) -> Result<(usize, usize), WsvcError> {
+    step(quiet, "Sync notes...");
+    let server_notes = recv_data(ws).await?;
+    let server_notes: Vec<Note> = serde_json::from_slice(&server_notes)?;
+    let mut wanted = 0;
+    for note in &server_notes {
+        if note.calc_hash()? != note.hash {
+            return Err(WsvcError::DataError(format!(
+                "note hash mismatch: {}",
+                note.hash.0
+            )));
+        }
+        if !repo.note_exists(&note.hash).await? {
+            repo.store_note(note).await?;
+            wanted += 1;
+        }
+    }
+    let will_give_notes = repo
+        .get_notes()
+        .await?
+        .into_iter()
+        .filter(|n| push && !server_notes.iter().any(|s| s.hash == n.hash))
+        .collect::<Vec<_>>();
+    let packet_body = serde_json::to_string(&will_give_notes)?;
+    send_data(ws, packet_body.into_bytes()).await?;
+    Ok((wanted, will_give_notes.len()))
+}
+
+/// returns tags of the server chosen by the fetch refspecs to store after records, tags
+/// sent, and tags of the server pointing to other records than the local tags with the
+/// same names.
+async fn sync_tags(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+    refs: &RefSpecs,
+    quiet: bool,
+) -> Result<(Vec<SyncTag>, usize, Vec<SyncTag>), WsvcError> {
+    step(quiet, "Sync tags...");
+    let server_tags: Vec<SyncTag> = serde_json::from_slice(&recv_data(ws).await?)?;
+    let local_tags = repo.get_sync_tags().await?;
+    let fetched_tags = server_tags
+        .iter()
+        .filter(|s| refs.fetches(&tag_ref(&s.tag.name)))
+        .cloned()
+        .collect::<Vec<_>>();
+    let conflicting = fetched_tags
+        .iter()
+        .filter(|s| {
+            local_tags
+                .iter()
+                .any(|l| l.tag.name == s.tag.name && l.tag.record != s.tag.record)
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    let will_give_tags = local_tags
+        .into_iter()
+        .filter(|l| {
+            refs.pushes(&tag_ref(&l.tag.name))
+                && !server_tags.iter().any(|s| s.tag.name == l.tag.name)
+        })
+        .collect::<Vec<_>>();
+    send_data(ws, serde_json::to_string(&will_give_tags)?.into_bytes()).await?;
+    Ok((fetched_tags, will_give_tags.len(), conflicting))
+}
+
+/// returns branches of the server chosen by the fetch refspecs to store after records,
+/// branches sent, and local branches chosen by the push refspecs diverged from the
+/// branches of the server with the same names, with the records of the server.
+///
+/// only branches the server does not have, or has at an ancestor of the local record,
+/// are sent, or leased branches. `wanted_records` are the records received from the
+/// server.
+async fn sync_branches(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+    wanted_records: &[Record],
+    refs: &SyncRefs,
+    quiet: bool,
+) -> Result<(Vec<Branch>, usize, Vec<(Branch, ObjectId)>), WsvcError> {
+    step(quiet, "Sync branches...");
+    let server_branches: Vec<Branch> = serde_json::from_slice(&recv_data(ws).await?)?;
+    let mut records = repo.get_records().await?;
+    records.extend_from_slice(wanted_records);
+    let graph = RecordGraph::new(records);
+    let mut will_give_branches = vec![];
+    let mut diverged = vec![];
+    for branch in repo.get_branches().await? {
+        if !refs.specs.pushes(&branch_ref(&branch.name)) {
+            continue;
+        }
+        let server = server_branches.iter().find(|s| s.name == branch.name);
+        if let Some(lease) = refs.lease(&branch.name) {
+            if lease.expected.is_none() && server.is_some_and(|s| s.record == branch.record) {
+                continue;
+            }
+            let lease = lease.expected.clone().or(server.map(|s| s.record.clone()));
+            will_give_branches.push(SyncBranch { branch, lease });
+            continue;
+        }
+        match server {
+            // the same record or ahead of the local branch.
+            Some(server) if graph.is_ancestor(&branch.record, &server.record) => {}
+            Some(server) if !graph.is_ancestor(&server.record, &branch.record) => {
+                diverged.push((branch, server.record.clone()))
+            }
+            _ => will_give_branches.push(SyncBranch {
+                branch,
+                lease: None,
+            }),
+        }
+    }
+    send_data(ws, serde_json::to_string(&will_give_branches)?.into_bytes()).await?;
+    let fetched_branches = server_branches
+        .into_iter()
+        .filter(|s| refs.specs.fetches(&branch_ref(&s.name)))
+        .collect();
+    Ok((fetched_branches, will_give_branches.len(), diverged))
+}
+
+/// options of connecting to a remote.
+#[derive(clap::Args, Clone, Default)]
+pub struct ConnectOptions {
+    /// passphrase to encrypt frames end to end, e.g. when syncing through a relay
+    #[clap(long)]
+    pub passphrase: Option<String>,
+    /// connect over ipv4 only
+    #[clap(long, action = clap::ArgAction::SetTrue, conflicts_with = "ipv6")]
+    pub ipv4: bool,
+    /// connect over ipv6 only
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    pub ipv6: bool,
+    /// fail at once instead of accessing the network, like `net.offline`
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    pub offline: bool,
+}
+
+impl ConnectOptions {
+    pub fn family(&self) -> AddrFamily {
+        match (self.ipv4, self.ipv6) {
+            (true, _) => AddrFamily::V4,
+            (_, true) => AddrFamily::V6,
+            _ => AddrFamily::Any,
+        }
+    }
+
+    /// fail with `WsvcError::Offline` before reaching `url` if network access is forbidden
+    /// by `--offline`, `net.offline` or `WSVC_OFFLINE`.
+    pub fn ensure_online(&self, config: &WsvcConfig, url: &str) -> Result<(), WsvcError> {
+        if self.offline || config.offline() {
+            return Err(WsvcError::Offline(format!(
+                "{} needs network access, unset --offline, net.offline or {}",
+                url,
+                super::config::OFFLINE_ENV
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// `err` of dialing `host` as `WsvcError::Offline` if the network is down, so commands
+/// fail fast with a distinct exit code instead of trying fallbacks.
+fn dial_error(host: &str, err: std::io::Error) -> WsvcError {
+    match err.kind() {
+        std::io::ErrorKind::NetworkUnreachable
+        | std::io::ErrorKind::NetworkDown
+        | std::io::ErrorKind::HostUnreachable => {
+            WsvcError::Offline(format!("can not reach {}: {}", host, err))
+        }
+        _ => tokio_tungstenite::tungstenite::Error::Io(err).into(),
+    }
+}
+
+/// connect to a websocket url, all addresses of the host are raced with happy eyeballs,
+/// so a broken address family does not hang the sync. `net.resolve` overrides the
+/// address to dial and `tls` settings apply to the tls connector, the tls server name is
+/// still the host in the url.
+async fn connect_ws(
+    url: &str,
+    family: AddrFamily,
+    config: &WsvcConfig,
+) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, WsvcError> {
+    let request = url.into_client_request()?;
+    let stream = dial_ws(&request, family, config).await?;
+    Ok(handshake_ws(request, stream, config).await?.0)
+}
+
+/// the host and port of a websocket request.
+fn ws_host(request: &Request) -> Result<(String, u16), WsvcError> {
+    let host = request
+        .uri()
+        .host()
+        .ok_or(WsvcError::BadUsage(format!(
+            "no host in url: {}",
+            request.uri()
+        )))?
+        .to_owned();
+    let port = match request.uri().port_u16() {
+        Some(port) => port,
+        None if request.uri().scheme_str() == Some("wss") => 443,
+        None => 80,
+    };
+    Ok((host, port))
+}
+
+/// connect the tcp stream of a websocket request.
+pub(super) async fn dial_ws(
+    request: &Request,
+    family: AddrFamily,
+    config: &WsvcConfig,
+) -> Result<TcpStream, WsvcError> {
+    let (host, port) = ws_host(request)?;
+    let dial_host = match config.resolve(&host)? {
+        Some(addr) => addr.to_string(),
+        None => host,
+    };
+    dial::connect_tcp(&dial_host, port, family)
+        .await
+        .map_err(|err| dial_error(&dial_host, err))
+}
+
+/// run the tls and websocket handshakes of a websocket request on `stream`.
+pub(super) async fn handshake_ws(
+    request: Request,
+    stream: TcpStream,
+    config: &WsvcConfig,
+) -> Result<(WebSocketStream<MaybeTlsStream<TcpStream>>, Response), WsvcError> {
+    let (host, _) = ws_host(&request)?;
+    let connector = config
+        .tls
+        .client_config(host.trim_start_matches('[').trim_end_matches(']'))?
+        .map(Connector::Rustls);
+    Ok(tokio_tungstenite::client_async_tls_with_config(request, stream, None, connector).await?)
+}
+
+/// `SyncSummary` stand for what a sync with a remote transferred.
+#[derive(Clone, Debug, Default)]
+pub(super) struct SyncSummary {
+    /// records received from the remote.
+    pub received: Vec<Record>,
+    /// records sent to the remote, empty if the push was rejected.
+    pub sent: Vec<Record>,
+    /// attestations received and sent.
+    pub attestations: (usize, usize),
+    /// notes received and sent.
+    pub notes: (usize, usize),
+    /// tags received and sent.
+    pub tags: (usize, usize),
+    /// tags of the remote pointing to other records than the local tags of their names.
+    pub conflicting_tags: Vec<SyncTag>,
+    /// branches received and sent.
+    pub branches: (usize, usize),
+    /// branches of the remote not received, diverged from the local branches of their
+    /// names.
+    pub diverged_branches: Vec<Branch>,
+    /// local branches not sent, diverged from the branches of the remote of their names,
+    /// with the records of the remote.
+    pub rejected_branches: Vec<(Branch, ObjectId)>,
+}
+
+impl SyncSummary {
+    /// whether nothing was transferred.
+    fn is_empty(&self) -> bool {
+        self.received.is_empty()
+            && self.sent.is_empty()
+            && self.attestations == (0, 0)
+            && self.notes == (0, 0)
+            && self.tags == (0, 0)
+            && self.conflicting_tags.is_empty()
+            && self.branches == (0, 0)
+            && self.diverged_branches.is_empty()
+            && self.rejected_branches.is_empty()
+    }
+
+    /// print the transferred records and counts.
+    fn print(&self) {
+        for (records, arrow) in [
+            (&self.received, "<<".bright_yellow()),
+            (&self.sent, ">>".bright_blue()),
+        ] {
+            for record in records {
+                println!(
+                    "  {} ({}) {}",
+                    arrow,
+                    record.hash.0.to_string()[0..6].dimmed().bold(),
+                    record.message
+                );
+            }
+        }
+        if self.attestations.0 + self.attestations.1 > 0 {
+            println!(
+                "  {} {} attestations received, {} sent",
+                "**".bright_magenta(),
+                self.attestations.0,
+                self.attestations.1
+            );
+        }
+        if self.notes.0 + self.notes.1 > 0 {
+            println!(
+                "  {} {} notes received, {} sent",
+                "##".bright_cyan(),
+                self.notes.0,
+                self.notes.1
+            );
+        }
+        if self.tags.0 + self.tags.1 > 0 {
+            println!(
+                "  {} {} tags received, {} sent",
+                "@@".bright_yellow(),
+                self.tags.0,
+                self.tags.1
+            );
+        }
+        for tag in &self.conflicting_tags {
+            println!(
+                "  {} tag {} points to {} on the remote, the local tag is kept",
+                "!!".bright_red(),
+                tag.tag.name.bright_yellow(),
+                &tag.tag.record.0.to_hex()[0..6]
+            );
+        }
+        if self.branches.0 + self.branches.1 > 0 {
+            println!(
+                "  {} {} branches received, {} sent",
+                "%%".bright_green(),
+                self.branches.0,
+                self.branches.1
+            );
+        }
+        for branch in &self.diverged_branches {
+            println!(
+                "  {} branch {} diverged from the local one, not received",
+                "!!".bright_red(),
+                branch.name.bright_green()
+            );
+        }
+        for (branch, remote) in &self.rejected_branches {
+            println!(
+                "  {} branch {} diverged from {} on the remote, not sent",
+                "!!".bright_red(),
+                branch.name.bright_green(),
+                &remote.0.to_hex()[0..6]
+            );
+        }
+    }
+}
+
+/// the refspecs of the remote at `url` in the config, the remote origin if `url` is
+/// `None`, defaults of `RefSpecs` for a remote not in the config.
+async fn remote_refspecs(repo: &Repository, config: &WsvcConfig, url: Option<&str>) -> RefSpecs {
+    let origin = repo.read_origin().await.ok();
+    let name = match url {
+        Some(url) if origin.as_deref() != Some(url) => config.remote_name(url),
+        _ => Some(ORIGIN_REMOTE.to_owned()),
+    };
+    name.map(|name| config.refspecs(&name)).unwrap_or_default()
+}
+
+/// the refs shared with `url` or the remote origin by its refspecs. without `checkout`,
+/// the branch HEAD is on is not fetched, so HEAD stays at the record checked out.
+async fn remote_sync_refs(
+    repo: &Repository,
+    config: &WsvcConfig,
+    url: Option<&str>,
+    checkout: bool,
+) -> Result<SyncRefs, WsvcError> {
+    let mut specs = remote_refspecs(repo, config, url).await;
+    if let (false, HeadRef::Branch { name, .. }) = (checkout, repo.read_head().await?) {
+        let head = RefSpec::exact(&branch_ref(&name));
+        specs.fetch.push(RefSpec(format!("!{}", head.0)));
+    }
+    Ok(SyncRefs {
+        specs,
+        leases: vec![],
+    })
+}
+
+/// sync with `url` or the remote origin, sharing the refs chosen by `refs` or by the
+/// refspecs of the remote. steps and the summary are not printed if `quiet`, e.g. when
+/// syncing with many remotes at once.
+async fn sync_impl(
+    repo: &Repository,
+    url: Option<&str>,
+    refs: Option<&SyncRefs>,
+    options: &ConnectOptions,
+    quiet: bool,
+) -> Result<SyncSummary, WsvcError> {
+    let passphrase = options.passphrase.as_deref();
+    let mut origin = match url {
+        Some(url) => url.to_owned(),
+        None => repo.read_origin().await?,
+    };
+    let config = WsvcConfig::load(Some(repo)).await?;
+    let refs = match refs {
+        Some(refs) => refs.clone(),
+        None => SyncRefs {
+            specs: remote_refspecs(repo, &config, url).await,
+            leases: vec![],
+        },
+    };
+    options.ensure_online(&config, &origin)?;
+    if origin.starts_with(super::peers::PEER_SCHEME) {
+        origin = super::peers::resolve(&origin).await?;
+    }
+    let credential = super::credentials::credential(&origin, &config).await;
+    let credential = credential.as_ref();
+    step(quiet, "Connecting to remote server...");
+    if origin.starts_with("quic://") {
+        let family = options.family();
+        return sync_quic(repo, &origin, family, passphrase, credential, &refs, quiet).await;
+    }
+    let ws = match connect_ws(&origin, options.family(), &config).await {
+        Ok(ws) => ws,
+        // no fallback can reach the remote without a network.
+        Err(err @ WsvcError::Offline(_)) => return Err(err),
+        Err(err) => return sync_poll(repo, &origin, passphrase, &config, err, &refs, quiet).await,
+    };
+    match passphrase {
+        Some(passphrase) => {
+            let mut ws = EncryptedStream::handshake(ws, passphrase, true).await?;
+            sync_rounds(repo, &mut ws, credential, &refs, quiet).await
+        }
+        None => sync_rounds(repo, &mut { ws }, credential, &refs, quiet).await,
+    }
+}
+
+/// sync over http long-polling when the websocket upgrade fails, e.g. blocked by a proxy.
+/// `ws_err` is returned if the long-poll endpoint is not available either.
+async fn sync_poll(
+    repo: &Repository,
+    origin: &str,
+    passphrase: Option<&str>,
+    config: &WsvcConfig,
+    ws_err: WsvcError,
+    refs: &SyncRefs,
+    quiet: bool,
+) -> Result<SyncSummary, WsvcError> {
+    let url = match super::poll::poll_url(origin) {
+        Some(url) => url,
+        None => return Err(ws_err),
+    };
+    let stream = match super::poll::PollStream::open(&url, config).await {
+        Ok(stream) => stream,
+        Err(_) => return Err(ws_err),
+    };
+    if !quiet {
+        println!(
+            "{} {}",
+            "[!]".bright_yellow(),
+            "Websocket unavailable, falling back to http long-polling...".bold()
+        );
+    }
+    let credential = super::credentials::credential(origin, config).await;
+    let credential = credential.as_ref();
+    // the session is closed even if the sync fails, so the server gives up at once.
+    let (result, stream) = match passphrase {
+        Some(passphrase) => match EncryptedStream::handshake(stream, passphrase, true).await {
+            Ok(mut stream) => {
+                let result = sync_rounds(repo, &mut stream, credential, refs, quiet).await;
+                (result, Some(stream.into_inner()))
+            }
+            // the session expires on the server without polls.
+            Err(err) => (Err(err), None),
+        },
+        None => {
+            let mut stream = stream;
+            let result = sync_rounds(repo, &mut stream, credential, refs, quiet).await;
+            (result, Some(stream))
+        }
+    };
+    if let Some(stream) = stream {
+        stream.close().await.ok();
+    }
+    result
+}
+
+#[cfg(feature = "quic")]
+async fn sync_quic(
+    repo: &Repository,
+    url: &str,
+    family: AddrFamily,
+    passphrase: Option<&str>,
+    credential: Option<&SyncCredential>,
+    refs: &SyncRefs,
+    quiet: bool,
+) -> Result<SyncSummary, WsvcError> {
+    let stream = wsvc::quic::QuicStream::connect(url, family).await?;
+    let (summary, stream) = match passphrase {
+        Some(passphrase) => {
+            let mut stream = EncryptedStream::handshake(stream, passphrase, true).await?;
+            let summary = sync_rounds(repo, &mut stream, credential, refs, quiet).await?;
+            (summary, stream.into_inner())
+        }
+        None => {
+            let mut stream = stream;
+            (
+                sync_rounds(repo, &mut stream, credential, refs, quiet).await?,
+                stream,
+            )
+        }
+    };
+    stream.close().await;
+    Ok(summary)
+}
+
+#[cfg(not(feature = "quic"))]
+async fn sync_quic(
+    _repo: &Repository,
+    url: &str,
+    _family: AddrFamily,
+    _passphrase: Option<&str>,
+    _credential: Option<&SyncCredential>,
+    _refs: &SyncRefs,
+    _quiet: bool,
+) -> Result<SyncSummary, WsvcError> {
+    Err(WsvcError::BadUsage(format!(
+        "quic transport is not enabled in this build: {}",
+        url
+    )))
+}
+
+async fn sync_rounds(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+    credential: Option<&SyncCredential>,
+    refs: &SyncRefs,
+    quiet: bool,
+) -> Result<SyncSummary, WsvcError> {
+    let (codec, protocol, read_only) = sync_identity(repo, ws, credential).await?;
+    let ws = &mut CodecStream::new(ws, codec.as_ref(), protocol)?;
+    // a client only allowed to read pushes nothing, not even refs.
+    let fetch_only = SyncRefs {
+        specs: RefSpecs {
+            push: vec![],
+            fetch: refs.specs.fetch.clone(),
+        },
+        leases: vec![],
+    };
+    let refs = match read_only {
+        true => {
+            if !quiet {
+                println!(
+                    "{} {}",
+                    "[*]".bright_blue(),
+                    "Read-only access to the remote, nothing is pushed.".bold()
+                );
+            }
+            &fetch_only
+        }
+        false => refs,
+    };
     // the first round for client, receive server's all records
-    println!(
-        "{} {}",
-        "[+]".bright_green(),
-        "Connecting to remote server...".bold()
-    );
-    let (mut ws, _) = tokio_tungstenite::connect_async(origin).await?;
-    let (wanted_records, given_records) = sync_records(repo, &mut ws).await?;
-    let (wanted_trees, given_trees) = sync_trees(repo, &mut ws, given_records.as_slice()).await?;
+    let (wanted_records, given_records) = sync_records(repo, ws, !read_only, quiet).await?;
+    let (wanted_trees, given_trees) = sync_trees(repo, ws, given_records.as_slice(), quiet).await?;
     let (wanted_blobs, given_blobs) =
-        sync_blobs_meta(repo, &mut ws, given_trees.as_slice()).await?;
+        sync_blobs_meta(repo, ws, given_trees.as_slice(), quiet).await?;
     sync_blobs(
         repo,
-        &mut ws,
+        ws,
         wanted_blobs.as_slice(),
         given_blobs.as_slice(),
+        quiet,
     )
     .await?;
-    let trees_dir = repo.trees_dir().await.map_err(WsvcError::FsError)?;
+    let attestations = sync_attestations(repo, ws, !read_only, quiet).await?;
+    let notes = sync_notes(repo, ws, !read_only, quiet).await?;
+    let (server_tags, given_tags, conflicting_tags) =
+        sync_tags(repo, ws, &refs.specs, quiet).await?;
+    let (server_branches, given_branches, rejected_branches) =
+        sync_branches(repo, ws, &wanted_records, refs, quiet).await?;
+    let report: SyncReport = serde_json::from_slice(&recv_data(ws).await?)?;
+    if !report.accepted && !quiet {
+        println!(
+            "{} {}",
+            "[!]".bright_red(),
+            "Push rejected by remote:".bold()
+        );
+        for violation in &report.violations {
+            println!("  {} {}", "--".bright_red(), violation);
+        }
+    }
     for tree in &wanted_trees {
-        write(
-            trees_dir.join(tree.hash.0.to_hex().as_str()),
-            serde_json::to_string(tree)
-                .map_err(|err| WsvcError::FsError(WsvcFsError::SerializationFailed(err)))?,
-        )
-        .await
-        .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
+        repo.store_synced_tree(tree)
+            .await
+            .map_err(WsvcError::FsError)?;
     }
-    let records_dir = repo.records_dir().await.map_err(WsvcError::FsError)?;
-    println!("{} {}", "[*]".bright_blue(), "Summary:".bold());
     for record in &wanted_records {
-        println!("  {} ({}) {}", "<<".bright_yellow(), record.hash.0.to_string()[0..6].dimmed().bold(), record.message);
-        write(
-            records_dir.join(record.hash.0.to_hex().as_str()),
-            serde_json::to_string(record)
-                .map_err(|err| WsvcError::FsError(WsvcFsError::SerializationFailed(err)))?,
-        )
-        .await
-        .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
+        repo.store_record(record)
+            .await
+            .map_err(WsvcError::FsError)?;
     }
-    for record in &given_records {
-        println!("  {} ({}) {}", ">>".bright_blue(), record.hash.0.to_string()[0..6].dimmed().bold(), record.message);
+    let wanted_tags = repo.store_sync_tags(&server_tags).await?.len();
+    let server_branches = server_branches
+        .into_iter()
+        .map(|branch| SyncBranch {
+            branch,
+            lease: None,
+        })
+        .collect::<Vec<_>>();
+    let (wanted_branches, diverged_branches) = repo.store_sync_branches(&server_branches).await?;
+    let summary = SyncSummary {
+        received: wanted_records,
+        sent: match report.accepted {
+            true => given_records,
+            false => vec![],
+        },
+        attestations,
+        notes,
+        tags: (wanted_tags, given_tags),
+        conflicting_tags,
+        branches: (
+            wanted_branches.len(),
+            match report.accepted {
+                true => given_branches,
+                false => 0,
+            },
+        ),
+        diverged_branches,
+        rejected_branches,
+    };
+    if !quiet {
+        println!("{} {}", "[*]".bright_blue(), "Summary:".bold());
+        summary.print();
     }
-    Ok(())
+    if !report.accepted {
+        return Err(WsvcError::RepoError(format!(
+            "push rejected: {}",
+            report.violations.join("; ")
+        )));
+    }
+    Ok(summary)
 }
 
-pub async fn clone(url: String, dir: Option<String>) -> Result<(), WsvcError> {
+/// clone `url` into `dir`, with `filter` `blobless` only records and trees are synced
+/// and blobs are fetched when they are checked out. the latest record is checked out
+/// if `checkout`.
+pub async fn clone(
+    url: String,
+    dir: Option<String>,
+    filter: Option<String>,
+    checkout: bool,
+    options: ConnectOptions,
+) -> Result<(), WsvcError> {
     let pwd = std::env::current_dir().map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
     let repo_path = match dir {
         Some(p) => pwd.join(p),
-        None => pwd.join(url.split('/').last().unwrap()),
+        None => pwd.join(url.split('/').next_back().unwrap()),
     };
-    let repo = Repository::new(&repo_path, false)
+    let mut repo = Repository::new(&repo_path, false)
         .await
         .map_err(WsvcError::FsError)?;
+    if filter.as_deref() == Some("blobless") {
+        repo.set_blobless().await?;
+    }
+    let config = WsvcConfig::load(Some(&repo)).await?;
+    config.configure(&mut repo);
     let guard = RepoGuard::new(&repo).await.map_err(WsvcError::FsError)?;
-    repo.write_origin(url).await?;
-    sync_impl(&repo).await?;
-    let latest_record = repo
-        .get_latest_record()
-        .await
-        .map_err(WsvcError::FsError)?
-        .ok_or(WsvcError::EmptyRepoError)?;
-    repo.checkout_record(&latest_record.hash, &repo_path)
+    repo.write_origin(url.clone()).await?;
+    sync_impl(&repo, None, None, &options, false).await?;
+    let received = repo.get_records().await?.len();
+    repo.append_event(EventKind::Sync {
+        remote: url.clone(),
+        received,
+    })
+    .await
+    .ok();
+    let latest_record = repo.get_latest_record().await.map_err(WsvcError::FsError)?;
+    repo.record_clone(&url, latest_record.as_ref().map(|r| r.hash.clone()))
         .await?;
+    let latest_record = latest_record.ok_or(WsvcError::EmptyRepoError)?;
+    if checkout {
+        repo.checkout_record(&latest_record.hash, &repo_path)
+            .await?;
+    }
     drop(guard);
     Ok(())
 }
 
-pub async fn sync() -> Result<(), WsvcError> {
+/// sync with `url` or the remote origin without checking out, and journal the sync.
+pub(super) async fn sync_journaled(
+    repo: &Repository,
+    url: Option<&str>,
+    refs: Option<&SyncRefs>,
+    options: &ConnectOptions,
+    quiet: bool,
+) -> Result<SyncSummary, WsvcError> {
+    let summary = sync_impl(repo, url, refs, options, quiet).await?;
+    let remote = match url {
+        Some(url) => url.to_owned(),
+        None => repo.read_origin().await?,
+    };
+    repo.append_event(EventKind::Sync {
+        remote,
+        received: summary.received.len(),
+    })
+    .await
+    .ok();
+    // records were exchanged, only the diverged branches are refused.
+    if !summary.rejected_branches.is_empty() {
+        let names = summary
+            .rejected_branches
+            .iter()
+            .map(|(branch, _)| branch.name.as_str())
+            .collect::<Vec<_>>();
+        return Err(WsvcError::RepoError(format!(
+            "push of diverged branches refused: {}. the remote has records the local \
+             branches do not, pull and merge them, or push with --force-with-lease to \
+             replace the branches of the remote",
+            names.join(", ")
+        )));
+    }
+    Ok(summary)
+}
+
+/// sync with every remote of the config and the remote origin, `jobs` of them at once,
+/// then print a summary of each remote. a failing remote does not stop the others.
+/// without `checkout`, the branch HEAD is on is not fetched.
+async fn sync_all(
+    repo: &Repository,
+    config: &WsvcConfig,
+    jobs: usize,
+    checkout: bool,
+    options: &ConnectOptions,
+) -> Result<(), WsvcError> {
+    let mut remotes = config.remotes();
+    if let Ok(origin) = repo.read_origin().await {
+        if !remotes.iter().any(|(name, _)| name == ORIGIN_REMOTE) {
+            remotes.insert(0, (ORIGIN_REMOTE.to_owned(), origin));
+        }
+    }
+    if remotes.is_empty() {
+        return Err(WsvcFsError::RemoteNotSet.into());
+    }
+    println!(
+        "{} {}",
+        "[+]".bright_green(),
+        format!("Syncing with {} remotes...", remotes.len()).bold()
+    );
+    let results = futures::stream::iter(remotes.iter())
+        .map(|(_, url)| async move {
+            let refs = remote_sync_refs(repo, config, Some(url), checkout).await?;
+            sync_journaled(repo, Some(url), Some(&refs), options, true).await
+        })
+        .buffered(jobs.max(1))
+        .collect::<Vec<_>>()
+        .await;
+    let mut failed = vec![];
+    let mut offline = true;
+    for ((name, url), result) in remotes.iter().zip(results) {
+        offline &= matches!(result, Err(WsvcError::Offline(_)));
+        match result {
+            Ok(summary) => {
+                println!("{} {} ({})", "[*]".bright_blue(), name.bold(), url);
+                if summary.is_empty() {
+                    println!("  {} up to date", "==".dimmed());
+                }
+                summary.print();
+            }
+            Err(err) => {
+                println!("{} {} ({}): {}", "[!]".bright_red(), name.bold(), url, err);
+                failed.push(name.as_str());
+            }
+        }
+    }
+    if offline {
+        return Err(WsvcError::Offline(format!(
+            "no remote reachable: {}",
+            failed.join(", ")
+        )));
+    }
+    if !failed.is_empty() {
+        return Err(WsvcError::RepoError(format!(
+            "sync failed with {}",
+            failed.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+pub async fn sync(
+    url: Option<String>,
+    all: bool,
+    jobs: usize,
+    checkout: bool,
+    options: ConnectOptions,
+) -> Result<(), WsvcError> {
     let pwd = std::env::current_dir().map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
-    let repo = Repository::try_open(&pwd)
+    let mut repo = Repository::try_open(&pwd)
         .await
         .map_err(WsvcError::FsError)?;
+    let config = WsvcConfig::load(Some(&repo)).await?;
+    config.configure(&mut repo);
     let guard = RepoGuard::new(&repo).await.map_err(WsvcError::FsError)?;
-    sync_impl(&repo).await?;
-    let latest_record = repo
-        .get_latest_record()
-        .await
-        .map_err(WsvcError::FsError)?
-        .ok_or(WsvcError::EmptyRepoError)?;
-    repo.checkout_record(&latest_record.hash, pwd.as_path())
-        .await?;
+    // a sync without checkout leaves the workspace and HEAD as is, there is nothing to
+    // undo or to check out again.
+    let state = match checkout {
+        true => Some(repo.begin_operation(Operation::Sync, &pwd, None).await?),
+        false => None,
+    };
+    // records received from remotes that did sync are checked out even if others failed.
+    let result = match all {
+        true => sync_all(&repo, &config, jobs, checkout, &options).await,
+        false => {
+            // a remote of the config by its name, or a url.
+            let url = url.map(|url| config.remote_url(&url).unwrap_or(url));
+            let refs = remote_sync_refs(&repo, &config, url.as_deref(), checkout).await?;
+            let synced = sync_journaled(&repo, url.as_deref(), Some(&refs), &options, false)
+                .await
+                .map(|_| ());
+            if synced.is_err() && state.is_some() {
+                // nothing was checked out.
+                repo.end_operation(Operation::Sync).await?;
+                return synced;
+            }
+            synced
+        }
+    };
+    let checkout = match state {
+        Some(state) => checkout_synced(&repo, &pwd, state.head.as_ref()).await,
+        None => Ok(()),
+    };
     drop(guard);
+    result.and(checkout)
+}
+
+/// checkout what a sync brought to `workspace`, the latest record if HEAD is not on a
+/// branch, or the record of the branch HEAD is on if the sync moved it forward. `head` is
+/// the HEAD record before the sync.
+///
+/// the sync marker is removed once checked out, it is kept if the checkout failed so the
+/// workspace is not recorded half checked out, and the next sync checks out again.
+async fn checkout_synced(
+    repo: &Repository,
+    workspace: &Path,
+    head: Option<&ObjectId>,
+) -> Result<(), WsvcError> {
+    match repo.read_head().await? {
+        // HEAD on a branch stays there.
+        HeadRef::Branch { record, .. } => {
+            if record.as_ref() != head {
+                repo.checkout_head(workspace).await?;
+            }
+        }
+        HeadRef::Record(_) => match repo.get_latest_record().await? {
+            Some(latest_record) => {
+                repo.checkout_record(&latest_record.hash, workspace).await?;
+            }
+            None => return Err(WsvcError::EmptyRepoError),
+        },
+    }
+    repo.end_operation(Operation::Sync).await?;
     Ok(())
 }
+
+/// the refspecs of a ref given on the command line, a refspec as is, or the branch or tag
+/// `name`. a name pulled may be a branch or a tag of the remote, a name pushed must be a
+/// local branch or tag.
+async fn ref_arg(repo: &Repository, name: &str, push: bool) -> Result<Vec<RefSpec>, WsvcError> {
+    if name.starts_with('!')
+        || [BRANCHES_DIR, TAGS_DIR]
+            .iter()
+            .any(|dir| name.starts_with(&format!("{}/", dir)))
+    {
+        return Ok(vec![RefSpec(name.to_owned())]);
+    }
+    let mut result = vec![];
+    if !push || repo.read_branch(name).await?.is_some() {
+        result.push(RefSpec::exact(&branch_ref(name)));
+    }
+    if !push || repo.read_tag(name).await?.is_some() {
+        result.push(RefSpec::exact(&tag_ref(name)));
+    }
+    if result.is_empty() {
+        return Err(WsvcError::BadUsage(format!("no branch or tag {}", name)));
+    }
+    Ok(result)
+}
+
+/// the lease of a `--force-with-lease` value, empty for all branches, `<branch>` or
+/// `<branch>:<record>`.
+async fn lease_arg(repo: &Repository, value: &str) -> Result<Lease, WsvcError> {
+    if value.is_empty() {
+        return Ok(Lease {
+            branch: None,
+            expected: None,
+        });
+    }
+    let (branch, expected) = match value.split_once(':') {
+        Some((branch, rev)) => (
+            branch,
+            Some(
+                resolve_record(&repo.to_read_only(), Some(rev.to_owned()))
+                    .await?
+                    .hash,
+            ),
+        ),
+        None => (value, None),
+    };
+    Ok(Lease {
+        branch: Some(branch.to_owned()),
+        expected,
+    })
+}
+
+/// sync with `remote` or the remote origin, only sending refs if `push`, or only storing
+/// refs of the remote otherwise. `refs` are branch or tag names or refspecs, the refspecs
+/// of the remote if empty. records are still exchanged whole.
+///
+/// `leases` are values of `--force-with-lease`, allowing to push diverged branches.
+pub async fn sync_refs(
+    remote: Option<String>,
+    refs: Vec<String>,
+    push: bool,
+    leases: Vec<String>,
+    options: ConnectOptions,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir().map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
+    let mut repo = Repository::try_open(&pwd)
+        .await
+        .map_err(WsvcError::FsError)?;
+    let config = WsvcConfig::load(Some(&repo)).await?;
+    config.configure(&mut repo);
+    let guard = RepoGuard::new(&repo).await.map_err(WsvcError::FsError)?;
+    let state = repo.begin_operation(Operation::Sync, &pwd, None).await?;
+    // a remote of the config by its name, or a url. `origin` is the remote origin unless
+    // the config names a remote so.
+    let url = remote
+        .map(|remote| config.remote_url(&remote).unwrap_or(remote))
+        .filter(|url| url != ORIGIN_REMOTE);
+    let mut specs = remote_refspecs(&repo, &config, url.as_deref()).await;
+    let mut sync_refs = SyncRefs::default();
+    for value in &leases {
+        sync_refs.leases.push(lease_arg(&repo, value).await?);
+    }
+    let mut chosen = vec![];
+    for name in &refs {
+        chosen.extend(ref_arg(&repo, name, push).await?);
+    }
+    match push {
+        true => {
+            if !chosen.is_empty() {
+                specs.push = chosen;
+            }
+            specs.fetch = vec![];
+        }
+        false => {
+            if !chosen.is_empty() {
+                specs.fetch = chosen;
+            }
+            specs.push = vec![];
+        }
+    }
+    sync_refs.specs = specs;
+    let result = sync_journaled(&repo, url.as_deref(), Some(&sync_refs), &options, false)
+        .await
+        .map(|_| ());
+    let checkout = checkout_synced(&repo, &pwd, state.head.as_ref()).await;
+    drop(guard);
+    result.and(checkout)
+}
+
+/// host the repository through a relay, the remote origin should be a relay room.
+///
+/// the relay pairs this host with the next client joining the same room, then this
+/// host serves the sync like a server. it keeps serving until interrupted.
+#[cfg(feature = "server")]
+pub async fn relay_host(options: ConnectOptions) -> Result<(), WsvcError> {
+    use tokio_tungstenite::tungstenite::Message;
+
+    let pwd = std::env::current_dir().map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
+    let repo = Repository::try_open(&pwd)
+        .await
+        .map_err(WsvcError::FsError)?;
+    let origin = repo.read_origin().await?;
+    let config = WsvcConfig::load(Some(&repo)).await?;
+    options.ensure_online(&config, &origin)?;
+    loop {
+        println!(
+            "{} {}",
+            "[+]".bright_green(),
+            "Waiting for a peer on the relay...".bold()
+        );
+        let mut ws = connect_ws(&origin, options.family(), &config).await?;
+        loop {
+            match ws.next().await {
+                Some(Ok(Message::Text(text))) if text == wsvc::server::relay::PAIRED => break,
+                Some(Ok(_)) => continue,
+                Some(Err(err)) => return Err(err.into()),
+                None => return Err(WsvcError::DataError("relay closed".to_owned())),
+            }
+        }
+        println!(
+            "{} {}",
+            "[+]".bright_green(),
+            "Peer joined, syncing...".bold()
+        );
+        let result = match &options.passphrase {
+            Some(passphrase) => match EncryptedStream::handshake(ws, passphrase, false).await {
+                Ok(mut ws) => wsvc::server::sync_with(&repo, &mut ws).await,
+                Err(err) => Err(err.into()),
+            },
+            None => wsvc::server::sync_with(&repo, &mut ws).await,
+        };
+        match result {
+            Ok(_) => println!("{} {}", "[*]".bright_blue(), "Peer synced.".bold()),
+            Err(err) => println!("{} {}", "[!]".bright_red(), err),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+    /// `PipeStream` stand for one end of an in-memory connection.
+    struct PipeStream(UnboundedSender<Vec<u8>>, UnboundedReceiver<Vec<u8>>);
+
+    #[async_trait]
+    impl FrameStream for PipeStream {
+        async fn send_frame(&mut self, data: Vec<u8>) -> Result<(), WsvcError> {
+            self.0
+                .send(data)
+                .map_err(|_| WsvcError::DataError("pipe closed".to_owned()))
+        }
+
+        async fn recv_frame(&mut self) -> Result<Option<Vec<u8>>, WsvcError> {
+            Ok(self.1.recv().await)
+        }
+    }
+
+    fn pipe() -> (PipeStream, PipeStream) {
+        let (client_tx, server_rx) = unbounded_channel();
+        let (server_tx, client_rx) = unbounded_channel();
+        (
+            PipeStream(client_tx, client_rx),
+            PipeStream(server_tx, server_rx),
+        )
+    }
+
+    #[tokio::test]
+    async fn rejected_pushes_leave_no_objects() {
+        let root = std::env::temp_dir().join(format!("wsvc-test-{}", nanoid!()));
+        let (client_dir, server_dir) = (root.join("client"), root.join("server"));
+        create_dir_all(&client_dir).await.unwrap();
+        let client = Repository::new(&client_dir, false).await.unwrap();
+        let server = Repository::new(&server_dir, true).await.unwrap();
+        tokio::fs::write(
+            server.path.join(wsvc::server::policy::POLICY_FILE),
+            "[content]\nmax_file_size = 4\n",
+        )
+        .await
+        .unwrap();
+        tokio::fs::write(client_dir.join("big"), "too large")
+            .await
+            .unwrap();
+        let record = client
+            .commit_record(&client_dir, "me", "big", None)
+            .await
+            .unwrap();
+        let (mut client_end, mut server_end) = pipe();
+        let refs = SyncRefs {
+            specs: RefSpecs::default(),
+            leases: vec![],
+        };
+        let (pushed, served) = tokio::join!(
+            sync_rounds(&client, &mut client_end, None, &refs, true),
+            wsvc::server::sync_with(&server, &mut server_end),
+        );
+        assert!(matches!(pushed, Err(WsvcError::RepoError(_))));
+        assert!(matches!(
+            served,
+            Err(wsvc::server::WsvcServerError::Rejected(_))
+        ));
+        let blob = client.read_tree(&record.root).await.unwrap().blobs()[0]
+            .hash
+            .clone();
+        assert_eq!(
+            server
+                .missing_objects(std::slice::from_ref(&blob))
+                .await
+                .unwrap(),
+            vec![blob]
+        );
+        assert!(server.get_records().await.unwrap().is_empty());
+        let mut partials = tokio::fs::read_dir(server.partial_dir().await.unwrap())
+            .await
+            .unwrap();
+        assert!(partials.next_entry().await.unwrap().is_none());
+        remove_dir_all(root).await.unwrap();
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashSet;
+
+use colored::Colorize;
+use wsvc::{
+    fs::WsvcFsError, history::author_changes, mailmap::Mailmap, model::Repository, walk::WalkOrder,
+    WsvcError,
+};
+
+use super::resolve_record;
+
+/// summarize who changed `path`, a file or a dir, how many times and how recently, over the
+/// records of `range`.
+///
+/// `range` is `<from>..<to>` of tag names or record hash prefixes, the records reachable
+/// from `to` but not from `from`. `to` is HEAD if omitted and all records up to `to` are
+/// counted if `from` is omitted, a range without `..` is `from` only.
+pub async fn who_changed(
+    root: Option<String>,
+    path: String,
+    range: Option<String>,
+    json: bool,
+) -> Result<(), WsvcError> {
+    let pwd = std::env::current_dir()
+        .map_err(WsvcFsError::Os)?
+        .to_str()
+        .unwrap()
+        .to_string();
+    let root = root.unwrap_or(pwd);
+    let repo = Repository::open_read_only(&root).await?;
+    let mailmap = Mailmap::load(&root).await?;
+    let (from, to) = match range.as_deref() {
+        Some(range) => match range.split_once("..") {
+            Some((from, to)) => (from, to),
+            None => (range, ""),
+        },
+        None => ("", ""),
+    };
+    let non_empty = |rev: &str| (!rev.is_empty()).then(|| rev.to_owned());
+    let to = resolve_record(&repo, non_empty(to)).await?;
+    let excluded = match non_empty(from) {
+        Some(from) => {
+            let from = resolve_record(&repo, Some(from)).await?;
+            repo.walk_ids(Some(&from.hash), WalkOrder::Topological)
+                .await?
+                .into_iter()
+                .collect()
+        }
+        None => HashSet::new(),
+    };
+    let revisions = repo
+        .file_history(&path, Some(&to.hash))
+        .await?
+        .into_iter()
+        .filter(|revision| !excluded.contains(&revision.record.hash))
+        .collect::<Vec<_>>();
+    let authors = author_changes(&revisions, &mailmap);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&authors)?);
+        return Ok(());
+    }
+    if authors.is_empty() {
+        return Err(WsvcError::BadUsage(match range {
+            Some(range) => format!("no record in {} changed {}", range, path),
+            None => format!("no record changed {}", path),
+        }));
+    }
+    let width = authors
+        .iter()
+        .map(|author| author.changes.to_string().len())
+        .max()
+        .unwrap_or_default();
+    for author in &authors {
+        println!(
+            "{}  {}  {}  {}",
+            format!("{:>width$}", author.changes).bold(),
+            author.last.naive_local().to_string().yellow(),
+            author.record.0.to_hex()[0..6].dimmed(),
+            author.author.bright_blue(),
+        );
+    }
+    println!(
+        "{} {} changes by {} authors",
+        "[*]".bright_blue(),
+        revisions.len(),
+        authors.len()
+    );
+    Ok(())
+}
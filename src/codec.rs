@@ -0,0 +1,258 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use tokio::fs::{create_dir_all, read, read_dir, remove_file};
+
+use crate::{
+    encoding::decode_object,
+    frame::FrameStream,
+    fs::WsvcFsError,
+    model::{Repository, Tree},
+    refs::write_atomic,
+    WsvcError,
+};
+
+/// name of the zstd transport codec offered by servers when syncing.
+pub const ZSTD_CODEC: &str = "zstd";
+
+/// file of the transport dictionary trained for the repository, in the repository.
+pub const TRANSPORT_DICT_FILE: &str = "transport.dict";
+
+/// dir of transport dictionaries received from remotes, in the repository.
+pub const TRANSPORT_DICTS_DIR: &str = "transport-dicts";
+
+/// default size of trained transport dictionaries.
+pub const DEFAULT_DICT_SIZE: usize = 32 * 1024;
+
+/// records and trees sampled at most to train a transport dictionary.
+const MAX_SAMPLES: usize = 50000;
+
+/// compression level of frames, fast enough to keep up with the network.
+const FRAME_LEVEL: i32 = 3;
+
+/// largest frame accepted after decompression, frames of sync are at most 16KiB.
+const MAX_FRAME_SIZE: usize = 1 << 20;
+
+/// leading byte of frames sent as is, e.g. when compressing does not shrink them.
+const RAW_FRAME: u8 = 0;
+
+/// leading byte of compressed frames.
+const COMPRESSED_FRAME: u8 = 1;
+
+/// `TransportDict` stand for a zstd dictionary trained on the records and trees of a
+/// repository, shrinking the many small json packets of sync rounds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransportDict {
+    /// blake3 hex of the dictionary, both sides of a sync compare ids.
+    pub id: String,
+    pub data: Vec<u8>,
+}
+
+impl TransportDict {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self {
+            id: blake3::hash(&data).to_hex().to_string(),
+            data,
+        }
+    }
+}
+
+/// `TransportCodec` stand for how frames are compressed after the identity round of a
+/// sync, negotiated by name so more codecs can be added without breaking older peers.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TransportCodec {
+    /// zstd without a dictionary.
+    Zstd,
+    /// zstd with the transport dictionary of the server.
+    ZstdDict(TransportDict),
+}
+
+/// `CodecStream` compresses every frame of the inner stream with a transport codec, or
+/// passes them through if no codec was negotiated, and tells the protocol version
+/// negotiated with the codec.
+///
+/// each frame is led by a byte telling whether it is compressed, frames compressing
+/// badly, e.g. of blobs already compressed, are sent as is. files sent on parallel
+/// channels by `send_files` are not compressed.
+pub struct CodecStream<S> {
+    inner: S,
+    codec: Option<(
+        zstd::bulk::Compressor<'static>,
+        zstd::bulk::Decompressor<'static>,
+    )>,
+    protocol: u32,
+}
+
+impl<S: FrameStream> CodecStream<S> {
+    pub fn new(inner: S, codec: Option<&TransportCodec>, protocol: u32) -> Result<Self, WsvcError> {
+        let codec = match codec {
+            None => None,
+            Some(TransportCodec::Zstd) => Some((
+                zstd::bulk::Compressor::new(FRAME_LEVEL),
+                zstd::bulk::Decompressor::new(),
+            )),
+            Some(TransportCodec::ZstdDict(dict)) => Some((
+                zstd::bulk::Compressor::with_dictionary(FRAME_LEVEL, &dict.data),
+                zstd::bulk::Decompressor::with_dictionary(&dict.data),
+            )),
+        };
+        let codec = match codec {
+            Some((compressor, decompressor)) => Some((
+                compressor.map_err(WsvcFsError::Os)?,
+                decompressor.map_err(WsvcFsError::Os)?,
+            )),
+            None => None,
+        };
+        Ok(Self {
+            inner,
+            codec,
+            protocol,
+        })
+    }
+}
+
+#[async_trait]
+impl<S: FrameStream> FrameStream for CodecStream<S> {
+    async fn send_frame(&mut self, data: Vec<u8>) -> Result<(), WsvcError> {
+        let Some((compressor, _)) = &mut self.codec else {
+            return self.inner.send_frame(data).await;
+        };
+        let compressed = compressor.compress(&data).map_err(WsvcFsError::Os)?;
+        let mut frame = Vec::with_capacity(compressed.len().min(data.len()) + 1);
+        if compressed.len() < data.len() {
+            frame.push(COMPRESSED_FRAME);
+            frame.extend_from_slice(&compressed);
+        } else {
+            frame.push(RAW_FRAME);
+            frame.extend_from_slice(&data);
+        }
+        self.inner.send_frame(frame).await
+    }
+
+    async fn recv_frame(&mut self) -> Result<Option<Vec<u8>>, WsvcError> {
+        let Some(frame) = self.inner.recv_frame().await? else {
+            return Ok(None);
+        };
+        let Some((_, decompressor)) = &mut self.codec else {
+            return Ok(Some(frame));
+        };
+        match frame.split_first() {
+            Some((&RAW_FRAME, data)) => Ok(Some(data.to_vec())),
+            Some((&COMPRESSED_FRAME, data)) => Ok(Some(
+                decompressor
+                    .decompress(data, MAX_FRAME_SIZE)
+                    .map_err(|err| WsvcError::DataError(format!("invalid frame: {}", err)))?,
+            )),
+            _ => Err(WsvcError::DataError("invalid frame codec".to_owned())),
+        }
+    }
+
+    async fn send_files(&mut self, files: &[(String, PathBuf)]) -> Result<bool, WsvcError> {
+        self.inner.send_files(files).await
+    }
+
+    async fn recv_files(&mut self, count: usize, dir: &Path) -> Result<bool, WsvcError> {
+        self.inner.recv_files(count, dir).await
+    }
+
+    fn protocol(&self) -> u32 {
+        self.protocol
+    }
+}
+
+async fn read_dict(path: &Path) -> Result<Option<TransportDict>, WsvcFsError> {
+    match read(path).await {
+        Ok(data) => Ok(Some(TransportDict::new(data))),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+impl Repository {
+    /// the transport dictionary trained for the repository, offered by it as a server.
+    pub async fn transport_dict(&self) -> Result<Option<TransportDict>, WsvcFsError> {
+        read_dict(&self.path.join(TRANSPORT_DICT_FILE)).await
+    }
+
+    /// the transport dictionary `id`, trained for the repository or received from a remote.
+    pub async fn transport_dict_by_id(
+        &self,
+        id: &str,
+    ) -> Result<Option<TransportDict>, WsvcFsError> {
+        if let Some(dict) = self.transport_dict().await? {
+            if dict.id == id {
+                return Ok(Some(dict));
+            }
+        }
+        if !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Ok(None);
+        }
+        Ok(read_dict(&self.path.join(TRANSPORT_DICTS_DIR).join(id))
+            .await?
+            .filter(|dict| dict.id == id))
+    }
+
+    /// keep the transport dictionary of a remote, so later syncs do not receive it again.
+    pub async fn store_remote_dict(&self, dict: &TransportDict) -> Result<(), WsvcFsError> {
+        self.check_writable()?;
+        let dir = self.path.join(TRANSPORT_DICTS_DIR);
+        create_dir_all(&dir).await?;
+        write_atomic(&dir.join(&dict.id), &dict.data).await
+    }
+
+    /// transport dictionaries received from remotes.
+    pub async fn remote_dicts(&self) -> Result<Vec<TransportDict>, WsvcFsError> {
+        let dir = self.path.join(TRANSPORT_DICTS_DIR);
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut result = vec![];
+        let mut entries = read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            result.push(TransportDict::new(read(entry.path()).await?));
+        }
+        Ok(result)
+    }
+
+    /// train the transport dictionary of the repository of up to `size` bytes on its
+    /// records and trees, replacing the one trained before.
+    pub async fn train_transport_dict(&self, size: usize) -> Result<TransportDict, WsvcError> {
+        self.check_writable()?;
+        let mut samples = vec![];
+        for record in self.get_records().await? {
+            samples.push(serde_json::to_vec(&record).map_err(WsvcFsError::from)?);
+        }
+        let mut entries = read_dir(self.trees_dir().await?)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        while let Some(entry) = entries.next_entry().await.map_err(WsvcFsError::Os)? {
+            if samples.len() >= MAX_SAMPLES {
+                break;
+            }
+            let content = read(entry.path()).await.map_err(WsvcFsError::Os)?;
+            let tree: Tree = decode_object(&content)?;
+            samples.push(serde_json::to_vec(&tree).map_err(WsvcFsError::from)?);
+        }
+        samples.truncate(MAX_SAMPLES);
+        let data = zstd::dict::from_samples(&samples, size).map_err(|err| {
+            WsvcError::BadUsage(format!(
+                "failed to train a dictionary on {} records and trees: {}",
+                samples.len(),
+                err
+            ))
+        })?;
+        write_atomic(&self.path.join(TRANSPORT_DICT_FILE), &data).await?;
+        Ok(TransportDict::new(data))
+    }
+
+    /// remove the transport dictionary of the repository, syncs use zstd without one.
+    pub async fn remove_transport_dict(&self) -> Result<bool, WsvcFsError> {
+        self.check_writable()?;
+        let path = self.path.join(TRANSPORT_DICT_FILE);
+        if !path.exists() {
+            return Ok(false);
+        }
+        remove_file(path).await?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,142 @@
+use miniz_oxide::{deflate::compress_to_vec, inflate::decompress_to_vec_with_limit};
+use serde::{Deserialize, Serialize};
+
+use crate::{fs::WsvcFsError, model::DEFAULT_COMPRESSION_LEVEL};
+
+/// `Codec` stand for how a frame of a stored object is compressed.
+///
+/// objects are stored in frames, each led by the two magic bytes of its codec and the
+/// length of the compressed data, so objects of different codecs, or chunks of a blob
+/// stored with different codecs, are all read back the same way.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    /// deflate by miniz, frames of 16KiB with a 16 bit length, the format of objects
+    /// stored by every wsvc version.
+    #[default]
+    Deflate,
+    /// zstd, frames of 128KiB with a 32 bit length. smaller and faster to read, but not
+    /// readable by wsvc versions without zstd.
+    Zstd,
+}
+
+impl Codec {
+    /// leading bytes of frames of the codec.
+    pub const fn magic(self) -> [u8; 2] {
+        match self {
+            Codec::Deflate => [0x78, 0xda],
+            Codec::Zstd => [0x28, 0xb5],
+        }
+    }
+
+    /// the codec of frames led by `magic`.
+    pub fn from_magic(magic: [u8; 2]) -> Option<Self> {
+        [Codec::Deflate, Codec::Zstd]
+            .into_iter()
+            .find(|codec| codec.magic() == magic)
+    }
+
+    /// bytes of the length following the magic of a frame, big endian.
+    pub const fn len_bytes(self) -> usize {
+        match self {
+            Codec::Deflate => 2,
+            Codec::Zstd => 4,
+        }
+    }
+
+    /// bytes of content in a frame.
+    pub const fn frame_size(self) -> usize {
+        match self {
+            Codec::Deflate => 16 * 1024,
+            Codec::Zstd => 128 * 1024,
+        }
+    }
+
+    /// the highest level of the codec.
+    pub const fn max_level(self) -> u8 {
+        match self {
+            Codec::Deflate => 10,
+            Codec::Zstd => 22,
+        }
+    }
+
+    /// decompress the data of a frame of the codec. frames hold at most `frame_size` bytes,
+    /// a frame decompressing to more is refused before it is decompressed whole.
+    pub fn decompress(self, data: &[u8]) -> Result<Vec<u8>, WsvcFsError> {
+        match self {
+            Codec::Deflate => decompress_to_vec_with_limit(data, self.frame_size())
+                .map_err(|_| WsvcFsError::DecompressFailed("decode chunk failed".to_owned())),
+            Codec::Zstd => zstd::bulk::decompress(data, self.frame_size())
+                .map_err(|e| WsvcFsError::DecompressFailed(format!("decode chunk failed: {}", e))),
+        }
+    }
+}
+
+/// `Compression` stand for the codec and level newly stored objects are compressed with.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Compression {
+    pub codec: Codec,
+    pub level: u8,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::new(Codec::default(), DEFAULT_COMPRESSION_LEVEL)
+    }
+}
+
+impl Compression {
+    /// levels above the highest one of `codec` are treated as the highest.
+    pub fn new(codec: Codec, level: u8) -> Self {
+        Self {
+            codec,
+            level: level.min(codec.max_level()),
+        }
+    }
+
+    /// `data` of at most a frame size compressed as one frame, header included.
+    pub fn frame(&self, data: &[u8]) -> Result<Vec<u8>, WsvcFsError> {
+        let compressed = match self.codec {
+            Codec::Deflate => compress_to_vec(data, self.level),
+            Codec::Zstd => zstd::bulk::compress(data, self.level as i32)?,
+        };
+        let len = (compressed.len() as u32).to_be_bytes();
+        let mut result = Vec::with_capacity(6 + compressed.len());
+        result.extend_from_slice(&self.codec.magic());
+        result.extend_from_slice(&len[4 - self.codec.len_bytes()..]);
+        result.extend_from_slice(&compressed);
+        Ok(result)
+    }
+
+    /// `data` compressed in frames, the format of stored objects.
+    pub fn frames(&self, data: &[u8]) -> Result<Vec<u8>, WsvcFsError> {
+        let mut result = Vec::new();
+        for chunk in data.chunks(self.codec.frame_size()) {
+            result.extend_from_slice(&self.frame(chunk)?);
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_decompress_within_their_size() {
+        for codec in [Codec::Deflate, Codec::Zstd] {
+            let compression = Compression::new(codec, DEFAULT_COMPRESSION_LEVEL);
+            let noise = (0..codec.frame_size())
+                .map(|i| (i * 7919 % 251) as u8)
+                .collect::<Vec<_>>();
+            for data in [vec![0; codec.frame_size()], noise] {
+                let frame = compression.frame(&data).unwrap();
+                let header = 2 + codec.len_bytes();
+                assert_eq!(codec.decompress(&frame[header..]).unwrap(), data);
+            }
+            // a frame expanding past the frame size, e.g. made by hand.
+            let bomb = compression.frame(&vec![0; codec.frame_size() + 1]).unwrap();
+            assert!(codec.decompress(&bomb[2 + codec.len_bytes()..]).is_err());
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::{future::Future, io, net::SocketAddr, time::Duration};
+
+use futures::{stream::FuturesUnordered, StreamExt};
+use tokio::net::TcpStream;
+
+/// delay before the next connection attempt is started, as recommended by RFC 8305.
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// `AddrFamily` restricts the address family used to reach a remote.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AddrFamily {
+    #[default]
+    Any,
+    V4,
+    V6,
+}
+
+impl AddrFamily {
+    pub fn matches(&self, addr: &SocketAddr) -> bool {
+        match self {
+            AddrFamily::Any => true,
+            AddrFamily::V4 => addr.is_ipv4(),
+            AddrFamily::V6 => addr.is_ipv6(),
+        }
+    }
+}
+
+/// resolve all addresses of `host`, ordered to be dialed: address families are
+/// interleaved and ipv6 goes first, so a broken family only delays the other one.
+pub async fn resolve(host: &str, port: u16, family: AddrFamily) -> io::Result<Vec<SocketAddr>> {
+    // `[::1]` in urls is `::1` for the resolver.
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let (v6, v4): (Vec<_>, Vec<_>) = tokio::net::lookup_host((host, port))
+        .await?
+        .filter(|addr| family.matches(addr))
+        .partition(|addr| addr.is_ipv6());
+    let mut v6 = v6.into_iter();
+    let mut v4 = v4.into_iter();
+    let mut addrs = vec![];
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => break,
+            (a, b) => addrs.extend(a.into_iter().chain(b)),
+        }
+    }
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no {:?} address found for {}", family, host),
+        ));
+    }
+    Ok(addrs)
+}
+
+/// race connection attempts to `addrs` in order, an attempt is started every 250ms or as
+/// soon as an attempt failed. the first established connection wins and the
+/// others are dropped, the last error is returned if all of them failed.
+pub async fn race<T, E, F, Fut>(addrs: &[SocketAddr], connect: F) -> Option<Result<T, E>>
+where
+    F: Fn(SocketAddr) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut remaining = addrs.iter().copied();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_err = None;
+    let mut start_next = true;
+    loop {
+        if start_next {
+            match remaining.next() {
+                Some(addr) => attempts.push(connect(addr)),
+                None if attempts.is_empty() => return last_err.map(Err),
+                None => {}
+            }
+        }
+        let has_more = remaining.len() > 0;
+        tokio::select! {
+            Some(result) = attempts.next() => match result {
+                Ok(conn) => return Some(Ok(conn)),
+                Err(err) => {
+                    last_err = Some(err);
+                    start_next = true;
+                }
+            },
+            _ = tokio::time::sleep(ATTEMPT_DELAY), if has_more => start_next = true,
+            else => return last_err.map(Err),
+        }
+    }
+}
+
+/// connect to `host` with happy eyeballs.
+pub async fn connect_tcp(host: &str, port: u16, family: AddrFamily) -> io::Result<TcpStream> {
+    let addrs = resolve(host, port, family).await?;
+    race(&addrs, TcpStream::connect)
+        .await
+        .unwrap_or_else(|| Err(io::Error::from(io::ErrorKind::NotFound)))
+}
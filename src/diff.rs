@@ -0,0 +1,664 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs::{create_dir_all, read, read_dir, remove_dir, remove_file, rename};
+
+use crate::{
+    attributes::Attributes,
+    binary::is_binary,
+    fs::{join_path, WsvcFsError},
+    model::{Entry, EntryKind, ObjectId, Repository},
+    patch::equal_lines,
+    readonly::ReadOnlyRepository,
+};
+
+/// default threshold of `find_similar_renames`, in percent.
+pub const DEFAULT_SIMILARITY: u8 = 50;
+
+/// the most removed or added files `find_similar_renames` compares, more are left as they
+/// are since every removed file is compared with every added file.
+const SIMILARITY_LIMIT: usize = 1000;
+
+/// `Change` stand for a changed file between two snapshots, paths are separated by `/`.
+///
+/// dirs are not reported, a dir added or removed shows as its files. empty dirs are
+/// recorded, so a dir becoming empty or no longer empty is reported as `DirAdded` or
+/// `DirRemoved`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Change {
+    Added {
+        path: String,
+        hash: ObjectId,
+    },
+    Removed {
+        path: String,
+        hash: ObjectId,
+    },
+    /// content, kind or mode changed, hashes are equal if only the mode changed.
+    Modified {
+        path: String,
+        old_hash: ObjectId,
+        new_hash: ObjectId,
+    },
+    /// moved without changing the content.
+    Renamed {
+        old_path: String,
+        new_path: String,
+        hash: ObjectId,
+    },
+    /// moved and changed, found by `Repository::find_similar_renames`.
+    Moved {
+        old_path: String,
+        new_path: String,
+        old_hash: ObjectId,
+        new_hash: ObjectId,
+        /// percent of the lines of both files kept.
+        similarity: u8,
+    },
+    /// an empty dir, added or left after its files were removed.
+    DirAdded {
+        path: String,
+    },
+    /// an empty dir, removed or filled with files.
+    DirRemoved {
+        path: String,
+    },
+}
+
+impl Change {
+    /// the change as a porcelain line, a stable format for scripts.
+    ///
+    /// fields are separated by tabs: the status letter (`A`, `D`, `M` or `R`), the old
+    /// hash, the new hash and the path, renames and moves add the old path. missing hashes
+    /// are `-`, paths of empty dirs end with `/`.
+    /// lines end with `\n` and paths are quoted by `quote_path`, or with NUL and paths are
+    /// kept as is if `nul` is set.
+    pub fn porcelain(&self, nul: bool) -> String {
+        let quote = |path: &str| match nul {
+            true => path.to_owned(),
+            false => quote_path(path),
+        };
+        let hex = |hash: &ObjectId| hash.0.to_hex().to_string();
+        let mut line = match self {
+            Change::Added { path, hash } => format!("A\t-\t{}\t{}", hex(hash), quote(path)),
+            Change::Removed { path, hash } => format!("D\t{}\t-\t{}", hex(hash), quote(path)),
+            Change::Modified {
+                path,
+                old_hash,
+                new_hash,
+            } => format!("M\t{}\t{}\t{}", hex(old_hash), hex(new_hash), quote(path)),
+            Change::Renamed {
+                old_path,
+                new_path,
+                hash,
+            } => format!(
+                "R\t{}\t{}\t{}\t{}",
+                hex(hash),
+                hex(hash),
+                quote(new_path),
+                quote(old_path)
+            ),
+            Change::Moved {
+                old_path,
+                new_path,
+                old_hash,
+                new_hash,
+                ..
+            } => format!(
+                "R\t{}\t{}\t{}\t{}",
+                hex(old_hash),
+                hex(new_hash),
+                quote(new_path),
+                quote(old_path)
+            ),
+            Change::DirAdded { path } => format!("A\t-\t-\t{}/", quote(path)),
+            Change::DirRemoved { path } => format!("D\t-\t-\t{}/", quote(path)),
+        };
+        line.push(if nul { '\0' } else { '\n' });
+        line
+    }
+
+    /// the path in the newer snapshot, or the removed path.
+    pub fn path(&self) -> &str {
+        match self {
+            Change::Added { path, .. }
+            | Change::Removed { path, .. }
+            | Change::Modified { path, .. }
+            | Change::DirAdded { path }
+            | Change::DirRemoved { path } => path,
+            Change::Renamed { new_path, .. } | Change::Moved { new_path, .. } => new_path,
+        }
+    }
+
+    /// the change undoing this change, from the newer snapshot back to the older one.
+    pub fn inverse(&self) -> Change {
+        match self.clone() {
+            Change::Added { path, hash } => Change::Removed { path, hash },
+            Change::Removed { path, hash } => Change::Added { path, hash },
+            Change::Modified {
+                path,
+                old_hash,
+                new_hash,
+            } => Change::Modified {
+                path,
+                old_hash: new_hash,
+                new_hash: old_hash,
+            },
+            Change::Renamed {
+                old_path,
+                new_path,
+                hash,
+            } => Change::Renamed {
+                old_path: new_path,
+                new_path: old_path,
+                hash,
+            },
+            Change::Moved {
+                old_path,
+                new_path,
+                old_hash,
+                new_hash,
+                similarity,
+            } => Change::Moved {
+                old_path: new_path,
+                new_path: old_path,
+                old_hash: new_hash,
+                new_hash: old_hash,
+                similarity,
+            },
+            Change::DirAdded { path } => Change::DirRemoved { path },
+            Change::DirRemoved { path } => Change::DirAdded { path },
+        }
+    }
+}
+
+/// whether the workspace file at `path` has the content `hash`, or is missing if `hash` is
+/// `None`.
+async fn has_content(
+    attributes: &Attributes,
+    full_path: &Path,
+    path: &str,
+    hash: Option<&ObjectId>,
+) -> bool {
+    match hash {
+        Some(hash) if full_path.is_file() => attributes
+            .hash_file(full_path, path)
+            .await
+            .is_ok_and(|h| h == *hash),
+        Some(_) => false,
+        None => !full_path.exists(),
+    }
+}
+
+/// remove the dirs of `path` left empty, up to `workspace`.
+async fn remove_empty_dirs(workspace: &Path, path: &Path) {
+    let mut dir = path.parent();
+    while let Some(current) = dir.filter(|d| *d != workspace && d.starts_with(workspace)) {
+        let empty = match read_dir(current).await {
+            Ok(mut entries) => matches!(entries.next_entry().await, Ok(None)),
+            Err(_) => false,
+        };
+        if !empty || remove_dir(current).await.is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
+
+/// quote a path for line based output if it has tabs, newlines, quotes, backslashes or
+/// other control characters, e.g. `"a\tb"`. other paths are kept as is.
+pub fn quote_path(path: &str) -> String {
+    if !path
+        .chars()
+        .any(|c| c == '"' || c == '\\' || c.is_control())
+    {
+        return path.to_owned();
+    }
+    let mut result = String::from("\"");
+    for c in path.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\t' => result.push_str("\\t"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            c if c.is_control() => result.push_str(&format!("\\{:03o}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// pair removed and added files of the same content as renames, in order of paths.
+pub(crate) fn detect_renames(changes: Vec<Change>) -> Vec<Change> {
+    let mut removed = HashMap::<ObjectId, VecDeque<String>>::new();
+    for change in &changes {
+        if let Change::Removed { path, hash } = change {
+            removed
+                .entry(hash.clone())
+                .or_default()
+                .push_back(path.clone());
+        }
+    }
+    let mut renamed = HashSet::new();
+    let mut result = Vec::with_capacity(changes.len());
+    for change in changes {
+        match change {
+            Change::Added { path, hash } => {
+                match removed.get_mut(&hash).and_then(|p| p.pop_front()) {
+                    Some(old_path) => {
+                        renamed.insert(old_path.clone());
+                        result.push(Change::Renamed {
+                            old_path,
+                            new_path: path,
+                            hash,
+                        });
+                    }
+                    None => result.push(Change::Added { path, hash }),
+                }
+            }
+            change => result.push(change),
+        }
+    }
+    result.retain(|c| !matches!(c, Change::Removed { path, .. } if renamed.contains(path)));
+    result
+}
+
+/// percent of the lines of `a` and `b` kept by the line diff of both.
+fn similarity(a: &str, b: &str) -> u8 {
+    let (a, b) = (a.lines().collect::<Vec<_>>(), b.lines().collect::<Vec<_>>());
+    if a.is_empty() && b.is_empty() {
+        return 100;
+    }
+    (equal_lines(&a, &b).len() * 200 / (a.len() + b.len())) as u8
+}
+
+/// the content of `data` if it is a text file.
+fn text_of(data: Vec<u8>) -> Option<String> {
+    if is_binary(&data) {
+        return None;
+    }
+    String::from_utf8(data).ok()
+}
+
+impl Repository {
+    /// pair removed and added text files of `changes` with at least `threshold` percent of
+    /// their lines in common as moves, the most similar pairs first. call it on changes
+    /// with exact renames detected, they are kept.
+    ///
+    /// removed files are read from blobs, added files from `workspace` if it is given,
+    /// e.g. for changes of `status`, or from blobs otherwise.
+    pub async fn find_similar_renames(
+        &self,
+        changes: Vec<Change>,
+        threshold: u8,
+        workspace: Option<&Path>,
+    ) -> Result<Vec<Change>, WsvcFsError> {
+        let removed = changes
+            .iter()
+            .filter_map(|c| match c {
+                Change::Removed { path, hash } => Some((path.clone(), hash.clone())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let added = changes
+            .iter()
+            .filter_map(|c| match c {
+                Change::Added { path, hash } => Some((path.clone(), hash.clone())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if removed.is_empty()
+            || added.is_empty()
+            || removed.len() > SIMILARITY_LIMIT
+            || added.len() > SIMILARITY_LIMIT
+        {
+            return Ok(changes);
+        }
+        let mut removed_texts = vec![];
+        for (path, hash) in removed {
+            if let Some(text) = text_of(self.read_blob(&hash).await?) {
+                removed_texts.push((path, hash, text));
+            }
+        }
+        let mut attributes = match workspace {
+            Some(workspace) => self.load_attributes(workspace).await?,
+            None => Attributes::default(),
+        };
+        let mut added_texts = vec![];
+        for (path, hash) in added {
+            let data = match workspace {
+                Some(workspace) => {
+                    attributes.enter_path(workspace, &path).await?;
+                    let data = read(workspace.join(&path)).await?;
+                    attributes.clean(&path, data).await?
+                }
+                None => self.read_blob(&hash).await?,
+            };
+            if let Some(text) = text_of(data) {
+                added_texts.push((path, hash, text));
+            }
+        }
+        let mut pairs = vec![];
+        for (i, (_, _, old)) in removed_texts.iter().enumerate() {
+            for (j, (_, _, new)) in added_texts.iter().enumerate() {
+                // files of very different sizes can not be similar enough.
+                let (short, long) = (old.len().min(new.len()), old.len().max(new.len()));
+                if long > 0 && short * 100 < long * threshold as usize {
+                    continue;
+                }
+                let score = similarity(old, new);
+                if score >= threshold {
+                    pairs.push((score, i, j));
+                }
+            }
+        }
+        pairs.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+        let (mut used_removed, mut used_added) = (HashSet::new(), HashSet::new());
+        let mut moves = HashMap::new();
+        for (score, i, j) in pairs {
+            if used_removed.contains(&i) || used_added.contains(&j) {
+                continue;
+            }
+            used_removed.insert(i);
+            used_added.insert(j);
+            let (old_path, old_hash, _) = &removed_texts[i];
+            let (new_path, new_hash, _) = &added_texts[j];
+            moves.insert(
+                new_path.clone(),
+                Change::Moved {
+                    old_path: old_path.clone(),
+                    new_path: new_path.clone(),
+                    old_hash: old_hash.clone(),
+                    new_hash: new_hash.clone(),
+                    similarity: score,
+                },
+            );
+        }
+        let moved = moves
+            .values()
+            .filter_map(|c| match c {
+                Change::Moved { old_path, .. } => Some(old_path.clone()),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        let mut result = vec![];
+        for change in changes {
+            match change {
+                Change::Added { path, .. } if moves.contains_key(&path) => {
+                    result.push(moves.remove(&path).unwrap());
+                }
+                Change::Removed { path, .. } if moved.contains(&path) => {}
+                change => result.push(change),
+            }
+        }
+        result.sort_by(|a, b| a.path().cmp(b.path()));
+        Ok(result)
+    }
+
+    /// changes from the snapshot of record `from` to the one of record `to`.
+    pub async fn changes_between(
+        &self,
+        from: &ObjectId,
+        to: &ObjectId,
+    ) -> Result<Vec<Change>, WsvcFsError> {
+        let from = self.read_record(from).await?;
+        let to = self.read_record(to).await?;
+        self.changes_of_trees(Some(&from.root), Some(&to.root))
+            .await
+    }
+
+    /// changes from tree `from` to tree `to`, `None` stands for an empty tree. subtrees of
+    /// the same hash are skipped without reading them.
+    pub async fn changes_of_trees(
+        &self,
+        from: Option<&ObjectId>,
+        to: Option<&ObjectId>,
+    ) -> Result<Vec<Change>, WsvcFsError> {
+        let mut changes = Vec::new();
+        let mut queue = vec![(String::new(), from.cloned(), to.cloned())];
+        while let Some((prefix, from, to)) = queue.pop() {
+            if from == to {
+                continue;
+            }
+            let mut entries = BTreeMap::<String, (Option<Entry>, Option<Entry>)>::new();
+            let (mut from_empty, mut to_empty) = (false, false);
+            if let Some(from) = &from {
+                let tree = self.read_tree(from).await?;
+                from_empty = tree.entries.is_empty();
+                for entry in tree.entries {
+                    let name = entry.name.clone();
+                    entries.entry(name).or_default().0 = Some(entry);
+                }
+            }
+            if let Some(to) = &to {
+                let tree = self.read_tree(to).await?;
+                to_empty = tree.entries.is_empty();
+                for entry in tree.entries {
+                    let name = entry.name.clone();
+                    entries.entry(name).or_default().1 = Some(entry);
+                }
+            }
+            if !prefix.is_empty() && from_empty != to_empty {
+                let path = prefix.clone();
+                changes.push(match to_empty {
+                    true => Change::DirAdded { path },
+                    false => Change::DirRemoved { path },
+                });
+            }
+            for (name, (old, new)) in entries {
+                let path = join_path(&prefix, &name);
+                let is_dir =
+                    |e: &Option<Entry>| e.as_ref().is_some_and(|e| e.kind == EntryKind::Dir);
+                let (old_dir, new_dir) = (is_dir(&old), is_dir(&new));
+                if old_dir || new_dir {
+                    let old_tree = old.as_ref().filter(|_| old_dir).map(|e| e.hash.clone());
+                    let new_tree = new.as_ref().filter(|_| new_dir).map(|e| e.hash.clone());
+                    queue.push((path.clone(), old_tree, new_tree));
+                }
+                match (old.filter(|_| !old_dir), new.filter(|_| !new_dir)) {
+                    (Some(old), Some(new)) => {
+                        if old.hash != new.hash || old.kind != new.kind || old.mode != new.mode {
+                            changes.push(Change::Modified {
+                                path,
+                                old_hash: old.hash,
+                                new_hash: new.hash,
+                            });
+                        }
+                    }
+                    (Some(old), None) => changes.push(Change::Removed {
+                        path,
+                        hash: old.hash,
+                    }),
+                    (None, Some(new)) => changes.push(Change::Added {
+                        path,
+                        hash: new.hash,
+                    }),
+                    (None, None) => {}
+                }
+            }
+        }
+        changes.sort_by(|a, b| a.path().cmp(b.path()));
+        let mut changes = detect_renames(changes);
+        changes.sort_by(|a, b| a.path().cmp(b.path()));
+        Ok(changes)
+    }
+}
+
+impl Repository {
+    /// paths of `changes` that can not be applied to `workspace`, in order of `changes`.
+    ///
+    /// a workspace file is untouched if it has the content before the change, or already
+    /// has the content after the change.
+    pub async fn conflicting_changes(&self, workspace: &Path, changes: &[Change]) -> Vec<String> {
+        let mut attributes = self.load_attributes(workspace).await.unwrap_or_default();
+        for change in changes {
+            if let Change::Renamed { old_path, .. } = change {
+                attributes.enter_path(workspace, old_path).await.ok();
+            }
+            attributes.enter_path(workspace, change.path()).await.ok();
+        }
+        let mut conflicts = vec![];
+        for change in changes {
+            let untouched = |path: &str, hash: Option<&ObjectId>| {
+                let full_path = workspace.join(path);
+                let (path, hash) = (path.to_owned(), hash.cloned());
+                let attributes = &attributes;
+                async move { has_content(attributes, &full_path, &path, hash.as_ref()).await }
+            };
+            let clean = match change {
+                Change::Added { path, hash } => {
+                    untouched(path, None).await || untouched(path, Some(hash)).await
+                }
+                Change::Removed { path, hash } => {
+                    untouched(path, Some(hash)).await || untouched(path, None).await
+                }
+                Change::Modified {
+                    path,
+                    old_hash,
+                    new_hash,
+                } => untouched(path, Some(old_hash)).await || untouched(path, Some(new_hash)).await,
+                Change::Renamed {
+                    old_path,
+                    new_path,
+                    hash,
+                } => untouched(old_path, Some(hash)).await && untouched(new_path, None).await,
+                Change::Moved {
+                    old_path,
+                    new_path,
+                    old_hash,
+                    ..
+                } => untouched(old_path, Some(old_hash)).await && untouched(new_path, None).await,
+                Change::DirAdded { path } | Change::DirRemoved { path } => {
+                    let full_path = workspace.join(path);
+                    !full_path.exists() || full_path.is_dir()
+                }
+            };
+            if !clean {
+                conflicts.push(change.path().to_owned());
+            }
+        }
+        conflicts
+    }
+
+    /// write `changes` to `workspace`, blobs of added and modified files are checked out.
+    /// dirs left empty by removed files are removed, then empty dirs are removed and added
+    /// as the changes say, a removed dir is kept if it has files.
+    ///
+    /// check the changes with `conflicting_changes` first, workspace changes of the paths
+    /// are overwritten.
+    pub async fn apply_changes(
+        &self,
+        workspace: &Path,
+        changes: Vec<Change>,
+    ) -> Result<(), WsvcFsError> {
+        let mut attributes = self.load_attributes(workspace).await?;
+        let mut dir_changes = vec![];
+        for change in changes {
+            match change {
+                Change::DirAdded { .. } | Change::DirRemoved { .. } => dir_changes.push(change),
+                Change::Added { path, hash }
+                | Change::Modified {
+                    path,
+                    new_hash: hash,
+                    ..
+                } => {
+                    if let Some(parent) = workspace.join(&path).parent() {
+                        create_dir_all(parent).await?;
+                    }
+                    self.checkout_blob(&hash, workspace, &path).await?;
+                    attributes.enter_path(workspace, &path).await?;
+                    attributes
+                        .smudge_file(&workspace.join(&path), &path)
+                        .await?;
+                }
+                Change::Removed { path, .. } => {
+                    let path = workspace.join(path);
+                    if path.exists() {
+                        remove_file(&path).await?;
+                    }
+                    remove_empty_dirs(workspace, &path).await;
+                }
+                Change::Renamed {
+                    old_path, new_path, ..
+                } => {
+                    let (old_path, new_path) = (workspace.join(old_path), workspace.join(new_path));
+                    if let Some(parent) = new_path.parent() {
+                        create_dir_all(parent).await?;
+                    }
+                    rename(&old_path, new_path).await?;
+                    remove_empty_dirs(workspace, &old_path).await;
+                }
+                Change::Moved {
+                    old_path,
+                    new_path,
+                    new_hash,
+                    ..
+                } => {
+                    if let Some(parent) = workspace.join(&new_path).parent() {
+                        create_dir_all(parent).await?;
+                    }
+                    self.checkout_blob(&new_hash, workspace, &new_path).await?;
+                    attributes.enter_path(workspace, &new_path).await?;
+                    attributes
+                        .smudge_file(&workspace.join(&new_path), &new_path)
+                        .await?;
+                    let old_path = workspace.join(old_path);
+                    if old_path.exists() {
+                        remove_file(&old_path).await?;
+                    }
+                    remove_empty_dirs(workspace, &old_path).await;
+                }
+            }
+        }
+        // removed first, so dirs left empty by a removal can be added back.
+        dir_changes.sort_by_key(|change| matches!(change, Change::DirAdded { .. }));
+        for change in dir_changes {
+            match change {
+                Change::DirAdded { path } => create_dir_all(workspace.join(path)).await?,
+                Change::DirRemoved { path } => {
+                    let path = workspace.join(path);
+                    if path.is_dir() && remove_dir(&path).await.is_ok() {
+                        remove_empty_dirs(workspace, &path).await;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ReadOnlyRepository {
+    pub async fn find_similar_renames(
+        &self,
+        changes: Vec<Change>,
+        threshold: u8,
+        workspace: Option<&Path>,
+    ) -> Result<Vec<Change>, WsvcFsError> {
+        self.repository()
+            .find_similar_renames(changes, threshold, workspace)
+            .await
+    }
+
+    pub async fn changes_between(
+        &self,
+        from: &ObjectId,
+        to: &ObjectId,
+    ) -> Result<Vec<Change>, WsvcFsError> {
+        self.repository().changes_between(from, to).await
+    }
+
+    pub async fn changes_of_trees(
+        &self,
+        from: Option<&ObjectId>,
+        to: Option<&ObjectId>,
+    ) -> Result<Vec<Change>, WsvcFsError> {
+        self.repository().changes_of_trees(from, to).await
+    }
+}
@@ -0,0 +1,161 @@
+use ciborium::Value;
+use serde::{
+    de::{
+        self,
+        value::{MapDeserializer, SeqDeserializer, U32Deserializer},
+        DeserializeOwned, IntoDeserializer, Visitor,
+    },
+    forward_to_deserialize_any, Deserialize, Deserializer, Serialize,
+};
+
+use crate::fs::WsvcFsError;
+
+/// leading bytes of binary encoded trees and records, json ones start with `{`.
+pub const BINARY_MAGIC: [u8; 4] = *b"WSVB";
+
+/// version of the binary encoding, the byte following `BINARY_MAGIC`.
+pub const BINARY_VERSION: u8 = 2;
+
+/// version of packed cbor objects, where struct fields and enum variants are stored by
+/// their index. still read, never written.
+pub const PACKED_BINARY_VERSION: u8 = 1;
+
+/// `ObjectFormat` stand for how trees and records are stored on disk.
+///
+/// hashes are always of the json encoding, so a tree or record has the same hash in
+/// either format, and objects of both formats are read back the same way.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ObjectFormat {
+    /// json, the format of objects stored by every wsvc version.
+    #[default]
+    Json,
+    /// cbor led by `BINARY_MAGIC` and the version. smaller and faster to read, but not
+    /// readable by wsvc versions without it.
+    Binary,
+}
+
+/// encode a tree or record to store it in `format`.
+pub fn encode_object<T: Serialize>(
+    value: &T,
+    format: ObjectFormat,
+) -> Result<Vec<u8>, WsvcFsError> {
+    match format {
+        ObjectFormat::Json => Ok(serde_json::to_vec(value)?),
+        ObjectFormat::Binary => {
+            let mut result = BINARY_MAGIC.to_vec();
+            result.push(BINARY_VERSION);
+            ciborium::into_writer(value, &mut result)
+                .map_err(|err| WsvcFsError::DecodeFailed(err.to_string()))?;
+            Ok(result)
+        }
+    }
+}
+
+/// decode a stored tree or record of either format.
+pub fn decode_object<T: DeserializeOwned>(content: &[u8]) -> Result<T, WsvcFsError> {
+    match content.strip_prefix(&BINARY_MAGIC) {
+        None => Ok(serde_json::from_slice(content)?),
+        Some([BINARY_VERSION, rest @ ..]) => {
+            ciborium::from_reader(rest).map_err(|err| WsvcFsError::DecodeFailed(err.to_string()))
+        }
+        Some([PACKED_BINARY_VERSION, rest @ ..]) => {
+            let value: Value = ciborium::from_reader(rest)
+                .map_err(|err| WsvcFsError::DecodeFailed(err.to_string()))?;
+            T::deserialize(PackedValue(value))
+                .map_err(|err| WsvcFsError::DecodeFailed(err.to_string()))
+        }
+        Some(_) => Err(WsvcFsError::DecodeFailed(
+            "unknown version of binary encoding".to_owned(),
+        )),
+    }
+}
+
+/// the json encoding of an object stored as `content`, which its hash is of.
+pub fn json_content<T: Serialize + DeserializeOwned>(
+    content: &[u8],
+) -> Result<Vec<u8>, WsvcFsError> {
+    match content.starts_with(&BINARY_MAGIC) {
+        true => encode_object(&decode_object::<T>(content)?, ObjectFormat::Json),
+        false => Ok(content.to_vec()),
+    }
+}
+
+/// `PackedValue` stand for a decoded packed cbor value, deserialized by handing integer
+/// field and variant indices to the visitors of the target type, which accept them.
+struct PackedValue(Value);
+
+impl<'de> IntoDeserializer<'de, de::value::Error> for PackedValue {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> Deserializer<'de> for PackedValue {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Integer(n) => match u64::try_from(n) {
+                Ok(n) => visitor.visit_u64(n),
+                Err(_) => visitor.visit_i64(
+                    i64::try_from(n).map_err(|_| de::Error::custom("integer out of range"))?,
+                ),
+            },
+            Value::Bytes(bytes) => visitor.visit_byte_buf(bytes),
+            Value::Float(f) => visitor.visit_f64(f),
+            Value::Text(text) => visitor.visit_string(text),
+            Value::Bool(b) => visitor.visit_bool(b),
+            Value::Null => visitor.visit_unit(),
+            Value::Tag(_, value) => PackedValue(*value).deserialize_any(visitor),
+            Value::Array(items) => {
+                let mut seq = SeqDeserializer::new(items.into_iter().map(PackedValue));
+                let result = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(result)
+            }
+            Value::Map(entries) => {
+                let mut map = MapDeserializer::new(
+                    entries
+                        .into_iter()
+                        .map(|(k, v)| (PackedValue(k), PackedValue(v))),
+                );
+                let result = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(result)
+            }
+            _ => Err(de::Error::custom("unsupported cbor value")),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        // unit variants are packed as their index.
+        match self.0 {
+            Value::Integer(n) => visitor.visit_enum(U32Deserializer::new(
+                u32::try_from(n).map_err(|_| de::Error::custom("variant index out of range"))?,
+            )),
+            Value::Text(text) => visitor.visit_enum(text.into_deserializer()),
+            _ => Err(de::Error::custom("unsupported enum variant")),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
@@ -0,0 +1,116 @@
+use std::path::PathBuf;
+
+use chrono::serde::ts_seconds::{deserialize as from_ts, serialize as to_ts};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{metadata, read_to_string, rename, OpenOptions},
+    io::AsyncWriteExt,
+};
+
+use crate::{
+    fs::WsvcFsError,
+    model::{ObjectId, Repository},
+    readonly::ReadOnlyRepository,
+};
+
+/// file name of the event journal in the repository dir.
+pub const EVENTS_FILE: &str = "events.log";
+
+/// the journal is rotated to `events.log.1` once it grows larger than this, so the journal
+/// takes at most about twice of it.
+pub const EVENTS_MAX_SIZE: u64 = 1024 * 1024;
+
+/// `Event` stand for something done to a repository, stored as a line of json.
+///
+/// ```json
+/// {"date":1700000000,"event":"commit","record":"<hash>","author":"alice","message":"fix"}
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Event {
+    #[serde(deserialize_with = "from_ts", serialize_with = "to_ts")]
+    pub date: DateTime<Utc>,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum EventKind {
+    Commit {
+        record: ObjectId,
+        author: String,
+        message: String,
+    },
+    Checkout {
+        record: ObjectId,
+    },
+    Sync {
+        remote: String,
+        /// count of records received from the remote.
+        received: usize,
+    },
+    Gc {
+        trees: usize,
+        blobs: usize,
+    },
+}
+
+impl Repository {
+    /// the current journal file, the rotated one has the `.1` suffix.
+    pub fn events_path(&self) -> PathBuf {
+        self.path.join(EVENTS_FILE)
+    }
+
+    /// append an event to the journal, rotating it if it is too large.
+    pub async fn append_event(&self, kind: EventKind) -> Result<(), WsvcFsError> {
+        self.check_writable()?;
+        let path = self.events_path();
+        if metadata(&path)
+            .await
+            .is_ok_and(|m| m.len() >= EVENTS_MAX_SIZE)
+        {
+            rename(&path, path.with_extension("log.1")).await?;
+        }
+        let mut line = serde_json::to_vec(&Event {
+            date: Utc::now(),
+            kind,
+        })?;
+        line.push(b'\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        file.write_all(&line).await?;
+        Ok(())
+    }
+
+    /// events of the journal, oldest first. lines that are not events are skipped.
+    pub async fn read_events(&self) -> Result<Vec<Event>, WsvcFsError> {
+        let path = self.events_path();
+        let mut result = Vec::new();
+        for path in [path.with_extension("log.1"), path] {
+            if !path.is_file() {
+                continue;
+            }
+            result.extend(
+                read_to_string(&path)
+                    .await?
+                    .lines()
+                    .filter_map(|line| serde_json::from_str(line).ok()),
+            );
+        }
+        Ok(result)
+    }
+}
+
+impl ReadOnlyRepository {
+    pub fn events_path(&self) -> PathBuf {
+        self.path().join(EVENTS_FILE)
+    }
+
+    pub async fn read_events(&self) -> Result<Vec<Event>, WsvcFsError> {
+        self.repository().read_events().await
+    }
+}
@@ -0,0 +1,425 @@
+use std::{
+    collections::HashMap,
+    process::Stdio,
+    sync::{Mutex, OnceLock},
+};
+
+use chacha20poly1305::{aead::Aead, ChaCha20Poly1305, Key, KeyInit, Nonce};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use crate::{binary::is_binary, fs::WsvcFsError, model::Record};
+
+/// env var of the passphrase of the `encrypt` filter, unless a driver sets `key_env`.
+pub const DEFAULT_KEY_ENV: &str = "WSVC_FILTER_KEY";
+
+/// leading bytes of content sealed by the `encrypt` filter.
+const ENCRYPTED_MAGIC: &[u8] = b"WSVCENC2";
+
+/// leading bytes of content sealed by earlier versions with a key hashed from the
+/// passphrase without salt, still opened.
+const UNSALTED_MAGIC: &[u8] = b"WSVCENC1";
+
+/// minimum bytes of the salt of the `encrypt` filter.
+const MIN_SALT_LEN: usize = 16;
+
+/// keywords expanded by the `keywords` filter, as `$WsvcHash$` in files.
+pub const KEYWORDS: [&str; 3] = ["WsvcHash", "WsvcDate", "WsvcAuthor"];
+
+/// `BuiltinFilter` stand for the filters wsvc runs itself, usable by name with `filter=`
+/// without any driver.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BuiltinFilter {
+    /// seal files with ChaCha20-Poly1305 when recording and open them when checking out.
+    ///
+    /// the key is the passphrase stretched with argon2id, salted by the `salt` of the
+    /// driver. the nonce is derived from the key and the content, so the same content is always
+    /// recorded as the same blob and unchanged files are not reported as modified.
+    Encrypt,
+    /// clear outputs and execution counts of jupyter notebooks when recording, checking
+    /// out keeps the recorded content.
+    StripOutputs,
+    /// expand `KEYWORDS` of text files to `$WsvcHash: <hash> $` with the record checked
+    /// out, and collapse them to `$WsvcHash$` when recording.
+    Keywords,
+}
+
+impl BuiltinFilter {
+    /// the builtin filter named `name`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "encrypt" => Some(BuiltinFilter::Encrypt),
+            "strip-outputs" => Some(BuiltinFilter::StripOutputs),
+            "keywords" => Some(BuiltinFilter::Keywords),
+            _ => None,
+        }
+    }
+}
+
+/// `FilterDriver` stand for a content filter of paths with a `filter=<name>` attribute,
+/// see `RepositoryBuilder::filter`.
+///
+/// `clean` runs when recording a workspace file and `smudge` when checking it out. a
+/// command is run by the shell with the content on stdin and the converted content read
+/// from stdout, `%f` in it stands for the path relative to the workspace root, passed
+/// as an argument and in `WSVC_FILTER_PATH` rather than pasted into the command. a
+/// missing command leaves the content as is.
+///
+/// ```toml
+/// [filter.secret]
+/// builtin = "encrypt"
+/// key_env = "SECRET_KEY"
+/// salt = "9b1f0c6e4a7d2b85e3c0f19a6d4b7e21"
+///
+/// [filter.compress]
+/// clean = "gzip -cn"
+/// smudge = "gzip -cd"
+/// ```
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct FilterDriver {
+    pub clean: Option<String>,
+    pub smudge: Option<String>,
+    /// a builtin filter run instead of the commands.
+    pub builtin: Option<BuiltinFilter>,
+    /// env var of the passphrase of the `encrypt` filter, `WSVC_FILTER_KEY` if not set.
+    pub key_env: Option<String>,
+    /// salt of the passphrase of the `encrypt` filter, at least 16 bytes, e.g. random hex.
+    /// clones must use the same salt to open the files.
+    pub salt: Option<String>,
+}
+
+impl FilterDriver {
+    /// a driver running the builtin filter `filter`.
+    pub fn builtin(filter: BuiltinFilter) -> Self {
+        Self {
+            builtin: Some(filter),
+            ..Default::default()
+        }
+    }
+
+    /// the content of the workspace file at `path` as recorded.
+    pub async fn clean(&self, path: &str, data: Vec<u8>) -> Result<Vec<u8>, WsvcFsError> {
+        match self.builtin {
+            Some(BuiltinFilter::Encrypt) => self.encrypt(path, data),
+            Some(BuiltinFilter::StripOutputs) => Ok(strip_outputs(data)),
+            Some(BuiltinFilter::Keywords) => Ok(replace_keywords(data, |_| None)),
+            None => match &self.clean {
+                Some(command) => run_filter(command, path, data).await,
+                None => Ok(data),
+            },
+        }
+    }
+
+    /// the content of the recorded file at `path` as checked out, `record` is the record
+    /// checked out if known.
+    pub async fn smudge(
+        &self,
+        path: &str,
+        data: Vec<u8>,
+        record: Option<&Record>,
+    ) -> Result<Vec<u8>, WsvcFsError> {
+        match self.builtin {
+            Some(BuiltinFilter::Encrypt) => self.decrypt(path, data),
+            Some(BuiltinFilter::StripOutputs) => Ok(data),
+            Some(BuiltinFilter::Keywords) => Ok(replace_keywords(data, |keyword| {
+                let record = record?;
+                Some(match keyword {
+                    "WsvcHash" => record.hash.0.to_hex().to_string(),
+                    "WsvcDate" => record.date.format("%Y-%m-%d %H:%M:%S %z").to_string(),
+                    _ => record.author.clone(),
+                })
+            })),
+            None => match &self.smudge {
+                Some(command) => run_filter(command, path, data).await,
+                None => Ok(data),
+            },
+        }
+    }
+
+    /// the passphrase of the filter, read from its env var.
+    fn passphrase(&self, path: &str) -> Result<String, WsvcFsError> {
+        let key_env = self.key_env.as_deref().unwrap_or(DEFAULT_KEY_ENV);
+        std::env::var(key_env).map_err(|_| {
+            WsvcFsError::FilterFailed(format!(
+                "{} is encrypted but {} is not set to the passphrase",
+                path, key_env
+            ))
+        })
+    }
+
+    fn cipher(&self, path: &str) -> Result<(ChaCha20Poly1305, [u8; 32]), WsvcFsError> {
+        let passphrase = self.passphrase(path)?;
+        let salt = self
+            .salt
+            .as_deref()
+            .filter(|salt| salt.len() >= MIN_SALT_LEN)
+            .ok_or_else(|| {
+                WsvcFsError::FilterFailed(format!(
+                    "{} is encrypted but its filter has no salt of {} bytes or more",
+                    path, MIN_SALT_LEN
+                ))
+            })?;
+        let key = stretch_key(&passphrase, salt.as_bytes())
+            .map_err(|err| WsvcFsError::FilterFailed(format!("{}: {}", path, err)))?;
+        Ok((ChaCha20Poly1305::new(Key::from_slice(&key)), key))
+    }
+
+    /// the cipher of content sealed by earlier versions.
+    fn unsalted_cipher(&self, path: &str) -> Result<ChaCha20Poly1305, WsvcFsError> {
+        let passphrase = self.passphrase(path)?;
+        let key = blake3::derive_key("wsvc 2023 filter encryption key", passphrase.as_bytes());
+        Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+    }
+
+    /// seal `data`, content sealed already is kept as is.
+    fn encrypt(&self, path: &str, data: Vec<u8>) -> Result<Vec<u8>, WsvcFsError> {
+        if data.starts_with(ENCRYPTED_MAGIC) || data.starts_with(UNSALTED_MAGIC) {
+            return Ok(data);
+        }
+        let (cipher, key) = self.cipher(path)?;
+        let digest = blake3::keyed_hash(&key, &data);
+        let nonce = Nonce::from_slice(&digest.as_bytes()[..12]);
+        let sealed = cipher
+            .encrypt(nonce, data.as_slice())
+            .map_err(|_| WsvcFsError::FilterFailed(format!("failed to encrypt {}", path)))?;
+        let mut result = Vec::with_capacity(ENCRYPTED_MAGIC.len() + 12 + sealed.len());
+        result.extend_from_slice(ENCRYPTED_MAGIC);
+        result.extend_from_slice(nonce);
+        result.extend_from_slice(&sealed);
+        Ok(result)
+    }
+
+    /// open `data`, content not sealed, e.g. recorded before the filter was set, is kept
+    /// as is.
+    fn decrypt(&self, path: &str, data: Vec<u8>) -> Result<Vec<u8>, WsvcFsError> {
+        let (cipher, rest) = match (
+            data.strip_prefix(ENCRYPTED_MAGIC),
+            data.strip_prefix(UNSALTED_MAGIC),
+        ) {
+            (Some(rest), _) => (self.cipher(path)?.0, rest),
+            (None, Some(rest)) => (self.unsalted_cipher(path)?, rest),
+            (None, None) => return Ok(data),
+        };
+        if rest.len() < 12 {
+            return Err(WsvcFsError::FilterFailed(format!("{} is truncated", path)));
+        }
+        let (nonce, sealed) = rest.split_at(12);
+        cipher
+            .decrypt(Nonce::from_slice(nonce), sealed)
+            .map_err(|_| {
+                WsvcFsError::FilterFailed(format!(
+                    "failed to decrypt {}, passphrase mismatch?",
+                    path
+                ))
+            })
+    }
+}
+
+/// keys stretched by `stretch_key` by their passphrase and salt.
+type StretchedKeys = HashMap<(String, Vec<u8>), [u8; 32]>;
+
+/// the key of `passphrase` stretched with argon2id salted by `salt`. keys are kept for
+/// the process, stretching takes a while and every filtered file needs the key.
+fn stretch_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32], argon2::Error> {
+    static KEYS: OnceLock<Mutex<StretchedKeys>> = OnceLock::new();
+    let keys = KEYS.get_or_init(Default::default);
+    let id = (passphrase.to_owned(), salt.to_vec());
+    if let Some(key) = keys.lock().expect("keys are never poisoned").get(&id) {
+        return Ok(*key);
+    }
+    let mut key = [0u8; 32];
+    argon2::Argon2::default().hash_password_into(passphrase.as_bytes(), salt, &mut key)?;
+    keys.lock()
+        .expect("keys are never poisoned")
+        .insert(id, key);
+    Ok(key)
+}
+
+/// clear `outputs` and `execution_count` of the cells of a notebook, content that is not
+/// a notebook is kept as is.
+fn strip_outputs(data: Vec<u8>) -> Vec<u8> {
+    let Ok(mut notebook) = serde_json::from_slice::<Value>(&data) else {
+        return data;
+    };
+    let Some(cells) = notebook.get_mut("cells").and_then(Value::as_array_mut) else {
+        return data;
+    };
+    for cell in cells.iter_mut().filter_map(Value::as_object_mut) {
+        if let Some(outputs) = cell.get_mut("outputs") {
+            *outputs = Value::Array(vec![]);
+        }
+        if let Some(count) = cell.get_mut("execution_count") {
+            *count = Value::Null;
+        }
+    }
+    // notebooks are written with one space indent and a trailing newline.
+    let mut result = vec![];
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(b" ");
+    let mut serializer = serde_json::Serializer::with_formatter(&mut result, formatter);
+    if notebook.serialize(&mut serializer).is_err() {
+        return data;
+    }
+    result.push(b'\n');
+    result
+}
+
+/// replace the keywords of a text file, collapsed like `$WsvcHash$` or expanded like
+/// `$WsvcHash: <value> $`, by `$keyword: <value> $` if `value` gives one, or collapse
+/// them otherwise. binary files are kept as is.
+fn replace_keywords(data: Vec<u8>, value: impl Fn(&str) -> Option<String>) -> Vec<u8> {
+    if is_binary(&data) || !data.contains(&b'$') {
+        return data;
+    }
+    let text = match String::from_utf8(data) {
+        Ok(text) => text,
+        Err(e) => return e.into_bytes(),
+    };
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text.as_str();
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let found = KEYWORDS.iter().find_map(|keyword| {
+            let after = rest[1..].strip_prefix(keyword)?;
+            let len = if after.starts_with('$') {
+                0
+            } else {
+                // an expanded keyword ends at the next `$` of the line.
+                let value = after.strip_prefix(": ")?;
+                value
+                    .find(['$', '\n'])
+                    .filter(|&end| value[end..].starts_with('$'))?
+                    + 2
+            };
+            Some((*keyword, 1 + keyword.len() + len + 1))
+        });
+        match found {
+            Some((keyword, len)) => {
+                match value(keyword) {
+                    Some(value) => result.push_str(&format!("${}: {} $", keyword, value)),
+                    None => result.push_str(&format!("${}$", keyword)),
+                }
+                rest = &rest[len..];
+            }
+            None => {
+                result.push('$');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result.into_bytes()
+}
+
+/// env var holding the path a filter command runs for.
+pub const FILTER_PATH_ENV: &str = "WSVC_FILTER_PATH";
+
+/// run the filter `command` of `path` by the shell, writing `data` to its stdin.
+///
+/// paths come from repository content, so they are never pasted into the command. `%f`
+/// stands for the quoted first argument of the shell, which is the path.
+async fn run_filter(command: &str, path: &str, data: Vec<u8>) -> Result<Vec<u8>, WsvcFsError> {
+    let mut child = if cfg!(windows) {
+        // cmd expands env vars before parsing the command, the path must be inert.
+        if path.contains(['"', '%', '&', '|', '<', '>', '^', '!', '\n', '\r']) {
+            return Err(WsvcFsError::FilterFailed(format!(
+                "{} has chars the shell would run, not filtered",
+                path
+            )));
+        }
+        let mut child = Command::new("cmd");
+        child
+            .arg("/C")
+            .arg(command.replace("%f", &format!("\"%{}%\"", FILTER_PATH_ENV)));
+        child
+    } else {
+        let mut child = Command::new("sh");
+        child
+            .arg("-c")
+            .arg(command.replace("%f", "\"$1\""))
+            .arg("sh")
+            .arg(path);
+        child
+    }
+    .env(FILTER_PATH_ENV, path)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::inherit())
+    .spawn()?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // written while the output is read, so a filter filling its stdout never blocks.
+    let writer = tokio::spawn(async move {
+        let result = stdin.write_all(&data).await;
+        drop(stdin);
+        result
+    });
+    let output = child.wait_with_output().await?;
+    // a filter may exit without reading all of its input.
+    let _ = writer.await;
+    if !output.status.success() {
+        return Err(WsvcFsError::FilterFailed(format!(
+            "`{}` of {} exited with {}",
+            command, path, output.status
+        )));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn paths_are_never_run_by_the_shell() {
+        let marker = std::env::temp_dir().join(format!("wsvc-test-{}", nanoid::nanoid!()));
+        let path = format!(
+            "x;touch {};$(touch {})`touch {}`",
+            marker.display(),
+            marker.display(),
+            marker.display()
+        );
+        let output = run_filter("cat >/dev/null; printf '%s' %f", &path, b"data".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(output, path.as_bytes());
+        assert!(!marker.exists());
+        let output = run_filter("printf '%s' \"$WSVC_FILTER_PATH\"", &path, vec![])
+            .await
+            .unwrap();
+        assert_eq!(output, path.as_bytes());
+        assert!(!marker.exists());
+    }
+
+    fn encrypt_filter(salt: &str) -> FilterDriver {
+        FilterDriver {
+            builtin: Some(BuiltinFilter::Encrypt),
+            key_env: Some("WSVC_TEST_ENCRYPT_KEY".to_owned()),
+            salt: Some(salt.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn encrypt_keys_are_salted() {
+        std::env::set_var("WSVC_TEST_ENCRYPT_KEY", "passphrase");
+        let filter = encrypt_filter("0123456789abcdef0123456789abcdef");
+        let sealed = filter.clean("a", b"secret".to_vec()).await.unwrap();
+        assert!(sealed.starts_with(ENCRYPTED_MAGIC));
+        assert_eq!(filter.clean("a", b"secret".to_vec()).await.unwrap(), sealed);
+        assert_eq!(
+            filter.smudge("a", sealed.clone(), None).await.unwrap(),
+            b"secret"
+        );
+        let other = encrypt_filter("fedcba9876543210fedcba9876543210");
+        assert_ne!(other.clean("a", b"secret".to_vec()).await.unwrap(), sealed);
+        assert!(other.smudge("a", sealed, None).await.is_err());
+        assert!(encrypt_filter("short")
+            .clean("a", b"secret".to_vec())
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,336 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use chacha20poly1305::{aead::Aead, ChaCha20Poly1305, Key, KeyInit, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use crate::WsvcError;
+
+/// version of this wsvc build.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// version of the sync wire protocol of this build, peers use the lower version of both.
+/// version 4 sends files again once if the receiver finds them corrupt. version 3 resumes
+/// files a sync died sending, see `PartialFile`. version 2 has 64 bit sizes in packet and
+/// file headers, version 1 has 32 bit sizes limiting packets and files to 4GiB.
+pub const PROTOCOL_VERSION: u32 = 4;
+
+/// first version of the sync wire protocol sending files again the receiver found corrupt.
+pub const REFETCH_PROTOCOL: u32 = 4;
+
+/// leading bytes of packet headers, followed by the size of the packet.
+pub const PACKET_MAGIC: [u8; 2] = [0x33, 0x07];
+
+/// leading bytes of file headers, followed by the size of the file.
+pub const FILE_MAGIC: [u8; 2] = [0x07, 0x15];
+
+/// header of websocket upgrade responses carrying the version of the wsvc server.
+pub const VERSION_HEADER: &str = "x-wsvc-version";
+
+/// header of websocket upgrade responses listing optional endpoints served beside the
+/// sync, comma separated, e.g. `poll,stats,search`.
+pub const CAPABILITIES_HEADER: &str = "x-wsvc-capabilities";
+
+/// `FrameStream` stand for a connection that carries binary frames, the sync protocol
+/// is built on it so the same rounds could run over different transports.
+#[async_trait]
+pub trait FrameStream: Send {
+    async fn send_frame(&mut self, data: Vec<u8>) -> Result<(), WsvcError>;
+
+    /// receive a binary frame, `None` if the connection is closed.
+    async fn recv_frame(&mut self) -> Result<Option<Vec<u8>>, WsvcError>;
+
+    /// send `(name, path)` files on parallel channels if the transport multiplexes, returns
+    /// `false` if it does not, and the files should be sent as frames instead.
+    async fn send_files(&mut self, _files: &[(String, PathBuf)]) -> Result<bool, WsvcError> {
+        Ok(false)
+    }
+
+    /// receive `count` files sent by `send_files` into `dir`, returns `false` if the
+    /// transport does not multiplex.
+    async fn recv_files(&mut self, _count: usize, _dir: &Path) -> Result<bool, WsvcError> {
+        Ok(false)
+    }
+
+    /// version of the sync wire protocol negotiated on the stream, 1 until negotiated.
+    fn protocol(&self) -> u32 {
+        1
+    }
+}
+
+#[async_trait]
+impl<S: FrameStream + ?Sized> FrameStream for &mut S {
+    async fn send_frame(&mut self, data: Vec<u8>) -> Result<(), WsvcError> {
+        (**self).send_frame(data).await
+    }
+
+    async fn recv_frame(&mut self) -> Result<Option<Vec<u8>>, WsvcError> {
+        (**self).recv_frame().await
+    }
+
+    async fn send_files(&mut self, files: &[(String, PathBuf)]) -> Result<bool, WsvcError> {
+        (**self).send_files(files).await
+    }
+
+    async fn recv_files(&mut self, count: usize, dir: &Path) -> Result<bool, WsvcError> {
+        (**self).recv_files(count, dir).await
+    }
+
+    fn protocol(&self) -> u32 {
+        (**self).protocol()
+    }
+}
+
+/// encode the header of a packet or file of `size` bytes led by `magic`, the size takes 8
+/// bytes since protocol version 2 and 4 bytes before.
+pub fn encode_size_header(magic: [u8; 2], size: u64, protocol: u32) -> Result<Vec<u8>, WsvcError> {
+    let mut header = magic.to_vec();
+    match protocol >= 2 {
+        true => header.extend_from_slice(&size.to_be_bytes()),
+        false => {
+            let size = u32::try_from(size).map_err(|_| {
+                WsvcError::DataError(format!(
+                    "{} bytes can not be sent to a peer of protocol version 1, upgrade it",
+                    size
+                ))
+            })?;
+            header.extend_from_slice(&size.to_be_bytes());
+        }
+    }
+    Ok(header)
+}
+
+/// decode a header encoded by `encode_size_header`, returns the size and the data after
+/// the header, `None` if the frame is not such a header.
+pub fn decode_size_header(frame: &[u8], magic: [u8; 2], protocol: u32) -> Option<(u64, &[u8])> {
+    let rest = frame.strip_prefix(&magic)?;
+    match protocol >= 2 {
+        true => {
+            let (size, rest) = rest.split_first_chunk::<8>()?;
+            Some((u64::from_be_bytes(*size), rest))
+        }
+        false => {
+            let (size, rest) = rest.split_first_chunk::<4>()?;
+            Some((u32::from_be_bytes(*size) as u64, rest))
+        }
+    }
+}
+
+/// encode frames as a batch, each frame is prefixed with its 4 bytes size.
+pub fn encode_frames(frames: &[Vec<u8>]) -> Vec<u8> {
+    let mut batch = Vec::with_capacity(frames.iter().map(|f| f.len() + 4).sum());
+    for frame in frames {
+        batch.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+        batch.extend_from_slice(frame);
+    }
+    batch
+}
+
+/// decode a batch encoded by `encode_frames`.
+pub fn decode_frames(mut batch: &[u8]) -> Result<Vec<Vec<u8>>, WsvcError> {
+    let mut frames = vec![];
+    while !batch.is_empty() {
+        if batch.len() < 4 {
+            return Err(WsvcError::DataError("truncated frame batch".to_owned()));
+        }
+        let size = u32::from_be_bytes([batch[0], batch[1], batch[2], batch[3]]) as usize;
+        if batch.len() < 4 + size {
+            return Err(WsvcError::DataError("truncated frame batch".to_owned()));
+        }
+        frames.push(batch[4..4 + size].to_vec());
+        batch = &batch[4 + size..];
+    }
+    Ok(frames)
+}
+
+#[cfg(feature = "server")]
+#[async_trait]
+impl FrameStream for axum::extract::ws::WebSocket {
+    async fn send_frame(&mut self, data: Vec<u8>) -> Result<(), WsvcError> {
+        self.send(axum::extract::ws::Message::Binary(data))
+            .await
+            .map_err(|err| WsvcError::DataError(err.to_string()))
+    }
+
+    async fn recv_frame(&mut self) -> Result<Option<Vec<u8>>, WsvcError> {
+        use axum::extract::ws::Message;
+        while let Some(msg) = self.recv().await {
+            match msg.map_err(|err| WsvcError::DataError(err.to_string()))? {
+                Message::Binary(data) => return Ok(Some(data)),
+                Message::Close(_) => return Ok(None),
+                _ => continue,
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "cli")]
+#[async_trait]
+impl<S> FrameStream for tokio_tungstenite::WebSocketStream<S>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send,
+{
+    async fn send_frame(&mut self, data: Vec<u8>) -> Result<(), WsvcError> {
+        use futures::SinkExt;
+        self.send(tokio_tungstenite::tungstenite::Message::Binary(data))
+            .await?;
+        Ok(())
+    }
+
+    async fn recv_frame(&mut self) -> Result<Option<Vec<u8>>, WsvcError> {
+        use futures::StreamExt;
+        use tokio_tungstenite::tungstenite::Message;
+        while let Some(msg) = self.next().await {
+            match msg? {
+                Message::Binary(data) => return Ok(Some(data)),
+                Message::Close(_) => return Ok(None),
+                _ => continue,
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// bytes of the random each side of an `EncryptedStream` sends first.
+const HELLO_SIZE: usize = 32;
+
+/// bytes of the number leading each sealed frame.
+const COUNTER_SIZE: usize = 8;
+
+/// `EncryptedStream` encrypts every frame of the inner stream with a passphrase.
+///
+/// both sides first send a random hello in the clear. the passphrase is stretched with
+/// argon2id salted by both hellos, and a key for each direction is derived from it with
+/// HKDF-SHA256, so each session has keys of its own and frames of an earlier session can
+/// not be replayed into another. frames are sealed with ChaCha20-Poly1305 and led by their
+/// number, used as nonce, a frame not numbered right after the previous one is rejected.
+/// the sides are told apart by `initiator`, which must differ on both ends.
+pub struct EncryptedStream<S> {
+    inner: S,
+    sealing: ChaCha20Poly1305,
+    opening: ChaCha20Poly1305,
+    sent: u64,
+    received: u64,
+}
+
+fn handshake_error(message: impl std::fmt::Display) -> WsvcError {
+    WsvcError::DataError(format!("encryption handshake failed: {}", message))
+}
+
+impl<S: FrameStream> EncryptedStream<S> {
+    /// exchange hellos over `inner` and derive the keys of the session from `passphrase`.
+    pub async fn handshake(
+        mut inner: S,
+        passphrase: &str,
+        initiator: bool,
+    ) -> Result<Self, WsvcError> {
+        let mut hello = [0u8; HELLO_SIZE];
+        getrandom::getrandom(&mut hello).map_err(handshake_error)?;
+        inner.send_frame(hello.to_vec()).await?;
+        let peer: [u8; HELLO_SIZE] = inner
+            .recv_frame()
+            .await?
+            .ok_or(handshake_error("connection closed"))?
+            .try_into()
+            .map_err(|_| handshake_error("unexpected hello, does the peer encrypt frames?"))?;
+        // a peer echoing the hello would get the keys of this side.
+        if peer == hello {
+            return Err(handshake_error("the hello was reflected"));
+        }
+        let mut salt = [0u8; HELLO_SIZE * 2];
+        let (first, second) = match initiator {
+            true => (&hello, &peer),
+            false => (&peer, &hello),
+        };
+        salt[..HELLO_SIZE].copy_from_slice(first);
+        salt[HELLO_SIZE..].copy_from_slice(second);
+        // stretching takes tens of milliseconds, off the runtime threads.
+        let passphrase = passphrase.to_owned();
+        let stretched = tokio::task::spawn_blocking(move || {
+            let mut stretched = [0u8; 32];
+            argon2::Argon2::default()
+                .hash_password_into(passphrase.as_bytes(), &salt, &mut stretched)
+                .map(|_| stretched)
+        })
+        .await
+        .map_err(handshake_error)?
+        .map_err(handshake_error)?;
+        let hkdf = Hkdf::<Sha256>::new(Some(&salt), &stretched);
+        let cipher = |info: &[u8]| {
+            let mut key = [0u8; 32];
+            hkdf.expand(info, &mut key)
+                .map(|_| ChaCha20Poly1305::new(Key::from_slice(&key)))
+        };
+        let from_initiator = cipher(b"wsvc frames from initiator").map_err(handshake_error)?;
+        let from_responder = cipher(b"wsvc frames from responder").map_err(handshake_error)?;
+        let (sealing, opening) = match initiator {
+            true => (from_initiator, from_responder),
+            false => (from_responder, from_initiator),
+        };
+        Ok(Self {
+            inner,
+            sealing,
+            opening,
+            sent: 0,
+            received: 0,
+        })
+    }
+
+    fn nonce(counter: u64) -> Nonce {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&counter.to_be_bytes());
+        *Nonce::from_slice(&nonce)
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<S: FrameStream> FrameStream for EncryptedStream<S> {
+    async fn send_frame(&mut self, data: Vec<u8>) -> Result<(), WsvcError> {
+        let counter = self.sent;
+        self.sent += 1;
+        let sealed = self
+            .sealing
+            .encrypt(&Self::nonce(counter), data.as_slice())
+            .map_err(|_| WsvcError::DataError("failed to encrypt frame".to_owned()))?;
+        let mut frame = Vec::with_capacity(COUNTER_SIZE + sealed.len());
+        frame.extend_from_slice(&counter.to_be_bytes());
+        frame.extend_from_slice(&sealed);
+        self.inner.send_frame(frame).await
+    }
+
+    async fn recv_frame(&mut self) -> Result<Option<Vec<u8>>, WsvcError> {
+        let frame = match self.inner.recv_frame().await? {
+            Some(frame) if frame.len() >= COUNTER_SIZE => frame,
+            Some(_) => return Err(WsvcError::DataError("encrypted frame too short".to_owned())),
+            None => return Ok(None),
+        };
+        let (counter, sealed) = frame.split_at(COUNTER_SIZE);
+        let counter = u64::from_be_bytes(counter.try_into().unwrap_or_default());
+        if counter < self.received {
+            return Err(WsvcError::DataError(format!(
+                "replayed frame {}, expected frame {}",
+                counter, self.received
+            )));
+        }
+        if counter != self.received {
+            return Err(WsvcError::DataError(format!(
+                "frame {} out of order, expected frame {}",
+                counter, self.received
+            )));
+        }
+        let data = self
+            .opening
+            .decrypt(&Self::nonce(counter), sealed)
+            .map_err(|_| {
+                WsvcError::DataError("failed to decrypt frame, passphrase mismatch?".to_owned())
+            })?;
+        self.received += 1;
+        Ok(Some(data))
+    }
+}
@@ -1,25 +1,79 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Component, Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
 
 use blake3::{Hash, HexError};
-use miniz_oxide::{deflate::compress_to_vec, inflate::decompress_to_vec};
+use futures::{stream, Stream, StreamExt};
 use nanoid::nanoid;
 use thiserror::Error;
 use tokio::{
-    fs::{create_dir_all, read, read_dir, remove_dir_all, remove_file, rename, write, File},
-    io::{AsyncReadExt, AsyncWriteExt},
+    fs::{
+        create_dir_all, read, read_dir, read_link, remove_dir_all, remove_file, rename,
+        symlink_metadata, write, File,
+    },
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, Take},
 };
 
-use crate::model::Record;
+use crate::{
+    attributes::{Attributes, AutoCrlf},
+    blobless::BlobSource,
+    chunk::{
+        chunks, cut_point, ChunkManifest, ChunkRef, CHUNKED_MAGIC, CHUNKED_SIZE, MAX_CHUNK_SIZE,
+    },
+    compress::{Codec, Compression},
+    encoding::{decode_object, encode_object, ObjectFormat},
+    events::EventKind,
+    filter::FilterDriver,
+    identity::{RepoIdentity, IDENTITY_FILE},
+    ignore::Ignore,
+    model::Record,
+    pack::{
+        check_pack, find_in_index, install_pack, pack_indexes, pack_size, read_index, remove_pack,
+        PackEntry, PackWriter, RepackStats, StorageStats,
+    },
+    refs::write_atomic,
+    size::SizePolicy,
+    stat::StatCache,
+};
 
-use super::model::{Blob, ObjectId, Repository, Tree};
+use super::model::{Blob, Entry, EntryKind, ObjectId, RepoOptions, Repository, Tree};
 
 pub struct RepoGuard {
     pub repo: Repository,
 }
 
+/// below this count of blobs, `missing_objects` checks each blob instead of listing the
+/// objects dir.
+const LIST_OBJECTS_THRESHOLD: usize = 64;
+
+/// count of blobs read at the same time by `read_objects_stream`.
+const READ_OBJECTS_CONCURRENCY: usize = 16;
+
+/// interval of checking the lock again while waiting for other writers.
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
 impl RepoGuard {
+    /// lock the repository, waiting for other writers up to the lock timeout of the repository.
     pub async fn new(repo: &Repository) -> Result<Self, WsvcFsError> {
-        repo.check_lock().await?;
+        let start = Instant::now();
+        loop {
+            match repo.check_lock().await {
+                Err(WsvcFsError::WorkspaceLocked)
+                    if repo
+                        .options
+                        .lock_timeout
+                        .is_some_and(|timeout| start.elapsed() < timeout) =>
+                {
+                    tokio::time::sleep(LOCK_RETRY_INTERVAL).await;
+                }
+                result => break result?,
+            }
+        }
         repo.lock().await?;
         Ok(Self { repo: repo.clone() })
     }
@@ -43,6 +97,8 @@ pub enum WsvcFsError {
     Os(#[from] std::io::Error),
     #[error("decompress error: {0}")]
     DecompressFailed(String),
+    #[error("decode error: {0}")]
+    DecodeFailed(String),
     #[error("unknown path: {0}")]
     UnknownPath(String),
     #[error("invalid hex string: {0}")]
@@ -61,26 +117,80 @@ pub enum WsvcFsError {
     WorkspaceLocked,
     #[error("remote not set")]
     RemoteNotSet,
+    #[error("repository is read-only")]
+    ReadOnly,
+    #[error("ref changed by another writer: {0}")]
+    RefChanged(String),
+    #[error("ref locked by another writer, remove {0} if no writer is running")]
+    RefLocked(String),
+    #[error("content filter failed: {0}")]
+    FilterFailed(String),
+    #[error("files too large: {0}")]
+    FilesTooLarge(String),
+    #[error("object does not match its hash: {0}")]
+    ObjectMismatch(String),
+    #[error("{0}")]
+    OperationInProgress(String),
 }
 
 #[derive(Clone, Debug)]
 struct TreeImpl {
     name: String,
     trees: Vec<TreeImpl>,
-    blobs: Vec<Blob>,
+    files: Vec<Entry>,
+}
+
+/// write `data` to the object `name` through a temp file, replacing the object.
+async fn place_object(
+    objects_dir: &Path,
+    temp: &Path,
+    name: &str,
+    data: &[u8],
+) -> Result<(), WsvcFsError> {
+    let temp_path = temp.join(nanoid!());
+    write(&temp_path, data).await?;
+    rename(&temp_path, objects_dir.join(name)).await?;
+    Ok(())
+}
+
+/// store `data` compressed as the object `hash`, unless it is stored already.
+async fn write_object(
+    objects_dir: &Path,
+    temp: &Path,
+    hash: &Hash,
+    data: &[u8],
+    compression: Compression,
+) -> Result<(), WsvcFsError> {
+    if locate(objects_dir, hash).await?.is_some() {
+        return Ok(());
+    }
+    place_object(
+        objects_dir,
+        temp,
+        &hash.to_hex(),
+        &compression.frames(data)?,
+    )
+    .await
 }
 
 /// Store a blob file to objects dir.
+///
+/// files of `CHUNKED_SIZE` or more are stored in content-defined chunks, see
+/// `store_chunked_file`.
 async fn store_blob_file_impl(
     path: impl AsRef<Path>,
     objects_dir: impl AsRef<Path>,
     temp: impl AsRef<Path>,
+    compression: Compression,
 ) -> Result<ObjectId, WsvcFsError> {
     if !temp.as_ref().exists() {
         create_dir_all(temp.as_ref()).await?;
     }
-    let mut buffer: [u8; 16384] = [0; 16384];
     let mut file = File::open(&path).await?;
+    if file.metadata().await?.len() >= CHUNKED_SIZE {
+        return store_chunked_file(file, objects_dir.as_ref(), temp.as_ref(), compression).await;
+    }
+    let mut buffer = vec![0; compression.codec.frame_size()];
     let compressed_file_path = temp.as_ref().join(nanoid!());
     let mut compressed_file = File::create(&compressed_file_path).await?;
     let mut hasher = blake3::Hasher::new();
@@ -90,16 +200,9 @@ async fn store_blob_file_impl(
             break;
         }
         hasher.update(&buffer[..n]);
-        let compressed_data = compress_to_vec(&buffer[..n], 8);
         compressed_file
-            .write_all(&[
-                0x78,
-                0xda,
-                (&compressed_data.len() / 256).try_into().unwrap(),
-                (&compressed_data.len() % 256).try_into().unwrap(),
-            ])
+            .write_all(&compression.frame(&buffer[..n])?)
             .await?;
-        compressed_file.write_all(&compressed_data).await?;
     }
     let hash = hasher.finalize();
     let blob = objects_dir.as_ref().join(hash.to_hex().as_str());
@@ -107,6 +210,290 @@ async fn store_blob_file_impl(
     Ok(ObjectId(hash))
 }
 
+/// store a file in content-defined chunks, each chunk as an object of its own, and the
+/// manifest of the chunks as the blob object. chunks stored already, e.g. by an earlier
+/// version of the file, are not written again.
+async fn store_chunked_file(
+    mut file: File,
+    objects_dir: &Path,
+    temp: &Path,
+    compression: Compression,
+) -> Result<ObjectId, WsvcFsError> {
+    let mut hasher = blake3::Hasher::new();
+    let mut manifest = ChunkManifest {
+        size: 0,
+        chunks: vec![],
+    };
+    let mut buffer = Vec::with_capacity(2 * MAX_CHUNK_SIZE);
+    let mut eof = false;
+    loop {
+        // a cut point is only found with a whole max chunk ahead.
+        while !eof && buffer.len() < MAX_CHUNK_SIZE {
+            let start = buffer.len();
+            buffer.resize(start + MAX_CHUNK_SIZE, 0);
+            let n = file.read(&mut buffer[start..]).await?;
+            buffer.truncate(start + n);
+            eof = n == 0;
+        }
+        if buffer.is_empty() {
+            break;
+        }
+        let cut = cut_point(&buffer);
+        let chunk = &buffer[..cut];
+        hasher.update(chunk);
+        let chunk_hash = blake3::hash(chunk);
+        write_object(objects_dir, temp, &chunk_hash, chunk, compression).await?;
+        manifest.size += cut as u64;
+        manifest.chunks.push(ChunkRef {
+            hash: ObjectId(chunk_hash),
+            size: cut as u64,
+        });
+        buffer.drain(..cut);
+    }
+    let hash = hasher.finalize();
+    place_object(objects_dir, temp, &hash.to_hex(), &manifest.encode()?).await?;
+    Ok(ObjectId(hash))
+}
+
+/// `ObjectLocation` stand for where a stored object is, a loose file in the objects dir
+/// or an entry of a pack.
+enum ObjectLocation {
+    Loose(PathBuf),
+    /// the index of the pack and the entry of the object in it.
+    Packed(PathBuf, PackEntry),
+}
+
+/// where the object `hash` is stored in `objects_dir`, packs are looked up before loose
+/// objects.
+async fn locate(objects_dir: &Path, hash: &Hash) -> Result<Option<ObjectLocation>, WsvcFsError> {
+    let bytes = hash.as_bytes();
+    for index in pack_indexes(objects_dir).await? {
+        if let Some(entry) = find_in_index(&index, bytes).await? {
+            return Ok(Some(ObjectLocation::Packed(index, entry)));
+        }
+    }
+    let path = objects_dir.join(hash.to_hex().as_str());
+    Ok(path.exists().then_some(ObjectLocation::Loose(path)))
+}
+
+/// a reader of the stored bytes of the object `hash`, wherever it is stored.
+async fn open_object(objects_dir: &Path, hash: &Hash) -> Result<Take<File>, WsvcFsError> {
+    match locate(objects_dir, hash).await? {
+        Some(ObjectLocation::Loose(path)) => Ok(File::open(path).await?.take(u64::MAX)),
+        Some(ObjectLocation::Packed(index, entry)) => {
+            let mut file = File::open(index.with_extension("pack")).await?;
+            file.seek(std::io::SeekFrom::Start(entry.offset)).await?;
+            Ok(file.take(entry.len))
+        }
+        None => Err(WsvcFsError::Os(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("object not found: {}", hash.to_hex()),
+        ))),
+    }
+}
+
+/// the manifest of the blob object `hash`, `None` if the object holds the content.
+async fn read_manifest(
+    objects_dir: &Path,
+    hash: &Hash,
+) -> Result<Option<ChunkManifest>, WsvcFsError> {
+    let mut object = open_object(objects_dir, hash).await?;
+    let mut data = Vec::with_capacity(CHUNKED_MAGIC.len());
+    (&mut object)
+        .take(CHUNKED_MAGIC.len() as u64)
+        .read_to_end(&mut data)
+        .await?;
+    if data != CHUNKED_MAGIC {
+        return Ok(None);
+    }
+    object.read_to_end(&mut data).await?;
+    ChunkManifest::decode(&data)
+}
+
+/// decompress the frames read from `file` to `out`, each frame by the codec its magic
+/// names. frames longer than `MAX_CHUNK_SIZE` are refused before they are read, no frame
+/// of a codec compresses to that much.
+async fn copy_frames(
+    file: &mut (impl AsyncRead + Unpin),
+    out: &mut (impl AsyncWrite + Unpin),
+) -> Result<(), WsvcFsError> {
+    let broken = |_| WsvcFsError::DecompressFailed("broken chunk".to_owned());
+    let mut buffer = vec![];
+    loop {
+        let mut magic = [0; 2];
+        if file.read(&mut magic[..1]).await? == 0 {
+            break;
+        }
+        file.read_exact(&mut magic[1..]).await.map_err(broken)?;
+        let codec = Codec::from_magic(magic)
+            .ok_or_else(|| WsvcFsError::DecompressFailed("magic header not match".to_owned()))?;
+        let mut len = [0; 4];
+        file.read_exact(&mut len[4 - codec.len_bytes()..])
+            .await
+            .map_err(broken)?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_CHUNK_SIZE {
+            return Err(WsvcFsError::DecompressFailed(format!(
+                "frame of {} bytes",
+                len
+            )));
+        }
+        buffer.resize(len, 0);
+        file.read_exact(&mut buffer).await.map_err(broken)?;
+        out.write_all(&codec.decompress(&buffer)?).await?;
+    }
+    Ok(())
+}
+
+/// decompress the blob object `hash` to `out`, the chunks of a chunked blob in order.
+async fn copy_object(
+    objects_dir: &Path,
+    hash: &ObjectId,
+    out: &mut (impl AsyncWrite + Unpin),
+) -> Result<(), WsvcFsError> {
+    match read_manifest(objects_dir, &hash.0).await? {
+        Some(manifest) => {
+            for chunk in manifest.chunks {
+                copy_frames(&mut open_object(objects_dir, &chunk.hash.0).await?, out).await?;
+            }
+            Ok(())
+        }
+        None => copy_frames(&mut open_object(objects_dir, &hash.0).await?, out).await,
+    }
+}
+
+/// `HashWriter` stand for a writer hashing the content written to it.
+struct HashWriter(blake3::Hasher);
+
+impl AsyncWrite for HashWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.0.update(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// the bytes of the content of the object file at `path` received from another
+/// repository, `None` if it does not hold the content of the blob `blob_hash`. its frames
+/// are decompressed and hashed without holding the content, a file failing to decompress
+/// is corrupt too.
+pub async fn verified_object_size(
+    path: &Path,
+    blob_hash: &ObjectId,
+) -> Result<Option<u64>, WsvcFsError> {
+    let mut writer = HashWriter(blake3::Hasher::new());
+    match copy_frames(&mut File::open(path).await?, &mut writer).await {
+        Ok(()) => Ok((writer.0.finalize() == blob_hash.0).then(|| writer.0.count())),
+        Err(WsvcFsError::DecompressFailed(_)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// the bytes of the content of the object files of `blobs` in `dir` received from another
+/// repository, and the names of those not holding the content of their blob. corrupt
+/// files are removed, so they are neither imported nor resumed.
+pub async fn check_objects(
+    dir: &Path,
+    blobs: &[Blob],
+) -> Result<(HashMap<ObjectId, u64>, Vec<String>), WsvcFsError> {
+    let mut sizes = HashMap::new();
+    let mut corrupt = vec![];
+    for blob in blobs {
+        let path = dir.join(blob.hash.0.to_string());
+        match verified_object_size(&path, &blob.hash).await? {
+            Some(size) => {
+                sizes.insert(blob.hash.clone(), size);
+            }
+            None => {
+                remove_file(&path).await?;
+                corrupt.push(blob.hash.0.to_string());
+            }
+        }
+    }
+    Ok((sizes, corrupt))
+}
+
+/// the names of the object files of `blobs` in `dir` not holding the content of their
+/// blob, removed, see `check_objects`.
+pub async fn corrupt_objects(dir: &Path, blobs: &[Blob]) -> Result<Vec<String>, WsvcFsError> {
+    Ok(check_objects(dir, blobs).await?.1)
+}
+
+/// the stored object `hash` in the pack at `pack` with its index entries `entries`.
+async fn open_pack_entry(
+    pack: &Path,
+    entries: &HashMap<[u8; 32], PackEntry>,
+    hash: &ObjectId,
+) -> Result<Take<File>, WsvcFsError> {
+    let entry = entries
+        .get(hash.0.as_bytes())
+        .ok_or(WsvcFsError::ObjectMismatch(hash.0.to_hex().to_string()))?;
+    let mut file = File::open(pack).await?;
+    file.seek(std::io::SeekFrom::Start(entry.offset)).await?;
+    Ok(file.take(entry.len))
+}
+
+/// check the content of the blobs `blob_hashes` in the pack at `pack` with its index at
+/// `index` before it is installed, chunked blobs by their chunks in the pack.
+async fn verify_pack_blobs(
+    pack: &Path,
+    index: &Path,
+    blob_hashes: &[ObjectId],
+) -> Result<(), WsvcFsError> {
+    let entries = read_index(index)
+        .await?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    for hash in blob_hashes {
+        let mismatch = || WsvcFsError::ObjectMismatch(hash.0.to_hex().to_string());
+        let mut head = vec![];
+        open_pack_entry(pack, &entries, hash)
+            .await?
+            .take(CHUNKED_MAGIC.len() as u64)
+            .read_to_end(&mut head)
+            .await?;
+        // a chunked blob is the frames of its chunks in order.
+        let parts = match head == CHUNKED_MAGIC {
+            true => {
+                let mut object = vec![];
+                open_pack_entry(pack, &entries, hash)
+                    .await?
+                    .read_to_end(&mut object)
+                    .await?;
+                ChunkManifest::decode(&object)?
+                    .ok_or_else(mismatch)?
+                    .chunks
+                    .into_iter()
+                    .map(|chunk| chunk.hash)
+                    .collect()
+            }
+            false => vec![hash.clone()],
+        };
+        let mut writer = HashWriter(blake3::Hasher::new());
+        for part in &parts {
+            let mut object = open_pack_entry(pack, &entries, part).await?;
+            copy_frames(&mut object, &mut writer)
+                .await
+                .map_err(|_| mismatch())?;
+        }
+        if writer.0.finalize() != hash.0 {
+            return Err(mismatch());
+        }
+    }
+    Ok(())
+}
+
 /// Checkout a blob file from objects dir to path
 async fn checkout_blob_file_impl(
     path: impl AsRef<Path>,
@@ -114,34 +501,11 @@ async fn checkout_blob_file_impl(
     blob_hash: &ObjectId,
     temp: impl AsRef<Path>,
 ) -> Result<(), WsvcFsError> {
-    let blob_path = objects_dir.as_ref().join(blob_hash.0.to_hex().as_str());
-    let mut buffer: [u8; 32768] = [0; 32768];
-    let mut header_buffer: [u8; 4] = [0; 4];
-    let mut file = File::open(&blob_path).await?;
     let decompressed_file_path = temp.as_ref().join(nanoid!());
     let mut decompressed_file = File::create(&decompressed_file_path).await?;
-    loop {
-        let n = file.read(&mut header_buffer).await?;
-        if n == 0 {
-            break;
-        }
-        if header_buffer[0] != 0x78 || header_buffer[1] != 0xda {
-            return Err(WsvcFsError::DecompressFailed(
-                "magic header not match".to_owned(),
-            ));
-        }
-        let size = (header_buffer[2] as usize) * 256 + (header_buffer[3] as usize);
-        let n = file.read(&mut buffer[..size]).await?;
-        if n != size {
-            return Err(WsvcFsError::DecompressFailed("broken chunk".to_owned()));
-        }
-        decompressed_file
-            .write_all(
-                &decompress_to_vec(&buffer[..n])
-                    .map_err(|_| WsvcFsError::DecompressFailed("decode chunk failed".to_owned()))?,
-            )
-            .await?;
-    }
+    copy_object(objects_dir.as_ref(), blob_hash, &mut decompressed_file).await?;
+    decompressed_file.flush().await?;
+    drop(decompressed_file);
     rename(&decompressed_file_path, path).await?;
     Ok(())
 }
@@ -155,25 +519,31 @@ async fn checkout_blob_file_impl(
 async fn store_tree_file_impl(
     tree: TreeImpl,
     trees_dir: &Path,
+    format: ObjectFormat,
 ) -> Result<(Tree, bool), WsvcFsError> {
     let mut result = Tree {
         name: tree.name,
         hash: ObjectId(Hash::from([0; 32])),
-        trees: vec![],
-        blobs: tree.blobs.clone(),
+        entries: tree.files,
     };
     for tree in tree.trees {
-        result
-            .trees
-            .push(store_tree_file_impl(tree, trees_dir).await?.0.hash);
+        let name = tree.name.clone();
+        result.entries.push(Entry {
+            kind: EntryKind::Dir,
+            name,
+            hash: store_tree_file_impl(tree, trees_dir, format).await?.0.hash,
+            mode: 0,
+            size: 0,
+        });
     }
+    result.sort();
     let hash = blake3::hash(serde_json::to_vec(&result)?.as_slice());
     result.hash = ObjectId(hash);
     let tree_file_path = trees_dir.join(hash.to_hex().as_str());
     if !tree_file_path.exists() {
         write(
             trees_dir.join(hash.to_string()),
-            serde_json::to_vec(&result)?,
+            encode_object(&result, format)?,
         )
         .await?;
         return Ok((result, true));
@@ -182,11 +552,126 @@ async fn store_tree_file_impl(
     Ok((result, false))
 }
 
+/// unix permission bits of a file, 0 on other platforms.
+pub(crate) fn file_mode(metadata: &std::fs::Metadata) -> u32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.permissions().mode() & 0o7777
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        0
+    }
+}
+
+/// the target of the symlink at `path` as recorded, with `/` separators.
+pub(crate) async fn symlink_target(path: &Path) -> Result<Vec<u8>, WsvcFsError> {
+    let target = read_link(path).await?;
+    Ok(target.to_string_lossy().replace('\\', "/").into_bytes())
+}
+
+/// create a symlink at `path` to the recorded `target`, or a file holding the target on
+/// platforms without symlinks.
+async fn create_symlink(path: &Path, target: &[u8]) -> Result<(), WsvcFsError> {
+    #[cfg(unix)]
+    {
+        tokio::fs::symlink(String::from_utf8_lossy(target).as_ref(), path).await?;
+    }
+    #[cfg(not(unix))]
+    {
+        write(path, target).await?;
+    }
+    Ok(())
+}
+
+/// set the unix permission bits of the file at `path` to `mode` if they differ, nothing
+/// if `mode` is unknown or on other platforms.
+async fn set_file_mode(path: &Path, mode: u32) -> Result<(), WsvcFsError> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if mode != 0 && file_mode(&symlink_metadata(path).await?) != mode {
+            tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).await?;
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (path, mode);
+    }
+    Ok(())
+}
+
+/// remove the file, symlink or dir at `path`, symlinks are removed without following
+/// them. nothing if there is nothing at `path`.
+async fn remove_path(path: &Path) -> Result<(), WsvcFsError> {
+    match symlink_metadata(path).await {
+        Ok(meta) if meta.is_dir() => Ok(remove_dir_all(path).await?),
+        Ok(_) => Ok(remove_file(path).await?),
+        Err(_) => Ok(()),
+    }
+}
+
+/// store the symlink at `full_path` as a blob of its target, returns the blob hash and
+/// the size of the target.
+async fn store_workspace_symlink(
+    repo: &Repository,
+    full_path: &Path,
+) -> Result<(ObjectId, u64), WsvcFsError> {
+    let target = symlink_target(full_path).await?;
+    Ok((repo.store_blob_data(&target).await?, target.len() as u64))
+}
+
+/// store a workspace file as a blob, filtered and with line endings of text files
+/// converted by `attributes`. returns the blob hash and the size of the recorded content.
+///
+/// files with the stat cached in `cache` and their blob stored are not read again,
+/// converted files are always read, their content depends on attributes and filters.
+async fn store_workspace_file(
+    repo: &Repository,
+    attributes: &Attributes,
+    cache: &mut StatCache,
+    full_path: &Path,
+    path: &str,
+    metadata: &std::fs::Metadata,
+) -> Result<(ObjectId, u64), WsvcFsError> {
+    if attributes.converts(path) {
+        let data = attributes.clean(path, read(full_path).await?).await?;
+        return Ok((repo.store_blob_data(&data).await?, data.len() as u64));
+    }
+    let objects_dir = repo.objects_dir().await?;
+    if let Some(hash) = cache.get(path, metadata) {
+        if objects_dir.join(hash.0.to_hex().as_str()).exists()
+            || locate(&objects_dir, &hash.0).await?.is_some()
+        {
+            return Ok((hash, metadata.len()));
+        }
+    }
+    let hash = store_blob_file_impl(
+        full_path,
+        &objects_dir,
+        &repo.temp_dir().await?,
+        repo.options.compression(),
+    )
+    .await?;
+    cache.insert(path, metadata, &hash);
+    Ok((hash, metadata.len()))
+}
+
 /// Build a tree from a work dir.
 ///
-/// all blobs will be stored to objects dir when building.
+/// all blobs will be stored to objects dir when building, ignored paths are skipped. `dir`
+/// is the path of the work dir relative to the workspace root.
 #[async_recursion::async_recursion(?Send)]
-async fn build_tree(root: &Path, work_dir: &Path) -> Result<TreeImpl, WsvcFsError> {
+async fn build_tree(
+    repo: &Repository,
+    work_dir: &Path,
+    ignore: &mut Ignore,
+    attributes: &mut Attributes,
+    cache: &mut StatCache,
+    dir: &str,
+) -> Result<TreeImpl, WsvcFsError> {
     let mut result = TreeImpl {
         name: work_dir
             .file_name()
@@ -195,81 +680,298 @@ async fn build_tree(root: &Path, work_dir: &Path) -> Result<TreeImpl, WsvcFsErro
             .ok_or(WsvcFsError::InvalidOsString(format!("{:?}", work_dir)))?
             .to_string(),
         trees: vec![],
-        blobs: vec![],
+        files: vec![],
     };
     let mut entries = read_dir(work_dir).await?;
     while let Some(entry) = entries.next_entry().await? {
         let entry_type = entry.file_type().await?;
+        let name = entry
+            .file_name()
+            .to_str()
+            .ok_or(WsvcFsError::InvalidOsString(format!("{:?}", entry)))?
+            .to_string();
+        let path = join_path(dir, &name);
+        if ignore.is_ignored(&path, entry_type.is_dir()) {
+            continue;
+        }
         if entry_type.is_dir() {
-            if entry.file_name() == ".wsvc" {
+            if name == ".wsvc" {
                 continue;
             }
-            result.trees.push(build_tree(root, &entry.path()).await?);
+            ignore.enter_dir(&path, &entry.path()).await?;
+            attributes.enter_dir(&path, &entry.path()).await?;
+            result
+                .trees
+                .push(build_tree(repo, &entry.path(), ignore, attributes, cache, &path).await?);
         } else if entry_type.is_file() {
-            result.blobs.push(
-                Blob {
-                    name: entry
-                        .file_name()
-                        .to_str()
-                        .ok_or(WsvcFsError::InvalidOsString(format!("{:?}", entry)))?
-                        .to_string(),
-                    hash: store_blob_file_impl(
-                        &entry.path(),
-                        &root.join("objects"),
-                        &root.join("temp"),
-                    )
-                    .await?,
-                }
-                .clone(),
-            );
+            let metadata = entry.metadata().await?;
+            let (hash, size) =
+                store_workspace_file(repo, attributes, cache, &entry.path(), &path, &metadata)
+                    .await?;
+            result.files.push(Entry {
+                kind: EntryKind::File,
+                name,
+                hash,
+                mode: file_mode(&metadata),
+                size,
+            });
+        } else if entry_type.is_symlink() {
+            let (hash, size) = store_workspace_symlink(repo, &entry.path()).await?;
+            result.files.push(Entry {
+                kind: EntryKind::Symlink,
+                name,
+                hash,
+                mode: 0,
+                size,
+            });
         }
     }
     Ok(result)
 }
 
-impl Repository {
-    /// init a new repository at path.
-    pub async fn new(path: impl AsRef<Path>, is_bare: bool) -> Result<Self, WsvcFsError> {
-        let mut path = path.as_ref().to_owned();
-        if !is_bare {
-            path = path.join(".wsvc");
-        }
-        if !path.exists() {
-            create_dir_all(path.join("objects")).await?;
-            create_dir_all(path.join("trees")).await?;
-            create_dir_all(path.join("records")).await?;
-            write(path.join("HEAD"), "").await?;
-        } else {
-            return Err(WsvcFsError::DirAlreadyExists(format!("{:?}", path)));
+/// Replace the entry at `components` under `tree` with `entry`, or remove it if `entry`
+/// is `None`. missing dirs along the path are created and dirs left empty are removed,
+/// subtrees off the path keep their hashes.
+#[async_recursion::async_recursion(?Send)]
+async fn splice_tree(
+    repo: &Repository,
+    mut tree: Tree,
+    components: &[String],
+    entry: Option<Entry>,
+) -> Result<Tree, WsvcFsError> {
+    let Some((first, rest)) = components.split_first() else {
+        return Ok(tree);
+    };
+    let old = tree
+        .entries
+        .iter()
+        .position(|e| &e.name == first)
+        .map(|pos| tree.entries.remove(pos));
+    let new = if rest.is_empty() {
+        entry.map(|e| Entry {
+            name: first.clone(),
+            ..e
+        })
+    } else {
+        let sub_tree = match old {
+            Some(old) if old.kind == EntryKind::Dir => repo.read_tree(&old.hash).await?,
+            _ => Tree {
+                name: first.clone(),
+                hash: ObjectId::default(),
+                entries: vec![],
+            },
+        };
+        let sub_tree = splice_tree(repo, sub_tree, rest, entry).await?;
+        (!sub_tree.entries.is_empty()).then(|| Entry {
+            kind: EntryKind::Dir,
+            name: first.clone(),
+            hash: sub_tree.hash,
+            mode: 0,
+            size: 0,
+        })
+    };
+    tree.entries.extend(new);
+    repo.store_tree(&tree).await
+}
+
+/// check `name` of a tree entry is a single normal path component and not `.wsvc`, so
+/// checking it out never writes outside the dir of its tree or into the repository.
+pub fn check_entry_name(name: &str) -> Result<(), WsvcFsError> {
+    let single = matches!(
+        Path::new(name).components().collect::<Vec<_>>().as_slice(),
+        [Component::Normal(_)]
+    );
+    if !single || name == ".wsvc" || name.contains(['/', '\\', '\0']) {
+        return Err(WsvcFsError::InvalidFilename(name.to_owned()));
+    }
+    Ok(())
+}
+
+/// check names of the entries of `tree` with `check_entry_name`, and that no two entries
+/// share a name, e.g. a symlink and a dir the checkout would write through.
+pub fn check_tree_names(tree: &Tree) -> Result<(), WsvcFsError> {
+    let mut names = HashSet::new();
+    for entry in &tree.entries {
+        check_entry_name(&entry.name)?;
+        if !names.insert(entry.name.as_str()) {
+            return Err(WsvcFsError::InvalidFilename(format!(
+                "{} is in tree {} twice",
+                entry.name,
+                tree.hash.0.to_hex()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// join a name to a `/` separated path relative to the workspace root.
+pub(crate) fn join_path(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// `RepositoryBuilder` creates or opens a repository with options.
+///
+/// ```ignore
+/// let repo = RepositoryBuilder::new("/srv/wsvc/repo")
+///     .bare(true)
+///     .objects_dir("/mnt/blobs/repo")
+///     .lock_timeout(Duration::from_secs(5))
+///     .open()
+///     .await?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct RepositoryBuilder {
+    path: PathBuf,
+    bare: Option<bool>,
+    options: RepoOptions,
+}
+
+impl RepositoryBuilder {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+            bare: None,
+            options: RepoOptions::default(),
         }
-        Ok(Self {
+    }
+
+    /// whether the repository data is at path itself instead of `.wsvc` in it.
+    ///
+    /// guessed when opening if not set, a new repository is not bare by default.
+    pub fn bare(mut self, bare: bool) -> Self {
+        self.bare = Some(bare);
+        self
+    }
+
+    /// store blobs in `dir` instead of `objects` in the repository dir, e.g. on another
+    /// disk. a relative dir is relative to the repository dir. the same dir must be given
+    /// every time the repository is opened.
+    pub fn objects_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.options.objects_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// compression level of newly stored blobs, levels above the highest one of the codec
+    /// are treated as the highest.
+    pub fn compression_level(mut self, level: u8) -> Self {
+        self.options.compression_level = level;
+        self
+    }
+
+    /// format of newly stored trees and records. objects stored in the other format are
+    /// still read.
+    pub fn object_format(mut self, format: ObjectFormat) -> Self {
+        self.options.object_format = format;
+        self
+    }
+
+    /// codec of newly stored blobs. blobs stored with another codec are still read.
+    pub fn codec(mut self, codec: Codec) -> Self {
+        self.options.codec = codec;
+        self
+    }
+
+    /// wait up to `timeout` for other writers to release the lock.
+    pub fn lock_timeout(mut self, timeout: Duration) -> Self {
+        self.options.lock_timeout = Some(timeout);
+        self
+    }
+
+    /// refuse to lock the repository and to store anything in it.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.options.read_only = read_only;
+        self
+    }
+
+    /// apply ignore patterns of the file at `path` to every workspace, see `Ignore`.
+    pub fn excludes_file(mut self, path: impl AsRef<Path>) -> Self {
+        self.options.excludes_file = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// convert line endings of text files without an `eol` attribute, see `Attributes`.
+    pub fn autocrlf(mut self, autocrlf: AutoCrlf) -> Self {
+        self.options.autocrlf = autocrlf;
+        self
+    }
+
+    /// how large files of new records may be, see `Repository::commit_record`.
+    pub fn size_policy(mut self, policy: SizePolicy) -> Self {
+        self.options.size_policy = policy;
+        self
+    }
+
+    /// the content filter of paths with a `filter=<name>` attribute, see `FilterDriver`.
+    pub fn filter(mut self, name: impl Into<String>, driver: FilterDriver) -> Self {
+        self.options.filters.insert(name.into(), driver);
+        self
+    }
+
+    /// where a blobless repository fetches blobs it did not sync, see `BlobSource`.
+    pub fn blob_source(mut self, source: Arc<dyn BlobSource>) -> Self {
+        self.options.blob_source = Some(source);
+        self
+    }
+
+    fn repo(&self, bare: bool) -> Repository {
+        let path = if bare {
+            self.path.clone()
+        } else {
+            self.path.join(".wsvc")
+        };
+        let mut options = self.options.clone();
+        options.objects_dir = options.objects_dir.map(|dir| path.join(dir));
+        Repository {
             path,
             lock: nanoid!(),
-        })
+            options,
+        }
     }
 
-    /// open a repository at path.
-    pub async fn open(path: impl AsRef<Path>, is_bare: bool) -> Result<Self, WsvcFsError> {
-        let mut path = path.as_ref().to_owned();
-        if !is_bare {
-            path = path.join(".wsvc");
+    /// init a new repository.
+    pub async fn create(self) -> Result<Repository, WsvcFsError> {
+        if self.options.read_only {
+            return Err(WsvcFsError::ReadOnly);
         }
-        if !path.exists() {
-            return Err(WsvcFsError::UnknownPath(
-                path.to_str()
-                    .ok_or(WsvcFsError::InvalidOsString(format!("{:?}", path)))?
-                    .to_string(),
-            ));
+        let repo = self.repo(self.bare.unwrap_or(false));
+        let path = &repo.path;
+        if path.exists() {
+            return Err(WsvcFsError::DirAlreadyExists(format!("{:?}", path)));
+        }
+        create_dir_all(repo.objects_path()).await?;
+        create_dir_all(path.join("trees")).await?;
+        create_dir_all(path.join("records")).await?;
+        write(path.join("HEAD"), "").await?;
+        write(
+            path.join(IDENTITY_FILE),
+            serde_json::to_vec_pretty(&RepoIdentity::generate())?,
+        )
+        .await?;
+        Ok(repo)
+    }
+
+    /// open an existing repository.
+    pub async fn open(self) -> Result<Repository, WsvcFsError> {
+        match self.bare {
+            Some(bare) => self.open_impl(bare),
+            None => self.open_impl(false).or_else(|_| self.open_impl(true)),
         }
-        if path.join("objects").exists()
+    }
+
+    fn open_impl(&self, bare: bool) -> Result<Repository, WsvcFsError> {
+        let repo = self.repo(bare);
+        let path = &repo.path;
+        if path.exists()
+            && repo.objects_path().exists()
             && path.join("trees").exists()
             && path.join("records").exists()
             && path.join("HEAD").exists()
         {
-            Ok(Self {
-                path,
-                lock: nanoid!(),
-            })
+            Ok(repo)
         } else {
             Err(WsvcFsError::UnknownPath(
                 path.to_str()
@@ -278,6 +980,38 @@ impl Repository {
             ))
         }
     }
+}
+
+impl Repository {
+    /// init a new repository at path.
+    pub async fn new(path: impl AsRef<Path>, is_bare: bool) -> Result<Self, WsvcFsError> {
+        RepositoryBuilder::new(path).bare(is_bare).create().await
+    }
+
+    /// open a repository at path.
+    pub async fn open(path: impl AsRef<Path>, is_bare: bool) -> Result<Self, WsvcFsError> {
+        RepositoryBuilder::new(path).bare(is_bare).open().await
+    }
+
+    /// a builder to create or open a repository at path with options.
+    pub fn builder(path: impl AsRef<Path>) -> RepositoryBuilder {
+        RepositoryBuilder::new(path)
+    }
+
+    /// fail if the repository is opened read-only.
+    pub fn check_writable(&self) -> Result<(), WsvcFsError> {
+        if self.options.read_only {
+            return Err(WsvcFsError::ReadOnly);
+        }
+        Ok(())
+    }
+
+    fn objects_path(&self) -> PathBuf {
+        self.options
+            .objects_dir
+            .clone()
+            .unwrap_or_else(|| self.path.join("objects"))
+    }
 
     /// lock repository for write.
     ///
@@ -285,6 +1019,7 @@ impl Repository {
     /// cloned multiple Repository struct in different threads. it is recommended to construct
     /// `Repository` everytime when you need it, the `lock` function is just for convenience.
     pub async fn lock(&self) -> Result<(), WsvcFsError> {
+        self.check_writable()?;
         write(self.path.join("LOCK"), self.lock.clone()).await?;
         Ok(())
     }
@@ -316,17 +1051,13 @@ impl Repository {
     ///
     /// the `bare` option will be guessed.
     pub async fn try_open(path: impl AsRef<Path>) -> Result<Self, WsvcFsError> {
-        if let Ok(repo) = Repository::open(&path, false).await {
-            Ok(repo)
-        } else {
-            Repository::open(&path, true).await
-        }
+        RepositoryBuilder::new(path).open().await
     }
 
     /// get the temp folder of the repository.
     pub async fn temp_dir(&self) -> Result<PathBuf, WsvcFsError> {
         let result = self.path.join("temp");
-        if !result.exists() {
+        if !result.exists() && !self.options.read_only {
             create_dir_all(&result).await?;
         }
         Ok(result)
@@ -334,8 +1065,8 @@ impl Repository {
 
     /// get the objects folder of the repository.
     pub async fn objects_dir(&self) -> Result<PathBuf, WsvcFsError> {
-        let result = self.path.join("objects");
-        if !result.exists() {
+        let result = self.objects_path();
+        if !result.exists() && !self.options.read_only {
             create_dir_all(&result).await?;
         }
         Ok(result)
@@ -344,7 +1075,7 @@ impl Repository {
     /// get the trees folder of the repository.
     pub async fn trees_dir(&self) -> Result<PathBuf, WsvcFsError> {
         let result = self.path.join("trees");
-        if !result.exists() {
+        if !result.exists() && !self.options.read_only {
             create_dir_all(&result).await?;
         }
         Ok(result)
@@ -353,7 +1084,7 @@ impl Repository {
     /// get the records folder of the repository.
     pub async fn records_dir(&self) -> Result<PathBuf, WsvcFsError> {
         let result = self.path.join("records");
-        if !result.exists() {
+        if !result.exists() && !self.options.read_only {
             create_dir_all(&result).await?;
         }
         Ok(result)
@@ -365,6 +1096,7 @@ impl Repository {
         workspace: impl AsRef<Path>,
         rel_path: impl AsRef<Path>,
     ) -> Result<Blob, WsvcFsError> {
+        self.check_writable()?;
         Ok(Blob {
             name: rel_path
                 .as_ref()
@@ -383,11 +1115,48 @@ impl Repository {
                 workspace.as_ref().join(rel_path),
                 &self.objects_dir().await?,
                 &self.temp_dir().await?,
+                self.options.compression(),
             )
             .await?,
         })
     }
 
+    /// store data as a blob in objects dir, in the same frames and chunks as files.
+    pub async fn store_blob_data(&self, data: &[u8]) -> Result<ObjectId, WsvcFsError> {
+        self.check_writable()?;
+        let temp = self.temp_dir().await?;
+        if !temp.exists() {
+            create_dir_all(&temp).await?;
+        }
+        let objects_dir = self.objects_dir().await?;
+        let compression = self.options.compression();
+        let hash = blake3::hash(data);
+        if (data.len() as u64) < CHUNKED_SIZE {
+            place_object(
+                &objects_dir,
+                &temp,
+                &hash.to_hex(),
+                &compression.frames(data)?,
+            )
+            .await?;
+            return Ok(ObjectId(hash));
+        }
+        let mut manifest = ChunkManifest {
+            size: data.len() as u64,
+            chunks: vec![],
+        };
+        for chunk in chunks(data) {
+            let chunk_hash = blake3::hash(chunk);
+            write_object(&objects_dir, &temp, &chunk_hash, chunk, compression).await?;
+            manifest.chunks.push(ChunkRef {
+                hash: ObjectId(chunk_hash),
+                size: chunk.len() as u64,
+            });
+        }
+        place_object(&objects_dir, &temp, &hash.to_hex(), &manifest.encode()?).await?;
+        Ok(ObjectId(hash))
+    }
+
     /// checkout a blob file from objects dir to workspace.
     pub async fn checkout_blob(
         &self,
@@ -395,6 +1164,7 @@ impl Repository {
         workspace: impl AsRef<Path>,
         rel_path: impl AsRef<Path>,
     ) -> Result<(), WsvcFsError> {
+        self.ensure_blob(blob_hash).await?;
         checkout_blob_file_impl(
             &workspace.as_ref().join(rel_path),
             &self.objects_dir().await?,
@@ -405,56 +1175,460 @@ impl Repository {
     }
 
     pub async fn blob_exists(&self, blob_hash: &ObjectId) -> Result<bool, WsvcFsError> {
-        Ok(self
-            .objects_dir()
+        Ok(locate(&self.objects_dir().await?, &blob_hash.0)
             .await?
-            .join(blob_hash.0.to_hex().as_str())
-            .exists())
+            .is_some())
     }
 
-    /// read blob data from objects database.
-    pub async fn read_blob(&self, blob_hash: &ObjectId) -> Result<Vec<u8>, WsvcFsError> {
-        let blob_path = self
-            .objects_dir()
-            .await?
-            .join(blob_hash.0.to_hex().as_str());
-        let mut buffer: [u8; 32768] = [0; 32768];
-        let mut header_buffer: [u8; 4] = [0; 4];
-        let mut file = File::open(&blob_path).await?;
+    /// blobs of `ids` not stored in objects dir, in the order of `ids` without duplicates.
+    ///
+    /// the objects dir and pack indexes are listed once for many blobs instead of looking
+    /// up each one.
+    pub async fn missing_objects(&self, ids: &[ObjectId]) -> Result<Vec<ObjectId>, WsvcFsError> {
+        let objects_dir = self.objects_dir().await?;
+        let mut stored = HashSet::new();
+        if ids.len() >= LIST_OBJECTS_THRESHOLD && objects_dir.exists() {
+            let mut entries = read_dir(&objects_dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                stored.insert(entry.file_name().to_string_lossy().to_string());
+            }
+            for index in pack_indexes(&objects_dir).await? {
+                for (hash, _) in read_index(&index).await? {
+                    stored.insert(Hash::from(hash).to_hex().to_string());
+                }
+            }
+        } else {
+            for id in ids {
+                if locate(&objects_dir, &id.0).await?.is_some() {
+                    stored.insert(id.0.to_hex().to_string());
+                }
+            }
+        }
+        let mut seen = HashSet::new();
+        Ok(ids
+            .iter()
+            .filter(|id| !stored.contains(id.0.to_hex().as_str()) && seen.insert(*id))
+            .cloned()
+            .collect())
+    }
+
+    /// read blobs of `ids` concurrently, yielded in the order of `ids`.
+    ///
+    /// a missing or broken blob yields an error, the rest are still read.
+    pub fn read_objects_stream(
+        &self,
+        ids: Vec<ObjectId>,
+    ) -> impl Stream<Item = Result<(ObjectId, Vec<u8>), WsvcFsError>> + '_ {
+        stream::iter(ids)
+            .map(move |id| async move {
+                let data = self.read_blob(&id).await?;
+                Ok((id, data))
+            })
+            .buffered(READ_OBJECTS_CONCURRENCY)
+    }
+
+    /// read blob data from objects database.
+    pub async fn read_blob(&self, blob_hash: &ObjectId) -> Result<Vec<u8>, WsvcFsError> {
+        self.ensure_blob(blob_hash).await?;
         let mut result = Vec::new();
-        loop {
-            let n = file.read(&mut header_buffer).await?;
-            if n == 0 {
+        copy_object(&self.objects_dir().await?, blob_hash, &mut result).await?;
+        Ok(result)
+    }
+
+    /// bytes of the content of the blob `blob_hash`, from the manifest of a chunked blob.
+    pub async fn blob_size(&self, blob_hash: &ObjectId) -> Result<u64, WsvcFsError> {
+        match self.blob_chunks(blob_hash).await? {
+            Some(manifest) => Ok(manifest.size),
+            None => Ok(self.read_blob(blob_hash).await?.len() as u64),
+        }
+    }
+
+    /// bytes `start..end` of the blob `blob_hash`, cut to its content. only the chunks of
+    /// a chunked blob holding the range are decompressed, so a part of a large file is
+    /// read without the whole of it.
+    pub async fn read_blob_range(
+        &self,
+        blob_hash: &ObjectId,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<u8>, WsvcFsError> {
+        self.ensure_blob(blob_hash).await?;
+        let objects_dir = self.objects_dir().await?;
+        let Some(manifest) = read_manifest(&objects_dir, &blob_hash.0).await? else {
+            let data = self.read_blob(blob_hash).await?;
+            let end = end.min(data.len() as u64) as usize;
+            return Ok(data.get(start as usize..end).unwrap_or_default().to_vec());
+        };
+        let mut result = vec![];
+        let mut offset = 0;
+        for chunk in manifest.chunks {
+            let chunk_end = offset + chunk.size;
+            if chunk_end > start && offset < end {
+                let mut data = Vec::with_capacity(chunk.size as usize);
+                copy_frames(
+                    &mut open_object(&objects_dir, &chunk.hash.0).await?,
+                    &mut data,
+                )
+                .await?;
+                let from = start.saturating_sub(offset) as usize;
+                let to = (end.min(chunk_end) - offset) as usize;
+                result.extend_from_slice(data.get(from..to).ok_or_else(|| {
+                    WsvcFsError::DecompressFailed("chunk shorter than its size".to_owned())
+                })?);
+            }
+            offset = chunk_end;
+            if offset >= end {
                 break;
             }
-            if header_buffer[0] != 0x78 || header_buffer[1] != 0xda {
-                return Err(WsvcFsError::DecompressFailed(
-                    "magic header not match".to_owned(),
-                ));
+        }
+        Ok(result)
+    }
+
+    /// the chunks of the blob `blob_hash`, `None` if it is not stored in chunks.
+    pub async fn blob_chunks(
+        &self,
+        blob_hash: &ObjectId,
+    ) -> Result<Option<ChunkManifest>, WsvcFsError> {
+        self.ensure_blob(blob_hash).await?;
+        read_manifest(&self.objects_dir().await?, &blob_hash.0).await
+    }
+
+    /// a file holding the blob `blob_hash` as an object with the content inline, to send it
+    /// to another repository. a chunked blob is joined from its chunks and a packed blob
+    /// copied out of its pack into a temp file, other blobs are their objects.
+    pub async fn export_object(&self, blob_hash: &ObjectId) -> Result<PathBuf, WsvcFsError> {
+        self.ensure_blob(blob_hash).await?;
+        let objects_dir = self.objects_dir().await?;
+        let manifest = read_manifest(&objects_dir, &blob_hash.0).await?;
+        let location = locate(&objects_dir, &blob_hash.0).await?;
+        if let (None, Some(ObjectLocation::Loose(path))) = (&manifest, location) {
+            return Ok(path);
+        }
+        let temp = self.temp_dir().await?;
+        create_dir_all(&temp).await?;
+        // syncs with many remotes at once may export the same blob.
+        let joined_path = temp.join(format!("export-{}", nanoid!()));
+        let mut joined = File::create(&joined_path).await?;
+        match manifest {
+            // frames of the chunks in order are the frames of the whole content.
+            Some(manifest) => {
+                for chunk in manifest.chunks {
+                    let mut object = open_object(&objects_dir, &chunk.hash.0).await?;
+                    tokio::io::copy(&mut object, &mut joined).await?;
+                }
             }
-            let size = (header_buffer[2] as usize) * 256 + (header_buffer[3] as usize);
-            let n = file.read(&mut buffer[..size]).await?;
-            if n != size {
-                return Err(WsvcFsError::DecompressFailed("broken chunk".to_owned()));
+            None => {
+                let mut object = open_object(&objects_dir, &blob_hash.0).await?;
+                tokio::io::copy(&mut object, &mut joined).await?;
+            }
+        }
+        joined.flush().await?;
+        Ok(joined_path)
+    }
+
+    /// a pack in the temp dir holding the blobs `blob_hashes` as stored, chunked blobs
+    /// with their chunks, returns the paths of the pack and its index. it is sent instead
+    /// of the objects one by one to clones of large repositories.
+    pub async fn export_pack(
+        &self,
+        blob_hashes: &[ObjectId],
+    ) -> Result<(PathBuf, PathBuf), WsvcFsError> {
+        let objects_dir = self.objects_dir().await?;
+        let mut writer = PackWriter::create(&self.temp_dir().await?).await?;
+        for hash in blob_hashes {
+            let mut hashes = vec![hash.0];
+            if let Some(manifest) = read_manifest(&objects_dir, &hash.0).await? {
+                hashes.extend(manifest.chunks.iter().map(|chunk| chunk.hash.0));
+            }
+            for hash in hashes {
+                if !writer.contains(hash.as_bytes()) {
+                    let mut object = open_object(&objects_dir, &hash).await?;
+                    writer.add(*hash.as_bytes(), &mut object).await?;
+                }
+            }
+        }
+        writer.finish_temp().await
+    }
+
+    /// names of the stored blobs, loose and packed, chunks of chunked blobs included.
+    pub(crate) async fn stored_objects(&self) -> Result<HashSet<String>, WsvcFsError> {
+        let objects_dir = self.objects_dir().await?;
+        let mut result = HashSet::new();
+        let mut entries = read_dir(&objects_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_file() {
+                result.insert(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        for index in pack_indexes(&objects_dir).await? {
+            for (hash, _) in read_index(&index).await? {
+                result.insert(Hash::from(hash).to_hex().to_string());
             }
-            result
-                .extend_from_slice(&decompress_to_vec(&buffer[..n]).map_err(|_| {
-                    WsvcFsError::DecompressFailed("decode chunk failed".to_owned())
-                })?);
         }
         Ok(result)
     }
 
+    /// the hash of the content of the blob `blob_hash` as stored, decompressed frame by
+    /// frame and chunk by chunk without holding the content.
+    pub(crate) async fn hash_blob(&self, blob_hash: &ObjectId) -> Result<Hash, WsvcFsError> {
+        let mut writer = HashWriter(blake3::Hasher::new());
+        copy_object(&self.objects_dir().await?, blob_hash, &mut writer).await?;
+        Ok(writer.0.finalize())
+    }
+
+    /// move the stored blobs `names` into `dir`, packed ones are copied out and their
+    /// packs written again without them.
+    pub(crate) async fn quarantine_objects(
+        &self,
+        names: &HashSet<String>,
+        dir: &Path,
+    ) -> Result<(), WsvcFsError> {
+        self.check_writable()?;
+        create_dir_all(dir).await?;
+        let objects_dir = self.objects_dir().await?;
+        let mut packed = false;
+        for name in names {
+            match locate(&objects_dir, &Hash::from_hex(name)?).await? {
+                Some(ObjectLocation::Loose(path)) => rename(path, dir.join(name)).await?,
+                Some(ObjectLocation::Packed(..)) => {
+                    let mut object = open_object(&objects_dir, &Hash::from_hex(name)?).await?;
+                    let mut file = File::create(dir.join(name)).await?;
+                    tokio::io::copy(&mut object, &mut file).await?;
+                    packed = true;
+                }
+                None => {}
+            }
+        }
+        if packed {
+            let keep = self
+                .stored_objects()
+                .await?
+                .into_iter()
+                .filter(|name| !names.contains(name))
+                .collect();
+            self.repack_objects(false, Some(&keep)).await?;
+        }
+        Ok(())
+    }
+
+    /// copy the stored object `hash` of `source` into this repository as is, unless it is
+    /// stored already. returns whether it was copied.
+    pub(crate) async fn copy_raw_object(
+        &self,
+        source: &Repository,
+        hash: &ObjectId,
+    ) -> Result<bool, WsvcFsError> {
+        let objects_dir = self.objects_dir().await?;
+        if locate(&objects_dir, &hash.0).await?.is_some() {
+            return Ok(false);
+        }
+        let temp = self.temp_dir().await?;
+        create_dir_all(&temp).await?;
+        let temp_path = temp.join(nanoid!());
+        let mut file = File::create(&temp_path).await?;
+        let mut object = open_object(&source.objects_dir().await?, &hash.0).await?;
+        tokio::io::copy(&mut object, &mut file).await?;
+        file.flush().await?;
+        drop(file);
+        rename(&temp_path, objects_dir.join(hash.0.to_hex().as_str())).await?;
+        Ok(true)
+    }
+
+    /// store the object file at `path` received from another repository as the blob
+    /// `blob_hash`, the file is moved or removed.
+    ///
+    /// objects that may be large enough are stored in chunks again, and their content is
+    /// checked against `blob_hash`.
+    pub async fn import_object(
+        &self,
+        blob_hash: &ObjectId,
+        path: &Path,
+    ) -> Result<(), WsvcFsError> {
+        self.check_writable()?;
+        let objects_dir = self.objects_dir().await?;
+        // compressed objects are never larger than their content.
+        if tokio::fs::metadata(path).await?.len() < MAX_CHUNK_SIZE as u64 {
+            rename(path, objects_dir.join(blob_hash.0.to_hex().as_str())).await?;
+            return Ok(());
+        }
+        let temp = self.temp_dir().await?;
+        create_dir_all(&temp).await?;
+        let raw_path = temp.join(nanoid!());
+        let mut raw = File::create(&raw_path).await?;
+        copy_frames(&mut File::open(path).await?, &mut raw).await?;
+        raw.flush().await?;
+        drop(raw);
+        let stored =
+            store_blob_file_impl(&raw_path, &objects_dir, &temp, self.options.compression()).await;
+        remove_file(&raw_path).await?;
+        remove_file(path).await?;
+        let stored = stored?;
+        if stored != *blob_hash {
+            return Err(WsvcFsError::ObjectMismatch(
+                blob_hash.0.to_hex().to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// store the pack at `pack` with its index at `index` received from another
+    /// repository, both files are moved. the pack must hold every blob of `blob_hashes`
+    /// with the content of its hash.
+    pub async fn import_pack(
+        &self,
+        pack: &Path,
+        index: &Path,
+        blob_hashes: &[ObjectId],
+    ) -> Result<(), WsvcFsError> {
+        self.check_writable()?;
+        check_pack(pack, index).await?;
+        let packed = read_index(index)
+            .await?
+            .into_iter()
+            .map(|(hash, _)| hash)
+            .collect::<HashSet<_>>();
+        if let Some(missing) = blob_hashes
+            .iter()
+            .find(|hash| !packed.contains(hash.0.as_bytes()))
+        {
+            return Err(WsvcFsError::ObjectMismatch(missing.0.to_hex().to_string()));
+        }
+        verify_pack_blobs(pack, index, blob_hashes).await?;
+        install_pack(&self.objects_dir().await?, pack, index).await?;
+        Ok(())
+    }
+
     /// write all trees of current workspace to trees dir.
     pub async fn write_tree_recursively(
         &self,
         workspace: impl AsRef<Path> + Clone,
     ) -> Result<(Tree, bool), WsvcFsError> {
-        let stored_tree = build_tree(&self.path, workspace.as_ref()).await?;
-        let result = store_tree_file_impl(stored_tree, &self.trees_dir().await?).await?;
+        self.check_writable()?;
+        let mut ignore = self.load_ignore(workspace.as_ref()).await?;
+        let mut attributes = self.load_attributes(workspace.as_ref()).await?;
+        let mut cache = StatCache::load(&self.path, workspace.as_ref()).await;
+        let stored_tree = build_tree(
+            self,
+            workspace.as_ref(),
+            &mut ignore,
+            &mut attributes,
+            &mut cache,
+            "",
+        )
+        .await?;
+        let result = store_tree_file_impl(
+            stored_tree,
+            &self.trees_dir().await?,
+            self.options.object_format,
+        )
+        .await?;
+        // files not found in the workspace are gone, the cache is only a hint.
+        cache.retain_seen();
+        cache.save(&self.path).await.ok();
         Ok(result)
     }
 
+    /// store the tree `base` with the files and dirs at `paths` taken from `workspace`,
+    /// paths missing or ignored in the workspace are removed. `base` is the root tree of a
+    /// record, `None` for an empty tree.
+    ///
+    /// only blobs under `paths` are stored, paths are `/` separated and relative to the
+    /// workspace root.
+    pub async fn write_tree_of_paths(
+        &self,
+        workspace: &Path,
+        base: Option<&ObjectId>,
+        paths: &[String],
+    ) -> Result<Tree, WsvcFsError> {
+        self.check_writable()?;
+        let mut tree = match base {
+            Some(base) => self.read_tree(base).await?,
+            None => Tree {
+                name: workspace
+                    .file_name()
+                    .unwrap_or(std::ffi::OsStr::new("."))
+                    .to_string_lossy()
+                    .to_string(),
+                hash: ObjectId::default(),
+                entries: vec![],
+            },
+        };
+        let mut attributes = self.load_attributes(workspace).await?;
+        let mut cache = StatCache::load(&self.path, workspace).await;
+        for path in paths {
+            let components = path.split('/').map(str::to_owned).collect::<Vec<_>>();
+            // rules of the dirs along the path decide whether the path is ignored.
+            let mut ignore = self.load_ignore(workspace).await?;
+            for depth in 1..components.len() {
+                let dir = components[..depth].join("/");
+                ignore.enter_dir(&dir, &workspace.join(&dir)).await?;
+            }
+            attributes.enter_path(workspace, path).await?;
+            let full_path = workspace.join(path);
+            let entry = match symlink_metadata(&full_path).await {
+                Ok(meta) if ignore.is_ignored(path, meta.is_dir()) => None,
+                Ok(meta) if meta.is_dir() => {
+                    ignore.enter_dir(path, &full_path).await?;
+                    attributes.enter_dir(path, &full_path).await?;
+                    let tree = build_tree(
+                        self,
+                        &full_path,
+                        &mut ignore,
+                        &mut attributes,
+                        &mut cache,
+                        path,
+                    )
+                    .await?;
+                    let tree = store_tree_file_impl(
+                        tree,
+                        &self.trees_dir().await?,
+                        self.options.object_format,
+                    )
+                    .await?;
+                    Some(Entry {
+                        kind: EntryKind::Dir,
+                        name: String::new(),
+                        hash: tree.0.hash,
+                        mode: 0,
+                        size: 0,
+                    })
+                }
+                Ok(meta) if meta.is_file() => {
+                    let (hash, size) = store_workspace_file(
+                        self,
+                        &attributes,
+                        &mut cache,
+                        &full_path,
+                        path,
+                        &meta,
+                    )
+                    .await?;
+                    Some(Entry {
+                        kind: EntryKind::File,
+                        name: String::new(),
+                        hash,
+                        mode: file_mode(&meta),
+                        size,
+                    })
+                }
+                Ok(meta) if meta.is_symlink() => {
+                    let (hash, size) = store_workspace_symlink(self, &full_path).await?;
+                    Some(Entry {
+                        kind: EntryKind::Symlink,
+                        name: String::new(),
+                        hash,
+                        mode: 0,
+                        size,
+                    })
+                }
+                _ => None,
+            };
+            tree = splice_tree(self, tree, &components, entry).await?;
+        }
+        cache.save(&self.path).await.ok();
+        Ok(tree)
+    }
+
     pub async fn tree_exists(&self, tree_hash: &ObjectId) -> Result<bool, WsvcFsError> {
         Ok(self
             .trees_dir()
@@ -465,14 +1639,63 @@ impl Repository {
 
     /// read a tree object from trees dir
     pub async fn read_tree(&self, tree_hash: &ObjectId) -> Result<Tree, WsvcFsError> {
-        let tree_path = self.trees_dir().await?.join(tree_hash.0.to_hex().as_str());
-        let result = serde_json::from_slice::<Tree>(&tokio::fs::read(tree_path).await?)?;
+        let mut result = self.read_tree_file(tree_hash).await?;
+        // trees stored before entries only know names of dirs by their trees.
+        for entry in result.entries.iter_mut() {
+            if entry.kind == EntryKind::Dir && entry.name.is_empty() {
+                entry.name = self.read_tree_file(&entry.hash).await?.name;
+            }
+        }
+        result.sort();
         Ok(result)
     }
 
+    async fn read_tree_file(&self, tree_hash: &ObjectId) -> Result<Tree, WsvcFsError> {
+        let tree_path = self.trees_dir().await?.join(tree_hash.0.to_hex().as_str());
+        decode_object::<Tree>(&tokio::fs::read(tree_path).await?)
+    }
+
     /// checkout a tree to workspace.
-    #[async_recursion::async_recursion(?Send)]
+    ///
+    /// files not in the tree are deleted, unless they are ignored or in overlays.
+    /// files are checked out by the attributes files of the tree, see `Attributes`.
     pub async fn checkout_tree(&self, tree: &Tree, workspace: &Path) -> Result<(), WsvcFsError> {
+        let mut ignore = self.excludes().await?;
+        self.ignore_overlays(&mut ignore).await?;
+        let mut attributes = self.new_attributes();
+        self.checkout_tree_impl(tree, workspace, &mut ignore, &mut attributes, "")
+            .await
+    }
+
+    /// checkout the tree of `record` to workspace like `checkout_tree`, keywords of files
+    /// with the `keywords` filter are expanded with the record.
+    pub(crate) async fn checkout_record_tree(
+        &self,
+        record: &Record,
+        workspace: &Path,
+    ) -> Result<(), WsvcFsError> {
+        let mut ignore = self.excludes().await?;
+        self.ignore_overlays(&mut ignore).await?;
+        let mut attributes = self.new_attributes();
+        attributes.record = Some(record.clone());
+        let tree = self.read_tree(&record.root).await?;
+        self.checkout_tree_impl(&tree, workspace, &mut ignore, &mut attributes, "")
+            .await
+    }
+
+    #[async_recursion::async_recursion]
+    pub(crate) async fn checkout_tree_impl(
+        &self,
+        tree: &Tree,
+        workspace: &Path,
+        ignore: &mut Ignore,
+        attributes: &mut Attributes,
+        dir: &str,
+    ) -> Result<(), WsvcFsError> {
+        // names come from remotes, a name with separators or a symlink of the same name
+        // as a dir would write outside the workspace.
+        check_tree_names(tree)?;
+        attributes.enter_tree(self, dir, tree).await?;
         // collect files to be deleted
         // delete files that not in the tree or hash not match
         let mut entries = read_dir(workspace).await?;
@@ -481,40 +1704,88 @@ impl Repository {
             should_be_del.push(entry.file_name());
         }
 
-        for tree in &tree.trees {
-            let tree = self.read_tree(tree).await?;
-            let tree_path = workspace.join(&tree.name);
-            if !tree_path.exists() {
-                create_dir_all(&tree_path).await?;
-            } else {
-                if !tree_path.is_dir() {
-                    remove_file(&tree_path).await?;
+        for blob in tree.files() {
+            let blob_path = workspace.join(&blob.name);
+            let path = join_path(dir, &blob.name);
+            // symlinks are never followed, a symlink in place of a file is replaced.
+            let existing = symlink_metadata(&blob_path).await.ok();
+            if blob.kind == EntryKind::Symlink {
+                let target = self.read_blob(&blob.hash).await?;
+                let same = match &existing {
+                    Some(meta) if meta.is_symlink() => symlink_target(&blob_path).await? == target,
+                    _ => false,
+                };
+                if !same {
+                    remove_path(&blob_path).await?;
+                    create_symlink(&blob_path, &target).await?;
                 }
-                if let Some(pos) = should_be_del
-                    .iter()
-                    .position(|x| x == tree_path.file_name().unwrap_or_default())
-                {
-                    should_be_del.remove(pos);
+            } else if attributes.get(&path).lazy && !self.blob_exists(&blob.hash).await? {
+                // kept as is until the blob is fetched.
+            } else {
+                let is_file = existing.as_ref().is_some_and(|meta| meta.is_file());
+                if !is_file || attributes.hash_file(&blob_path, &path).await? != blob.hash {
+                    remove_path(&blob_path).await?;
+                    self.checkout_blob(&blob.hash, &workspace, &blob.name)
+                        .await?;
+                    attributes.smudge_file(&blob_path, &path).await?;
+                } else if attributes.stamps(&path) {
+                    // expanded with the record checked out before.
+                    attributes.smudge_file(&blob_path, &path).await?;
                 }
+                set_file_mode(&blob_path, blob.mode).await?;
+            }
+            if let Some(pos) = should_be_del
+                .iter()
+                .position(|x| x == blob_path.file_name().unwrap_or_default())
+            {
+                should_be_del.remove(pos);
             }
-            self.checkout_tree(&tree, &tree_path).await?;
         }
-        for blob in &tree.blobs {
-            let blob_path = workspace.join(&blob.name);
-            if !blob_path.exists() || !blob.checksum(&blob_path).await? {
-                self.checkout_blob(&blob.hash, &workspace, &blob.name)
-                    .await?;
+        // the ignore file of the dir is checked out now, its rules keep ignored files.
+        ignore.enter_dir(dir, workspace).await?;
+        for dir_entry in tree.dirs() {
+            let tree_path = workspace.join(&dir_entry.name);
+            let is_dir = symlink_metadata(&tree_path)
+                .await
+                .is_ok_and(|meta| meta.is_dir());
+            if !is_dir {
+                // a file or a symlink, even to a dir, in place of the dir.
+                remove_path(&tree_path).await?;
+                create_dir_all(&tree_path).await?;
+            }
+            // never descend through a symlink, even one made by this checkout.
+            if !symlink_metadata(&tree_path).await?.is_dir() {
+                return Err(WsvcFsError::InvalidFilename(join_path(
+                    dir,
+                    &dir_entry.name,
+                )));
             }
             if let Some(pos) = should_be_del
                 .iter()
-                .position(|x| x == blob_path.file_name().unwrap_or_default())
+                .position(|x| x == tree_path.file_name().unwrap_or_default())
             {
                 should_be_del.remove(pos);
             }
+            self.checkout_tree_impl(
+                &self.read_tree(&dir_entry.hash).await?,
+                &tree_path,
+                ignore,
+                attributes,
+                &join_path(dir, &dir_entry.name),
+            )
+            .await?;
         }
         for entry in should_be_del {
-            let entry_path = workspace.join(entry);
-            if entry_path.is_dir() {
+            let entry_path = workspace.join(&entry);
+            let path = join_path(dir, &entry.to_string_lossy());
+            // symlinks to dirs are removed as files, never followed.
+            let is_dir = symlink_metadata(&entry_path)
+                .await
+                .is_ok_and(|meta| meta.is_dir());
+            if ignore.is_ignored(&path, is_dir) {
+                continue;
+            }
+            if is_dir {
                 if entry_path.file_name().unwrap().eq(".wsvc") {
                     continue;
                 }
@@ -528,37 +1799,347 @@ impl Repository {
 
     /// store a record to records dir.
     pub async fn store_record(&self, record: &Record) -> Result<(), WsvcFsError> {
+        self.check_writable()?;
         let record_path = self
             .records_dir()
             .await?
             .join(record.hash.0.to_hex().as_str());
-        write(record_path, serde_json::to_vec(record)?).await?;
+        write(
+            record_path,
+            encode_object(record, self.options.object_format)?,
+        )
+        .await?;
+        self.index_record(record).await
+    }
+
+    /// remove a record from records dir.
+    pub async fn remove_record(&self, record_hash: &ObjectId) -> Result<(), WsvcFsError> {
+        self.check_writable()?;
+        let record_path = self
+            .records_dir()
+            .await?
+            .join(record_hash.0.to_hex().as_str());
+        if record_path.exists() {
+            remove_file(record_path).await?;
+        }
+        Ok(())
+    }
+
+    /// store a tree object to trees dir.
+    ///
+    /// the hash of the tree will be recalculated, the stored tree is returned.
+    pub async fn store_tree(&self, tree: &Tree) -> Result<Tree, WsvcFsError> {
+        self.check_writable()?;
+        let mut result = Tree {
+            hash: ObjectId(Hash::from([0; 32])),
+            ..tree.clone()
+        };
+        result.sort();
+        let hash = blake3::hash(serde_json::to_vec(&result)?.as_slice());
+        result.hash = ObjectId(hash);
+        let tree_path = self.trees_dir().await?.join(hash.to_hex().as_str());
+        if !tree_path.exists() {
+            write(
+                tree_path,
+                encode_object(&result, self.options.object_format)?,
+            )
+            .await?;
+        }
+        Ok(result)
+    }
+
+    /// store a tree received from a remote by its hash. the tree is refused if its names
+    /// could escape a workspace, see `check_tree_names`, or it does not match its hash.
+    pub async fn store_synced_tree(&self, tree: &Tree) -> Result<(), WsvcFsError> {
+        self.check_writable()?;
+        check_tree_names(tree)?;
+        if tree.calc_hash()? != tree.hash {
+            return Err(WsvcFsError::ObjectMismatch(
+                tree.hash.0.to_hex().to_string(),
+            ));
+        }
+        let tree_path = self.trees_dir().await?.join(tree.hash.0.to_hex().as_str());
+        write(tree_path, encode_object(tree, self.options.object_format)?).await?;
         Ok(())
     }
 
+    /// remove all trees and blobs that are not reachable from any record.
+    ///
+    /// returns a tuple of `(removed_trees, removed_blobs)`, see `gc`.
+    pub async fn prune_unreachable(&self) -> Result<(usize, usize), WsvcFsError> {
+        let stats = self.gc(false).await?;
+        Ok((stats.trees, stats.blobs))
+    }
+
+    /// names of the trees and blobs reachable from records, release assets, overlays and
+    /// stashes, chunks of reachable chunked blobs included.
+    pub(crate) async fn reachable_objects(
+        &self,
+    ) -> Result<(HashSet<String>, HashSet<String>), WsvcFsError> {
+        let mut reachable_trees = HashSet::new();
+        let mut reachable_blobs = HashSet::new();
+        for record in self.get_records().await? {
+            for tree in self.get_trees_of_record(&record.hash).await? {
+                for blob in tree.files() {
+                    reachable_blobs.insert(blob.hash.0.to_hex().to_string());
+                }
+                reachable_trees.insert(tree.hash.0.to_hex().to_string());
+            }
+        }
+        // release assets are stored as blobs without records.
+        for hash in self.release_asset_blobs().await? {
+            reachable_blobs.insert(hash.0.to_hex().to_string());
+        }
+        // overlays keep trees of records from other repositories, stashes keep trees of
+        // workspaces.
+        let mut roots = self.overlay_roots().await?;
+        roots.extend(self.stash_roots().await?);
+        for root in roots {
+            let mut queue = vec![root];
+            while let Some(tree_hash) = queue.pop() {
+                let tree = self.read_tree(&tree_hash).await?;
+                reachable_blobs.extend(
+                    tree.blobs()
+                        .into_iter()
+                        .map(|b| b.hash.0.to_hex().to_string()),
+                );
+                queue.extend(tree.dirs().map(|e| e.hash.clone()));
+                reachable_trees.insert(tree_hash.0.to_hex().to_string());
+            }
+        }
+        // chunks of chunked blobs are reachable with their blobs.
+        let objects_dir = self.objects_dir().await?;
+        let mut reachable_chunks = vec![];
+        for name in &reachable_blobs {
+            let hash = Hash::from_hex(name)?;
+            if locate(&objects_dir, &hash).await?.is_none() {
+                continue;
+            }
+            if let Some(manifest) = read_manifest(&objects_dir, &hash).await? {
+                reachable_chunks.extend(
+                    manifest
+                        .chunks
+                        .into_iter()
+                        .map(|c| c.hash.0.to_hex().to_string()),
+                );
+            }
+        }
+        reachable_blobs.extend(reachable_chunks);
+        Ok((reachable_trees, reachable_blobs))
+    }
+
+    /// how the objects are stored: loose and packed, the chunks shared by chunked blobs and
+    /// the bytes a repack would reclaim, to tell whether a repack is worth it.
+    pub async fn storage_stats(&self) -> Result<StorageStats, WsvcFsError> {
+        let objects_dir = self.objects_dir().await?;
+        let mut stats = StorageStats::default();
+        // stored bytes of each object, of the first copy found.
+        let mut stored: HashMap<Hash, u64> = HashMap::new();
+        if objects_dir.exists() {
+            let mut entries = read_dir(&objects_dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let Ok(hash) = Hash::from_hex(entry.file_name().to_string_lossy().as_bytes())
+                else {
+                    continue;
+                };
+                let metadata = entry.metadata().await?;
+                if !metadata.is_file() {
+                    continue;
+                }
+                stats.loose_objects += 1;
+                stats.loose_size += metadata.len();
+                stored.insert(hash, metadata.len());
+            }
+        }
+        for index in pack_indexes(&objects_dir).await? {
+            stats.packs += 1;
+            for path in [index.clone(), index.with_extension("pack")] {
+                stats.packed_size += tokio::fs::metadata(path).await?.len();
+            }
+            for (hash, entry) in read_index(&index).await? {
+                stats.packed_objects += 1;
+                match stored.contains_key(&Hash::from(hash)) {
+                    true => stats.duplicate_objects += 1,
+                    false => {
+                        stored.insert(Hash::from(hash), entry.len);
+                    }
+                }
+            }
+        }
+        let mut references: HashMap<Hash, u64> = HashMap::new();
+        for hash in stored.keys() {
+            if let Some(manifest) = read_manifest(&objects_dir, hash).await? {
+                stats.chunked_blobs += 1;
+                for chunk in manifest.chunks {
+                    *references.entry(chunk.hash.0).or_insert(0) += 1;
+                }
+            }
+        }
+        stats.chunks = references.len();
+        stats.shared_chunk_size = references
+            .iter()
+            .map(|(hash, count)| (count - 1) * stored.get(hash).copied().unwrap_or(0))
+            .sum();
+        let repacked = match stored.is_empty() {
+            true => 0,
+            false => pack_size(stored.len(), stored.values().sum()),
+        };
+        stats.repack_savings = (stats.loose_size + stats.packed_size).saturating_sub(repacked);
+        Ok(stats)
+    }
+
+    /// move all loose objects and packs into one new pack, so big repositories are not
+    /// stored in millions of small files. objects are looked up in packs transparently.
+    pub async fn repack(&self) -> Result<RepackStats, WsvcFsError> {
+        self.check_writable()?;
+        self.repack_objects(true, None).await
+    }
+
+    /// write the objects of all packs, and the loose objects if `loose`, into one new pack,
+    /// leaving out objects not in `keep` if given. the old packs and the packed loose
+    /// objects are removed once the new pack is in place.
+    ///
+    /// without `loose`, packs are only written again if an object is left out.
+    pub(crate) async fn repack_objects(
+        &self,
+        loose: bool,
+        keep: Option<&HashSet<String>>,
+    ) -> Result<RepackStats, WsvcFsError> {
+        let objects_dir = self.objects_dir().await?;
+        let indexes = pack_indexes(&objects_dir).await?;
+        let mut stats = RepackStats {
+            packs: indexes.len(),
+            ..Default::default()
+        };
+        if indexes.is_empty() && !loose {
+            return Ok(stats);
+        }
+        let mut writer = PackWriter::create(&self.temp_dir().await?).await?;
+        for index in &indexes {
+            let mut pack = File::open(index.with_extension("pack")).await?;
+            for (hash, entry) in read_index(index).await? {
+                if writer.contains(&hash) {
+                    continue;
+                }
+                if keep.is_some_and(|keep| !keep.contains(Hash::from(hash).to_hex().as_str())) {
+                    stats.dropped += 1;
+                    continue;
+                }
+                pack.seek(std::io::SeekFrom::Start(entry.offset)).await?;
+                writer.add(hash, &mut (&mut pack).take(entry.len)).await?;
+            }
+        }
+        let mut packed_loose = vec![];
+        if loose {
+            let mut entries = read_dir(&objects_dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let Ok(hash) = Hash::from_hex(entry.file_name().to_string_lossy().as_bytes())
+                else {
+                    continue;
+                };
+                if !entry.file_type().await?.is_file() {
+                    continue;
+                }
+                if !writer.contains(hash.as_bytes()) {
+                    writer
+                        .add(*hash.as_bytes(), &mut File::open(entry.path()).await?)
+                        .await?;
+                    stats.loose += 1;
+                }
+                packed_loose.push(entry.path());
+            }
+        }
+        if !loose && stats.dropped == 0 {
+            writer.discard().await?;
+            return Ok(stats);
+        }
+        stats.objects = writer.len();
+        let new_index = if writer.len() > 0 {
+            let (index, size) = writer.finish(&objects_dir).await?;
+            stats.size = size;
+            Some(index)
+        } else {
+            writer.discard().await?;
+            None
+        };
+        for index in indexes {
+            // the same objects are packed into the same pack again.
+            if Some(&index) != new_index.as_ref() {
+                remove_pack(&index).await?;
+            }
+        }
+        for path in packed_loose {
+            remove_file(path).await?;
+        }
+        Ok(stats)
+    }
+
     /// find a record for the specified tree.
     pub async fn find_record_for_tree(
         &self,
         tree_id: &Hash,
     ) -> Result<Option<Record>, WsvcFsError> {
-        let records = self.get_records().await?;
-        for record in records {
-            let tree = self.read_tree(&record.root).await?;
-            if tree.hash.0 == *tree_id {
-                return Ok(Some(record));
-            }
+        match self
+            .record_summaries()
+            .await?
+            .into_iter()
+            .find(|summary| summary.root.0 == *tree_id)
+        {
+            Some(summary) => Ok(Some(self.read_record(&summary.hash).await?)),
+            None => Ok(None),
         }
-        Ok(None)
     }
 
-    /// commit a record.
+    /// commit a record, advancing the branch HEAD is on or HEAD itself.
+    ///
+    /// the whole workspace is recorded if `paths` is `None`, otherwise only the files and
+    /// dirs at `paths` are, the rest of the record is the HEAD record.
+    ///
+    /// fails if files are over the size policy of the options and it blocks, see
+    /// `size_warnings`.
     pub async fn commit_record(
         &self,
         workspace: &Path,
         author: impl AsRef<str>,
         message: impl AsRef<str>,
+        paths: Option<&[String]>,
+    ) -> Result<Record, WsvcFsError> {
+        self.commit_record_at(workspace, author, message, paths, chrono::Utc::now())
+            .await
+    }
+
+    /// commit a record like `commit_record` dated `date` instead of now, e.g. when
+    /// importing history. records keep whole seconds in UTC.
+    pub async fn commit_record_at(
+        &self,
+        workspace: &Path,
+        author: impl AsRef<str>,
+        message: impl AsRef<str>,
+        paths: Option<&[String]>,
+        date: chrono::DateTime<chrono::Utc>,
     ) -> Result<Record, WsvcFsError> {
-        let tree = self.write_tree_recursively(workspace).await?;
+        // the workspace may be half checked out.
+        self.check_no_operation().await?;
+        let head_ref = self.read_head().await?;
+        let head = head_ref.record().cloned();
+        let base = match &head {
+            Some(head) => Some(self.read_record(head).await?.root),
+            None => None,
+        };
+        let tree = match paths {
+            Some(paths) => {
+                let tree = self
+                    .write_tree_of_paths(workspace, base.as_ref(), paths)
+                    .await?;
+                if let (Some(head), Some(base)) = (&head, &base) {
+                    if tree.hash == *base {
+                        return Err(WsvcFsError::NoChanges(head.0.to_hex().to_string()));
+                    }
+                }
+                (tree, true)
+            }
+            None => self.write_tree_recursively(workspace).await?,
+        };
         if !tree.1 {
             if let Some(record) = self.find_record_for_tree(&tree.0.hash.0).await? {
                 return Err(WsvcFsError::NoChanges(
@@ -566,21 +2147,47 @@ impl Repository {
                 ));
             }
         }
+        let policy = &self.options.size_policy;
+        if policy.block {
+            let warnings = self
+                .size_warnings(base.as_ref(), &tree.0.hash, policy)
+                .await?;
+            if !warnings.is_empty() {
+                return Err(WsvcFsError::FilesTooLarge(
+                    warnings
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                ));
+            }
+        }
         let record = Record {
             hash: ObjectId(Hash::from([0; 32])),
             message: String::from(message.as_ref()),
             author: String::from(author.as_ref()),
-            date: chrono::Utc::now(),
+            date,
             root: tree.0.hash,
+            parents: Some(head.iter().cloned().collect()),
         };
         let hash = blake3::hash(serde_json::to_vec(&record)?.as_slice());
         let record = Record {
             hash: ObjectId(hash),
             ..record
         };
-        // write record to HEAD
+        // write record to HEAD or the branch it is on, unless it was moved meanwhile.
         self.store_record(&record).await?;
-        write(self.path.join("HEAD"), hash.to_hex().to_string()).await?;
+        self.transaction()
+            .compare_and_set(head_ref.target(), head.as_ref(), &record.hash)
+            .commit()
+            .await?;
+        self.append_event(EventKind::Commit {
+            record: record.hash.clone(),
+            author: record.author.clone(),
+            message: record.message.clone(),
+        })
+        .await
+        .ok();
         Ok(record)
     }
 
@@ -590,22 +2197,26 @@ impl Repository {
             .records_dir()
             .await?
             .join(record_hash.0.to_hex().as_str());
-        let result = serde_json::from_slice::<Record>(&tokio::fs::read(record_path).await?)?;
-        Ok(result)
+        decode_object::<Record>(&tokio::fs::read(record_path).await?)
     }
 
-    /// checkout a record to workspace.
+    /// checkout a record to workspace, HEAD is set to the record and leaves its branch.
     pub async fn checkout_record(
         &self,
         record_hash: &ObjectId,
         workspace: &Path,
     ) -> Result<Record, WsvcFsError> {
+        self.check_writable()?;
         let record = self.read_record(record_hash).await?;
-        self.checkout_tree(&self.read_tree(&record.root).await?, workspace)
-            .await?;
+        self.checkout_record_tree(&record, workspace).await?;
         // write record to HEAD
-        write(self.path.join("HEAD"), record_hash.0.to_hex().to_string()).await?;
+        self.transaction().set("HEAD", record_hash).commit().await?;
         remove_dir_all(self.temp_dir().await?).await?;
+        self.append_event(EventKind::Checkout {
+            record: record.hash.clone(),
+        })
+        .await
+        .ok();
         Ok(record)
     }
 
@@ -630,6 +2241,21 @@ impl Repository {
         Ok(result)
     }
 
+    /// find records whose hash starts with `prefix`, case insensitive.
+    pub async fn find_records_by_prefix(
+        &self,
+        prefix: impl AsRef<str>,
+    ) -> Result<Vec<Record>, WsvcFsError> {
+        let prefix = prefix.as_ref().to_ascii_lowercase();
+        let mut result = vec![];
+        for summary in self.record_summaries().await? {
+            if summary.hash.0.to_hex().starts_with(&prefix) {
+                result.push(self.read_record(&summary.hash).await?);
+            }
+        }
+        Ok(result)
+    }
+
     /// get all trees of a record
     pub async fn get_trees_of_record(
         &self,
@@ -640,62 +2266,92 @@ impl Repository {
         let mut queue = vec![record.root];
         while let Some(tree_hash) = queue.pop() {
             let tree = self.read_tree(&tree_hash).await?;
-            result.push(tree.clone());
-            for tree_hash in tree.trees {
-                queue.push(tree_hash);
-            }
+            queue.extend(tree.dirs().map(|e| e.hash.clone()));
+            result.push(tree);
         }
         Ok(result)
     }
 
     pub async fn get_blobs_of_tree(&self, tree_hash: &ObjectId) -> Result<Vec<Blob>, WsvcFsError> {
-        let tree = self.read_tree(tree_hash).await?;
-        let mut result = tree.blobs.clone();
-        let mut queue = tree.trees;
+        let mut result = Vec::new();
+        let mut queue = vec![tree_hash.clone()];
         while let Some(tree_hash) = queue.pop() {
             let tree = self.read_tree(&tree_hash).await?;
-            result.extend(tree.blobs.clone());
-            for tree_hash in tree.trees {
-                queue.push(tree_hash);
-            }
+            result.extend(tree.blobs());
+            queue.extend(tree.dirs().map(|e| e.hash.clone()));
         }
         Ok(result)
     }
 
+    /// resolve `path` in a record to its entry, reading only the trees along the path.
+    ///
+    /// components are separated by `/`, an empty path resolves to the root dir. `None`
+    /// if nothing is at the path.
+    pub async fn resolve_path(
+        &self,
+        record_hash: &ObjectId,
+        path: impl AsRef<str>,
+    ) -> Result<Option<Entry>, WsvcFsError> {
+        let record = self.read_record(record_hash).await?;
+        self.resolve_tree_path(&record.root, path).await
+    }
+
+    /// resolve `path` in the tree `root` to its entry, like `resolve_path`.
+    pub async fn resolve_tree_path(
+        &self,
+        root: &ObjectId,
+        path: impl AsRef<str>,
+    ) -> Result<Option<Entry>, WsvcFsError> {
+        let mut current = Entry {
+            kind: EntryKind::Dir,
+            name: String::new(),
+            hash: root.clone(),
+            mode: 0,
+            size: 0,
+        };
+        for component in path.as_ref().split('/') {
+            match component {
+                "" | "." => continue,
+                ".." => return Err(WsvcFsError::InvalidFilename(path.as_ref().to_owned())),
+                _ => {}
+            }
+            if current.kind != EntryKind::Dir {
+                return Ok(None);
+            }
+            let tree = self.read_tree(&current.hash).await?;
+            match tree.entries.into_iter().find(|e| e.name == component) {
+                Some(entry) => current = entry,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(current))
+    }
+
     /// get the latest record
     pub async fn get_latest_record(&self) -> Result<Option<Record>, WsvcFsError> {
-        let mut records = self.get_records().await?;
-        if records.is_empty() {
-            return Ok(None);
+        let latest = self.record_summaries().await?.into_iter().max_by(|a, b| {
+            a.date
+                .cmp(&b.date)
+                .then_with(|| a.hash.0.as_bytes().cmp(b.hash.0.as_bytes()))
+        });
+        match latest {
+            Some(summary) => Ok(Some(self.read_record(&summary.hash).await?)),
+            None => Ok(None),
         }
-        records.sort_by(|a, b| b.date.cmp(&a.date));
-        Ok(Some(records[0].clone()))
     }
 
     /// get the head record
     pub async fn get_head_record(&self) -> Result<Option<Record>, WsvcFsError> {
-        let head_hash = read(self.path.join("HEAD")).await?;
-        if String::from_utf8(head_hash.clone())
-            .map_err(|err| WsvcFsError::InvalidOsString(format!("{:?}", err)))?
-            == *""
-        {
-            return Ok(None);
+        match self.read_ref("HEAD").await? {
+            Some(head_hash) => Ok(Some(self.read_record(&head_hash).await?)),
+            None => Ok(None),
         }
-        Ok(Some(
-            self.read_record(
-                &String::from_utf8(head_hash)
-                    .map_err(|err| WsvcFsError::InvalidOsString(format!("{:?}", err)))?
-                    .try_into()?,
-            )
-            .await?,
-        ))
     }
 
     pub async fn write_origin(&self, url: String) -> Result<(), WsvcFsError> {
+        self.check_writable()?;
         // write remote repo url to ORIGIN
-        write(self.path.join("ORIGIN"), url)
-            .await
-            .map_err(WsvcFsError::Os)
+        write_atomic(&self.path.join("ORIGIN"), url.as_bytes()).await
     }
 
     pub async fn read_origin(&self) -> Result<String, WsvcFsError> {
@@ -709,16 +2365,143 @@ impl Repository {
 impl Blob {
     /// get the checksum of a blob file.
     pub async fn checksum(&self, rel_path: impl AsRef<Path>) -> Result<bool, WsvcFsError> {
-        let mut file = File::open(rel_path).await?;
-        let mut buffer: [u8; 16384] = [0; 16384];
-        let mut hasher = blake3::Hasher::new();
-        loop {
-            let n = file.read(&mut buffer).await?;
-            if n == 0 {
-                break;
-            }
-            hasher.update(&buffer[..n]);
+        Ok(hash_file(rel_path).await? == self.hash)
+    }
+}
+
+/// the blob hash of a file, without storing it.
+pub(crate) async fn hash_file(path: impl AsRef<Path>) -> Result<ObjectId, WsvcFsError> {
+    let mut file = File::open(path).await?;
+    let mut buffer: [u8; 16384] = [0; 16384];
+    let mut hasher = blake3::Hasher::new();
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(ObjectId(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a new repository in a temp dir, its workspace is the dir.
+    async fn temp_repo() -> (Repository, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("wsvc-test-{}", nanoid!()));
+        create_dir_all(&dir).await.unwrap();
+        (Repository::new(&dir, false).await.unwrap(), dir)
+    }
+
+    fn entry(kind: EntryKind, name: &str, hash: ObjectId) -> Entry {
+        Entry {
+            kind,
+            name: name.to_owned(),
+            hash,
+            mode: 0,
+            size: 0,
         }
-        Ok(hasher.finalize() == self.hash.0)
+    }
+
+    fn tree(entries: Vec<Entry>) -> Tree {
+        Tree {
+            name: String::new(),
+            hash: ObjectId::default(),
+            entries,
+        }
+    }
+
+    #[test]
+    fn entry_names_are_single_components() {
+        for name in ["a", ".hidden", "a.b", "..a"] {
+            assert!(check_entry_name(name).is_ok(), "{}", name);
+        }
+        for name in [
+            "", ".", "..", ".wsvc", "../x", "/etc/x", "a/b", "a\\b", "a\0b",
+        ] {
+            assert!(check_entry_name(name).is_err(), "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_frames_are_refused() {
+        let (_, dir) = temp_repo().await;
+        let path = dir.join("object");
+        let mut object = Codec::Zstd.magic().to_vec();
+        object.extend_from_slice(&u32::MAX.to_be_bytes());
+        tokio::fs::write(&path, &object).await.unwrap();
+        let hash = ObjectId(blake3::hash(b""));
+        assert_eq!(verified_object_size(&path, &hash).await.unwrap(), None);
+        remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn synced_trees_are_checked() {
+        let (repo, dir) = temp_repo().await;
+        let blob = repo.store_blob_data(b"x").await.unwrap();
+        let mut escaping = tree(vec![entry(EntryKind::File, "../../x", blob.clone())]);
+        escaping.hash = escaping.calc_hash().unwrap();
+        assert!(repo.store_synced_tree(&escaping).await.is_err());
+        let mut good = tree(vec![entry(EntryKind::File, "x", blob)]);
+        assert!(repo.store_synced_tree(&good).await.is_err());
+        good.hash = good.calc_hash().unwrap();
+        repo.store_synced_tree(&good).await.unwrap();
+        assert_eq!(repo.read_tree(&good.hash).await.unwrap(), good);
+        remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn checkout_refuses_escaping_names() {
+        let (repo, dir) = temp_repo().await;
+        let blob = repo.store_blob_data(b"x").await.unwrap();
+        let name = format!("../{}-escaped", dir.file_name().unwrap().to_string_lossy());
+        let root = repo
+            .store_tree(&tree(vec![entry(EntryKind::File, &name, blob)]))
+            .await
+            .unwrap();
+        assert!(repo.checkout_tree(&root, &dir).await.is_err());
+        assert!(!dir.join(&name).exists());
+        remove_dir_all(dir).await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn checkout_never_writes_through_symlinks() {
+        let (repo, dir) = temp_repo().await;
+        let outside = std::env::temp_dir().join(format!("wsvc-test-{}", nanoid!()));
+        create_dir_all(&outside).await.unwrap();
+        let blob = repo.store_blob_data(b"x").await.unwrap();
+        let target = repo
+            .store_blob_data(outside.to_string_lossy().as_bytes())
+            .await
+            .unwrap();
+        let sub = repo
+            .store_tree(&tree(vec![entry(EntryKind::File, "x", blob)]))
+            .await
+            .unwrap();
+        // a symlink and a dir of the same name, the dir is written after the symlink.
+        let root = repo
+            .store_tree(&tree(vec![
+                entry(EntryKind::Symlink, "d", target),
+                entry(EntryKind::Dir, "d", sub.hash.clone()),
+            ]))
+            .await
+            .unwrap();
+        assert!(repo.checkout_tree(&root, &dir).await.is_err());
+        assert!(!outside.join("x").exists());
+        // a symlink left in place of a dir is replaced, not followed.
+        tokio::fs::symlink(&outside, dir.join("d")).await.unwrap();
+        let root = repo
+            .store_tree(&tree(vec![entry(EntryKind::Dir, "d", sub.hash)]))
+            .await
+            .unwrap();
+        repo.checkout_tree(&root, &dir).await.unwrap();
+        assert!(!outside.join("x").exists());
+        assert!(symlink_metadata(dir.join("d")).await.unwrap().is_dir());
+        assert_eq!(read(dir.join("d/x")).await.unwrap(), b"x");
+        remove_dir_all(dir).await.unwrap();
+        remove_dir_all(outside).await.unwrap();
     }
 }
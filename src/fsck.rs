@@ -0,0 +1,290 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use tokio::fs::{create_dir_all, read, read_dir, rename};
+
+use crate::{
+    chunk::ChunkManifest,
+    encoding::{decode_object, json_content},
+    fs::WsvcFsError,
+    model::{EntryKind, ObjectId, Record, Repository, Tree},
+};
+
+/// dir of objects moved aside by `Repository::fsck`, in the repository.
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+/// `ObjectKind` stand for the kinds of stored objects checked by `Repository::fsck`.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ObjectKind {
+    Record,
+    Tree,
+    Blob,
+}
+
+impl ObjectKind {
+    /// dir of quarantined objects of the kind.
+    fn quarantine_dir(self) -> &'static str {
+        match self {
+            ObjectKind::Record => "records",
+            ObjectKind::Tree => "trees",
+            ObjectKind::Blob => "objects",
+        }
+    }
+}
+
+/// `FsckProblem` stand for what is wrong with an object.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase", tag = "problem")]
+pub enum FsckProblem {
+    /// referenced by a record or tree but not stored.
+    Missing { referenced_by: String },
+    /// stored, but the content hashes to `actual` instead of the name of the object.
+    Mismatch { actual: String },
+    /// stored, but the content can not be read, e.g. broken frames or json.
+    Corrupt { reason: String },
+}
+
+/// `FsckIssue` stand for a problem of an object found by `Repository::fsck`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct FsckIssue {
+    pub kind: ObjectKind,
+    pub hash: String,
+    #[serde(flatten)]
+    pub problem: FsckProblem,
+    /// the object was moved to the quarantine dir.
+    pub quarantined: bool,
+}
+
+/// `FsckReport` stand for the objects checked by `Repository::fsck` and the problems found.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct FsckReport {
+    pub records: usize,
+    pub trees: usize,
+    /// blobs and chunks of chunked blobs.
+    pub blobs: usize,
+    pub issues: Vec<FsckIssue>,
+}
+
+impl FsckReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    fn push(&mut self, kind: ObjectKind, hash: &str, problem: FsckProblem) {
+        self.issues.push(FsckIssue {
+            kind,
+            hash: hash.to_owned(),
+            problem,
+            quarantined: false,
+        });
+    }
+}
+
+/// the problem of a stored record or tree named `name`, whose content holds its own hash
+/// and is hashed with that hash zeroed, like when it was stored. checking the content as
+/// stored also covers trees stored in older shapes. binary encoded objects are checked
+/// by their json encoding.
+fn check_stored_hash(name: &str, content: &[u8]) -> Option<FsckProblem> {
+    let own = format!("\"hash\":\"{}\"", name);
+    let Some(at) = content
+        .windows(own.len())
+        .position(|window| window == own.as_bytes())
+    else {
+        return Some(FsckProblem::Corrupt {
+            reason: "the stored hash is not the name".to_owned(),
+        });
+    };
+    let start = at + own.len() - 1 - name.len();
+    let mut zeroed = content.to_vec();
+    zeroed[start..start + name.len()].copy_from_slice(ObjectId::default().0.to_hex().as_bytes());
+    let actual = blake3::hash(&zeroed).to_hex().to_string();
+    (actual != name).then_some(FsckProblem::Mismatch { actual })
+}
+
+impl Repository {
+    /// check the integrity of the repository, sync only checks objects exist.
+    ///
+    /// every record and tree is hashed again as stored, every blob is decompressed and
+    /// hashed again frame by frame, chunked blobs chunk by chunk, and trees and blobs of
+    /// records are checked to be stored. with `quarantine`, corrupt objects are moved to
+    /// `QUARANTINE_DIR`, so they are never read or sent to remotes again.
+    pub async fn fsck(&self, quarantine: bool) -> Result<FsckReport, WsvcFsError> {
+        if quarantine {
+            self.check_writable()?;
+        }
+        let mut report = FsckReport::default();
+        let mut roots = vec![];
+        let records_dir = self.records_dir().await?;
+        let mut entries = read_dir(&records_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            report.records += 1;
+            let content = read(entry.path()).await?;
+            let problem = match decode_object::<Record>(&content) {
+                Ok(record) => {
+                    roots.push((record.root, name.clone()));
+                    check_stored_hash(&name, &json_content::<Record>(&content)?)
+                }
+                Err(err) => Some(FsckProblem::Corrupt {
+                    reason: err.to_string(),
+                }),
+            };
+            if let Some(problem) = problem {
+                report.push(ObjectKind::Record, &name, problem);
+            }
+        }
+        let trees_dir = self.trees_dir().await?;
+        let mut trees = HashSet::new();
+        let mut entries = read_dir(&trees_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            report.trees += 1;
+            let content = read(entry.path()).await?;
+            let problem = match decode_object::<Tree>(&content) {
+                Ok(_) => check_stored_hash(&name, &json_content::<Tree>(&content)?),
+                Err(err) => Some(FsckProblem::Corrupt {
+                    reason: err.to_string(),
+                }),
+            };
+            match problem {
+                Some(problem) => report.push(ObjectKind::Tree, &name, problem),
+                None => {
+                    trees.insert(name);
+                }
+            }
+        }
+        let blobs = self.stored_objects().await?;
+        let mut bad_blobs = HashSet::new();
+        // chunked blobs last, a blob with a broken or missing chunk is not broken itself.
+        let mut chunked = vec![];
+        let mut plain = vec![];
+        for name in &blobs {
+            let hash = ObjectId(blake3::Hash::from_hex(name)?);
+            match self.blob_chunks(&hash).await {
+                Ok(Some(manifest)) => chunked.push((name, Some(manifest))),
+                _ => plain.push((name, None)),
+            }
+        }
+        for (name, manifest) in plain.into_iter().chain(chunked) {
+            report.blobs += 1;
+            let chunk_broken = manifest.is_some_and(|manifest: ChunkManifest| {
+                manifest.chunks.iter().any(|chunk| {
+                    let chunk = chunk.hash.0.to_hex();
+                    bad_blobs.contains(chunk.as_str()) || !blobs.contains(chunk.as_str())
+                })
+            });
+            if chunk_broken {
+                continue;
+            }
+            let problem = match self
+                .hash_blob(&ObjectId(blake3::Hash::from_hex(name)?))
+                .await
+            {
+                Ok(actual) if actual.to_hex().as_str() == name => None,
+                Ok(actual) => Some(FsckProblem::Mismatch {
+                    actual: actual.to_hex().to_string(),
+                }),
+                Err(err) => Some(FsckProblem::Corrupt {
+                    reason: err.to_string(),
+                }),
+            };
+            if let Some(problem) = problem {
+                report.push(ObjectKind::Blob, name, problem);
+                bad_blobs.insert(name.clone());
+            }
+        }
+        // trees and blobs of records, objects found corrupt are reported once.
+        let reported: HashSet<String> = report.issues.iter().map(|i| i.hash.clone()).collect();
+        let mut visited = HashSet::new();
+        let mut missing = vec![];
+        let blobless = self.is_blobless();
+        while let Some((tree_hash, by)) = roots.pop() {
+            let name = tree_hash.0.to_hex().to_string();
+            if !visited.insert(name.clone()) || reported.contains(&name) {
+                continue;
+            }
+            if !trees.contains(&name) {
+                missing.push((ObjectKind::Tree, name, by));
+                continue;
+            }
+            let tree: Tree = decode_object(&read(trees_dir.join(&name)).await?)?;
+            for entry in tree.entries {
+                let entry_name = entry.hash.0.to_hex().to_string();
+                if entry.kind == EntryKind::Dir {
+                    roots.push((entry.hash, name.clone()));
+                } else if !blobs.contains(&entry_name) {
+                    // blobless repositories fetch blobs when they are read.
+                    if !blobless {
+                        missing.push((ObjectKind::Blob, entry_name, name.clone()));
+                    }
+                } else if !bad_blobs.contains(&entry_name) && visited.insert(entry_name) {
+                    // chunks of a chunked blob are needed with it.
+                    if let Ok(Some(ChunkManifest { chunks, .. })) =
+                        self.blob_chunks(&entry.hash).await
+                    {
+                        for chunk in chunks {
+                            let chunk_name = chunk.hash.0.to_hex().to_string();
+                            if !blobs.contains(&chunk_name) {
+                                missing.push((
+                                    ObjectKind::Blob,
+                                    chunk_name,
+                                    entry.hash.0.to_hex().to_string(),
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        let mut seen = HashSet::new();
+        for (kind, name, referenced_by) in missing {
+            if seen.insert(name.clone()) {
+                report.push(kind, &name, FsckProblem::Missing { referenced_by });
+            }
+        }
+        if quarantine {
+            self.quarantine(&mut report).await?;
+        }
+        Ok(report)
+    }
+
+    /// move the corrupt objects of `report` to the quarantine dir.
+    async fn quarantine(&self, report: &mut FsckReport) -> Result<(), WsvcFsError> {
+        let root = self.path.join(QUARANTINE_DIR);
+        let mut blobs = HashSet::new();
+        for issue in report.issues.iter_mut() {
+            if matches!(issue.problem, FsckProblem::Missing { .. }) {
+                continue;
+            }
+            let dir = root.join(issue.kind.quarantine_dir());
+            match issue.kind {
+                ObjectKind::Blob => {
+                    blobs.insert(issue.hash.clone());
+                }
+                ObjectKind::Record => {
+                    create_dir_all(&dir).await?;
+                    rename(
+                        self.records_dir().await?.join(&issue.hash),
+                        dir.join(&issue.hash),
+                    )
+                    .await?;
+                }
+                ObjectKind::Tree => {
+                    create_dir_all(&dir).await?;
+                    rename(
+                        self.trees_dir().await?.join(&issue.hash),
+                        dir.join(&issue.hash),
+                    )
+                    .await?;
+                }
+            }
+            issue.quarantined = true;
+        }
+        if !blobs.is_empty() {
+            self.quarantine_objects(&blobs, &root.join(ObjectKind::Blob.quarantine_dir()))
+                .await?;
+        }
+        Ok(())
+    }
+}
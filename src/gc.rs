@@ -0,0 +1,83 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use tokio::fs::{read_dir, remove_file};
+
+use crate::{
+    events::EventKind,
+    fs::WsvcFsError,
+    model::Repository,
+    pack::{pack_indexes, read_index},
+};
+
+/// `GcStats` stand for the unreachable trees and blobs found by `Repository::gc`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct GcStats {
+    pub trees: usize,
+    /// loose and packed blobs, including chunks of chunked blobs.
+    pub blobs: usize,
+    /// bytes the unreachable trees and blobs take in the repository.
+    pub bytes: u64,
+}
+
+/// unreachable files in `dir`, with their bytes, removed unless `dry_run`.
+async fn collect_dir(
+    dir: &std::path::Path,
+    reachable: &HashSet<String>,
+    dry_run: bool,
+) -> Result<(usize, u64), WsvcFsError> {
+    let (mut count, mut bytes) = (0, 0);
+    let mut entries = read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let metadata = entry.metadata().await?;
+        if !metadata.is_file() || reachable.contains(entry.file_name().to_string_lossy().as_ref()) {
+            continue;
+        }
+        count += 1;
+        bytes += metadata.len();
+        if !dry_run {
+            remove_file(entry.path()).await?;
+        }
+    }
+    Ok((count, bytes))
+}
+
+impl Repository {
+    /// remove trees and blobs not reachable from any record, release asset, overlay or
+    /// stash, e.g. objects left by interrupted syncs or by removed records. packs holding
+    /// unreachable blobs are written again without them.
+    ///
+    /// with `dry_run` nothing is removed, the stats tell what would be.
+    pub async fn gc(&self, dry_run: bool) -> Result<GcStats, WsvcFsError> {
+        if !dry_run {
+            self.check_writable()?;
+        }
+        let (reachable_trees, reachable_blobs) = self.reachable_objects().await?;
+        let (trees, trees_bytes) =
+            collect_dir(&self.trees_dir().await?, &reachable_trees, dry_run).await?;
+        let objects_dir = self.objects_dir().await?;
+        let (mut blobs, mut bytes) = collect_dir(&objects_dir, &reachable_blobs, dry_run).await?;
+        let mut packed = 0;
+        for index in pack_indexes(&objects_dir).await? {
+            for (hash, entry) in read_index(&index).await? {
+                if !reachable_blobs.contains(blake3::Hash::from(hash).to_hex().as_str()) {
+                    packed += 1;
+                    bytes += entry.len;
+                }
+            }
+        }
+        blobs += packed;
+        if !dry_run && packed > 0 {
+            self.repack_objects(false, Some(&reachable_blobs)).await?;
+        }
+        if !dry_run {
+            // the journal is best effort, a failed append never fails the operation.
+            self.append_event(EventKind::Gc { trees, blobs }).await.ok();
+        }
+        Ok(GcStats {
+            trees,
+            blobs,
+            bytes: bytes + trees_bytes,
+        })
+    }
+}
@@ -0,0 +1,127 @@
+use std::{collections::HashSet, path::Path};
+
+use crate::{
+    branch::HeadRef,
+    fs::WsvcFsError,
+    model::{ObjectId, Repository},
+    readonly::ReadOnlyRepository,
+    walk::{RecordGraph, WalkOrder},
+    WsvcError,
+};
+
+/// `HeadState` stand for where HEAD is, new records are based on HEAD.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HeadState {
+    /// HEAD is not set, e.g. no record yet.
+    Unborn,
+    /// HEAD is the latest record.
+    Latest(ObjectId),
+    /// HEAD is on a branch, so it is never detached. `head` is `None` before the first
+    /// commit on the branch.
+    Branch {
+        name: String,
+        head: Option<ObjectId>,
+    },
+    /// HEAD is not the latest record, new records fork from HEAD.
+    Detached {
+        head: ObjectId,
+        latest: ObjectId,
+        /// count of records reachable from the latest record but not from HEAD.
+        behind: usize,
+        /// HEAD is an ancestor of the latest record, so it can be advanced to it.
+        fast_forward: bool,
+    },
+}
+
+impl HeadState {
+    fn new(graph: &RecordGraph, head: Option<ObjectId>, latest: Option<ObjectId>) -> Self {
+        match (head, latest) {
+            (Some(head), Some(latest)) if head != latest => {
+                let ancestors = graph
+                    .walk(Some(&head), WalkOrder::Topological)
+                    .into_iter()
+                    .map(|r| r.hash)
+                    .collect::<HashSet<_>>();
+                let to_latest = graph.walk(Some(&latest), WalkOrder::Topological);
+                Self::Detached {
+                    fast_forward: to_latest.iter().any(|r| r.hash == head),
+                    behind: to_latest
+                        .iter()
+                        .filter(|r| !ancestors.contains(&r.hash))
+                        .count(),
+                    head,
+                    latest,
+                }
+            }
+            (Some(head), _) => Self::Latest(head),
+            (None, _) => Self::Unborn,
+        }
+    }
+
+    pub fn is_detached(&self) -> bool {
+        matches!(self, Self::Detached { .. })
+    }
+}
+
+impl Repository {
+    /// where HEAD is compared to the latest record.
+    pub async fn head_state(&self) -> Result<HeadState, WsvcFsError> {
+        if let HeadRef::Branch { name, record } = self.read_head().await? {
+            return Ok(HeadState::Branch { name, head: record });
+        }
+        let graph = self.record_graph().await?;
+        Ok(HeadState::new(
+            &graph,
+            self.get_head_record().await?.map(|r| r.hash),
+            self.get_latest_record().await?.map(|r| r.hash),
+        ))
+    }
+
+    /// point HEAD to a record without touching the workspace.
+    pub async fn set_head(&self, record_hash: &ObjectId) -> Result<(), WsvcFsError> {
+        self.check_writable()?;
+        self.read_record(record_hash).await?;
+        self.transaction().set("HEAD", record_hash).commit().await
+    }
+
+    /// advance a detached HEAD to the latest record, keeping changes of the workspace.
+    ///
+    /// files changed from HEAD to the latest record are written to the workspace, which
+    /// fails without touching anything if the workspace changed any of them too. returns
+    /// the record HEAD is advanced to, `None` if HEAD is not detached.
+    pub async fn advance_head(&self, workspace: &Path) -> Result<Option<ObjectId>, WsvcError> {
+        let (head, latest) = match self.head_state().await? {
+            HeadState::Detached {
+                head,
+                latest,
+                fast_forward: true,
+                ..
+            } => (head, latest),
+            HeadState::Detached { head, latest, .. } => {
+                return Err(WsvcError::RepoError(format!(
+                    "HEAD {} is not an ancestor of the latest record {}, it can not be advanced",
+                    &head.0.to_hex()[0..6],
+                    &latest.0.to_hex()[0..6]
+                )))
+            }
+            _ => return Ok(None),
+        };
+        let changes = self.changes_between(&head, &latest).await?;
+        let conflicts = self.conflicting_changes(workspace, &changes).await;
+        if !conflicts.is_empty() {
+            return Err(WsvcError::RepoError(format!(
+                "workspace changes conflict with records after HEAD: {}",
+                conflicts.join(", ")
+            )));
+        }
+        self.apply_changes(workspace, changes).await?;
+        self.set_head(&latest).await?;
+        Ok(Some(latest))
+    }
+}
+
+impl ReadOnlyRepository {
+    pub async fn head_state(&self) -> Result<HeadState, WsvcFsError> {
+        self.repository().head_state().await
+    }
+}
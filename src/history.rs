@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    fs::WsvcFsError,
+    mailmap::Mailmap,
+    model::{Entry, ObjectId, Record, Repository},
+    readonly::ReadOnlyRepository,
+    walk::WalkOrder,
+};
+
+/// `FileRevision` stand for a record changing a path.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct FileRevision {
+    pub record: Record,
+    /// the entry at the path after the record, `None` if the record removed it.
+    pub entry: Option<Entry>,
+    /// whether the path was missing before the record, i.e. the record added it.
+    pub added: bool,
+}
+
+/// `AuthorChanges` stand for how many times and how recently an author changed a path.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct AuthorChanges {
+    /// the canonical identity of the author, see `Mailmap`.
+    pub author: String,
+    /// records of the author changing the path.
+    pub changes: usize,
+    /// date of the first record of the author changing the path.
+    pub first: DateTime<Utc>,
+    /// date of the latest record of the author changing the path.
+    pub last: DateTime<Utc>,
+    /// the latest record of the author changing the path.
+    pub record: ObjectId,
+}
+
+/// authors of `revisions` with how they changed the path, the most changes first and the
+/// latest change first among equals. authors are merged by `mailmap`.
+pub fn author_changes(revisions: &[FileRevision], mailmap: &Mailmap) -> Vec<AuthorChanges> {
+    let mut authors = HashMap::<&str, AuthorChanges>::new();
+    for revision in revisions {
+        let record = &revision.record;
+        let author = mailmap.resolve(&record.author);
+        let changes = authors.entry(author).or_insert_with(|| AuthorChanges {
+            author: author.to_owned(),
+            changes: 0,
+            first: record.date,
+            last: record.date,
+            record: record.hash.clone(),
+        });
+        changes.changes += 1;
+        changes.first = changes.first.min(record.date);
+        if record.date > changes.last {
+            changes.last = record.date;
+            changes.record = record.hash.clone();
+        }
+    }
+    let mut result = authors.into_values().collect::<Vec<_>>();
+    result.sort_by(|a, b| {
+        b.changes
+            .cmp(&a.changes)
+            .then_with(|| b.last.cmp(&a.last))
+            .then_with(|| a.author.cmp(&b.author))
+    });
+    result
+}
+
+impl Repository {
+    /// records reachable from `start` that changed `path`, the latest first. all records
+    /// if `start` is `None`.
+    ///
+    /// a record changed the path if its entry differs from the one of every parent, so a
+    /// merge taking the path from a parent is left out. a dir changes with any file under
+    /// it, and renames are not followed.
+    pub async fn file_history(
+        &self,
+        path: &str,
+        start: Option<&ObjectId>,
+    ) -> Result<Vec<FileRevision>, WsvcFsError> {
+        let graph = self.record_graph().await?;
+        let records = self.walk(start, WalkOrder::Date).await?;
+        let mut entries = HashMap::<ObjectId, Option<Entry>>::new();
+        let mut result = vec![];
+        for record in records {
+            let entry = self.entry_of(&mut entries, &record.hash, path).await?;
+            let mut parent_entries = vec![];
+            for parent in graph.parents(&record.hash) {
+                parent_entries.push(self.entry_of(&mut entries, parent, path).await?);
+            }
+            let changed = if parent_entries.is_empty() {
+                entry.is_some()
+            } else {
+                parent_entries.iter().all(|p| !same_entry(p, &entry))
+            };
+            if changed {
+                result.push(FileRevision {
+                    added: entry.is_some() && parent_entries.iter().all(Option::is_none),
+                    record,
+                    entry,
+                });
+            }
+        }
+        Ok(result)
+    }
+
+    /// the entry at `path` of a record, cached in `entries`.
+    async fn entry_of(
+        &self,
+        entries: &mut HashMap<ObjectId, Option<Entry>>,
+        record_hash: &ObjectId,
+        path: &str,
+    ) -> Result<Option<Entry>, WsvcFsError> {
+        if let Some(entry) = entries.get(record_hash) {
+            return Ok(entry.clone());
+        }
+        let entry = self.resolve_path(record_hash, path).await?;
+        entries.insert(record_hash.clone(), entry.clone());
+        Ok(entry)
+    }
+}
+
+/// whether two entries of a path have the same content, kind and mode.
+fn same_entry(a: &Option<Entry>, b: &Option<Entry>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.hash == b.hash && a.kind == b.kind && a.mode == b.mode,
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+impl ReadOnlyRepository {
+    pub async fn file_history(
+        &self,
+        path: &str,
+        start: Option<&ObjectId>,
+    ) -> Result<Vec<FileRevision>, WsvcFsError> {
+        self.repository().file_history(path, start).await
+    }
+}
@@ -0,0 +1,209 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{read, read_dir};
+
+use crate::{
+    auth::SyncCredential,
+    frame::PROTOCOL_VERSION,
+    fs::WsvcFsError,
+    model::{ObjectId, Repository},
+    refs::write_atomic,
+};
+
+/// file of the identity of the repository, in the repository.
+pub const IDENTITY_FILE: &str = "identity.json";
+
+/// `CloneProvenance` stand for where a cloned repository came from.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct CloneProvenance {
+    /// url the repository was cloned from.
+    pub url: String,
+    /// latest record right after cloning, `None` if the remote was empty.
+    pub tip: Option<ObjectId>,
+    pub date: DateTime<Utc>,
+}
+
+/// `RepoIdentity` stand for the stable identity of a repository and its clones.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RepoIdentity {
+    /// uuid generated when the repository is created, shared by all its clones.
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clone: Option<CloneProvenance>,
+}
+
+impl RepoIdentity {
+    /// a new identity with a random uuid.
+    pub fn generate() -> Self {
+        Self {
+            id: new_repo_id(),
+            clone: None,
+        }
+    }
+}
+
+/// a random version 4 uuid.
+pub fn new_repo_id() -> String {
+    let mut bytes = nanoid::rngs::default(16);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// `SyncIdentity` stand for the identity packet both sides of a sync send first.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct SyncIdentity {
+    /// `None` for repositories created before ids.
+    pub id: Option<String>,
+    /// whether the repository has no record.
+    pub empty: bool,
+    /// transport codecs offered by the server, see `TransportCodec`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub codecs: Vec<String>,
+    /// the codec the client chose among those of the server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+    /// id of the transport dictionary of the server, sent back by a client having it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dict: Option<String>,
+    /// version of the sync wire protocol, 0 for versions before it was sent, which speak
+    /// version 1. both sides use the lower version after the identity round.
+    #[serde(default)]
+    pub protocol: u32,
+    /// whether the server requires the client to authenticate, see `AuthResult`.
+    #[serde(default)]
+    pub auth: bool,
+    /// whether a server requiring auth lets clients without a credential read, e.g. of a
+    /// public repository.
+    #[serde(default)]
+    pub public: bool,
+    /// the credential of the client, only sent to servers requiring auth.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<SyncCredential>,
+}
+
+/// `IdentityCheck` stand for the outcome of comparing the identities of a sync.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IdentityCheck {
+    /// the same repository, or the other side adopts the local id.
+    Same,
+    /// the local repository adopts the id of the other side.
+    Adopt(String),
+    /// the other side is another repository.
+    Mismatch { local: String, remote: String },
+}
+
+impl SyncIdentity {
+    /// compare with the identity of the other side, both sides come to the same outcome.
+    ///
+    /// a repository without an id or without records adopts the id of the other side,
+    /// e.g. a new clone, or a new repository on a server pushed to the first time. when
+    /// both are empty, the smaller id wins.
+    pub fn check(&self, remote: &SyncIdentity) -> IdentityCheck {
+        let (local_id, remote_id) = match (&self.id, &remote.id) {
+            (_, None) => return IdentityCheck::Same,
+            (None, Some(remote_id)) => return IdentityCheck::Adopt(remote_id.clone()),
+            (Some(local_id), Some(remote_id)) => (local_id, remote_id),
+        };
+        if local_id == remote_id {
+            return IdentityCheck::Same;
+        }
+        let adopt = match (self.empty, remote.empty) {
+            (true, true) => remote_id < local_id,
+            (empty, _) => empty,
+        };
+        match adopt {
+            true => IdentityCheck::Adopt(remote_id.clone()),
+            false if remote.empty => IdentityCheck::Same,
+            false => IdentityCheck::Mismatch {
+                local: local_id.clone(),
+                remote: remote_id.clone(),
+            },
+        }
+    }
+
+    /// the protocol version of the sync with the side of identity `remote`.
+    pub fn protocol_with(&self, remote: &SyncIdentity) -> u32 {
+        self.protocol.max(1).min(remote.protocol.max(1))
+    }
+}
+
+impl Repository {
+    /// the identity of the repository, `None` if it was created before ids.
+    pub async fn identity(&self) -> Result<Option<RepoIdentity>, WsvcFsError> {
+        match read(self.path.join(IDENTITY_FILE)).await {
+            Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub async fn write_identity(&self, identity: &RepoIdentity) -> Result<(), WsvcFsError> {
+        self.check_writable()?;
+        write_atomic(
+            &self.path.join(IDENTITY_FILE),
+            &serde_json::to_vec_pretty(identity)?,
+        )
+        .await
+    }
+
+    /// the identity sent when syncing. with `generate`, a writable repository created
+    /// before ids gets one first, servers do so and their clients adopt it.
+    pub async fn sync_identity(&self, generate: bool) -> Result<SyncIdentity, WsvcFsError> {
+        let empty = read_dir(self.records_dir().await?)
+            .await?
+            .next_entry()
+            .await?
+            .is_none();
+        let id = match self.identity().await? {
+            Some(identity) => Some(identity.id),
+            None if generate && self.check_writable().is_ok() => {
+                let identity = RepoIdentity::generate();
+                self.write_identity(&identity).await?;
+                Some(identity.id)
+            }
+            None => None,
+        };
+        Ok(SyncIdentity {
+            id,
+            empty,
+            protocol: PROTOCOL_VERSION,
+            ..Default::default()
+        })
+    }
+
+    /// take the id of another repository, keeping the clone provenance.
+    pub async fn adopt_id(&self, id: &str) -> Result<(), WsvcFsError> {
+        let clone = self.identity().await?.and_then(|identity| identity.clone);
+        self.write_identity(&RepoIdentity {
+            id: id.to_owned(),
+            clone,
+        })
+        .await
+    }
+
+    /// record the repository was cloned from `url` with `tip` as the latest record.
+    pub async fn record_clone(&self, url: &str, tip: Option<ObjectId>) -> Result<(), WsvcFsError> {
+        let mut identity = self
+            .identity()
+            .await?
+            .unwrap_or_else(RepoIdentity::generate);
+        identity.clone = Some(CloneProvenance {
+            url: url.to_owned(),
+            tip,
+            date: Utc::now(),
+        });
+        self.write_identity(&identity).await
+    }
+}
@@ -0,0 +1,269 @@
+use std::path::Path;
+
+use tokio::fs::read_to_string;
+
+use crate::{
+    fs::WsvcFsError,
+    model::Repository,
+    modules::{ModulesManifest, MODULES_FILE},
+    readonly::ReadOnlyRepository,
+};
+
+/// file name of ignore files in the workspace, in the root dir or any sub dir.
+pub const IGNORE_FILE: &str = ".wsvcignore";
+
+/// `Pattern` stand for a glob pattern of a file in a dir of the workspace.
+#[derive(Clone, Debug)]
+pub(crate) struct Pattern {
+    /// dir of the file relative to the workspace root, empty for the root.
+    base: String,
+    /// pattern split by `/`, each segment as chars for matching.
+    segments: Vec<Vec<char>>,
+    /// patterns with a `/` match paths relative to `base`, others match names at any depth.
+    anchored: bool,
+}
+
+impl Pattern {
+    pub(crate) fn parse(base: &str, pattern: &str) -> Option<Self> {
+        let anchored = pattern.contains('/');
+        let pattern = pattern.trim_start_matches('/');
+        if pattern.is_empty() {
+            return None;
+        }
+        Some(Self {
+            base: base.trim_matches('/').to_owned(),
+            segments: pattern.split('/').map(|s| s.chars().collect()).collect(),
+            anchored,
+        })
+    }
+
+    /// whether a path relative to the workspace root matches, `/` separated.
+    pub(crate) fn matches(&self, path: &str) -> bool {
+        let path = match self.base.as_str() {
+            "" => path,
+            base => match path.strip_prefix(base).and_then(|p| p.strip_prefix('/')) {
+                Some(path) => path,
+                None => return false,
+            },
+        };
+        if self.anchored {
+            match_segments(&self.segments, &path.split('/').collect::<Vec<_>>())
+        } else {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            glob_match(&self.segments[0], &name.chars().collect::<Vec<_>>())
+        }
+    }
+}
+
+/// `Rule` stand for a pattern line of an ignore file.
+#[derive(Clone, Debug)]
+struct Rule {
+    pattern: Pattern,
+    /// `!pattern` includes paths an earlier rule ignores.
+    negated: bool,
+    /// `pattern/` only matches dirs.
+    dir_only: bool,
+}
+
+impl Rule {
+    fn parse(base: &str, line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        Some(Self {
+            pattern: Pattern::parse(base, line)?,
+            negated,
+            dir_only,
+        })
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        (is_dir || !self.dir_only) && self.pattern.matches(path)
+    }
+}
+
+/// match path components against pattern segments, `**` matches any number of components.
+fn match_segments(pattern: &[Vec<char>], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((segment, rest)) if segment.iter().collect::<String>() == "**" => {
+            (0..=path.len()).any(|i| match_segments(rest, &path[i..]))
+        }
+        Some((segment, rest)) => match path.split_first() {
+            Some((name, path)) => {
+                glob_match(segment, &name.chars().collect::<Vec<_>>()) && match_segments(rest, path)
+            }
+            None => false,
+        },
+    }
+}
+
+/// match a name against a glob with `*`, `?`, `[a-z]`, `[!a-z]` and `\` escapes.
+pub(crate) fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|i| glob_match(rest, &name[i..])),
+        Some(('?', rest)) => !name.is_empty() && glob_match(rest, &name[1..]),
+        Some(('[', rest)) => match match_class(rest, name.first()) {
+            Some((matched, rest)) => matched && glob_match(rest, &name[1..]),
+            // an unclosed `[` is a plain char.
+            None => name.first() == Some(&'[') && glob_match(rest, &name[1..]),
+        },
+        Some(('\\', rest)) if !rest.is_empty() => {
+            name.first() == Some(&rest[0]) && glob_match(&rest[1..], &name[1..])
+        }
+        Some((c, rest)) => name.first() == Some(c) && glob_match(rest, &name[1..]),
+    }
+}
+
+/// match a char against a class after its `[`, returns whether it matched and the pattern
+/// after the `]`, `None` if the class is not closed.
+fn match_class<'a>(pattern: &'a [char], c: Option<&char>) -> Option<(bool, &'a [char])> {
+    let (negated, pattern) = match pattern.first() {
+        Some('!') | Some('^') => (true, &pattern[1..]),
+        _ => (false, pattern),
+    };
+    let mut matched = false;
+    let mut i = 0;
+    loop {
+        match pattern.get(i) {
+            None => return None,
+            // a `]` first in the class is a plain char.
+            Some(']') if i > 0 => break,
+            Some(&start) => {
+                let end = match (pattern.get(i + 1), pattern.get(i + 2)) {
+                    (Some('-'), Some(&end)) if end != ']' => {
+                        i += 2;
+                        end
+                    }
+                    _ => start,
+                };
+                matched |= c.is_some_and(|c| (start..=end).contains(c));
+                i += 1;
+            }
+        }
+    }
+    Some((c.is_some() && matched != negated, &pattern[i + 1..]))
+}
+
+/// `Ignore` stand for ignore rules of a workspace, in the `.gitignore` syntax.
+///
+/// a line is a glob pattern with `*`, `?`, `[...]` and `**`. patterns without a `/` match
+/// names at any depth, others match paths relative to the dir of the ignore file. a
+/// trailing `/` only matches dirs, a leading `!` includes paths ignored by earlier lines,
+/// and lines starting with `#` are comments. later rules win, and rules of ignore files in
+/// sub dirs come after rules of their parents. paths in an ignored dir are always ignored.
+///
+/// ```text
+/// target/
+/// *.swp
+/// !keep.swp
+/// /docs/build
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Ignore {
+    rules: Vec<Rule>,
+}
+
+impl Ignore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// add the pattern lines of an ignore file in dir `base`, relative to the workspace root.
+    pub fn add_patterns(&mut self, base: &str, content: &str) {
+        self.rules
+            .extend(content.lines().filter_map(|line| Rule::parse(base, line)));
+    }
+
+    /// add patterns of the file at `path` for dir `base`, nothing if there is no such file.
+    pub async fn add_file(&mut self, base: &str, path: &Path) -> Result<(), WsvcFsError> {
+        if path.is_file() {
+            self.add_patterns(base, &read_to_string(path).await?);
+        }
+        Ok(())
+    }
+
+    /// add patterns of the ignore file in `dir`, the dir `base` relative to the workspace root.
+    ///
+    /// walks call this entering a dir, so its rules apply to paths in it.
+    pub async fn enter_dir(&mut self, base: &str, dir: &Path) -> Result<(), WsvcFsError> {
+        self.add_file(base, &dir.join(IGNORE_FILE)).await?;
+        // sub-repositories are workspaces of their own.
+        if base.is_empty() {
+            let manifest = ModulesManifest::load(dir).await.map_err(|err| {
+                WsvcFsError::Os(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{}: {}", MODULES_FILE, err),
+                ))
+            })?;
+            for module in manifest.modules {
+                self.add_dir(&module.path);
+            }
+        }
+        Ok(())
+    }
+
+    /// ignore the dir at `path` relative to the workspace root, glob chars match themselves.
+    pub fn add_dir(&mut self, path: &str) {
+        let mut pattern = String::from("/");
+        for c in path.trim_matches('/').chars() {
+            if matches!(c, '*' | '?' | '[' | '\\') {
+                pattern.push('\\');
+            }
+            pattern.push(c);
+        }
+        pattern.push('/');
+        self.add_patterns("", &pattern);
+    }
+
+    /// whether a path relative to the workspace root is ignored, `/` separated.
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        let path = path.trim_matches('/');
+        path.match_indices('/')
+            .any(|(i, _)| self.matches(&path[..i], true))
+            || self.matches(path, is_dir)
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(path, is_dir))
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+impl Repository {
+    /// rules of the global excludes file, see `RepositoryBuilder::excludes_file`.
+    pub async fn excludes(&self) -> Result<Ignore, WsvcFsError> {
+        let mut result = Ignore::new();
+        if let Some(path) = &self.options.excludes_file {
+            result.add_file("", path).await?;
+        }
+        Ok(result)
+    }
+
+    /// rules of the global excludes file, overlays and the root ignore file of `workspace`.
+    pub async fn load_ignore(&self, workspace: &Path) -> Result<Ignore, WsvcFsError> {
+        let mut result = self.excludes().await?;
+        self.ignore_overlays(&mut result).await?;
+        result.enter_dir("", workspace).await?;
+        Ok(result)
+    }
+}
+
+impl ReadOnlyRepository {
+    pub async fn load_ignore(&self, workspace: &Path) -> Result<Ignore, WsvcFsError> {
+        self.repository().load_ignore(workspace).await
+    }
+}
@@ -0,0 +1,126 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tokio::fs::{read, remove_file};
+
+use crate::{
+    fs::WsvcFsError, model::Repository, readonly::ReadOnlyRepository, refs::write_atomic, WsvcError,
+};
+
+/// file of staged paths in the repository.
+pub const INDEX_FILE: &str = "index.json";
+
+/// `Index` stand for the paths of the workspace staged for the next record, see
+/// `Repository::commit_record`.
+///
+/// paths are staged as paths, not contents, the content of a staged path is read when
+/// committing.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct Index {
+    /// staged paths relative to the workspace root, `/` separated and sorted. a path
+    /// never has another staged path under it.
+    pub paths: Vec<String>,
+}
+
+impl Index {
+    /// whether nothing is staged.
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+}
+
+/// normalize a workspace path to `/` separated components.
+fn normalize_path(path: &str) -> Result<String, WsvcError> {
+    let components = path
+        .split(['/', '\\'])
+        .filter(|c| !c.is_empty() && *c != ".")
+        .collect::<Vec<_>>();
+    if components.is_empty() || components.iter().any(|c| *c == ".." || *c == ".wsvc") {
+        return Err(WsvcError::BadUsage(format!("invalid path: {}", path)));
+    }
+    Ok(components.join("/"))
+}
+
+/// whether `path` is `dir` or under it.
+fn is_under(path: &str, dir: &str) -> bool {
+    path == dir || path.starts_with(&format!("{}/", dir))
+}
+
+impl Repository {
+    /// read the index, an empty index if nothing is staged.
+    pub async fn read_index(&self) -> Result<Index, WsvcFsError> {
+        let path = self.path.join(INDEX_FILE);
+        if !path.exists() {
+            return Ok(Index::default());
+        }
+        Ok(serde_json::from_slice(&read(path).await?)?)
+    }
+
+    async fn write_index(&self, index: &Index) -> Result<(), WsvcFsError> {
+        if index.is_empty() {
+            return self.clear_index().await;
+        }
+        write_atomic(&self.path.join(INDEX_FILE), &serde_json::to_vec(index)?).await
+    }
+
+    /// stage `paths` of `workspace`, a path must be in the workspace or the HEAD record,
+    /// e.g. a removed file. staging a dir stages everything under it.
+    pub async fn stage_paths(
+        &self,
+        workspace: &Path,
+        paths: &[String],
+    ) -> Result<Index, WsvcError> {
+        self.check_writable()?;
+        let head = self.get_head_record().await?;
+        let mut index = self.read_index().await?;
+        for path in paths {
+            let path = normalize_path(path)?;
+            let recorded = match &head {
+                Some(head) => self.resolve_path(&head.hash, &path).await?.is_some(),
+                None => false,
+            };
+            if !recorded && !workspace.join(&path).exists() {
+                return Err(WsvcError::BadUsage(format!(
+                    "{} is neither in the workspace nor in HEAD",
+                    path
+                )));
+            }
+            if index.paths.iter().any(|p| is_under(&path, p)) {
+                continue;
+            }
+            index.paths.retain(|p| !is_under(p, &path));
+            index.paths.push(path);
+        }
+        index.paths.sort();
+        self.write_index(&index).await?;
+        Ok(index)
+    }
+
+    /// unstage `paths` and the staged paths under them.
+    pub async fn unstage_paths(&self, paths: &[String]) -> Result<Index, WsvcError> {
+        self.check_writable()?;
+        let mut index = self.read_index().await?;
+        for path in paths {
+            let path = normalize_path(path)?;
+            index.paths.retain(|p| !is_under(p, &path));
+        }
+        self.write_index(&index).await?;
+        Ok(index)
+    }
+
+    /// unstage everything, e.g. after committing.
+    pub async fn clear_index(&self) -> Result<(), WsvcFsError> {
+        self.check_writable()?;
+        let path = self.path.join(INDEX_FILE);
+        if path.exists() {
+            remove_file(path).await?;
+        }
+        Ok(())
+    }
+}
+
+impl ReadOnlyRepository {
+    pub async fn read_index(&self) -> Result<Index, WsvcFsError> {
+        self.repository().read_index().await
+    }
+}
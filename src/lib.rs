@@ -2,10 +2,62 @@ use std::string::FromUtf8Error;
 use thiserror::Error;
 use toml::{de, ser};
 
+pub mod archive;
+pub mod attest;
+pub mod attributes;
+pub mod auth;
+pub mod binary;
+pub mod blame;
+pub mod blobless;
+pub mod branch;
+pub mod changelog;
+pub mod chunk;
+pub mod codec;
+pub mod compress;
+pub mod dial;
+pub mod diff;
+pub mod encoding;
+pub mod events;
+pub mod filter;
+pub mod frame;
 pub mod fs;
+pub mod fsck;
+pub mod gc;
+pub mod head;
+pub mod history;
+pub mod identity;
+pub mod ignore;
+pub mod index;
+pub mod mailmap;
+pub mod meta;
 pub mod model;
+pub mod modules;
+pub mod note;
+pub mod overlay;
+pub mod pack;
+pub mod patch;
+pub mod query;
+#[cfg(feature = "quic")]
+pub mod quic;
+pub mod readonly;
+pub mod records;
+pub mod refs;
+pub mod refspec;
+pub mod release;
+pub mod resume;
+pub mod revert;
+pub mod rewrite;
 #[cfg(feature = "server")]
 pub mod server;
+pub mod sign;
+pub mod size;
+pub mod stash;
+pub mod stat;
+pub mod state;
+pub mod status;
+pub mod tag;
+pub mod trailer;
+pub mod walk;
 
 /// Error type for wsvc
 #[derive(Error, Debug)]
@@ -30,9 +82,20 @@ pub enum WsvcError {
     SerializationError(#[from] serde_json::Error),
     #[cfg(feature = "cli")]
     #[error("network error: {0}")]
-    NetworkError(#[from] tokio_tungstenite::tungstenite::Error),
+    NetworkError(Box<tokio_tungstenite::tungstenite::Error>),
     #[error("data error: {0}")]
     DataError(String),
     #[error("repo without record")]
     EmptyRepoError,
+    #[error("signature error: {0}")]
+    SignatureError(String),
+    #[error("offline: {0}")]
+    Offline(String),
+}
+
+#[cfg(feature = "cli")]
+impl From<tokio_tungstenite::tungstenite::Error> for WsvcError {
+    fn from(err: tokio_tungstenite::tungstenite::Error) -> Self {
+        WsvcError::NetworkError(Box::new(err))
+    }
 }
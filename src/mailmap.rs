@@ -0,0 +1,83 @@
+use std::{collections::HashMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{fs::WsvcFsError, WsvcError};
+
+/// file name of the mailmap in the workspace root.
+pub const MAILMAP_FILE: &str = ".wsvcmailmap";
+
+/// `Mailmap` maps old author strings to canonical identities.
+///
+/// the mailmap file is a toml table, keys are author strings found in records and
+/// values are the canonical identities:
+///
+/// ```toml
+/// [authors]
+/// "reverier" = "Reverier-Xu <reverier.xu@woooo.tech>"
+/// ```
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Mailmap {
+    #[serde(default)]
+    pub authors: HashMap<String, String>,
+}
+
+impl Mailmap {
+    /// load mailmap from `dir`, an empty mailmap is returned if there is no mailmap file.
+    pub async fn load(dir: impl AsRef<Path>) -> Result<Self, WsvcError> {
+        let path = dir.as_ref().join(MAILMAP_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    /// resolve an author string to its canonical identity.
+    pub fn resolve<'a>(&'a self, author: &'a str) -> &'a str {
+        self.authors
+            .get(author)
+            .map(|canonical| canonical.as_str())
+            .unwrap_or(author)
+    }
+}
+
+/// split an author string into its name and email, `Name <email>` or a bare name.
+pub fn split_identity(author: &str) -> (&str, Option<&str>) {
+    match author
+        .trim()
+        .strip_suffix('>')
+        .and_then(|rest| rest.rsplit_once('<'))
+    {
+        Some((name, email)) => (name.trim(), Some(email)),
+        None => (author.trim(), None),
+    }
+}
+
+/// the author string of `name` and `email`, `Name <email>` or a bare name, checked so it
+/// splits back into them.
+pub fn identity(name: &str, email: Option<&str>) -> Result<String, WsvcError> {
+    let name = name.trim();
+    if name.is_empty() || name.contains(['<', '>']) || name.contains(char::is_control) {
+        return Err(WsvcError::BadUsage(format!(
+            "invalid author name {:?}, it must not be empty or hold `<`, `>` or control characters",
+            name
+        )));
+    }
+    let Some(email) = email else {
+        return Ok(name.to_owned());
+    };
+    let valid = email
+        .split_once('@')
+        .is_some_and(|(user, host)| !user.is_empty() && !host.is_empty())
+        && !email.contains(|c: char| c.is_whitespace() || c.is_control() || "<>".contains(c));
+    if !valid {
+        return Err(WsvcError::BadUsage(format!(
+            "invalid author email {:?}, expected an address like alice@example.com",
+            email
+        )));
+    }
+    Ok(format!("{} <{}>", name, email))
+}
@@ -4,13 +4,21 @@ mod cli;
 #[cfg(feature = "cli")]
 use colored::Colorize;
 
+/// exit code of commands failing for lack of network access, `EX_TEMPFAIL` of sysexits,
+/// so scripts and CI runners can tell them from other failures.
+#[cfg(feature = "cli")]
+const OFFLINE_EXIT_CODE: i32 = 75;
+
 #[tokio::main]
 async fn main() {
     match cli::run().await {
         Ok(_) => {}
         Err(e) => {
             eprintln!("{}: {}", "error".bright_red().bold(), e);
-            std::process::exit(-1);
+            std::process::exit(match e {
+                wsvc::WsvcError::Offline(_) => OFFLINE_EXIT_CODE,
+                _ => -1,
+            });
         }
     }
 }
@@ -0,0 +1,195 @@
+use std::{path::Path, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs::read_dir;
+
+use crate::{
+    attributes::Attributes,
+    fs::{join_path, symlink_target, WsvcFsError},
+    ignore::Ignore,
+    model::{EntryKind, Repository, Tree},
+    readonly::ReadOnlyRepository,
+    WsvcError,
+};
+
+/// `RepoMeta` stand for the repository state embedded into builds.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct RepoMeta {
+    /// hash of the HEAD record, `None` if the repository has no record.
+    pub record: Option<String>,
+    /// description of the HEAD record, see `Repository::describe_record`.
+    pub describe: Option<String>,
+    /// whether the workspace differs from the HEAD record.
+    pub dirty: bool,
+    /// date of the HEAD record.
+    pub date: Option<DateTime<Utc>>,
+}
+
+/// output format of `RepoMeta`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetaFormat {
+    Json,
+    Env,
+}
+
+impl FromStr for MetaFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(MetaFormat::Json),
+            "env" => Ok(MetaFormat::Env),
+            _ => Err(format!("unknown meta format: {}, use json or env", s)),
+        }
+    }
+}
+
+/// check whether `dir` differs from `tree`, blobs are compared by checksum and ignored
+/// paths are skipped. `path` is the path of `dir` relative to the workspace root.
+#[async_recursion::async_recursion(?Send)]
+async fn dir_differs(
+    repo: &ReadOnlyRepository,
+    tree: &Tree,
+    dir: &Path,
+    ignore: &mut Ignore,
+    attributes: &mut Attributes,
+    path: &str,
+) -> Result<bool, WsvcFsError> {
+    let mut entries = read_dir(dir).await?;
+    let mut count = 0;
+    while let Some(entry) = entries.next_entry().await? {
+        let entry_type = entry.file_type().await?;
+        let entry_path = join_path(path, &entry.file_name().to_string_lossy());
+        if ignore.is_ignored(&entry_path, entry_type.is_dir()) {
+            continue;
+        }
+        if entry_type.is_dir() {
+            if entry.file_name() == ".wsvc" {
+                continue;
+            }
+            count += 1;
+            let mut matched = false;
+            for dir in tree.dirs() {
+                if entry.file_name().to_str() == Some(dir.name.as_str()) {
+                    let sub_tree = repo.read_tree(&dir.hash).await?;
+                    ignore.enter_dir(&entry_path, &entry.path()).await?;
+                    attributes.enter_dir(&entry_path, &entry.path()).await?;
+                    if dir_differs(
+                        repo,
+                        &sub_tree,
+                        &entry.path(),
+                        ignore,
+                        attributes,
+                        &entry_path,
+                    )
+                    .await?
+                    {
+                        return Ok(true);
+                    }
+                    matched = true;
+                    break;
+                }
+            }
+            if !matched {
+                return Ok(true);
+            }
+        } else if entry_type.is_file() {
+            count += 1;
+            match tree
+                .files()
+                .find(|f| entry.file_name().to_str() == Some(f.name.as_str()))
+            {
+                Some(file)
+                    if file.kind == EntryKind::File
+                        && attributes.hash_file(&entry.path(), &entry_path).await? == file.hash => {
+                }
+                _ => return Ok(true),
+            }
+        } else if entry_type.is_symlink() {
+            count += 1;
+            match tree
+                .files()
+                .find(|f| entry.file_name().to_str() == Some(f.name.as_str()))
+            {
+                Some(file)
+                    if file.kind == EntryKind::Symlink
+                        && blake3::hash(&symlink_target(&entry.path()).await?) == file.hash.0 => {}
+                _ => return Ok(true),
+            }
+        }
+    }
+    Ok(count != tree.entries.len())
+}
+
+impl RepoMeta {
+    /// collect the state of `repo` and its `workspace`.
+    pub async fn collect(repo: &ReadOnlyRepository, workspace: &Path) -> Result<Self, WsvcError> {
+        let record = match repo.get_head_record().await? {
+            Some(record) => record,
+            None => {
+                return Ok(RepoMeta {
+                    dirty: true,
+                    ..Default::default()
+                })
+            }
+        };
+        let root = repo.read_tree(&record.root).await?;
+        Ok(RepoMeta {
+            record: Some(record.hash.0.to_hex().to_string()),
+            describe: Some(repo.describe_record(&record.hash).await?),
+            dirty: dir_differs(
+                repo,
+                &root,
+                workspace,
+                &mut repo.load_ignore(workspace).await?,
+                &mut repo.load_attributes(workspace).await?,
+                "",
+            )
+            .await?,
+            date: Some(record.date),
+        })
+    }
+
+    /// render the meta in `format`.
+    ///
+    /// the env format is a list of `WSVC_*=value` lines, missing values are left empty.
+    pub fn render(&self, format: MetaFormat) -> Result<String, WsvcError> {
+        match format {
+            MetaFormat::Json => Ok(serde_json::to_string_pretty(self)?),
+            MetaFormat::Env => Ok(format!(
+                "WSVC_RECORD={}\nWSVC_DESCRIBE={}\nWSVC_DIRTY={}\nWSVC_DATE={}\n",
+                self.record.clone().unwrap_or_default(),
+                self.describe.clone().unwrap_or_default(),
+                self.dirty,
+                self.date.map(|d| d.to_rfc3339()).unwrap_or_default()
+            )),
+        }
+    }
+}
+
+/// collect the state of the repository of `workspace` and write it to `output`.
+///
+/// this is a blocking function, so it could be called from `build.rs` directly:
+///
+/// ```no_run
+/// wsvc::meta::emit_blocking(".", "version.json", wsvc::meta::MetaFormat::Json).unwrap();
+/// ```
+pub fn emit_blocking(
+    workspace: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    format: MetaFormat,
+) -> Result<RepoMeta, WsvcError> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(WsvcFsError::Os)?
+        .block_on(async {
+            let repo = Repository::open_read_only(workspace.as_ref()).await?;
+            let meta = RepoMeta::collect(&repo, workspace.as_ref()).await?;
+            tokio::fs::write(output.as_ref(), meta.render(format)?)
+                .await
+                .map_err(WsvcFsError::Os)?;
+            Ok(meta)
+        })
+}
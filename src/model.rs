@@ -1,11 +1,20 @@
-use std::path::PathBuf;
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc, time::Duration};
 
 use chrono::serde::ts_seconds::{deserialize as from_ts, serialize as to_ts};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+use crate::{
+    attributes::AutoCrlf,
+    blobless::BlobSource,
+    compress::{Codec, Compression},
+    encoding::ObjectFormat,
+    filter::FilterDriver,
+    size::SizePolicy,
+};
+
 /// `ObjectId` stand for a hash.
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct ObjectId(pub blake3::Hash);
 
 impl Default for ObjectId {
@@ -40,9 +49,9 @@ impl From<blake3::Hash> for ObjectId {
     }
 }
 
-impl Into<String> for ObjectId {
-    fn into(self) -> String {
-        self.0.to_string()
+impl From<ObjectId> for String {
+    fn from(id: ObjectId) -> Self {
+        id.0.to_string()
     }
 }
 
@@ -71,13 +80,122 @@ pub struct Blob {
     pub hash: ObjectId,
 }
 
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+/// `EntryKind` stand for the kind of a tree entry.
+pub enum EntryKind {
+    Dir,
+    File,
+    Symlink,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+/// `Entry` stand for an item of a dir.
+pub struct Entry {
+    pub kind: EntryKind,
+    pub name: String,
+    /// hash of the tree of a dir, or of the blob of a file or a symlink target.
+    pub hash: ObjectId,
+    /// unix permission bits, 0 if unknown.
+    #[serde(default)]
+    pub mode: u32,
+    /// size of a file in bytes, 0 for dirs.
+    #[serde(default)]
+    pub size: u64,
+}
+
+impl Entry {
+    /// the blob of a file or symlink entry.
+    pub fn blob(&self) -> Blob {
+        Blob {
+            name: self.name.clone(),
+            hash: self.hash.clone(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(from = "TreeRepr")]
 /// `Tree` stand for a dir.
 pub struct Tree {
     pub name: String,
     pub hash: ObjectId,
-    pub trees: Vec<ObjectId>,
-    pub blobs: Vec<Blob>,
+    /// entries sorted by name, see `Tree::sort`.
+    pub entries: Vec<Entry>,
+}
+
+/// the stored shape of `Tree`, trees stored before entries have `trees` and `blobs`.
+#[derive(Deserialize)]
+struct TreeRepr {
+    name: String,
+    hash: ObjectId,
+    #[serde(default)]
+    entries: Vec<Entry>,
+    #[serde(default)]
+    trees: Vec<ObjectId>,
+    #[serde(default)]
+    blobs: Vec<Blob>,
+}
+
+impl From<TreeRepr> for Tree {
+    fn from(repr: TreeRepr) -> Self {
+        let mut entries = repr.entries;
+        // names of dirs are only known by their trees, `Repository::read_tree` fills them.
+        entries.extend(repr.trees.into_iter().map(|hash| Entry {
+            kind: EntryKind::Dir,
+            name: String::new(),
+            hash,
+            mode: 0,
+            size: 0,
+        }));
+        entries.extend(repr.blobs.into_iter().map(|blob| Entry {
+            kind: EntryKind::File,
+            name: blob.name,
+            hash: blob.hash,
+            mode: 0,
+            size: 0,
+        }));
+        Tree {
+            name: repr.name,
+            hash: repr.hash,
+            entries,
+        }
+    }
+}
+
+impl Tree {
+    /// calculate the hash of the tree from its content as is, `hash` is excluded.
+    pub fn calc_hash(&self) -> Result<ObjectId, serde_json::Error> {
+        let tree = Tree {
+            hash: ObjectId::default(),
+            ..self.clone()
+        };
+        Ok(ObjectId(blake3::hash(
+            serde_json::to_vec(&tree)?.as_slice(),
+        )))
+    }
+
+    /// sort entries by name then kind, so the hash of a tree does not depend on the
+    /// order entries were added.
+    pub fn sort(&mut self) {
+        self.entries
+            .sort_by(|a, b| a.name.cmp(&b.name).then(a.kind.cmp(&b.kind)));
+    }
+
+    /// entries of sub dirs.
+    pub fn dirs(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter().filter(|e| e.kind == EntryKind::Dir)
+    }
+
+    /// entries stored as blobs, files and symlinks.
+    pub fn files(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter().filter(|e| e.kind != EntryKind::Dir)
+    }
+
+    /// blobs of files and symlinks directly in the tree.
+    pub fn blobs(&self) -> Vec<Blob> {
+        self.files().map(Entry::blob).collect()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -89,6 +207,23 @@ pub struct Record {
     #[serde(deserialize_with = "from_ts", serialize_with = "to_ts")]
     pub date: DateTime<Utc>,
     pub root: ObjectId,
+    /// records this record is based on, empty for the first record. `None` for records
+    /// made before parents were recorded, their parent is the previous record by date.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parents: Option<Vec<ObjectId>>,
+}
+
+impl Record {
+    /// calculate the hash of the record from its content, `hash` is excluded.
+    pub fn calc_hash(&self) -> Result<ObjectId, serde_json::Error> {
+        let record = Record {
+            hash: ObjectId::default(),
+            ..self.clone()
+        };
+        Ok(ObjectId(blake3::hash(
+            serde_json::to_vec(&record)?.as_slice(),
+        )))
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -96,4 +231,68 @@ pub struct Record {
 pub struct Repository {
     pub path: PathBuf,
     pub lock: String,
+    #[serde(default)]
+    pub options: RepoOptions,
+}
+
+/// default compression level of blobs.
+pub const DEFAULT_COMPRESSION_LEVEL: u8 = 8;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// `RepoOptions` stand for options a repository is opened with, see `RepositoryBuilder`.
+pub struct RepoOptions {
+    /// dir storing blobs, `objects` in the repository dir if not set.
+    pub objects_dir: Option<PathBuf>,
+    /// compression level of newly stored blobs, from 0 to the highest level of `codec`.
+    pub compression_level: u8,
+    /// codec of newly stored blobs, deflate if not set.
+    #[serde(default)]
+    pub codec: Codec,
+    /// how long to wait for other writers to release the lock, fail at once if not set.
+    pub lock_timeout: Option<Duration>,
+    /// refuse to lock the repository and to store anything in it.
+    pub read_only: bool,
+    /// ignore file applied to every workspace before its own `.wsvcignore` files.
+    #[serde(default)]
+    pub excludes_file: Option<PathBuf>,
+    /// line ending conversion of text files without an `eol` attribute, see `Attributes`.
+    #[serde(default)]
+    pub autocrlf: AutoCrlf,
+    /// content filters by name, for paths with a `filter=<name>` attribute.
+    #[serde(default)]
+    pub filters: BTreeMap<String, FilterDriver>,
+    /// how large files of new records may be, see `Repository::commit_record`.
+    #[serde(default)]
+    pub size_policy: SizePolicy,
+    /// format of newly stored trees and records, json if not set.
+    #[serde(default)]
+    pub object_format: ObjectFormat,
+    /// where a blobless repository fetches blobs it did not sync, see `BlobSource`.
+    #[serde(skip)]
+    pub blob_source: Option<Arc<dyn BlobSource>>,
+}
+
+impl RepoOptions {
+    /// the codec and level newly stored blobs are compressed with.
+    pub fn compression(&self) -> Compression {
+        Compression::new(self.codec, self.compression_level)
+    }
+}
+
+impl Default for RepoOptions {
+    fn default() -> Self {
+        Self {
+            objects_dir: None,
+            compression_level: DEFAULT_COMPRESSION_LEVEL,
+            codec: Codec::Deflate,
+            lock_timeout: None,
+            read_only: false,
+            excludes_file: None,
+            autocrlf: AutoCrlf::False,
+            filters: BTreeMap::new(),
+            size_policy: SizePolicy::default(),
+            object_format: ObjectFormat::default(),
+            blob_source: None,
+        }
+    }
 }
@@ -0,0 +1,171 @@
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    diff::Change,
+    fs::{RepositoryBuilder, WsvcFsError},
+    model::{ObjectId, Repository},
+    WsvcError,
+};
+
+/// file name of the sub-repositories manifest in the workspace root.
+pub const MODULES_FILE: &str = ".wsvcmodules";
+
+/// `Module` stand for another wsvc repository pinned at a record in a dir of the workspace.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Module {
+    /// dir of the module relative to the workspace root, `/` separated.
+    pub path: String,
+    /// remote origin the module is cloned from.
+    pub url: String,
+    /// the pinned record of the module.
+    pub record: ObjectId,
+}
+
+impl Module {
+    /// the workspace dir of the module in `workspace`.
+    pub fn dir(&self, workspace: impl AsRef<Path>) -> PathBuf {
+        workspace.as_ref().join(&self.path)
+    }
+
+    /// open the repository of the module in `workspace`, `None` if it is not cloned yet.
+    pub async fn open(
+        &self,
+        workspace: impl AsRef<Path>,
+        read_only: bool,
+    ) -> Result<Option<Repository>, WsvcFsError> {
+        let dir = self.dir(workspace);
+        if !dir.join(".wsvc").exists() {
+            return Ok(None);
+        }
+        Ok(Some(
+            RepositoryBuilder::new(dir)
+                .bare(false)
+                .read_only(read_only)
+                .open()
+                .await?,
+        ))
+    }
+
+    /// the state of the module in `workspace`, `excludes_file` is the global ignore file of
+    /// the module repository.
+    pub async fn status(
+        &self,
+        workspace: impl AsRef<Path>,
+        excludes_file: Option<PathBuf>,
+    ) -> Result<ModuleStatus, WsvcError> {
+        let dir = self.dir(&workspace);
+        let Some(mut repo) = self.open(&workspace, true).await? else {
+            return Ok(ModuleStatus {
+                module: self.clone(),
+                cloned: false,
+                fetched: false,
+                head: None,
+                changes: vec![],
+            });
+        };
+        repo.options.excludes_file = excludes_file;
+        let status = repo.status(&dir).await?;
+        Ok(ModuleStatus {
+            module: self.clone(),
+            cloned: true,
+            fetched: repo.read_record(&self.record).await.is_ok(),
+            head: status.head,
+            changes: status.changes,
+        })
+    }
+}
+
+/// whether `path` is a relative dir in the workspace without glob chars.
+pub(crate) fn is_workspace_dir(path: &str) -> bool {
+    !path.is_empty()
+        && !path.contains(['*', '?', '[', '\\'])
+        && Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(name) if name != ".wsvc"))
+}
+
+/// check whether `path` can be the dir of a module.
+pub fn check_module_path(path: &str) -> Result<(), WsvcError> {
+    if is_workspace_dir(path) {
+        Ok(())
+    } else {
+        Err(WsvcError::BadUsage(format!(
+            "invalid module path: {}",
+            path
+        )))
+    }
+}
+
+/// `ModulesManifest` stand for the sub-repositories of a workspace.
+///
+/// the manifest is a toml file in the workspace root, each module pins a repository at a
+/// full record hash. module dirs are ignored by the workspace, they are workspaces of
+/// their own:
+///
+/// ```toml
+/// [[module]]
+/// path = "vendor/lib"
+/// url = "wss://example.com/lib"
+/// record = "<record hash>"
+/// ```
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct ModulesManifest {
+    #[serde(default, rename = "module")]
+    pub modules: Vec<Module>,
+}
+
+impl ModulesManifest {
+    /// parse a manifest, module paths are checked and must not repeat.
+    pub fn parse(content: &str) -> Result<Self, WsvcError> {
+        let manifest: Self = toml::from_str(content)?;
+        for (i, module) in manifest.modules.iter().enumerate() {
+            check_module_path(&module.path)?;
+            if manifest.modules[..i]
+                .iter()
+                .any(|m| Path::new(&m.path) == Path::new(&module.path))
+            {
+                return Err(WsvcError::BadUsage(format!(
+                    "module path {} is pinned twice",
+                    module.path
+                )));
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// load the manifest from `workspace`, an empty manifest is returned if there is no
+    /// manifest file.
+    pub async fn load(workspace: impl AsRef<Path>) -> Result<Self, WsvcError> {
+        let path = workspace.as_ref().join(MODULES_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        Self::parse(&content)
+    }
+}
+
+/// `ModuleStatus` stand for the state of a module in a workspace.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ModuleStatus {
+    pub module: Module,
+    /// whether the module repository is cloned.
+    pub cloned: bool,
+    /// whether the pinned record is in the module repository.
+    pub fetched: bool,
+    /// the HEAD record of the module repository.
+    pub head: Option<ObjectId>,
+    /// changes of the module workspace not recorded.
+    pub changes: Vec<Change>,
+}
+
+impl ModuleStatus {
+    /// whether the module workspace is the pinned record without changes.
+    pub fn is_synced(&self) -> bool {
+        self.head.as_ref() == Some(&self.module.record) && self.changes.is_empty()
+    }
+}
@@ -0,0 +1,120 @@
+use std::path::PathBuf;
+
+use blake3::Hash;
+use chrono::serde::ts_seconds::{deserialize as from_ts, serialize as to_ts};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{create_dir_all, read, read_dir, write};
+
+use crate::{
+    fs::WsvcFsError,
+    model::{ObjectId, Repository},
+    WsvcError,
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+/// `Note` stand for a message attached to a record after it was committed.
+///
+/// notes are stored beside records, so adding a note never changes the record hash.
+pub struct Note {
+    pub hash: ObjectId,
+    pub record: ObjectId,
+    pub author: String,
+    #[serde(deserialize_with = "from_ts", serialize_with = "to_ts")]
+    pub date: DateTime<Utc>,
+    pub message: String,
+}
+
+impl Note {
+    /// calculate the hash of the note, `hash` is excluded.
+    pub fn calc_hash(&self) -> Result<ObjectId, WsvcFsError> {
+        let note = Note {
+            hash: ObjectId(Hash::from([0; 32])),
+            ..self.clone()
+        };
+        Ok(ObjectId(blake3::hash(
+            serde_json::to_vec(&note)?.as_slice(),
+        )))
+    }
+}
+
+impl Repository {
+    /// get the notes folder of the repository.
+    pub async fn notes_dir(&self) -> Result<PathBuf, WsvcFsError> {
+        let result = self.path.join("notes");
+        if !result.exists() && !self.options.read_only {
+            create_dir_all(&result).await?;
+        }
+        Ok(result)
+    }
+
+    /// store a note to notes dir.
+    pub async fn store_note(&self, note: &Note) -> Result<(), WsvcFsError> {
+        self.check_writable()?;
+        write(
+            self.notes_dir().await?.join(note.hash.0.to_hex().as_str()),
+            serde_json::to_vec(note)?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn note_exists(&self, hash: &ObjectId) -> Result<bool, WsvcFsError> {
+        Ok(self
+            .notes_dir()
+            .await?
+            .join(hash.0.to_hex().as_str())
+            .exists())
+    }
+
+    /// get all notes
+    pub async fn get_notes(&self) -> Result<Vec<Note>, WsvcFsError> {
+        let mut result = Vec::new();
+        let notes_dir = self.notes_dir().await?;
+        if !notes_dir.exists() {
+            return Ok(result);
+        }
+        let mut entries = read_dir(notes_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_file() {
+                result.push(serde_json::from_slice(&read(entry.path()).await?)?);
+            }
+        }
+        Ok(result)
+    }
+
+    /// get all notes of a record, sorted by date.
+    pub async fn get_notes_of_record(
+        &self,
+        record_hash: &ObjectId,
+    ) -> Result<Vec<Note>, WsvcFsError> {
+        let mut result = self
+            .get_notes()
+            .await?
+            .into_iter()
+            .filter(|n| n.record == *record_hash)
+            .collect::<Vec<_>>();
+        result.sort_by_key(|n| n.date);
+        Ok(result)
+    }
+
+    /// append a note to a record.
+    pub async fn add_note(
+        &self,
+        record_hash: &ObjectId,
+        author: impl AsRef<str>,
+        message: impl AsRef<str>,
+    ) -> Result<Note, WsvcError> {
+        let record = self.read_record(record_hash).await?;
+        let mut note = Note {
+            hash: ObjectId(Hash::from([0; 32])),
+            record: record.hash,
+            author: author.as_ref().to_string(),
+            date: Utc::now(),
+            message: message.as_ref().to_string(),
+        };
+        note.hash = note.calc_hash()?;
+        self.store_note(&note).await?;
+        Ok(note)
+    }
+}
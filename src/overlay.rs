@@ -0,0 +1,222 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tokio::fs::{copy, create_dir_all, read, remove_dir_all};
+
+use crate::{
+    fs::WsvcFsError,
+    ignore::Ignore,
+    model::{ObjectId, Repository},
+    modules::is_workspace_dir,
+    readonly::ReadOnlyRepository,
+    refs::write_atomic,
+    WsvcError,
+};
+
+/// file of overlays in the repository.
+pub const OVERLAYS_FILE: &str = "overlays.json";
+
+/// `Overlay` stand for a record materialized into a dir of the workspace, the dir is kept
+/// out of records of the workspace.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Overlay {
+    /// dir of the overlay relative to the workspace root, `/` separated.
+    pub path: String,
+    /// root dir of the repository the record comes from, `None` for this repository.
+    pub source: Option<String>,
+    /// the materialized record.
+    pub record: ObjectId,
+    /// root tree of the record, its trees and blobs are copied into this repository.
+    pub root: ObjectId,
+}
+
+/// normalize an overlay path to `/` separated components.
+fn normalize_path(path: &str) -> Result<String, WsvcError> {
+    let path = path
+        .split(['/', '\\'])
+        .filter(|c| !c.is_empty() && *c != ".")
+        .collect::<Vec<_>>()
+        .join("/");
+    if !is_workspace_dir(&path) {
+        return Err(WsvcError::BadUsage(format!(
+            "invalid overlay path: {}",
+            path
+        )));
+    }
+    Ok(path)
+}
+
+/// whether one of the dirs `a` and `b` contains the other.
+fn overlaps(a: &str, b: &str) -> bool {
+    let (short, long) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    long == short || long.starts_with(&format!("{}/", short))
+}
+
+impl Repository {
+    /// get overlays of the workspace, sorted by path.
+    pub async fn get_overlays(&self) -> Result<Vec<Overlay>, WsvcFsError> {
+        let path = self.path.join(OVERLAYS_FILE);
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+        Ok(serde_json::from_slice(&read(path).await?)?)
+    }
+
+    /// read the overlay at `path`, `None` if there is no such overlay.
+    pub async fn read_overlay(&self, path: &str) -> Result<Option<Overlay>, WsvcError> {
+        let path = normalize_path(path)?;
+        Ok(self
+            .get_overlays()
+            .await?
+            .into_iter()
+            .find(|o| o.path == path))
+    }
+
+    async fn write_overlays(&self, overlays: &mut [Overlay]) -> Result<(), WsvcFsError> {
+        overlays.sort_by(|a, b| a.path.cmp(&b.path));
+        write_atomic(
+            &self.path.join(OVERLAYS_FILE),
+            &serde_json::to_vec_pretty(overlays)?,
+        )
+        .await
+    }
+
+    /// add overlay dirs to ignore rules of the workspace.
+    pub(crate) async fn ignore_overlays(&self, ignore: &mut Ignore) -> Result<(), WsvcFsError> {
+        for overlay in self.get_overlays().await? {
+            ignore.add_dir(&overlay.path);
+        }
+        Ok(())
+    }
+
+    /// copy trees and blobs of the tree `root` from `source` into this repository.
+    async fn import_tree(&self, source: &Repository, root: &ObjectId) -> Result<(), WsvcFsError> {
+        let (trees_dir, source_trees) = (self.trees_dir().await?, source.trees_dir().await?);
+        let mut queue = vec![root.clone()];
+        while let Some(tree_hash) = queue.pop() {
+            let tree = source.read_tree(&tree_hash).await?;
+            for blob in tree.blobs() {
+                if self.blob_exists(&blob.hash).await? {
+                    continue;
+                }
+                // chunks first, a chunked blob is only stored with all its chunks.
+                let chunks = source.blob_chunks(&blob.hash).await?;
+                for chunk in chunks.map(|m| m.chunks).unwrap_or_default() {
+                    self.copy_raw_object(source, &chunk.hash).await?;
+                }
+                self.copy_raw_object(source, &blob.hash).await?;
+            }
+            queue.extend(tree.dirs().map(|e| e.hash.clone()));
+            let name = tree_hash.0.to_hex();
+            if !trees_dir.join(name.as_str()).exists() {
+                copy(
+                    source_trees.join(name.as_str()),
+                    trees_dir.join(name.as_str()),
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// materialize a record of `source` into the dir `path` of `workspace` as an overlay.
+    /// `source` is another repository with its root dir, kept to update the overlay, or
+    /// this repository if `None`.
+    ///
+    /// an existing overlay at `path` is moved to the record, files of the dir not in the
+    /// record are deleted unless they are ignored.
+    pub async fn set_overlay(
+        &self,
+        path: &str,
+        source: Option<(&Repository, String)>,
+        record_hash: &ObjectId,
+        workspace: &Path,
+    ) -> Result<Overlay, WsvcError> {
+        self.check_writable()?;
+        let path = normalize_path(path)?;
+        let mut overlays = self.get_overlays().await?;
+        if let Some(other) = overlays
+            .iter()
+            .find(|o| o.path != path && overlaps(&o.path, &path))
+        {
+            return Err(WsvcError::BadUsage(format!(
+                "overlay {} overlaps overlay {}",
+                path, other.path
+            )));
+        }
+        let record = match &source {
+            Some((repo, _)) => repo.read_record(record_hash).await?,
+            None => self.read_record(record_hash).await?,
+        };
+        if let Some((repo, _)) = &source {
+            self.import_tree(repo, &record.root).await?;
+        }
+        let dir = workspace.join(&path);
+        create_dir_all(&dir).await.map_err(WsvcFsError::Os)?;
+        self.checkout_tree_impl(
+            &self.read_tree(&record.root).await?,
+            &dir,
+            &mut self.excludes().await?,
+            &mut self.new_attributes(),
+            "",
+        )
+        .await?;
+        remove_dir_all(self.temp_dir().await?)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        let overlay = Overlay {
+            path: path.clone(),
+            source: source.map(|(_, root)| root),
+            record: record.hash,
+            root: record.root,
+        };
+        overlays.retain(|o| o.path != path);
+        overlays.push(overlay.clone());
+        self.write_overlays(&mut overlays).await?;
+        Ok(overlay)
+    }
+
+    /// stop tracking the overlay at `path`, its files are kept in the workspace and get
+    /// recorded like other files unless `remove` is set.
+    pub async fn detach_overlay(
+        &self,
+        path: &str,
+        remove: bool,
+        workspace: &Path,
+    ) -> Result<Overlay, WsvcError> {
+        self.check_writable()?;
+        let path = normalize_path(path)?;
+        let mut overlays = self.get_overlays().await?;
+        let pos = overlays
+            .iter()
+            .position(|o| o.path == path)
+            .ok_or(WsvcError::BadUsage(format!("no overlay at {}", path)))?;
+        let overlay = overlays.remove(pos);
+        self.write_overlays(&mut overlays).await?;
+        let dir = workspace.join(&path);
+        if remove && dir.exists() {
+            remove_dir_all(dir).await.map_err(WsvcFsError::Os)?;
+        }
+        Ok(overlay)
+    }
+
+    /// root trees of overlays, kept by `prune_unreachable` with their trees and blobs.
+    pub(crate) async fn overlay_roots(&self) -> Result<Vec<ObjectId>, WsvcFsError> {
+        Ok(self
+            .get_overlays()
+            .await?
+            .into_iter()
+            .map(|o| o.root)
+            .collect())
+    }
+}
+
+impl ReadOnlyRepository {
+    pub async fn get_overlays(&self) -> Result<Vec<Overlay>, WsvcFsError> {
+        self.repository().get_overlays().await
+    }
+
+    pub async fn read_overlay(&self, path: &str) -> Result<Option<Overlay>, WsvcError> {
+        self.repository().read_overlay(path).await
+    }
+}
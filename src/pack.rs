@@ -0,0 +1,360 @@
+use std::{
+    collections::BTreeMap,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+};
+
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{create_dir_all, read, read_dir, remove_file, rename, File},
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+use crate::fs::WsvcFsError;
+
+/// dir of packs in the objects dir.
+pub const PACKS_DIR: &str = "pack";
+
+/// leading bytes of pack files, followed by the objects.
+const PACK_MAGIC: &[u8] = b"WSVCPAK1";
+
+/// leading bytes of pack index files.
+const INDEX_MAGIC: &[u8] = b"WSVCIDX1";
+
+/// bytes of an index entry, the object hash, its offset and its length in the pack.
+const ENTRY_SIZE: u64 = 32 + 8 + 8;
+
+/// bytes before the entries of an index, the magic, the count of objects and the fanout
+/// table, the count of objects with a hash starting with each byte or a smaller one.
+const INDEX_HEADER_SIZE: u64 = 8 + 8 + 256 * 8;
+
+/// least blobs a new clone wants for the server to send them in a single pack instead of
+/// one by one.
+pub const PACK_BOOTSTRAP_MIN_BLOBS: usize = 64;
+
+/// names of the pack and its index sent to a new clone.
+pub const BOOTSTRAP_PACK: &str = "bootstrap.pack";
+pub const BOOTSTRAP_INDEX: &str = "bootstrap.idx";
+
+/// `BlobTransfer` stand for how the server sends the blobs the client wants, the first
+/// packet of the blobs round.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BlobTransfer {
+    /// the blobs come in a pack with its index, see `Repository::export_pack`.
+    pub pack: bool,
+}
+
+/// `PackEntry` stand for where an object is in a pack file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PackEntry {
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// `RepackStats` stand for what a repack did, see `Repository::repack`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct RepackStats {
+    /// objects in the new pack.
+    pub objects: usize,
+    /// loose objects moved into the new pack.
+    pub loose: usize,
+    /// packs merged into the new pack.
+    pub packs: usize,
+    /// packed objects left out of the new pack, unreachable ones when pruning.
+    pub dropped: usize,
+    /// bytes of the new pack.
+    pub size: u64,
+}
+
+/// `StorageStats` stand for how the objects of a repository are stored, see
+/// `Repository::storage_stats`. packs hold whole objects, versions of a large file share
+/// the chunks they have in common instead of being stored as deltas.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct StorageStats {
+    pub loose_objects: usize,
+    /// bytes of loose objects.
+    pub loose_size: u64,
+    pub packs: usize,
+    /// objects in packs, counted once for each pack holding them.
+    pub packed_objects: usize,
+    /// bytes of packs and their indexes.
+    pub packed_size: u64,
+    /// copies of objects stored more than once, loose and packed or in many packs.
+    pub duplicate_objects: usize,
+    /// blobs stored as a manifest of chunks.
+    pub chunked_blobs: usize,
+    /// distinct chunks of chunked blobs.
+    pub chunks: usize,
+    /// bytes not stored again because chunks are shared by many blobs or parts of a blob.
+    pub shared_chunk_size: u64,
+    /// bytes a repack would reclaim, the copies of duplicate objects and the headers of
+    /// packs merged, less the index entries of loose objects packed.
+    pub repack_savings: u64,
+}
+
+/// bytes of a pack and its index holding `objects` objects of `size` stored bytes.
+pub(crate) fn pack_size(objects: usize, size: u64) -> u64 {
+    PACK_MAGIC.len() as u64 + size + INDEX_HEADER_SIZE + objects as u64 * ENTRY_SIZE
+}
+
+/// index files of the packs in `objects_dir`, the pack of an index is beside it with the
+/// `pack` extension. an index is written after its pack, so a pack without an index is
+/// never read.
+pub(crate) async fn pack_indexes(objects_dir: &Path) -> Result<Vec<PathBuf>, WsvcFsError> {
+    let dir = objects_dir.join(PACKS_DIR);
+    let mut result = vec![];
+    if !dir.exists() {
+        return Ok(result);
+    }
+    let mut entries = read_dir(&dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| ext == "idx") && path.with_extension("pack").exists()
+        {
+            result.push(path);
+        }
+    }
+    result.sort();
+    Ok(result)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_be_bytes(bytes[..8].try_into().expect("8 bytes"))
+}
+
+/// read the header of the index at `path`, returns the fanout table.
+async fn read_fanout(file: &mut File, path: &Path) -> Result<Vec<u64>, WsvcFsError> {
+    let mut header = vec![0; INDEX_HEADER_SIZE as usize];
+    file.read_exact(&mut header).await?;
+    if &header[..8] != INDEX_MAGIC {
+        return Err(WsvcFsError::DecompressFailed(format!(
+            "broken pack index: {}",
+            path.display()
+        )));
+    }
+    Ok(header[16..].chunks(8).map(read_u64).collect())
+}
+
+/// the entry of the object `hash` in the index at `path`, by a binary search over the
+/// entries starting with the first byte of the hash.
+pub(crate) async fn find_in_index(
+    path: &Path,
+    hash: &[u8; 32],
+) -> Result<Option<PackEntry>, WsvcFsError> {
+    let mut file = File::open(path).await?;
+    let fanout = read_fanout(&mut file, path).await?;
+    let first = hash[0] as usize;
+    let (mut low, mut high) = match first {
+        0 => (0, fanout[0]),
+        _ => (fanout[first - 1], fanout[first]),
+    };
+    let mut entry = [0; ENTRY_SIZE as usize];
+    while low < high {
+        let middle = low + (high - low) / 2;
+        file.seek(SeekFrom::Start(INDEX_HEADER_SIZE + middle * ENTRY_SIZE))
+            .await?;
+        file.read_exact(&mut entry).await?;
+        match entry[..32].cmp(hash) {
+            std::cmp::Ordering::Less => low = middle + 1,
+            std::cmp::Ordering::Greater => high = middle,
+            std::cmp::Ordering::Equal => {
+                return Ok(Some(PackEntry {
+                    offset: read_u64(&entry[32..]),
+                    len: read_u64(&entry[40..]),
+                }))
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// all entries of the index at `path`, sorted by hash.
+pub(crate) async fn read_index(path: &Path) -> Result<Vec<([u8; 32], PackEntry)>, WsvcFsError> {
+    let mut file = File::open(path).await?;
+    let fanout = read_fanout(&mut file, path).await?;
+    let mut entries = vec![0; (fanout[255] * ENTRY_SIZE) as usize];
+    file.read_exact(&mut entries).await?;
+    Ok(entries
+        .chunks(ENTRY_SIZE as usize)
+        .map(|entry| {
+            (
+                entry[..32].try_into().expect("32 bytes"),
+                PackEntry {
+                    offset: read_u64(&entry[32..]),
+                    len: read_u64(&entry[40..]),
+                },
+            )
+        })
+        .collect())
+}
+
+/// remove the pack of the index at `path`, the index first so the pack is never read
+/// without it.
+pub(crate) async fn remove_pack(path: &Path) -> Result<(), WsvcFsError> {
+    remove_file(path).await?;
+    remove_file(path.with_extension("pack")).await?;
+    Ok(())
+}
+
+/// move the pack at `pack` and its index at `index` into the packs dir of `objects_dir`,
+/// returns the new index path. the pack is named by the hash of its index and moved
+/// first, so it is never read without its index.
+pub(crate) async fn install_pack(
+    objects_dir: &Path,
+    pack: &Path,
+    index: &Path,
+) -> Result<PathBuf, WsvcFsError> {
+    let dir = objects_dir.join(PACKS_DIR);
+    create_dir_all(&dir).await?;
+    let name = format!("pack-{}", blake3::hash(&read(index).await?).to_hex());
+    let index_path = dir.join(format!("{}.idx", name));
+    rename(pack, dir.join(format!("{}.pack", name))).await?;
+    rename(index, &index_path).await?;
+    Ok(index_path)
+}
+
+/// check the pack at `pack` and its index at `index` received from another repository,
+/// entries must be sorted and within the pack.
+pub(crate) async fn check_pack(pack: &Path, index: &Path) -> Result<(), WsvcFsError> {
+    let broken = |what: &str| {
+        WsvcFsError::DecompressFailed(format!("broken pack {}: {}", what, pack.display()))
+    };
+    let mut magic = vec![0; PACK_MAGIC.len()];
+    File::open(pack).await?.read_exact(&mut magic).await?;
+    if magic != PACK_MAGIC {
+        return Err(broken("magic"));
+    }
+    let size = tokio::fs::metadata(pack).await?.len();
+    let content = read(index).await?;
+    if (content.len() as u64) < INDEX_HEADER_SIZE || &content[..8] != INDEX_MAGIC {
+        return Err(broken("index"));
+    }
+    let count = read_u64(&content[8..]);
+    if read_u64(&content[INDEX_HEADER_SIZE as usize - 8..]) != count
+        || count
+            .checked_mul(ENTRY_SIZE)
+            .map(|len| INDEX_HEADER_SIZE + len)
+            != Some(content.len() as u64)
+    {
+        return Err(broken("index"));
+    }
+    let mut last = None;
+    for (hash, entry) in read_index(index).await? {
+        // entries are searched by hash, they must be sorted.
+        if last.is_some_and(|last| last >= hash) {
+            return Err(broken("index order"));
+        }
+        last = Some(hash);
+        if entry.offset < PACK_MAGIC.len() as u64
+            || entry
+                .offset
+                .checked_add(entry.len)
+                .is_none_or(|end| end > size)
+        {
+            return Err(broken("entry"));
+        }
+    }
+    Ok(())
+}
+
+/// `PackWriter` stand for a pack being written to a temp file.
+pub(crate) struct PackWriter {
+    file: File,
+    path: PathBuf,
+    offset: u64,
+    entries: BTreeMap<[u8; 32], PackEntry>,
+}
+
+impl PackWriter {
+    pub async fn create(temp: &Path) -> Result<Self, WsvcFsError> {
+        create_dir_all(temp).await?;
+        let path = temp.join(format!("pack-{}", nanoid!()));
+        let mut file = File::create(&path).await?;
+        file.write_all(PACK_MAGIC).await?;
+        Ok(Self {
+            file,
+            path,
+            offset: PACK_MAGIC.len() as u64,
+            entries: BTreeMap::new(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn contains(&self, hash: &[u8; 32]) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    /// append the stored bytes of the object `hash` read from `object`.
+    pub async fn add(
+        &mut self,
+        hash: [u8; 32],
+        object: &mut (impl AsyncRead + Unpin),
+    ) -> Result<(), WsvcFsError> {
+        let len = tokio::io::copy(object, &mut self.file).await?;
+        self.entries.insert(
+            hash,
+            PackEntry {
+                offset: self.offset,
+                len,
+            },
+        );
+        self.offset += len;
+        Ok(())
+    }
+
+    /// remove the pack being written.
+    pub async fn discard(self) -> Result<(), WsvcFsError> {
+        drop(self.file);
+        remove_file(&self.path).await?;
+        Ok(())
+    }
+
+    /// the index of the objects added so far.
+    fn index(&self) -> Vec<u8> {
+        let mut index = Vec::with_capacity(
+            INDEX_HEADER_SIZE as usize + self.entries.len() * ENTRY_SIZE as usize,
+        );
+        index.extend_from_slice(INDEX_MAGIC);
+        index.extend_from_slice(&(self.entries.len() as u64).to_be_bytes());
+        let mut fanout = [0u64; 256];
+        for hash in self.entries.keys() {
+            fanout[hash[0] as usize] += 1;
+        }
+        let mut count = 0;
+        for n in fanout {
+            count += n;
+            index.extend_from_slice(&count.to_be_bytes());
+        }
+        for (hash, entry) in &self.entries {
+            index.extend_from_slice(hash);
+            index.extend_from_slice(&entry.offset.to_be_bytes());
+            index.extend_from_slice(&entry.len.to_be_bytes());
+        }
+        index
+    }
+
+    /// write the index beside the pack in the temp dir, returns the paths of the pack and
+    /// its index, e.g. to send them to another repository.
+    pub async fn finish_temp(mut self) -> Result<(PathBuf, PathBuf), WsvcFsError> {
+        self.file.flush().await?;
+        self.file.sync_all().await?;
+        let index = self.index();
+        let index_path = self.path.with_extension("idx");
+        let mut file = File::create(&index_path).await?;
+        file.write_all(&index).await?;
+        file.sync_all().await?;
+        Ok((self.path, index_path))
+    }
+
+    /// move the pack into the packs dir of `objects_dir` and write its index, returns the
+    /// index path and the bytes of the pack.
+    pub async fn finish(self, objects_dir: &Path) -> Result<(PathBuf, u64), WsvcFsError> {
+        let size = self.offset;
+        let (pack, index) = self.finish_temp().await?;
+        Ok((install_pack(objects_dir, &pack, &index).await?, size))
+    }
+}
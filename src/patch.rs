@@ -0,0 +1,437 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    attributes::{Attributes, DiffAttribute},
+    binary::is_binary,
+    diff::Change,
+    fs::WsvcFsError,
+    model::Repository,
+    readonly::ReadOnlyRepository,
+};
+
+/// edit distance above which lines or words are diffed as fully replaced, bounding the
+/// time and memory of diffing very different contents.
+const MAX_EDITS: usize = 1000;
+
+/// an edit of the shortest edit script, by indexes into both sides.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// the shortest edit script from `a` to `b`, by the greedy algorithm of Myers.
+fn myers<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Edit> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    let mut v = vec![0isize; 2 * max + 3];
+    // `trace[d]` is `v` after step `d` for diagonals `-d..=d`.
+    let mut trace: Vec<Vec<isize>> = vec![];
+    'search: for d in 0..=max as isize {
+        if d as usize > MAX_EDITS {
+            return (0..a.len())
+                .map(Edit::Delete)
+                .chain((0..b.len()).map(Edit::Insert))
+                .collect();
+        }
+        for k in (-d..=d).step_by(2) {
+            let at = |k: isize| (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+                v[at(k + 1)]
+            } else {
+                v[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[at(k)] = x;
+            if x >= n && y >= m {
+                trace.push(v[at(-d)..=at(d)].to_vec());
+                break 'search;
+            }
+        }
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+    }
+    let mut edits = vec![];
+    let (mut x, mut y) = (n, m);
+    for d in (1..trace.len() as isize).rev() {
+        let prev = &trace[d as usize - 1];
+        let get = |k: isize| prev[(k + d - 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = get(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit::Equal(x as usize, y as usize));
+        }
+        if x == prev_x {
+            edits.push(Edit::Insert(prev_y as usize));
+        } else {
+            edits.push(Edit::Delete(prev_x as usize));
+        }
+        (x, y) = (prev_x, prev_y);
+    }
+    while x > 0 && y > 0 {
+        x -= 1;
+        y -= 1;
+        edits.push(Edit::Equal(x as usize, y as usize));
+    }
+    edits.reverse();
+    edits
+}
+
+/// index pairs of the lines of `a` and `b` kept by the shortest edit script, in order.
+pub(crate) fn equal_lines(a: &[&str], b: &[&str]) -> Vec<(usize, usize)> {
+    myers(a, b)
+        .into_iter()
+        .filter_map(|edit| match edit {
+            Edit::Equal(x, y) => Some((x, y)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// split a line into words, runs of whitespace and single punctuation chars, so that
+/// joining them gives the line back.
+pub fn split_words(line: &str) -> Vec<&str> {
+    let class = |c: char| match c {
+        c if c.is_alphanumeric() || c == '_' => 0,
+        c if c.is_whitespace() => 1,
+        _ => 2,
+    };
+    let mut result = vec![];
+    let mut start = 0;
+    let mut prev = None;
+    for (i, c) in line.char_indices() {
+        let current = class(c);
+        if prev.is_some_and(|p| p != current || p == 2) {
+            result.push(&line[start..i]);
+            start = i;
+        }
+        prev = Some(current);
+    }
+    if start < line.len() {
+        result.push(&line[start..]);
+    }
+    result
+}
+
+/// `WordChange` stand for a run of words of a modified line.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "kind", content = "text", rename_all = "lowercase")]
+pub enum WordChange {
+    Equal(String),
+    Removed(String),
+    Added(String),
+}
+
+/// diff two lines by words, adjacent words of the same change are joined.
+pub fn diff_words(old: &str, new: &str) -> Vec<WordChange> {
+    let (a, b) = (split_words(old), split_words(new));
+    let mut result: Vec<WordChange> = vec![];
+    for edit in myers(&a, &b) {
+        let word = match edit {
+            Edit::Equal(i, _) => WordChange::Equal(a[i].to_owned()),
+            Edit::Delete(i) => WordChange::Removed(a[i].to_owned()),
+            Edit::Insert(j) => WordChange::Added(b[j].to_owned()),
+        };
+        match (result.last_mut(), word) {
+            (Some(WordChange::Equal(last)), WordChange::Equal(word))
+            | (Some(WordChange::Removed(last)), WordChange::Removed(word))
+            | (Some(WordChange::Added(last)), WordChange::Added(word)) => last.push_str(&word),
+            (_, word) => result.push(word),
+        }
+    }
+    result
+}
+
+/// `Whitespace` stand for how whitespace counts when comparing lines.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Whitespace {
+    /// lines are compared as they are.
+    #[default]
+    #[serde(rename = "exact")]
+    Exact,
+    /// runs of whitespace compare equal to a single space, trailing whitespace is ignored.
+    #[serde(rename = "change")]
+    IgnoreChange,
+    /// whitespace is ignored entirely.
+    #[serde(rename = "all")]
+    IgnoreAll,
+}
+
+impl Whitespace {
+    /// the key `line` is compared by.
+    fn key(self, line: &str) -> std::borrow::Cow<'_, str> {
+        match self {
+            Whitespace::Exact => line.into(),
+            Whitespace::IgnoreChange => {
+                line.split_whitespace().collect::<Vec<_>>().join(" ").into()
+            }
+            Whitespace::IgnoreAll => line
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .collect::<String>()
+                .into(),
+        }
+    }
+}
+
+impl std::str::FromStr for Whitespace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exact" => Ok(Whitespace::Exact),
+            "change" => Ok(Whitespace::IgnoreChange),
+            "all" => Ok(Whitespace::IgnoreAll),
+            _ => Err(format!(
+                "unknown whitespace mode: {}, use exact, change or all",
+                s
+            )),
+        }
+    }
+}
+
+/// `LineKind` stand for whether a line of a hunk is kept, removed or added.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LineKind {
+    Context,
+    Removed,
+    Added,
+}
+
+/// `Line` stand for a line of a hunk, without its line ending.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Line {
+    pub kind: LineKind,
+    pub text: String,
+    /// the word diff of a removed line and the added line replacing it, set on both.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub words: Option<Vec<WordChange>>,
+}
+
+/// `LineStat` stand for how many lines a change of a text file adds and removes.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LineStat {
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+impl LineStat {
+    /// count the added and removed lines of `hunks`.
+    pub fn of(hunks: &[Hunk]) -> Self {
+        let mut stat = Self::default();
+        for line in hunks.iter().flat_map(|hunk| &hunk.lines) {
+            match line.kind {
+                LineKind::Added => stat.insertions += 1,
+                LineKind::Removed => stat.deletions += 1,
+                LineKind::Context => {}
+            }
+        }
+        stat
+    }
+
+    pub fn total(&self) -> usize {
+        self.insertions + self.deletions
+    }
+}
+
+/// `Hunk` stand for changed lines with their context lines, line numbers start at 1.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    pub lines: Vec<Line>,
+}
+
+impl Hunk {
+    /// the hunk header, e.g. `@@ -1,3 +1,4 @@`.
+    pub fn header(&self) -> String {
+        format!(
+            "@@ -{},{} +{},{} @@",
+            self.old_start, self.old_lines, self.new_start, self.new_lines
+        )
+    }
+
+    /// pair each run of removed lines with the run of added lines after it, line by line,
+    /// and set their word diffs.
+    fn pair_lines(&mut self) {
+        let mut i = 0;
+        while i < self.lines.len() {
+            let removed = self.lines[i..]
+                .iter()
+                .take_while(|l| l.kind == LineKind::Removed)
+                .count();
+            let added = self.lines[i + removed..]
+                .iter()
+                .take_while(|l| l.kind == LineKind::Added)
+                .count();
+            for j in 0..removed.min(added) {
+                let words = diff_words(&self.lines[i + j].text, &self.lines[i + removed + j].text);
+                self.lines[i + j].words = Some(words.clone());
+                self.lines[i + removed + j].words = Some(words);
+            }
+            i += (removed + added).max(1);
+        }
+    }
+}
+
+/// diff two texts by lines into hunks with `context` lines around changes, paired
+/// removed and added lines get word diffs. lines are compared by `whitespace`, lines
+/// only changed in ignored whitespace show as context with the new text.
+pub fn diff_lines(old: &str, new: &str, context: usize, whitespace: Whitespace) -> Vec<Hunk> {
+    let (a, b) = (
+        old.lines().collect::<Vec<_>>(),
+        new.lines().collect::<Vec<_>>(),
+    );
+    let edits = myers(
+        &a.iter().map(|l| whitespace.key(l)).collect::<Vec<_>>(),
+        &b.iter().map(|l| whitespace.key(l)).collect::<Vec<_>>(),
+    );
+    // line indexes of both sides before each edit.
+    let mut positions = Vec::with_capacity(edits.len() + 1);
+    let (mut x, mut y) = (0, 0);
+    for edit in &edits {
+        positions.push((x, y));
+        match edit {
+            Edit::Equal(..) => (x, y) = (x + 1, y + 1),
+            Edit::Delete(_) => x += 1,
+            Edit::Insert(_) => y += 1,
+        }
+    }
+    positions.push((x, y));
+    let is_change = |e: &Edit| !matches!(e, Edit::Equal(..));
+    let mut hunks = vec![];
+    let mut i = 0;
+    while let Some(start) = edits[i..].iter().position(is_change).map(|p| p + i) {
+        // changes closer than twice the context share a hunk.
+        let mut end = start;
+        loop {
+            end += edits[end..].iter().take_while(|e| is_change(e)).count();
+            let equal = edits[end..].iter().take_while(|e| !is_change(e)).count();
+            if end + equal == edits.len() || equal > 2 * context {
+                break;
+            }
+            end += equal;
+        }
+        let from = start.saturating_sub(context).max(i);
+        let to = (end + context).min(edits.len());
+        let lines = edits[from..to]
+            .iter()
+            .map(|edit| {
+                let (kind, text) = match *edit {
+                    Edit::Equal(_, y) => (LineKind::Context, b[y]),
+                    Edit::Delete(x) => (LineKind::Removed, a[x]),
+                    Edit::Insert(y) => (LineKind::Added, b[y]),
+                };
+                Line {
+                    kind,
+                    text: text.to_owned(),
+                    words: None,
+                }
+            })
+            .collect();
+        let ((old_from, new_from), (old_to, new_to)) = (positions[from], positions[to]);
+        let mut hunk = Hunk {
+            // an empty side starts at the line before it, the same as unified diffs.
+            old_start: if old_to > old_from {
+                old_from + 1
+            } else {
+                old_from
+            },
+            old_lines: old_to - old_from,
+            new_start: if new_to > new_from {
+                new_from + 1
+            } else {
+                new_from
+            },
+            new_lines: new_to - new_from,
+            lines,
+        };
+        hunk.pair_lines();
+        hunks.push(hunk);
+        i = to;
+    }
+    hunks
+}
+
+impl Repository {
+    /// line diff of a change of text files, `None` if the content did not change or
+    /// either side is binary or not UTF-8. no hunks if only ignored whitespace changed.
+    ///
+    /// the `diff` attribute of the path in `attributes` overrides whether it is binary.
+    pub async fn text_diff(
+        &self,
+        change: &Change,
+        context: usize,
+        whitespace: Whitespace,
+        attributes: &Attributes,
+    ) -> Result<Option<Vec<Hunk>>, WsvcFsError> {
+        let diff = attributes.get(change.path()).diff;
+        if matches!(
+            diff,
+            Some(DiffAttribute::Binary) | Some(DiffAttribute::Driver(_))
+        ) {
+            return Ok(None);
+        }
+        let (old, new) = match change {
+            Change::Added { hash, .. } => (None, Some(hash)),
+            Change::Removed { hash, .. } => (Some(hash), None),
+            Change::Modified {
+                old_hash, new_hash, ..
+            }
+            | Change::Moved {
+                old_hash, new_hash, ..
+            } if old_hash != new_hash => (Some(old_hash), Some(new_hash)),
+            _ => return Ok(None),
+        };
+        let mut texts = vec![];
+        for hash in [old, new] {
+            let data = match hash {
+                Some(hash) => self.read_blob(hash).await?,
+                None => vec![],
+            };
+            // filtered files are compared as checked out, e.g. decrypted.
+            let data = match attributes.driver(change.path()) {
+                Some(driver) => driver.smudge(change.path(), data, None).await?,
+                None => data,
+            };
+            if diff.is_none() && is_binary(&data) {
+                return Ok(None);
+            }
+            match String::from_utf8(data) {
+                Ok(text) => texts.push(text),
+                Err(_) => return Ok(None),
+            }
+        }
+        Ok(Some(diff_lines(&texts[0], &texts[1], context, whitespace)))
+    }
+}
+
+impl ReadOnlyRepository {
+    pub async fn text_diff(
+        &self,
+        change: &Change,
+        context: usize,
+        whitespace: Whitespace,
+        attributes: &Attributes,
+    ) -> Result<Option<Vec<Hunk>>, WsvcFsError> {
+        self.repository()
+            .text_diff(change, context, whitespace, attributes)
+            .await
+    }
+}
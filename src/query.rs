@@ -0,0 +1,81 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{model::Record, WsvcError};
+
+/// `RecordQuery` stand for filters of the records listed by `wsvc logs` and by the records
+/// endpoint of servers, a record must match all filters given.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct RecordQuery {
+    /// text the author holds, case-insensitive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// records at or after this date.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<DateTime<Utc>>,
+    /// records before this date.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<DateTime<Utc>>,
+    /// words each starting a word of the message, case-insensitive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub q: Option<String>,
+}
+
+/// `RecordsPage` stand for a page of the records matching a query, the latest first.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct RecordsPage {
+    /// count of all records matching the query.
+    pub total: usize,
+    /// records of the query skipped before this page.
+    pub offset: usize,
+    pub records: Vec<Record>,
+}
+
+/// lowercase words of `text`.
+pub fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+}
+
+/// parse a date of a query, rfc3339 or `YYYY-MM-DD`. a day is its start, or its end if
+/// `end`, so `until` of a day keeps the records of that day.
+pub fn parse_date(value: &str, end: bool) -> Result<DateTime<Utc>, WsvcError> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Ok(date.with_timezone(&Utc));
+    }
+    let day = NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| {
+        WsvcError::BadUsage(format!(
+            "invalid date {:?}, expected YYYY-MM-DD or rfc3339",
+            value
+        ))
+    })?;
+    let day = match end {
+        true => day.succ_opt().unwrap_or(day),
+        false => day,
+    };
+    Ok(day.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc())
+}
+
+impl RecordQuery {
+    /// whether `record` matches the author and date filters.
+    pub fn matches_meta(&self, record: &Record) -> bool {
+        self.author
+            .as_ref()
+            .is_none_or(|a| record.author.to_lowercase().contains(&a.to_lowercase()))
+            && self.since.is_none_or(|since| record.date >= since)
+            && self.until.is_none_or(|until| record.date < until)
+    }
+
+    /// whether `record` matches all filters.
+    pub fn matches(&self, record: &Record) -> bool {
+        if !self.matches_meta(record) {
+            return false;
+        }
+        let Some(q) = &self.q else {
+            return true;
+        };
+        let message = words(&record.message).collect::<Vec<_>>();
+        words(q).all(|word| message.iter().any(|w| w.starts_with(&word)))
+    }
+}
@@ -0,0 +1,410 @@
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use futures::{StreamExt, TryStreamExt};
+use quinn::{
+    crypto::rustls::{QuicClientConfig, QuicServerConfig},
+    Connection, Endpoint, RecvStream, SendStream,
+};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{ring, CryptoProvider},
+    pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
+    DigitallySignedStruct, SignatureScheme,
+};
+use tokio::fs::{read, write, File};
+
+use crate::{
+    dial::{self, AddrFamily},
+    frame::FrameStream,
+    fs::WsvcFsError,
+    WsvcError,
+};
+
+/// url scheme of quic remotes, e.g. `quic://example.com:7420?fingerprint=<hex>`.
+pub const QUIC_SCHEME: &str = "quic://";
+
+/// alpn protocol name of the wsvc sync protocol.
+const ALPN: &[u8] = b"wsvc";
+
+/// sent by the client when opening the control stream, quic streams are only seen by
+/// the peer after something is written to them.
+const PREAMBLE: &[u8] = b"wsvc";
+
+/// blob files sent at the same time.
+const PARALLEL_FILES: usize = 16;
+
+/// largest frame of the control stream, like the frame limit of websocket syncs.
+const MAX_FRAME_SIZE: usize = 16 << 20;
+
+const CERT_FILE: &str = "quic-cert.der";
+
+const KEY_FILE: &str = "quic-key.der";
+
+fn quic_error(err: impl std::fmt::Display) -> WsvcError {
+    WsvcError::DataError(format!("quic error: {}", err))
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+/// `QuicIdentity` stand for the self signed certificate of a quic server.
+///
+/// clients pin the certificate by its fingerprint instead of trusting a CA.
+pub struct QuicIdentity {
+    cert: CertificateDer<'static>,
+    key: Vec<u8>,
+}
+
+impl QuicIdentity {
+    /// load the certificate stored in `dir`, a new one is generated and stored if there is
+    /// none, so the fingerprint stays the same across restarts.
+    pub async fn load_or_generate(dir: impl AsRef<Path>) -> Result<Self, WsvcError> {
+        let cert_path = dir.as_ref().join(CERT_FILE);
+        let key_path = dir.as_ref().join(KEY_FILE);
+        if cert_path.exists() && key_path.exists() {
+            return Ok(Self {
+                cert: CertificateDer::from(read(cert_path).await.map_err(WsvcFsError::Os)?),
+                key: read(key_path).await.map_err(WsvcFsError::Os)?,
+            });
+        }
+        let certified =
+            rcgen::generate_simple_self_signed(vec!["wsvc".to_owned()]).map_err(quic_error)?;
+        let identity = Self {
+            cert: certified.cert.der().clone(),
+            key: certified.key_pair.serialize_der(),
+        };
+        write(cert_path, identity.cert.as_ref())
+            .await
+            .map_err(WsvcFsError::Os)?;
+        write(key_path, &identity.key)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        Ok(identity)
+    }
+
+    /// hex blake3 hash of the certificate.
+    pub fn fingerprint(&self) -> String {
+        blake3::hash(self.cert.as_ref()).to_hex().to_string()
+    }
+}
+
+/// `FingerprintVerifier` accepts only the server certificate with the pinned fingerprint.
+#[derive(Debug)]
+struct FingerprintVerifier {
+    fingerprint: String,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for FingerprintVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if blake3::hash(end_entity.as_ref()).to_hex().as_str() == self.fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(
+                "server certificate fingerprint mismatch".to_owned(),
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// `QuicStream` carries the sync protocol over a quic connection.
+///
+/// frames are sent on one bidirectional control stream with a 4 bytes size prefix, blob
+/// files are sent on their own unidirectional streams in parallel, so a lost packet only
+/// stalls the file it belongs to.
+pub struct QuicStream {
+    conn: Connection,
+    send: SendStream,
+    recv: RecvStream,
+    // keeps the client endpoint alive as long as the connection.
+    endpoint: Option<Endpoint>,
+}
+
+impl QuicStream {
+    /// connect to `quic://host:port?fingerprint=<hex>`, all addresses of the host are
+    /// raced with happy eyeballs.
+    pub async fn connect(url: &str, family: AddrFamily) -> Result<Self, WsvcError> {
+        let rest = url
+            .strip_prefix(QUIC_SCHEME)
+            .ok_or(WsvcError::BadUsage(format!("not a quic url: {}", url)))?;
+        let (host, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let (host, port) = host
+            .trim_end_matches('/')
+            .rsplit_once(':')
+            .and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)))
+            .ok_or(WsvcError::BadUsage(format!(
+                "quic url requires a port: {}",
+                url
+            )))?;
+        let fingerprint = query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("fingerprint="))
+            .ok_or(WsvcError::BadUsage(format!(
+                "quic url requires the server certificate fingerprint: {}",
+                url
+            )))?;
+        let addrs = dial::resolve(host, port, family)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        let provider = provider();
+        let mut crypto = rustls::ClientConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(quic_error)?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(FingerprintVerifier {
+                fingerprint: fingerprint.to_lowercase(),
+                provider,
+            }))
+            .with_no_client_auth();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let config = quinn::ClientConfig::new(Arc::new(
+            QuicClientConfig::try_from(crypto).map_err(quic_error)?,
+        ));
+        let (endpoint, conn) = dial::race(&addrs, |addr| {
+            let config = config.clone();
+            async move {
+                let bind: SocketAddr = if addr.is_ipv4() {
+                    ([0, 0, 0, 0], 0).into()
+                } else {
+                    ([0u16; 8], 0).into()
+                };
+                let mut endpoint = Endpoint::client(bind).map_err(WsvcFsError::Os)?;
+                endpoint.set_default_client_config(config);
+                let conn = endpoint
+                    .connect(addr, "wsvc")
+                    .map_err(quic_error)?
+                    .await
+                    .map_err(quic_error)?;
+                Ok::<_, WsvcError>((endpoint, conn))
+            }
+        })
+        .await
+        .ok_or(quic_error("no address to connect"))??;
+        let (mut send, recv) = conn.open_bi().await.map_err(quic_error)?;
+        send.write_all(PREAMBLE).await.map_err(quic_error)?;
+        Ok(Self {
+            conn,
+            send,
+            recv,
+            endpoint: Some(endpoint),
+        })
+    }
+
+    /// address of the peer.
+    pub fn remote_address(&self) -> SocketAddr {
+        self.conn.remote_address()
+    }
+
+    /// finish the control stream and wait for the peer to finish too, so the last
+    /// frames are delivered before the connection is closed.
+    pub async fn close(mut self) {
+        self.send.finish().ok();
+        let wait = async { while let Ok(Some(_)) = self.recv_frame().await {} };
+        tokio::time::timeout(Duration::from_secs(5), wait)
+            .await
+            .ok();
+        self.conn.close(0u32.into(), b"done");
+        if let Some(endpoint) = &self.endpoint {
+            endpoint.wait_idle().await;
+        }
+    }
+}
+
+#[async_trait]
+impl FrameStream for QuicStream {
+    async fn send_frame(&mut self, data: Vec<u8>) -> Result<(), WsvcError> {
+        self.send
+            .write_all(&(data.len() as u32).to_be_bytes())
+            .await
+            .map_err(quic_error)?;
+        self.send.write_all(&data).await.map_err(quic_error)
+    }
+
+    async fn recv_frame(&mut self) -> Result<Option<Vec<u8>>, WsvcError> {
+        let mut size = [0u8; 4];
+        match self.recv.read_exact(&mut size).await {
+            Ok(_) => {}
+            Err(quinn::ReadExactError::FinishedEarly(0)) => return Ok(None),
+            Err(err) => return Err(quic_error(err)),
+        }
+        let size = u32::from_be_bytes(size) as usize;
+        if size > MAX_FRAME_SIZE {
+            return Err(WsvcError::DataError(format!(
+                "frame of {} bytes exceeds the limit of {} bytes",
+                size, MAX_FRAME_SIZE
+            )));
+        }
+        let mut data = vec![0u8; size];
+        self.recv.read_exact(&mut data).await.map_err(quic_error)?;
+        Ok(Some(data))
+    }
+
+    async fn send_files(&mut self, files: &[(String, PathBuf)]) -> Result<bool, WsvcError> {
+        futures::stream::iter(files.to_vec())
+            .map(|(name, path)| {
+                let conn = self.conn.clone();
+                async move {
+                    let mut stream = conn.open_uni().await.map_err(quic_error)?;
+                    stream
+                        .write_all(&(name.len() as u16).to_be_bytes())
+                        .await
+                        .map_err(quic_error)?;
+                    stream
+                        .write_all(name.as_bytes())
+                        .await
+                        .map_err(quic_error)?;
+                    let mut file = File::open(path).await.map_err(WsvcFsError::Os)?;
+                    tokio::io::copy(&mut file, &mut stream)
+                        .await
+                        .map_err(WsvcFsError::Os)?;
+                    stream.finish().map_err(quic_error)?;
+                    Ok::<_, WsvcError>(())
+                }
+            })
+            .buffer_unordered(PARALLEL_FILES)
+            .try_collect::<Vec<_>>()
+            .await?;
+        Ok(true)
+    }
+
+    async fn recv_files(&mut self, count: usize, dir: &Path) -> Result<bool, WsvcError> {
+        futures::stream::iter(0..count)
+            .map(|_| {
+                let conn = self.conn.clone();
+                let dir = dir.to_path_buf();
+                async move {
+                    let mut stream = conn.accept_uni().await.map_err(quic_error)?;
+                    let mut size = [0u8; 2];
+                    stream.read_exact(&mut size).await.map_err(quic_error)?;
+                    let mut name = vec![0u8; u16::from_be_bytes(size) as usize];
+                    stream.read_exact(&mut name).await.map_err(quic_error)?;
+                    let name = String::from_utf8(name)?;
+                    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+                        return Err(WsvcError::DataError(format!("invalid file name: {}", name)));
+                    }
+                    let mut file = File::create(dir.join(name))
+                        .await
+                        .map_err(WsvcFsError::Os)?;
+                    tokio::io::copy(&mut stream, &mut file)
+                        .await
+                        .map_err(WsvcFsError::Os)?;
+                    Ok::<_, WsvcError>(())
+                }
+            })
+            .buffer_unordered(PARALLEL_FILES)
+            .try_collect::<Vec<_>>()
+            .await?;
+        Ok(true)
+    }
+}
+
+/// `QuicServer` accepts sync connections over quic.
+///
+/// ```ignore
+/// let identity = QuicIdentity::load_or_generate(&repo.path).await?;
+/// let server = QuicServer::bind("0.0.0.0:7420".parse()?, &identity)?;
+/// while let Some(mut stream) = server.accept().await {
+///     wsvc::server::sync_with(&repo, &mut stream).await?;
+///     stream.close().await;
+/// }
+/// ```
+pub struct QuicServer {
+    endpoint: Endpoint,
+}
+
+impl QuicServer {
+    pub fn bind(addr: SocketAddr, identity: &QuicIdentity) -> Result<Self, WsvcError> {
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(identity.key.clone()));
+        let mut crypto = rustls::ServerConfig::builder_with_provider(provider())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(quic_error)?
+            .with_no_client_auth()
+            .with_single_cert(vec![identity.cert.clone()], key)
+            .map_err(quic_error)?;
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let config = quinn::ServerConfig::with_crypto(Arc::new(
+            QuicServerConfig::try_from(crypto).map_err(quic_error)?,
+        ));
+        Ok(Self {
+            endpoint: Endpoint::server(config, addr).map_err(WsvcFsError::Os)?,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, WsvcError> {
+        Ok(self.endpoint.local_addr().map_err(WsvcFsError::Os)?)
+    }
+
+    /// accept the next connection, `None` if the server is closed. failed handshakes are
+    /// skipped.
+    pub async fn accept(&self) -> Option<QuicStream> {
+        while let Some(incoming) = self.endpoint.accept().await {
+            let stream = async {
+                let conn = incoming.await.map_err(quic_error)?;
+                let (send, mut recv) = conn.accept_bi().await.map_err(quic_error)?;
+                let mut preamble = [0u8; PREAMBLE.len()];
+                recv.read_exact(&mut preamble).await.map_err(quic_error)?;
+                if preamble != PREAMBLE {
+                    return Err(WsvcError::DataError("invalid quic preamble".to_owned()));
+                }
+                Ok::<_, WsvcError>(QuicStream {
+                    conn,
+                    send,
+                    recv,
+                    endpoint: None,
+                })
+            };
+            if let Ok(stream) = stream.await {
+                return Some(stream);
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,198 @@
+use std::path::Path;
+
+use futures::Stream;
+
+use crate::{
+    attest::Attestation,
+    fs::{RepositoryBuilder, WsvcFsError},
+    model::{Blob, Entry, ObjectId, Record, Repository, Tree},
+    note::Note,
+    records::RecordSummary,
+    sign::Signature,
+    state::OperationState,
+    tag::{Tag, TagAnnotation},
+    WsvcError,
+};
+
+/// `ReadOnlyRepository` is a handle of a repository that can only read it.
+///
+/// it never takes the lock, so any number of readers may run beside a writer holding
+/// the lock, e.g. `wsvc logs` while a sync is storing records. records are stored after
+/// their trees and blobs, so records read are complete, but records being stored may be
+/// missed.
+///
+/// ```ignore
+/// let repo = Repository::open_read_only(".").await?;
+/// for record in repo.get_records().await? {
+///     println!("{} {}", record.hash.0.to_hex(), record.message);
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ReadOnlyRepository {
+    repo: Repository,
+}
+
+impl Repository {
+    /// open a repository at path read-only, the `bare` option will be guessed.
+    pub async fn open_read_only(path: impl AsRef<Path>) -> Result<ReadOnlyRepository, WsvcFsError> {
+        Ok(ReadOnlyRepository {
+            repo: RepositoryBuilder::new(path).read_only(true).open().await?,
+        })
+    }
+
+    /// a read-only handle of the same repository.
+    pub fn to_read_only(&self) -> ReadOnlyRepository {
+        let mut repo = self.clone();
+        repo.options.read_only = true;
+        ReadOnlyRepository { repo }
+    }
+}
+
+impl ReadOnlyRepository {
+    /// the repository read through, for reading helpers of other modules.
+    pub(crate) fn repository(&self) -> &Repository {
+        &self.repo
+    }
+
+    /// the dir storing the repository data.
+    pub fn path(&self) -> &Path {
+        &self.repo.path
+    }
+
+    pub async fn blob_exists(&self, blob_hash: &ObjectId) -> Result<bool, WsvcFsError> {
+        self.repo.blob_exists(blob_hash).await
+    }
+
+    pub async fn read_blob(&self, blob_hash: &ObjectId) -> Result<Vec<u8>, WsvcFsError> {
+        self.repo.read_blob(blob_hash).await
+    }
+
+    pub async fn missing_objects(&self, ids: &[ObjectId]) -> Result<Vec<ObjectId>, WsvcFsError> {
+        self.repo.missing_objects(ids).await
+    }
+
+    pub fn read_objects_stream(
+        &self,
+        ids: Vec<ObjectId>,
+    ) -> impl Stream<Item = Result<(ObjectId, Vec<u8>), WsvcFsError>> + '_ {
+        self.repo.read_objects_stream(ids)
+    }
+
+    pub async fn tree_exists(&self, tree_hash: &ObjectId) -> Result<bool, WsvcFsError> {
+        self.repo.tree_exists(tree_hash).await
+    }
+
+    pub async fn read_tree(&self, tree_hash: &ObjectId) -> Result<Tree, WsvcFsError> {
+        self.repo.read_tree(tree_hash).await
+    }
+
+    pub async fn read_record(&self, record_hash: &ObjectId) -> Result<Record, WsvcFsError> {
+        self.repo.read_record(record_hash).await
+    }
+
+    pub async fn get_records(&self) -> Result<Vec<Record>, WsvcFsError> {
+        self.repo.get_records().await
+    }
+
+    pub async fn record_summaries(&self) -> Result<Vec<RecordSummary>, WsvcFsError> {
+        self.repo.record_summaries().await
+    }
+
+    pub async fn find_records_by_prefix(
+        &self,
+        prefix: impl AsRef<str>,
+    ) -> Result<Vec<Record>, WsvcFsError> {
+        self.repo.find_records_by_prefix(prefix).await
+    }
+
+    pub async fn get_trees_of_record(
+        &self,
+        record_hash: &ObjectId,
+    ) -> Result<Vec<Tree>, WsvcFsError> {
+        self.repo.get_trees_of_record(record_hash).await
+    }
+
+    pub async fn get_blobs_of_tree(&self, tree_hash: &ObjectId) -> Result<Vec<Blob>, WsvcFsError> {
+        self.repo.get_blobs_of_tree(tree_hash).await
+    }
+
+    pub async fn resolve_path(
+        &self,
+        record_hash: &ObjectId,
+        path: impl AsRef<str>,
+    ) -> Result<Option<Entry>, WsvcFsError> {
+        self.repo.resolve_path(record_hash, path).await
+    }
+
+    pub async fn resolve_tree_path(
+        &self,
+        root: &ObjectId,
+        path: impl AsRef<str>,
+    ) -> Result<Option<Entry>, WsvcFsError> {
+        self.repo.resolve_tree_path(root, path).await
+    }
+
+    pub async fn get_latest_record(&self) -> Result<Option<Record>, WsvcFsError> {
+        self.repo.get_latest_record().await
+    }
+
+    pub async fn get_head_record(&self) -> Result<Option<Record>, WsvcFsError> {
+        self.repo.get_head_record().await
+    }
+
+    pub async fn read_ref(&self, name: impl AsRef<str>) -> Result<Option<ObjectId>, WsvcFsError> {
+        self.repo.read_ref(name).await
+    }
+
+    pub async fn operation_in_progress(&self) -> Result<Option<OperationState>, WsvcFsError> {
+        self.repo.operation_in_progress().await
+    }
+
+    pub async fn read_origin(&self) -> Result<String, WsvcFsError> {
+        self.repo.read_origin().await
+    }
+
+    pub async fn get_tags(&self) -> Result<Vec<Tag>, WsvcFsError> {
+        self.repo.get_tags().await
+    }
+
+    pub async fn read_tag(&self, name: impl AsRef<str>) -> Result<Option<Tag>, WsvcFsError> {
+        self.repo.read_tag(name).await
+    }
+
+    pub async fn resolve_tag(&self, name: impl AsRef<str>) -> Result<Option<Record>, WsvcFsError> {
+        self.repo.resolve_tag(name).await
+    }
+
+    pub async fn read_tag_annotation(
+        &self,
+        name: impl AsRef<str>,
+    ) -> Result<Option<TagAnnotation>, WsvcFsError> {
+        self.repo.read_tag_annotation(name).await
+    }
+
+    pub async fn describe_record(&self, record_hash: &ObjectId) -> Result<String, WsvcError> {
+        self.repo.describe_record(record_hash).await
+    }
+
+    pub async fn get_notes_of_record(
+        &self,
+        record_hash: &ObjectId,
+    ) -> Result<Vec<Note>, WsvcFsError> {
+        self.repo.get_notes_of_record(record_hash).await
+    }
+
+    pub async fn get_attestations_of_record(
+        &self,
+        record_hash: &ObjectId,
+    ) -> Result<Vec<Attestation>, WsvcFsError> {
+        self.repo.get_attestations_of_record(record_hash).await
+    }
+
+    pub async fn read_signatures(
+        &self,
+        record_hash: &ObjectId,
+    ) -> Result<Vec<Signature>, WsvcFsError> {
+        self.repo.read_signatures(record_hash).await
+    }
+}
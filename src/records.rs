@@ -0,0 +1,111 @@
+use std::collections::HashSet;
+
+use blake3::Hash;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{read, read_dir, OpenOptions},
+    io::AsyncWriteExt,
+};
+
+use crate::{
+    fs::WsvcFsError,
+    model::{ObjectId, Record, Repository},
+    refs::write_atomic,
+};
+
+/// file of the records index in the repository, a line of json per record.
+pub const RECORDS_INDEX_FILE: &str = "records.idx";
+
+/// `RecordSummary` stand for what the records index keeps of a record, enough to find the
+/// latest record, the record of a tree and walk the history without reading records.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RecordSummary {
+    pub hash: ObjectId,
+    pub date: DateTime<Utc>,
+    pub root: ObjectId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parents: Option<Vec<ObjectId>>,
+}
+
+impl From<&Record> for RecordSummary {
+    fn from(record: &Record) -> Self {
+        Self {
+            hash: record.hash.clone(),
+            date: record.date,
+            root: record.root.clone(),
+            parents: record.parents.clone(),
+        }
+    }
+}
+
+fn index_line(summary: &RecordSummary) -> Result<Vec<u8>, WsvcFsError> {
+    let mut line = serde_json::to_vec(summary)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+impl Repository {
+    /// append `record` to the records index, called when the record is stored.
+    pub(crate) async fn index_record(&self, record: &Record) -> Result<(), WsvcFsError> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path.join(RECORDS_INDEX_FILE))
+            .await?;
+        file.write_all(&index_line(&RecordSummary::from(record))?)
+            .await?;
+        Ok(())
+    }
+
+    /// summaries of all records, from the records index.
+    ///
+    /// record files are listed to catch records stored or removed without the index,
+    /// e.g. by older versions or an interrupted store. only those are read, and the index
+    /// is brought up to date unless the repository is read-only.
+    pub async fn record_summaries(&self) -> Result<Vec<RecordSummary>, WsvcFsError> {
+        let mut names = HashSet::new();
+        let mut entries = read_dir(self.records_dir().await?).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_file() {
+                names.insert(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        let content = match read(self.path.join(RECORDS_INDEX_FILE)).await {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(err) => return Err(err.into()),
+        };
+        let mut result = vec![];
+        let mut indexed = HashSet::new();
+        // lines of removed records, broken or repeated lines need the index rewritten.
+        let mut stale = false;
+        for line in content.split(|b| *b == b'\n').filter(|l| !l.is_empty()) {
+            match serde_json::from_slice::<RecordSummary>(line) {
+                Ok(summary) => {
+                    let name = summary.hash.0.to_hex().to_string();
+                    if names.contains(&name) && indexed.insert(name) {
+                        result.push(summary);
+                    } else {
+                        stale = true;
+                    }
+                }
+                Err(_) => stale = true,
+            }
+        }
+        let mut missing = vec![];
+        for name in names.difference(&indexed) {
+            let record = self.read_record(&ObjectId(Hash::from_hex(name)?)).await?;
+            missing.push(RecordSummary::from(&record));
+        }
+        result.extend_from_slice(&missing);
+        if (stale || !missing.is_empty()) && self.check_writable().is_ok() {
+            let mut lines = vec![];
+            for summary in &result {
+                lines.extend(index_line(summary)?);
+            }
+            write_atomic(&self.path.join(RECORDS_INDEX_FILE), &lines).await?;
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,385 @@
+use std::path::{Path, PathBuf};
+
+use nanoid::nanoid;
+use tokio::{
+    fs::{create_dir_all, read_to_string, remove_file, rename, File, OpenOptions},
+    io::AsyncWriteExt,
+};
+
+use crate::{
+    fs::WsvcFsError,
+    model::{ObjectId, Repository},
+};
+
+/// write `content` to `path` so readers see the old or the new content, never a part.
+///
+/// the content is written to a temp file beside `path`, synced and renamed over it.
+pub(crate) async fn write_atomic(path: &Path, content: &[u8]) -> Result<(), WsvcFsError> {
+    let file_name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or(WsvcFsError::InvalidFilename(format!("{:?}", path)))?;
+    let temp_path = path.with_file_name(format!(".{}.{}", file_name, nanoid!()));
+    let mut file = File::create(&temp_path).await?;
+    let result = async {
+        file.write_all(content).await?;
+        file.sync_all().await
+    }
+    .await;
+    drop(file);
+    if let Err(err) = result {
+        remove_file(&temp_path).await.ok();
+        return Err(err.into());
+    }
+    rename(&temp_path, path).await?;
+    // the rename itself is durable once the dir is synced.
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        if let Ok(dir) = File::open(parent).await {
+            dir.sync_all().await.ok();
+        }
+    }
+    Ok(())
+}
+
+/// suffix of the lock file of a ref, e.g. `tags/v1.0.lock`, ref names never end with it.
+pub const LOCK_SUFFIX: &str = ".lock";
+
+/// `RefLocks` stand for the lock files a `RefTransaction` holds, removed when dropped.
+#[derive(Default)]
+struct RefLocks(Vec<PathBuf>);
+
+impl RefLocks {
+    /// take the lock of the ref at `path`, failing if another writer holds it.
+    async fn acquire(&mut self, path: &Path) -> Result<(), WsvcFsError> {
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent).await?;
+        }
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(LOCK_SUFFIX);
+        let lock_path = PathBuf::from(lock_path);
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+            .await
+        {
+            Ok(_) => {
+                self.0.push(lock_path);
+                Ok(())
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => Err(
+                WsvcFsError::RefLocked(lock_path.to_string_lossy().into_owned()),
+            ),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl Drop for RefLocks {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            std::fs::remove_file(path).ok();
+        }
+    }
+}
+
+/// `RefUpdate` stand for a pending update of a ref in a `RefTransaction`.
+#[derive(Clone, Debug)]
+struct RefUpdate {
+    name: String,
+    /// the value the ref must have before the update, `Some(None)` if it must not be set.
+    expected: Option<Option<ObjectId>>,
+    /// the new content, a record hash or `ref: <name>` of a symbolic ref. `None` deletes
+    /// the ref.
+    new: Option<String>,
+}
+
+/// `RefTransaction` stand for updates of refs applied together, e.g. HEAD and tags.
+///
+/// refs are named by their path in the repository dir, e.g. `HEAD` or `tags/v1.0`. a ref
+/// is a record hash, or a symbolic ref `ref: <name>` pointing to another ref, e.g. HEAD
+/// on a branch. expected values are compared after resolving symbolic refs, while
+/// updates replace the ref itself.
+/// expected values of all refs are checked before any is written, so a writer acting on
+/// a stale read fails instead of overwriting a newer value. each ref is replaced
+/// atomically, a crashed writer leaves refs either old or new.
+/// each updated ref is locked by creating `<ref>.lock` from the check to the rename, so
+/// concurrent transactions on the same ref fail with `WsvcFsError::RefLocked` instead of
+/// both passing the check. a lock left by a crashed writer has to be removed by hand.
+///
+/// ```ignore
+/// repo.transaction()
+///     .compare_and_set("HEAD", Some(&old_head), &new_head)
+///     .set("tags/nightly", &new_head)
+///     .commit()
+///     .await?;
+/// ```
+#[must_use = "updates are only applied by `commit`"]
+pub struct RefTransaction<'a> {
+    repo: &'a Repository,
+    updates: Vec<RefUpdate>,
+}
+
+impl<'a> RefTransaction<'a> {
+    /// set a ref whatever its current value is.
+    pub fn set(mut self, name: impl AsRef<str>, new: &ObjectId) -> Self {
+        self.updates.push(RefUpdate {
+            name: name.as_ref().to_owned(),
+            expected: None,
+            new: Some(new.0.to_hex().to_string()),
+        });
+        self
+    }
+
+    /// make a ref a symbolic ref pointing to ref `target`, e.g. HEAD to `refs/main`.
+    pub fn set_symbolic(mut self, name: impl AsRef<str>, target: impl AsRef<str>) -> Self {
+        self.updates.push(RefUpdate {
+            name: name.as_ref().to_owned(),
+            expected: None,
+            new: Some(format!("{}{}", SYMBOLIC_PREFIX, target.as_ref())),
+        });
+        self
+    }
+
+    /// set a ref only if its current value is `expected`, `None` if it must not be set.
+    pub fn compare_and_set(
+        mut self,
+        name: impl AsRef<str>,
+        expected: Option<&ObjectId>,
+        new: &ObjectId,
+    ) -> Self {
+        self.updates.push(RefUpdate {
+            name: name.as_ref().to_owned(),
+            expected: Some(expected.cloned()),
+            new: Some(new.0.to_hex().to_string()),
+        });
+        self
+    }
+
+    /// delete a ref only if its current value is `expected`.
+    pub fn delete(mut self, name: impl AsRef<str>, expected: &ObjectId) -> Self {
+        self.updates.push(RefUpdate {
+            name: name.as_ref().to_owned(),
+            expected: Some(Some(expected.clone())),
+            new: None,
+        });
+        self
+    }
+
+    /// lock the updated refs, check expected values and apply all updates in order.
+    pub async fn commit(self) -> Result<(), WsvcFsError> {
+        self.repo.check_writable()?;
+        for update in &self.updates {
+            check_ref_name(&update.name)?;
+            if let Some(target) = update
+                .new
+                .as_deref()
+                .and_then(|new| new.strip_prefix(SYMBOLIC_PREFIX))
+            {
+                check_ref_name(target)?;
+            }
+        }
+        // refs are locked in name order, so transactions never wait on each other in a cycle.
+        let mut names: Vec<&str> = self.updates.iter().map(|u| u.name.as_str()).collect();
+        names.sort_unstable();
+        names.dedup();
+        let mut locks = RefLocks::default();
+        for name in names {
+            locks.acquire(&self.repo.path.join(name)).await?;
+        }
+        for update in &self.updates {
+            if let Some(expected) = &update.expected {
+                if self.repo.read_ref(&update.name).await? != *expected {
+                    return Err(WsvcFsError::RefChanged(update.name.clone()));
+                }
+            }
+        }
+        for update in self.updates {
+            let path = self.repo.path.join(&update.name);
+            match update.new {
+                Some(new) => {
+                    if let Some(parent) = path.parent() {
+                        create_dir_all(parent).await?;
+                    }
+                    write_atomic(&path, new.as_bytes()).await?;
+                }
+                None => remove_file(&path).await?,
+            }
+        }
+        drop(locks);
+        Ok(())
+    }
+}
+
+/// prefix of symbolic refs, e.g. `ref: refs/main` in HEAD.
+pub const SYMBOLIC_PREFIX: &str = "ref: ";
+
+/// ref names are relative paths without `.` and `..` components, not ending with
+/// `LOCK_SUFFIX`.
+fn check_ref_name(name: &str) -> Result<(), WsvcFsError> {
+    if name.is_empty()
+        || name.starts_with('/')
+        || name.ends_with(LOCK_SUFFIX)
+        || name
+            .split('/')
+            .any(|c| c.is_empty() || c == "." || c == "..")
+    {
+        return Err(WsvcFsError::InvalidFilename(name.to_owned()));
+    }
+    Ok(())
+}
+
+impl Repository {
+    /// start a transaction updating refs.
+    pub fn transaction(&self) -> RefTransaction<'_> {
+        RefTransaction {
+            repo: self,
+            updates: vec![],
+        }
+    }
+
+    /// read the content of a ref, `None` if it is missing or empty.
+    async fn read_ref_content(&self, name: &str) -> Result<Option<String>, WsvcFsError> {
+        check_ref_name(name)?;
+        let path = self.path.join(name);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = read_to_string(path).await?;
+        match content.trim() {
+            "" => Ok(None),
+            content => Ok(Some(content.to_owned())),
+        }
+    }
+
+    /// read a ref, `None` if it is missing or empty. a symbolic ref is resolved to the
+    /// ref it points to, which is `None` if that one is missing.
+    pub async fn read_ref(&self, name: impl AsRef<str>) -> Result<Option<ObjectId>, WsvcFsError> {
+        let content = match self.read_ref_content(name.as_ref()).await? {
+            Some(content) => content,
+            None => return Ok(None),
+        };
+        let content = match content.strip_prefix(SYMBOLIC_PREFIX) {
+            // symbolic refs never point to symbolic refs.
+            Some(target) => match self.read_ref_content(target).await? {
+                Some(content) if !content.starts_with(SYMBOLIC_PREFIX) => content,
+                _ => return Ok(None),
+            },
+            None => content,
+        };
+        Ok(Some(content.as_str().try_into()?))
+    }
+
+    /// the ref a symbolic ref points to, `None` if `name` is not a symbolic ref.
+    pub async fn read_symbolic_ref(
+        &self,
+        name: impl AsRef<str>,
+    ) -> Result<Option<String>, WsvcFsError> {
+        Ok(self
+            .read_ref_content(name.as_ref())
+            .await?
+            .and_then(|content| {
+                content
+                    .strip_prefix(SYMBOLIC_PREFIX)
+                    .map(|target| target.to_owned())
+            }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn temp_repo() -> (Repository, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("wsvc-test-{}", nanoid!()));
+        create_dir_all(&dir).await.unwrap();
+        (Repository::new(&dir, false).await.unwrap(), dir)
+    }
+
+    fn id(content: &str) -> ObjectId {
+        ObjectId(blake3::hash(content.as_bytes()))
+    }
+
+    #[tokio::test]
+    async fn stale_updates_fail() {
+        let (repo, dir) = temp_repo().await;
+        let (a, b, c) = (id("a"), id("b"), id("c"));
+        repo.transaction()
+            .compare_and_set("refs/main", None, &a)
+            .commit()
+            .await
+            .unwrap();
+        assert!(matches!(
+            repo.transaction()
+                .compare_and_set("refs/main", None, &b)
+                .commit()
+                .await,
+            Err(WsvcFsError::RefChanged(_))
+        ));
+        // no update of a failed transaction is applied.
+        assert!(matches!(
+            repo.transaction()
+                .set("tags/v1", &c)
+                .compare_and_set("refs/main", Some(&b), &c)
+                .commit()
+                .await,
+            Err(WsvcFsError::RefChanged(_))
+        ));
+        assert_eq!(repo.read_ref("tags/v1").await.unwrap(), None);
+        // expected values of symbolic refs are the refs they point to.
+        repo.transaction()
+            .set_symbolic("HEAD", "refs/main")
+            .commit()
+            .await
+            .unwrap();
+        assert!(repo
+            .transaction()
+            .compare_and_set("refs/main", Some(&a), &b)
+            .delete("tags/v1", &c)
+            .commit()
+            .await
+            .is_err());
+        assert_eq!(repo.read_ref("HEAD").await.unwrap(), Some(a));
+        repo.transaction()
+            .compare_and_set(
+                "refs/main",
+                repo.read_ref("HEAD").await.unwrap().as_ref(),
+                &b,
+            )
+            .commit()
+            .await
+            .unwrap();
+        assert_eq!(repo.read_ref("HEAD").await.unwrap(), Some(b));
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn locked_refs_fail() {
+        let (repo, dir) = temp_repo().await;
+        let a = id("a");
+        let lock = repo.path.join(format!("refs/main{}", LOCK_SUFFIX));
+        create_dir_all(lock.parent().unwrap()).await.unwrap();
+        File::create(&lock).await.unwrap();
+        assert!(matches!(
+            repo.transaction().set("refs/main", &a).commit().await,
+            Err(WsvcFsError::RefLocked(_))
+        ));
+        assert_eq!(repo.read_ref("refs/main").await.unwrap(), None);
+        remove_file(&lock).await.unwrap();
+        repo.transaction()
+            .set("refs/main", &a)
+            .commit()
+            .await
+            .unwrap();
+        // locks are released once committed, and never taken as refs.
+        assert!(!lock.exists());
+        assert!(repo
+            .transaction()
+            .set("refs/x.lock", &a)
+            .commit()
+            .await
+            .is_err());
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+}
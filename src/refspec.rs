@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{ignore::glob_match, tag::TAGS_DIR};
+
+/// `RefSpec` stand for a glob over ref names choosing refs shared by sync, e.g.
+/// `refs/main`, `refs/release-*` or `tags/*`. branches are named `refs/<name>` and tags
+/// `tags/<name>`, like their files in the repository dir. a leading `!` excludes refs
+/// matched by earlier specs, e.g. `["refs/*", "!refs/wip-*"]`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct RefSpec(pub String);
+
+impl RefSpec {
+    /// the spec of exactly the ref `name`, glob chars in it are escaped.
+    pub fn exact(name: &str) -> Self {
+        let mut pattern = String::with_capacity(name.len());
+        for c in name.chars() {
+            if matches!(c, '*' | '?' | '[' | '\\' | '!') {
+                pattern.push('\\');
+            }
+            pattern.push(c);
+        }
+        Self(pattern)
+    }
+
+    /// whether the spec matches the ref `name`, ignoring a leading `!`.
+    pub fn matches(&self, name: &str) -> bool {
+        let pattern = self.0.strip_prefix('!').unwrap_or(&self.0);
+        glob_match(
+            &pattern.chars().collect::<Vec<_>>(),
+            &name.chars().collect::<Vec<_>>(),
+        )
+    }
+
+    /// whether the spec excludes the refs it matches.
+    pub fn is_exclude(&self) -> bool {
+        self.0.starts_with('!')
+    }
+}
+
+/// whether `specs` select the ref `name`, the last spec matching it decides.
+pub fn selects(specs: &[RefSpec], name: &str) -> bool {
+    specs
+        .iter()
+        .rev()
+        .find(|spec| spec.matches(name))
+        .is_some_and(|spec| !spec.is_exclude())
+}
+
+/// `RefSpecs` stand for the refs a sync shares with a remote, records are exchanged
+/// whole whatever refs are chosen.
+///
+/// by default all tags are pushed and fetched while branches stay local.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RefSpecs {
+    /// local refs sent to the remote.
+    pub push: Vec<RefSpec>,
+    /// refs of the remote stored locally.
+    pub fetch: Vec<RefSpec>,
+}
+
+impl Default for RefSpecs {
+    fn default() -> Self {
+        Self {
+            push: default_specs(),
+            fetch: default_specs(),
+        }
+    }
+}
+
+/// the specs shared when none are configured, all tags.
+pub fn default_specs() -> Vec<RefSpec> {
+    vec![RefSpec(format!("{}/*", TAGS_DIR))]
+}
+
+impl RefSpecs {
+    pub fn pushes(&self, name: &str) -> bool {
+        selects(&self.push, name)
+    }
+
+    pub fn fetches(&self, name: &str) -> bool {
+        selects(&self.fetch, name)
+    }
+}
@@ -0,0 +1,409 @@
+use std::path::{Path, PathBuf};
+
+use chrono::serde::ts_seconds::{deserialize as from_ts, serialize as to_ts};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{create_dir_all, read, read_dir};
+
+use crate::{
+    fs::WsvcFsError,
+    model::{ObjectId, Repository},
+    readonly::ReadOnlyRepository,
+    refs::write_atomic,
+    tag::{check_tag_name, TagAnnotation},
+    WsvcError,
+};
+
+/// file name of the release manifest in the dir of a release.
+pub const RELEASE_FILE: &str = "release.json";
+
+/// file name of the archive in the dir of a release.
+pub const ARCHIVE_FILE: &str = "archive.tar.gz";
+
+/// file name of the asset list in the dir of a release.
+pub const ASSETS_FILE: &str = "assets.json";
+
+/// `ReleaseArchive` stand for the archive of a release, checked when it is stored.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ReleaseArchive {
+    /// file name to download the archive as, e.g. `wsvc-v1.0.tar.gz`.
+    pub name: String,
+    pub size: u64,
+    /// blake3 hash of the archive.
+    pub hash: ObjectId,
+}
+
+impl ReleaseArchive {
+    pub fn new(name: impl AsRef<str>, data: &[u8]) -> Self {
+        Self {
+            name: name.as_ref().to_owned(),
+            size: data.len() as u64,
+            hash: ObjectId(blake3::hash(data)),
+        }
+    }
+
+    fn check(&self, data: &[u8]) -> Result<(), WsvcError> {
+        if data.len() as u64 != self.size || blake3::hash(data) != self.hash.0 {
+            return Err(WsvcError::DataError(format!(
+                "archive {} does not match the release",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// `Release` stand for a tagged record with its notes and archive.
+///
+/// releases are stored in `releases/<version>` of the repository, the manifest in
+/// `release.json` and the archive in `archive.tar.gz`, both locally and on servers.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Release {
+    /// the version, also the name of the tag of the record.
+    pub version: String,
+    pub record: ObjectId,
+    pub author: String,
+    #[serde(deserialize_with = "from_ts", serialize_with = "to_ts")]
+    pub date: DateTime<Utc>,
+    /// message of the annotated tag.
+    pub message: String,
+    /// release notes in markdown, e.g. the changelog since the previous release.
+    pub notes: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<ReleaseArchive>,
+}
+
+impl Release {
+    /// the annotation of the release tag.
+    pub fn annotation(&self) -> TagAnnotation {
+        TagAnnotation {
+            author: self.author.clone(),
+            date: self.date,
+            message: self.message.clone(),
+        }
+    }
+}
+
+/// `ReleaseAsset` stand for a binary attached to a tag, e.g. a build of the record.
+///
+/// the content is stored as a blob in the objects dir, listed in `releases/<tag>/assets.json`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ReleaseAsset {
+    pub name: String,
+    pub size: u64,
+    /// hash of the blob storing the asset.
+    pub hash: ObjectId,
+    /// media type given on upload, e.g. `application/zip`.
+    pub content_type: String,
+    #[serde(deserialize_with = "from_ts", serialize_with = "to_ts")]
+    pub date: DateTime<Utc>,
+}
+
+/// check whether `name` can name an asset, the same rules as tag names.
+pub fn check_asset_name(name: &str) -> Result<(), WsvcError> {
+    check_tag_name(name).map_err(|_| WsvcError::BadUsage(format!("invalid asset name: {}", name)))
+}
+
+/// read an asset list, empty if it does not exist.
+async fn read_assets(path: &Path) -> Result<Vec<ReleaseAsset>, WsvcFsError> {
+    if !path.is_file() {
+        return Ok(vec![]);
+    }
+    Ok(serde_json::from_slice(&read(path).await?)?)
+}
+
+impl Repository {
+    /// get the releases folder of the repository.
+    pub async fn releases_dir(&self) -> Result<PathBuf, WsvcFsError> {
+        let result = self.path.join("releases");
+        if !result.exists() && !self.options.read_only {
+            create_dir_all(&result).await?;
+        }
+        Ok(result)
+    }
+
+    /// tag the record of `release` with an annotated tag named by its version, and store
+    /// the release with its archive if given.
+    ///
+    /// publishing again is fine while the tag points to the same record, another record is
+    /// only tagged if `force` is set.
+    pub async fn publish_release(
+        &self,
+        release: &Release,
+        archive: Option<&[u8]>,
+        force: bool,
+    ) -> Result<(), WsvcError> {
+        self.check_writable()?;
+        check_tag_name(&release.version)?;
+        if let (Some(expected), Some(data)) = (&release.archive, archive) {
+            expected.check(data)?;
+        }
+        let same_record = self
+            .read_tag(&release.version)
+            .await?
+            .is_some_and(|tag| tag.record == release.record);
+        self.create_annotated_tag(
+            &release.version,
+            &release.record,
+            force || same_record,
+            &release.annotation(),
+        )
+        .await?;
+        let dir = self.releases_dir().await?.join(&release.version);
+        create_dir_all(&dir).await.map_err(WsvcFsError::Os)?;
+        write_atomic(&dir.join(RELEASE_FILE), &serde_json::to_vec(release)?).await?;
+        if let Some(data) = archive {
+            write_atomic(&dir.join(ARCHIVE_FILE), data).await?;
+        }
+        Ok(())
+    }
+
+    /// store the archive of a published release, it must match the release manifest.
+    pub async fn store_release_archive(
+        &self,
+        version: impl AsRef<str>,
+        data: &[u8],
+    ) -> Result<(), WsvcError> {
+        self.check_writable()?;
+        let version = version.as_ref();
+        let release = self
+            .read_release(version)
+            .await?
+            .ok_or(WsvcError::BadUsage(format!("no release {}", version)))?;
+        match &release.archive {
+            Some(expected) => expected.check(data)?,
+            None => {
+                return Err(WsvcError::BadUsage(format!(
+                    "release {} has no archive",
+                    version
+                )))
+            }
+        }
+        let path = self.releases_dir().await?.join(version).join(ARCHIVE_FILE);
+        write_atomic(&path, data).await?;
+        Ok(())
+    }
+
+    /// read a release by version, `None` if not exists.
+    pub async fn read_release(
+        &self,
+        version: impl AsRef<str>,
+    ) -> Result<Option<Release>, WsvcError> {
+        check_tag_name(version.as_ref())?;
+        let path = self
+            .releases_dir()
+            .await?
+            .join(version.as_ref())
+            .join(RELEASE_FILE);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(
+            &read(path).await.map_err(WsvcFsError::Os)?,
+        )?))
+    }
+
+    /// read the archive of a release, `None` if it is not stored.
+    pub async fn read_release_archive(
+        &self,
+        version: impl AsRef<str>,
+    ) -> Result<Option<Vec<u8>>, WsvcError> {
+        check_tag_name(version.as_ref())?;
+        let path = self
+            .releases_dir()
+            .await?
+            .join(version.as_ref())
+            .join(ARCHIVE_FILE);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(read(path).await.map_err(WsvcFsError::Os)?))
+    }
+
+    /// get all releases, newest first.
+    pub async fn get_releases(&self) -> Result<Vec<Release>, WsvcError> {
+        let mut result = vec![];
+        let dir = self.releases_dir().await?;
+        if !dir.exists() {
+            return Ok(result);
+        }
+        let mut entries = read_dir(dir).await.map_err(WsvcFsError::Os)?;
+        while let Some(entry) = entries.next_entry().await.map_err(WsvcFsError::Os)? {
+            let path = entry.path().join(RELEASE_FILE);
+            if path.is_file() {
+                result.push(serde_json::from_slice::<Release>(
+                    &read(path).await.map_err(WsvcFsError::Os)?,
+                )?);
+            }
+        }
+        result.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| b.version.cmp(&a.version)));
+        Ok(result)
+    }
+
+    /// attach `data` to tag `tag` as asset `name`, replacing an asset of the same name.
+    ///
+    /// returns the stored asset and the replaced one.
+    pub async fn store_release_asset(
+        &self,
+        tag: impl AsRef<str>,
+        name: impl AsRef<str>,
+        content_type: impl AsRef<str>,
+        data: &[u8],
+    ) -> Result<(ReleaseAsset, Option<ReleaseAsset>), WsvcError> {
+        self.check_writable()?;
+        let (tag, name) = (tag.as_ref(), name.as_ref());
+        check_asset_name(name)?;
+        if self.read_tag(tag).await?.is_none() {
+            return Err(WsvcError::BadUsage(format!("no tag {}", tag)));
+        }
+        let asset = ReleaseAsset {
+            name: name.to_owned(),
+            size: data.len() as u64,
+            hash: self.store_blob_data(data).await?,
+            content_type: content_type.as_ref().to_owned(),
+            date: Utc::now(),
+        };
+        let dir = self.releases_dir().await?.join(tag);
+        create_dir_all(&dir).await.map_err(WsvcFsError::Os)?;
+        let path = dir.join(ASSETS_FILE);
+        let mut assets = read_assets(&path).await?;
+        let replaced = assets
+            .iter()
+            .position(|a| a.name == name)
+            .map(|i| assets.remove(i));
+        assets.push(asset.clone());
+        assets.sort_by(|a, b| a.name.cmp(&b.name));
+        write_atomic(&path, &serde_json::to_vec(&assets)?).await?;
+        Ok((asset, replaced))
+    }
+
+    /// get assets attached to tag `tag`, sorted by name.
+    pub async fn get_release_assets(
+        &self,
+        tag: impl AsRef<str>,
+    ) -> Result<Vec<ReleaseAsset>, WsvcError> {
+        check_tag_name(tag.as_ref())?;
+        let path = self
+            .releases_dir()
+            .await?
+            .join(tag.as_ref())
+            .join(ASSETS_FILE);
+        Ok(read_assets(&path).await?)
+    }
+
+    /// read an asset of tag `tag` with its content, `None` if not exists.
+    pub async fn read_release_asset(
+        &self,
+        tag: impl AsRef<str>,
+        name: impl AsRef<str>,
+    ) -> Result<Option<(ReleaseAsset, Vec<u8>)>, WsvcError> {
+        let asset = self
+            .get_release_assets(tag)
+            .await?
+            .into_iter()
+            .find(|a| a.name == name.as_ref());
+        match asset {
+            Some(asset) => {
+                let data = self.read_blob(&asset.hash).await?;
+                Ok(Some((asset, data)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// detach an asset from tag `tag`, returns it if it existed. its blob is dropped by
+    /// `prune_unreachable` unless another asset has the same content.
+    pub async fn remove_release_asset(
+        &self,
+        tag: impl AsRef<str>,
+        name: impl AsRef<str>,
+    ) -> Result<Option<ReleaseAsset>, WsvcError> {
+        self.check_writable()?;
+        check_tag_name(tag.as_ref())?;
+        let path = self
+            .releases_dir()
+            .await?
+            .join(tag.as_ref())
+            .join(ASSETS_FILE);
+        let mut assets = read_assets(&path).await?;
+        let removed = match assets.iter().position(|a| a.name == name.as_ref()) {
+            Some(i) => assets.remove(i),
+            None => return Ok(None),
+        };
+        write_atomic(&path, &serde_json::to_vec(&assets)?).await?;
+        Ok(Some(removed))
+    }
+
+    /// assets of all tags.
+    async fn all_release_assets(&self) -> Result<Vec<ReleaseAsset>, WsvcFsError> {
+        let mut result = vec![];
+        let dir = self.releases_dir().await?;
+        if !dir.exists() {
+            return Ok(result);
+        }
+        let mut entries = read_dir(dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            result.extend(read_assets(&entry.path().join(ASSETS_FILE)).await?);
+        }
+        Ok(result)
+    }
+
+    /// total size of assets of all tags, e.g. for quotas.
+    pub async fn release_assets_size(&self) -> Result<u64, WsvcFsError> {
+        Ok(self
+            .all_release_assets()
+            .await?
+            .iter()
+            .map(|a| a.size)
+            .sum())
+    }
+
+    /// blobs of assets, kept by `prune_unreachable`.
+    pub(crate) async fn release_asset_blobs(&self) -> Result<Vec<ObjectId>, WsvcFsError> {
+        Ok(self
+            .all_release_assets()
+            .await?
+            .into_iter()
+            .map(|a| a.hash)
+            .collect())
+    }
+}
+
+impl ReadOnlyRepository {
+    pub async fn read_release(
+        &self,
+        version: impl AsRef<str>,
+    ) -> Result<Option<Release>, WsvcError> {
+        self.repository().read_release(version).await
+    }
+
+    pub async fn read_release_archive(
+        &self,
+        version: impl AsRef<str>,
+    ) -> Result<Option<Vec<u8>>, WsvcError> {
+        self.repository().read_release_archive(version).await
+    }
+
+    pub async fn get_releases(&self) -> Result<Vec<Release>, WsvcError> {
+        self.repository().get_releases().await
+    }
+
+    pub async fn get_release_assets(
+        &self,
+        tag: impl AsRef<str>,
+    ) -> Result<Vec<ReleaseAsset>, WsvcError> {
+        self.repository().get_release_assets(tag).await
+    }
+
+    pub async fn read_release_asset(
+        &self,
+        tag: impl AsRef<str>,
+        name: impl AsRef<str>,
+    ) -> Result<Option<(ReleaseAsset, Vec<u8>)>, WsvcError> {
+        self.repository().read_release_asset(tag, name).await
+    }
+
+    pub async fn release_assets_size(&self) -> Result<u64, WsvcFsError> {
+        self.repository().release_assets_size().await
+    }
+}
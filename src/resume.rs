@@ -0,0 +1,187 @@
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{create_dir_all, read_dir, remove_file, rename, File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+
+use crate::{fs::WsvcFsError, model::Repository};
+
+/// first version of the sync wire protocol resuming files, see `PartialFile`.
+pub const RESUME_PROTOCOL: u32 = 3;
+
+/// leading bytes of the frame following a file header since protocol version 3, followed
+/// by the 8 bytes offset the sender continues the file from.
+pub const RESUME_MAGIC: [u8; 2] = [0x07, 0x16];
+
+/// dir of a repository keeping files a failed sync received part of, out of the temp dir
+/// which is removed when the repository is unlocked.
+const PARTIAL_DIR: &str = "partial";
+
+/// partial files are removed after this long, their sender has likely moved on.
+const PARTIAL_FILE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// `PartialFile` stand for a file a receiver already has the leading bytes of, e.g. left by
+/// a sync that died mid-way. receivers send them before a file round, and senders continue
+/// each file from its offset if their file starts with the same bytes.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct PartialFile {
+    pub name: String,
+    /// bytes the receiver has.
+    pub offset: u64,
+    /// blake3 hex of the bytes the receiver has.
+    pub hash: String,
+}
+
+/// the blake3 of the first `len` bytes of the file at `path`, `None` if it is shorter.
+async fn prefix_hash(path: &Path, len: u64) -> Result<Option<blake3::Hash>, WsvcFsError> {
+    let file = File::open(path).await?;
+    if file.metadata().await?.len() < len {
+        return Ok(None);
+    }
+    let mut reader = file.take(len);
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(Some(hasher.finalize()))
+}
+
+/// the partial files of `dir` among `names`, the files the receiver is about to get.
+pub async fn partial_files(dir: &Path, names: &[String]) -> Result<Vec<PartialFile>, WsvcFsError> {
+    let mut partials = vec![];
+    for name in names {
+        let path = dir.join(name);
+        let offset = match tokio::fs::metadata(&path).await {
+            Ok(meta) if meta.is_file() && meta.len() > 0 => meta.len(),
+            _ => continue,
+        };
+        if let Some(hash) = prefix_hash(&path, offset).await? {
+            partials.push(PartialFile {
+                name: name.clone(),
+                offset,
+                hash: hash.to_hex().to_string(),
+            });
+        }
+    }
+    Ok(partials)
+}
+
+/// the offset the file at `path` is sent from to a receiver holding `partial` of it, 0 if
+/// the receiver has none or the leading bytes differ.
+pub async fn resume_offset(path: &Path, partial: Option<&PartialFile>) -> Result<u64, WsvcFsError> {
+    let Some(partial) = partial else {
+        return Ok(0);
+    };
+    let Ok(expected) = blake3::Hash::from_hex(&partial.hash) else {
+        return Ok(0);
+    };
+    match prefix_hash(path, partial.offset).await? {
+        Some(hash) if hash == expected => Ok(partial.offset),
+        _ => Ok(0),
+    }
+}
+
+/// encode the resume frame of a file continued from `offset`.
+pub fn encode_resume_frame(offset: u64) -> Vec<u8> {
+    let mut frame = RESUME_MAGIC.to_vec();
+    frame.extend_from_slice(&offset.to_be_bytes());
+    frame
+}
+
+/// decode a frame encoded by `encode_resume_frame`, `None` if it is not one.
+pub fn decode_resume_frame(frame: &[u8]) -> Option<u64> {
+    let rest = frame.strip_prefix(&RESUME_MAGIC)?;
+    let (offset, _) = rest.split_first_chunk::<8>()?;
+    Some(u64::from_be_bytes(*offset))
+}
+
+/// open the file at `path` being received from `offset`, keeping its leading bytes, or
+/// created anew from 0.
+pub async fn open_received(path: &Path, offset: u64) -> Result<File, WsvcFsError> {
+    if offset == 0 {
+        return Ok(File::create(path).await?);
+    }
+    let mut file = OpenOptions::new().write(true).open(path).await?;
+    if file.metadata().await?.len() < offset {
+        return Err(WsvcFsError::Os(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            format!("partial file {:?} is shorter than {}", path, offset),
+        )));
+    }
+    file.set_len(offset).await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+    Ok(file)
+}
+
+impl Repository {
+    /// the dir keeping partial files of failed syncs, servers receive blobs right into it.
+    pub async fn partial_dir(&self) -> Result<PathBuf, WsvcFsError> {
+        let result = self.path.join(PARTIAL_DIR);
+        if !result.exists() && !self.options.read_only {
+            create_dir_all(&result).await?;
+        }
+        Ok(result)
+    }
+
+    /// remove partial files not continued for a long time.
+    pub async fn prune_partial_files(&self) -> Result<(), WsvcFsError> {
+        let dir = self.path.join(PARTIAL_DIR);
+        if !dir.exists() {
+            return Ok(());
+        }
+        let mut entries = read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let stale = entry
+                .metadata()
+                .await?
+                .modified()?
+                .elapsed()
+                .is_ok_and(|age| age > PARTIAL_FILE_TTL);
+            if stale {
+                remove_file(entry.path()).await.ok();
+            }
+        }
+        Ok(())
+    }
+
+    /// move the partial files among `names` into `dir`, the dir a sync receives into.
+    /// files are moved, so syncs running at once never share one.
+    pub async fn claim_partial_files(
+        &self,
+        dir: &Path,
+        names: &[String],
+    ) -> Result<(), WsvcFsError> {
+        let partial_dir = self.path.join(PARTIAL_DIR);
+        if !partial_dir.exists() {
+            return Ok(());
+        }
+        for name in names {
+            // another sync may have claimed it meanwhile.
+            rename(partial_dir.join(name), dir.join(name)).await.ok();
+        }
+        Ok(())
+    }
+
+    /// keep the files of `dir`, the dir a failed sync received into, for later syncs.
+    pub async fn keep_partial_files(&self, dir: &Path) -> Result<(), WsvcFsError> {
+        let partial_dir = self.partial_dir().await?;
+        let mut entries = read_dir(dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_file() {
+                rename(entry.path(), partial_dir.join(entry.file_name())).await?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    diff::Change,
+    model::{ObjectId, Record, Repository},
+    WsvcError,
+};
+
+/// `Revert` stand for a record undoing the changes of an earlier record.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Revert {
+    /// the reverted record.
+    pub reverted: ObjectId,
+    /// the new record.
+    pub record: Record,
+    /// changes written to the workspace, the inverse of the changes of the reverted record.
+    pub changes: Vec<Change>,
+}
+
+/// the default message of a record reverting `record`.
+pub fn revert_message(record: &Record) -> String {
+    format!(
+        "Revert \"{}\"\n\nThis reverts record {}.",
+        record.message.lines().next().unwrap_or_default(),
+        record.hash.0.to_hex()
+    )
+}
+
+impl Repository {
+    /// undo the changes `record_hash` made to its first parent in `workspace` and commit
+    /// them as a new record, `message` defaults to `revert_message`.
+    ///
+    /// the workspace must have no changes not recorded. fails without touching anything if
+    /// a file changed by the record was changed again by a later record.
+    pub async fn revert_record(
+        &self,
+        record_hash: &ObjectId,
+        workspace: &Path,
+        author: impl AsRef<str>,
+        message: Option<String>,
+    ) -> Result<Revert, WsvcError> {
+        self.check_writable()?;
+        let record = self.read_record(record_hash).await?;
+        if !self.status(workspace).await?.is_clean() {
+            return Err(WsvcError::BadUsage(
+                "workspace has changes not recorded, commit or checkout them first".to_owned(),
+            ));
+        }
+        let graph = self.record_graph().await?;
+        let parent = match graph.parents(&record.hash).first() {
+            Some(parent) => Some(self.read_record(parent).await?.root),
+            None => None,
+        };
+        let changes = self
+            .changes_of_trees(parent.as_ref(), Some(&record.root))
+            .await?
+            .iter()
+            .map(Change::inverse)
+            .collect::<Vec<_>>();
+        if changes.is_empty() {
+            return Err(WsvcError::BadUsage(format!(
+                "record {} has no changes to revert",
+                &record.hash.0.to_hex()[0..6]
+            )));
+        }
+        let conflicts = self.conflicting_changes(workspace, &changes).await;
+        if !conflicts.is_empty() {
+            return Err(WsvcError::RepoError(format!(
+                "files changed by later records can not be reverted: {}",
+                conflicts.join(", ")
+            )));
+        }
+        self.apply_changes(workspace, changes.clone()).await?;
+        let message = message.unwrap_or_else(|| revert_message(&record));
+        let new_record = self.commit_record(workspace, author, message, None).await?;
+        Ok(Revert {
+            reverted: record.hash,
+            record: new_record,
+            changes,
+        })
+    }
+}
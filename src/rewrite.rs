@@ -0,0 +1,200 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path},
+};
+
+use blake3::Hash;
+
+use crate::{
+    branch::branch_ref,
+    fs::WsvcFsError,
+    mailmap::Mailmap,
+    model::{Entry, EntryKind, ObjectId, Record, Repository, Tree},
+    walk::WalkOrder,
+};
+
+/// Store `record` with a recalculated hash and remove the old one.
+async fn replace_record(repo: &Repository, record: Record) -> Result<Record, WsvcFsError> {
+    let old_hash = record.hash.clone();
+    let new_record = Record {
+        hash: ObjectId(Hash::from([0; 32])),
+        ..record
+    };
+    let hash = blake3::hash(serde_json::to_vec(&new_record)?.as_slice());
+    let new_record = Record {
+        hash: ObjectId(hash),
+        ..new_record
+    };
+    repo.store_record(&new_record).await?;
+    repo.remove_record(&old_hash).await?;
+    Ok(new_record)
+}
+
+/// Parents of `record` after rewriting the records in `mapping`.
+///
+/// records are rewritten parents first, so parents are already in `mapping`.
+fn remap_parents(record: &Record, mapping: &HashMap<ObjectId, ObjectId>) -> Option<Vec<ObjectId>> {
+    record.parents.as_ref().map(|parents| {
+        parents
+            .iter()
+            .map(|p| mapping.get(p).unwrap_or(p).clone())
+            .collect()
+    })
+}
+
+/// Move HEAD and branches along the rewritten records.
+async fn remap_head(
+    repo: &Repository,
+    head: Option<ObjectId>,
+    mapping: &HashMap<ObjectId, ObjectId>,
+) -> Result<(), WsvcFsError> {
+    // HEAD on a branch moves with the branch.
+    if repo.read_head().await?.branch().is_none() {
+        if let Some(head) = head {
+            if let Some(new_head) = mapping.get(&head) {
+                repo.transaction()
+                    .compare_and_set("HEAD", Some(&head), new_head)
+                    .commit()
+                    .await?;
+            }
+        }
+    }
+    for branch in repo.get_branches().await? {
+        if let Some(new_record) = mapping.get(&branch.record) {
+            repo.transaction()
+                .compare_and_set(branch_ref(&branch.name), Some(&branch.record), new_record)
+                .commit()
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Rewrite a tree without the entry at `components`.
+///
+/// returns `None` if nothing was removed under this tree.
+#[async_recursion::async_recursion(?Send)]
+async fn remove_path_from_tree(
+    repo: &Repository,
+    tree: &Tree,
+    components: &[String],
+) -> Result<Option<Tree>, WsvcFsError> {
+    let (first, rest) = match components.split_first() {
+        Some(split) => split,
+        None => return Ok(None),
+    };
+    let mut result = tree.clone();
+    let mut changed = false;
+    result.entries.clear();
+    for entry in &tree.entries {
+        if &entry.name != first {
+            result.entries.push(entry.clone());
+        } else if rest.is_empty() {
+            changed = true;
+        } else if entry.kind != EntryKind::Dir {
+            result.entries.push(entry.clone());
+        } else if let Some(sub_tree) =
+            remove_path_from_tree(repo, &repo.read_tree(&entry.hash).await?, rest).await?
+        {
+            result.entries.push(Entry {
+                hash: sub_tree.hash,
+                ..entry.clone()
+            });
+            changed = true;
+        } else {
+            result.entries.push(entry.clone());
+        }
+    }
+    if !changed {
+        return Ok(None);
+    }
+    Ok(Some(repo.store_tree(&result).await?))
+}
+
+impl Repository {
+    /// rewrite all records to drop the file or dir at `path`.
+    ///
+    /// records whose tree or parents changed are stored with a new hash and the old ones
+    /// are removed, HEAD and branches are moved to the rewritten records. call `prune_unreachable` afterwards to drop
+    /// the removed blobs.
+    ///
+    /// returns the `(old, new)` hash mapping of rewritten records.
+    pub async fn remove_path_from_history(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<Vec<(ObjectId, ObjectId)>, WsvcFsError> {
+        let components = path
+            .as_ref()
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(
+                    name.to_str()
+                        .map(|name| name.to_string())
+                        .ok_or(WsvcFsError::InvalidOsString(format!("{:?}", name))),
+                ),
+                _ => None,
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if components.is_empty() {
+            return Err(WsvcFsError::UnknownPath(format!("{:?}", path.as_ref())));
+        }
+        let head = self.get_head_record().await?.map(|r| r.hash);
+        let mut mapping = HashMap::new();
+        let mut result = Vec::new();
+        for record in self.walk(None, WalkOrder::Reverse).await? {
+            let root = self.read_tree(&record.root).await?;
+            let new_root = remove_path_from_tree(self, &root, &components).await?;
+            let parents = remap_parents(&record, &mapping);
+            if new_root.is_none() && parents == record.parents {
+                continue;
+            }
+            let old_hash = record.hash.clone();
+            let new_record = replace_record(
+                self,
+                Record {
+                    root: new_root.map(|t| t.hash).unwrap_or(record.root),
+                    parents,
+                    ..record
+                },
+            )
+            .await?;
+            mapping.insert(old_hash.clone(), new_record.hash.clone());
+            result.push((old_hash, new_record.hash));
+        }
+        remap_head(self, head, &mapping).await?;
+        Ok(result)
+    }
+
+    /// rewrite all records to use the canonical authors of `mailmap`.
+    ///
+    /// returns the `(old, new)` hash mapping of rewritten records.
+    pub async fn rewrite_authors(
+        &self,
+        mailmap: &Mailmap,
+    ) -> Result<Vec<(ObjectId, ObjectId)>, WsvcFsError> {
+        let head = self.get_head_record().await?.map(|r| r.hash);
+        let mut mapping = HashMap::new();
+        let mut result = Vec::new();
+        for record in self.walk(None, WalkOrder::Reverse).await? {
+            let author = mailmap.resolve(&record.author).to_string();
+            let parents = remap_parents(&record, &mapping);
+            if author == record.author && parents == record.parents {
+                continue;
+            }
+            let old_hash = record.hash.clone();
+            let new_record = replace_record(
+                self,
+                Record {
+                    author,
+                    parents,
+                    ..record
+                },
+            )
+            .await?;
+            mapping.insert(old_hash.clone(), new_record.hash.clone());
+            result.push((old_hash, new_record.hash));
+        }
+        remap_head(self, head, &mapping).await?;
+        Ok(result)
+    }
+}
@@ -0,0 +1,382 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{
+    auth::{LoginRequest, LoginResponse, SyncCredential},
+    fs::WsvcFsError,
+    model::Repository,
+    refs::write_atomic,
+    server::visibility::Access,
+    WsvcError,
+};
+
+/// prefix of tokens issued by `TokenStore`, telling them apart from passwords.
+pub const TOKEN_PREFIX: &str = "wsvc_";
+
+/// `CredentialVerifier` decides whether a client may sync a repository, given to
+/// `sync_with_auth` or `sync_with_access` by servers guarding repositories, e.g. backed by
+/// an account database or ldap.
+#[async_trait]
+pub trait CredentialVerifier: Send + Sync {
+    /// whether `credential` may sync `repo`, errors of the backend fail the sync.
+    async fn verify(
+        &self,
+        repo: &Repository,
+        credential: &SyncCredential,
+    ) -> Result<bool, WsvcError>;
+
+    /// what an accepted `credential` may do with `repo` by itself, e.g. by the scope of a
+    /// deploy token, whatever the access of its account. `None` if the access of the
+    /// account decides, as for passwords.
+    async fn grant(
+        &self,
+        _repo: &Repository,
+        _credential: &SyncCredential,
+    ) -> Result<Option<Access>, WsvcError> {
+        Ok(None)
+    }
+}
+
+/// `StaticAccounts` verifies credentials against a fixed table of accounts, e.g. of a
+/// small self-hosted server. passwords are kept as salted bcrypt hashes, e.g. made by
+/// `StaticAccounts::hash_password` or `htpasswd -nbB <account> <password>`, entries of
+/// other hashes never match.
+///
+/// ```toml
+/// [accounts]
+/// alice = "$2y$05$hMPbQa3BjhoqOp41vxnzbe3aRU6pXXZ.JXUJ6ODDrvbBkkY.AA2Yq"
+/// ```
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StaticAccounts {
+    /// account to the bcrypt hash of its password.
+    #[serde(default)]
+    pub accounts: HashMap<String, String>,
+}
+
+/// the blake3 hex of a token issued by a server, kept instead of the token. tokens are
+/// long and random, so they need no salt, unlike passwords.
+pub fn token_hash(token: &str) -> String {
+    blake3::hash(token.as_bytes()).to_hex().to_string()
+}
+
+impl StaticAccounts {
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self, WsvcError> {
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    /// the bcrypt hash of `password` with a random salt, to keep in `accounts`.
+    pub fn hash_password(password: &str) -> Result<String, WsvcFsError> {
+        bcrypt::hash(password, bcrypt::DEFAULT_COST)
+            .map_err(|err| WsvcFsError::Os(std::io::Error::other(err)))
+    }
+}
+
+#[async_trait]
+impl CredentialVerifier for StaticAccounts {
+    async fn verify(
+        &self,
+        _repo: &Repository,
+        credential: &SyncCredential,
+    ) -> Result<bool, WsvcError> {
+        let Some(hash) = self.accounts.get(&credential.account).cloned() else {
+            return Ok(false);
+        };
+        if !hash.starts_with("$2") {
+            return Ok(false);
+        }
+        // bcrypt takes a while by design, out of the runtime.
+        let password = credential.password.clone();
+        tokio::task::spawn_blocking(move || bcrypt::verify(password, &hash).unwrap_or(false))
+            .await
+            .map_err(|err| WsvcFsError::Os(std::io::Error::other(err)).into())
+    }
+}
+
+/// `HtpasswdAccounts` verifies credentials against an htpasswd file of apache, e.g. made by
+/// `htpasswd -B`, so a server can share the accounts of an existing web server. bcrypt,
+/// apr1 md5 and `{SHA}` hashes are supported, lines with other hashes never match.
+///
+/// ```text
+/// alice:$2y$05$hMPbQa3BjhoqOp41vxnzbe3aRU6pXXZ.JXUJ6ODDrvbBkkY.AA2Yq
+/// bob:$apr1$Ce7hv1Cm$c6I/AvQqm/3pCE1DDRO/L1
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct HtpasswdAccounts {
+    /// account to the hash of its password.
+    pub accounts: HashMap<String, String>,
+}
+
+/// chars of the base64 of crypt hashes.
+const CRYPT_BASE64: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// the md5 crypt of `password` with `salt`, `$apr1$` hashes of apache or `$1$` hashes of
+/// unix crypt by `magic`.
+fn md5_crypt(password: &[u8], salt: &[u8], magic: &str) -> String {
+    use md5::{Digest, Md5};
+    let alternate = Md5::new()
+        .chain_update(password)
+        .chain_update(salt)
+        .chain_update(password)
+        .finalize();
+    let mut digest = Md5::new()
+        .chain_update(password)
+        .chain_update(magic)
+        .chain_update(salt);
+    for chunk in (0..password.len()).step_by(16) {
+        digest.update(&alternate[..(password.len() - chunk).min(16)]);
+    }
+    let mut bits = password.len();
+    while bits > 0 {
+        match bits & 1 {
+            1 => digest.update([0u8]),
+            _ => digest.update(&password[..1]),
+        }
+        bits >>= 1;
+    }
+    let mut hash = digest.finalize();
+    for round in 0..1000 {
+        let mut digest = Md5::new();
+        match round & 1 {
+            1 => digest.update(password),
+            _ => digest.update(hash),
+        }
+        if round % 3 != 0 {
+            digest.update(salt);
+        }
+        if round % 7 != 0 {
+            digest.update(password);
+        }
+        match round & 1 {
+            1 => digest.update(hash),
+            _ => digest.update(password),
+        }
+        hash = digest.finalize();
+    }
+    let mut encoded = String::new();
+    let mut push = |value: u32, chars: usize| {
+        for i in 0..chars {
+            encoded.push(CRYPT_BASE64[(value >> (6 * i) & 0x3f) as usize] as char);
+        }
+    };
+    for (a, b, c) in [(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+        push(
+            (hash[a] as u32) << 16 | (hash[b] as u32) << 8 | hash[c] as u32,
+            4,
+        );
+    }
+    push(hash[11] as u32, 2);
+    format!("{}{}${}", magic, String::from_utf8_lossy(salt), encoded)
+}
+
+/// whether `password` matches `hash` of an htpasswd line.
+pub fn verify_htpasswd(password: &str, hash: &str) -> bool {
+    use base64::Engine;
+    use sha1::{Digest, Sha1};
+    // comparing hashes takes constant time.
+    let same = |a: &str, b: &str| blake3::hash(a.as_bytes()) == blake3::hash(b.as_bytes());
+    if hash.starts_with("$2") {
+        return bcrypt::verify(password, hash).unwrap_or(false);
+    }
+    for magic in ["$apr1$", "$1$"] {
+        if let Some(rest) = hash.strip_prefix(magic) {
+            let Some((salt, _)) = rest.split_once('$') else {
+                return false;
+            };
+            let salt = &salt.as_bytes()[..salt.len().min(8)];
+            return same(&md5_crypt(password.as_bytes(), salt, magic), hash);
+        }
+    }
+    if let Some(digest) = hash.strip_prefix("{SHA}") {
+        let expected =
+            base64::engine::general_purpose::STANDARD.encode(Sha1::digest(password.as_bytes()));
+        return same(&expected, digest);
+    }
+    false
+}
+
+impl HtpasswdAccounts {
+    /// parse the lines `account:hash` of an htpasswd file, skipping blank and `#` lines.
+    pub fn parse(content: &str) -> Self {
+        let accounts = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once(':'))
+            .map(|(account, hash)| (account.to_owned(), hash.to_owned()))
+            .collect();
+        Self { accounts }
+    }
+
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self, WsvcError> {
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        Ok(Self::parse(&content))
+    }
+}
+
+#[async_trait]
+impl CredentialVerifier for HtpasswdAccounts {
+    async fn verify(
+        &self,
+        _repo: &Repository,
+        credential: &SyncCredential,
+    ) -> Result<bool, WsvcError> {
+        let Some(hash) = self.accounts.get(&credential.account).cloned() else {
+            return Ok(false);
+        };
+        // bcrypt takes a while by design, out of the runtime.
+        let password = credential.password.clone();
+        tokio::task::spawn_blocking(move || verify_htpasswd(&password, &hash))
+            .await
+            .map_err(|err| WsvcFsError::Os(std::io::Error::other(err)).into())
+    }
+}
+
+/// `IssuedToken` stand for a token issued to an account, only its hash is kept.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct IssuedToken {
+    pub account: String,
+    /// blake3 hex of the token.
+    pub hash: String,
+    pub created: DateTime<Utc>,
+}
+
+/// `TokenStore` keeps the tokens issued by the login endpoint of a server in a json file,
+/// e.g. `tokens.json` in the dir of a root of hosted repositories.
+pub struct TokenStore {
+    path: PathBuf,
+    /// serializes updates of the file by concurrent logins.
+    lock: Mutex<()>,
+}
+
+impl TokenStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// tokens issued so far.
+    pub async fn tokens(&self) -> Result<Vec<IssuedToken>, WsvcError> {
+        match tokio::fs::read(&self.path).await {
+            Ok(content) => Ok(serde_json::from_slice(&content)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+            Err(err) => Err(WsvcFsError::Os(err).into()),
+        }
+    }
+
+    /// issue a new token to `account`.
+    pub async fn issue(&self, account: &str) -> Result<String, WsvcError> {
+        let token = format!("{}{}", TOKEN_PREFIX, nanoid::nanoid!(40));
+        let _lock = self.lock.lock().await;
+        let mut tokens = self.tokens().await?;
+        tokens.push(IssuedToken {
+            account: account.to_owned(),
+            hash: token_hash(&token),
+            created: Utc::now(),
+        });
+        write_atomic(&self.path, &serde_json::to_vec_pretty(&tokens)?).await?;
+        Ok(token)
+    }
+
+    /// revoke all tokens of `account`, returns how many there were.
+    pub async fn revoke(&self, account: &str) -> Result<usize, WsvcError> {
+        let _lock = self.lock.lock().await;
+        let mut tokens = self.tokens().await?;
+        let count = tokens.len();
+        tokens.retain(|token| token.account != account);
+        let revoked = count - tokens.len();
+        if revoked > 0 {
+            write_atomic(&self.path, &serde_json::to_vec_pretty(&tokens)?).await?;
+        }
+        Ok(revoked)
+    }
+
+    /// whether the password of `credential` is a token issued to its account.
+    pub async fn verify(&self, credential: &SyncCredential) -> Result<bool, WsvcError> {
+        if !credential.password.starts_with(TOKEN_PREFIX) {
+            return Ok(false);
+        }
+        let hash = blake3::hash(credential.password.as_bytes());
+        Ok(self.tokens().await?.iter().any(|token| {
+            token.account == credential.account
+                && blake3::Hash::from_hex(&token.hash).is_ok_and(|issued| issued == hash)
+        }))
+    }
+}
+
+/// `TokenVerifier` accepts the tokens of `tokens` besides the credentials `inner` accepts,
+/// and issues tokens to clients logging in with a credential `inner` accepts.
+///
+/// ```ignore
+/// async fn login(
+///     Path(name): Path<String>,
+///     Json(request): Json<LoginRequest>,
+/// ) -> Result<Json<LoginResponse>, StatusCode> {
+///     let repo = repo_of(&name).await.ok_or(StatusCode::NOT_FOUND)?;
+///     match VERIFIER.login(&repo, &request).await {
+///         Ok(Some(response)) => Ok(Json(response)),
+///         Ok(None) => Err(StatusCode::UNAUTHORIZED),
+///         Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+///     }
+/// }
+///
+/// Router::new().route("/repos/:name/login", post(login))
+/// ```
+pub struct TokenVerifier<V> {
+    pub tokens: TokenStore,
+    pub inner: V,
+}
+
+impl<V: CredentialVerifier> TokenVerifier<V> {
+    /// a token for the account of `request` if `inner` accepts its password, `None` if not.
+    /// a token does not log in for another one.
+    pub async fn login(
+        &self,
+        repo: &Repository,
+        request: &LoginRequest,
+    ) -> Result<Option<LoginResponse>, WsvcError> {
+        let credential = SyncCredential {
+            account: request.account.clone(),
+            password: request.password.clone(),
+        };
+        if !self.inner.verify(repo, &credential).await? {
+            return Ok(None);
+        }
+        Ok(Some(LoginResponse {
+            account: request.account.clone(),
+            token: self.tokens.issue(&request.account).await?,
+        }))
+    }
+}
+
+#[async_trait]
+impl<V: CredentialVerifier> CredentialVerifier for TokenVerifier<V> {
+    async fn verify(
+        &self,
+        repo: &Repository,
+        credential: &SyncCredential,
+    ) -> Result<bool, WsvcError> {
+        Ok(self.tokens.verify(credential).await? || self.inner.verify(repo, credential).await?)
+    }
+
+    async fn grant(
+        &self,
+        repo: &Repository,
+        credential: &SyncCredential,
+    ) -> Result<Option<Access>, WsvcError> {
+        self.inner.grant(repo, credential).await
+    }
+}
@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    binary::{is_text, sniff_mime, SNIFF_LEN},
+    model::{ObjectId, Repository},
+    WsvcError,
+};
+
+/// mime types of blobs a web ui may render inline unless a root sets `inline`. html and
+/// svg may run scripts in the origin of the server, so they are downloaded instead.
+pub const DEFAULT_INLINE_TYPES: &[&str] = &[
+    "text/plain",
+    "text/markdown",
+    "text/csv",
+    "application/json",
+    "image/png",
+    "image/gif",
+    "image/jpeg",
+    "image/webp",
+    "image/bmp",
+];
+
+/// `BlobInfo` stand for what a web ui needs to know to show a blob before downloading it,
+/// served by the `blobs/<hash>/meta` endpoint.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct BlobInfo {
+    pub hash: String,
+    pub size: u64,
+    /// mime type sniffed from the leading bytes, see `sniff_mime`.
+    pub mime: String,
+    /// whether the blob is not utf-8 text, see `is_text`.
+    pub binary: bool,
+    /// whether the mime type is allowed to be rendered inline, otherwise the blob is only
+    /// offered as a download.
+    pub inline: bool,
+}
+
+impl BlobInfo {
+    /// sniff the blob `hash`, named `name` in a tree if known, reading only its leading
+    /// bytes. `inline_types` are the mime types allowed to be rendered inline.
+    pub async fn load(
+        repo: &Repository,
+        hash: &ObjectId,
+        name: Option<&str>,
+        inline_types: &[String],
+    ) -> Result<Self, WsvcError> {
+        let size = repo.blob_size(hash).await?;
+        let head = repo
+            .read_blob_range(hash, 0, size.min(SNIFF_LEN as u64))
+            .await?;
+        let mime = sniff_mime(&head, name);
+        Ok(Self {
+            hash: hash.0.to_hex().to_string(),
+            size,
+            mime: mime.to_owned(),
+            binary: !is_text(&head),
+            inline: inline_types.iter().any(|allowed| allowed == mime),
+        })
+    }
+
+    /// the `Content-Type` the blob is served with, its mime type if it may be rendered
+    /// inline, so browsers never render other blobs.
+    pub fn content_type(&self) -> String {
+        match (self.inline, self.binary) {
+            (true, false) => format!("{}; charset=utf-8", self.mime),
+            (true, true) => self.mime.clone(),
+            (false, _) => "application/octet-stream".to_owned(),
+        }
+    }
+}
+
+/// `ByteRange` stand for the part of a blob asked by the `Range` header of a download.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteRange {
+    /// the whole blob, without a `Range` header or with one the server may ignore, e.g.
+    /// of many ranges or another unit.
+    Full,
+    /// bytes `start..end` of the blob, answered with `206 Partial Content`.
+    Partial { start: u64, end: u64 },
+    /// a range starting after the blob, answered with `416 Range Not Satisfiable`.
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// the range of `header`, the value of a `Range` header, of a blob of `size` bytes.
+    ///
+    /// single ranges of bytes are supported, `bytes=100-199`, `bytes=100-` and the last
+    /// bytes `bytes=-100`. an end after the blob is cut to it.
+    ///
+    /// ```ignore
+    /// async fn blob(
+    ///     Path((name, hash)): Path<(String, String)>,
+    ///     headers: HeaderMap,
+    /// ) -> Result<Response, StatusCode> {
+    ///     let repo = repo_of(&name).await.ok_or(StatusCode::NOT_FOUND)?;
+    ///     let hash = ObjectId::try_from(hash.as_str()).map_err(|_| StatusCode::BAD_REQUEST)?;
+    ///     let size = repo.blob_size(&hash).await.map_err(|_| StatusCode::NOT_FOUND)?;
+    ///     let range = headers.get(header::RANGE).and_then(|v| v.to_str().ok());
+    ///     match ByteRange::parse(range, size) {
+    ///         ByteRange::Partial { start, end } => { /* 206 of read_blob_range */ }
+    ///         ...
+    ///     }
+    /// }
+    /// ```
+    pub fn parse(header: Option<&str>, size: u64) -> Self {
+        let Some(spec) = header.and_then(|h| h.trim().strip_prefix("bytes=")) else {
+            return ByteRange::Full;
+        };
+        if spec.contains(',') {
+            return ByteRange::Full;
+        }
+        let Some((first, last)) = spec.trim().split_once('-') else {
+            return ByteRange::Full;
+        };
+        let (first, last) = (first.trim(), last.trim());
+        let (start, end) = match (first.parse::<u64>(), last.parse::<u64>()) {
+            // the last bytes.
+            _ if first.is_empty() => match last.parse::<u64>() {
+                Ok(0) => return ByteRange::Unsatisfiable,
+                Ok(suffix) => (size.saturating_sub(suffix), size),
+                Err(_) => return ByteRange::Full,
+            },
+            (Ok(start), _) if last.is_empty() => (start, size),
+            (Ok(start), Ok(last)) if last >= start => (start, last.saturating_add(1).min(size)),
+            _ => return ByteRange::Full,
+        };
+        if start >= size {
+            return ByteRange::Unsatisfiable;
+        }
+        ByteRange::Partial { start, end }
+    }
+}
@@ -0,0 +1,219 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{
+    auth::SyncCredential, fs::WsvcFsError, ignore::glob_match, model::Repository,
+    refs::write_atomic, WsvcError,
+};
+
+use super::{
+    auth::{token_hash, CredentialVerifier},
+    visibility::Access,
+};
+
+/// prefix of deploy tokens, telling them apart from passwords and login tokens.
+pub const DEPLOY_TOKEN_PREFIX: &str = "wsvcd_";
+
+/// file of the deploy tokens of a root in the dir of the root, hidden from repository names.
+pub const DEPLOY_TOKENS_FILE: &str = ".deploy-tokens.json";
+
+/// `DeployScope` stand for what a deploy token may do: read or write the repositories
+/// whose name matches a glob, until it expires.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct DeployScope {
+    /// glob of repository names, e.g. `web-*`, or a single name.
+    pub repos: String,
+    /// `read` or `write`.
+    pub access: Access,
+    /// the token is refused after this time, never if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl DeployScope {
+    /// whether the scope covers the repository `name` at `now`.
+    pub fn covers(&self, name: &str, now: DateTime<Utc>) -> bool {
+        self.expires.is_none_or(|expires| now < expires)
+            && glob_match(
+                &self.repos.chars().collect::<Vec<_>>(),
+                &name.chars().collect::<Vec<_>>(),
+            )
+    }
+}
+
+/// `DeployToken` stand for a deploy token minted for ci or deployment scripts, only its
+/// hash is kept. clients send it as the password of the account `name`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct DeployToken {
+    /// id revoking the token.
+    pub id: String,
+    pub name: String,
+    /// blake3 hex of the token.
+    pub hash: String,
+    pub scope: DeployScope,
+    pub created: DateTime<Utc>,
+}
+
+/// `DeployTokenStore` keeps the deploy tokens of a server in a json file, e.g.
+/// `.deploy-tokens.json` in the dir of a root of hosted repositories. admin apis mint and
+/// revoke them:
+///
+/// ```ignore
+/// async fn mint(Json(request): Json<MintRequest>) -> Result<Json<MintResponse>, StatusCode> {
+///     let (token, secret) = DEPLOY.mint(&request.name, request.scope).await
+///         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+///     Ok(Json(MintResponse { token, secret }))
+/// }
+///
+/// async fn revoke(Path(id): Path<String>) -> StatusCode {
+///     match DEPLOY.revoke(&id).await {
+///         Ok(true) => StatusCode::NO_CONTENT,
+///         Ok(false) => StatusCode::NOT_FOUND,
+///         Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+///     }
+/// }
+///
+/// Router::new()
+///     .route("/admin/deploy-tokens", post(mint))
+///     .route("/admin/deploy-tokens/:id", delete(revoke))
+/// ```
+pub struct DeployTokenStore {
+    path: PathBuf,
+    /// serializes updates of the file by concurrent mints and revokes.
+    lock: Mutex<()>,
+}
+
+impl DeployTokenStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// deploy tokens minted so far, expired ones included.
+    pub async fn tokens(&self) -> Result<Vec<DeployToken>, WsvcError> {
+        match tokio::fs::read(&self.path).await {
+            Ok(content) => Ok(serde_json::from_slice(&content)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+            Err(err) => Err(WsvcFsError::Os(err).into()),
+        }
+    }
+
+    /// mint a deploy token named `name` with `scope`, returns it and its secret, which is
+    /// not kept.
+    pub async fn mint(
+        &self,
+        name: &str,
+        scope: DeployScope,
+    ) -> Result<(DeployToken, String), WsvcError> {
+        if scope.access == Access::None {
+            return Err(WsvcError::BadUsage(
+                "deploy tokens must read or write".to_owned(),
+            ));
+        }
+        let secret = format!("{}{}", DEPLOY_TOKEN_PREFIX, nanoid::nanoid!(40));
+        let token = DeployToken {
+            id: nanoid::nanoid!(12),
+            name: name.to_owned(),
+            hash: token_hash(&secret),
+            scope,
+            created: Utc::now(),
+        };
+        let _lock = self.lock.lock().await;
+        let mut tokens = self.tokens().await?;
+        tokens.push(token.clone());
+        write_atomic(&self.path, &serde_json::to_vec_pretty(&tokens)?).await?;
+        Ok((token, secret))
+    }
+
+    /// revoke the deploy token `id`, returns whether there was one.
+    pub async fn revoke(&self, id: &str) -> Result<bool, WsvcError> {
+        let _lock = self.lock.lock().await;
+        let mut tokens = self.tokens().await?;
+        let count = tokens.len();
+        tokens.retain(|token| token.id != id);
+        if tokens.len() == count {
+            return Ok(false);
+        }
+        write_atomic(&self.path, &serde_json::to_vec_pretty(&tokens)?).await?;
+        Ok(true)
+    }
+
+    /// the deploy token whose secret is `secret`, if it covers the repository `repo` now.
+    /// http auth layers check bearer tokens with it.
+    pub async fn find(&self, secret: &str, repo: &str) -> Result<Option<DeployToken>, WsvcError> {
+        if !secret.starts_with(DEPLOY_TOKEN_PREFIX) {
+            return Ok(None);
+        }
+        let hash = blake3::hash(secret.as_bytes());
+        let now = Utc::now();
+        Ok(self.tokens().await?.into_iter().find(|token| {
+            blake3::Hash::from_hex(&token.hash).is_ok_and(|minted| minted == hash)
+                && token.scope.covers(repo, now)
+        }))
+    }
+}
+
+/// the name of `repo`, its dir name in the root.
+pub fn repo_name(repo: &Repository) -> String {
+    repo.path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// `DeployVerifier` accepts the deploy tokens of `tokens` besides the credentials `inner`
+/// accepts, granting deploy tokens their scope whatever the visibility of the repository.
+pub struct DeployVerifier<V> {
+    pub tokens: DeployTokenStore,
+    pub inner: V,
+}
+
+impl<V> DeployVerifier<V> {
+    /// the deploy token of `credential` covering `repo`, it must be sent as the password
+    /// of the account named by the token.
+    async fn token(
+        &self,
+        repo: &Repository,
+        credential: &SyncCredential,
+    ) -> Result<Option<DeployToken>, WsvcError> {
+        Ok(self
+            .tokens
+            .find(&credential.password, &repo_name(repo))
+            .await?
+            .filter(|token| token.name == credential.account))
+    }
+}
+
+#[async_trait]
+impl<V: CredentialVerifier> CredentialVerifier for DeployVerifier<V> {
+    async fn verify(
+        &self,
+        repo: &Repository,
+        credential: &SyncCredential,
+    ) -> Result<bool, WsvcError> {
+        if credential.password.starts_with(DEPLOY_TOKEN_PREFIX) {
+            return Ok(self.token(repo, credential).await?.is_some());
+        }
+        self.inner.verify(repo, credential).await
+    }
+
+    async fn grant(
+        &self,
+        repo: &Repository,
+        credential: &SyncCredential,
+    ) -> Result<Option<Access>, WsvcError> {
+        if credential.password.starts_with(DEPLOY_TOKEN_PREFIX) {
+            return Ok(self
+                .token(repo, credential)
+                .await?
+                .map(|token| token.scope.access));
+        }
+        self.inner.grant(repo, credential).await
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    fs::WsvcFsError,
+    model::Repository,
+    server::{
+        blobs::DEFAULT_INLINE_TYPES,
+        trash::RepoTrash,
+        usage::{UsageConfig, UsageLedger, USAGE_FILE},
+        visibility::{RepoAccess, Visibility},
+        SessionLimits,
+    },
+    WsvcError,
+};
+
+#[cfg(feature = "oidc")]
+use crate::server::oidc::OidcConfig;
+
+/// days deleted repositories stay in the trash of a root without `trash_days`.
+pub const DEFAULT_TRASH_DAYS: u64 = 30;
+
+/// `HostingConfig` maps hostnames and url prefixes to storage roots, so one server can
+/// serve distinct tenants.
+///
+/// ```toml
+/// relay = true
+/// admin_htpasswd = "/srv/wsvc/admins.htpasswd"
+///
+/// [[root]]
+/// prefix = "/public"
+/// path = "/srv/wsvc/public"
+/// asset_quota = 1073741824
+/// memory_budget = 4194304
+/// inline = ["text/plain", "text/markdown", "image/png"]
+/// visibility = "public"
+///
+/// [[root]]
+/// host = "internal.example.com"
+/// prefix = "/internal"
+/// path = "/srv/wsvc/internal"
+/// auth = "htpasswd"
+/// htpasswd = "/srv/wsvc/htpasswd"
+/// visibility = "internal"
+///
+/// trash_days = 7
+///
+/// [root.usage]
+/// transfer_cap = 10737418240
+/// storage_quota = 1073741824
+///
+/// [[root]]
+/// prefix = "/team"
+/// path = "/srv/wsvc/team"
+/// auth = "oidc"
+///
+/// [root.oidc]
+/// issuer = "https://accounts.example.com"
+/// audience = "wsvc"
+/// ```
+///
+/// a request matches a root if the host matches, or the root has no host, and the path
+/// is under its prefix. roots with a host win over roots without one, then the longest
+/// prefix wins. the rest of the path starts with the repository name.
+///
+/// with `relay`, peers can also sync through the relay rooms under `/relay/<room>`, see
+/// `RelayHub`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HostingConfig {
+    /// whether to serve relay rooms under `/relay/<room>`, shadowing a repository named
+    /// `relay` of a root prefixed `/`.
+    #[serde(default)]
+    pub relay: bool,
+    /// htpasswd file of the admins of the server, who may use the admin api under
+    /// `<prefix>/.admin` of each root. the admin api is off if not set.
+    #[serde(default)]
+    pub admin_htpasswd: Option<PathBuf>,
+    #[serde(default, rename = "root")]
+    pub roots: Vec<RepoRoot>,
+}
+
+/// `RepoRoot` stand for a dir of hosted repositories.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RepoRoot {
+    /// hostname the root is served for, any host if not set.
+    #[serde(default)]
+    pub host: Option<String>,
+    /// url prefix of repositories, e.g. `/public`.
+    #[serde(default)]
+    pub prefix: String,
+    /// dir storing the repositories, each one in a dir named by the repository.
+    pub path: PathBuf,
+    /// name of the auth backend guarding the root, interpreted by the server. anyone can
+    /// access the root if not set.
+    #[serde(default)]
+    pub auth: Option<String>,
+    /// htpasswd file of the accounts of the root, see `HtpasswdAccounts`. used by the
+    /// `htpasswd` auth backend.
+    #[serde(default)]
+    pub htpasswd: Option<PathBuf>,
+    /// openid connect issuer whose tokens are accepted, used by the `oidc` auth backend.
+    #[cfg(feature = "oidc")]
+    #[serde(default)]
+    pub oidc: Option<OidcConfig>,
+    /// largest total size in bytes of release assets of each repository, unlimited if not
+    /// set.
+    #[serde(default)]
+    pub asset_quota: Option<u64>,
+    /// bytes of a metadata payload each sync session keeps in memory, see
+    /// `SessionLimits`. 16 MiB if not set.
+    #[serde(default)]
+    pub memory_budget: Option<usize>,
+    /// mime types of blobs the web ui may render inline, `DEFAULT_INLINE_TYPES` if not
+    /// set. other blobs are only served as downloads.
+    #[serde(default)]
+    pub inline: Option<Vec<String>>,
+    /// visibility of repositories of the root without one in their `visibility.toml`,
+    /// see `RepoAccess`. anyone may read and write them if neither sets one.
+    #[serde(default)]
+    pub visibility: Option<Visibility>,
+    /// usage accounting of each user of the root, in `usage.json` of its dir. usage is not
+    /// accounted if not set.
+    #[serde(default)]
+    pub usage: Option<UsageConfig>,
+    /// days a repository deleted by the admin api stays in the trash of the root before it
+    /// is purged, see `RepoTrash`. 30 days if not set.
+    #[serde(default)]
+    pub trash_days: Option<u64>,
+}
+
+/// strip the port of a `Host` header value, `[::1]:80` becomes `[::1]`.
+fn hostname(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host,
+    }
+}
+
+impl RepoRoot {
+    /// the prefix without trailing `/`, e.g. `/public`, or empty for the url root.
+    pub fn normalized_prefix(&self) -> String {
+        let prefix = self.prefix.trim_matches('/');
+        if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("/{}", prefix)
+        }
+    }
+
+    /// the path after the prefix if `path` is under it.
+    fn strip<'a>(&self, path: &'a str) -> Option<&'a str> {
+        let rest = path.strip_prefix(&self.normalized_prefix())?;
+        if rest.is_empty() || rest.starts_with('/') {
+            Some(rest.trim_start_matches('/'))
+        } else {
+            None
+        }
+    }
+
+    /// limits of sync sessions with repositories of the root.
+    pub fn session_limits(&self) -> SessionLimits {
+        let mut limits = SessionLimits::default();
+        if let Some(budget) = self.memory_budget {
+            limits.memory_budget = budget;
+        }
+        limits
+    }
+
+    /// mime types of blobs of the root allowed to be rendered inline.
+    pub fn inline_types(&self) -> Vec<String> {
+        match &self.inline {
+            Some(types) => types.clone(),
+            None => DEFAULT_INLINE_TYPES.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+
+    /// who may read and write `repo` of the root, `None` if anyone may.
+    pub async fn access(&self, repo: &Repository) -> Result<Option<RepoAccess>, WsvcError> {
+        RepoAccess::load(repo, self.visibility).await
+    }
+
+    /// the usage ledger of the root, `None` if usage is not accounted.
+    pub async fn usage_ledger(&self) -> Result<Option<UsageLedger>, WsvcError> {
+        match &self.usage {
+            Some(_) => Ok(Some(UsageLedger::open(self.path.join(USAGE_FILE)).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// the trash of repositories of the root deleted by the admin api.
+    pub fn trash(&self) -> RepoTrash {
+        let days = self.trash_days.unwrap_or(DEFAULT_TRASH_DAYS);
+        RepoTrash::new(&self.path, Duration::from_secs(days * 24 * 3600))
+    }
+
+    /// open the repository `name` of the root.
+    pub async fn open(&self, name: &str) -> Result<Repository, WsvcError> {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return Err(WsvcError::BadUsage(format!(
+                "invalid repository name: {}",
+                name
+            )));
+        }
+        Ok(Repository::try_open(self.path.join(name)).await?)
+    }
+}
+
+impl HostingConfig {
+    /// load the config from a toml file.
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self, WsvcError> {
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    /// the root serving `path` requested from `host`, with the rest of the path.
+    pub fn resolve<'a>(&self, host: Option<&str>, path: &'a str) -> Option<(&RepoRoot, &'a str)> {
+        let host = host.map(hostname);
+        self.roots
+            .iter()
+            .filter(|root| match (&root.host, host) {
+                (None, _) => true,
+                (Some(expected), Some(host)) => expected.eq_ignore_ascii_case(host),
+                (Some(_), None) => false,
+            })
+            .filter_map(|root| Some((root, root.strip(path)?)))
+            .max_by_key(|(root, _)| (root.host.is_some(), root.normalized_prefix().len()))
+    }
+}
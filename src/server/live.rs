@@ -0,0 +1,158 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use crate::{
+    fs::WsvcFsError,
+    model::{ObjectId, Record, Repository},
+    refs::write_atomic,
+    walk::RecordGraph,
+    WsvcError,
+};
+
+/// file name of the live tree config in the server side repository dir.
+pub const LIVE_FILE: &str = "live.toml";
+
+/// file keeping the hash of the record checked out to the live tree, beside `live.toml`.
+const LIVE_RECORD_FILE: &str = "live.record";
+
+/// `LiveTree` stand for a worktree a hosted repository keeps checked out at its tip, e.g.
+/// the document root of a site deployed on push. it is only moved forward, a tip not
+/// descending from the record checked out is left for an admin to deploy.
+///
+/// ```toml
+/// path = "/var/www/site"
+/// branch = "main"
+/// hook = "systemctl reload nginx"
+/// ```
+///
+/// the hook runs with `sh -c` in the worktree after each checkout, with the record in
+/// `WSVC_RECORD` and the one checked out before in `WSVC_PREVIOUS_RECORD`, if any.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct LiveTree {
+    /// dir of the worktree, created if missing. files not in the record are removed.
+    pub path: PathBuf,
+    /// branch checked out, the HEAD record or the latest record if not set.
+    #[serde(default)]
+    pub branch: Option<String>,
+    /// command run after each checkout.
+    #[serde(default)]
+    pub hook: Option<String>,
+}
+
+impl LiveTree {
+    /// the live tree of `repo`, `None` if it has no `live.toml`.
+    pub async fn load(repo: &Repository) -> Result<Option<Self>, WsvcError> {
+        match tokio::fs::read_to_string(repo.path.join(LIVE_FILE)).await {
+            Ok(content) => Ok(Some(toml::from_str(&content)?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(WsvcFsError::Os(err).into()),
+        }
+    }
+
+    /// the record the live tree should be at, `None` if there is none yet.
+    pub async fn tip(&self, repo: &Repository) -> Result<Option<ObjectId>, WsvcError> {
+        if let Some(branch) = &self.branch {
+            return Ok(repo.read_branch(branch).await?.map(|branch| branch.record));
+        }
+        Ok(match repo.get_head_record().await? {
+            Some(head) => Some(head.hash),
+            None => repo.get_latest_record().await?.map(|record| record.hash),
+        })
+    }
+
+    /// the record checked out to the live tree, `None` if it was never checked out.
+    pub async fn deployed(repo: &Repository) -> Result<Option<ObjectId>, WsvcError> {
+        match tokio::fs::read_to_string(repo.path.join(LIVE_RECORD_FILE)).await {
+            Ok(hash) => Ok(Some(
+                ObjectId::try_from(hash.trim()).map_err(WsvcFsError::InvalidHexString)?,
+            )),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(WsvcFsError::Os(err).into()),
+        }
+    }
+
+    /// move the live tree forward to the tip of `repo` and run the hook, returns the
+    /// record checked out, `None` if the tree is at the tip already. the repository
+    /// should be locked by the caller.
+    pub async fn update(&self, repo: &Repository) -> Result<Option<Record>, WsvcError> {
+        let Some(tip) = self.tip(repo).await? else {
+            return Ok(None);
+        };
+        let previous = Self::deployed(repo).await?;
+        if let Some(previous) = &previous {
+            if *previous == tip {
+                return Ok(None);
+            }
+            let graph = RecordGraph::from_summaries(repo.record_summaries().await?);
+            if !graph.is_ancestor(previous, &tip) {
+                return Err(WsvcError::DataError(format!(
+                    "{} does not descend from {} checked out to {}, not a fast-forward",
+                    &tip.0.to_hex()[0..6],
+                    &previous.0.to_hex()[0..6],
+                    self.path.display()
+                )));
+            }
+        }
+        let record = repo.read_record(&tip).await?;
+        tokio::fs::create_dir_all(&self.path)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        repo.checkout_record_tree(&record, &self.path).await?;
+        write_atomic(&repo.path.join(LIVE_RECORD_FILE), tip.0.to_hex().as_bytes()).await?;
+        if let Some(hook) = &self.hook {
+            self.run_hook(hook, &tip, previous.as_ref()).await?;
+        }
+        Ok(Some(record))
+    }
+
+    async fn run_hook(
+        &self,
+        hook: &str,
+        record: &ObjectId,
+        previous: Option<&ObjectId>,
+    ) -> Result<(), WsvcError> {
+        let mut command = Command::new("sh");
+        command
+            .args(["-c", hook])
+            .current_dir(&self.path)
+            .env("WSVC_RECORD", record.0.to_hex().as_str());
+        if let Some(previous) = previous {
+            command.env("WSVC_PREVIOUS_RECORD", previous.0.to_hex().as_str());
+        }
+        let output = command.output().await.map_err(|err| {
+            WsvcError::DataError(format!("failed to run live tree hook: {}", err))
+        })?;
+        if !output.status.success() {
+            return Err(WsvcError::DataError(format!(
+                "live tree hook failed with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// move the live tree of `repo` forward after a push, failures are logged and ignored,
+/// the push is stored already.
+pub async fn record_push(repo: &Repository) {
+    let live = match LiveTree::load(repo).await {
+        Ok(Some(live)) => live,
+        Ok(None) => return,
+        Err(err) => {
+            tracing::warn!("failed to load live tree config: {}", err);
+            return;
+        }
+    };
+    match live.update(repo).await {
+        Ok(Some(record)) => tracing::info!(
+            "live tree {} checked out at {}",
+            live.path.display(),
+            &record.hash.0.to_hex()[0..6]
+        ),
+        Ok(None) => {}
+        Err(err) => tracing::warn!("failed to update live tree: {}", err),
+    }
+}
@@ -1,19 +1,65 @@
-use std::path::Path;
+use std::{
+    collections::HashMap,
+    io::{BufReader, SeekFrom},
+    path::{Path, PathBuf},
+};
 
-use axum::extract::ws::{Message as AxumMessage, WebSocket};
-use serde::{Deserialize, Serialize};
+use nanoid::nanoid;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use thiserror::Error;
 use tokio::{
-    fs::{create_dir_all, rename, write, File},
-    io::{AsyncReadExt, AsyncWriteExt},
+    fs::{create_dir_all, remove_file, File},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
 };
 
+pub mod auth;
+pub mod blobs;
+pub mod deploy;
+pub mod hosting;
+pub mod live;
+pub mod notify;
+#[cfg(feature = "oidc")]
+pub mod oidc;
+pub mod packs;
+pub mod policy;
+pub mod poll;
+pub mod readme;
+pub mod relay;
+pub mod search;
+pub mod stats;
+pub mod trash;
+pub mod usage;
+pub mod visibility;
+
 use crate::{
-    fs::{RepoGuard, WsvcFsError},
-    model::{Blob, Record, Repository, Tree},
+    attest::Attestation,
+    auth::AuthResult,
+    branch::{branch_ref, SyncBranch},
+    codec::{CodecStream, TransportCodec, ZSTD_CODEC},
+    frame::{
+        decode_size_header, encode_size_header, FrameStream, FILE_MAGIC, PACKET_MAGIC,
+        REFETCH_PROTOCOL,
+    },
+    fs::{check_objects, RepoGuard, WsvcFsError},
+    identity::{IdentityCheck, SyncIdentity},
+    model::{Blob, ObjectId, Record, Repository, Tree},
+    note::Note,
+    pack::{BlobTransfer, BOOTSTRAP_INDEX, BOOTSTRAP_PACK, PACK_BOOTSTRAP_MIN_BLOBS},
+    resume::{
+        decode_resume_frame, encode_resume_frame, open_received, partial_files, resume_offset,
+        PartialFile, RESUME_PROTOCOL,
+    },
+    tag::{tag_ref, SyncTag},
     WsvcError,
 };
 
+use self::{
+    auth::CredentialVerifier,
+    stats::RepoStats,
+    usage::{MeteredStream, UsageMeter, ANONYMOUS_USER},
+    visibility::{Access, RepoAccess, Visibility},
+};
+
 /// `WsvcServerError` stand for server error.
 #[derive(Error, Debug)]
 pub enum WsvcServerError {
@@ -25,6 +71,35 @@ pub enum WsvcServerError {
     NetworkError(#[from] axum::Error),
     #[error("data error: {0}")]
     DataError(String),
+    #[error("push rejected: {0}")]
+    Rejected(String),
+}
+
+/// `SyncReport` is the final packet of a sync, tells client whether its push is accepted.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SyncReport {
+    pub accepted: bool,
+    pub violations: Vec<String>,
+}
+
+/// default bytes of a payload a sync session keeps in memory, see `SessionLimits`.
+pub const DEFAULT_MEMORY_BUDGET: usize = 16 * 1024 * 1024;
+
+/// `SessionLimits` stand for resource limits of a sync session with a client.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct SessionLimits {
+    /// bytes of a metadata payload from the client kept in memory, larger payloads are
+    /// written to a temp file and parsed from it while reading, so many concurrent syncs
+    /// of large repositories do not exhaust the memory of the server.
+    pub memory_budget: usize,
+}
+
+impl Default for SessionLimits {
+    fn default() -> Self {
+        Self {
+            memory_budget: DEFAULT_MEMORY_BUDGET,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -48,60 +123,90 @@ pub struct BlobWithState {
     pub state: i32,
 }
 
-async fn send_data(ws: &mut WebSocket, data: Vec<u8>) -> Result<(), WsvcServerError> {
-    let mut header_buf = [0x33u8, 0x07u8, 0u8, 0u8, 0u8, 0u8];
-    let size = data.len();
-    header_buf[2] = (size >> 24) as u8;
-    header_buf[3] = (size >> 16) as u8;
-    header_buf[4] = (size >> 8) as u8;
-    header_buf[5] = size as u8;
-    ws.send(header_buf[..].into()).await?;
+async fn send_data(ws: &mut impl FrameStream, data: Vec<u8>) -> Result<(), WsvcServerError> {
+    let header = encode_size_header(PACKET_MAGIC, data.len() as u64, ws.protocol())?;
+    ws.send_frame(header).await?;
     // split data into 16384 bytes
     let mut offset = 0;
     while offset < data.len() {
         let end = offset + 16384;
         let end = if end > data.len() { data.len() } else { end };
-        ws.send(data[offset..end].into()).await?;
+        ws.send_frame(data[offset..end].to_vec()).await?;
         offset = end;
     }
     Ok(())
 }
 
-async fn recv_data(ws: &mut WebSocket) -> Result<Vec<u8>, WsvcServerError> {
-    // match header and get size
-    if let Some(Ok(AxumMessage::Binary(msg))) = ws.recv().await {
-        let mut header_buf = [0u8; 6];
-        header_buf.copy_from_slice(&msg[..6]);
-        if header_buf[0] != 0x33 || header_buf[1] != 0x07 {
-            return Err(WsvcServerError::DataError(
-                "invalid packet header".to_owned(),
-            ));
-        }
-        let size = ((header_buf[2] as usize) << 24)
-            + ((header_buf[3] as usize) << 16)
-            + ((header_buf[4] as usize) << 8)
-            + (header_buf[5] as usize);
+async fn recv_frame(ws: &mut impl FrameStream, what: &str) -> Result<Vec<u8>, WsvcServerError> {
+    ws.recv_frame()
+        .await?
+        .ok_or(WsvcServerError::DataError(format!(
+            "invalid {}: none",
+            what
+        )))
+}
+
+/// receive a packet header, returns the size of the packet and the data after the header.
+async fn recv_header(ws: &mut impl FrameStream) -> Result<(usize, Vec<u8>), WsvcServerError> {
+    let msg = recv_frame(ws, "packet header").await?;
+    let (size, rest) = decode_size_header(&msg, PACKET_MAGIC, ws.protocol()).ok_or(
+        WsvcServerError::DataError("invalid packet header".to_owned()),
+    )?;
+    Ok((size as usize, rest.to_vec()))
+}
+
+/// receive a json packet and parse it, packets larger than the memory budget of `limits`
+/// are spilled to a temp file of `repo` and parsed from it.
+async fn recv_json<T: DeserializeOwned + Send + 'static>(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+    limits: &SessionLimits,
+) -> Result<T, WsvcServerError> {
+    let os_error = |err| WsvcError::FsError(WsvcFsError::Os(err));
+    let (size, first) = recv_header(ws).await?;
+    if size <= limits.memory_budget {
         let mut data = Vec::with_capacity(size);
-        data.extend_from_slice(&msg[6..]);
-        let mut offset = data.len();
-        while offset < size {
-            if let Some(Ok(AxumMessage::Binary(msg))) = ws.recv().await {
-                data.extend_from_slice(&msg);
-                offset = data.len();
-            }
+        data.extend_from_slice(&first);
+        while data.len() < size {
+            data.extend_from_slice(&recv_frame(ws, "packet data").await?);
+        }
+        tracing::trace!("recv packet: {:?}", data);
+        return Ok(serde_json::from_slice(&data)?);
+    }
+    tracing::debug!("spill packet of {} bytes to disk", size);
+    let temp_dir = repo.temp_dir().await.map_err(WsvcError::from)?;
+    create_dir_all(&temp_dir).await.map_err(os_error)?;
+    let path = temp_dir.join(format!("packet-{}", nanoid!()));
+    let received = async {
+        let mut file = File::create(&path).await.map_err(os_error)?;
+        file.write_all(&first).await.map_err(os_error)?;
+        let mut written = first.len();
+        while written < size {
+            let data = recv_frame(ws, "packet data").await?;
+            file.write_all(&data).await.map_err(os_error)?;
+            written += data.len();
         }
-        Ok(data)
-    } else {
-        Err(WsvcServerError::DataError(
-            "invalid packet header".to_owned(),
-        ))
+        file.flush().await.map_err(os_error)?;
+        drop(file);
+        // parsed while reading, only the parsed value is kept in memory.
+        let file = std::fs::File::open(&path).map_err(os_error)?;
+        let parsed =
+            tokio::task::spawn_blocking(move || serde_json::from_reader(BufReader::new(file)))
+                .await
+                .map_err(|err| WsvcServerError::DataError(err.to_string()))?;
+        Ok(parsed?)
     }
+    .await;
+    remove_file(&path).await.ok();
+    received
 }
 
+/// send `file` named `file_name` from `offset`, the bytes the receiver already has.
 async fn send_file(
-    ws: &mut WebSocket,
+    ws: &mut impl FrameStream,
     file_name: &str,
     mut file: File,
+    offset: u64,
 ) -> Result<(), WsvcServerError> {
     // file name packet header: 0x09 0x28 [size], 9.28 is Kamisato Ayaka's birthday
     let mut header_buf = [0x09u8, 0x28u8, 0u8, 0u8];
@@ -111,126 +216,244 @@ async fn send_file(
     }
     header_buf[2] = (file_name_size >> 8) as u8;
     header_buf[3] = file_name_size as u8;
-    ws.send(header_buf[..].into()).await?;
-    let mut file_header_buf = [0x07u8, 0x15u8, 0u8, 0u8, 0u8, 0u8];
+    ws.send_frame(header_buf.to_vec()).await?;
+    ws.send_frame(file_name.as_bytes().to_vec()).await?;
     let mut buf = [0u8; 16384];
     let size = file
         .metadata()
         .await
         .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?
-        .len() as usize;
-    file_header_buf[2] = (size >> 24) as u8;
-    file_header_buf[3] = (size >> 16) as u8;
-    file_header_buf[4] = (size >> 8) as u8;
-    file_header_buf[5] = size as u8;
-    ws.send(file_header_buf[..].into()).await?;
-    let mut offset = 0;
+        .len();
+    ws.send_frame(encode_size_header(FILE_MAGIC, size, ws.protocol())?)
+        .await?;
+    let mut offset = offset.min(size);
+    if ws.protocol() >= RESUME_PROTOCOL {
+        ws.send_frame(encode_resume_frame(offset)).await?;
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
+    }
     while offset != size {
         let read_size = file
             .read(&mut buf)
             .await
             .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
-        ws.send(buf[..read_size].into()).await?;
-        offset += read_size;
+        if read_size == 0 {
+            return Err(WsvcServerError::DataError(format!(
+                "file {} changed while sending",
+                file_name
+            )));
+        }
+        ws.send_frame(buf[..read_size].to_vec()).await?;
+        offset += read_size as u64;
     }
     Ok(())
 }
 
 async fn recv_file(
-    ws: &mut WebSocket,
+    ws: &mut impl FrameStream,
     storage_dir: impl AsRef<Path>,
 ) -> Result<(), WsvcServerError> {
-    let file_name_header = ws
-        .recv()
-        .await
-        .ok_or(WsvcServerError::DataError(format!(
-            "invalid file name header: {}",
-            "none"
-        )))?
-        .map_err(WsvcServerError::NetworkError)?;
-    let mut file_name_header_buf = [0u8; 4];
-    if let AxumMessage::Binary(msg) = file_name_header {
-        file_name_header_buf.copy_from_slice(&msg[..4]);
-    } else {
+    let file_name_header = recv_frame(ws, "file name header").await?;
+    if file_name_header.len() < 4 || file_name_header[0] != 0x09 || file_name_header[1] != 0x28 {
         return Err(WsvcServerError::DataError(format!(
             "invalid file name header: {:?}",
             file_name_header
         )));
     }
-    if file_name_header_buf[0] != 0x09 || file_name_header_buf[1] != 0x28 {
-        return Err(WsvcServerError::DataError(format!(
-            "invalid file name header: {:?}",
-            file_name_header_buf
-        )));
-    }
-    let file_name_size =
-        ((file_name_header_buf[2] as usize) << 8) + (file_name_header_buf[3] as usize);
-    let file_name = ws
-        .recv()
-        .await
-        .ok_or(WsvcServerError::DataError(format!(
-            "invalid file name: {}",
-            "none"
-        )))?
-        .map_err(WsvcServerError::NetworkError)?;
-    let file_name = if let AxumMessage::Binary(msg) = file_name {
-        String::from_utf8(msg[..file_name_size].to_vec())
-            .map_err(|err| WsvcServerError::DataError(err.to_string()))?
-    } else {
-        return Err(WsvcServerError::DataError(format!(
-            "invalid file name: {:?}",
-            file_name
-        )));
-    };
+    let file_name_size = ((file_name_header[2] as usize) << 8) + (file_name_header[3] as usize);
+    let file_name = recv_frame(ws, "file name").await?;
+    let file_name = String::from_utf8(file_name[..file_name_size.min(file_name.len())].to_vec())
+        .map_err(|err| WsvcServerError::DataError(err.to_string()))?;
     let file_path = storage_dir.as_ref().join(file_name);
-    let file_header = ws
-        .recv()
-        .await
-        .ok_or(WsvcServerError::DataError("invalid file header".to_owned()))?
-        .map_err(WsvcServerError::NetworkError)?;
-    let mut file_header_buf = [0u8; 6];
-    if let AxumMessage::Binary(msg) = file_header {
-        file_header_buf.copy_from_slice(&msg[..6]);
-    } else {
-        return Err(WsvcServerError::DataError("invalid file header".to_owned()));
-    }
-    if file_header_buf[0] != 0x07 || file_header_buf[1] != 0x15 {
-        return Err(WsvcServerError::DataError("invalid file header".to_owned()));
-    }
-    let size = ((file_header_buf[2] as usize) << 24)
-        + ((file_header_buf[3] as usize) << 16)
-        + ((file_header_buf[4] as usize) << 8)
-        + (file_header_buf[5] as usize);
-    let mut file = File::create(&file_path)
+    let file_header = recv_frame(ws, "file header").await?;
+    let (size, _) = decode_size_header(&file_header, FILE_MAGIC, ws.protocol())
+        .ok_or(WsvcServerError::DataError("invalid file header".to_owned()))?;
+    // the sender continues from the bytes we already have since protocol version 3.
+    let offset = match ws.protocol() >= RESUME_PROTOCOL {
+        true => decode_resume_frame(&recv_frame(ws, "resume frame").await?).ok_or(
+            WsvcServerError::DataError("invalid resume frame".to_owned()),
+        )?,
+        false => 0,
+    };
+    let mut file = open_received(&file_path, offset)
         .await
-        .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
-    let mut offset = 0;
-    while offset != size {
-        let data = ws
-            .recv()
+        .map_err(WsvcError::FsError)?;
+    let mut offset = offset;
+    while offset < size {
+        let data = recv_frame(ws, "file data").await?;
+        offset += data.len() as u64;
+        file.write_all(&data)
             .await
-            .ok_or(WsvcServerError::DataError("invalid file data".to_owned()))?
-            .map_err(WsvcServerError::NetworkError)?;
-        if let AxumMessage::Binary(data) = data {
-            offset += data.len();
-            file.write(&data)
-                .await
-                .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
-        } else {
-            return Err(WsvcServerError::DataError("invalid file data".to_owned()));
-        }
+            .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
     }
 
     Ok(())
 }
 
+/// what the client of `remote` may do with `repo`, the account it authenticated as with
+/// `verifier` checked against `access`. any account accepted by `verifier` may write
+/// without `access`, and anyone without both.
+async fn client_access(
+    repo: &Repository,
+    remote: &SyncIdentity,
+    verifier: Option<&dyn CredentialVerifier>,
+    access: Option<&RepoAccess>,
+) -> Result<Access, WsvcServerError> {
+    let account = match (verifier, &remote.credential) {
+        (Some(verifier), Some(credential)) => match verifier.verify(repo, credential).await? {
+            true => {
+                tracing::debug!("client authenticated as {}", credential.account);
+                // scoped credentials are granted their scope whatever the visibility.
+                if let Some(granted) = verifier.grant(repo, credential).await? {
+                    return Ok(granted);
+                }
+                Some(credential.account.as_str())
+            }
+            false => {
+                return Err(WsvcServerError::Rejected(format!(
+                    "invalid credential of {}",
+                    credential.account
+                )))
+            }
+        },
+        // without a verifier, credentials can not be checked.
+        _ => None,
+    };
+    Ok(match (access, verifier) {
+        (Some(access), _) => access.access(account),
+        (None, Some(_)) if account.is_none() => Access::None,
+        _ => Access::Write,
+    })
+}
+
+/// `ClientSession` stand for what the identity round agreed with a client.
+struct ClientSession {
+    identity: SyncIdentity,
+    /// account the client authenticated as, `None` if anonymous.
+    account: Option<String>,
+    /// codec of the next rounds.
+    codec: Option<TransportCodec>,
+    /// protocol version of the next rounds.
+    protocol: u32,
+    /// whether the client may only read.
+    read_only: bool,
+}
+
+/// `sync_identity` exchanges repository ids with client, so a client syncing another
+/// repository by mistake is refused before anything is sent. with a `verifier` or an
+/// `access`, the client is told whether it may access the repository with an `AuthResult`
+/// before ids are compared.
+///
+/// with a `meter`, a client whose monthly transfer cap is used up is refused too.
+async fn sync_identity(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+    limits: &SessionLimits,
+    verifier: Option<&dyn CredentialVerifier>,
+    access: Option<&RepoAccess>,
+    meter: Option<&UsageMeter<'_>>,
+) -> Result<ClientSession, WsvcServerError> {
+    tracing::debug!("ROUND 0: sync identity...");
+    let mut local = repo.sync_identity(true).await.map_err(WsvcError::FsError)?;
+    let dict = repo.transport_dict().await.map_err(WsvcError::FsError)?;
+    local.codecs = vec![ZSTD_CODEC.to_owned()];
+    local.dict = dict.as_ref().map(|dict| dict.id.clone());
+    local.auth = verifier.is_some() || access.is_some();
+    local.public = access.is_some_and(|access| access.visibility == Visibility::Public);
+    send_data(ws, serde_json::to_string(&local)?.into_bytes()).await?;
+    let remote: SyncIdentity = recv_json(repo, ws, limits).await?;
+    let mut read_only = false;
+    let mut account = None;
+    if local.auth {
+        let message = match client_access(repo, &remote, verifier, access).await {
+            Ok(Access::None) => Some(match &remote.credential {
+                Some(credential) if verifier.is_some() => {
+                    format!("{} may not access the repository", credential.account)
+                }
+                _ => "credential required".to_owned(),
+            }),
+            Ok(granted) => {
+                read_only = granted == Access::Read;
+                // credentials are verified if there is a verifier.
+                account = verifier
+                    .and(remote.credential.as_ref())
+                    .map(|credential| credential.account.clone());
+                let user = account.as_deref().unwrap_or(ANONYMOUS_USER);
+                match meter {
+                    Some(meter) if meter.remaining(user).await == Some(0) => {
+                        Some(format!("monthly transfer cap of {} is used up", user))
+                    }
+                    _ => None,
+                }
+            }
+            Err(WsvcServerError::Rejected(message)) => Some(message),
+            Err(err) => return Err(err),
+        };
+        let result = AuthResult {
+            accepted: message.is_none(),
+            read_only,
+            message,
+        };
+        send_data(ws, serde_json::to_string(&result)?.into_bytes()).await?;
+        if let Some(message) = result.message {
+            return Err(WsvcServerError::Rejected(message));
+        }
+    }
+    match local.check(&remote) {
+        IdentityCheck::Same => {}
+        IdentityCheck::Adopt(id) => {
+            if repo.check_writable().is_ok() {
+                repo.adopt_id(&id).await.map_err(WsvcError::FsError)?;
+            }
+        }
+        IdentityCheck::Mismatch { local, remote } => {
+            return Err(WsvcServerError::Rejected(format!(
+                "repository id {} of client is not {}",
+                remote, local
+            )))
+        }
+    }
+    // older clients choose no codec, frames are sent as is then.
+    let codec = match (remote.codec.as_deref(), dict) {
+        (Some(ZSTD_CODEC), Some(dict)) => {
+            if remote.dict.as_ref() != Some(&dict.id) {
+                send_data(ws, dict.data.clone()).await?;
+            }
+            Some(TransportCodec::ZstdDict(dict))
+        }
+        (Some(ZSTD_CODEC), None) => Some(TransportCodec::Zstd),
+        _ => None,
+    };
+    let protocol = local.protocol_with(&remote);
+    Ok(ClientSession {
+        identity: remote,
+        account,
+        codec,
+        protocol,
+        read_only,
+    })
+}
+
+/// refuse a client allowed only to read pushing `given` of `what`, before any is stored.
+fn check_read_only(read_only: bool, given: usize, what: &str) -> Result<(), WsvcServerError> {
+    match read_only && given > 0 {
+        true => Err(WsvcServerError::Rejected(format!(
+            "read-only access, {} {} can not be pushed",
+            given, what
+        ))),
+        false => Ok(()),
+    }
+}
+
 /// `sync_records` syncs records with client.
 ///
 /// ## returns
 /// (wanted_records, will_given_records)
 async fn sync_records(
     repo: &Repository,
-    ws: &mut WebSocket,
+    ws: &mut impl FrameStream,
+    limits: &SessionLimits,
 ) -> Result<(Vec<Record>, Vec<Record>), WsvcServerError> {
     // packet header: 0x33 0x07 [size]
     // the first round for server, pack all record and send it to client
@@ -239,9 +462,7 @@ async fn sync_records(
     let packet_body = serde_json::to_string(&records)?;
     tracing::trace!("send records: {:?}", records);
     send_data(ws, packet_body.into_bytes()).await?;
-    let diff_records = recv_data(ws).await?;
-    tracing::trace!("recv diff records: {:?}", diff_records);
-    let diff_records: Vec<RecordWithState> = serde_json::from_slice(&diff_records)?;
+    let diff_records: Vec<RecordWithState> = recv_json(repo, ws, limits).await?;
     let wanted_records = diff_records
         .iter()
         .filter(|r| r.state == 1)
@@ -258,7 +479,8 @@ async fn sync_records(
 
 async fn sync_trees(
     repo: &Repository,
-    ws: &mut WebSocket,
+    ws: &mut impl FrameStream,
+    limits: &SessionLimits,
     wanted_records: &[Record],
 ) -> Result<(Vec<Tree>, Vec<Tree>), WsvcServerError> {
     tracing::debug!("ROUND 2: sync trees...");
@@ -274,9 +496,7 @@ async fn sync_trees(
     let packet_body = serde_json::to_string(&trees)?;
     tracing::trace!("send trees: {:?}", trees);
     send_data(ws, packet_body.into_bytes()).await?;
-    let diff_trees = recv_data(ws).await?;
-    tracing::trace!("recv diff trees: {:?}", diff_trees);
-    let diff_trees: Vec<TreeWithState> = serde_json::from_slice(&diff_trees)?;
+    let diff_trees: Vec<TreeWithState> = recv_json(repo, ws, limits).await?;
     let wanted_trees = diff_trees
         .iter()
         .filter(|t| t.state == 1)
@@ -292,7 +512,8 @@ async fn sync_trees(
 
 async fn sync_blobs_meta(
     repo: &Repository,
-    ws: &mut WebSocket,
+    ws: &mut impl FrameStream,
+    limits: &SessionLimits,
     wanted_trees: &[Tree],
 ) -> Result<(Vec<Blob>, Vec<Blob>), WsvcServerError> {
     tracing::debug!("ROUND 3: sync blobs meta...");
@@ -308,9 +529,7 @@ async fn sync_blobs_meta(
     let packet_body = serde_json::to_string(&blobs)?;
     tracing::trace!("send blobs meta: {:?}", blobs);
     send_data(ws, packet_body.into_bytes()).await?;
-    let diff_blobs = recv_data(ws).await?;
-    tracing::trace!("recv diff blobs meta: {:?}", diff_blobs);
-    let diff_blobs: Vec<BlobWithState> = serde_json::from_slice(&diff_blobs)?;
+    let diff_blobs: Vec<BlobWithState> = recv_json(repo, ws, limits).await?;
     let wanted_blobs = diff_blobs
         .iter()
         .filter(|b| b.state == 1)
@@ -324,37 +543,132 @@ async fn sync_blobs_meta(
     Ok((wanted_blobs, will_given_blobs))
 }
 
+/// the cached pack of `wanted_blobs` for a new clone, `None` if there are too few blobs
+/// to be worth it, the repository is read-only or the pack is too large for a file frame
+/// of `protocol`.
+async fn bootstrap_pack(
+    repo: &Repository,
+    wanted_blobs: &[Blob],
+    protocol: u32,
+) -> Result<Option<(PathBuf, PathBuf)>, WsvcServerError> {
+    if wanted_blobs.len() < PACK_BOOTSTRAP_MIN_BLOBS || repo.check_writable().is_err() {
+        return Ok(None);
+    }
+    let hashes = wanted_blobs
+        .iter()
+        .map(|b| b.hash.clone())
+        .collect::<Vec<_>>();
+    let Some((pack, index)) = packs::cached_pack(repo, &hashes).await? else {
+        return Ok(None);
+    };
+    let size = tokio::fs::metadata(&pack)
+        .await
+        .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?
+        .len();
+    Ok((protocol >= 2 || size <= u32::MAX as u64).then_some((pack, index)))
+}
+
+/// send wanted blobs and receive given ones, returns the bytes of the content of each
+/// received blob. received blobs stay in the partial dir until the push is accepted, see
+/// `store_given_blobs`.
 async fn sync_blobs(
     repo: &Repository,
-    ws: &mut WebSocket,
+    ws: &mut impl FrameStream,
     wanted_blobs: &[Blob],
     will_given_blobs: &[Blob],
-) -> Result<(), WsvcServerError> {
+    client_empty: bool,
+    limits: &SessionLimits,
+) -> Result<HashMap<ObjectId, u64>, WsvcServerError> {
     tracing::debug!("ROUND 4: sync blobs...");
-    let objects_dir = repo
-        .objects_dir()
-        .await
-        .map_err(|err| WsvcError::from(err))?;
-    let temp_objects_dir = repo
-        .temp_dir()
-        .await
-        .map_err(|err| WsvcError::from(err))?
-        .join("objects");
-    if !temp_objects_dir.exists() {
-        create_dir_all(&temp_objects_dir)
-            .await
-            .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
+    // blobs are received where a session that died mid-way left them, sessions of a
+    // repository never run at once.
+    repo.prune_partial_files().await.map_err(WsvcError::from)?;
+    let temp_objects_dir = repo.partial_dir().await.map_err(WsvcError::from)?;
+    // a new clone gets all its blobs in a pack, later syncs get them one by one.
+    let pack = match client_empty {
+        true => bootstrap_pack(repo, wanted_blobs, ws.protocol()).await?,
+        false => None,
+    };
+    let transfer = BlobTransfer {
+        pack: pack.is_some(),
+    };
+    send_data(ws, serde_json::to_string(&transfer)?.into_bytes()).await?;
+    // the client tells the files it has the leading bytes of since protocol version 3.
+    let partials: HashMap<String, PartialFile> = match ws.protocol() >= RESUME_PROTOCOL {
+        true => recv_json::<Vec<PartialFile>>(repo, ws, limits)
+            .await?
+            .into_iter()
+            .map(|partial| (partial.name.clone(), partial))
+            .collect(),
+        false => HashMap::new(),
+    };
+    let mut files = vec![];
+    match &pack {
+        Some((pack, index)) => {
+            tracing::debug!("send {} blobs in a pack", wanted_blobs.len());
+            files.push((BOOTSTRAP_PACK.to_owned(), pack.clone()));
+            files.push((BOOTSTRAP_INDEX.to_owned(), index.clone()));
+        }
+        None => {
+            for blob in wanted_blobs {
+                let path = repo
+                    .export_object(&blob.hash)
+                    .await
+                    .map_err(WsvcError::from)?;
+                files.push((blob.hash.0.to_string(), path));
+            }
+        }
+    }
+    if !ws.send_files(&files).await? {
+        for (name, path) in &files {
+            let file = File::open(path)
+                .await
+                .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
+            let offset = resume_offset(path, partials.get(name))
+                .await
+                .map_err(WsvcError::FsError)?;
+            tracing::trace!("send blob file: {} from {}", name, offset);
+            send_file(ws, name, file, offset).await?;
+        }
     }
-    for i in wanted_blobs {
-        let object_file = objects_dir.join(i.hash.0.to_string());
-        let file = File::open(&object_file)
+    // files the client found corrupt are sent again whole, once.
+    if ws.protocol() >= REFETCH_PROTOCOL {
+        let corrupt: Vec<String> = recv_json(repo, ws, limits).await?;
+        for name in corrupt {
+            let (_, path) =
+                files
+                    .iter()
+                    .find(|(n, _)| *n == name)
+                    .ok_or(WsvcServerError::DataError(format!(
+                        "file not sent: {}",
+                        name
+                    )))?;
+            let file = File::open(path)
+                .await
+                .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
+            tracing::warn!("send corrupt blob file again: {}", name);
+            send_file(ws, &name, file, 0).await?;
+        }
+    }
+    // files left by a sync that died mid-way are continued.
+    if ws.protocol() >= RESUME_PROTOCOL {
+        let names = will_given_blobs
+            .iter()
+            .map(|b| b.hash.0.to_string())
+            .collect::<Vec<_>>();
+        let partials = partial_files(&temp_objects_dir, &names)
             .await
-            .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
-        tracing::trace!("send blob file: {:?}", i);
-        send_file(ws, &i.hash.0.to_string(), file).await?;
+            .map_err(WsvcError::FsError)?;
+        tracing::debug!("resume {} partial blob files", partials.len());
+        send_data(ws, serde_json::to_string(&partials)?.into_bytes()).await?;
     }
-    for _ in 0..will_given_blobs.len() {
-        recv_file(ws, &temp_objects_dir).await?;
+    if !ws
+        .recv_files(will_given_blobs.len(), &temp_objects_dir)
+        .await?
+    {
+        for _ in 0..will_given_blobs.len() {
+            recv_file(ws, &temp_objects_dir).await?;
+        }
     }
     for i in will_given_blobs {
         let object_file = temp_objects_dir.join(i.hash.0.to_string());
@@ -365,79 +679,525 @@ async fn sync_blobs(
             )));
         }
     }
-    for i in will_given_blobs {
-        rename(
-            temp_objects_dir.join(i.hash.0.to_string()),
-            objects_dir.join(i.hash.0.to_string()),
-        )
+    let (mut sizes, mut corrupt) = check_objects(&temp_objects_dir, will_given_blobs)
         .await
-        .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
+        .map_err(WsvcError::FsError)?;
+    if ws.protocol() >= REFETCH_PROTOCOL {
+        send_data(ws, serde_json::to_string(&corrupt)?.into_bytes()).await?;
+        for _ in 0..corrupt.len() {
+            recv_file(ws, &temp_objects_dir).await?;
+        }
+        let refetched = will_given_blobs
+            .iter()
+            .filter(|b| corrupt.contains(&b.hash.0.to_string()))
+            .cloned()
+            .collect::<Vec<_>>();
+        let (refetched_sizes, refetched_corrupt) = check_objects(&temp_objects_dir, &refetched)
+            .await
+            .map_err(WsvcError::FsError)?;
+        sizes.extend(refetched_sizes);
+        corrupt = refetched_corrupt;
+    }
+    if !corrupt.is_empty() {
+        return Err(WsvcServerError::DataError(format!(
+            "corrupt blob files: {}",
+            corrupt.join(", ")
+        )));
+    }
+    Ok(sizes)
+}
+
+async fn sync_attestations(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+    limits: &SessionLimits,
+    read_only: bool,
+) -> Result<(), WsvcServerError> {
+    tracing::debug!("ROUND 5: sync attestations...");
+    let attestations = repo.get_attestations().await.map_err(WsvcError::FsError)?;
+    let packet_body = serde_json::to_string(&attestations)?;
+    tracing::trace!("send attestations: {:?}", attestations);
+    send_data(ws, packet_body.into_bytes()).await?;
+    let given_attestations: Vec<Attestation> = recv_json(repo, ws, limits).await?;
+    check_read_only(read_only, given_attestations.len(), "attestations")?;
+    for attestation in &given_attestations {
+        if attestation.calc_hash().map_err(WsvcError::FsError)? != attestation.hash {
+            return Err(WsvcServerError::DataError(format!(
+                "attestation hash mismatch: {}",
+                attestation.hash.0
+            )));
+        }
+        repo.store_attestation(attestation)
+            .await
+            .map_err(WsvcError::FsError)?;
     }
     Ok(())
 }
 
+async fn sync_notes(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+    limits: &SessionLimits,
+    read_only: bool,
+) -> Result<(), WsvcServerError> {
+    tracing::debug!("ROUND 6: sync notes...");
+    let notes = repo.get_notes().await.map_err(WsvcError::FsError)?;
+    let packet_body = serde_json::to_string(&notes)?;
+    tracing::trace!("send notes: {:?}", notes);
+    send_data(ws, packet_body.into_bytes()).await?;
+    let given_notes: Vec<Note> = recv_json(repo, ws, limits).await?;
+    check_read_only(read_only, given_notes.len(), "notes")?;
+    for note in &given_notes {
+        if note.calc_hash().map_err(WsvcError::FsError)? != note.hash {
+            return Err(WsvcServerError::DataError(format!(
+                "note hash mismatch: {}",
+                note.hash.0
+            )));
+        }
+        repo.store_note(note).await.map_err(WsvcError::FsError)?;
+    }
+    Ok(())
+}
+
+async fn sync_tags(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+    limits: &SessionLimits,
+) -> Result<Vec<SyncTag>, WsvcServerError> {
+    tracing::debug!("ROUND 7: sync tags...");
+    let tags = repo.get_sync_tags().await.map_err(WsvcError::FsError)?;
+    let packet_body = serde_json::to_string(&tags)?;
+    tracing::trace!("send tags: {:?}", tags);
+    send_data(ws, packet_body.into_bytes()).await?;
+    // stored after the records they point to.
+    recv_json(repo, ws, limits).await
+}
+
+async fn sync_branches(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+    limits: &SessionLimits,
+) -> Result<Vec<SyncBranch>, WsvcServerError> {
+    tracing::debug!("ROUND 8: sync branches...");
+    let branches = repo.get_branches().await.map_err(WsvcError::FsError)?;
+    let packet_body = serde_json::to_string(&branches)?;
+    tracing::trace!("send branches: {:?}", branches);
+    send_data(ws, packet_body.into_bytes()).await?;
+    // stored after the records they point to.
+    recv_json(repo, ws, limits).await
+}
+
+/// store the blobs received by `sync_blobs` once the push is accepted, or remove them if
+/// it is rejected, so a rejected push leaves no objects behind.
+async fn store_given_blobs(
+    repo: &Repository,
+    given_blobs: &[Blob],
+    accepted: bool,
+) -> Result<(), WsvcServerError> {
+    let temp_objects_dir = repo.partial_dir().await.map_err(WsvcError::from)?;
+    for blob in given_blobs {
+        let path = temp_objects_dir.join(blob.hash.0.to_string());
+        match accepted {
+            true => repo
+                .import_object(&blob.hash, &path)
+                .await
+                .map_err(WsvcError::from)?,
+            false => remove_file(&path)
+                .await
+                .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?,
+        }
+    }
+    Ok(())
+}
+
+/// check pushed records against the repository policy.
+///
+/// contents of all pushed records are checked, while attestations are checked for the
+/// records that become tips: the new latest record, and the records of protected
+/// branches and tags the push moves or creates.
+async fn check_policy(
+    repo: &Repository,
+    given_records: &[Record],
+    given_trees: &[Tree],
+    given_sizes: &HashMap<ObjectId, u64>,
+    given_tags: &[SyncTag],
+    given_branches: &[SyncBranch],
+) -> Result<Vec<String>, WsvcServerError> {
+    let policy = policy::RepoPolicy::load(repo).await?;
+    let mut violations = Vec::new();
+    if let Some(content_policy) = &policy.content {
+        violations.extend(
+            content_policy
+                .check(repo, given_records, given_trees, given_sizes)
+                .await?,
+        );
+    }
+    let Some(attestation_policy) = &policy.attestation else {
+        return Ok(violations);
+    };
+    let mut tips = Vec::new();
+    let latest = repo.get_latest_record().await.map_err(WsvcError::FsError)?;
+    if let Some(new_latest) = given_records
+        .iter()
+        .max_by_key(|r| r.date)
+        .filter(|r| latest.as_ref().map(|l| r.date > l.date).unwrap_or(true))
+    {
+        tips.push(("latest record".to_owned(), new_latest.hash.clone()));
+    }
+    // a branch behind its current record is not moved, see `store_sync_branches`.
+    let graph = repo.record_graph().await.map_err(WsvcError::FsError)?;
+    for SyncBranch { branch, lease } in given_branches {
+        let name = branch_ref(&branch.name);
+        if !attestation_policy.protects(&name) {
+            continue;
+        }
+        let moved = match (repo.read_branch(&branch.name).await?, lease) {
+            (Some(current), None) => !graph.is_ancestor(&branch.record, &current.record),
+            (Some(current), Some(_)) => current.record != branch.record,
+            (None, _) => true,
+        };
+        if moved {
+            tips.push((format!("branch {}", branch.name), branch.record.clone()));
+        }
+    }
+    // existing tags are never moved by sync.
+    for SyncTag { tag, .. } in given_tags {
+        if attestation_policy.protects(&tag_ref(&tag.name))
+            && repo
+                .read_tag(&tag.name)
+                .await
+                .map_err(WsvcError::FsError)?
+                .is_none()
+        {
+            tips.push((format!("tag {}", tag.name), tag.record.clone()));
+        }
+    }
+    let mut checked = HashMap::new();
+    for (name, hash) in tips {
+        let record_violations = match checked.get(&hash) {
+            Some(record_violations) => record_violations,
+            None => {
+                let record_violations = attestation_policy.check(repo, &hash).await?;
+                checked.entry(hash).or_insert(record_violations)
+            }
+        };
+        violations.extend(
+            record_violations
+                .iter()
+                .map(|violation| format!("{}: {}", name, violation)),
+        );
+    }
+    Ok(violations)
+}
+
 /// `sync_with` syncs repository with client.
 ///
 /// - round 1: sync records. server send all records to client, client get records,
-///     and diff its own records with server's records, then send diff records to server.
-///     then server got the `wanted_records` and `will_given_records`
+///   and diff its own records with server's records, then send diff records to server.
+///   then server got the `wanted_records` and `will_given_records`
 /// - round 2: sync trees. server send all trees of `wanted_records` recursively to client,
-///     client get trees, and diff its own trees with server's trees, then send diff trees to server.
+///   client get trees, and diff its own trees with server's trees, then send diff trees to server.
 /// - round 3: sync blobs list. server send all blobs meta of diff tree to client,
-///     client get blobs meta, and diff its own blobs meta with server's blobs meta, then send diff blobs meta to server.
+///   client get blobs meta, and diff its own blobs meta with server's blobs meta, then send diff blobs meta to server.
 /// - round 4: sync blobs. server send all blobs of diff blobs meta to client,
-///     client send all blobs of diff blobs meta to server.
-/// - end process: server store all trees and blobs, then store all records.
+///   client send all blobs of diff blobs meta to server, kept in the partial dir.
+/// - round 5: sync attestations. server send all attestations to client,
+///   client store the missing ones and send back attestations that server does not have.
+/// - round 6: sync notes. same as round 5, but for notes.
+/// - round 7: sync tags. server send all tags to client, client send back tags with
+///   names that server does not have. existing tags are never moved by sync.
+/// - round 8: sync branches. server send all branches to client, client send back the
+///   branches chosen by its push refspecs. a branch is only created or moved forward,
+///   unless it is pushed with a lease and still at it.
+/// - round 9: report. server check pushed records against `policy.toml` and leases of
+///   pushed branches against the branches, then send a `SyncReport` to client, pushed
+///   trees, blobs, records, tags and branches are not stored if rejected, and the
+///   received blobs are removed.
+/// - end process: server store all trees and blobs, then store all records, tags and
+///   branches, update `stats.json` and `search.json`, move the worktree configured in
+///   `live.toml` forward, and send notifications configured in `notify.toml` about the
+///   pushed records.
 ///
 /// when failed, both server and client should cleanup all temp files.
 ///
 /// ## arguments
 ///
 /// * `repo` - repository to sync with.
-/// * `ws` - websocket connection from axum.
-pub async fn sync_with(repo: &Repository, ws: &mut WebSocket) -> Result<(), WsvcServerError> {
-    let guard = RepoGuard::new(repo)
-        .await
-        .map_err(|err| WsvcError::FsError(err))?;
-    let (wanted_records, given_records) = sync_records(repo, ws).await?;
-    let (wanted_trees, given_trees) = sync_trees(repo, ws, wanted_records.as_slice()).await?;
+/// * `ws` - frame stream connected to the client, e.g. websocket connection from axum.
+pub async fn sync_with(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+) -> Result<(), WsvcServerError> {
+    sync_with_limits(repo, ws, &SessionLimits::default()).await
+}
+
+/// `sync_with_limits` syncs repository with client like `sync_with`, within `limits`.
+pub async fn sync_with_limits(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+    limits: &SessionLimits,
+) -> Result<(), WsvcServerError> {
+    sync_session(repo, ws, limits, None, None, None).await
+}
+
+/// `sync_with_auth` syncs repository with client like `sync_with_limits`, once the client
+/// authenticates with a credential accepted by `verifier`. the client is refused before
+/// anything of the repository but its id is sent.
+pub async fn sync_with_auth(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+    limits: &SessionLimits,
+    verifier: &dyn CredentialVerifier,
+) -> Result<(), WsvcServerError> {
+    sync_session(repo, ws, limits, Some(verifier), None, None).await
+}
+
+/// `sync_with_access` syncs repository with client like `sync_with_limits`, letting the
+/// client read or write as `access` allows the account it authenticates as with
+/// `verifier`. without a `verifier` every client is anonymous. clients without an
+/// accepted credential only read public repositories, and clients allowed only to read
+/// are refused if they push anything, before any of it is stored.
+pub async fn sync_with_access(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+    limits: &SessionLimits,
+    verifier: Option<&dyn CredentialVerifier>,
+    access: &RepoAccess,
+) -> Result<(), WsvcServerError> {
+    sync_session(repo, ws, limits, verifier, Some(access), None).await
+}
+
+/// `sync_with_meter` syncs repository with client like `sync_with_access`, and accounts
+/// the transfer and the storage added by the client in the ledger of `meter`, as the
+/// account it authenticates as or `ANONYMOUS_USER`. a client whose monthly transfer cap
+/// is used up is refused, and the sync fails once the cap is exceeded. transfer of failed
+/// syncs is accounted too.
+pub async fn sync_with_meter(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+    limits: &SessionLimits,
+    verifier: Option<&dyn CredentialVerifier>,
+    access: Option<&RepoAccess>,
+    meter: &UsageMeter<'_>,
+) -> Result<(), WsvcServerError> {
+    sync_session(repo, ws, limits, verifier, access, Some(meter)).await
+}
+
+async fn sync_session(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+    limits: &SessionLimits,
+    verifier: Option<&dyn CredentialVerifier>,
+    access: Option<&RepoAccess>,
+    meter: Option<&UsageMeter<'_>>,
+) -> Result<(), WsvcServerError> {
+    let guard = RepoGuard::new(repo).await.map_err(WsvcError::FsError)?;
+    let Some(meter) = meter else {
+        let session = sync_identity(repo, ws, limits, verifier, access, None).await?;
+        return sync_rounds(repo, ws, limits, session, guard).await;
+    };
+    // the parallel file transfer of the stream is not used, files are counted as frames.
+    let mut metered = MeteredStream::new(ws, None);
+    let session = sync_identity(repo, &mut metered, limits, verifier, access, Some(meter)).await?;
+    let user = session
+        .account
+        .clone()
+        .unwrap_or_else(|| ANONYMOUS_USER.to_owned());
+    let limit = meter.remaining(&user).await;
+    if limit == Some(0) {
+        return Err(WsvcServerError::Rejected(format!(
+            "monthly transfer cap of {} is used up",
+            user
+        )));
+    }
+    metered.set_limit(limit);
+    let store_size = RepoStats::load(repo).await?.store_size;
+    let result = sync_rounds(repo, &mut metered, limits, session, guard).await;
+    let storage = match result {
+        Ok(_) => RepoStats::load(repo)
+            .await?
+            .store_size
+            .saturating_sub(store_size),
+        Err(_) => 0,
+    };
+    meter
+        .ledger
+        .record(&user, metered.transfer, storage)
+        .await?;
+    result
+}
+
+/// the rounds of a sync after the identity round, until the pushed records are stored.
+async fn sync_rounds(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+    limits: &SessionLimits,
+    session: ClientSession,
+    guard: RepoGuard,
+) -> Result<(), WsvcServerError> {
+    let ClientSession {
+        identity: client,
+        codec,
+        protocol,
+        read_only,
+        ..
+    } = session;
+    let ws = &mut CodecStream::new(ws, codec.as_ref(), protocol)?;
+    let (wanted_records, given_records) = sync_records(repo, ws, limits).await?;
+    check_read_only(read_only, given_records.len(), "records")?;
+    let (wanted_trees, given_trees) =
+        sync_trees(repo, ws, limits, wanted_records.as_slice()).await?;
     let (wanted_blobs, will_given_blobs) =
-        sync_blobs_meta(repo, ws, wanted_trees.as_slice()).await?;
+        sync_blobs_meta(repo, ws, limits, wanted_trees.as_slice()).await?;
+    check_read_only(read_only, will_given_blobs.len(), "blobs")?;
+    // given trees and blobs may be stored already, only new ones are counted in stats.
+    let new_trees = stats::not_stored(
+        &repo.trees_dir().await.map_err(WsvcError::FsError)?,
+        &given_trees,
+        |t| t.hash.0.to_hex().to_string(),
+    )
+    .await;
+    let new_blobs = repo
+        .missing_objects(
+            &will_given_blobs
+                .iter()
+                .map(|b| b.hash.clone())
+                .collect::<Vec<_>>(),
+        )
+        .await
+        .map_err(WsvcError::FsError)?
+        .into_iter()
+        .filter_map(|id| will_given_blobs.iter().find(|b| b.hash == id).cloned())
+        .collect::<Vec<_>>();
     // now all wanted trees and blobs are ready in server's and client's memory, now we should sync blob files.
-    sync_blobs(
+    let given_sizes = sync_blobs(
         repo,
         ws,
         wanted_blobs.as_slice(),
         will_given_blobs.as_slice(),
+        client.empty,
+        limits,
+    )
+    .await?;
+    sync_attestations(repo, ws, limits, read_only).await?;
+    sync_notes(repo, ws, limits, read_only).await?;
+    let given_tags = sync_tags(repo, ws, limits).await?;
+    check_read_only(read_only, given_tags.len(), "tags")?;
+    let given_branches = sync_branches(repo, ws, limits).await?;
+    check_read_only(read_only, given_branches.len(), "branches")?;
+    tracing::debug!("ROUND 9: report...");
+    let mut violations = check_policy(
+        repo,
+        &given_records,
+        &given_trees,
+        &given_sizes,
+        &given_tags,
+        &given_branches,
     )
     .await?;
+    violations.extend(repo.check_branch_leases(&given_branches).await?);
+    let report = SyncReport {
+        accepted: violations.is_empty(),
+        violations,
+    };
+    send_data(ws, serde_json::to_string(&report)?.into_bytes()).await?;
+    store_given_blobs(repo, &will_given_blobs, report.accepted).await?;
+    if !report.accepted {
+        return Err(WsvcServerError::Rejected(report.violations.join("; ")));
+    }
 
     // store trees
     tracing::debug!("write trees to tree database...");
-    let trees_dir = repo.trees_dir().await.map_err(WsvcError::FsError)?;
     for tree in &given_trees {
-        write(
-            trees_dir.join(tree.hash.0.to_hex().as_str()),
-            serde_json::to_string(tree)
-                .map_err(|err| WsvcError::FsError(WsvcFsError::SerializationFailed(err)))?,
-        )
-        .await
-        .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
+        repo.store_synced_tree(tree)
+            .await
+            .map_err(WsvcError::FsError)?;
     }
 
     // store records
     tracing::debug!("write records to record database...");
-    let records_dir = repo.records_dir().await.map_err(WsvcError::FsError)?;
     for record in &given_records {
-        write(
-            records_dir.join(record.hash.0.to_hex().as_str()),
-            serde_json::to_string(record)
-                .map_err(|err| WsvcError::FsError(WsvcFsError::SerializationFailed(err)))?,
-        )
-        .await
-        .map_err(|err| WsvcError::FsError(WsvcFsError::Os(err)))?;
+        repo.store_record(record)
+            .await
+            .map_err(WsvcError::FsError)?;
     }
 
+    // store tags
+    repo.store_sync_tags(&given_tags).await?;
+    repo.store_sync_branches(&given_branches).await?;
+
+    if !given_records.is_empty() {
+        stats::record_push(repo, &given_records, &new_trees, &new_blobs).await;
+        search::record_push(repo, &given_records).await;
+    }
+    if !given_records.is_empty() || !given_branches.is_empty() {
+        live::record_push(repo).await;
+    }
     drop(guard);
+    if !given_records.is_empty() {
+        let repo = repo.clone();
+        tokio::spawn(async move { packs::record_push(&repo).await });
+    }
+    notify::notify_pushed(repo, &given_records).await;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::branch::Branch;
+
+    #[tokio::test]
+    async fn backdated_tips_need_attestations() {
+        let dir = std::env::temp_dir().join(format!("wsvc-test-{}", nanoid!()));
+        create_dir_all(&dir).await.unwrap();
+        let repo = Repository::new(&dir, false).await.unwrap();
+        tokio::fs::write(dir.join("a"), "a").await.unwrap();
+        let latest = repo.commit_record(&dir, "me", "a", None).await.unwrap();
+        let mut backdated = Record {
+            hash: Default::default(),
+            message: "b".to_owned(),
+            author: "me".to_owned(),
+            date: latest.date - chrono::Duration::days(1),
+            root: latest.root.clone(),
+            parents: Some(vec![latest.hash.clone()]),
+        };
+        backdated.hash = backdated.calc_hash().unwrap();
+        let branches = [SyncBranch {
+            branch: Branch {
+                name: "main".to_owned(),
+                record: backdated.hash.clone(),
+            },
+            lease: None,
+        }];
+        let write_policy = |protected_refs: &str| {
+            std::fs::write(
+                repo.path.join(policy::POLICY_FILE),
+                format!(
+                    "[attestation]\nrequired = 1\nrequire_signature = true\nprotected_refs = {}\n",
+                    protected_refs
+                ),
+            )
+            .unwrap()
+        };
+        write_policy("[]");
+        let records = [backdated];
+        let violations = check_policy(&repo, &records, &[], &HashMap::new(), &[], &branches)
+            .await
+            .unwrap();
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].starts_with("branch main: "));
+        write_policy("[\"refs/release\"]");
+        let violations = check_policy(&repo, &records, &[], &HashMap::new(), &[], &branches)
+            .await
+            .unwrap();
+        assert!(violations.is_empty());
+        std::fs::write(
+            repo.path.join(policy::POLICY_FILE),
+            "[attestation]\nrequired = 1\n",
+        )
+        .unwrap();
+        assert!(policy::RepoPolicy::load(&repo).await.is_err());
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+}
@@ -0,0 +1,306 @@
+use std::process::Stdio;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    process::Command,
+};
+
+use crate::{
+    fs::WsvcFsError,
+    model::{Record, Repository},
+    WsvcError,
+};
+
+/// file name of the notification config in the server side repository dir.
+pub const NOTIFY_FILE: &str = "notify.toml";
+
+const DEFAULT_SUBJECT: &str = "[{repo}] {count} new records";
+
+const DEFAULT_TEMPLATE: &str = "{count} new records were pushed to {repo}:\n\n{records}\n";
+
+/// `NotifyConfig` stand for notifications sent after a push is stored.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct NotifyConfig {
+    #[serde(default)]
+    pub email: Option<EmailConfig>,
+}
+
+/// `EmailConfig` sends a summary of pushed records to `recipients`.
+///
+/// mails are sent to the `smtp` relay if set, or piped to `sendmail` otherwise.
+/// `{repo}`, `{count}` and `{records}` in subject and template are replaced.
+///
+/// ```toml
+/// [email]
+/// from = "wsvc@example.com"
+/// recipients = ["team@example.com"]
+/// smtp = "localhost:25"
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EmailConfig {
+    pub from: String,
+    pub recipients: Vec<String>,
+    /// address of a smtp relay that accepts mails without authentication.
+    #[serde(default)]
+    pub smtp: Option<String>,
+    /// sendmail compatible program, `sendmail` by default.
+    #[serde(default)]
+    pub sendmail: Option<String>,
+    #[serde(default)]
+    pub subject: Option<String>,
+    #[serde(default)]
+    pub template: Option<String>,
+}
+
+/// `Mail` stand for a plain text mail.
+#[derive(Clone, Debug)]
+pub struct Mail {
+    pub from: String,
+    pub to: Vec<String>,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Mail {
+    /// render the mail with headers, lines are separated by CRLF.
+    pub fn render(&self) -> String {
+        let mut result = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+            self.from,
+            self.to.join(", "),
+            self.subject,
+            chrono::Utc::now().to_rfc2822()
+        );
+        for line in self.body.lines() {
+            result.push_str(line);
+            result.push_str("\r\n");
+        }
+        result
+    }
+}
+
+/// `Mailer` delivers a mail.
+#[async_trait]
+pub trait Mailer {
+    async fn send(&self, mail: &Mail) -> Result<(), WsvcError>;
+}
+
+/// `SendmailMailer` pipes mails to `sendmail -t -i`.
+pub struct SendmailMailer {
+    pub program: String,
+}
+
+#[async_trait]
+impl Mailer for SendmailMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), WsvcError> {
+        let mut child = Command::new(&self.program)
+            .args(["-t", "-i"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|err| {
+                WsvcError::DataError(format!("failed to run {}: {}", self.program, err))
+            })?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(mail.render().as_bytes())
+                .await
+                .map_err(WsvcFsError::Os)?;
+        }
+        if !child.wait().await.map_err(WsvcFsError::Os)?.success() {
+            return Err(WsvcError::DataError(format!(
+                "{} failed to send mail",
+                self.program
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// `SmtpMailer` talks plain smtp to a relay.
+pub struct SmtpMailer {
+    pub addr: String,
+}
+
+/// read a smtp reply, multiline replies are joined.
+async fn read_reply(stream: &mut BufReader<TcpStream>) -> Result<(u16, String), WsvcError> {
+    let mut reply = String::new();
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await.map_err(WsvcFsError::Os)? == 0 {
+            return Err(WsvcError::DataError("smtp connection closed".to_owned()));
+        }
+        reply.push_str(&line);
+        // `250-` continues, `250 ` ends a reply.
+        if line.len() < 4 || line.as_bytes()[3] != b'-' {
+            let code = line
+                .get(0..3)
+                .and_then(|code| code.parse().ok())
+                .ok_or(WsvcError::DataError(format!("bad smtp reply: {}", line)))?;
+            return Ok((code, reply));
+        }
+    }
+}
+
+/// send a smtp command and check the reply code.
+async fn command(
+    stream: &mut BufReader<TcpStream>,
+    line: &str,
+    expected: u16,
+) -> Result<(), WsvcError> {
+    stream
+        .get_mut()
+        .write_all(format!("{}\r\n", line).as_bytes())
+        .await
+        .map_err(WsvcFsError::Os)?;
+    let (code, reply) = read_reply(stream).await?;
+    if code != expected {
+        return Err(WsvcError::DataError(format!(
+            "smtp `{}` failed: {}",
+            line,
+            reply.trim()
+        )));
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), WsvcError> {
+        let stream = TcpStream::connect(&self.addr)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        let mut stream = BufReader::new(stream);
+        let (code, reply) = read_reply(&mut stream).await?;
+        if code != 220 {
+            return Err(WsvcError::DataError(format!(
+                "smtp server not ready: {}",
+                reply.trim()
+            )));
+        }
+        command(&mut stream, "HELO wsvc", 250).await?;
+        command(&mut stream, &format!("MAIL FROM:<{}>", mail.from), 250).await?;
+        for to in &mail.to {
+            command(&mut stream, &format!("RCPT TO:<{}>", to), 250).await?;
+        }
+        command(&mut stream, "DATA", 354).await?;
+        // dot-stuffing, a line starting with `.` is escaped with another `.`.
+        let data = mail
+            .render()
+            .split("\r\n")
+            .map(|line| {
+                if line.starts_with('.') {
+                    format!(".{}", line)
+                } else {
+                    line.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\r\n");
+        stream
+            .get_mut()
+            .write_all(data.as_bytes())
+            .await
+            .map_err(WsvcFsError::Os)?;
+        command(&mut stream, ".", 250).await?;
+        command(&mut stream, "QUIT", 221).await.ok();
+        Ok(())
+    }
+}
+
+impl NotifyConfig {
+    /// load notification config of a repository, an empty config is returned if there is
+    /// no config file.
+    pub async fn load(repo: &Repository) -> Result<Self, WsvcError> {
+        let path = repo.path.join(NOTIFY_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        Ok(toml::from_str(&content)?)
+    }
+}
+
+impl EmailConfig {
+    /// render the summary mail of pushed `records`.
+    pub fn render(&self, repo_name: &str, records: &[Record]) -> Mail {
+        let mut records = records.to_vec();
+        records.sort_by_key(|r| r.date);
+        let summary = records
+            .iter()
+            .map(|r| {
+                format!(
+                    "{} {} {}: {}",
+                    &r.hash.0.to_hex()[0..6],
+                    r.date.naive_local(),
+                    r.author,
+                    r.message
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let fill = |template: &str| {
+            template
+                .replace("{repo}", repo_name)
+                .replace("{count}", &records.len().to_string())
+                .replace("{records}", &summary)
+        };
+        Mail {
+            from: self.from.clone(),
+            to: self.recipients.clone(),
+            subject: fill(self.subject.as_deref().unwrap_or(DEFAULT_SUBJECT)),
+            body: fill(self.template.as_deref().unwrap_or(DEFAULT_TEMPLATE)),
+        }
+    }
+
+    /// build the mailer of this config.
+    pub fn mailer(&self) -> Box<dyn Mailer + Send + Sync> {
+        match &self.smtp {
+            Some(addr) => Box::new(SmtpMailer { addr: addr.clone() }),
+            None => Box::new(SendmailMailer {
+                program: self
+                    .sendmail
+                    .clone()
+                    .unwrap_or_else(|| "sendmail".to_owned()),
+            }),
+        }
+    }
+}
+
+/// send notifications about pushed `records`, failures are logged and ignored.
+pub async fn notify_pushed(repo: &Repository, records: &[Record]) {
+    if records.is_empty() {
+        return;
+    }
+    let config = match NotifyConfig::load(repo).await {
+        Ok(config) => config,
+        Err(err) => {
+            tracing::warn!("failed to load notify config: {}", err);
+            return;
+        }
+    };
+    if let Some(email) = &config.email {
+        if email.recipients.is_empty() {
+            return;
+        }
+        let repo_path = if repo.path.ends_with(".wsvc") {
+            repo.path.parent().unwrap_or(&repo.path)
+        } else {
+            &repo.path
+        };
+        let repo_name = repo_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mail = email.render(&repo_name, records);
+        if let Err(err) = email.mailer().send(&mail).await {
+            tracing::warn!("failed to send push notification: {}", err);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::{auth::SyncCredential, model::Repository, WsvcError};
+
+use super::auth::CredentialVerifier;
+
+/// keys of the issuer are fetched again after this long, issuers rotate them.
+const JWKS_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// keys are fetched again for a token signed by an unknown key at most this often, so
+/// forged tokens do not hammer the issuer.
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(60);
+
+/// algorithms of tokens accepted, all signed by keys of the issuer. tokens signed with a
+/// shared secret or not signed are refused.
+const ALGORITHMS: &[Algorithm] = &[
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+fn default_account_claim() -> String {
+    "preferred_username".to_owned()
+}
+
+/// `OidcConfig` stand for the openid connect issuer whose tokens a server accepts.
+///
+/// ```toml
+/// issuer = "https://accounts.example.com"
+/// audience = "wsvc"
+/// claim = "email"
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct OidcConfig {
+    /// url of the issuer, its discovery document is at
+    /// `<issuer>/.well-known/openid-configuration`.
+    pub issuer: String,
+    /// audience tokens must be issued for, usually the client id of wsvc at the issuer.
+    pub audience: String,
+    /// claim holding the account of a token, `preferred_username` if not set.
+    #[serde(default = "default_account_claim")]
+    pub claim: String,
+}
+
+#[derive(Deserialize)]
+struct Discovery {
+    jwks_uri: String,
+}
+
+/// `OidcVerifier` validates bearer tokens of an openid connect issuer, e.g. keycloak,
+/// dex or a cloud identity provider, so a server needs no accounts of its own. a token is
+/// accepted if it is signed by a key of the issuer, issued by it for the audience and not
+/// expired. clients send the token as the password of the account it names, and http
+/// requests as `Authorization: Bearer <token>`.
+pub struct OidcVerifier {
+    pub config: OidcConfig,
+    client: reqwest::Client,
+    /// keys of the issuer and when they were fetched.
+    keys: RwLock<Option<(Instant, JwkSet)>>,
+}
+
+fn issuer_error(config: &OidcConfig, err: impl std::fmt::Display) -> WsvcError {
+    WsvcError::DataError(format!("oidc issuer {}: {}", config.issuer, err))
+}
+
+impl OidcVerifier {
+    pub fn new(config: OidcConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+            keys: RwLock::new(None),
+        }
+    }
+
+    async fn get_json<T: for<'de> Deserialize<'de>>(&self, url: &str) -> Result<T, WsvcError> {
+        let body = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| issuer_error(&self.config, err))?
+            .bytes()
+            .await
+            .map_err(|err| issuer_error(&self.config, err))?;
+        serde_json::from_slice(&body).map_err(|err| issuer_error(&self.config, err))
+    }
+
+    /// fetch the keys of the issuer from the `jwks_uri` of its discovery document.
+    async fn fetch_keys(&self) -> Result<JwkSet, WsvcError> {
+        let discovery: Discovery = self
+            .get_json(&format!(
+                "{}/.well-known/openid-configuration",
+                self.config.issuer.trim_end_matches('/')
+            ))
+            .await?;
+        self.get_json(&discovery.jwks_uri).await
+    }
+
+    /// the key `kid` of the issuer, fetching the keys again if they are stale or lack it.
+    async fn key(&self, kid: Option<&str>) -> Result<Option<Jwk>, WsvcError> {
+        let find = |keys: &JwkSet| match kid {
+            Some(kid) => keys.find(kid).cloned(),
+            None => keys.keys.first().cloned(),
+        };
+        if let Some((fetched, keys)) = self.keys.read().await.as_ref() {
+            let key = find(keys);
+            let fresh = fetched.elapsed() < JWKS_TTL;
+            if (key.is_some() && fresh) || fetched.elapsed() < JWKS_MIN_REFRESH {
+                return Ok(key);
+            }
+        }
+        let keys = self.fetch_keys().await?;
+        let key = find(&keys);
+        *self.keys.write().await = Some((Instant::now(), keys));
+        Ok(key)
+    }
+
+    /// the account of `token` if it is a valid token of the issuer, `None` if not. errors
+    /// of reaching the issuer fail.
+    pub async fn validate(&self, token: &str) -> Result<Option<String>, WsvcError> {
+        let Ok(header) = decode_header(token) else {
+            return Ok(None);
+        };
+        if !ALGORITHMS.contains(&header.alg) {
+            return Ok(None);
+        }
+        let Some(jwk) = self.key(header.kid.as_deref()).await? else {
+            return Ok(None);
+        };
+        let Ok(key) = DecodingKey::from_jwk(&jwk) else {
+            return Ok(None);
+        };
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.config.issuer]);
+        validation.set_audience(&[&self.config.audience]);
+        let Ok(data) = decode::<HashMap<String, serde_json::Value>>(token, &key, &validation)
+        else {
+            return Ok(None);
+        };
+        Ok(data
+            .claims
+            .get(&self.config.claim)
+            .and_then(|account| account.as_str())
+            .map(|account| account.to_owned()))
+    }
+}
+
+#[async_trait]
+impl CredentialVerifier for OidcVerifier {
+    async fn verify(
+        &self,
+        _repo: &Repository,
+        credential: &SyncCredential,
+    ) -> Result<bool, WsvcError> {
+        Ok(self.validate(&credential.password).await?.as_deref() == Some(&credential.account))
+    }
+}
@@ -0,0 +1,125 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use tokio::fs::{create_dir_all, read_dir, remove_file, rename};
+
+use crate::{
+    fs::WsvcFsError,
+    model::{ObjectId, Repository},
+    pack::read_index,
+    WsvcError,
+};
+
+/// dir of the cached packs sent to new clones, in the server side repository dir.
+pub const PACK_CACHE_DIR: &str = "pack-cache";
+
+/// the cached pack of the latest record of `repo` and its index, `None` if the repository
+/// has no record.
+async fn cache_paths(repo: &Repository) -> Result<Option<(PathBuf, PathBuf)>, WsvcError> {
+    let Some(tip) = repo.get_latest_record().await? else {
+        return Ok(None);
+    };
+    let path = repo
+        .path
+        .join(PACK_CACHE_DIR)
+        .join(tip.hash.0.to_hex().as_str());
+    Ok(Some((
+        path.with_extension("pack"),
+        path.with_extension("idx"),
+    )))
+}
+
+/// whether the pack of the index at `index` holds every blob of `blobs`.
+async fn covers(index: &Path, blobs: &[ObjectId]) -> Result<bool, WsvcFsError> {
+    let packed = read_index(index)
+        .await?
+        .into_iter()
+        .map(|(hash, _)| hash)
+        .collect::<HashSet<_>>();
+    Ok(blobs.iter().all(|blob| packed.contains(blob.0.as_bytes())))
+}
+
+/// build a pack of `blobs` and move it to `pack` with its index at `index`, the index is
+/// moved last so a pack is never read without it.
+async fn build(
+    repo: &Repository,
+    blobs: &[ObjectId],
+    pack: &Path,
+    index: &Path,
+) -> Result<(), WsvcError> {
+    let (temp_pack, temp_index) = repo.export_pack(blobs).await?;
+    if let Some(dir) = pack.parent() {
+        create_dir_all(dir).await.map_err(WsvcFsError::Os)?;
+    }
+    rename(&temp_pack, pack).await.map_err(WsvcFsError::Os)?;
+    rename(&temp_index, index).await.map_err(WsvcFsError::Os)?;
+    Ok(())
+}
+
+/// the pack of `blobs` to send to a new clone with its index, from the cache of the
+/// latest record of `repo`, built and cached if it is missing or lacks some blobs.
+///
+/// concurrent clones of a popular repository are served the same files instead of each
+/// enumerating and packing every object again.
+pub async fn cached_pack(
+    repo: &Repository,
+    blobs: &[ObjectId],
+) -> Result<Option<(PathBuf, PathBuf)>, WsvcError> {
+    let Some((pack, index)) = cache_paths(repo).await? else {
+        return Ok(None);
+    };
+    if pack.exists() && index.exists() && covers(&index, blobs).await? {
+        tracing::debug!("serve cached pack {}", pack.display());
+        return Ok(Some((pack, index)));
+    }
+    build(repo, blobs, &pack, &index).await?;
+    Ok(Some((pack, index)))
+}
+
+/// all blobs of the records of `repo`, what a new clone wants.
+async fn all_blobs(repo: &Repository) -> Result<Vec<ObjectId>, WsvcError> {
+    let mut seen = HashSet::new();
+    let mut blobs = vec![];
+    for record in repo.get_records().await? {
+        for tree in repo.get_trees_of_record(&record.hash).await? {
+            for blob in repo.get_blobs_of_tree(&tree.hash).await? {
+                if seen.insert(blob.hash.clone()) {
+                    blobs.push(blob.hash);
+                }
+            }
+        }
+    }
+    Ok(blobs)
+}
+
+/// build the cached pack of the new latest record after a push, if the repository has
+/// cached packs, i.e. it was cloned since the cache was emptied, and remove the packs of
+/// older records. failures are logged, the pack is built by the next clone then.
+pub async fn record_push(repo: &Repository) {
+    let dir = repo.path.join(PACK_CACHE_DIR);
+    if !dir.exists() {
+        return;
+    }
+    let result = async {
+        let Some((pack, index)) = cache_paths(repo).await? else {
+            return Ok(());
+        };
+        if !index.exists() {
+            build(repo, &all_blobs(repo).await?, &pack, &index).await?;
+        }
+        let mut entries = read_dir(&dir).await.map_err(WsvcFsError::Os)?;
+        while let Some(entry) = entries.next_entry().await.map_err(WsvcFsError::Os)? {
+            let path = entry.path();
+            if path != pack && path != index {
+                remove_file(&path).await.map_err(WsvcFsError::Os)?;
+            }
+        }
+        Ok::<_, WsvcError>(())
+    }
+    .await;
+    if let Err(err) = result {
+        tracing::warn!("failed to update cached pack: {}", err);
+    }
+}
@@ -0,0 +1,362 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    fs::WsvcFsError,
+    model::{Blob, ObjectId, Record, Repository, Tree},
+    sign::{GpgKey, GpgVerifier, SignatureKind, SshVerifier},
+    WsvcError,
+};
+
+/// file name of the policy in the server side repository dir.
+pub const POLICY_FILE: &str = "policy.toml";
+
+/// `RepoPolicy` stand for rules enforced by the server when ingesting a push.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RepoPolicy {
+    #[serde(default)]
+    pub attestation: Option<AttestationPolicy>,
+    #[serde(default)]
+    pub content: Option<ContentPolicy>,
+}
+
+fn default_gpg_program() -> String {
+    "gpg".to_owned()
+}
+
+/// `AttestationPolicy` requires attestations on a record before it becomes the tip.
+///
+/// the new latest record of a push and the new records of the protected branches and
+/// tags it moves or creates are checked, whatever their dates.
+///
+/// ```toml
+/// [attestation]
+/// required = 1
+/// kinds = ["reviewed-by"]
+/// signers = ["alice@example.com", "0123456789ABCDEF0123456789ABCDEF01234567"]
+/// require_signature = true
+/// allowed_signers = "/etc/wsvc/allowed_signers"
+/// protected_refs = ["refs/main", "tags/v*"]
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AttestationPolicy {
+    /// minimum count of distinct signers of valid attestations, needs `require_signature`.
+    #[serde(default)]
+    pub required: usize,
+    /// attestation kinds that are counted, all kinds are counted if empty.
+    #[serde(default)]
+    pub kinds: Vec<String>,
+    /// signers that must have signed a valid attestation, ssh principals of
+    /// `allowed_signers`, or fingerprints or long key ids of gpg keys.
+    #[serde(default)]
+    pub signers: Vec<String>,
+    /// acknowledges unsigned attestations are ignored, needed by `required`.
+    #[serde(default)]
+    pub require_signature: bool,
+    /// ssh allowed signers file, ssh signatures are treated as invalid if not set.
+    #[serde(default)]
+    pub allowed_signers: Option<PathBuf>,
+    #[serde(default = "default_gpg_program")]
+    pub gpg_program: String,
+    /// patterns of refs whose new records are checked, e.g. `refs/main` for branch `main`
+    /// or `tags/v*`, matched like `ContentPolicy` patterns. all branches and tags if empty.
+    #[serde(default)]
+    pub protected_refs: Vec<String>,
+}
+
+/// `ContentPolicy` restricts the files of pushed records.
+///
+/// patterns are matched against the whole path relative to workspace, `*` matches any
+/// characters including `/` and `?` matches one character.
+///
+/// ```toml
+/// [content]
+/// max_file_size = 10485760
+/// forbidden_extensions = ["exe", "pem"]
+/// required_paths = ["LICENSE"]
+/// denied_patterns = ["secrets/*", "*.key"]
+/// ```
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ContentPolicy {
+    /// max size in bytes of a pushed file.
+    #[serde(default)]
+    pub max_file_size: Option<u64>,
+    /// file extensions that can not be pushed, without the leading dot.
+    #[serde(default)]
+    pub forbidden_extensions: Vec<String>,
+    /// paths that must exist in every pushed record.
+    #[serde(default)]
+    pub required_paths: Vec<String>,
+    /// path patterns that can not be pushed.
+    #[serde(default)]
+    pub denied_patterns: Vec<String>,
+}
+
+/// match `path` with a wildcard `pattern`.
+pub fn match_pattern(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let path = path.chars().collect::<Vec<_>>();
+    // position of the last `*` in pattern and the matched position in path.
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut s) = (0, 0);
+    while s < path.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == path[s]) {
+            p += 1;
+            s += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, s));
+            p += 1;
+        } else if let Some((star_p, star_s)) = star {
+            p = star_p + 1;
+            s = star_s + 1;
+            star = Some((star_p, star_s + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// collect all blobs of `tree` with their paths, pushed trees are looked up first.
+#[async_recursion::async_recursion]
+pub(crate) async fn collect_blobs(
+    repo: &Repository,
+    pushed_trees: &HashMap<String, &Tree>,
+    tree_hash: &ObjectId,
+    prefix: &str,
+    result: &mut Vec<(String, Blob)>,
+) -> Result<(), WsvcError> {
+    let tree = match pushed_trees.get(&tree_hash.0.to_hex().to_string()) {
+        Some(tree) => (*tree).clone(),
+        None => repo.read_tree(tree_hash).await?,
+    };
+    for blob in tree.blobs() {
+        result.push((format!("{}{}", prefix, blob.name), blob));
+    }
+    for dir in tree.dirs() {
+        // pushed trees of older clients only know names of dirs by their trees.
+        let name = match (
+            dir.name.as_str(),
+            pushed_trees.get(&dir.hash.0.to_hex().to_string()),
+        ) {
+            ("", Some(tree)) => tree.name.clone(),
+            ("", None) => repo.read_tree(&dir.hash).await?.name,
+            (name, _) => name.to_owned(),
+        };
+        collect_blobs(
+            repo,
+            pushed_trees,
+            &dir.hash,
+            &format!("{}{}/", prefix, name),
+            result,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+impl ContentPolicy {
+    /// check files of pushed records, returns violations prefixed with the file path.
+    ///
+    /// `pushed_trees` are the trees not stored yet, and only the pushed blobs are checked
+    /// against `max_file_size`, by `pushed_sizes` measured when they were received, since
+    /// other blobs were accepted before. no blob is read.
+    pub async fn check(
+        &self,
+        repo: &Repository,
+        pushed_records: &[Record],
+        pushed_trees: &[Tree],
+        pushed_sizes: &HashMap<ObjectId, u64>,
+    ) -> Result<Vec<String>, WsvcError> {
+        let pushed_trees = pushed_trees
+            .iter()
+            .map(|t| (t.hash.0.to_hex().to_string(), t))
+            .collect::<HashMap<_, _>>();
+        let mut violations = Vec::new();
+        let mut push_violation = |violation: String| {
+            if !violations.contains(&violation) {
+                violations.push(violation);
+            }
+        };
+        for record in pushed_records {
+            let mut blobs = Vec::new();
+            collect_blobs(repo, &pushed_trees, &record.root, "", &mut blobs).await?;
+            for (path, blob) in &blobs {
+                let extension = path
+                    .rsplit_once('.')
+                    .map(|(_, ext)| ext.to_ascii_lowercase())
+                    .unwrap_or_default();
+                if self
+                    .forbidden_extensions
+                    .iter()
+                    .any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(&extension))
+                {
+                    push_violation(format!("{}: extension `{}` is forbidden", path, extension));
+                }
+                if let Some(pattern) = self.denied_patterns.iter().find(|p| match_pattern(p, path))
+                {
+                    push_violation(format!("{}: path is denied by `{}`", path, pattern));
+                }
+                if let (Some(max_file_size), Some(size)) =
+                    (self.max_file_size, pushed_sizes.get(&blob.hash))
+                {
+                    if *size > max_file_size {
+                        push_violation(format!(
+                            "{}: {} bytes exceeds the limit of {} bytes",
+                            path, size, max_file_size
+                        ));
+                    }
+                }
+            }
+            for required in &self.required_paths {
+                let required = required.trim_matches('/');
+                if !blobs.iter().any(|(path, _)| {
+                    path == required || path.starts_with(&format!("{}/", required))
+                }) {
+                    push_violation(format!(
+                        "{}: required by record {}",
+                        required,
+                        &record.hash.0.to_hex()[0..6]
+                    ));
+                }
+            }
+        }
+        Ok(violations)
+    }
+}
+
+impl RepoPolicy {
+    /// load policy of a repository, an empty policy is returned if there is no policy file.
+    ///
+    /// a policy requiring attestations without `require_signature` is refused, unsigned
+    /// attestations never count so it would reject every push.
+    pub async fn load(repo: &Repository) -> Result<Self, WsvcError> {
+        let path = repo.path.join(POLICY_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        let policy: Self = toml::from_str(&content)?;
+        if let Some(attestation) = &policy.attestation {
+            if attestation.required > 0 && !attestation.require_signature {
+                return Err(WsvcError::LackOfConfig(
+                    format!(
+                        "{} requires attestations without `require_signature`",
+                        POLICY_FILE
+                    ),
+                    "unsigned attestations never count, set `require_signature = true` in its [attestation]"
+                        .to_owned(),
+                ));
+            }
+        }
+        Ok(policy)
+    }
+}
+
+/// `Attester` stand for the verified identity behind a signed attestation.
+enum Attester {
+    /// principal of the allowed signers file the ssh signature was verified with.
+    Ssh(String),
+    /// key the gpg signature was verified with.
+    Gpg(GpgKey),
+}
+
+impl Attester {
+    /// the identity, attestations of the same identity are counted once.
+    fn id(&self) -> String {
+        match self {
+            Self::Ssh(principal) => format!("ssh:{}", principal),
+            Self::Gpg(key) => format!("gpg:{}", key.primary_fingerprint),
+        }
+    }
+
+    /// whether `signer` of the policy names this identity, gpg keys are named by
+    /// fingerprint or long key id.
+    fn matches(&self, signer: &str) -> bool {
+        match self {
+            Self::Ssh(principal) => principal == signer,
+            Self::Gpg(key) => key.matches(signer),
+        }
+    }
+}
+
+impl AttestationPolicy {
+    /// whether records pushed to ref `name`, e.g. `refs/main`, are checked.
+    pub fn protects(&self, name: &str) -> bool {
+        self.protected_refs.is_empty() || self.protected_refs.iter().any(|p| match_pattern(p, name))
+    }
+
+    /// check attestations of a record, returns violations.
+    ///
+    /// `required` counts distinct verified signers, several attestations signed by the
+    /// same key count once. unsigned attestations have no verified signer and never count.
+    pub async fn check(
+        &self,
+        repo: &Repository,
+        record_hash: &ObjectId,
+    ) -> Result<Vec<String>, WsvcError> {
+        let mut attesters: Vec<Attester> = Vec::new();
+        for attestation in repo.get_attestations_of_record(record_hash).await? {
+            if !self.kinds.is_empty() && !self.kinds.contains(&attestation.kind) {
+                continue;
+            }
+            let attester = match attestation.signature.as_ref().map(|s| &s.kind) {
+                Some(SignatureKind::Ssh) => match &self.allowed_signers {
+                    Some(allowed_signers) => attestation
+                        .verify(
+                            repo,
+                            &SshVerifier {
+                                allowed_signers: allowed_signers.clone(),
+                            },
+                        )
+                        .await?
+                        .unwrap_or(false)
+                        .then(|| {
+                            attestation
+                                .signature
+                                .as_ref()
+                                .map(|s| Attester::Ssh(s.signer.clone()))
+                        })
+                        .flatten(),
+                    None => None,
+                },
+                Some(SignatureKind::Gpg) => attestation
+                    .verified_gpg_key(
+                        repo,
+                        &GpgVerifier {
+                            program: self.gpg_program.clone(),
+                        },
+                    )
+                    .await?
+                    .map(Attester::Gpg),
+                None => None,
+            };
+            if let Some(attester) = attester {
+                if !attesters.iter().any(|a| a.id() == attester.id()) {
+                    attesters.push(attester);
+                }
+            }
+        }
+        let mut violations = Vec::new();
+        if attesters.len() < self.required {
+            violations.push(format!(
+                "record {} has valid attestations of {} distinct signers, {} required",
+                record_hash.0,
+                attesters.len(),
+                self.required
+            ));
+        }
+        for signer in &self.signers {
+            if !attesters.iter().any(|a| a.matches(signer)) {
+                violations.push(format!(
+                    "record {} lacks a valid attestation signed by {}",
+                    record_hash.0, signer
+                ));
+            }
+        }
+        Ok(violations)
+    }
+}
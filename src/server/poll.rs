@@ -0,0 +1,165 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use tokio::sync::{mpsc, Mutex};
+
+use crate::{
+    frame::{decode_frames, encode_frames, FrameStream},
+    WsvcError,
+};
+
+/// how long a pull waits for frames before returning an empty batch.
+const PULL_TIMEOUT: Duration = Duration::from_secs(25);
+
+/// how long the sync waits for the client before the session is given up.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// frames buffered in each direction of a session.
+const SESSION_BUFFER: usize = 256;
+
+struct Session {
+    incoming: mpsc::Sender<Vec<u8>>,
+    outgoing: Arc<Mutex<mpsc::Receiver<Vec<u8>>>>,
+}
+
+/// `PollStream` is the server side of a long-poll session, frames pushed by the client
+/// are received from it and frames sent to it are pulled by the client.
+pub struct PollStream {
+    incoming: mpsc::Receiver<Vec<u8>>,
+    outgoing: mpsc::Sender<Vec<u8>>,
+}
+
+#[async_trait]
+impl FrameStream for PollStream {
+    async fn send_frame(&mut self, data: Vec<u8>) -> Result<(), WsvcError> {
+        tokio::time::timeout(IDLE_TIMEOUT, self.outgoing.send(data))
+            .await
+            .map_err(|_| WsvcError::DataError("long-poll client timed out".to_owned()))?
+            .map_err(|_| WsvcError::DataError("long-poll session closed".to_owned()))
+    }
+
+    async fn recv_frame(&mut self) -> Result<Option<Vec<u8>>, WsvcError> {
+        tokio::time::timeout(IDLE_TIMEOUT, self.incoming.recv())
+            .await
+            .map_err(|_| WsvcError::DataError("long-poll client timed out".to_owned()))
+    }
+}
+
+/// `PollHub` serves the sync over plain http for clients whose proxies block websocket.
+///
+/// a client opens a session, pushes its frames with `POST` and pulls the frames of the
+/// server with long-polling `GET`, both carry batches of size prefixed frames. clients
+/// fall back to it at `<sync url>/poll` when the websocket upgrade fails.
+///
+/// ```ignore
+/// async fn open(Path(name): Path<String>, State(hub): State<Arc<PollHub>>) -> String {
+///     let (id, mut stream) = hub.open().await;
+///     tokio::spawn(async move { sync_with(&repo_of(&name).await, &mut stream).await });
+///     id
+/// }
+///
+/// async fn push(Path((_, id)): Path<(String, String)>, State(hub): State<Arc<PollHub>>, body: Bytes) -> StatusCode {
+///     match hub.push(&id, &body).await {
+///         true => StatusCode::NO_CONTENT,
+///         false => StatusCode::NOT_FOUND,
+///     }
+/// }
+///
+/// async fn pull(Path((_, id)): Path<(String, String)>, State(hub): State<Arc<PollHub>>) -> Result<Vec<u8>, StatusCode> {
+///     hub.pull(&id).await.ok_or(StatusCode::GONE)
+/// }
+///
+/// Router::new()
+///     .route("/repo/:name/poll", post(open))
+///     .route("/repo/:name/poll/:id", post(push).get(pull).delete(close))
+/// ```
+#[derive(Default)]
+pub struct PollHub {
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl PollHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// open a session, returns its id and the stream to run the sync on.
+    pub async fn open(&self) -> (String, PollStream) {
+        let (incoming_tx, incoming_rx) = mpsc::channel(SESSION_BUFFER);
+        let (outgoing_tx, outgoing_rx) = mpsc::channel(SESSION_BUFFER);
+        let id = nanoid::nanoid!();
+        let mut sessions = self.sessions.lock().await;
+        // drop sessions whose sync is over and all frames are pulled, or never will be.
+        sessions.retain(|_, session| {
+            !session.incoming.is_closed()
+                || session
+                    .outgoing
+                    .try_lock()
+                    .map(|outgoing| !outgoing.is_empty())
+                    .unwrap_or(true)
+        });
+        sessions.insert(
+            id.clone(),
+            Session {
+                incoming: incoming_tx,
+                outgoing: Arc::new(Mutex::new(outgoing_rx)),
+            },
+        );
+        tracing::debug!("long-poll session {} is opened", id);
+        (
+            id,
+            PollStream {
+                incoming: incoming_rx,
+                outgoing: outgoing_tx,
+            },
+        )
+    }
+
+    /// push a batch of frames from the client, `false` if there is no such session or the
+    /// batch is malformed.
+    pub async fn push(&self, id: &str, batch: &[u8]) -> bool {
+        let incoming = match self.sessions.lock().await.get(id) {
+            Some(session) => session.incoming.clone(),
+            None => return false,
+        };
+        let frames = match decode_frames(batch) {
+            Ok(frames) => frames,
+            Err(_) => return false,
+        };
+        for frame in frames {
+            if incoming.send(frame).await.is_err() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// pull a batch of frames for the client, waits until some frames are sent or the poll
+    /// times out with an empty batch. `None` if there is no such session or the sync is
+    /// over and all frames are pulled.
+    pub async fn pull(&self, id: &str) -> Option<Vec<u8>> {
+        let outgoing = self.sessions.lock().await.get(id)?.outgoing.clone();
+        let mut outgoing = outgoing.lock().await;
+        let first = match tokio::time::timeout(PULL_TIMEOUT, outgoing.recv()).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                drop(outgoing);
+                self.close(id).await;
+                return None;
+            }
+            Err(_) => return Some(vec![]),
+        };
+        let mut frames = vec![first];
+        while let Ok(frame) = outgoing.try_recv() {
+            frames.push(frame);
+        }
+        Some(encode_frames(&frames))
+    }
+
+    /// close a session, the sync of it fails if not finished yet.
+    pub async fn close(&self, id: &str) {
+        if self.sessions.lock().await.remove(id).is_some() {
+            tracing::debug!("long-poll session {} is closed", id);
+        }
+    }
+}
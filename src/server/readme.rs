@@ -0,0 +1,119 @@
+use pulldown_cmark::{escape::escape_html, html::push_html, Options, Parser};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    model::{Entry, EntryKind, ObjectId, Record, Repository},
+    WsvcError,
+};
+
+/// names of readmes in a dir, in the order they are preferred, matched ignoring case.
+/// `.md` and `.markdown` are rendered as markdown, the others as plain text.
+const README_NAMES: &[&str] = &["readme.md", "readme.markdown", "readme", "readme.txt"];
+
+/// readmes larger than this are not rendered, a web ui links their blob instead.
+pub const MAX_README_SIZE: u64 = 1024 * 1024;
+
+/// `Readme` stand for the readme of a dir of a record rendered for a web ui, served by
+/// the `readme` endpoint.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Readme {
+    /// hash of the record the dir is in.
+    pub record: String,
+    /// path of the dir in the record, empty for the root dir.
+    pub path: String,
+    /// name of the readme in the dir, e.g. `README.md`.
+    pub name: String,
+    /// hash of the blob of the readme.
+    pub blob: String,
+    /// the readme as html stripped of scripts, styles and event handlers, safe to be
+    /// inserted into a page. relative links are kept, they are relative to the dir.
+    pub html: String,
+}
+
+/// whether `name` is rendered as markdown.
+fn is_markdown(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.ends_with(".md") || name.ends_with(".markdown")
+}
+
+/// the readme among `entries` of a dir, by the order of `README_NAMES`.
+fn pick_readme(entries: &[Entry]) -> Option<&Entry> {
+    README_NAMES.iter().find_map(|readme| {
+        entries
+            .iter()
+            .filter(|e| e.kind == EntryKind::File)
+            .find(|e| e.name.eq_ignore_ascii_case(readme))
+    })
+}
+
+/// render `text` as html safe to be inserted into a page, as markdown or as plain text.
+/// raw html of markdown is kept if it is harmless, e.g. `<details>` or `<img>`, but
+/// scripts, styles, event handlers, forms and `javascript:` links are removed.
+pub fn render_readme(text: &str, markdown: bool) -> String {
+    let mut html = String::new();
+    match markdown {
+        true => {
+            let options =
+                Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_FOOTNOTES;
+            push_html(&mut html, Parser::new_ext(text, options));
+        }
+        false => {
+            html.push_str("<pre>");
+            // writing to a string never fails.
+            escape_html(&mut html, text).ok();
+            html.push_str("</pre>");
+        }
+    }
+    ammonia::clean(&html)
+}
+
+impl Readme {
+    /// the readme of the dir `path` in `record`, `None` if the path is not a dir, the dir
+    /// has no readme or it is larger than `MAX_README_SIZE`.
+    pub async fn load(
+        repo: &Repository,
+        record: &Record,
+        path: &str,
+    ) -> Result<Option<Self>, WsvcError> {
+        let dir = match repo.resolve_tree_path(&record.root, path).await? {
+            Some(entry) if entry.kind == EntryKind::Dir => entry,
+            _ => return Ok(None),
+        };
+        let tree = repo.read_tree(&dir.hash).await?;
+        let Some(entry) = pick_readme(&tree.entries) else {
+            return Ok(None);
+        };
+        if entry.size > MAX_README_SIZE || repo.blob_size(&entry.hash).await? > MAX_README_SIZE {
+            return Ok(None);
+        }
+        let content = repo.read_blob(&entry.hash).await?;
+        let html = render_readme(&String::from_utf8_lossy(&content), is_markdown(&entry.name));
+        Ok(Some(Self {
+            record: record.hash.0.to_hex().to_string(),
+            path: path.trim_matches('/').to_owned(),
+            name: entry.name.clone(),
+            blob: entry.hash.0.to_hex().to_string(),
+            html,
+        }))
+    }
+
+    /// the readme of the dir `path` in the record `record`, or in the tip record of the
+    /// repository, its HEAD or else its latest record. `None` if there is no such record.
+    pub async fn load_at(
+        repo: &Repository,
+        record: Option<&ObjectId>,
+        path: &str,
+    ) -> Result<Option<Self>, WsvcError> {
+        let record = match record {
+            Some(hash) => Some(repo.read_record(hash).await?),
+            None => match repo.get_head_record().await? {
+                Some(head) => Some(head),
+                None => repo.get_latest_record().await?,
+            },
+        };
+        match record {
+            Some(record) => Self::load(repo, &record, path).await,
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use axum::extract::ws::{Message, WebSocket};
+use futures::{SinkExt, StreamExt};
+use tokio::sync::Mutex;
+
+/// text frame sent to both peers when they are paired.
+pub const PAIRED: &str = "wsvc-relay-paired";
+
+/// `RelayHub` pairs two peers joining the same room and forwards frames between them.
+///
+/// the relay never looks into frames, so it stores nothing and works with encrypted
+/// frames too. a peer hosting a repository joins first and waits, the next peer joining
+/// the same room syncs with it.
+///
+/// ```ignore
+/// async fn relay(
+///     ws: WebSocketUpgrade,
+///     Path(room): Path<String>,
+///     State(hub): State<Arc<RelayHub>>,
+/// ) -> Response {
+///     ws.on_upgrade(move |ws| async move { hub.join(room, ws).await })
+/// }
+/// ```
+#[derive(Default)]
+pub struct RelayHub {
+    waiting: Mutex<HashMap<String, WebSocket>>,
+}
+
+impl RelayHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// join `room`, returns when the peer is paired and one side closes, or immediately
+    /// if this is the first peer of the room.
+    pub async fn join(&self, room: String, mut ws: WebSocket) {
+        let peer = {
+            let mut waiting = self.waiting.lock().await;
+            match waiting.remove(&room) {
+                Some(peer) => peer,
+                None => {
+                    tracing::debug!("relay room {} is waiting for a peer", room);
+                    waiting.insert(room, ws);
+                    return;
+                }
+            }
+        };
+        let mut peer = peer;
+        if peer.send(Message::Text(PAIRED.to_owned())).await.is_err() {
+            // the waiting peer is gone, wait for the next one instead.
+            self.waiting.lock().await.insert(room, ws);
+            return;
+        }
+        if ws.send(Message::Text(PAIRED.to_owned())).await.is_err() {
+            return;
+        }
+        tracing::debug!("relay room {} is paired", room);
+        let (mut peer_tx, mut peer_rx) = peer.split();
+        let (mut ws_tx, mut ws_rx) = ws.split();
+        let forward = async {
+            while let Some(Ok(msg)) = peer_rx.next().await {
+                let close = matches!(msg, Message::Close(_));
+                if ws_tx.send(msg).await.is_err() || close {
+                    break;
+                }
+            }
+        };
+        let backward = async {
+            while let Some(Ok(msg)) = ws_rx.next().await {
+                let close = matches!(msg, Message::Close(_));
+                if peer_tx.send(msg).await.is_err() || close {
+                    break;
+                }
+            }
+        };
+        tokio::select! {
+            _ = forward => {},
+            _ = backward => {},
+        }
+        tracing::debug!("relay room {} is closed", room);
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs::{read_to_string, rename, write};
+
+use crate::{
+    fs::WsvcFsError,
+    model::{Record, Repository},
+    query::{words, RecordQuery, RecordsPage},
+    WsvcError,
+};
+
+use super::policy::collect_blobs;
+
+/// file name of the search index in the server side repository dir.
+pub const SEARCH_FILE: &str = "search.json";
+
+/// records returned by a search or a page if no limit is given.
+const DEFAULT_LIMIT: usize = 50;
+
+/// most records of a page, so a client can not have every record sent at once.
+pub const MAX_PAGE_LIMIT: usize = 500;
+
+/// `SearchQuery` stand for the query string of the search endpoint.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    pub limit: Option<usize>,
+}
+
+/// `SearchIndex` is an inverted index of records of a hosted repository.
+///
+/// words of messages and authors are indexed as is, authors as `author:<word>` too, and
+/// paths of files as `path:<path>` and `path:<file name>`. words of a query must all
+/// match, each as a prefix of an indexed term, e.g. `fix author:bob path:src/`.
+///
+/// it is built once by scanning the repository and then updated by each accepted push.
+/// delete `search.json` to have it rebuilt.
+///
+/// ```ignore
+/// async fn search(
+///     Path(name): Path<String>,
+///     Query(query): Query<SearchQuery>,
+/// ) -> Result<Json<Vec<Record>>, StatusCode> {
+///     let repo = repo_of(&name).await.ok_or(StatusCode::NOT_FOUND)?;
+///     match SearchIndex::load(&repo).await {
+///         Ok(index) => Ok(Json(index.search(&query.q, query.limit))),
+///         Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+///     }
+/// }
+///
+/// Router::new().route("/repos/:name/search", get(search))
+/// ```
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SearchIndex {
+    /// indexed records by hash.
+    pub records: HashMap<String, Record>,
+    /// hashes of records by term.
+    pub terms: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl SearchIndex {
+    /// build the index by scanning the whole repository.
+    pub async fn build(repo: &Repository) -> Result<Self, WsvcError> {
+        let mut index = Self::default();
+        for record in repo.get_records().await? {
+            index.add(repo, record).await?;
+        }
+        Ok(index)
+    }
+
+    /// load the index of `repo`, built and saved if missing.
+    pub async fn load(repo: &Repository) -> Result<Self, WsvcError> {
+        let path = repo.path.join(SEARCH_FILE);
+        if path.exists() {
+            let content = read_to_string(&path).await.map_err(WsvcFsError::Os)?;
+            return Ok(serde_json::from_str(&content)?);
+        }
+        let index = Self::build(repo).await?;
+        index.save(repo).await?;
+        Ok(index)
+    }
+
+    /// save the index, readers never see a partially written file.
+    pub async fn save(&self, repo: &Repository) -> Result<(), WsvcError> {
+        let path = repo.path.join(SEARCH_FILE);
+        let temp_path = repo
+            .path
+            .join(format!("{}.{}", SEARCH_FILE, nanoid::nanoid!()));
+        write(&temp_path, serde_json::to_vec(self)?)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        rename(&temp_path, &path).await.map_err(WsvcFsError::Os)?;
+        Ok(())
+    }
+
+    /// index a stored record.
+    pub async fn add(&mut self, repo: &Repository, record: Record) -> Result<(), WsvcError> {
+        let hash = record.hash.0.to_hex().to_string();
+        if self.records.contains_key(&hash) {
+            return Ok(());
+        }
+        let mut terms = BTreeSet::new();
+        terms.extend(words(&record.message));
+        for word in words(&record.author) {
+            terms.insert(format!("author:{}", word));
+            terms.insert(word);
+        }
+        let mut blobs = Vec::new();
+        collect_blobs(repo, &HashMap::new(), &record.root, "", &mut blobs).await?;
+        for (path, blob) in blobs {
+            terms.insert(format!("path:{}", path.to_lowercase()));
+            terms.insert(format!("path:{}", blob.name.to_lowercase()));
+        }
+        for term in terms {
+            self.terms.entry(term).or_default().insert(hash.clone());
+        }
+        self.records.insert(hash, record);
+        Ok(())
+    }
+
+    /// hashes of records having a term starting with `prefix`.
+    fn matches(&self, prefix: &str) -> BTreeSet<&String> {
+        self.terms
+            .range(prefix.to_owned()..)
+            .take_while(|(term, _)| term.starts_with(prefix))
+            .flat_map(|(_, hashes)| hashes)
+            .collect()
+    }
+
+    /// hashes of records matching all words of `query`, `None` if it has no word.
+    fn search_hashes(&self, query: &str) -> Option<BTreeSet<&String>> {
+        let mut result: Option<BTreeSet<&String>> = None;
+        let terms = query.split_whitespace().flat_map(|word| {
+            if word.starts_with("author:") || word.starts_with("path:") {
+                vec![word.to_lowercase()]
+            } else {
+                words(word).collect()
+            }
+        });
+        for term in terms {
+            let matched = self.matches(&term);
+            result = Some(match result {
+                Some(result) => result.intersection(&matched).copied().collect(),
+                None => matched,
+            });
+        }
+        result
+    }
+
+    /// records matching all words of `query`, the latest first.
+    pub fn search(&self, query: &str, limit: Option<usize>) -> Vec<Record> {
+        let mut records = self
+            .search_hashes(query)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|hash| self.records.get(hash))
+            .cloned()
+            .collect::<Vec<_>>();
+        records.sort_by_key(|r| std::cmp::Reverse(r.date));
+        records.truncate(limit.unwrap_or(DEFAULT_LIMIT));
+        records
+    }
+
+    /// the page of records matching `query` after skipping `offset` of them, the latest
+    /// first, of `limit` records up to `MAX_PAGE_LIMIT`.
+    ///
+    /// words of `q` match like `search`, so `author:` and `path:` words work too, and
+    /// records are taken from the index instead of reading every record of the
+    /// repository, e.g. to list records of a bare hosted repository page by page.
+    pub fn page(&self, query: &RecordQuery, offset: usize, limit: Option<usize>) -> RecordsPage {
+        let hashes = query.q.as_deref().and_then(|q| self.search_hashes(q));
+        let mut records = match hashes {
+            Some(hashes) => hashes
+                .into_iter()
+                .filter_map(|hash| self.records.get(hash))
+                .collect::<Vec<_>>(),
+            None => self.records.values().collect(),
+        };
+        records.retain(|record| query.matches_meta(record));
+        records.sort_by(|a, b| {
+            b.date
+                .cmp(&a.date)
+                .then_with(|| b.hash.0.as_bytes().cmp(a.hash.0.as_bytes()))
+        });
+        let total = records.len();
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_PAGE_LIMIT);
+        RecordsPage {
+            total,
+            offset,
+            records: records
+                .into_iter()
+                .skip(offset)
+                .take(limit)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// index records of a push already stored, failures are logged and the index is rebuilt
+/// on next load.
+pub async fn record_push(repo: &Repository, records: &[Record]) {
+    let result = async {
+        let index = if repo.path.join(SEARCH_FILE).exists() {
+            let mut index = SearchIndex::load(repo).await?;
+            for record in records {
+                index.add(repo, record.clone()).await?;
+            }
+            index
+        } else {
+            // the scan already sees the stored push.
+            SearchIndex::build(repo).await?
+        };
+        index.save(repo).await
+    }
+    .await;
+    if let Err(err) = result {
+        tracing::warn!("failed to update search index: {}", err);
+        tokio::fs::remove_file(repo.path.join(SEARCH_FILE))
+            .await
+            .ok();
+    }
+}
@@ -0,0 +1,243 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{metadata, read_dir, read_to_string, rename, write};
+
+use crate::{
+    fs::WsvcFsError,
+    model::{Blob, Record, Repository, Tree},
+    pack::{pack_indexes, read_index, StorageStats},
+    WsvcError,
+};
+
+/// file name of the stats in the server side repository dir.
+pub const STATS_FILE: &str = "stats.json";
+
+/// count of authors in `StatsReport::top_authors`.
+const TOP_AUTHORS: usize = 10;
+
+/// `RepoStats` stand for the statistics of a hosted repository.
+///
+/// it is computed once by scanning the repository and then updated by each accepted push,
+/// so serving it never walks the store. delete `stats.json` to have it recomputed.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RepoStats {
+    pub records: usize,
+    pub trees: usize,
+    pub objects: usize,
+    /// bytes of records, trees and objects on disk.
+    pub store_size: u64,
+    /// time of the last accepted push, unknown for pushes before the stats were computed.
+    pub last_push: Option<DateTime<Utc>>,
+    /// count of records by each author.
+    pub authors: HashMap<String, usize>,
+    /// how objects are stored, packs are only counted again when the stats are recomputed.
+    #[serde(default)]
+    pub storage: StorageStats,
+}
+
+/// `AuthorStats` stand for the records of an author.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuthorStats {
+    pub author: String,
+    pub records: usize,
+}
+
+/// `StatsReport` is the response of the stats endpoint.
+///
+/// ```ignore
+/// async fn stats(Path(name): Path<String>) -> Result<Json<StatsReport>, StatusCode> {
+///     let repo = repo_of(&name).await.ok_or(StatusCode::NOT_FOUND)?;
+///     match RepoStats::load(&repo).await {
+///         Ok(stats) => Ok(Json(stats.report())),
+///         Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+///     }
+/// }
+///
+/// Router::new().route("/repos/:name/stats", get(stats))
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StatsReport {
+    pub records: usize,
+    pub trees: usize,
+    pub objects: usize,
+    pub store_size: u64,
+    pub last_push: Option<DateTime<Utc>>,
+    /// authors with the most records, at most 10.
+    pub top_authors: Vec<AuthorStats>,
+    pub storage: StorageStats,
+}
+
+/// count and total size of files in `dir`.
+async fn dir_usage(dir: &Path) -> Result<(usize, u64), WsvcFsError> {
+    let mut count = 0;
+    let mut size = 0;
+    let mut entries = read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let meta = entry.metadata().await?;
+        if meta.is_file() {
+            count += 1;
+            size += meta.len();
+        }
+    }
+    Ok((count, size))
+}
+
+/// entries of `items` not stored in `dir` yet, `name` is the file name of an entry.
+/// duplicated entries are returned once.
+pub(crate) async fn not_stored<T: Clone>(
+    dir: &Path,
+    items: &[T],
+    name: impl Fn(&T) -> String,
+) -> Vec<T> {
+    let mut seen = HashSet::new();
+    let mut result = vec![];
+    for item in items {
+        let name = name(item);
+        if !dir.join(&name).exists() && seen.insert(name) {
+            result.push(item.clone());
+        }
+    }
+    result
+}
+
+async fn file_size(path: &Path) -> u64 {
+    metadata(path).await.map(|meta| meta.len()).unwrap_or(0)
+}
+
+impl RepoStats {
+    /// compute the stats by scanning the whole repository.
+    pub async fn compute(repo: &Repository) -> Result<Self, WsvcError> {
+        let (records, records_size) = dir_usage(&repo.records_dir().await?).await?;
+        let (trees, trees_size) = dir_usage(&repo.trees_dir().await?).await?;
+        let objects_dir = repo.objects_dir().await?;
+        let (mut objects, mut objects_size) = dir_usage(&objects_dir).await?;
+        // packed objects are counted by the indexes of their packs.
+        for index in pack_indexes(&objects_dir).await? {
+            objects += read_index(&index).await?.len();
+            objects_size +=
+                file_size(&index).await + file_size(&index.with_extension("pack")).await;
+        }
+        let mut authors = HashMap::new();
+        for record in repo.get_records().await? {
+            *authors.entry(record.author).or_insert(0) += 1;
+        }
+        Ok(Self {
+            records,
+            trees,
+            objects,
+            store_size: records_size + trees_size + objects_size,
+            last_push: None,
+            authors,
+            storage: repo.storage_stats().await?,
+        })
+    }
+
+    /// load the stats of `repo`, computed and saved if missing.
+    pub async fn load(repo: &Repository) -> Result<Self, WsvcError> {
+        let path = repo.path.join(STATS_FILE);
+        if path.exists() {
+            let content = read_to_string(&path).await.map_err(WsvcFsError::Os)?;
+            return Ok(serde_json::from_str(&content)?);
+        }
+        let stats = Self::compute(repo).await?;
+        stats.save(repo).await?;
+        Ok(stats)
+    }
+
+    /// save the stats, readers never see a partially written file.
+    pub async fn save(&self, repo: &Repository) -> Result<(), WsvcError> {
+        let path = repo.path.join(STATS_FILE);
+        let temp_path = repo
+            .path
+            .join(format!("{}.{}", STATS_FILE, nanoid::nanoid!()));
+        write(&temp_path, serde_json::to_vec(self)?)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        rename(&temp_path, &path).await.map_err(WsvcFsError::Os)?;
+        Ok(())
+    }
+
+    /// add records, trees and objects stored by a push, trees and objects must be the ones
+    /// the repository did not have before.
+    pub async fn add_push(
+        &mut self,
+        repo: &Repository,
+        records: &[Record],
+        trees: &[Tree],
+        blobs: &[Blob],
+    ) -> Result<(), WsvcError> {
+        let records_dir = repo.records_dir().await?;
+        for record in records {
+            self.records += 1;
+            self.store_size += file_size(&records_dir.join(record.hash.0.to_hex().as_str())).await;
+            *self.authors.entry(record.author.clone()).or_insert(0) += 1;
+        }
+        let trees_dir = repo.trees_dir().await?;
+        for tree in trees {
+            self.trees += 1;
+            self.store_size += file_size(&trees_dir.join(tree.hash.0.to_hex().as_str())).await;
+        }
+        let objects_dir = repo.objects_dir().await?;
+        for blob in blobs {
+            let size = file_size(&objects_dir.join(blob.hash.0.to_string())).await;
+            self.objects += 1;
+            self.store_size += size;
+            // pushed blobs are stored loose.
+            self.storage.loose_objects += 1;
+            self.storage.loose_size += size;
+        }
+        Ok(())
+    }
+
+    /// the stats with only the top authors.
+    pub fn report(&self) -> StatsReport {
+        let mut top_authors = self
+            .authors
+            .iter()
+            .map(|(author, records)| AuthorStats {
+                author: author.clone(),
+                records: *records,
+            })
+            .collect::<Vec<_>>();
+        top_authors.sort_by(|a, b| b.records.cmp(&a.records).then(a.author.cmp(&b.author)));
+        top_authors.truncate(TOP_AUTHORS);
+        StatsReport {
+            records: self.records,
+            trees: self.trees,
+            objects: self.objects,
+            store_size: self.store_size,
+            last_push: self.last_push,
+            top_authors,
+            storage: self.storage.clone(),
+        }
+    }
+}
+
+/// update the stats with a push already stored, failures are logged and the stats are
+/// recomputed on next load.
+pub async fn record_push(repo: &Repository, records: &[Record], trees: &[Tree], blobs: &[Blob]) {
+    let result = async {
+        let mut stats = if repo.path.join(STATS_FILE).exists() {
+            let mut stats = RepoStats::load(repo).await?;
+            stats.add_push(repo, records, trees, blobs).await?;
+            stats
+        } else {
+            // the scan already sees the stored push.
+            RepoStats::compute(repo).await?
+        };
+        stats.last_push = Some(Utc::now());
+        stats.save(repo).await
+    }
+    .await;
+    if let Err(err) = result {
+        tracing::warn!("failed to update repo stats: {}", err);
+        tokio::fs::remove_file(repo.path.join(STATS_FILE))
+            .await
+            .ok();
+    }
+}
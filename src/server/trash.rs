@@ -0,0 +1,201 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{
+    create_dir_all, read_dir, read_to_string, remove_dir_all, remove_file, rename, write,
+};
+
+use crate::{fs::WsvcFsError, model::Repository, WsvcError};
+
+/// dir of deleted repositories in the root dir of hosted repositories.
+pub const TRASH_DIR: &str = ".trash";
+
+/// file describing a deleted repository, beside the repository dir in its trash entry.
+const DELETED_FILE: &str = "deleted.json";
+
+/// dir of the repository itself in its trash entry.
+const REPO_DIR: &str = "repo";
+
+/// `DeletedRepo` stand for a repository in the trash.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeletedRepo {
+    pub id: String,
+    pub name: String,
+    pub deleted_at: DateTime<Utc>,
+    /// the repository is purged after this time.
+    pub expires_at: DateTime<Utc>,
+}
+
+/// `RepoTrash` soft-deletes hosted repositories under `root`.
+///
+/// deleted repositories are moved to `root/.trash` and can be restored until the
+/// retention period is over, a purge job removes them after that.
+///
+/// ```ignore
+/// let trash = Arc::new(RepoTrash::new("/srv/wsvc", Duration::from_secs(30 * 24 * 3600)));
+/// trash.clone().spawn_purge_job(Duration::from_secs(3600));
+///
+/// async fn delete(Path(name): Path<String>, State(trash): State<Arc<RepoTrash>>) -> Result<Json<DeletedRepo>, StatusCode> {
+///     trash.delete(&name).await.map(Json).map_err(|_| StatusCode::CONFLICT)
+/// }
+///
+/// async fn list(State(trash): State<Arc<RepoTrash>>) -> Result<Json<Vec<DeletedRepo>>, StatusCode> {
+///     trash.list().await.map(Json).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+/// }
+///
+/// async fn restore(Path(id): Path<String>, State(trash): State<Arc<RepoTrash>>) -> Result<Json<DeletedRepo>, StatusCode> {
+///     trash.restore(&id).await.map(Json).map_err(|_| StatusCode::CONFLICT)
+/// }
+///
+/// Router::new()
+///     .route("/admin/repos/:name", delete(delete))
+///     .route("/admin/trash", get(list))
+///     .route("/admin/trash/:id/restore", post(restore))
+/// ```
+pub struct RepoTrash {
+    root: PathBuf,
+    retention: Duration,
+}
+
+/// names of repositories and trash entries are single path components.
+fn check_name(name: &str) -> Result<(), WsvcError> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(WsvcError::BadUsage(format!(
+            "invalid repository name: {}",
+            name
+        )));
+    }
+    Ok(())
+}
+
+async fn read_entry(entry_dir: &Path) -> Result<DeletedRepo, WsvcError> {
+    let content = read_to_string(entry_dir.join(DELETED_FILE))
+        .await
+        .map_err(WsvcFsError::Os)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+impl RepoTrash {
+    pub fn new(root: impl AsRef<Path>, retention: Duration) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            retention,
+        }
+    }
+
+    fn trash_dir(&self) -> PathBuf {
+        self.root.join(TRASH_DIR)
+    }
+
+    /// move the repository `name` to the trash, fails if it is being synced.
+    pub async fn delete(&self, name: &str) -> Result<DeletedRepo, WsvcError> {
+        check_name(name)?;
+        let repo_dir = self.root.join(name);
+        let retention = chrono::Duration::from_std(self.retention)
+            .map_err(|_| WsvcError::BadUsage("retention period is too long".to_owned()))?;
+        let repo = Repository::try_open(&repo_dir).await?;
+        // hold the lock while moving, so no sync starts on the repository.
+        let guard = crate::fs::RepoGuard::new(&repo).await?;
+        let deleted_at = Utc::now();
+        let deleted = DeletedRepo {
+            id: nanoid::nanoid!(),
+            name: name.to_owned(),
+            deleted_at,
+            expires_at: deleted_at + retention,
+        };
+        let entry_dir = self.trash_dir().join(&deleted.id);
+        create_dir_all(&entry_dir).await.map_err(WsvcFsError::Os)?;
+        write(entry_dir.join(DELETED_FILE), serde_json::to_vec(&deleted)?)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        if let Err(err) = rename(&repo_dir, entry_dir.join(REPO_DIR)).await {
+            remove_dir_all(&entry_dir).await.ok();
+            return Err(WsvcFsError::Os(err).into());
+        }
+        drop(guard);
+        // the lock moved with the repository.
+        let moved = Repository::try_open(entry_dir.join(REPO_DIR)).await?;
+        remove_file(moved.path.join("LOCK")).await.ok();
+        tracing::info!("repository {} is moved to trash as {}", name, deleted.id);
+        Ok(deleted)
+    }
+
+    /// all repositories in the trash, the latest deleted first.
+    pub async fn list(&self) -> Result<Vec<DeletedRepo>, WsvcError> {
+        let trash_dir = self.trash_dir();
+        let mut result = vec![];
+        if !trash_dir.exists() {
+            return Ok(result);
+        }
+        let mut entries = read_dir(&trash_dir).await.map_err(WsvcFsError::Os)?;
+        while let Some(entry) = entries.next_entry().await.map_err(WsvcFsError::Os)? {
+            match read_entry(&entry.path()).await {
+                Ok(deleted) => result.push(deleted),
+                Err(err) => tracing::warn!("invalid trash entry {:?}: {}", entry.path(), err),
+            }
+        }
+        result.sort_by_key(|d| std::cmp::Reverse(d.deleted_at));
+        Ok(result)
+    }
+
+    /// restore a deleted repository to its name, fails if the name is taken again.
+    pub async fn restore(&self, id: &str) -> Result<DeletedRepo, WsvcError> {
+        check_name(id)?;
+        let entry_dir = self.trash_dir().join(id);
+        let deleted = read_entry(&entry_dir)
+            .await
+            .map_err(|_| WsvcError::RepoError(format!("no deleted repository {}", id)))?;
+        let repo_dir = self.root.join(&deleted.name);
+        if repo_dir.exists() {
+            return Err(WsvcError::FsError(WsvcFsError::DirAlreadyExists(format!(
+                "{:?}",
+                repo_dir
+            ))));
+        }
+        rename(entry_dir.join(REPO_DIR), &repo_dir)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        remove_dir_all(&entry_dir).await.map_err(WsvcFsError::Os)?;
+        tracing::info!("repository {} is restored from trash", deleted.name);
+        Ok(deleted)
+    }
+
+    /// remove repositories whose retention period is over, returns the removed ones.
+    pub async fn purge_expired(&self) -> Result<Vec<DeletedRepo>, WsvcError> {
+        let now = Utc::now();
+        let mut purged = vec![];
+        for deleted in self.list().await? {
+            if deleted.expires_at > now {
+                continue;
+            }
+            remove_dir_all(self.trash_dir().join(&deleted.id))
+                .await
+                .map_err(WsvcFsError::Os)?;
+            tracing::info!(
+                "deleted repository {} ({}) is purged",
+                deleted.name,
+                deleted.id
+            );
+            purged.push(deleted);
+        }
+        Ok(purged)
+    }
+
+    /// purge expired repositories every `interval` in background.
+    pub fn spawn_purge_job(self: Arc<Self>, interval: Duration) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(err) = self.purge_expired().await {
+                    tracing::warn!("failed to purge trash: {}", err);
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,441 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{
+    frame::FrameStream, fs::WsvcFsError, model::Repository, refs::write_atomic,
+    release::ReleaseAsset, WsvcError,
+};
+
+use super::{auth::CredentialVerifier, visibility::RepoAccess, SessionLimits, WsvcServerError};
+
+/// file name of the usage ledger in the root dir of hosted repositories.
+pub const USAGE_FILE: &str = "usage.json";
+
+/// `Transfer` stand for bytes transferred by syncs, seen from the server.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct Transfer {
+    /// bytes received from clients.
+    pub pushed: u64,
+    /// bytes sent to clients.
+    pub pulled: u64,
+}
+
+impl Transfer {
+    pub fn total(&self) -> u64 {
+        self.pushed + self.pulled
+    }
+}
+
+/// `UserUsage` stand for the usage of a user or token.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct UserUsage {
+    /// bytes of the store added by pushes of the user. never reduced, the store of a
+    /// repository is shared by all users pushing to it.
+    pub storage: u64,
+    /// bytes of release assets uploaded by the user, less the assets the user replaced or
+    /// removed, counted against `UsageConfig::storage_quota`.
+    #[serde(default)]
+    pub assets: u64,
+    /// transfer by month, e.g. `2024-01`.
+    pub months: BTreeMap<String, Transfer>,
+}
+
+fn current_month() -> String {
+    Utc::now().format("%Y-%m").to_string()
+}
+
+/// `UsageConfig` stand for the usage accounting of a root of hosted repositories, see
+/// `RepoRoot::usage`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct UsageConfig {
+    /// bytes each user may push and pull in a month, unlimited if not set.
+    #[serde(default)]
+    pub transfer_cap: Option<u64>,
+    /// bytes of release assets each user may keep uploaded, see `UserUsage::assets`.
+    /// pushes are not counted. unlimited if not set.
+    #[serde(default)]
+    pub storage_quota: Option<u64>,
+}
+
+/// `UsageLedger` accounts transfer and storage of each user of a server.
+///
+/// ```ignore
+/// let ledger = UsageLedger::open(root.join(USAGE_FILE)).await?;
+///
+/// // in the websocket handler, the client authenticates in the sync.
+/// ws.on_upgrade(move |mut ws| async move {
+///     sync_metered(&repo, &mut ws, &ledger, &verifier, &access, Some(10 << 30)).await.ok();
+/// })
+///
+/// // admin api and prometheus metrics.
+/// async fn usage(State(ledger): State<Arc<UsageLedger>>) -> Json<BTreeMap<String, UserUsage>> {
+///     Json(ledger.all().await)
+/// }
+/// async fn metrics(State(ledger): State<Arc<UsageLedger>>) -> String {
+///     ledger.metrics().await
+/// }
+/// ```
+pub struct UsageLedger {
+    path: PathBuf,
+    users: Mutex<BTreeMap<String, UserUsage>>,
+}
+
+impl UsageLedger {
+    /// open the ledger saved at `path`, empty if it does not exist.
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self, WsvcError> {
+        let path = path.as_ref().to_path_buf();
+        let users = if path.exists() {
+            let content = tokio::fs::read_to_string(&path)
+                .await
+                .map_err(WsvcFsError::Os)?;
+            serde_json::from_str(&content)?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Self {
+            path,
+            users: Mutex::new(users),
+        })
+    }
+
+    /// add a sync of `user` in the current month and save the ledger.
+    pub async fn record(
+        &self,
+        user: &str,
+        transfer: Transfer,
+        storage: u64,
+    ) -> Result<(), WsvcError> {
+        self.update(user, transfer, |usage| usage.storage += storage)
+            .await
+    }
+
+    /// add an upload or removal of release assets by `user` in the current month and save
+    /// the ledger, `added` bytes of assets uploaded and `freed` bytes of assets replaced or
+    /// removed.
+    pub async fn record_assets(
+        &self,
+        user: &str,
+        transfer: Transfer,
+        added: u64,
+        freed: u64,
+    ) -> Result<(), WsvcError> {
+        self.update(user, transfer, |usage| {
+            usage.assets = (usage.assets + added).saturating_sub(freed)
+        })
+        .await
+    }
+
+    async fn update(
+        &self,
+        user: &str,
+        transfer: Transfer,
+        update: impl FnOnce(&mut UserUsage),
+    ) -> Result<(), WsvcError> {
+        let mut users = self.users.lock().await;
+        let usage = users.entry(user.to_owned()).or_default();
+        update(usage);
+        let month = usage.months.entry(current_month()).or_default();
+        month.pushed += transfer.pushed;
+        month.pulled += transfer.pulled;
+        write_atomic(&self.path, &serde_json::to_vec(&*users)?).await?;
+        Ok(())
+    }
+
+    pub async fn usage(&self, user: &str) -> UserUsage {
+        self.users
+            .lock()
+            .await
+            .get(user)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub async fn all(&self) -> BTreeMap<String, UserUsage> {
+        self.users.lock().await.clone()
+    }
+
+    /// bytes `user` can still transfer this month under a monthly `cap`.
+    pub async fn remaining(&self, user: &str, cap: u64) -> u64 {
+        let used = self
+            .usage(user)
+            .await
+            .months
+            .get(&current_month())
+            .map(|t| t.total())
+            .unwrap_or(0);
+        cap.saturating_sub(used)
+    }
+
+    /// the usage in prometheus text format.
+    pub async fn metrics(&self) -> String {
+        let users = self.users.lock().await;
+        let month = current_month();
+        let mut result = String::new();
+        result.push_str("# TYPE wsvc_transfer_bytes counter\n");
+        for (user, usage) in users.iter() {
+            let transfer = usage.months.get(&month).copied().unwrap_or_default();
+            for (direction, bytes) in [("push", transfer.pushed), ("pull", transfer.pulled)] {
+                writeln!(
+                    result,
+                    "wsvc_transfer_bytes{{user=\"{}\",direction=\"{}\",month=\"{}\"}} {}",
+                    escape_label(user),
+                    direction,
+                    month,
+                    bytes
+                )
+                .ok();
+            }
+        }
+        result.push_str("# TYPE wsvc_storage_bytes gauge\n");
+        for (user, usage) in users.iter() {
+            writeln!(
+                result,
+                "wsvc_storage_bytes{{user=\"{}\"}} {}",
+                escape_label(user),
+                usage.storage
+            )
+            .ok();
+        }
+        result.push_str("# TYPE wsvc_asset_bytes gauge\n");
+        for (user, usage) in users.iter() {
+            writeln!(
+                result,
+                "wsvc_asset_bytes{{user=\"{}\"}} {}",
+                escape_label(user),
+                usage.assets
+            )
+            .ok();
+        }
+        result
+    }
+}
+
+/// `value` escaped as a prometheus label value, which only escapes `\`, `"` and newlines.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// user accounted for clients that did not authenticate.
+pub const ANONYMOUS_USER: &str = "anonymous";
+
+/// `UsageMeter` stand for the ledger a sync session accounts its client in, as the account
+/// the client authenticates as in the sync, see `sync_with_meter`.
+pub struct UsageMeter<'a> {
+    pub ledger: &'a UsageLedger,
+    /// monthly transfer cap in bytes of each user, unlimited if not set.
+    pub cap: Option<u64>,
+}
+
+impl UsageMeter<'_> {
+    /// bytes `user` can still transfer this month, `None` if unlimited.
+    pub async fn remaining(&self, user: &str) -> Option<u64> {
+        match self.cap {
+            Some(cap) => Some(self.ledger.remaining(user, cap).await),
+            None => None,
+        }
+    }
+}
+
+/// `MeteredStream` counts bytes of frames, and fails the sync once `limit` is exceeded.
+///
+/// parallel file transfer of the inner stream is not used, so files are counted as frames.
+pub struct MeteredStream<S> {
+    inner: S,
+    pub transfer: Transfer,
+    limit: Option<u64>,
+}
+
+impl<S: FrameStream> MeteredStream<S> {
+    pub fn new(inner: S, limit: Option<u64>) -> Self {
+        Self {
+            inner,
+            transfer: Transfer::default(),
+            limit,
+        }
+    }
+
+    /// fail the sync once the transfer exceeds `limit`, counting the transfer so far.
+    pub fn set_limit(&mut self, limit: Option<u64>) {
+        self.limit = limit;
+    }
+
+    pub fn into_inner(self) -> (S, Transfer) {
+        (self.inner, self.transfer)
+    }
+
+    fn check_limit(&self) -> Result<(), WsvcError> {
+        match self.limit {
+            Some(limit) if self.transfer.total() > limit => Err(WsvcError::DataError(
+                "monthly transfer cap exceeded".to_owned(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: FrameStream> FrameStream for MeteredStream<S> {
+    async fn send_frame(&mut self, data: Vec<u8>) -> Result<(), WsvcError> {
+        self.transfer.pulled += data.len() as u64;
+        self.check_limit()?;
+        self.inner.send_frame(data).await
+    }
+
+    async fn recv_frame(&mut self) -> Result<Option<Vec<u8>>, WsvcError> {
+        let frame = self.inner.recv_frame().await?;
+        if let Some(frame) = &frame {
+            self.transfer.pushed += frame.len() as u64;
+            self.check_limit()?;
+        }
+        Ok(frame)
+    }
+}
+
+/// sync with a client authenticated by `verifier` and allowed by `access` like
+/// `sync_with_access`, and account the transfer and the storage added to the repository
+/// as its account, see `sync_with_meter`. the sync fails if the monthly transfer `cap` of
+/// the account is exceeded, transfer of failed syncs is accounted too.
+pub async fn sync_metered(
+    repo: &Repository,
+    ws: &mut impl FrameStream,
+    ledger: &UsageLedger,
+    verifier: &dyn CredentialVerifier,
+    access: &RepoAccess,
+    cap: Option<u64>,
+) -> Result<(), WsvcServerError> {
+    let meter = UsageMeter { ledger, cap };
+    super::sync_with_meter(
+        repo,
+        ws,
+        &SessionLimits::default(),
+        Some(verifier),
+        Some(access),
+        &meter,
+    )
+    .await
+}
+
+/// `AssetUpload` stand for an asset uploaded to a tag, see `store_asset_metered`.
+pub struct AssetUpload<'a> {
+    pub tag: &'a str,
+    pub name: &'a str,
+    pub content_type: &'a str,
+    pub data: &'a [u8],
+}
+
+/// attach an asset uploaded by `user` to its tag, and account the upload and the asset
+/// bytes it adds. the upload is rejected if it makes the asset bytes of the user exceed
+/// `quota`.
+pub async fn store_asset_metered(
+    repo: &Repository,
+    upload: AssetUpload<'_>,
+    ledger: &UsageLedger,
+    user: &str,
+    quota: Option<u64>,
+) -> Result<ReleaseAsset, WsvcServerError> {
+    let size = upload.data.len() as u64;
+    if let Some(quota) = quota {
+        // a replaced asset frees its size.
+        let replaced = repo
+            .get_release_assets(upload.tag)
+            .await?
+            .into_iter()
+            .find(|a| a.name == upload.name)
+            .map(|a| a.size)
+            .unwrap_or(0);
+        if ledger.usage(user).await.assets.saturating_sub(replaced) + size > quota {
+            return Err(WsvcServerError::Rejected(
+                "storage quota exceeded".to_owned(),
+            ));
+        }
+    }
+    let (asset, replaced) = repo
+        .store_release_asset(upload.tag, upload.name, upload.content_type, upload.data)
+        .await?;
+    let transfer = Transfer {
+        pushed: size,
+        pulled: 0,
+    };
+    let freed = replaced.map(|a| a.size).unwrap_or(0);
+    ledger.record_assets(user, transfer, size, freed).await?;
+    Ok(asset)
+}
+
+/// detach an asset of tag `tag` removed by `user`, and account the asset bytes it frees.
+/// returns the asset if it existed.
+pub async fn remove_asset_metered(
+    repo: &Repository,
+    tag: &str,
+    name: &str,
+    ledger: &UsageLedger,
+    user: &str,
+) -> Result<Option<ReleaseAsset>, WsvcServerError> {
+    let removed = repo.remove_release_asset(tag, name).await?;
+    if let Some(removed) = &removed {
+        ledger
+            .record_assets(user, Transfer::default(), 0, removed.size)
+            .await?;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `Loopback` stand for a stream receiving the frames sent to it.
+    #[derive(Default)]
+    struct Loopback(Vec<Vec<u8>>);
+
+    #[async_trait]
+    impl FrameStream for Loopback {
+        async fn send_frame(&mut self, data: Vec<u8>) -> Result<(), WsvcError> {
+            self.0.push(data);
+            Ok(())
+        }
+
+        async fn recv_frame(&mut self) -> Result<Option<Vec<u8>>, WsvcError> {
+            Ok(self.0.pop())
+        }
+    }
+
+    #[tokio::test]
+    async fn metered_streams_fail_past_the_cap() {
+        let mut stream = MeteredStream::new(Loopback::default(), None);
+        stream.send_frame(vec![0; 6]).await.unwrap();
+        stream.set_limit(Some(12));
+        assert_eq!(stream.recv_frame().await.unwrap(), Some(vec![0; 6]));
+        assert!(stream.send_frame(vec![0; 1]).await.is_err());
+        let (inner, transfer) = stream.into_inner();
+        // frames over the cap are counted but never sent.
+        assert!(inner.0.is_empty());
+        assert_eq!((transfer.pulled, transfer.pushed), (7, 6));
+        let mut stream = MeteredStream::new(Loopback(vec![vec![0; 11]]), Some(10));
+        assert!(stream.recv_frame().await.is_err());
+        assert_eq!(stream.transfer.pushed, 11);
+    }
+
+    #[tokio::test]
+    async fn metrics_escape_label_values() {
+        let path = std::env::temp_dir().join(format!("wsvc-test-{}", nanoid::nanoid!()));
+        let ledger = UsageLedger::open(&path).await.unwrap();
+        ledger
+            .record("a\\b\"c\nd é", Transfer::default(), 1)
+            .await
+            .unwrap();
+        let metrics = ledger.metrics().await;
+        assert!(metrics.contains("wsvc_storage_bytes{user=\"a\\\\b\\\"c\\nd é\"} 1\n"));
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+}
@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{fs::WsvcFsError, model::Repository, WsvcError};
+
+/// file name of the visibility in the server side repository dir.
+pub const VISIBILITY_FILE: &str = "visibility.toml";
+
+/// `Visibility` stand for who may read a hosted repository.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    /// anyone may read, even without a credential.
+    Public,
+    /// any account accepted by the server may read.
+    Internal,
+    /// only members may read.
+    Private,
+}
+
+/// `Access` stand for what a client may do with a hosted repository, in growing order.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    None,
+    /// sync without pushing anything, and get over http.
+    Read,
+    /// push, and publish releases and assets.
+    Write,
+}
+
+/// `RepoAccess` stand for who may read and write a hosted repository, kept in
+/// `visibility.toml` of its dir.
+///
+/// ```toml
+/// visibility = "public"
+/// members = ["alice", "bob"]
+/// ```
+///
+/// members may read and write whatever the visibility, every account accepted by the
+/// server is a member if there are none. `visibility` falls back to the `visibility` of
+/// the root of the repository if not set.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RepoAccess {
+    pub visibility: Visibility,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<String>,
+}
+
+/// the stored shape of `RepoAccess`, the visibility may be left to the root.
+#[derive(Deserialize)]
+struct RepoAccessRepr {
+    #[serde(default)]
+    visibility: Option<Visibility>,
+    #[serde(default)]
+    members: Vec<String>,
+}
+
+impl RepoAccess {
+    /// the access of `repo` from its `visibility.toml`, `default` being the visibility of
+    /// its root. `None` if neither sets a visibility, anyone may read and write then.
+    pub async fn load(
+        repo: &Repository,
+        default: Option<Visibility>,
+    ) -> Result<Option<Self>, WsvcError> {
+        let repr = match tokio::fs::read_to_string(repo.path.join(VISIBILITY_FILE)).await {
+            Ok(content) => toml::from_str::<RepoAccessRepr>(&content)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => RepoAccessRepr {
+                visibility: None,
+                members: vec![],
+            },
+            Err(err) => return Err(WsvcFsError::Os(err).into()),
+        };
+        Ok(repr.visibility.or(default).map(|visibility| Self {
+            visibility,
+            members: repr.members,
+        }))
+    }
+
+    /// what the client authenticated as `account` may do, `None` for a client without an
+    /// accepted credential.
+    pub fn access(&self, account: Option<&str>) -> Access {
+        let member = account.is_some_and(|account| {
+            self.members.is_empty() || self.members.iter().any(|m| m == account)
+        });
+        match (self.visibility, account) {
+            _ if member => Access::Write,
+            (Visibility::Public, _) | (Visibility::Internal, Some(_)) => Access::Read,
+            _ => Access::None,
+        }
+    }
+}
@@ -0,0 +1,413 @@
+use std::{path::PathBuf, process::Stdio};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::{fs::write, io::AsyncWriteExt, process::Command};
+
+use crate::{
+    fs::WsvcFsError,
+    model::{ObjectId, Repository},
+    WsvcError,
+};
+
+/// namespace of ssh signatures, prevents signatures being reused by other tools.
+pub const SSH_SIGNATURE_NAMESPACE: &str = "wsvc";
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureKind {
+    Ssh,
+    Gpg,
+}
+
+/// `Signature` stand for a detached signature of a record.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Signature {
+    pub record: ObjectId,
+    pub kind: SignatureKind,
+    /// identity of the signer, e.g. email for ssh, fingerprint or long key id for gpg.
+    pub signer: String,
+    /// armored signature text.
+    pub signature: String,
+}
+
+/// `Signer` signs the payload of a record with external key infrastructure.
+#[async_trait]
+pub trait Signer {
+    fn kind(&self) -> SignatureKind;
+
+    fn identity(&self) -> String;
+
+    /// sign `payload`, returns the armored signature.
+    async fn sign(&self, payload: &[u8]) -> Result<String, WsvcError>;
+}
+
+/// `Verifier` checks a signature made by a `Signer` of the same kind.
+#[async_trait]
+pub trait Verifier {
+    fn kind(&self) -> SignatureKind;
+
+    async fn verify(
+        &self,
+        repo: &Repository,
+        payload: &[u8],
+        signature: &Signature,
+    ) -> Result<bool, WsvcError>;
+}
+
+/// run `program` with `args`, feed `stdin` and collect stdout.
+async fn run_with_stdin(
+    program: &str,
+    args: &[&str],
+    stdin: &[u8],
+) -> Result<(bool, Vec<u8>), WsvcError> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| WsvcError::SignatureError(format!("failed to run {}: {}", program, err)))?;
+    if let Some(mut child_stdin) = child.stdin.take() {
+        child_stdin
+            .write_all(stdin)
+            .await
+            .map_err(WsvcFsError::Os)?;
+    }
+    let output = child.wait_with_output().await.map_err(WsvcFsError::Os)?;
+    Ok((output.status.success(), output.stdout))
+}
+
+/// `SshSigner` signs with `ssh-keygen -Y sign`.
+///
+/// if `use_agent` is set, `key` should be the public key and the private key is taken
+/// from ssh-agent.
+pub struct SshSigner {
+    pub key: PathBuf,
+    pub identity: String,
+    pub use_agent: bool,
+}
+
+#[async_trait]
+impl Signer for SshSigner {
+    fn kind(&self) -> SignatureKind {
+        SignatureKind::Ssh
+    }
+
+    fn identity(&self) -> String {
+        self.identity.clone()
+    }
+
+    async fn sign(&self, payload: &[u8]) -> Result<String, WsvcError> {
+        let key = self.key.to_string_lossy().to_string();
+        let mut args = vec!["-Y", "sign", "-n", SSH_SIGNATURE_NAMESPACE, "-f", &key];
+        if self.use_agent {
+            args.push("-U");
+        }
+        let (success, stdout) = run_with_stdin("ssh-keygen", &args, payload).await?;
+        if !success {
+            return Err(WsvcError::SignatureError(format!(
+                "ssh-keygen failed to sign with {}",
+                key
+            )));
+        }
+        Ok(String::from_utf8(stdout)?)
+    }
+}
+
+/// `SshVerifier` verifies with `ssh-keygen -Y verify` against an allowed signers file.
+pub struct SshVerifier {
+    pub allowed_signers: PathBuf,
+}
+
+#[async_trait]
+impl Verifier for SshVerifier {
+    fn kind(&self) -> SignatureKind {
+        SignatureKind::Ssh
+    }
+
+    async fn verify(
+        &self,
+        repo: &Repository,
+        payload: &[u8],
+        signature: &Signature,
+    ) -> Result<bool, WsvcError> {
+        let signature_file = repo.temp_dir().await?.join(nanoid::nanoid!());
+        write(&signature_file, &signature.signature)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        let allowed_signers = self.allowed_signers.to_string_lossy().to_string();
+        let signature_path = signature_file.to_string_lossy().to_string();
+        let args = [
+            "-Y",
+            "verify",
+            "-n",
+            SSH_SIGNATURE_NAMESPACE,
+            "-f",
+            &allowed_signers,
+            "-I",
+            &signature.signer,
+            "-s",
+            &signature_path,
+        ];
+        let result = run_with_stdin("ssh-keygen", &args, payload).await;
+        tokio::fs::remove_file(&signature_file).await.ok();
+        Ok(result?.0)
+    }
+}
+
+/// `id` as an uppercase hex fingerprint or long key id, `None` if it is neither, e.g. a
+/// user id or short key id.
+fn gpg_key_id(id: &str) -> Option<String> {
+    let id = id.trim();
+    let id = id
+        .strip_prefix("0x")
+        .or_else(|| id.strip_prefix("0X"))
+        .unwrap_or(id)
+        .replace(' ', "")
+        .to_ascii_uppercase();
+    (id.len() >= 16 && id.chars().all(|c| c.is_ascii_hexdigit())).then_some(id)
+}
+
+/// `GpgSigner` signs with a gpg compatible binary, `key_id` should be a fingerprint or
+/// long key id, see `GpgVerifier`.
+pub struct GpgSigner {
+    pub program: String,
+    pub key_id: String,
+}
+
+#[async_trait]
+impl Signer for GpgSigner {
+    fn kind(&self) -> SignatureKind {
+        SignatureKind::Gpg
+    }
+
+    fn identity(&self) -> String {
+        self.key_id.clone()
+    }
+
+    async fn sign(&self, payload: &[u8]) -> Result<String, WsvcError> {
+        if gpg_key_id(&self.key_id).is_none() {
+            return Err(WsvcError::SignatureError(format!(
+                "name the gpg key by its fingerprint or long key id instead of {}",
+                self.key_id
+            )));
+        }
+        let args = [
+            "--batch",
+            "--armor",
+            "--local-user",
+            &self.key_id,
+            "--detach-sign",
+        ];
+        let (success, stdout) = run_with_stdin(&self.program, &args, payload).await?;
+        if !success {
+            return Err(WsvcError::SignatureError(format!(
+                "{} failed to sign with {}",
+                self.program, self.key_id
+            )));
+        }
+        Ok(String::from_utf8(stdout)?)
+    }
+}
+
+/// `GpgVerifier` verifies with a gpg compatible binary and its keyring.
+///
+/// a signature is only valid if it was made by the key named by its `signer`, a
+/// fingerprint or long key id of the key or its primary key.
+pub struct GpgVerifier {
+    pub program: String,
+}
+
+/// `GpgKey` stand for the key a gpg signature was verified with, by the `VALIDSIG`
+/// status line of gpg.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GpgKey {
+    /// fingerprint of the signing key, maybe a subkey.
+    pub fingerprint: String,
+    /// fingerprint of the primary key of the signing key.
+    pub primary_fingerprint: String,
+}
+
+impl GpgKey {
+    /// whether `id` names the key, a fingerprint or long key id of the key or its primary
+    /// key, e.g. `0x1234567890ABCDEF`. short key ids and user ids never match.
+    pub fn matches(&self, id: &str) -> bool {
+        gpg_key_id(id).is_some_and(|id| {
+            self.fingerprint.ends_with(&id) || self.primary_fingerprint.ends_with(&id)
+        })
+    }
+
+    /// the key of the status lines of `gpg --status-fd`, `None` unless exactly one good
+    /// and valid signature is reported.
+    fn from_status(status: &str) -> Option<Self> {
+        let mut good = 0;
+        let mut keys = vec![];
+        for line in status.lines() {
+            let mut fields = line.split_whitespace();
+            if fields.next() != Some("[GNUPG:]") {
+                continue;
+            }
+            match fields.next() {
+                Some("GOODSIG") => good += 1,
+                Some("VALIDSIG") => {
+                    let fields = fields.collect::<Vec<_>>();
+                    let fingerprint = fields.first()?.to_ascii_uppercase();
+                    keys.push(Self {
+                        primary_fingerprint: fields
+                            .get(9)
+                            .map(|f| f.to_ascii_uppercase())
+                            .unwrap_or_else(|| fingerprint.clone()),
+                        fingerprint,
+                    });
+                }
+                Some("BADSIG" | "ERRSIG" | "EXPSIG" | "EXPKEYSIG" | "REVKEYSIG") => return None,
+                _ => {}
+            }
+        }
+        match (good, keys.len()) {
+            (1, 1) => keys.pop(),
+            _ => None,
+        }
+    }
+}
+
+impl GpgVerifier {
+    /// verify `signature` of `payload`, returns the key it was made with, `None` if it is
+    /// invalid. the `signer` of the signature is not checked.
+    pub async fn verified_key(
+        &self,
+        repo: &Repository,
+        payload: &[u8],
+        signature: &Signature,
+    ) -> Result<Option<GpgKey>, WsvcError> {
+        let signature_file = repo.temp_dir().await?.join(nanoid::nanoid!());
+        write(&signature_file, &signature.signature)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        let signature_path = signature_file.to_string_lossy().to_string();
+        let args = [
+            "--batch",
+            "--status-fd",
+            "1",
+            "--verify",
+            &signature_path,
+            "-",
+        ];
+        let result = run_with_stdin(&self.program, &args, payload).await;
+        tokio::fs::remove_file(&signature_file).await.ok();
+        let (success, stdout) = result?;
+        if !success {
+            return Ok(None);
+        }
+        Ok(GpgKey::from_status(&String::from_utf8_lossy(&stdout)))
+    }
+}
+
+#[async_trait]
+impl Verifier for GpgVerifier {
+    fn kind(&self) -> SignatureKind {
+        SignatureKind::Gpg
+    }
+
+    async fn verify(
+        &self,
+        repo: &Repository,
+        payload: &[u8],
+        signature: &Signature,
+    ) -> Result<bool, WsvcError> {
+        Ok(self
+            .verified_key(repo, payload, signature)
+            .await?
+            .is_some_and(|key| key.matches(&signature.signer)))
+    }
+}
+
+impl Repository {
+    /// get the signatures folder of the repository.
+    pub async fn signatures_dir(&self) -> Result<PathBuf, WsvcFsError> {
+        let result = self.path.join("signatures");
+        if !result.exists() && !self.options.read_only {
+            tokio::fs::create_dir_all(&result).await?;
+        }
+        Ok(result)
+    }
+
+    /// read all signatures of a record.
+    pub async fn read_signatures(
+        &self,
+        record_hash: &ObjectId,
+    ) -> Result<Vec<Signature>, WsvcFsError> {
+        let path = self
+            .signatures_dir()
+            .await?
+            .join(record_hash.0.to_hex().as_str());
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+        Ok(serde_json::from_slice(&tokio::fs::read(path).await?)?)
+    }
+
+    /// store a signature of a record, signatures of the same signer are replaced.
+    pub async fn store_signature(&self, signature: &Signature) -> Result<(), WsvcFsError> {
+        self.check_writable()?;
+        let mut signatures = self.read_signatures(&signature.record).await?;
+        signatures.retain(|s| !(s.kind == signature.kind && s.signer == signature.signer));
+        signatures.push(signature.clone());
+        write(
+            self.signatures_dir()
+                .await?
+                .join(signature.record.0.to_hex().as_str()),
+            serde_json::to_vec(&signatures)?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// sign a record with `signer` and store the signature.
+    ///
+    /// the signed payload is the hex hash of the record, which covers all record fields.
+    pub async fn sign_record(
+        &self,
+        record_hash: &ObjectId,
+        signer: &(dyn Signer + Sync),
+    ) -> Result<Signature, WsvcError> {
+        let record = self.read_record(record_hash).await?;
+        let payload = record.hash.0.to_hex().to_string();
+        let signature = Signature {
+            record: record.hash,
+            kind: signer.kind(),
+            signer: signer.identity(),
+            signature: signer.sign(payload.as_bytes()).await?,
+        };
+        self.store_signature(&signature).await?;
+        Ok(signature)
+    }
+
+    /// verify all signatures of a record that `verifier` understands.
+    ///
+    /// the hash of the record is recalculated from its content first, signatures of a
+    /// record whose content does not match its hash are all invalid. returns each checked
+    /// signature with its verification result.
+    pub async fn verify_record(
+        &self,
+        record_hash: &ObjectId,
+        verifier: &(dyn Verifier + Sync),
+    ) -> Result<Vec<(Signature, bool)>, WsvcError> {
+        let record = self.read_record(record_hash).await?;
+        let intact = record.hash == *record_hash && record.calc_hash()? == *record_hash;
+        let payload = record_hash.0.to_hex().to_string();
+        let mut result = Vec::new();
+        for signature in self.read_signatures(record_hash).await? {
+            if signature.kind != verifier.kind() || signature.record != *record_hash {
+                continue;
+            }
+            let valid = intact
+                && verifier
+                    .verify(self, payload.as_bytes(), &signature)
+                    .await?;
+            result.push((signature, valid));
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,160 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    diff::Change,
+    fs::WsvcFsError,
+    model::{EntryKind, ObjectId, Repository},
+    readonly::ReadOnlyRepository,
+};
+
+/// `SizePolicy` stand for how large files of a new record may be, committing warns about
+/// files over it, or fails if `block` is set.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct SizePolicy {
+    /// times its size in the parent record a file may grow to, never compared if 0.
+    pub max_growth: f64,
+    /// files smaller than this many bytes are never reported as grown.
+    pub min_size: u64,
+    /// bytes no file may exceed, unlimited if not set.
+    pub max_size: Option<u64>,
+    /// fail committing instead of warning.
+    pub block: bool,
+}
+
+impl Default for SizePolicy {
+    fn default() -> Self {
+        Self {
+            max_growth: 10.0,
+            min_size: 1024 * 1024,
+            max_size: None,
+            block: false,
+        }
+    }
+}
+
+/// `SizeReason` stand for why a file is over a `SizePolicy`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SizeReason {
+    /// grew more than `max_growth` times its old size.
+    Grew { old_size: u64 },
+    /// larger than `max_size`.
+    TooLarge { limit: u64 },
+}
+
+/// `SizeWarning` stand for a file of a record over a `SizePolicy`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SizeWarning {
+    pub path: String,
+    pub size: u64,
+    pub reason: SizeReason,
+}
+
+impl Display for SizeWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.reason {
+            SizeReason::Grew { old_size } => write!(
+                f,
+                "{} grew from {} to {}",
+                self.path,
+                format_size(*old_size),
+                format_size(self.size)
+            ),
+            SizeReason::TooLarge { limit } => write!(
+                f,
+                "{} is {}, over the limit of {}",
+                self.path,
+                format_size(self.size),
+                format_size(*limit)
+            ),
+        }
+    }
+}
+
+/// a size in bytes with a binary unit, e.g. `1.5 MiB`.
+pub fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+impl SizePolicy {
+    /// the warning of a file of `size` bytes, `old_size` in the parent record if known.
+    pub fn check(&self, path: &str, size: u64, old_size: Option<u64>) -> Option<SizeWarning> {
+        let reason = match (self.max_size, old_size) {
+            (Some(limit), _) if size > limit => SizeReason::TooLarge { limit },
+            // sizes of entries recorded by older versions are unknown and 0.
+            (_, Some(old_size))
+                if self.max_growth > 0.0
+                    && old_size > 0
+                    && size >= self.min_size
+                    && size as f64 > old_size as f64 * self.max_growth =>
+            {
+                SizeReason::Grew { old_size }
+            }
+            _ => return None,
+        };
+        Some(SizeWarning {
+            path: path.to_owned(),
+            size,
+            reason,
+        })
+    }
+}
+
+impl Repository {
+    /// files added or changed from the tree `old_root` to the tree `new_root` over
+    /// `policy`, sorted by path. files are compared with the same path of the old tree.
+    pub async fn size_warnings(
+        &self,
+        old_root: Option<&ObjectId>,
+        new_root: &ObjectId,
+        policy: &SizePolicy,
+    ) -> Result<Vec<SizeWarning>, WsvcFsError> {
+        let mut result = vec![];
+        for change in self.changes_of_trees(old_root, Some(new_root)).await? {
+            let path = match &change {
+                Change::Added { path, .. } | Change::Modified { path, .. } => path,
+                _ => continue,
+            };
+            let size = match self.resolve_tree_path(new_root, path).await? {
+                Some(entry) if entry.kind == EntryKind::File => entry.size,
+                _ => continue,
+            };
+            let old_size = match old_root {
+                Some(old_root) => self
+                    .resolve_tree_path(old_root, path)
+                    .await?
+                    .filter(|entry| entry.kind == EntryKind::File)
+                    .map(|entry| entry.size),
+                None => None,
+            };
+            result.extend(policy.check(path, size, old_size));
+        }
+        Ok(result)
+    }
+}
+
+impl ReadOnlyRepository {
+    pub async fn size_warnings(
+        &self,
+        old_root: Option<&ObjectId>,
+        new_root: &ObjectId,
+        policy: &SizePolicy,
+    ) -> Result<Vec<SizeWarning>, WsvcFsError> {
+        self.repository()
+            .size_warnings(old_root, new_root, policy)
+            .await
+    }
+}
@@ -0,0 +1,152 @@
+use std::path::{Path, PathBuf};
+
+use chrono::serde::ts_seconds::{deserialize as from_ts, serialize as to_ts};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{create_dir_all, read, read_dir, remove_dir_all, remove_file, write};
+
+use crate::{
+    fs::WsvcFsError,
+    model::{ObjectId, Repository},
+    readonly::ReadOnlyRepository,
+    WsvcError,
+};
+
+/// dir of stashes in the repository.
+pub const STASHES_DIR: &str = "stashes";
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+/// `Stash` stand for workspace changes put aside without recording them.
+///
+/// the workspace is stored as a tree like a record, but stashes are not records, so they
+/// never show in logs or get synced.
+pub struct Stash {
+    /// sequence number of the stash, later stashes have greater ids.
+    pub id: u64,
+    pub message: String,
+    #[serde(deserialize_with = "from_ts", serialize_with = "to_ts")]
+    pub date: DateTime<Utc>,
+    /// the HEAD record the changes were made on.
+    pub base: ObjectId,
+    /// root tree of the stashed workspace.
+    pub root: ObjectId,
+}
+
+impl Repository {
+    /// get the stashes folder of the repository.
+    pub async fn stashes_dir(&self) -> Result<PathBuf, WsvcFsError> {
+        let result = self.path.join(STASHES_DIR);
+        if !result.exists() && !self.options.read_only {
+            create_dir_all(&result).await?;
+        }
+        Ok(result)
+    }
+
+    /// get stashes, the latest first.
+    pub async fn get_stashes(&self) -> Result<Vec<Stash>, WsvcFsError> {
+        let dir = self.stashes_dir().await?;
+        let mut result = vec![];
+        if !dir.exists() {
+            return Ok(result);
+        }
+        let mut entries = read_dir(dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            result.push(serde_json::from_slice::<Stash>(&read(entry.path()).await?)?);
+        }
+        result.sort_by_key(|s| std::cmp::Reverse(s.id));
+        Ok(result)
+    }
+
+    /// put the changes of `workspace` aside as a stash and reset the workspace to HEAD.
+    ///
+    /// `message` defaults to `WIP on <HEAD>: ` and the first line of the HEAD record message.
+    pub async fn push_stash(
+        &self,
+        workspace: &Path,
+        message: Option<String>,
+    ) -> Result<Stash, WsvcError> {
+        self.check_writable()?;
+        self.check_no_operation().await?;
+        let head = self
+            .get_head_record()
+            .await?
+            .ok_or(WsvcError::EmptyRepoError)?;
+        if self.status(workspace).await?.is_clean() {
+            return Err(WsvcError::BadUsage(
+                "no workspace changes to stash".to_owned(),
+            ));
+        }
+        let (tree, _) = self.write_tree_recursively(workspace).await?;
+        let message = message.unwrap_or_else(|| {
+            format!(
+                "WIP on {}: {}",
+                &head.hash.0.to_hex()[0..6],
+                head.message.lines().next().unwrap_or_default()
+            )
+        });
+        let stash = Stash {
+            id: self.get_stashes().await?.first().map_or(0, |s| s.id + 1),
+            message,
+            date: Utc::now(),
+            base: head.hash.clone(),
+            root: tree.hash,
+        };
+        write(
+            self.stashes_dir().await?.join(stash.id.to_string()),
+            serde_json::to_vec(&stash)?,
+        )
+        .await
+        .map_err(WsvcFsError::Os)?;
+        self.checkout_record_tree(&head, workspace).await?;
+        remove_dir_all(self.temp_dir().await?)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        Ok(stash)
+    }
+
+    /// apply the stash at `index` of `get_stashes` to `workspace` and drop it.
+    ///
+    /// the changes of the stash against its base record are applied, which fails without
+    /// touching anything if the workspace changed any of the files too.
+    pub async fn pop_stash(&self, workspace: &Path, index: usize) -> Result<Stash, WsvcError> {
+        self.check_writable()?;
+        let stash = self
+            .get_stashes()
+            .await?
+            .into_iter()
+            .nth(index)
+            .ok_or(WsvcError::BadUsage(format!("no stash at {}", index)))?;
+        let base = self.read_record(&stash.base).await?;
+        let changes = self
+            .changes_of_trees(Some(&base.root), Some(&stash.root))
+            .await?;
+        let conflicts = self.conflicting_changes(workspace, &changes).await;
+        if !conflicts.is_empty() {
+            return Err(WsvcError::RepoError(format!(
+                "workspace changes conflict with the stash: {}",
+                conflicts.join(", ")
+            )));
+        }
+        self.apply_changes(workspace, changes).await?;
+        remove_file(self.stashes_dir().await?.join(stash.id.to_string()))
+            .await
+            .map_err(WsvcFsError::Os)?;
+        Ok(stash)
+    }
+
+    /// root trees of stashes, kept by `prune_unreachable` with their trees and blobs.
+    pub(crate) async fn stash_roots(&self) -> Result<Vec<ObjectId>, WsvcFsError> {
+        Ok(self
+            .get_stashes()
+            .await?
+            .into_iter()
+            .map(|s| s.root)
+            .collect())
+    }
+}
+
+impl ReadOnlyRepository {
+    pub async fn get_stashes(&self) -> Result<Vec<Stash>, WsvcFsError> {
+        self.repository().get_stashes().await
+    }
+}
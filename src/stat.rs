@@ -0,0 +1,123 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs::read;
+
+use crate::{fs::WsvcFsError, model::ObjectId, refs::write_atomic};
+
+/// file of the stat cache in the repository.
+pub const STAT_CACHE_FILE: &str = "stat-cache.json";
+
+/// files modified this recently are not cached, a later write within the resolution of
+/// the file system clock would keep the same mtime.
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+/// `FileStat` stand for the metadata of a workspace file telling whether it changed.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FileStat {
+    pub size: u64,
+    pub mtime_secs: u64,
+    pub mtime_nanos: u32,
+    /// inode number on unix, 0 elsewhere.
+    pub ino: u64,
+}
+
+impl FileStat {
+    /// the stat of a file, `None` if its mtime is unknown or too recent to be trusted.
+    pub fn of(metadata: &std::fs::Metadata) -> Option<Self> {
+        let mtime = metadata.modified().ok()?;
+        if SystemTime::now().duration_since(mtime).ok()? < RACY_WINDOW {
+            return None;
+        }
+        let since_epoch = mtime.duration_since(UNIX_EPOCH).ok()?;
+        #[cfg(unix)]
+        let ino = std::os::unix::fs::MetadataExt::ino(metadata);
+        #[cfg(not(unix))]
+        let ino = 0;
+        Some(Self {
+            size: metadata.len(),
+            mtime_secs: since_epoch.as_secs(),
+            mtime_nanos: since_epoch.subsec_nanos(),
+            ino,
+        })
+    }
+}
+
+/// `StatEntry` stand for the blob a workspace file was stored as, with its stat then.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StatEntry {
+    pub stat: FileStat,
+    pub hash: ObjectId,
+}
+
+/// `StatCache` stand for the blobs of workspace files by path, so recording a workspace
+/// skips reading and compressing files whose stat did not change.
+///
+/// the cache only serves hints, a file with a different stat, a recent mtime or a
+/// missing blob is read again.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct StatCache {
+    /// the workspace the paths are relative to, the cache is dropped for another one.
+    pub workspace: PathBuf,
+    pub entries: HashMap<String, StatEntry>,
+    /// paths looked up or stored, the others are dropped by `retain_seen`.
+    #[serde(skip)]
+    seen: HashSet<String>,
+}
+
+impl StatCache {
+    /// load the cache of `workspace` from the repository at `repo_path`, an empty cache
+    /// if it is missing, broken or of another workspace.
+    pub async fn load(repo_path: &Path, workspace: &Path) -> Self {
+        let cache = match read(repo_path.join(STAT_CACHE_FILE)).await {
+            Ok(content) => serde_json::from_slice::<Self>(&content).unwrap_or_default(),
+            Err(_) => Self::default(),
+        };
+        match cache.workspace == workspace {
+            true => cache,
+            false => Self {
+                workspace: workspace.to_owned(),
+                ..Default::default()
+            },
+        }
+    }
+
+    pub async fn save(&self, repo_path: &Path) -> Result<(), WsvcFsError> {
+        write_atomic(&repo_path.join(STAT_CACHE_FILE), &serde_json::to_vec(self)?).await
+    }
+
+    /// the blob of `path` if the file still has the cached stat.
+    pub fn get(&mut self, path: &str, metadata: &std::fs::Metadata) -> Option<ObjectId> {
+        self.seen.insert(path.to_owned());
+        let stat = FileStat::of(metadata)?;
+        self.entries
+            .get(path)
+            .filter(|entry| entry.stat == stat)
+            .map(|entry| entry.hash.clone())
+    }
+
+    /// cache `hash` as the blob of `path`, unless the stat can not be trusted.
+    pub fn insert(&mut self, path: &str, metadata: &std::fs::Metadata, hash: &ObjectId) {
+        self.seen.insert(path.to_owned());
+        match FileStat::of(metadata) {
+            Some(stat) => self.entries.insert(
+                path.to_owned(),
+                StatEntry {
+                    stat,
+                    hash: hash.clone(),
+                },
+            ),
+            None => self.entries.remove(path),
+        };
+    }
+
+    /// drop paths not looked up since loading, e.g. removed files.
+    pub fn retain_seen(&mut self) {
+        let seen = std::mem::take(&mut self.seen);
+        self.entries.retain(|path, _| seen.contains(path));
+    }
+}
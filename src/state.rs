@@ -0,0 +1,233 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{create_dir_all, read, read_dir, remove_dir_all, remove_file};
+
+use crate::{
+    branch::{branch_ref, Branch},
+    events::EventKind,
+    fs::WsvcFsError,
+    model::{ObjectId, Repository},
+    refs::write_atomic,
+    WsvcError,
+};
+
+/// dir of markers of operations in progress, in the repository.
+pub const STATE_DIR: &str = "state";
+
+/// `Operation` stand for the multi-step operations leaving a marker while they run.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    /// a sync, checking out what it brought at the end.
+    Sync,
+    /// a checkout of a record or branch into the workspace.
+    Checkout,
+}
+
+impl Operation {
+    fn name(self) -> &'static str {
+        match self {
+            Operation::Sync => "sync",
+            Operation::Checkout => "checkout",
+        }
+    }
+
+    /// how to get out of the operation when it was interrupted.
+    pub fn hint(self) -> &'static str {
+        match self {
+            Operation::Sync => "run wsvc sync again to finish it or wsvc sync --abort to undo it",
+            Operation::Checkout => {
+                "run wsvc checkout again to finish it or wsvc checkout --abort to undo it"
+            }
+        }
+    }
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// `OperationState` stand for the marker of an operation in progress, with the state of
+/// the repository before it.
+///
+/// the marker is written before the operation changes anything and removed when it is
+/// done, a marker left behind means the operation was interrupted, e.g. killed while
+/// checking out, and the workspace may be half way between two records.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct OperationState {
+    pub operation: Operation,
+    pub started: DateTime<Utc>,
+    pub workspace: PathBuf,
+    /// the HEAD record before the operation.
+    pub head: Option<ObjectId>,
+    /// the branch HEAD was on before the operation.
+    pub branch: Option<String>,
+    /// branches before the operation.
+    pub branches: Vec<Branch>,
+    /// id of the stash of workspace changes made before the operation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stash: Option<u64>,
+}
+
+impl OperationState {
+    /// the notice shown by commands while the operation is in progress.
+    pub fn notice(&self) -> String {
+        format!(
+            "{} in progress since {}, {}",
+            self.operation,
+            self.started.format("%Y-%m-%d %H:%M:%S"),
+            self.operation.hint()
+        )
+    }
+}
+
+impl Repository {
+    /// the operation in progress or interrupted, if any.
+    pub async fn operation_in_progress(&self) -> Result<Option<OperationState>, WsvcFsError> {
+        let dir = self.path.join(STATE_DIR);
+        if !dir.exists() {
+            return Ok(None);
+        }
+        let mut entries = read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                return Ok(Some(serde_json::from_slice(&read(path).await?)?));
+            }
+        }
+        Ok(None)
+    }
+
+    /// fail if an operation is in progress, e.g. before committing a workspace that may be
+    /// half checked out.
+    pub async fn check_no_operation(&self) -> Result<(), WsvcFsError> {
+        match self.operation_in_progress().await? {
+            Some(state) => Err(WsvcFsError::OperationInProgress(state.notice())),
+            None => Ok(()),
+        }
+    }
+
+    /// write the marker of `operation` on `workspace` with the current HEAD and branches,
+    /// fails if another operation is in progress. the same operation may be run again to
+    /// finish it, its marker is kept with the state before the first run. `stash` is the
+    /// stash the workspace changes were saved to, popped again if the operation is aborted.
+    pub async fn begin_operation(
+        &self,
+        operation: Operation,
+        workspace: impl Into<PathBuf>,
+        stash: Option<u64>,
+    ) -> Result<OperationState, WsvcFsError> {
+        self.check_writable()?;
+        if let Some(state) = self.operation_in_progress().await? {
+            return match state.operation == operation {
+                true => Ok(state),
+                false => Err(WsvcFsError::OperationInProgress(state.notice())),
+            };
+        }
+        let head = self.read_head().await?;
+        let state = OperationState {
+            operation,
+            started: Utc::now(),
+            workspace: workspace.into(),
+            head: head.record().cloned(),
+            branch: head.branch().map(ToOwned::to_owned),
+            branches: self.get_branches().await?,
+            stash,
+        };
+        let dir = self.path.join(STATE_DIR);
+        create_dir_all(&dir).await?;
+        write_atomic(
+            &dir.join(format!("{}.json", operation.name())),
+            &serde_json::to_vec_pretty(&state)?,
+        )
+        .await?;
+        Ok(state)
+    }
+
+    /// remove the marker of `operation`, it is done.
+    pub async fn end_operation(&self, operation: Operation) -> Result<(), WsvcFsError> {
+        let path = self
+            .path
+            .join(STATE_DIR)
+            .join(format!("{}.json", operation.name()));
+        if path.exists() {
+            remove_file(path).await?;
+        }
+        Ok(())
+    }
+
+    /// undo `operation`, in progress or interrupted: branches and HEAD are set back as
+    /// they were before it, the HEAD record then is checked out to the workspace of the
+    /// operation and the stash made before it is popped. records the operation stored
+    /// are kept, branches it created are deleted. the stash of the returned state is `None`
+    /// if it was already popped.
+    pub async fn abort_operation(&self, operation: Operation) -> Result<OperationState, WsvcError> {
+        let mut state = self
+            .operation_in_progress()
+            .await?
+            .filter(|state| state.operation == operation)
+            .ok_or(WsvcError::BadUsage(format!("no {} in progress", operation)))?;
+        let saved = state
+            .branches
+            .iter()
+            .map(|branch| (branch.name.as_str(), &branch.record))
+            .collect::<HashMap<_, _>>();
+        let mut transaction = self.transaction();
+        for branch in self.get_branches().await? {
+            match saved.get(branch.name.as_str()) {
+                Some(record) if **record == branch.record => {}
+                Some(record) => transaction = transaction.set(branch_ref(&branch.name), record),
+                None => transaction = transaction.delete(branch_ref(&branch.name), &branch.record),
+            }
+        }
+        for branch in &state.branches {
+            if self.read_branch(&branch.name).await?.is_none() {
+                transaction = transaction.set(branch_ref(&branch.name), &branch.record);
+            }
+        }
+        transaction = match (&state.branch, &state.head) {
+            (Some(name), _) => transaction.set_symbolic("HEAD", branch_ref(name)),
+            (None, Some(head)) => transaction.set("HEAD", head),
+            (None, None) => transaction,
+        };
+        transaction.commit().await?;
+        if let Some(head) = &state.head {
+            self.restore_workspace(head, &state.workspace).await?;
+        }
+        if let Some(id) = state.stash {
+            let index = self.get_stashes().await?.iter().position(|s| s.id == id);
+            match index {
+                Some(index) => {
+                    self.pop_stash(&state.workspace, index).await?;
+                }
+                // popped meanwhile.
+                None => state.stash = None,
+            }
+        }
+        self.end_operation(operation).await?;
+        Ok(state)
+    }
+
+    /// checkout `head` to `workspace` without moving HEAD.
+    async fn restore_workspace(&self, head: &ObjectId, workspace: &Path) -> Result<(), WsvcError> {
+        let record = self.read_record(head).await?;
+        self.checkout_record_tree(&record, workspace).await?;
+        remove_dir_all(self.temp_dir().await?)
+            .await
+            .map_err(WsvcFsError::Os)?;
+        self.append_event(EventKind::Checkout {
+            record: record.hash.clone(),
+        })
+        .await
+        .ok();
+        Ok(())
+    }
+}
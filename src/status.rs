@@ -0,0 +1,201 @@
+use std::{collections::BTreeMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs::read_dir;
+
+use crate::{
+    attributes::Attributes,
+    diff::{detect_renames, Change},
+    fs::{file_mode, join_path, symlink_target, WsvcFsError},
+    ignore::Ignore,
+    model::{Entry, EntryKind, ObjectId, Repository},
+    readonly::ReadOnlyRepository,
+    state::OperationState,
+};
+
+/// `WorkspaceStatus` stand for the changes of a workspace not recorded yet.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct WorkspaceStatus {
+    /// the HEAD record, `None` if the repository has no record.
+    pub head: Option<ObjectId>,
+    /// changes from the HEAD record to the workspace, sorted by path.
+    pub changes: Vec<Change>,
+    /// the operation in progress or interrupted, the changes may be left by it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operation: Option<OperationState>,
+}
+
+impl WorkspaceStatus {
+    /// whether the workspace matches the HEAD record.
+    pub fn is_clean(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// collect files of `dir` with their hash and mode, ignored paths are skipped. `path` is the
+/// path of `dir` relative to the workspace root.
+///
+/// empty dirs are collected as dirs without a hash, a dir with only ignored paths is empty
+/// like committing records it.
+#[async_recursion::async_recursion(?Send)]
+async fn workspace_files(
+    dir: &Path,
+    ignore: &mut Ignore,
+    attributes: &mut Attributes,
+    path: &str,
+    files: &mut BTreeMap<String, (EntryKind, ObjectId, u32)>,
+) -> Result<(), WsvcFsError> {
+    let mut entries = read_dir(dir).await?;
+    let mut empty = true;
+    while let Some(entry) = entries.next_entry().await? {
+        let entry_type = entry.file_type().await?;
+        let name = entry
+            .file_name()
+            .to_str()
+            .ok_or(WsvcFsError::InvalidOsString(format!("{:?}", entry)))?
+            .to_string();
+        let entry_path = join_path(path, &name);
+        if ignore.is_ignored(&entry_path, entry_type.is_dir()) {
+            continue;
+        }
+        if entry_type.is_dir() {
+            if name == ".wsvc" {
+                continue;
+            }
+            ignore.enter_dir(&entry_path, &entry.path()).await?;
+            attributes.enter_dir(&entry_path, &entry.path()).await?;
+            workspace_files(&entry.path(), ignore, attributes, &entry_path, files).await?;
+            empty = false;
+        } else if entry_type.is_file() {
+            let mode = file_mode(&entry.metadata().await?);
+            let hash = attributes.hash_file(&entry.path(), &entry_path).await?;
+            files.insert(entry_path, (EntryKind::File, hash, mode));
+            empty = false;
+        } else if entry_type.is_symlink() {
+            let hash = ObjectId(blake3::hash(&symlink_target(&entry.path()).await?));
+            files.insert(entry_path, (EntryKind::Symlink, hash, 0));
+            empty = false;
+        }
+    }
+    if empty && !path.is_empty() {
+        files.insert(path.to_owned(), (EntryKind::Dir, ObjectId::default(), 0));
+    }
+    Ok(())
+}
+
+impl Repository {
+    /// files and empty dirs of the tree `root` by their path, dirs are walked into.
+    async fn tree_files(&self, root: &ObjectId) -> Result<BTreeMap<String, Entry>, WsvcFsError> {
+        let mut result = BTreeMap::new();
+        let mut queue = vec![(root.clone(), String::new())];
+        while let Some((tree_hash, dir)) = queue.pop() {
+            let tree = self.read_tree(&tree_hash).await?;
+            if tree.entries.is_empty() && !dir.is_empty() {
+                result.insert(
+                    dir,
+                    Entry {
+                        kind: EntryKind::Dir,
+                        name: tree.name,
+                        hash: tree_hash,
+                        mode: 0,
+                        size: 0,
+                    },
+                );
+                continue;
+            }
+            for entry in tree.entries {
+                let path = join_path(&dir, &entry.name);
+                match entry.kind {
+                    EntryKind::Dir => queue.push((entry.hash.clone(), path)),
+                    _ => {
+                        result.insert(path, entry);
+                    }
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// compare `workspace` against the tree of the HEAD record, without storing anything.
+    ///
+    /// ignored paths are skipped like committing does. a file is modified if its content
+    /// changed, or its mode changed while both modes are known.
+    pub async fn status(&self, workspace: &Path) -> Result<WorkspaceStatus, WsvcFsError> {
+        let head = self.get_head_record().await?;
+        let mut recorded = match &head {
+            Some(record) => self.tree_files(&record.root).await?,
+            None => BTreeMap::new(),
+        };
+        let mut files = BTreeMap::new();
+        let mut ignore = self.load_ignore(workspace).await?;
+        let mut attributes = self.load_attributes(workspace).await?;
+        workspace_files(workspace, &mut ignore, &mut attributes, "", &mut files).await?;
+        let mut changes = vec![];
+        for (path, (kind, hash, mode)) in files {
+            let entry = recorded.remove(&path);
+            if kind == EntryKind::Dir || entry.as_ref().is_some_and(|e| e.kind == EntryKind::Dir) {
+                // empty dirs in place of files or the other way round are two changes.
+                match entry {
+                    Some(entry) if entry.kind == kind => {}
+                    Some(entry) if entry.kind == EntryKind::Dir => {
+                        changes.push(Change::DirRemoved { path: path.clone() });
+                        changes.push(Change::Added { path, hash });
+                    }
+                    Some(entry) => {
+                        changes.push(Change::Removed {
+                            path: path.clone(),
+                            hash: entry.hash,
+                        });
+                        changes.push(Change::DirAdded { path });
+                    }
+                    None => changes.push(Change::DirAdded { path }),
+                }
+                continue;
+            }
+            match entry {
+                Some(entry)
+                    if entry.kind != kind
+                        || entry.hash != hash
+                        || (entry.mode != 0 && mode != 0 && entry.mode != mode) =>
+                {
+                    changes.push(Change::Modified {
+                        path,
+                        old_hash: entry.hash,
+                        new_hash: hash,
+                    })
+                }
+                Some(_) => {}
+                None => changes.push(Change::Added { path, hash }),
+            }
+        }
+        for (path, entry) in recorded {
+            if entry.kind == EntryKind::Dir {
+                changes.push(Change::DirRemoved { path });
+                continue;
+            }
+            // lazy files are not checked out while their blobs are missing.
+            attributes.enter_path(workspace, &path).await?;
+            if attributes.get(&path).lazy && !self.blob_exists(&entry.hash).await? {
+                continue;
+            }
+            changes.push(Change::Removed {
+                path,
+                hash: entry.hash,
+            });
+        }
+        changes.sort_by(|a, b| a.path().cmp(b.path()));
+        let mut changes = detect_renames(changes);
+        changes.sort_by(|a, b| a.path().cmp(b.path()));
+        Ok(WorkspaceStatus {
+            head: head.map(|r| r.hash),
+            changes,
+            operation: self.operation_in_progress().await?,
+        })
+    }
+}
+
+impl ReadOnlyRepository {
+    pub async fn status(&self, workspace: &Path) -> Result<WorkspaceStatus, WsvcFsError> {
+        self.repository().status(workspace).await
+    }
+}
@@ -0,0 +1,269 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use chrono::serde::ts_seconds::{deserialize as from_ts, serialize as to_ts};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{create_dir_all, read, read_dir, read_to_string, remove_file};
+
+use crate::{
+    fs::WsvcFsError,
+    model::{ObjectId, Record, Repository},
+    refs::{write_atomic, LOCK_SUFFIX},
+    walk::{RecordGraph, WalkOrder},
+    WsvcError,
+};
+
+/// length of the short hash used in record descriptions.
+pub const SHORT_HASH_LEN: usize = 7;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+/// `Tag` stand for a human-readable name of a record.
+pub struct Tag {
+    pub name: String,
+    pub record: ObjectId,
+}
+
+/// `TagAnnotation` stand for the author and message of an annotated tag, e.g. a release.
+///
+/// annotations are stored in the `annotations` dir by tag name, beside the tag itself.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct TagAnnotation {
+    pub author: String,
+    #[serde(deserialize_with = "from_ts", serialize_with = "to_ts")]
+    pub date: DateTime<Utc>,
+    pub message: String,
+}
+
+/// `SyncTag` stand for a tag exchanged by sync, with its annotation if it is annotated.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SyncTag {
+    #[serde(flatten)]
+    pub tag: Tag,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotation: Option<TagAnnotation>,
+}
+
+/// dir of tags in the repository, a tag is the ref `tags/<name>`.
+pub const TAGS_DIR: &str = "tags";
+
+/// the ref name of tag `name`.
+pub fn tag_ref(name: &str) -> String {
+    format!("{}/{}", TAGS_DIR, name)
+}
+
+/// check whether `name` could be used as a tag name, tags are stored as files.
+pub fn check_tag_name(name: &str) -> Result<(), WsvcError> {
+    if name.is_empty()
+        || name.starts_with('.')
+        || name.starts_with('-')
+        || name.ends_with(LOCK_SUFFIX)
+        || name.contains(['/', '\\'])
+        || name.chars().any(|c| c.is_whitespace() || c.is_control())
+    {
+        return Err(WsvcError::BadUsage(format!("invalid tag name: {}", name)));
+    }
+    Ok(())
+}
+
+impl Repository {
+    /// get the tags folder of the repository.
+    pub async fn tags_dir(&self) -> Result<PathBuf, WsvcFsError> {
+        let result = self.path.join(TAGS_DIR);
+        if !result.exists() && !self.options.read_only {
+            create_dir_all(&result).await?;
+        }
+        Ok(result)
+    }
+
+    /// get the annotations folder of the repository.
+    pub async fn annotations_dir(&self) -> Result<PathBuf, WsvcFsError> {
+        let result = self.path.join("annotations");
+        if !result.exists() && !self.options.read_only {
+            create_dir_all(&result).await?;
+        }
+        Ok(result)
+    }
+
+    /// read the annotation of a tag, `None` if the tag is not annotated.
+    pub async fn read_tag_annotation(
+        &self,
+        name: impl AsRef<str>,
+    ) -> Result<Option<TagAnnotation>, WsvcFsError> {
+        let path = self.annotations_dir().await?.join(name.as_ref());
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&read(path).await?)?))
+    }
+
+    /// tag a record with an annotation, like `create_tag`.
+    pub async fn create_annotated_tag(
+        &self,
+        name: impl AsRef<str>,
+        record_hash: &ObjectId,
+        force: bool,
+        annotation: &TagAnnotation,
+    ) -> Result<Tag, WsvcError> {
+        let tag = self.create_tag(name, record_hash, force).await?;
+        write_atomic(
+            &self.annotations_dir().await?.join(&tag.name),
+            &serde_json::to_vec(annotation)?,
+        )
+        .await?;
+        Ok(tag)
+    }
+
+    /// get all tags, sorted by name.
+    pub async fn get_tags(&self) -> Result<Vec<Tag>, WsvcFsError> {
+        let mut result = Vec::new();
+        let tags_dir = self.tags_dir().await?;
+        if !tags_dir.exists() {
+            return Ok(result);
+        }
+        let mut entries = read_dir(tags_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry
+                .file_name()
+                .to_str()
+                .ok_or(WsvcFsError::InvalidOsString(format!("{:?}", entry)))?
+                .to_string();
+            // tag names never start with `.` or end with `.lock`, such files are temp and
+            // lock files of ref updates.
+            if entry.file_type().await?.is_file()
+                && !name.starts_with('.')
+                && !name.ends_with(LOCK_SUFFIX)
+            {
+                result.push(Tag {
+                    name,
+                    record: read_to_string(entry.path()).await?.trim().try_into()?,
+                });
+            }
+        }
+        result.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(result)
+    }
+
+    /// read a tag by name, `None` if not exists.
+    pub async fn read_tag(&self, name: impl AsRef<str>) -> Result<Option<Tag>, WsvcFsError> {
+        let path = self.tags_dir().await?.join(name.as_ref());
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(Tag {
+            name: name.as_ref().to_string(),
+            record: read_to_string(path).await?.trim().try_into()?,
+        }))
+    }
+
+    /// the record a tag points to, `None` if there is no such tag.
+    pub async fn resolve_tag(&self, name: impl AsRef<str>) -> Result<Option<Record>, WsvcFsError> {
+        if check_tag_name(name.as_ref()).is_err() {
+            return Ok(None);
+        }
+        match self.read_tag(name).await? {
+            Some(tag) => Ok(Some(self.read_record(&tag.record).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// all tags with their annotations, sent by sync.
+    pub async fn get_sync_tags(&self) -> Result<Vec<SyncTag>, WsvcFsError> {
+        let mut result = vec![];
+        for tag in self.get_tags().await? {
+            let annotation = self.read_tag_annotation(&tag.name).await?;
+            result.push(SyncTag { tag, annotation });
+        }
+        Ok(result)
+    }
+
+    /// store tags received by sync, returns the tags stored.
+    ///
+    /// tags are never moved by sync, a tag that exists here is kept even if it points to
+    /// another record. tags of records not in the repository are skipped.
+    pub async fn store_sync_tags(&self, tags: &[SyncTag]) -> Result<Vec<Tag>, WsvcError> {
+        let mut result = vec![];
+        for SyncTag { tag, annotation } in tags {
+            if check_tag_name(&tag.name).is_err()
+                || self.read_tag(&tag.name).await?.is_some()
+                || self.read_record(&tag.record).await.is_err()
+            {
+                continue;
+            }
+            result.push(match annotation {
+                Some(annotation) => {
+                    self.create_annotated_tag(&tag.name, &tag.record, false, annotation)
+                        .await?
+                }
+                None => self.create_tag(&tag.name, &tag.record, false).await?,
+            });
+        }
+        Ok(result)
+    }
+
+    /// tag a record, an existing tag with the same name is only replaced if `force` is set.
+    pub async fn create_tag(
+        &self,
+        name: impl AsRef<str>,
+        record_hash: &ObjectId,
+        force: bool,
+    ) -> Result<Tag, WsvcError> {
+        self.check_writable()?;
+        let name = name.as_ref();
+        check_tag_name(name)?;
+        let record = self.read_record(record_hash).await?;
+        let tag_ref = tag_ref(name);
+        let transaction = if force {
+            self.transaction().set(&tag_ref, &record.hash)
+        } else {
+            if self.read_tag(name).await?.is_some() {
+                return Err(WsvcError::BadUsage(format!("tag {} already exists", name)));
+            }
+            // fails if the tag is created meanwhile.
+            self.transaction()
+                .compare_and_set(&tag_ref, None, &record.hash)
+        };
+        transaction.commit().await?;
+        // the annotation of a replaced tag belongs to the old record.
+        let annotation = self.annotations_dir().await?.join(name);
+        if annotation.is_file() {
+            remove_file(annotation).await.map_err(WsvcFsError::Os)?;
+        }
+        Ok(Tag {
+            name: name.to_string(),
+            record: record.hash,
+        })
+    }
+
+    /// describe a record as `<nearest-tag>-<n>-g<shorthash>`.
+    ///
+    /// like `git describe`, the nearest tag is the tag of an ancestor of `record_hash` with
+    /// the fewest records after it, and `n` is the count of records reachable from
+    /// `record_hash` but not from the tagged record. ties are broken by tag name. the tag
+    /// name itself is returned if the record is tagged, and the short hash is returned if
+    /// no ancestor is tagged.
+    pub async fn describe_record(&self, record_hash: &ObjectId) -> Result<String, WsvcError> {
+        let record = self.read_record(record_hash).await?;
+        let short_hash = record.hash.0.to_hex()[0..SHORT_HASH_LEN].to_string();
+        let tags = self.get_tags().await?;
+        if let Some(tag) = tags.iter().find(|t| t.record == record.hash) {
+            return Ok(tag.name.clone());
+        }
+        let graph = RecordGraph::from_summaries(self.record_summaries().await?);
+        let ancestors = graph
+            .walk_ids(Some(&record.hash), WalkOrder::Topological)
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let nearest = tags
+            .iter()
+            .filter(|t| ancestors.contains(&t.record))
+            .map(|t| {
+                let tagged = graph.walk_ids(Some(&t.record), WalkOrder::Topological);
+                (ancestors.len().saturating_sub(tagged.len()), &t.name)
+            })
+            .min();
+        Ok(match nearest {
+            Some((distance, name)) => format!("{}-{}-g{}", name, distance, short_hash),
+            None => short_hash,
+        })
+    }
+}
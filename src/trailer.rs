@@ -0,0 +1,137 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::model::Record;
+
+/// `Trailer` stand for a `Key: value` line at the end of a record message.
+///
+/// trailers are the last paragraph of a message if every line of it is a trailer, lines
+/// starting with whitespace continue the value of the previous trailer:
+///
+/// ```text
+/// fix the flaky sync test
+///
+/// Co-authored-by: alice <alice@example.com>
+/// Ticket: WSVC-42
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Trailer {
+    pub key: String,
+    pub value: String,
+}
+
+/// parse trailers of a message, empty if the message has none.
+///
+/// the first paragraph is never taken as trailers, it is the subject of the message.
+pub fn parse_trailers(message: &str) -> Vec<Trailer> {
+    let lines = message.trim_end().lines().collect::<Vec<_>>();
+    let start = match lines.iter().rposition(|line| line.trim().is_empty()) {
+        Some(blank) => blank + 1,
+        None => return vec![],
+    };
+    if !lines[..start].iter().any(|line| !line.trim().is_empty()) {
+        return vec![];
+    }
+    let mut trailers: Vec<Trailer> = vec![];
+    for line in &lines[start..] {
+        if line.starts_with([' ', '\t']) {
+            match trailers.last_mut() {
+                Some(last) => {
+                    last.value.push(' ');
+                    last.value.push_str(line.trim());
+                    continue;
+                }
+                None => return vec![],
+            }
+        }
+        match line.split_once(':') {
+            Some((key, value)) if is_trailer_key(key) => trailers.push(Trailer {
+                key: key.to_owned(),
+                value: value.trim().to_owned(),
+            }),
+            _ => return vec![],
+        }
+    }
+    trailers
+}
+
+/// keys are letters, digits and `-`, and start with a letter or a digit.
+fn is_trailer_key(key: &str) -> bool {
+    key.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+impl Record {
+    /// trailers of the record message.
+    pub fn trailers(&self) -> Vec<Trailer> {
+        parse_trailers(&self.message)
+    }
+
+    /// values of trailers named `key`, compared case-insensitively.
+    pub fn trailer_values(&self, key: &str) -> Vec<String> {
+        self.trailers()
+            .into_iter()
+            .filter(|t| t.key.eq_ignore_ascii_case(key))
+            .map(|t| t.value)
+            .collect()
+    }
+}
+
+/// `RecordWithTrailers` is a record with its parsed trailers, the shape of records in
+/// json output.
+#[derive(Clone, Debug, Serialize)]
+pub struct RecordWithTrailers<'a> {
+    #[serde(flatten)]
+    pub record: &'a Record,
+    pub trailers: Vec<Trailer>,
+}
+
+impl<'a> From<&'a Record> for RecordWithTrailers<'a> {
+    fn from(record: &'a Record) -> Self {
+        Self {
+            record,
+            trailers: record.trailers(),
+        }
+    }
+}
+
+/// `TrailerFilter` matches records having a trailer, parsed from `key` or `key=value`.
+///
+/// keys are compared case-insensitively and values exactly.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrailerFilter {
+    pub key: String,
+    pub value: Option<String>,
+}
+
+impl TrailerFilter {
+    pub fn matches(&self, record: &Record) -> bool {
+        let values = record.trailer_values(&self.key);
+        match &self.value {
+            Some(value) => values.iter().any(|v| v == value),
+            None => !values.is_empty(),
+        }
+    }
+}
+
+impl FromStr for TrailerFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = match s.split_once('=') {
+            Some((key, value)) => (key.trim(), Some(value.trim().to_owned())),
+            None => (s.trim(), None),
+        };
+        if !is_trailer_key(key) {
+            return Err(format!(
+                "invalid trailer key: {}, use `key` or `key=value`",
+                key
+            ));
+        }
+        Ok(Self {
+            key: key.to_owned(),
+            value,
+        })
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    collections::{BinaryHeap, HashMap, HashSet},
+    str::FromStr,
+};
+
+use blake3::Hash;
+use chrono::{DateTime, Utc};
+
+use crate::{
+    fs::WsvcFsError,
+    model::{ObjectId, Record, Repository},
+    readonly::ReadOnlyRepository,
+    records::RecordSummary,
+};
+
+/// order of records walked by `Repository::walk`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WalkOrder {
+    /// newest first by date.
+    #[default]
+    Date,
+    /// a record before its parents, newer first among unrelated records.
+    Topological,
+    /// a record after its parents, the reverse of `Topological`.
+    Reverse,
+}
+
+impl FromStr for WalkOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "date" => Ok(WalkOrder::Date),
+            "topo" | "topological" => Ok(WalkOrder::Topological),
+            "reverse" => Ok(WalkOrder::Reverse),
+            _ => Err(format!(
+                "unknown walk order: {}, use date, topo or reverse",
+                s
+            )),
+        }
+    }
+}
+
+/// `RecordGraph` stand for records of a repository with their parents and generation
+/// numbers.
+///
+/// the generation number of a record is one more than the largest one of its parents, 1
+/// for records without parents. a record always has a larger generation number than its
+/// ancestors, so taking the largest generation first visits a record before its parents
+/// without counting children in advance.
+pub struct RecordGraph {
+    /// records the graph was built from, empty for a graph of summaries.
+    records: HashMap<ObjectId, Record>,
+    dates: HashMap<ObjectId, DateTime<Utc>>,
+    parents: HashMap<ObjectId, Vec<ObjectId>>,
+    generations: HashMap<ObjectId, u64>,
+}
+
+/// key of a record in the walk queue, the largest is taken first.
+type WalkKey = (u64, DateTime<Utc>, [u8; 32]);
+
+impl RecordGraph {
+    pub fn new(records: Vec<Record>) -> Self {
+        let mut graph = Self::from_summaries(records.iter().map(RecordSummary::from).collect());
+        graph.records = records.into_iter().map(|r| (r.hash.clone(), r)).collect();
+        graph
+    }
+
+    /// a graph of record summaries, e.g. from the records index, walked by `walk_ids`.
+    pub fn from_summaries(summaries: Vec<RecordSummary>) -> Self {
+        let mut sorted = summaries;
+        sorted.sort_by(|a, b| {
+            a.date
+                .cmp(&b.date)
+                .then_with(|| a.hash.0.as_bytes().cmp(b.hash.0.as_bytes()))
+        });
+        let known = sorted
+            .iter()
+            .map(|r| r.hash.clone())
+            .collect::<HashSet<_>>();
+        let mut parents = HashMap::new();
+        for (i, record) in sorted.iter().enumerate() {
+            let record_parents = match &record.parents {
+                // parents missing in the repository are ignored.
+                Some(record_parents) => record_parents
+                    .iter()
+                    .filter(|p| known.contains(p))
+                    .cloned()
+                    .collect(),
+                None if i > 0 => vec![sorted[i - 1].hash.clone()],
+                None => vec![],
+            };
+            parents.insert(record.hash.clone(), record_parents);
+        }
+        let mut graph = Self {
+            records: HashMap::new(),
+            dates: sorted.into_iter().map(|r| (r.hash, r.date)).collect(),
+            parents,
+            generations: HashMap::new(),
+        };
+        graph.compute_generations();
+        graph
+    }
+
+    /// compute generation numbers with an explicit stack, histories can be deep. parents
+    /// forming a cycle, e.g. by clock skew of legacy records, are ignored.
+    fn compute_generations(&mut self) {
+        let mut visiting = HashSet::new();
+        for hash in self.dates.keys() {
+            let mut stack = vec![(hash, false)];
+            while let Some((hash, expanded)) = stack.pop() {
+                if expanded {
+                    let generation = self.parents[hash]
+                        .iter()
+                        .filter_map(|p| self.generations.get(p))
+                        .max()
+                        .map_or(1, |g| g + 1);
+                    self.generations.insert(hash.clone(), generation);
+                    visiting.remove(hash);
+                    continue;
+                }
+                if self.generations.contains_key(hash) || !visiting.insert(hash) {
+                    continue;
+                }
+                stack.push((hash, true));
+                for parent in &self.parents[hash] {
+                    if !self.generations.contains_key(parent) && !visiting.contains(parent) {
+                        stack.push((parent, false));
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn contains(&self, hash: &ObjectId) -> bool {
+        self.dates.contains_key(hash)
+    }
+
+    /// parents of a record, implied ones of legacy records included.
+    pub fn parents(&self, hash: &ObjectId) -> &[ObjectId] {
+        self.parents
+            .get(hash)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn generation(&self, hash: &ObjectId) -> Option<u64> {
+        self.generations.get(hash).copied()
+    }
+
+    fn key(&self, hash: &ObjectId) -> WalkKey {
+        (self.generations[hash], self.dates[hash], *hash.0.as_bytes())
+    }
+
+    /// hashes of records reachable from `start` in `order`, all records if `start` is
+    /// `None`.
+    pub fn walk_ids(&self, start: Option<&ObjectId>, order: WalkOrder) -> Vec<ObjectId> {
+        let mut queue = BinaryHeap::new();
+        let mut seen = HashSet::new();
+        match start {
+            Some(start) if self.contains(start) => {
+                queue.push(self.key(start));
+                seen.insert(start.clone());
+            }
+            Some(_) => return vec![],
+            None => {
+                queue.extend(self.dates.keys().map(|hash| self.key(hash)));
+                seen.extend(self.dates.keys().cloned());
+            }
+        }
+        let mut result = Vec::with_capacity(queue.len());
+        while let Some((_, _, hash)) = queue.pop() {
+            let hash = ObjectId(Hash::from(hash));
+            for parent in self.parents(&hash) {
+                if seen.insert(parent.clone()) {
+                    queue.push(self.key(parent));
+                }
+            }
+            result.push(hash);
+        }
+        match order {
+            WalkOrder::Date => result.sort_by(|a, b| {
+                self.dates[b]
+                    .cmp(&self.dates[a])
+                    .then_with(|| b.0.as_bytes().cmp(a.0.as_bytes()))
+            }),
+            WalkOrder::Topological => {}
+            WalkOrder::Reverse => result.reverse(),
+        }
+        result
+    }
+
+    /// records reachable from `start` in `order`, all records if `start` is `None`. a graph
+    /// of summaries has no records to return, walk it with `walk_ids`.
+    pub fn walk(&self, start: Option<&ObjectId>, order: WalkOrder) -> Vec<Record> {
+        self.walk_ids(start, order)
+            .into_iter()
+            .filter_map(|hash| self.records.get(&hash).cloned())
+            .collect()
+    }
+
+    /// records reachable from `to` but not from `from` in `order`, e.g. records of a
+    /// release between two tags. all records reachable from `to` if `from` is `None`.
+    pub fn range(&self, from: Option<&ObjectId>, to: &ObjectId, order: WalkOrder) -> Vec<Record> {
+        let excluded = match from {
+            Some(from) => self
+                .walk_ids(Some(from), WalkOrder::Topological)
+                .into_iter()
+                .collect(),
+            None => HashSet::new(),
+        };
+        self.walk(Some(to), order)
+            .into_iter()
+            .filter(|r| !excluded.contains(&r.hash))
+            .collect()
+    }
+
+    /// whether `ancestor` is reachable from `descendant`, a record is its own ancestor.
+    pub fn is_ancestor(&self, ancestor: &ObjectId, descendant: &ObjectId) -> bool {
+        self.walk_ids(Some(descendant), WalkOrder::Topological)
+            .contains(ancestor)
+    }
+}
+
+impl Repository {
+    /// the graph of all records.
+    pub async fn record_graph(&self) -> Result<RecordGraph, WsvcFsError> {
+        Ok(RecordGraph::new(self.get_records().await?))
+    }
+
+    /// walk records reachable from `start` in `order`, all records if `start` is `None`.
+    pub async fn walk(
+        &self,
+        start: Option<&ObjectId>,
+        order: WalkOrder,
+    ) -> Result<Vec<Record>, WsvcFsError> {
+        let graph = self.record_graph().await?;
+        if let Some(start) = start {
+            if !graph.contains(start) {
+                // fails with the error of reading a missing record.
+                self.read_record(start).await?;
+            }
+        }
+        Ok(graph.walk(start, order))
+    }
+
+    /// hashes of records reachable from `start` in `order` like `walk`, from the records
+    /// index without reading records.
+    pub async fn walk_ids(
+        &self,
+        start: Option<&ObjectId>,
+        order: WalkOrder,
+    ) -> Result<Vec<ObjectId>, WsvcFsError> {
+        let graph = RecordGraph::from_summaries(self.record_summaries().await?);
+        if let Some(start) = start {
+            if !graph.contains(start) {
+                self.read_record(start).await?;
+            }
+        }
+        Ok(graph.walk_ids(start, order))
+    }
+}
+
+impl ReadOnlyRepository {
+    pub async fn record_graph(&self) -> Result<RecordGraph, WsvcFsError> {
+        Ok(RecordGraph::new(self.get_records().await?))
+    }
+
+    /// walk records reachable from `start` in `order`, all records if `start` is `None`.
+    pub async fn walk(
+        &self,
+        start: Option<&ObjectId>,
+        order: WalkOrder,
+    ) -> Result<Vec<Record>, WsvcFsError> {
+        let graph = self.record_graph().await?;
+        if let Some(start) = start {
+            if !graph.contains(start) {
+                self.read_record(start).await?;
+            }
+        }
+        Ok(graph.walk(start, order))
+    }
+
+    /// hashes of records reachable from `start` in `order`, see `Repository::walk_ids`.
+    pub async fn walk_ids(
+        &self,
+        start: Option<&ObjectId>,
+        order: WalkOrder,
+    ) -> Result<Vec<ObjectId>, WsvcFsError> {
+        self.repository().walk_ids(start, order).await
+    }
+}